@@ -0,0 +1,57 @@
+// Depth update loop of a hot symbol, a 20 level window moving a few ticks per update, on each
+// level storage backend.
+use binance_orderbook::binance_payloads::DepthUpdate;
+use binance_orderbook::orderbook::OrderBook;
+use binance_orderbook::price_levels::BookBackend;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const LEVELS: u64 = 20;
+const TICK: f64 = 0.01;
+
+fn depth_updates(count: u64) -> Vec<DepthUpdate> {
+    (1..=count)
+        .map(|last_update_id| {
+            let mid = 2_500 + (last_update_id % 50) as i64 - 25;
+            let price = |offset: i64| (mid + offset) as f64 * TICK;
+            let quantity = (last_update_id % 7) as f64;
+            DepthUpdate {
+                event_time: None,
+                last_update_id,
+                bids: (1..=LEVELS as i64)
+                    .map(|level| (price(-level), quantity))
+                    .collect(),
+                asks: (1..=LEVELS as i64)
+                    .map(|level| (price(level), quantity))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn update_loop(c: &mut Criterion) {
+    let updates = depth_updates(1_000);
+    let mut group = c.benchmark_group("book_backend_updates");
+    for (name, backend) in [
+        ("btree", BookBackend::BTree),
+        ("dense", BookBackend::Dense { tick_size: 100 }),
+        ("skip_list", BookBackend::SkipList),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &backend,
+            |b, &backend| {
+                b.iter(|| {
+                    let mut orderbook = OrderBook::with_backend("BNBUSDT".to_string(), backend);
+                    for update in &updates {
+                        orderbook.update_depth(update);
+                    }
+                    criterion::black_box(orderbook.get_best_bid_ask())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, update_loop);
+criterion_main!(benches);
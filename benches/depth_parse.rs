@@ -0,0 +1,44 @@
+// 20 level depth payload parsed through serde and through `DepthUpdate::parse_bytes`.
+use binance_orderbook::binance_payloads::{DepthUpdate, DepthUpdateEnvelope};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn payload() -> String {
+    let levels = |start: f64, step: f64| {
+        (0..20)
+            .map(|level| {
+                format!(
+                    r#"["{:.2}","{:.5}"]"#,
+                    start + step * level as f64,
+                    1.5 + level as f64
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        r#"{{"stream":"ethusdc@depth20@100ms","data":{{"lastUpdateId":44172837561,"bids":[{}],"asks":[{}]}}}}"#,
+        levels(3456.78, -0.01),
+        levels(3456.79, 0.01)
+    )
+}
+
+fn depth_parsing(c: &mut Criterion) {
+    let payload = payload();
+    let bytes = payload.as_bytes();
+    let mut group = c.benchmark_group("depth_parse");
+
+    group.bench_function("serde", |b| {
+        b.iter(|| serde_json::from_slice::<DepthUpdateEnvelope>(criterion::black_box(bytes)))
+    });
+    group.bench_function("parse_bytes", |b| {
+        b.iter(|| DepthUpdate::parse_bytes(criterion::black_box(bytes)))
+    });
+    let mut update = DepthUpdate::parse_bytes(bytes).unwrap();
+    group.bench_function("parse_bytes_into", |b| {
+        b.iter(|| update.parse_bytes_into(criterion::black_box(bytes)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, depth_parsing);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+// Snapshot reads from a growing number of reader threads while one writer keeps applying depth
+// updates, the per read time should stay flat as readers are added.
+use binance_orderbook::binance_payloads::DepthUpdate;
+use binance_orderbook::shared_book::SharedOrderBook;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LEVELS: usize = 20;
+
+fn depth_update(last_update_id: u64) -> DepthUpdate {
+    let quantity = (last_update_id % 100 + 1) as f64;
+    DepthUpdate {
+        event_time: None,
+        last_update_id,
+        bids: (0..LEVELS)
+            .map(|level| (100.0 - level as f64, quantity))
+            .collect(),
+        asks: (0..LEVELS)
+            .map(|level| (101.0 + level as f64, quantity))
+            .collect(),
+    }
+}
+
+fn concurrent_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("shared_book_reads");
+
+    for readers in [1, 2, 4, 8] {
+        group.throughput(Throughput::Elements(readers));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(readers),
+            &readers,
+            |b, &readers| {
+                b.iter_custom(|iters| {
+                    let mut shared = SharedOrderBook::new("BNBUSDT".to_string());
+                    shared.update_depth(&depth_update(1));
+                    let running = Arc::new(AtomicBool::new(true));
+
+                    let reader_threads: Vec<_> = (0..readers)
+                        .map(|_| {
+                            let reader = shared.reader();
+                            thread::spawn(move || {
+                                let start = Instant::now();
+                                for _ in 0..iters {
+                                    criterion::black_box(reader.snapshot(LEVELS));
+                                }
+                                start.elapsed()
+                            })
+                        })
+                        .collect();
+
+                    let writer = {
+                        let running = Arc::clone(&running);
+                        thread::spawn(move || {
+                            let mut last_update_id = 1;
+                            while running.load(Ordering::Relaxed) {
+                                last_update_id += 1;
+                                shared.update_depth(&depth_update(last_update_id));
+                            }
+                        })
+                    };
+
+                    // Wall time of the slowest reader
+                    let elapsed = reader_threads
+                        .into_iter()
+                        .map(|reader| reader.join().unwrap())
+                        .max()
+                        .unwrap_or(Duration::ZERO);
+                    running.store(false, Ordering::Relaxed);
+                    writer.join().unwrap();
+                    elapsed
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_reads);
+criterion_main!(benches);
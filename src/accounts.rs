@@ -0,0 +1,555 @@
+/// Participant accounts for the matching engine.
+/// Every account holds a base and a quote balance for the instrument, in lots (`Quantity`) and
+/// in price times lots (`Notional`), so reserving, releasing and settling are exact. Resting
+/// orders reserve the funds they could need (quote for bids at the limit price, base for asks)
+/// and trades settle from the reservation. Fees are charged in quote, rounded up to a whole
+/// notional unit.
+use crate::orderbookv2::{
+    AccountId, Notional, Order, OrderId, OrderValidationError, Price, Quantity, Rejected, Side,
+    TradeInfo,
+};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance<T> {
+    pub available: T,
+    pub reserved: T,
+}
+
+impl<T: Funds> Balance<T> {
+    // None when the sum does not fit
+    pub fn total(&self) -> Option<T> {
+        self.available.checked_add(self.reserved)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountBalances {
+    pub base: Balance<Quantity>,
+    pub quote: Balance<Notional>,
+}
+
+// Amount of one of the two assets: `Quantity` for base, `Notional` for quote
+pub trait Funds: Copy + Ord + Default {
+    fn balance_mut(balances: &mut AccountBalances) -> &mut Balance<Self>;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_sub(self, other: Self) -> Option<Self>;
+    // Reported in `Rejected::InsufficientBalance`
+    fn raw(self) -> i64;
+}
+
+impl Funds for Quantity {
+    fn balance_mut(balances: &mut AccountBalances) -> &mut Balance<Quantity> {
+        &mut balances.base
+    }
+
+    fn checked_add(self, other: Quantity) -> Option<Quantity> {
+        Quantity::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Quantity) -> Option<Quantity> {
+        Quantity::checked_sub(self, other)
+    }
+
+    fn raw(self) -> i64 {
+        self.into()
+    }
+}
+
+impl Funds for Notional {
+    fn balance_mut(balances: &mut AccountBalances) -> &mut Balance<Notional> {
+        &mut balances.quote
+    }
+
+    fn checked_add(self, other: Notional) -> Option<Notional> {
+        Notional::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Notional) -> Option<Notional> {
+        Notional::checked_sub(self, other)
+    }
+
+    fn raw(self) -> i64 {
+        self.get()
+    }
+}
+
+// Moves `amount` from available to reserved
+fn hold<A: Funds>(
+    account_id: AccountId,
+    balance: &mut Balance<A>,
+    amount: A,
+) -> Result<(), Rejected> {
+    if balance.available < amount {
+        return Err(Rejected::InsufficientBalance {
+            account_id,
+            required: amount.raw(),
+            available: balance.available.raw(),
+        });
+    }
+    let reserved = balance
+        .reserved
+        .checked_add(amount)
+        .ok_or(Rejected::BalanceOverflow(account_id))?;
+    balance.available = balance.available.checked_sub(amount).unwrap();
+    balance.reserved = reserved;
+    Ok(())
+}
+
+// Takes `amount` out of reserved, it was put there by `hold`
+fn take_reserved<A: Funds>(balance: &mut Balance<A>, amount: A) {
+    balance.reserved = balance
+        .reserved
+        .checked_sub(amount)
+        .expect("More funds used than reserved | unreachable state");
+}
+
+fn credit<A: Funds>(balance: &mut Balance<A>, amount: A) {
+    balance.available = balance
+        .available
+        .checked_add(amount)
+        .expect("Balance overflow");
+}
+
+// Fees are f64 quote amounts, charges round up and rebates round towards zero
+fn fee_notional(fee: f64) -> Notional {
+    Notional(fee.ceil() as i64)
+}
+
+// Funds held for the open part of a resting order
+#[derive(Debug, Clone, Copy)]
+struct Reservation {
+    account_id: AccountId,
+    side: Side,
+    limit_price: Price,
+    remaining: Quantity,
+}
+
+#[derive(Debug, Default)]
+pub struct Accounts {
+    balances: HashMap<AccountId, AccountBalances>,
+    reservations: HashMap<OrderId, Reservation>,
+}
+
+impl Accounts {
+    pub fn new() -> Accounts {
+        Accounts::default()
+    }
+
+    pub fn open_account(&mut self, account_id: AccountId) {
+        self.balances.entry(account_id).or_default();
+    }
+
+    // `Quantity` deposits base, `Notional` quote
+    pub fn deposit<A: Funds>(&mut self, account_id: AccountId, amount: A) -> Result<(), Rejected> {
+        let balance = A::balance_mut(self.balances.entry(account_id).or_default());
+        balance.available = balance
+            .available
+            .checked_add(amount)
+            .ok_or(Rejected::BalanceOverflow(account_id))?;
+        Ok(())
+    }
+
+    pub fn withdraw<A: Funds>(&mut self, account_id: AccountId, amount: A) -> Result<(), Rejected> {
+        let balance = A::balance_mut(
+            self.balances
+                .get_mut(&account_id)
+                .ok_or(Rejected::UnknownAccount(account_id))?,
+        );
+        if balance.available < amount {
+            return Err(Rejected::InsufficientBalance {
+                account_id,
+                required: amount.raw(),
+                available: balance.available.raw(),
+            });
+        }
+        balance.available = balance.available.checked_sub(amount).unwrap();
+        Ok(())
+    }
+
+    pub fn balances(&self, account_id: AccountId) -> Option<&AccountBalances> {
+        self.balances.get(&account_id)
+    }
+
+    // Checks that the account can afford the order and moves the funds to reserved. Prices of
+    // zero or below would reserve nothing or a negative amount, so they are refused.
+    pub(crate) fn reserve(&mut self, order: &Order) -> Result<(), Rejected> {
+        if order.get_price() <= Price::ZERO {
+            return Err(Rejected::InvalidOrder(
+                OrderValidationError::NonPositivePrice(order.get_price()),
+            ));
+        }
+        let reservation = Reservation {
+            account_id: order.get_account_id(),
+            side: order.get_side(),
+            limit_price: order.get_price(),
+            remaining: order.get_remaining_quantity(),
+        };
+        self.hold_reservation(order.get_order_id(), reservation)
+    }
+
+    // Swaps the reservation of a resting order for the one of its amended version. The old
+    // reservation is held again when the new one can't be afforded.
+    pub(crate) fn replace(&mut self, order: &Order) -> Result<(), Rejected> {
+        let order_id = order.get_order_id();
+        let previous = self.reservations.get(&order_id).copied();
+        self.release(order_id);
+        self.reserve(order).inspect_err(|_| {
+            if let Some(previous) = previous {
+                self.hold_reservation(order_id, previous)
+                    .expect("Released funds cover the reservation again");
+            }
+        })
+    }
+
+    fn hold_reservation(
+        &mut self,
+        order_id: OrderId,
+        reservation: Reservation,
+    ) -> Result<(), Rejected> {
+        let account_id = reservation.account_id;
+        let balances = self
+            .balances
+            .get_mut(&account_id)
+            .ok_or(Rejected::UnknownAccount(account_id))?;
+        match reservation.side {
+            Side::Buy => hold(
+                account_id,
+                &mut balances.quote,
+                reservation.limit_price * reservation.remaining,
+            )?,
+            Side::Sell => hold(account_id, &mut balances.base, reservation.remaining)?,
+        }
+        self.reservations.insert(order_id, reservation);
+        Ok(())
+    }
+
+    // Gives back whatever is still held for an order leaving the book
+    pub(crate) fn release(&mut self, order_id: OrderId) {
+        let Some(reservation) = self.reservations.remove(&order_id) else {
+            return;
+        };
+        let Some(balances) = self.balances.get_mut(&reservation.account_id) else {
+            return;
+        };
+        match reservation.side {
+            Side::Buy => {
+                let amount = reservation.limit_price * reservation.remaining;
+                take_reserved(&mut balances.quote, amount);
+                credit(&mut balances.quote, amount);
+            }
+            Side::Sell => {
+                take_reserved(&mut balances.base, reservation.remaining);
+                credit(&mut balances.base, reservation.remaining);
+            }
+        }
+    }
+
+    // Settles one leg of a trade for the order owner
+    pub(crate) fn settle(&mut self, side: Side, leg: &TradeInfo) {
+        let notional = leg.price * leg.quantity;
+        let fee = fee_notional(leg.fee);
+
+        // Reserved funds are consumed first, orders without a reservation pay from available
+        let mut reserved = None;
+        if let Some(reservation) = self.reservations.get_mut(&leg.order_id) {
+            let filled = leg.quantity.min(reservation.remaining);
+            reserved = Some((reservation.limit_price, filled));
+            reservation.remaining -= filled;
+            if reservation.remaining.is_zero() {
+                self.reservations.remove(&leg.order_id);
+            }
+        }
+
+        let balances = self.balances.entry(leg.account_id).or_default();
+        match side {
+            Side::Buy => {
+                if let Some((limit_price, filled)) = reserved {
+                    let used = limit_price * filled;
+                    take_reserved(&mut balances.quote, used);
+                    credit(&mut balances.quote, used);
+                }
+                // Quote is signed, fees can take it below zero
+                balances.quote.available = balances
+                    .quote
+                    .available
+                    .checked_sub(notional)
+                    .and_then(|available| available.checked_sub(fee))
+                    .expect("Balance overflow");
+                credit(&mut balances.base, leg.quantity);
+            }
+            Side::Sell => {
+                if let Some((_, filled)) = reserved {
+                    take_reserved(&mut balances.base, filled);
+                    credit(&mut balances.base, filled);
+                }
+                // An order entered before accounts were enabled has no reservation and can sell
+                // more than the account holds, base floors at zero then
+                balances.base.available = balances.base.available.saturating_sub(leg.quantity);
+                balances.quote.available = balances
+                    .quote
+                    .available
+                    .checked_add(notional)
+                    .and_then(|available| available.checked_sub(fee))
+                    .expect("Balance overflow");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{OrderBook, OrderType};
+
+    fn engine() -> OrderBook {
+        let mut accounts = Accounts::new();
+        accounts.deposit(1, Notional(10_000)).unwrap();
+        accounts.deposit(2, Quantity(100)).unwrap();
+        let mut orderbook = OrderBook::new();
+        orderbook.enable_accounts(accounts);
+        orderbook
+    }
+
+    fn balances(orderbook: &OrderBook, account_id: AccountId) -> AccountBalances {
+        *orderbook.accounts().unwrap().balances(account_id).unwrap()
+    }
+
+    #[test]
+    fn test_resting_orders_reserve_funds() {
+        let mut orderbook = engine();
+        orderbook
+            .place_order(
+                Order::new(
+                    1,
+                    Price(50),
+                    Quantity(100),
+                    OrderType::GoodToCancel,
+                    Side::Buy,
+                )
+                .with_account(1),
+            )
+            .unwrap();
+        orderbook
+            .place_order(
+                Order::new(
+                    2,
+                    Price(60),
+                    Quantity(30),
+                    OrderType::GoodToCancel,
+                    Side::Sell,
+                )
+                .with_account(2),
+            )
+            .unwrap();
+
+        let buyer = balances(&orderbook, 1);
+        assert_eq!(buyer.quote.available, Notional(5_000));
+        assert_eq!(buyer.quote.reserved, Notional(5_000));
+        let seller = balances(&orderbook, 2);
+        assert_eq!(seller.base.available, Quantity(70));
+        assert_eq!(seller.base.reserved, Quantity(30));
+    }
+
+    #[test]
+    fn test_insufficient_balance_and_unknown_account_are_rejected() {
+        let mut orderbook = engine();
+        let rejected = orderbook.place_order(
+            Order::new(
+                1,
+                Price(200),
+                Quantity(100),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )
+            .with_account(1),
+        );
+        assert_eq!(
+            rejected.unwrap_err(),
+            Rejected::InsufficientBalance {
+                account_id: 1,
+                required: 20_000,
+                available: 10_000,
+            }
+        );
+
+        let rejected = orderbook.place_order(
+            Order::new(
+                2,
+                Price(10),
+                Quantity(1),
+                OrderType::GoodToCancel,
+                Side::Sell,
+            )
+            .with_account(9),
+        );
+        assert_eq!(rejected.unwrap_err(), Rejected::UnknownAccount(9));
+        assert_eq!(orderbook.orderbook_size(), 0);
+    }
+
+    #[test]
+    fn test_non_positive_prices_are_rejected() {
+        let mut orderbook = engine();
+        for price in [Price(-1000), Price(0)] {
+            let rejected = orderbook.place_order(
+                Order::new(1, price, Quantity(1000), OrderType::GoodToCancel, Side::Buy)
+                    .with_account(1),
+            );
+            assert_eq!(
+                rejected.unwrap_err(),
+                Rejected::InvalidOrder(OrderValidationError::NonPositivePrice(price))
+            );
+        }
+
+        let buyer = balances(&orderbook, 1);
+        assert_eq!(buyer.quote.available, Notional(10_000));
+        assert_eq!(buyer.quote.reserved, Notional(0));
+        assert!(orderbook
+            .accounts_mut()
+            .unwrap()
+            .withdraw(1, Notional(10_001))
+            .is_err());
+    }
+
+    #[test]
+    fn test_cancel_releases_reservation() {
+        let mut orderbook = engine();
+        orderbook
+            .place_order(
+                Order::new(
+                    1,
+                    Price(50),
+                    Quantity(100),
+                    OrderType::GoodToCancel,
+                    Side::Buy,
+                )
+                .with_account(1),
+            )
+            .unwrap();
+        orderbook.cancel_order(1).unwrap();
+
+        let buyer = balances(&orderbook, 1);
+        assert_eq!(buyer.quote.available, Notional(10_000));
+        assert_eq!(buyer.quote.reserved, Notional(0));
+    }
+
+    #[test]
+    fn test_trades_settle_at_resting_price() {
+        let mut orderbook = engine();
+        orderbook
+            .place_order(
+                Order::new(
+                    1,
+                    Price(40),
+                    Quantity(50),
+                    OrderType::GoodToCancel,
+                    Side::Sell,
+                )
+                .with_account(2),
+            )
+            .unwrap();
+        let trades = orderbook
+            .place_order(
+                Order::new(
+                    2,
+                    Price(50),
+                    Quantity(20),
+                    OrderType::GoodToCancel,
+                    Side::Buy,
+                )
+                .with_account(1),
+            )
+            .unwrap();
+        assert_eq!(trades[0].bid_trade.price, Price(40));
+        assert_eq!(trades[0].ask_trade.price, Price(40));
+
+        let buyer = balances(&orderbook, 1);
+        assert_eq!(buyer.quote.available, Notional(10_000 - 800));
+        assert_eq!(buyer.quote.reserved, Notional(0));
+        assert_eq!(buyer.base.available, Quantity(20));
+
+        let seller = balances(&orderbook, 2);
+        assert_eq!(seller.base.available, Quantity(50));
+        assert_eq!(seller.base.reserved, Quantity(30));
+        assert_eq!(seller.quote.available, Notional(800));
+    }
+
+    #[test]
+    fn test_fees_are_settled_in_quote() {
+        use crate::fees::{Fee, FeeRates};
+
+        let mut orderbook = engine();
+        orderbook.set_fees(FeeRates::new(Fee::Fixed(1.0), Fee::Fixed(2.0)));
+        orderbook
+            .place_order(
+                Order::new(
+                    1,
+                    Price(40),
+                    Quantity(10),
+                    OrderType::GoodToCancel,
+                    Side::Sell,
+                )
+                .with_account(2),
+            )
+            .unwrap();
+        orderbook
+            .place_order(
+                Order::new(
+                    2,
+                    Price(40),
+                    Quantity(10),
+                    OrderType::GoodToCancel,
+                    Side::Buy,
+                )
+                .with_account(1),
+            )
+            .unwrap();
+
+        assert_eq!(
+            balances(&orderbook, 1).quote.available,
+            Notional(10_000 - 400 - 2)
+        );
+        assert_eq!(balances(&orderbook, 2).quote.available, Notional(400 - 1));
+
+        // Fractional fees are rounded up to a whole notional unit, 2 bps of 400 is 0.08
+        orderbook.set_fees(FeeRates::new(Fee::Bps(2.0), Fee::Bps(2.0)));
+        for (order_id, side, account_id) in [(3, Side::Sell, 2), (4, Side::Buy, 1)] {
+            orderbook
+                .place_order(
+                    Order::new(
+                        order_id,
+                        Price(40),
+                        Quantity(10),
+                        OrderType::GoodToCancel,
+                        side,
+                    )
+                    .with_account(account_id),
+                )
+                .unwrap();
+        }
+        let buyer = balances(&orderbook, 1);
+        assert_eq!(buyer.quote.available, Notional(10_000 - 800 - 3));
+        assert_eq!(buyer.quote.reserved, Notional(0));
+        assert_eq!(balances(&orderbook, 2).quote.available, Notional(800 - 2));
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let mut accounts = Accounts::new();
+        accounts.deposit(1, Quantity(5)).unwrap();
+        assert!(accounts.withdraw(1, Quantity(3)).is_ok());
+        assert!(accounts.withdraw(1, Quantity(3)).is_err());
+        assert_eq!(
+            accounts.withdraw(2, Quantity(1)),
+            Err(Rejected::UnknownAccount(2))
+        );
+        assert_eq!(
+            accounts.balances(1).unwrap().base.total(),
+            Some(Quantity(2))
+        );
+        assert_eq!(
+            accounts.deposit(1, Quantity::MAX),
+            Err(Rejected::BalanceOverflow(1))
+        );
+    }
+}
@@ -0,0 +1,440 @@
+/// Alerts on fast moves of a live book.
+/// `AlertMonitor` follows the book of one instrument and checks three rules after every change:
+/// the mid moved more than some basis points from where it was within a time window, the spread
+/// is wider than some basis points of the mid, or the resting quantity of the best levels of a
+/// side fell by more than some fraction of its peak within a window. Each rule fires when its
+/// condition becomes true and again only after it cleared, so a sustained move is one alert and
+/// not one per payload. The monitor counts what it fired for the metrics report. Sizes are in
+/// base units.
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::{Side, Timestamp};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidMoveRule {
+    pub bps: f64,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthCollapseRule {
+    // Best levels of each side summed up
+    pub levels: usize,
+    // Fraction of the peak that has to go, 0.5 for half of it
+    pub drop: f64,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlertRules {
+    pub mid_move: Option<MidMoveRule>,
+    pub max_spread_bps: Option<f64>,
+    pub depth_collapse: Option<DepthCollapseRule>,
+}
+
+impl AlertRules {
+    // No rule, add them with the `with_*` methods
+    pub fn new() -> AlertRules {
+        AlertRules::default()
+    }
+
+    pub fn with_mid_move(mut self, bps: f64, window: Duration) -> AlertRules {
+        self.mid_move = Some(MidMoveRule { bps, window });
+        self
+    }
+
+    pub fn with_max_spread(mut self, bps: f64) -> AlertRules {
+        self.max_spread_bps = Some(bps);
+        self
+    }
+
+    pub fn with_depth_collapse(mut self, levels: usize, drop: f64, window: Duration) -> AlertRules {
+        self.depth_collapse = Some(DepthCollapseRule {
+            levels,
+            drop,
+            window,
+        });
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertKind {
+    // `bps` is signed, negative for a fall
+    MidMove { from: f64, to: f64, bps: f64 },
+    SpreadWide { spread: f64, bps: f64 },
+    DepthCollapse { side: Side, peak: f64, depth: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    pub timestamp: Timestamp,
+    pub kind: AlertKind,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertKind::MidMove { from, to, bps } => {
+                write!(f, "mid moved {:+.1} bps from {} to {}", bps, from, to)
+            }
+            AlertKind::SpreadWide { spread, bps } => {
+                write!(f, "spread of {} is {:.1} bps of the mid", spread, bps)
+            }
+            AlertKind::DepthCollapse { side, peak, depth } => {
+                write!(f, "{:?} depth fell from {} to {}", side, peak, depth)
+            }
+        }
+    }
+}
+
+// Alerts fired so far, by rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertCounts {
+    pub mid_moves: u64,
+    pub wide_spreads: u64,
+    pub depth_collapses: u64,
+}
+
+impl fmt::Display for AlertCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "alerts: {} mid moves, {} wide spreads, {} depth collapses",
+            self.mid_moves, self.wide_spreads, self.depth_collapses
+        )
+    }
+}
+
+// What the rules look at in a book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookSample {
+    pub mid: f64,
+    pub spread: f64,
+    // Quantity of the best `DepthCollapseRule::levels` of each side
+    pub bid_depth: f64,
+    pub ask_depth: f64,
+}
+
+// Highest and lowest value of the last `length` of time. Each deque holds the values that can
+// still become the extreme once older ones expire, oldest first: strictly decreasing for the
+// maxima, strictly increasing for the minima. A push is O(1) amortized.
+#[derive(Debug, Clone)]
+struct Window {
+    length: Duration,
+    maxima: VecDeque<(Timestamp, f64)>,
+    minima: VecDeque<(Timestamp, f64)>,
+}
+
+impl Window {
+    fn new(length: Duration) -> Window {
+        Window {
+            length,
+            maxima: VecDeque::new(),
+            minima: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, timestamp: Timestamp, value: f64) {
+        let length = self.length.as_nanos() as Timestamp;
+        for values in [&mut self.maxima, &mut self.minima] {
+            while let Some(&(oldest, _)) = values.front() {
+                if timestamp.saturating_sub(oldest) <= length {
+                    break;
+                }
+                values.pop_front();
+            }
+        }
+        while let Some(&(_, last)) = self.maxima.back() {
+            if last > value {
+                break;
+            }
+            self.maxima.pop_back();
+        }
+        self.maxima.push_back((timestamp, value));
+        while let Some(&(_, last)) = self.minima.back() {
+            if last < value {
+                break;
+            }
+            self.minima.pop_back();
+        }
+        self.minima.push_back((timestamp, value));
+    }
+
+    // Value of the window furthest from `value`, the older one on a tie
+    fn furthest(&self, value: f64) -> f64 {
+        match (self.maxima.front(), self.minima.front()) {
+            (Some(&(high_at, high)), Some(&(low_at, low))) => {
+                let (above, below) = (high - value, value - low);
+                if above > below || (above == below && high_at <= low_at) {
+                    high
+                } else {
+                    low
+                }
+            }
+            _ => value,
+        }
+    }
+
+    fn peak(&self) -> f64 {
+        self.maxima
+            .front()
+            .map_or(0.0, |&(_, value)| value.max(0.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertMonitor {
+    rules: AlertRules,
+    mids: Option<Window>,
+    // Bid then ask
+    depths: Option<[Window; 2]>,
+    // Whether the condition of each rule held after the last sample
+    mid_moved: bool,
+    spread_wide: bool,
+    collapsed: [bool; 2],
+    counts: AlertCounts,
+}
+
+impl AlertMonitor {
+    pub fn new(rules: AlertRules) -> AlertMonitor {
+        AlertMonitor {
+            mids: rules.mid_move.map(|rule| Window::new(rule.window)),
+            depths: rules
+                .depth_collapse
+                .map(|rule| [Window::new(rule.window), Window::new(rule.window)]),
+            rules,
+            mid_moved: false,
+            spread_wide: false,
+            collapsed: [false; 2],
+            counts: AlertCounts::default(),
+        }
+    }
+
+    pub fn rules(&self) -> &AlertRules {
+        &self.rules
+    }
+
+    pub fn counts(&self) -> AlertCounts {
+        self.counts
+    }
+
+    // Alerts fired by the book as it is now, none while a side is empty
+    pub fn on_book(&mut self, timestamp: Timestamp, book: &OrderBook) -> Vec<Alert> {
+        let (Some(mid), Some(spread)) = (book.mid_price(), book.spread()) else {
+            return Vec::new();
+        };
+        let converter = book.converter();
+        let levels = self.rules.depth_collapse.map_or(0, |rule| rule.levels);
+        let depth = |side: &mut dyn Iterator<Item = _>| {
+            side.take(levels)
+                .map(|(_, quantity)| converter.to_f64(quantity))
+                .sum::<f64>()
+        };
+        let sample = BookSample {
+            mid,
+            spread,
+            bid_depth: depth(&mut book.bids()),
+            ask_depth: depth(&mut book.asks()),
+        };
+        self.on_sample(timestamp, sample)
+    }
+
+    // Same as `on_book` from the values the rules look at
+    pub fn on_sample(&mut self, timestamp: Timestamp, sample: BookSample) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        let mut fire = |kind| alerts.push(Alert { timestamp, kind });
+
+        if let (Some(rule), Some(mids)) = (self.rules.mid_move, self.mids.as_mut()) {
+            mids.push(timestamp, sample.mid);
+            let from = mids.furthest(sample.mid);
+            let bps = (sample.mid - from) / from * 10_000.0;
+            let moved = from > 0.0 && bps.abs() > rule.bps;
+            if moved && !self.mid_moved {
+                self.counts.mid_moves += 1;
+                fire(AlertKind::MidMove {
+                    from,
+                    to: sample.mid,
+                    bps,
+                });
+            }
+            self.mid_moved = moved;
+        }
+
+        if let Some(max_bps) = self.rules.max_spread_bps {
+            let bps = sample.spread / sample.mid * 10_000.0;
+            let wide = sample.mid > 0.0 && bps > max_bps;
+            if wide && !self.spread_wide {
+                self.counts.wide_spreads += 1;
+                fire(AlertKind::SpreadWide {
+                    spread: sample.spread,
+                    bps,
+                });
+            }
+            self.spread_wide = wide;
+        }
+
+        if let (Some(rule), Some(depths)) = (self.rules.depth_collapse, self.depths.as_mut()) {
+            let sides = [
+                (Side::Buy, sample.bid_depth),
+                (Side::Sell, sample.ask_depth),
+            ];
+            for (index, (side, depth)) in sides.into_iter().enumerate() {
+                depths[index].push(timestamp, depth);
+                let peak = depths[index].peak();
+                let collapsed = peak > 0.0 && depth < peak * (1.0 - rule.drop);
+                if collapsed && !self.collapsed[index] {
+                    self.counts.depth_collapses += 1;
+                    fire(AlertKind::DepthCollapse { side, peak, depth });
+                }
+                self.collapsed[index] = collapsed;
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::BookTickerUpdate;
+    use crate::symbol::Symbol;
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    fn sample(mid: f64, spread: f64, bid_depth: f64, ask_depth: f64) -> BookSample {
+        BookSample {
+            mid,
+            spread,
+            bid_depth,
+            ask_depth,
+        }
+    }
+
+    #[test]
+    fn test_rules_fire_once_per_crossing() {
+        let mut monitor = AlertMonitor::new(
+            AlertRules::new()
+                .with_mid_move(50.0, Duration::from_secs(10))
+                .with_max_spread(20.0)
+                .with_depth_collapse(5, 0.5, Duration::from_secs(30)),
+        );
+        assert!(monitor
+            .on_sample(0, sample(100.0, 0.1, 10.0, 10.0))
+            .is_empty());
+        // 40 bps, below the rule
+        assert!(monitor
+            .on_sample(SECOND, sample(100.4, 0.1, 10.0, 10.0))
+            .is_empty());
+
+        let alerts = monitor.on_sample(2 * SECOND, sample(100.6, 0.1, 10.0, 10.0));
+        let [Alert {
+            timestamp,
+            kind: AlertKind::MidMove { from, to, bps },
+        }] = alerts[..]
+        else {
+            panic!("mid move expected, got {:?}", alerts);
+        };
+        assert_eq!((timestamp, from, to), (2 * SECOND, 100.0, 100.6));
+        assert!((bps - 60.0).abs() < 1e-9);
+        // Still moved, no new alert
+        assert!(monitor
+            .on_sample(3 * SECOND, sample(100.7, 0.1, 10.0, 10.0))
+            .is_empty());
+        // The 100.0 mid left the window, the move cleared
+        assert!(monitor
+            .on_sample(12 * SECOND, sample(100.7, 0.1, 10.0, 10.0))
+            .is_empty());
+        // A fall fires again
+        let alerts = monitor.on_sample(13 * SECOND, sample(100.1, 0.1, 10.0, 10.0));
+        assert!(matches!(
+            alerts[..],
+            [Alert {
+                kind: AlertKind::MidMove { bps, .. },
+                ..
+            }] if bps < -50.0
+        ));
+
+        // 30 bps spread and the asks lose 70% of their peak
+        let alerts = monitor.on_sample(14 * SECOND, sample(100.1, 0.3, 10.0, 3.0));
+        assert_eq!(alerts.len(), 2);
+        assert!(matches!(alerts[0].kind, AlertKind::SpreadWide { .. }));
+        assert_eq!(
+            alerts[1].kind,
+            AlertKind::DepthCollapse {
+                side: Side::Sell,
+                peak: 10.0,
+                depth: 3.0
+            }
+        );
+        assert_eq!(
+            monitor.counts(),
+            AlertCounts {
+                mid_moves: 2,
+                wide_spreads: 1,
+                depth_collapses: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_on_book() {
+        let mut book = OrderBook::new("BTCUSDT");
+        let mut monitor = AlertMonitor::new(AlertRules::new().with_max_spread(10.0));
+        assert!(monitor.on_book(0, &book).is_empty());
+        book.update_book_ticker(&BookTickerUpdate {
+            event_time: None,
+            update_id: 1,
+            symbol: Symbol::intern("BTCUSDT"),
+            best_bid_price: 100.0,
+            best_bid_quantity: 1.0,
+            best_ask_price: 100.5,
+            best_ask_quantity: 1.0,
+        })
+        .unwrap();
+        let alerts = monitor.on_book(SECOND, &book);
+        // 0.5 wide around a mid of 100.25
+        let [Alert {
+            kind: AlertKind::SpreadWide { spread, bps },
+            ..
+        }] = alerts[..]
+        else {
+            panic!("wide spread expected, got {:?}", alerts);
+        };
+        assert_eq!(spread, 0.5);
+        assert!((bps - 49.875).abs() < 0.01);
+    }
+
+    // The running extremes agree with a scan of every value in the window
+    #[test]
+    fn test_window_extremes() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut window = Window::new(Duration::from_secs(5));
+        let mut values: VecDeque<(Timestamp, f64)> = VecDeque::new();
+        let mut timestamp = 0;
+        for _ in 0..5_000 {
+            timestamp += rng.gen_range(0..SECOND);
+            let value = rng.gen_range(0..20) as f64;
+            window.push(timestamp, value);
+            values.push_back((timestamp, value));
+            values.retain(|&(at, _)| timestamp - at <= 5 * SECOND);
+
+            let high = values.iter().map(|&(_, v)| v).fold(f64::MIN, f64::max);
+            let low = values.iter().map(|&(_, v)| v).fold(f64::MAX, f64::min);
+            let expected = if high - value >= value - low {
+                high
+            } else {
+                low
+            };
+            assert_eq!(window.peak(), high);
+            assert_eq!(
+                (window.furthest(value) - value).abs(),
+                (expected - value).abs()
+            );
+        }
+    }
+}
@@ -0,0 +1,184 @@
+/// Per-order audit trail of the matching engine.
+/// Once enabled the engine records what happened to every order it accepted: entry,
+/// amendments, each fill with its price and quantity, and how the order left the book. Every
+/// cancellation carries a reason code, whether the user asked for it or the engine did it on
+/// its own, e.g. the remainder of a Fill and Kill order or a Day order at the session close.
+use crate::orderbookv2::{OrderId, Price, Quantity, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelReason {
+    User,
+    FillAndKillRemainder,
+    SelfTradePrevention,
+    // Day order swept by `OrderBook::roll_session`
+    Expired,
+    // Day order cancelled when the session state moved to Closed
+    SessionClose,
+    Risk,
+    // An amendment removes the order before entering it again, the new version was rejected
+    AmendRejected,
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            CancelReason::User => "user",
+            CancelReason::FillAndKillRemainder => "fak-remainder",
+            CancelReason::SelfTradePrevention => "stp",
+            CancelReason::Expired => "expired",
+            CancelReason::SessionClose => "session-close",
+            CancelReason::Risk => "risk",
+            CancelReason::AmendRejected => "amend-rejected",
+        };
+        write!(f, "{}", code)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderEvent {
+    Accepted {
+        price: Price,
+        quantity: Quantity,
+    },
+    Amended {
+        price: Price,
+        quantity: Quantity,
+    },
+    // A partial fill leaves a remaining quantity above zero
+    Filled {
+        price: Price,
+        quantity: Quantity,
+        remaining: Quantity,
+    },
+    Cancelled {
+        reason: CancelReason,
+        remaining: Quantity,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderAuditRecord {
+    pub timestamp: Timestamp,
+    pub event: OrderEvent,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditTrail {
+    histories: HashMap<OrderId, Vec<OrderAuditRecord>>,
+}
+
+impl AuditTrail {
+    pub fn new() -> AuditTrail {
+        AuditTrail::default()
+    }
+
+    pub fn record(&mut self, order_id: OrderId, timestamp: Timestamp, event: OrderEvent) {
+        self.histories
+            .entry(order_id)
+            .or_default()
+            .push(OrderAuditRecord { timestamp, event });
+    }
+
+    // Oldest first, empty for an order the trail has not seen
+    pub fn history(&self, order_id: OrderId) -> &[OrderAuditRecord] {
+        self.histories
+            .get(&order_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.histories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.histories.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{Order, OrderBook, OrderModify, OrderType, Side};
+
+    fn events(orderbook: &OrderBook, order_id: OrderId) -> Vec<OrderEvent> {
+        orderbook
+            .order_history(order_id)
+            .iter()
+            .map(|record| record.event)
+            .collect()
+    }
+
+    #[test]
+    fn test_order_history_and_cancel_reasons() {
+        let mut orderbook = OrderBook::new();
+        orderbook.enable_audit_trail();
+        let order = |order_id, side, price, quantity, order_type| {
+            Order::new(order_id, Price(price), Quantity(quantity), order_type, side)
+        };
+        let gtc = OrderType::GoodToCancel;
+
+        orderbook.add_order(order(1, Side::Sell, 100, 10, gtc));
+        orderbook.add_order(order(2, Side::Buy, 100, 4, gtc));
+        orderbook.match_order(OrderModify::new(1, Side::Sell, Price(101), Quantity(8)));
+        orderbook.add_order(order(3, Side::Buy, 101, 10, OrderType::FillAndKill));
+        orderbook.add_order(order(4, Side::Buy, 90, 1, OrderType::Day));
+        orderbook.add_order(order(5, Side::Buy, 91, 1, gtc));
+        orderbook.roll_session();
+        orderbook
+            .cancel_order_with_reason(5, CancelReason::Risk)
+            .unwrap();
+
+        assert_eq!(
+            events(&orderbook, 1),
+            vec![
+                OrderEvent::Accepted {
+                    price: Price(100),
+                    quantity: Quantity(10)
+                },
+                OrderEvent::Filled {
+                    price: Price(100),
+                    quantity: Quantity(4),
+                    remaining: Quantity(6)
+                },
+                OrderEvent::Amended {
+                    price: Price(101),
+                    quantity: Quantity(8)
+                },
+                OrderEvent::Filled {
+                    price: Price(101),
+                    quantity: Quantity(8),
+                    remaining: Quantity(0)
+                },
+            ]
+        );
+        assert_eq!(
+            events(&orderbook, 3)[1..],
+            [
+                OrderEvent::Filled {
+                    price: Price(101),
+                    quantity: Quantity(8),
+                    remaining: Quantity(2)
+                },
+                OrderEvent::Cancelled {
+                    reason: CancelReason::FillAndKillRemainder,
+                    remaining: Quantity(2)
+                },
+            ]
+        );
+        let last_reason = |order_id| match events(&orderbook, order_id).last() {
+            Some(OrderEvent::Cancelled { reason, .. }) => Some(*reason),
+            _ => None,
+        };
+        assert_eq!(last_reason(4), Some(CancelReason::Expired));
+        assert_eq!(last_reason(5), Some(CancelReason::Risk));
+        assert!(orderbook.order_history(42).is_empty());
+        assert_eq!(
+            CancelReason::FillAndKillRemainder.to_string(),
+            "fak-remainder"
+        );
+    }
+}
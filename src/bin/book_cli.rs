@@ -0,0 +1,472 @@
+use binance_orderbook::book_stream::OrderBookStream;
+use binance_orderbook::clock::{self, Stamp};
+use binance_orderbook::config::BinanceConfig;
+use binance_orderbook::historical::{self, TardisCsvReader};
+use binance_orderbook::latency::{LatencyStage, LatencyStatistics};
+use binance_orderbook::liquidity::LiquidityProfiler;
+use binance_orderbook::orderbook::{DepthSnapshot, OrderBook};
+use binance_orderbook::recording::{self, Recorder, ReplayStatistics, SeriesSummary};
+use binance_orderbook::shutdown::Shutdown;
+use binance_spot_connector_rust::{
+    market_stream::book_ticker::BookTickerStream, market_stream::partial_depth::PartialDepthStream,
+    tokio_tungstenite::BinanceWebSocketClient,
+};
+use env_logger::Builder;
+use futures_util::{future, SinkExt, StreamExt};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+const USAGE: &str = "\
+Usage: book-cli <command> [options]
+
+Commands:
+  record <symbol> [--out <file>] [--levels <n>]      capture the depth and book ticker streams
+  replay <file> [--symbol <symbol>] [--levels <n>]   rebuild the book and print it
+  stats <file> [--symbol <symbol>]                   spread, imbalance and latency summaries
+  liquidity <file> [--symbol <symbol>] [--format <json|csv>]
+                                                     liquidity profile of the whole file
+  serve <symbol> [--listen <addr>] [--levels <n>]    republish depth snapshots over websocket
+
+The recording symbol defaults to the file name, e.g. ETHUSDC for ethusdc.jsonl. replay and stats
+also read decompressed Tardis.dev CSV exports (.csv files), pass --symbol for those.";
+
+const DEFAULT_LEVELS: u16 = 20;
+const DEFAULT_LISTEN: &str = "127.0.0.1:9001";
+// How long serve waits for its clients to close after Ctrl-C
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+enum Command {
+    Record {
+        symbol: String,
+        out: PathBuf,
+        levels: u16,
+    },
+    Replay {
+        file: PathBuf,
+        symbol: String,
+        levels: u16,
+    },
+    Stats {
+        file: PathBuf,
+        symbol: String,
+    },
+    Liquidity {
+        file: PathBuf,
+        symbol: String,
+        csv: bool,
+    },
+    Serve {
+        symbol: String,
+        listen: String,
+        levels: u16,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    Builder::from_default_env().init();
+
+    let command = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                log::info!("Shutting down");
+                shutdown.trigger();
+            }
+        }
+    });
+    let result = match command {
+        Command::Record {
+            symbol,
+            out,
+            levels,
+        } => record(&symbol, &out, levels, &shutdown).await,
+        Command::Replay {
+            file,
+            symbol,
+            levels,
+        } => replay(&file, &symbol, levels),
+        Command::Stats { file, symbol } => stats(&file, &symbol),
+        Command::Liquidity { file, symbol, csv } => liquidity(&file, &symbol, csv),
+        Command::Serve {
+            symbol,
+            listen,
+            levels,
+        } => serve(&symbol, &listen, levels, &shutdown).await,
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let name = args.next().ok_or("missing command")?;
+    let target = args
+        .next()
+        .ok_or_else(|| format!("{} needs an argument", name))?;
+
+    let mut options = Vec::new();
+    while let Some(option) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", option))?;
+        options.push((option, value));
+    }
+    let mut option = |flag: &str| {
+        options
+            .iter()
+            .position(|(option, _)| option == flag)
+            .map(|index| options.remove(index).1)
+    };
+    let levels = match option("--levels") {
+        Some(levels) => levels
+            .parse()
+            .map_err(|_| format!("invalid level count {}", levels))?,
+        None => DEFAULT_LEVELS,
+    };
+
+    let command = match name.as_str() {
+        "record" => Command::Record {
+            out: option("--out")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.jsonl", target.to_lowercase()))),
+            symbol: target.to_uppercase(),
+            levels,
+        },
+        "replay" | "stats" | "liquidity" => {
+            let file = PathBuf::from(&target);
+            let symbol = option("--symbol")
+                .unwrap_or_else(|| symbol_from_path(&file))
+                .to_uppercase();
+            match name.as_str() {
+                "replay" => Command::Replay {
+                    file,
+                    symbol,
+                    levels,
+                },
+                "stats" => Command::Stats { file, symbol },
+                _ => Command::Liquidity {
+                    file,
+                    symbol,
+                    csv: match option("--format").as_deref() {
+                        None | Some("json") => false,
+                        Some("csv") => true,
+                        Some(format) => return Err(format!("unknown format {}", format)),
+                    },
+                },
+            }
+        }
+        "serve" => Command::Serve {
+            symbol: target.to_uppercase(),
+            listen: option("--listen").unwrap_or_else(|| DEFAULT_LISTEN.to_string()),
+            levels,
+        },
+        _ => return Err(format!("unknown command {}", name)),
+    };
+    match options.first() {
+        Some((option, _)) => Err(format!("unknown option {} for {}", option, name)),
+        None => Ok(command),
+    }
+}
+
+fn symbol_from_path(file: &Path) -> String {
+    file.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Records until the stream ends or Ctrl-C
+async fn record(symbol: &str, out: &Path, levels: u16, shutdown: &Shutdown) -> Result<(), String> {
+    let config = BinanceConfig::from_env().map_err(|error| error.to_string())?;
+    let mut recorder = Recorder::open(out).map_err(|error| error.to_string())?;
+    let (mut conn, _) = BinanceWebSocketClient::connect_async(&config.ws_url)
+        .await
+        .map_err(|error| format!("failed to connect: {}", error))?;
+    conn.subscribe(vec![
+        &PartialDepthStream::from_100ms(symbol, levels).into(),
+        &BookTickerStream::from_symbol(symbol).into(),
+    ])
+    .await;
+    log::info!("Recording {} to {}", symbol, out.display());
+
+    let clock = clock::system();
+    let signal = shutdown.signal();
+    loop {
+        let message = tokio::select! {
+            message = conn.as_mut().next() => message,
+            _ = signal.triggered() => break,
+        };
+        match message {
+            Some(Ok(message)) if message.is_text() || message.is_binary() => {
+                let received = clock.stamp();
+                let payload = String::from_utf8_lossy(&message.into_data()).into_owned();
+                recorder
+                    .record(received, &payload)
+                    .map_err(|error| error.to_string())?;
+            }
+            Some(Ok(_)) => {}
+            Some(Err(error)) => {
+                log::error!("Broken message received from the socket: {}", error);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    recorder.flush().map_err(|error| error.to_string())?;
+    log::info!("Recorded {} payloads", recorder.len());
+    conn.close().await.map_err(|error| error.to_string())
+}
+
+// `observe` sees the book after every update
+fn replay_file(
+    file: &Path,
+    symbol: &str,
+    observe: impl FnMut(Stamp, &OrderBook),
+) -> Result<(OrderBook, ReplayStatistics), String> {
+    let cannot_read =
+        |error: &dyn std::fmt::Display| format!("cannot read {}: {}", file.display(), error);
+    let (orderbook, statistics) = if file.extension().is_some_and(|extension| extension == "csv") {
+        let mut reader = TardisCsvReader::open(file).map_err(|error| cannot_read(&error))?;
+        historical::replay_with(symbol, &mut reader, observe)
+            .map_err(|error| cannot_read(&error))?
+    } else {
+        let payloads = recording::read_recording(file).map_err(|error| cannot_read(&error))?;
+        recording::replay_with(symbol, payloads, observe)
+    };
+    Ok((orderbook, statistics))
+}
+
+fn print_replay(orderbook: &OrderBook, statistics: &ReplayStatistics) {
+    println!(
+        "{} payloads, {} unrecognized, state digest {}",
+        statistics.payloads,
+        statistics.unrecognized,
+        orderbook.state_digest()
+    );
+}
+
+fn replay(file: &Path, symbol: &str, levels: u16) -> Result<(), String> {
+    let (orderbook, statistics) = replay_file(file, symbol, |_, _| {})?;
+    print_replay(&orderbook, &statistics);
+    print!("{}", orderbook.snapshot(levels.into()));
+    Ok(())
+}
+
+fn stats(file: &Path, symbol: &str) -> Result<(), String> {
+    let (orderbook, statistics) = replay_file(file, symbol, |_, _| {})?;
+    print_replay(&orderbook, &statistics);
+    print_summary("spread", statistics.spread);
+    print_summary("imbalance", statistics.imbalance);
+    print_latency(
+        "network latency",
+        statistics.latency.statistics(LatencyStage::Network),
+    );
+    Ok(())
+}
+
+// Report on stdout, nothing else is printed so it can be piped
+fn liquidity(file: &Path, symbol: &str, csv: bool) -> Result<(), String> {
+    let mut profiler = LiquidityProfiler::default();
+    let (orderbook, _) = replay_file(file, symbol, |received, orderbook| {
+        profiler.observe(received.wall, orderbook)
+    })?;
+    let report = profiler.report(orderbook.symbol());
+    if csv {
+        report
+            .write_csv(std::io::stdout())
+            .map_err(|error| error.to_string())
+    } else {
+        let json = report.to_json().map_err(|error| error.to_string())?;
+        println!("{}", json);
+        Ok(())
+    }
+}
+
+fn print_summary(name: &str, summary: Option<SeriesSummary>) {
+    match summary {
+        Some(summary) => println!(
+            "{}: n={} min={:.6} mean={:.6} max={:.6}",
+            name, summary.count, summary.min, summary.mean, summary.max
+        ),
+        None => println!("{}: no samples", name),
+    }
+}
+
+// Milliseconds, the samples are in nanoseconds
+fn print_latency(name: &str, statistics: Option<LatencyStatistics>) {
+    let ms = |nanos: i64| nanos as f64 / 1e6;
+    match statistics {
+        Some(statistics) => println!(
+            "{} (ms): n={} min={:.3} p50={:.3} p90={:.3} p99={:.3} max={:.3}",
+            name,
+            statistics.count,
+            ms(statistics.min),
+            ms(statistics.p50),
+            ms(statistics.p90),
+            ms(statistics.p99),
+            ms(statistics.max)
+        ),
+        None => println!("{}: no samples", name),
+    }
+}
+
+// Sends every changed depth snapshot as a JSON text message to all connected clients. Slow
+// clients skip snapshots instead of holding the feed back. On shutdown the listener stops
+// accepting, the final snapshot goes out and every client gets a close frame.
+async fn serve(symbol: &str, listen: &str, levels: u16, shutdown: &Shutdown) -> Result<(), String> {
+    let config = BinanceConfig::from_env().map_err(|error| error.to_string())?;
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|error| format!("cannot listen on {}: {}", listen, error))?;
+    let (sender, _) = broadcast::channel::<String>(64);
+
+    let clients = sender.clone();
+    let accepting = shutdown.clone();
+    tokio::spawn(async move {
+        let signal = accepting.signal();
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                },
+                _ = signal.triggered() => break,
+            };
+            let mut snapshots = clients.subscribe();
+            let client = accepting.register(format!("client {}", peer));
+            tokio::spawn(async move {
+                let mut websocket = match tokio_tungstenite::accept_async(stream).await {
+                    Ok(websocket) => websocket,
+                    Err(error) => {
+                        log::warn!("Handshake with {} failed: {}", peer, error);
+                        return;
+                    }
+                };
+                log::info!("Client {} connected", peer);
+                // Runs until the feed drops the sender after its final snapshot
+                loop {
+                    match snapshots.recv().await {
+                        Ok(snapshot) => {
+                            if websocket.send(Message::Text(snapshot)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::warn!("Client {} skipped {} snapshots", peer, skipped)
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+                let _ = websocket.close(None).await;
+                log::info!("Client {} disconnected", peer);
+                drop(client);
+            });
+        }
+    });
+
+    let (mut conn, _) = BinanceWebSocketClient::connect_async(&config.ws_url)
+        .await
+        .map_err(|error| format!("failed to connect: {}", error))?;
+    conn.subscribe(vec![
+        &PartialDepthStream::from_100ms(symbol, levels).into(),
+        &BookTickerStream::from_symbol(symbol).into(),
+    ])
+    .await;
+    log::info!("Serving {} on ws://{}", symbol, listen);
+
+    let payloads = conn
+        .as_mut()
+        .take_while(|message| future::ready(message.is_ok()))
+        .filter_map(|message| {
+            future::ready(
+                message
+                    .ok()
+                    .filter(|message| message.is_text() || message.is_binary())
+                    .map(|message| message.into_data()),
+            )
+        });
+    let publish = |snapshot: &DepthSnapshot| match serde_json::to_string(snapshot) {
+        // No receivers is fine, nobody is connected yet
+        Ok(json) => drop(sender.send(json)),
+        Err(error) => log::error!("Cannot encode snapshot: {}", error),
+    };
+    let mut snapshots = OrderBookStream::new(symbol, Box::pin(payloads), levels.into());
+    let signal = shutdown.signal();
+    loop {
+        let snapshot = tokio::select! {
+            snapshot = snapshots.next() => snapshot,
+            _ = signal.triggered() => None,
+        };
+        match snapshot {
+            Some(snapshot) => publish(&snapshot),
+            None => break,
+        }
+    }
+    publish(&snapshots.orderbook().snapshot(levels.into()));
+    drop(snapshots);
+    drop(sender);
+    if let Err(pending) = shutdown.shutdown_timeout(DRAIN_TIMEOUT).await {
+        log::warn!("Gave up waiting for {}", pending.join(", "));
+    }
+
+    conn.close().await.map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        match parse_args(args("record ethusdc --levels 5")).unwrap() {
+            Command::Record {
+                symbol,
+                out,
+                levels,
+            } => {
+                assert_eq!(symbol, "ETHUSDC");
+                assert_eq!(out, PathBuf::from("ethusdc.jsonl"));
+                assert_eq!(levels, 5);
+            }
+            command => panic!("unexpected {:?}", command),
+        }
+        match parse_args(args("stats data/btcusdt.jsonl")).unwrap() {
+            Command::Stats { symbol, .. } => assert_eq!(symbol, "BTCUSDT"),
+            command => panic!("unexpected {:?}", command),
+        }
+        match parse_args(args("liquidity a.csv --symbol ethusdc --format csv")).unwrap() {
+            Command::Liquidity { symbol, csv, .. } => {
+                assert_eq!((symbol.as_str(), csv), ("ETHUSDC", true))
+            }
+            command => panic!("unexpected {:?}", command),
+        }
+        assert!(parse_args(args("liquidity a.csv --format xml")).is_err());
+        assert!(parse_args(args("replay a.jsonl --listen x")).is_err());
+        assert!(parse_args(args("serve ethusdc --listen")).is_err());
+        assert!(parse_args(args("publish ethusdc")).is_err());
+        assert!(parse_args(vec![]).is_err());
+    }
+}
@@ -1,5 +1,9 @@
+use crate::orderbookv2::Side;
+use crate::symbol::Symbol;
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::num::ParseFloatError;
 
 // Transport types to work with Binance API
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,10 +14,13 @@ pub struct BookTickerUpdateEnvelope {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BookTickerUpdate {
+    // Event time in milliseconds, only sent on the streams that carry it
+    #[serde(rename = "E", default, skip_serializing_if = "Option::is_none")]
+    pub event_time: Option<u64>,
     #[serde(rename = "u")]
     pub update_id: u64,
     #[serde(rename = "s")]
-    pub symbol: String,
+    pub symbol: Symbol,
     #[serde(
         rename = "b",
         deserialize_with = "deserialize_string_to_f64",
@@ -48,6 +55,8 @@ pub struct DepthUpdateEnvelope {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DepthUpdate {
+    #[serde(rename = "E", default, skip_serializing_if = "Option::is_none")]
+    pub event_time: Option<u64>,
     #[serde(rename = "lastUpdateId")]
     pub last_update_id: u64,
     #[serde(
@@ -64,6 +73,486 @@ pub struct DepthUpdate {
     pub asks: Vec<(f64, f64)>,
 }
 
+// Borrowed variants of the payloads, the strings point into the websocket frame so decoding
+// a message does not allocate the symbol or the decimal strings. `to_owned` parses the
+// decimals into the owned payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookTickerUpdateEnvelopeRef<'a> {
+    pub stream: &'a str,
+    #[serde(borrow)]
+    pub data: BookTickerUpdateRef<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookTickerUpdateRef<'a> {
+    #[serde(rename = "E", default, skip_serializing_if = "Option::is_none")]
+    pub event_time: Option<u64>,
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "s")]
+    pub symbol: &'a str,
+    #[serde(rename = "b")]
+    pub best_bid_price: &'a str,
+    #[serde(rename = "B")]
+    pub best_bid_quantity: &'a str,
+    #[serde(rename = "a")]
+    pub best_ask_price: &'a str,
+    #[serde(rename = "A")]
+    pub best_ask_quantity: &'a str,
+}
+
+impl<'a> BookTickerUpdateRef<'a> {
+    pub fn to_owned(&self) -> Result<BookTickerUpdate, ParseFloatError> {
+        Ok(BookTickerUpdate {
+            event_time: self.event_time,
+            update_id: self.update_id,
+            symbol: Symbol::intern(self.symbol),
+            best_bid_price: self.best_bid_price.parse()?,
+            best_bid_quantity: self.best_bid_quantity.parse()?,
+            best_ask_price: self.best_ask_price.parse()?,
+            best_ask_quantity: self.best_ask_quantity.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthUpdateEnvelopeRef<'a> {
+    pub stream: &'a str,
+    #[serde(borrow)]
+    pub data: DepthUpdateRef<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthUpdateRef<'a> {
+    #[serde(rename = "E", default, skip_serializing_if = "Option::is_none")]
+    pub event_time: Option<u64>,
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    #[serde(borrow)]
+    pub bids: Vec<(&'a str, &'a str)>,
+    #[serde(borrow)]
+    pub asks: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> DepthUpdateRef<'a> {
+    pub fn to_owned(&self) -> Result<DepthUpdate, ParseFloatError> {
+        let levels = |levels: &[(&str, &str)]| {
+            levels
+                .iter()
+                .map(|(price, quantity)| Ok((price.parse()?, quantity.parse()?)))
+                .collect::<Result<Vec<(f64, f64)>, ParseFloatError>>()
+        };
+        Ok(DepthUpdate {
+            event_time: self.event_time,
+            last_update_id: self.last_update_id,
+            bids: levels(&self.bids)?,
+            asks: levels(&self.asks)?,
+        })
+    }
+}
+
+// Hand written parsing path for the depth stream, the numeric strings are parsed straight
+// from the payload bytes instead of going through a `String` per price and quantity
+impl DepthUpdate {
+    // Accepts the bare depth payload as well as the combined stream envelope
+    pub fn parse_bytes(bytes: &[u8]) -> Result<DepthUpdate, PayloadError> {
+        let mut update = DepthUpdate {
+            event_time: None,
+            last_update_id: 0,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        };
+        update.parse_bytes_into(bytes)?;
+        Ok(update)
+    }
+
+    // Reuses the level vectors, no allocation once they have grown to the stream depth
+    pub fn parse_bytes_into(&mut self, bytes: &[u8]) -> Result<(), PayloadError> {
+        self.bids.clear();
+        self.asks.clear();
+        self.event_time = None;
+        let mut scanner = Scanner { bytes, position: 0 };
+        scanner.depth_update(self)?;
+        scanner.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadError {
+    pub position: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.reason, self.position)
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn error<T>(&self, reason: &'static str) -> Result<T, PayloadError> {
+        Err(PayloadError {
+            position: self.position,
+            reason,
+        })
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(byte) = self.bytes.get(self.position) {
+            if !byte.is_ascii_whitespace() {
+                return Some(*byte);
+            }
+            self.position += 1;
+        }
+        None
+    }
+
+    fn next_byte(&mut self) -> Result<u8, PayloadError> {
+        match self.peek() {
+            Some(byte) => {
+                self.position += 1;
+                Ok(byte)
+            }
+            None => self.error("unexpected end of payload"),
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), PayloadError> {
+        if self.peek() != Some(expected) {
+            return self.error("unexpected character");
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), PayloadError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.error("trailing characters"),
+        }
+    }
+
+    // Raw string contents, escapes are left as they are
+    fn string(&mut self) -> Result<&'a [u8], PayloadError> {
+        self.expect(b'"')?;
+        let start = self.position;
+        while let Some(byte) = self.bytes.get(self.position) {
+            match byte {
+                b'"' => {
+                    self.position += 1;
+                    return Ok(&self.bytes[start..self.position - 1]);
+                }
+                b'\\' => self.position += 2,
+                _ => self.position += 1,
+            }
+        }
+        self.error("unterminated string")
+    }
+
+    fn unsigned(&mut self) -> Result<u64, PayloadError> {
+        self.peek();
+        let start = self.position;
+        let mut value: u64 = 0;
+        while let Some(byte @ b'0'..=b'9') = self.bytes.get(self.position) {
+            value = match value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(u64::from(byte - b'0')))
+            {
+                Some(value) => value,
+                None => return self.error("number out of range"),
+            };
+            self.position += 1;
+        }
+        if self.position == start {
+            return self.error("expected an unsigned integer");
+        }
+        Ok(value)
+    }
+
+    fn decimal(&mut self) -> Result<f64, PayloadError> {
+        let raw = self.string()?;
+        match std::str::from_utf8(raw).ok().and_then(|s| s.parse().ok()) {
+            Some(value) => Ok(value),
+            None => self.error("invalid decimal string"),
+        }
+    }
+
+    // [["price", "quantity"], ...]
+    fn levels(&mut self, levels: &mut Vec<(f64, f64)>) -> Result<(), PayloadError> {
+        self.expect(b'[')?;
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            self.expect(b'[')?;
+            let price = self.decimal()?;
+            self.expect(b',')?;
+            let quantity = self.decimal()?;
+            self.expect(b']')?;
+            levels.push((price, quantity));
+
+            match self.next_byte()? {
+                b',' => continue,
+                b']' => return Ok(()),
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Result<(), PayloadError> {
+        match self.peek() {
+            Some(b'"') => self.string().map(|_| ()),
+            Some(b'{' | b'[') => {
+                let mut depth = 0;
+                loop {
+                    match self.peek() {
+                        Some(b'"') => {
+                            self.string()?;
+                            continue;
+                        }
+                        Some(b'{' | b'[') => depth += 1,
+                        Some(b'}' | b']') => depth -= 1,
+                        Some(_) => {}
+                        None => return self.error("unexpected end of payload"),
+                    }
+                    self.position += 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(byte) = self.bytes.get(self.position) {
+                    if matches!(byte, b',' | b'}' | b']') || byte.is_ascii_whitespace() {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                Ok(())
+            }
+            None => self.error("unexpected end of payload"),
+        }
+    }
+
+    fn depth_update(&mut self, update: &mut DepthUpdate) -> Result<(), PayloadError> {
+        let (mut last_update_id, mut bids, mut asks) = (false, false, false);
+
+        self.expect(b'{')?;
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+        } else {
+            loop {
+                let key = self.string()?;
+                self.expect(b':')?;
+                match key {
+                    b"lastUpdateId" => {
+                        update.last_update_id = self.unsigned()?;
+                        last_update_id = true;
+                    }
+                    b"E" => update.event_time = Some(self.unsigned()?),
+                    b"bids" => {
+                        self.levels(&mut update.bids)?;
+                        bids = true;
+                    }
+                    b"asks" => {
+                        self.levels(&mut update.asks)?;
+                        asks = true;
+                    }
+                    // Combined stream envelope
+                    b"data" => {
+                        self.depth_update(update)?;
+                        (last_update_id, bids, asks) = (true, true, true);
+                    }
+                    _ => self.skip_value()?,
+                }
+
+                match self.next_byte()? {
+                    b',' => continue,
+                    b'}' => break,
+                    _ => return self.error("expected ',' or '}'"),
+                }
+            }
+        }
+
+        if !(last_update_id && bids && asks) {
+            return self.error("missing depth update field");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeUpdateEnvelope {
+    pub stream: String,
+    pub data: TradeUpdate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeUpdate {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: Symbol,
+    #[serde(rename = "t")]
+    pub trade_id: u64,
+    #[serde(
+        rename = "p",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub price: f64,
+    #[serde(
+        rename = "q",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub quantity: f64,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    // true when the buyer was the resting order, i.e. a seller hit the bid
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+}
+
+// USDⓈ-M futures streams
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarkPriceUpdate {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: Symbol,
+    #[serde(
+        rename = "p",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub mark_price: f64,
+    #[serde(
+        rename = "i",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub index_price: f64,
+    // Only meaningful in the last hour before the settlement
+    #[serde(
+        rename = "P",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub estimated_settle_price: f64,
+    #[serde(
+        rename = "r",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub funding_rate: f64,
+    #[serde(rename = "T")]
+    pub next_funding_time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForceOrderUpdate {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "o")]
+    pub order: LiquidationOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiquidationOrder {
+    #[serde(rename = "s")]
+    pub symbol: Symbol,
+    // Side of the liquidation order, a sell closes a long position
+    #[serde(
+        rename = "S",
+        deserialize_with = "deserialize_side",
+        serialize_with = "serialize_side"
+    )]
+    pub side: Side,
+    #[serde(rename = "o")]
+    pub order_type: String,
+    #[serde(rename = "f")]
+    pub time_in_force: String,
+    #[serde(
+        rename = "q",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub quantity: f64,
+    #[serde(
+        rename = "p",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub price: f64,
+    #[serde(
+        rename = "ap",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub average_price: f64,
+    #[serde(rename = "X")]
+    pub status: String,
+    #[serde(
+        rename = "z",
+        deserialize_with = "deserialize_string_to_f64",
+        serialize_with = "serialize_f64_to_string"
+    )]
+    pub filled_quantity: f64,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+}
+
+// Futures depth streams carry the update id range and the last id of the previous update,
+// and name the sides `b` and `a`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FuturesDepthUpdate {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "T")]
+    pub transaction_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: Symbol,
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    #[serde(rename = "pu")]
+    pub previous_final_update_id: u64,
+    #[serde(
+        rename = "b",
+        deserialize_with = "deserialize_string_tuple_vec",
+        serialize_with = "serialize_tuple_vec_to_string"
+    )]
+    pub bids: Vec<(f64, f64)>,
+    #[serde(
+        rename = "a",
+        deserialize_with = "deserialize_string_tuple_vec",
+        serialize_with = "serialize_tuple_vec_to_string"
+    )]
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl FuturesDepthUpdate {
+    // Spot shaped update for the L2 book, keyed by the final update id
+    pub fn to_depth_update(&self) -> DepthUpdate {
+        DepthUpdate {
+            event_time: Some(self.event_time),
+            last_update_id: self.final_update_id,
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+        }
+    }
+}
+
 fn deserialize_string_tuple_vec<'de, D>(deserializer: D) -> Result<Vec<(f64, f64)>, D::Error>
 where
     D: Deserializer<'de>,
@@ -79,7 +568,7 @@ where
         .collect()
 }
 
-fn deserialize_string_to_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
+pub(crate) fn deserialize_string_to_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -87,6 +576,28 @@ where
     s.parse().map_err(serde::de::Error::custom)
 }
 
+// Order sides are upper case in the trading and user data APIs
+pub(crate) fn deserialize_side<'de, D>(deserializer: D) -> Result<Side, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match String::deserialize(deserializer)?.as_str() {
+        "BUY" => Ok(Side::Buy),
+        "SELL" => Ok(Side::Sell),
+        other => Err(serde::de::Error::unknown_variant(other, &["BUY", "SELL"])),
+    }
+}
+
+fn serialize_side<S>(side: &Side, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    })
+}
+
 fn serialize_f64_to_string<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -118,8 +629,9 @@ mod tests {
     #[test]
     fn test_book_ticker_update_serde() {
         let update = BookTickerUpdate {
+            event_time: None,
             update_id: 123456789,
-            symbol: "BTCUSDT".to_string(),
+            symbol: Symbol::intern("BTCUSDT"),
             best_bid_price: 50000.0,
             best_bid_quantity: 0.5,
             best_ask_price: 50100.0,
@@ -156,6 +668,7 @@ mod tests {
     #[test]
     fn test_depth_update_serde() {
         let depth_update = DepthUpdate {
+            event_time: None,
             last_update_id: 987654321,
             bids: vec![(50000.0, 0.5), (49900.0, 1.2)],
             asks: vec![(50100.0, 0.3), (50200.0, 0.8)],
@@ -181,4 +694,86 @@ mod tests {
         assert_eq!(depth_update.bids, deserialized_update.bids);
         assert_eq!(depth_update.asks, deserialized_update.asks);
     }
+
+    #[test]
+    fn test_trade_update_deserialize() {
+        let json = r#"{"e":"trade","E":1672515782136,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","T":1672515782134,"m":true,"M":true}"#;
+        let trade: TradeUpdate = serde_json::from_str(json).unwrap();
+
+        assert_eq!(trade.event_time, 1672515782136);
+        assert_eq!(trade.symbol, "BNBBTC");
+        assert_eq!(trade.trade_id, 12345);
+        assert_eq!(trade.price, 0.001);
+        assert_eq!(trade.quantity, 100.0);
+        assert_eq!(trade.trade_time, 1672515782134);
+        assert!(trade.is_buyer_maker);
+    }
+
+    #[test]
+    fn test_depth_update_parse_bytes_matches_serde() {
+        let payload = r#"{"lastUpdateId":160,"bids":[["0.0024","10"],["0.0023","100.5"]],"asks":[["0.0026","100"]]}"#;
+        let parsed = DepthUpdate::parse_bytes(payload.as_bytes()).unwrap();
+        let expected: DepthUpdate = serde_json::from_str(payload).unwrap();
+        assert_eq!(parsed.last_update_id, expected.last_update_id);
+        assert_eq!(parsed.event_time, None);
+        assert_eq!(parsed.bids, expected.bids);
+        assert_eq!(parsed.asks, expected.asks);
+
+        // Envelope with unknown fields and whitespace, parsed into the same update
+        let mut update = parsed;
+        let envelope = r#"{ "stream": "bnbbtc@depth5", "extra": {"a": [1, "]"]},
+            "data": {"E": 1, "lastUpdateId": 161, "bids": [], "asks": [["0.0027", "1"]]} }"#;
+        update.parse_bytes_into(envelope.as_bytes()).unwrap();
+        assert_eq!(update.last_update_id, 161);
+        assert_eq!(update.event_time, Some(1));
+        assert!(update.bids.is_empty());
+        assert_eq!(update.asks, vec![(0.0027, 1.0)]);
+    }
+
+    #[test]
+    fn test_depth_update_parse_bytes_errors() {
+        let error = |payload: &str| DepthUpdate::parse_bytes(payload.as_bytes()).unwrap_err();
+
+        assert_eq!(
+            error(r#"{"lastUpdateId":1,"bids":[]}"#).reason,
+            "missing depth update field"
+        );
+        assert_eq!(
+            error(r#"{"lastUpdateId":1,"bids":[["x","1"]],"asks":[]}"#),
+            PayloadError {
+                position: 30,
+                reason: "invalid decimal string"
+            }
+        );
+        assert_eq!(
+            error(r#"{"lastUpdateId":1,"bids":[],"asks":[]} x"#).reason,
+            "trailing characters"
+        );
+        assert_eq!(
+            error(r#"{"lastUpdateId":"#).reason,
+            "expected an unsigned integer"
+        );
+    }
+
+    #[test]
+    fn test_borrowed_payloads() {
+        let frame = r#"{"stream":"bnbusdt@bookTicker","data":{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}}"#;
+        let envelope: BookTickerUpdateEnvelopeRef = serde_json::from_str(frame).unwrap();
+        assert_eq!(envelope.data.symbol, "BNBUSDT");
+        assert_eq!(envelope.data.best_bid_price, "25.35190000");
+        let owned = envelope.data.to_owned().unwrap();
+        assert_eq!(owned.symbol, "BNBUSDT");
+        assert_eq!(owned.best_ask_quantity, 40.66);
+
+        let frame = r#"{"stream":"bnbusdt@depth5","data":{"lastUpdateId":160,"bids":[["0.0024","10"]],"asks":[["0.0026","x"]]}}"#;
+        let envelope: DepthUpdateEnvelopeRef = serde_json::from_str(frame).unwrap();
+        assert_eq!(envelope.data.bids, vec![("0.0024", "10")]);
+        assert!(envelope.data.to_owned().is_err());
+
+        let depth: DepthUpdateRef =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["1.5","2"]],"asks":[]}"#).unwrap();
+        let owned = depth.to_owned().unwrap();
+        assert_eq!(owned.bids, vec![(1.5, 2.0)]);
+        assert!(owned.asks.is_empty());
+    }
 }
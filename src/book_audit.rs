@@ -0,0 +1,281 @@
+/// Human-readable audit log of every L2 book mutation.
+/// `BookAuditLog` writes one line per level change drained from `OrderBook::drain_mutations`,
+/// e.g.
+/// `2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=42 side=bid price=3000.5 old_qty=1.2 new_qty=0`
+/// with the time the payload was received, the update that made the change and the level before
+/// and after it. A zero quantity is an absent level. The log is separate from the tracing
+/// output and the metrics, so the state of a book at any update can be rebuilt from it after the
+/// fact. Once the file reaches `max_bytes` it is renamed to `<path>.1`, older files shift up to
+/// `<path>.<max_files>` and the oldest one is deleted.
+use crate::orderbook::LevelMutation;
+use crate::orderbookv2::Side;
+use crate::price_converter::PriceConverter;
+use crate::symbol::Symbol;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+#[derive(Debug)]
+pub struct BookAuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    writer: BufWriter<File>,
+    // Bytes in the current file
+    size: u64,
+    lines: u64,
+}
+
+impl BookAuditLog {
+    // Appends to an existing log, its size counts towards the first rotation
+    pub fn open(
+        path: impl AsRef<Path>,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> io::Result<BookAuditLog> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(BookAuditLog {
+            path,
+            max_bytes,
+            max_files,
+            writer: BufWriter::new(file),
+            size,
+            lines: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Lines written by this log, not counting what the files held before
+    pub fn len(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines == 0
+    }
+
+    // `received` is the wall time of the payload, in nanoseconds since the Unix epoch
+    pub fn log(
+        &mut self,
+        received: u64,
+        symbol: Symbol,
+        converter: PriceConverter,
+        mutation: &LevelMutation,
+    ) -> io::Result<()> {
+        let line = format_line(received, symbol, converter, mutation);
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        self.lines += 1;
+        if self.size >= self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated(self.max_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+}
+
+pub fn format_line(
+    received: u64,
+    symbol: Symbol,
+    converter: PriceConverter,
+    mutation: &LevelMutation,
+) -> String {
+    format!(
+        "{} symbol={} update_id={} side={} price={} old_qty={} new_qty={}",
+        utc_timestamp(received),
+        symbol,
+        mutation.update_id,
+        match mutation.side {
+            Side::Buy => "bid",
+            Side::Sell => "ask",
+        },
+        converter.to_f64(mutation.price),
+        converter.to_f64(mutation.old_quantity),
+        converter.to_f64(mutation.new_quantity),
+    )
+}
+
+// RFC 3339 in UTC with nanoseconds, the date from the days since the epoch as in Howard
+// Hinnant's `civil_from_days`
+fn utc_timestamp(nanos: u64) -> String {
+    let seconds = nanos / 1_000_000_000;
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+        nanos % 1_000_000_000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use crate::orderbook::OrderBook;
+
+    fn update(id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id: id,
+            bids,
+            asks,
+        }
+    }
+
+    #[test]
+    fn test_mutation_lines() {
+        let mut book = OrderBook::new("ETHUSDC");
+        book.update_depth(&update(1, vec![(3000.0, 1.0)], vec![]));
+        assert!(book.drain_mutations().is_empty());
+
+        book.record_mutations();
+        book.update_depth(&update(
+            2,
+            vec![(3000.0, 1.5), (2999.5, 0.0)],
+            vec![(3000.5, 2.0)],
+        ));
+        // A snapshot removes every level before it sets its own
+        book.replace_depth(&update(3, vec![(3000.0, 1.5)], vec![]));
+        let lines: Vec<String> = book
+            .drain_mutations()
+            .iter()
+            .map(|mutation| {
+                format_line(
+                    1_714_564_800_000_000_123,
+                    book.symbol(),
+                    book.converter(),
+                    mutation,
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=2 side=bid price=3000 old_qty=1 new_qty=1.5",
+                "2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=2 side=ask price=3000.5 old_qty=0 new_qty=2",
+                "2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=3 side=bid price=3000 old_qty=1.5 new_qty=0",
+                "2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=3 side=ask price=3000.5 old_qty=2 new_qty=0",
+                "2024-05-01T12:00:00.000000123Z symbol=ETHUSDC update_id=3 side=bid price=3000 old_qty=0 new_qty=1.5",
+            ]
+        );
+        assert!(book.drain_mutations().is_empty());
+        assert_eq!(
+            utc_timestamp(951_782_400_000_000_000),
+            "2000-02-29T00:00:00.000000000Z"
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let directory = std::env::temp_dir().join(format!("book-audit-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("book.log");
+        let mutation = LevelMutation {
+            update_id: 1,
+            side: Side::Buy,
+            price: crate::orderbook::Price(30_000_000),
+            old_quantity: crate::orderbook::Quantity(0),
+            new_quantity: crate::orderbook::Quantity(10_000),
+        };
+        let line_length = format_line(
+            0,
+            Symbol::intern("ETHUSDC"),
+            PriceConverter::default(),
+            &mutation,
+        )
+        .len() as u64
+            + 1;
+
+        // Two lines per file, two rotated files kept
+        let mut log = BookAuditLog::open(&path, 2 * line_length, 2).unwrap();
+        for _ in 0..7 {
+            log.log(
+                0,
+                Symbol::intern("ETHUSDC"),
+                PriceConverter::default(),
+                &mutation,
+            )
+            .unwrap();
+        }
+        log.flush().unwrap();
+        assert_eq!(log.len(), 7);
+
+        let lines = |path: &Path| {
+            fs::read_to_string(path)
+                .map(|text| text.lines().count())
+                .unwrap_or(0)
+        };
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&directory.join("book.log.1")), 2);
+        assert_eq!(lines(&directory.join("book.log.2")), 2);
+        assert!(!directory.join("book.log.3").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+/// Matching engine types without the standard library.
+/// The order types and units `orderbookv2::OrderBook` is built on, only needing `alloc`, for
+/// embedded targets and kernel-bypass loops that bring their own allocator. Together with
+/// `price_levels`, the level storage the engine keeps both sides of its book in, they are the
+/// data structures the engine shares with such environments, the matching itself stays in
+/// `orderbookv2`. With `--no-default-features` the crate is `#![no_std]` and holds this module, `units` and
+/// `price_levels` only.
+use crate::units;
+use core::ops::Neg;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum OrderType {
+    GoodToCancel,
+    FillAndKill,
+    Day,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+units::unit!(
+    // Signed, so prices can be offset below zero in spreads and synthetic instruments
+    Price(i32)
+);
+units::additive!(Price);
+units::unit!(Quantity(u32));
+units::additive!(Quantity);
+units::unit!(
+    // Price times quantity, wide enough for any single trade
+    Notional(i64)
+);
+units::additive!(Notional);
+units::notional!(Price * Quantity = Notional(i64));
+
+impl Price {
+    pub fn abs(self) -> Price {
+        Price(self.0.abs())
+    }
+
+    // Halfway between the two prices, rounded towards `self`
+    pub fn midpoint(self, other: Price) -> Price {
+        self + Price((other.0 - self.0) / 2)
+    }
+}
+
+impl Neg for Price {
+    type Output = Price;
+
+    fn neg(self) -> Price {
+        Price(-self.0)
+    }
+}
+
+impl From<Price> for i64 {
+    fn from(price: Price) -> i64 {
+        price.0 as i64
+    }
+}
+
+// Sums of quantities are kept in u64 where a level or a bar can exceed `Quantity::MAX`
+impl From<Quantity> for u64 {
+    fn from(quantity: Quantity) -> u64 {
+        quantity.0 as u64
+    }
+}
+
+impl From<Quantity> for i64 {
+    fn from(quantity: Quantity) -> i64 {
+        quantity.0 as i64
+    }
+}
+
+impl Quantity {
+    // Saturates at `Quantity::MAX`
+    pub fn saturating_from(quantity: u64) -> Quantity {
+        u32::try_from(quantity).map_or(Quantity::MAX, Quantity)
+    }
+}
+
+pub type OrderId = u64;
+pub type AccountId = u64;
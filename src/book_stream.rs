@@ -0,0 +1,264 @@
+/// Async view of the L2 book for strategy code.
+/// `OrderBookStream` wraps the raw websocket payloads, applies them to its book and yields a
+/// `DepthSnapshot` whenever the watched levels change. Payloads that arrived while the consumer
+/// was busy are applied together and yield a single snapshot, so a slow consumer always gets the
+/// latest book instead of a backlog. With a `LatencyRecorder` attached, every applied payload
+/// is timed from the socket read to the book update.
+use crate::binance_payloads;
+use crate::clock::SharedClock;
+use crate::latency::{LatencyRecorder, LatencySample};
+use crate::orderbook::{DepthSnapshot, OrderBook};
+use crate::symbol::Symbol;
+use futures_util::stream::{Fuse, Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// EXTENSION: It should be easy to create multiplexed stream with subscription on different pairs and handle here,
+// by extending DepthUpdateEnvelope struct to understand what stream it is operating on.
+// Applies a depth or book ticker payload, false when the payload is not recognized
+pub fn apply_payload(orderbook: &mut OrderBook, payload: &[u8]) -> bool {
+    match binance_payloads::DepthUpdate::parse_bytes(payload) {
+        Ok(depth_update) => {
+            log::debug!("{:?}", depth_update);
+            orderbook.update_depth(&depth_update);
+            true
+        }
+        Err(_) => {
+            match serde_json::from_slice::<binance_payloads::BookTickerUpdateEnvelopeRef>(payload) {
+                Ok(book_ticker_update) => {
+                    log::debug!("{:?}", book_ticker_update);
+                    match orderbook.update_book_ticker_ref(&book_ticker_update.data) {
+                        Ok(_) => true,
+                        Err(error) => {
+                            log::error!("Invalid book ticker update: {}", error);
+                            false
+                        }
+                    }
+                }
+                Err(_) => {
+                    log::error!("Unrecognized websocket message");
+                    false
+                }
+            }
+        }
+    }
+}
+
+pub struct OrderBookStream<S> {
+    payloads: Fuse<S>,
+    orderbook: OrderBook,
+    levels: usize,
+    last: Option<DepthSnapshot>,
+    latency: Option<LatencyRecorder>,
+}
+
+impl<S: Stream + Unpin> OrderBookStream<S>
+where
+    S::Item: AsRef<[u8]>,
+{
+    // Yields the top `levels` levels of each side
+    pub fn new(symbol: impl Into<Symbol>, payloads: S, levels: usize) -> OrderBookStream<S> {
+        OrderBookStream {
+            payloads: payloads.fuse(),
+            orderbook: OrderBook::new(symbol),
+            levels,
+            last: None,
+            latency: None,
+        }
+    }
+
+    // Stamps the book updates and the received payloads with `clock`
+    pub fn with_clock(mut self, clock: SharedClock) -> OrderBookStream<S> {
+        self.orderbook.set_clock(clock);
+        self
+    }
+
+    pub fn with_latency_recorder(mut self, recorder: LatencyRecorder) -> OrderBookStream<S> {
+        self.latency = Some(recorder);
+        self
+    }
+
+    pub fn latency(&self) -> Option<&LatencyRecorder> {
+        self.latency.as_ref()
+    }
+
+    // Only yields when the best bid or ask changes
+    pub fn top_of_book(self) -> OrderBookStream<S> {
+        OrderBookStream { levels: 1, ..self }
+    }
+
+    pub fn orderbook(&self) -> &OrderBook {
+        &self.orderbook
+    }
+
+    // Snapshot of the watched levels if they differ from the last one yielded
+    fn changed_snapshot(&mut self) -> Option<DepthSnapshot> {
+        let snapshot = self.orderbook.snapshot(self.levels);
+        let unchanged = self
+            .last
+            .as_ref()
+            .is_some_and(|last| last.bids == snapshot.bids && last.asks == snapshot.asks);
+        if unchanged {
+            return None;
+        }
+        self.last = Some(snapshot.clone());
+        Some(snapshot)
+    }
+}
+
+impl<S: Stream + Unpin> Stream for OrderBookStream<S>
+where
+    S::Item: AsRef<[u8]>,
+{
+    type Item = DepthSnapshot;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DepthSnapshot>> {
+        let this = &mut *self;
+        let mut updated = false;
+        let mut finished = false;
+
+        // Conflate everything that is ready right now
+        loop {
+            match this.payloads.poll_next_unpin(cx) {
+                Poll::Ready(Some(payload)) => {
+                    let received = this.orderbook.clock().stamp();
+                    let previous = this.orderbook.last_update();
+                    updated |= apply_payload(&mut this.orderbook, payload.as_ref());
+                    // Stale and invalid payloads leave the stamp alone and are not timed
+                    let applied = this
+                        .orderbook
+                        .last_update()
+                        .filter(|&stamp| Some(stamp) != previous);
+                    if let (Some(recorder), Some(applied)) = (this.latency.as_mut(), applied) {
+                        recorder.record(LatencySample {
+                            event_time: this.orderbook.last_event_time(),
+                            received,
+                            applied,
+                        });
+                    }
+                }
+                Poll::Ready(None) => {
+                    finished = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        if updated {
+            if let Some(snapshot) = this.changed_snapshot() {
+                return Poll::Ready(Some(snapshot));
+            }
+        }
+        if finished {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::latency::LatencyStage;
+    use crate::orderbook::{Price, Quantity};
+    use futures_util::stream;
+    use futures_util::task::noop_waker_ref;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn depth(last_update_id: u64, bids: &str, asks: &str) -> String {
+        format!(
+            r#"{{"stream":"bnbusdt@depth5","data":{{"lastUpdateId":{},"bids":{},"asks":{}}}}}"#,
+            last_update_id, bids, asks
+        )
+    }
+
+    #[tokio::test]
+    async fn test_ready_payloads_are_conflated() {
+        let payloads = vec![
+            depth(1, r#"[["25.35","1"]]"#, r#"[["25.36","1"]]"#),
+            "not a payload".to_string(),
+            depth(2, r#"[["25.35","2"]]"#, "[]"),
+        ];
+        let mut stream = OrderBookStream::new("BNBUSDT".to_string(), stream::iter(payloads), 5);
+
+        let mut snapshots = Vec::new();
+        while let Some(snapshot) = stream.next().await {
+            snapshots.push(snapshot);
+        }
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].last_update_id, 2);
+        assert_eq!(snapshots[0].bids, vec![(Price(253_500), Quantity(20_000))]);
+    }
+
+    #[test]
+    fn test_top_of_book_changes_only() {
+        // None stands for the socket having nothing to read yet
+        let mut pending: VecDeque<Option<String>> = VecDeque::from(vec![
+            Some(depth(1, r#"[["10","1"],["9","1"]]"#, r#"[["11","1"]]"#)),
+            None,
+            Some(depth(2, r#"[["9","5"]]"#, "[]")),
+            None,
+            Some(depth(3, r#"[["10","2"]]"#, "[]")),
+            None,
+        ]);
+        let payloads = stream::poll_fn(move |_| match pending.pop_front() {
+            Some(Some(payload)) => Poll::Ready(Some(payload)),
+            Some(None) => Poll::Pending,
+            None => Poll::Ready(None),
+        });
+        let mut stream = OrderBookStream::new("BNBUSDT".to_string(), payloads, 5).top_of_book();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        let Poll::Ready(Some(first)) = stream.poll_next_unpin(&mut cx) else {
+            panic!("expected the first snapshot");
+        };
+        assert_eq!(first.bids, vec![(Price(100_000), Quantity(10_000))]);
+        // The second level changed, the best bid did not
+        assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        assert_eq!(
+            stream.orderbook().snapshot(2).bids[1],
+            (Price(90_000), Quantity(50_000))
+        );
+
+        let Poll::Ready(Some(second)) = stream.poll_next_unpin(&mut cx) else {
+            panic!("expected the best bid change");
+        };
+        assert_eq!(second.bids, vec![(Price(100_000), Quantity(20_000))]);
+        assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Ready(None));
+    }
+
+    #[tokio::test]
+    async fn test_latency_is_recorded_for_applied_payloads() {
+        // Wall clock 2ms after the first event time
+        let clock = MockClock::new(1_700_000_000_002_000_000);
+        let payloads = vec![
+            r#"{"stream":"bnbusdt@depth","data":{"E":1700000000000,"lastUpdateId":1,"bids":[["25.35","1"]],"asks":[]}}"#,
+            // Stale, not timed
+            r#"{"stream":"bnbusdt@depth","data":{"E":1700000000001,"lastUpdateId":1,"bids":[],"asks":[]}}"#,
+            r#"{"stream":"bnbusdt@depth","data":{"lastUpdateId":2,"bids":[],"asks":[]}}"#,
+        ];
+        let ticking = clock.clone();
+        let payloads = stream::iter(payloads).map(move |payload| {
+            ticking.advance(Duration::from_millis(1));
+            payload
+        });
+        let mut stream = OrderBookStream::new("BNBUSDT", payloads, 5)
+            .with_clock(Arc::new(clock))
+            .with_latency_recorder(LatencyRecorder::default());
+        while stream.next().await.is_some() {}
+
+        let recorder = stream.latency().unwrap();
+        assert_eq!(recorder.len(LatencyStage::FeedHandler), 2);
+        assert_eq!(
+            recorder.percentile(LatencyStage::Network, 1.0),
+            Some(3_000_000)
+        );
+        assert_eq!(recorder.len(LatencyStage::Total), 1);
+        assert_eq!(stream.orderbook().last_event_time(), None);
+    }
+}
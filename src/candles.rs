@@ -0,0 +1,213 @@
+/// OHLCV candles built from matching engine trades.
+/// Candles are aligned to multiples of their interval on the engine clock. A candle closes when
+/// a trade of a later interval arrives or when the clock moves past its end, intervals without
+/// trades produce no candle. Attached to the engine with `OrderBook::set_candle_builder`, closed
+/// candles are emitted as `EngineEvent::CandleClosed`.
+use crate::orderbookv2::{Price, Quantity, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candle {
+    pub interval: Duration,
+    pub open_time: Timestamp,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: u64,
+    pub trade_count: u64,
+}
+
+impl Candle {
+    fn new(interval: Duration, open_time: Timestamp, price: Price, quantity: Quantity) -> Candle {
+        Candle {
+            interval,
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: quantity.into(),
+            trade_count: 1,
+        }
+    }
+
+    // End of the interval, exclusive
+    pub fn close_time(&self) -> Timestamp {
+        self.open_time + self.interval.as_nanos() as Timestamp
+    }
+
+    fn add(&mut self, price: Price, quantity: Quantity) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += u64::from(quantity);
+        self.trade_count += 1;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CandleBuilder {
+    // One open candle per interval
+    candles: Vec<(Duration, Option<Candle>)>,
+}
+
+impl CandleBuilder {
+    pub fn new(intervals: &[Duration]) -> CandleBuilder {
+        assert!(
+            intervals.iter().all(|interval| !interval.is_zero()),
+            "candle intervals must not be zero"
+        );
+        CandleBuilder {
+            candles: intervals.iter().map(|&interval| (interval, None)).collect(),
+        }
+    }
+
+    pub fn intervals(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.candles.iter().map(|(interval, _)| *interval)
+    }
+
+    // Candle still being built for the interval
+    pub fn current(&self, interval: Duration) -> Option<&Candle> {
+        self.candles
+            .iter()
+            .find(|(candle_interval, _)| *candle_interval == interval)
+            .and_then(|(_, candle)| candle.as_ref())
+    }
+
+    // Adds the trade to the open candles, returns the candles it closed
+    pub fn on_trade(
+        &mut self,
+        timestamp: Timestamp,
+        price: Price,
+        quantity: Quantity,
+    ) -> Vec<Candle> {
+        let closed = self.flush(timestamp);
+        for (interval, candle) in self.candles.iter_mut() {
+            match candle {
+                Some(candle) => candle.add(price, quantity),
+                None => {
+                    let length = interval.as_nanos() as Timestamp;
+                    let open_time = timestamp - timestamp % length;
+                    *candle = Some(Candle::new(*interval, open_time, price, quantity));
+                }
+            }
+        }
+        closed
+    }
+
+    // Closes the candles whose interval ended at or before `now`, earliest end first
+    pub fn flush(&mut self, now: Timestamp) -> Vec<Candle> {
+        let mut closed: Vec<Candle> = self
+            .candles
+            .iter_mut()
+            .filter_map(|(_, candle)| match candle {
+                Some(open) if open.close_time() <= now => candle.take(),
+                _ => None,
+            })
+            .collect();
+        closed.sort_by_key(|candle| (candle.close_time(), candle.interval));
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EngineEvent;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Side};
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_candles_per_interval() {
+        let minute = Duration::from_secs(60);
+        let mut builder = CandleBuilder::new(&[Duration::from_secs(1), minute]);
+
+        assert!(builder
+            .on_trade(SECOND / 2, Price(100), Quantity(2))
+            .is_empty());
+        assert!(builder
+            .on_trade(SECOND - 1, Price(103), Quantity(1))
+            .is_empty());
+        assert!(builder
+            .on_trade(SECOND - 1, Price(98), Quantity(1))
+            .is_empty());
+
+        let closed = builder.on_trade(3 * SECOND + 5, Price(101), Quantity(4));
+        assert_eq!(
+            closed,
+            vec![Candle {
+                interval: Duration::from_secs(1),
+                open_time: 0,
+                open: Price(100),
+                high: Price(103),
+                low: Price(98),
+                close: Price(98),
+                volume: 4,
+                trade_count: 3,
+            }]
+        );
+        assert_eq!(
+            builder.current(Duration::from_secs(1)).unwrap().open_time,
+            3 * SECOND
+        );
+
+        let minute_candle = *builder.current(minute).unwrap();
+        assert_eq!(
+            (minute_candle.open, minute_candle.close),
+            (Price(100), Price(101))
+        );
+        assert_eq!((minute_candle.volume, minute_candle.trade_count), (8, 4));
+
+        assert_eq!(builder.flush(59 * SECOND).len(), 1);
+        assert_eq!(builder.flush(60 * SECOND), vec![minute_candle]);
+        assert!(builder.current(minute).is_none());
+    }
+
+    #[test]
+    fn test_engine_emits_closed_candles() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_candle_builder(CandleBuilder::new(&[Duration::from_secs(1)]));
+
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(100),
+            Quantity(2),
+            OrderType::FillAndKill,
+            Side::Buy,
+        ));
+        orderbook.advance_clock(SECOND / 2);
+        orderbook.add_order(Order::new(
+            3,
+            Price(100),
+            Quantity(1),
+            OrderType::FillAndKill,
+            Side::Buy,
+        ));
+        assert!(orderbook.drain_events().is_empty());
+
+        orderbook.advance_clock(SECOND);
+        assert_eq!(
+            orderbook.drain_events(),
+            vec![EngineEvent::CandleClosed(Candle {
+                interval: Duration::from_secs(1),
+                open_time: 0,
+                open: Price(100),
+                high: Price(100),
+                low: Price(100),
+                close: Price(100),
+                volume: 3,
+                trade_count: 2,
+            })]
+        );
+    }
+}
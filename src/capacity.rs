@@ -0,0 +1,159 @@
+/// Memory bounds for the matching engine.
+/// Long running simulations can be fed pathological order flow, e.g. orders spread over
+/// thousands of prices or piling up at one level, and the book then grows without bound. The
+/// limits cap the resting orders of the whole book, the orders queued at one level and the
+/// number of levels per side. An order that would break a limit is rejected on entry, checked
+/// as if it rested in full, so a book at capacity only accepts Fill and Kill orders until
+/// something is cancelled or filled.
+use crate::orderbookv2::{Price, Side};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CapacityLimits {
+    pub max_orders: Option<usize>,
+    pub max_orders_per_level: Option<usize>,
+    pub max_levels_per_side: Option<usize>,
+}
+
+impl CapacityLimits {
+    pub fn new() -> CapacityLimits {
+        CapacityLimits::default()
+    }
+
+    pub fn with_max_orders(mut self, limit: usize) -> CapacityLimits {
+        self.max_orders = Some(limit);
+        self
+    }
+
+    pub fn with_max_orders_per_level(mut self, limit: usize) -> CapacityLimits {
+        self.max_orders_per_level = Some(limit);
+        self
+    }
+
+    pub fn with_max_levels_per_side(mut self, limit: usize) -> CapacityLimits {
+        self.max_levels_per_side = Some(limit);
+        self
+    }
+
+    // `level_orders` is None when the order would open a new level
+    pub fn check(
+        &self,
+        side: Side,
+        price: Price,
+        orders: usize,
+        levels: usize,
+        level_orders: Option<usize>,
+    ) -> Result<(), CapacityViolation> {
+        if let Some(limit) = self.max_orders {
+            if orders >= limit {
+                return Err(CapacityViolation::MaxOrders { limit });
+            }
+        }
+        match (
+            level_orders,
+            self.max_orders_per_level,
+            self.max_levels_per_side,
+        ) {
+            (Some(queued), Some(limit), _) if queued >= limit => {
+                Err(CapacityViolation::MaxOrdersPerLevel { price, limit })
+            }
+            (None, _, Some(limit)) if levels >= limit => {
+                Err(CapacityViolation::MaxLevelsPerSide { side, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityViolation {
+    MaxOrders { limit: usize },
+    MaxOrdersPerLevel { price: Price, limit: usize },
+    MaxLevelsPerSide { side: Side, limit: usize },
+}
+
+impl fmt::Display for CapacityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapacityViolation::MaxOrders { limit } => {
+                write!(f, "book already holds {} resting orders", limit)
+            }
+            CapacityViolation::MaxOrdersPerLevel { price, limit } => {
+                write!(f, "level {} already holds {} orders", price, limit)
+            }
+            CapacityViolation::MaxLevelsPerSide { side, limit } => {
+                write!(f, "{:?} side already has {} levels", side, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CapacityViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Quantity, Rejected};
+
+    fn order(order_id: u64, side: Side, price: i32, order_type: OrderType) -> Order {
+        Order::new(order_id, Price(price), Quantity(1), order_type, side)
+    }
+
+    #[test]
+    fn test_engine_rejects_orders_past_capacity() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_capacity_limits(
+            CapacityLimits::new()
+                .with_max_orders(4)
+                .with_max_orders_per_level(2)
+                .with_max_levels_per_side(2),
+        );
+        let gtc = OrderType::GoodToCancel;
+
+        orderbook
+            .place_order(order(1, Side::Buy, 100, gtc))
+            .unwrap();
+        orderbook
+            .place_order(order(2, Side::Buy, 100, gtc))
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .place_order(order(3, Side::Buy, 100, gtc))
+                .unwrap_err(),
+            Rejected::Capacity(CapacityViolation::MaxOrdersPerLevel {
+                price: Price(100),
+                limit: 2
+            })
+        );
+        orderbook.place_order(order(3, Side::Buy, 99, gtc)).unwrap();
+        assert_eq!(
+            orderbook
+                .place_order(order(4, Side::Buy, 98, gtc))
+                .unwrap_err(),
+            Rejected::Capacity(CapacityViolation::MaxLevelsPerSide {
+                side: Side::Buy,
+                limit: 2
+            })
+        );
+        // The other side has its own levels
+        orderbook
+            .place_order(order(4, Side::Sell, 110, gtc))
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .place_order(order(5, Side::Sell, 111, gtc))
+                .unwrap_err(),
+            Rejected::Capacity(CapacityViolation::MaxOrders { limit: 4 })
+        );
+
+        // Fill and Kill orders never rest, and filling frees capacity
+        let trades = orderbook
+            .place_order(order(6, Side::Sell, 100, OrderType::FillAndKill))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        orderbook
+            .place_order(order(7, Side::Sell, 111, gtc))
+            .unwrap();
+        assert_eq!(orderbook.orderbook_size(), 4);
+    }
+}
@@ -0,0 +1,166 @@
+/// Book checksums in the formats exchanges publish them.
+/// Kraken and OKX send a CRC32 of the top of their book with the depth updates, computed over
+/// the prices and quantities as decimal strings. Computing the same value over the local book
+/// tells whether it has drifted from the exchange and needs a resync. The strings are rebuilt
+/// from internal units with the instrument's price and quantity decimals, which matches the
+/// exchange as long as those are the decimals it sends.
+use crate::orderbook::{Price, Quantity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStyle {
+    // Top 10 asks then top 10 bids, price and quantity without the decimal point and leading
+    // zeros, all concatenated
+    Kraken {
+        price_decimals: u32,
+        quantity_decimals: u32,
+    },
+    // Top 25 levels, the bid and ask of each depth in turn as price:quantity, joined by ':'.
+    // OKX publishes the CRC as a signed integer, compare against `checksum as i32`.
+    Okx {
+        price_decimals: u32,
+        quantity_decimals: u32,
+    },
+}
+
+impl ChecksumStyle {
+    pub fn depth(&self) -> usize {
+        match self {
+            ChecksumStyle::Kraken { .. } => 10,
+            ChecksumStyle::Okx { .. } => 25,
+        }
+    }
+
+    // The string the CRC is computed over, levels best first and in internal units of `scale`
+    // decimals
+    pub fn payload(
+        &self,
+        bids: &[(Price, Quantity)],
+        asks: &[(Price, Quantity)],
+        scale: u32,
+    ) -> String {
+        let depth = self.depth();
+        let (bids, asks) = (
+            &bids[..bids.len().min(depth)],
+            &asks[..asks.len().min(depth)],
+        );
+        match *self {
+            ChecksumStyle::Kraken {
+                price_decimals,
+                quantity_decimals,
+            } => {
+                let digits = |units: u64, decimals: u32| {
+                    let decimal = format_units(units, scale, decimals).replace('.', "");
+                    match decimal.trim_start_matches('0') {
+                        "" => "0".to_string(),
+                        trimmed => trimmed.to_string(),
+                    }
+                };
+                asks.iter()
+                    .chain(bids)
+                    .map(|(price, quantity)| {
+                        digits(price.0, price_decimals) + &digits(quantity.0, quantity_decimals)
+                    })
+                    .collect()
+            }
+            ChecksumStyle::Okx {
+                price_decimals,
+                quantity_decimals,
+            } => {
+                let level = |(price, quantity): &(Price, Quantity)| {
+                    format!(
+                        "{}:{}",
+                        format_units(price.0, scale, price_decimals),
+                        format_units(quantity.0, scale, quantity_decimals)
+                    )
+                };
+                (0..bids.len().max(asks.len()))
+                    .flat_map(|depth| [bids.get(depth), asks.get(depth)])
+                    .flatten()
+                    .map(level)
+                    .collect::<Vec<_>>()
+                    .join(":")
+            }
+        }
+    }
+
+    pub fn checksum(
+        &self,
+        bids: &[(Price, Quantity)],
+        asks: &[(Price, Quantity)],
+        scale: u32,
+    ) -> u32 {
+        crc32fast::hash(self.payload(bids, asks, scale).as_bytes())
+    }
+}
+
+// Units with `scale` decimals as a decimal string with exactly `decimals` of them, digits
+// past `decimals` are cut off
+fn format_units(units: u64, scale: u32, decimals: u32) -> String {
+    let factor = 10u64.pow(scale);
+    let (integer, fraction) = (units / factor, units % factor);
+    if decimals == 0 {
+        return integer.to_string();
+    }
+    let fraction = match decimals.cmp(&scale) {
+        std::cmp::Ordering::Less => fraction / 10u64.pow(scale - decimals),
+        _ => fraction * 10u64.pow(decimals - scale),
+    };
+    format!(
+        "{}.{:0width$}",
+        integer,
+        fraction,
+        width = decimals as usize
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_units() {
+        assert_eq!(format_units(12_345, 4, 4), "1.2345");
+        assert_eq!(format_units(12_345, 4, 2), "1.23");
+        assert_eq!(format_units(12_345, 4, 6), "1.234500");
+        assert_eq!(format_units(500, 4, 0), "0");
+        assert_eq!(format_units(5, 4, 4), "0.0005");
+    }
+
+    #[test]
+    fn test_kraken_and_okx_payloads() {
+        // Eight decimals in internal units
+        let level = |price: f64, quantity: f64| {
+            (
+                Price((price * 1e8).round() as u64),
+                Quantity((quantity * 1e8).round() as u64),
+            )
+        };
+
+        let kraken = ChecksumStyle::Kraken {
+            price_decimals: 5,
+            quantity_decimals: 8,
+        };
+        let bids = [level(0.05, 1.5)];
+        let asks = [level(0.05005, 0.000005)];
+        assert_eq!(
+            kraken.payload(&bids, &asks, 8),
+            "5005500".to_string() + "5000150000000"
+        );
+
+        let okx = ChecksumStyle::Okx {
+            price_decimals: 2,
+            quantity_decimals: 1,
+        };
+        let bids = [level(100.5, 2.0), level(100.4, 1.5)];
+        let asks = [level(100.6, 3.0)];
+        let payload = "100.50:2.0:100.60:3.0:100.40:1.5";
+        assert_eq!(okx.payload(&bids, &asks, 8), payload);
+        assert_eq!(
+            okx.checksum(&bids, &asks, 8),
+            crc32fast::hash(payload.as_bytes())
+        );
+        // Only the top 25 levels count
+        let deep: Vec<_> = (0..30).map(|i| level(100.0 - i as f64, 1.0)).collect();
+        assert_eq!(okx.payload(&deep, &[], 8).split(':').count(), 50);
+    }
+}
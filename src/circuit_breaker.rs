@@ -0,0 +1,206 @@
+/// Volatility interruption for the matching engine.
+/// Trade prices are kept for a sliding window, a trade more than the configured percentage
+/// away from any price in the window trips the breaker. The engine then halts (or moves to an
+/// auction) and resumes continuous trading once the pause has elapsed on its clock.
+use crate::orderbookv2::{Price, Timestamp};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerAction {
+    // Refuse orders until the pause is over
+    Halt,
+    // Collect orders and uncross them when the pause is over
+    Auction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerConfig {
+    pub max_move_pct: f64,
+    pub window: Duration,
+    pub pause: Duration,
+    pub action: BreakerAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    prices: VecDeque<(Timestamp, Price)>,
+    resume_at: Option<Timestamp>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            config,
+            prices: VecDeque::new(),
+            resume_at: None,
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.resume_at.is_some()
+    }
+
+    pub fn resume_at(&self) -> Option<Timestamp> {
+        self.resume_at
+    }
+
+    // Returns the reference price the trade moved too far from when the breaker trips.
+    // The window starts over after a trip so the pre-interruption prices are forgotten.
+    pub fn record_trade(&mut self, timestamp: Timestamp, price: Price) -> Option<Price> {
+        let window = self.config.window.as_nanos() as Timestamp;
+        while let Some(&(oldest, _)) = self.prices.front() {
+            if timestamp.saturating_sub(oldest) <= window {
+                break;
+            }
+            self.prices.pop_front();
+        }
+
+        let reference = self
+            .prices
+            .iter()
+            .map(|&(_, reference)| reference)
+            .find(|&reference| {
+                let moved = (price - reference).abs().to_f64();
+                moved * 100.0 > self.config.max_move_pct * reference.abs().to_f64()
+            });
+
+        match reference {
+            Some(reference) => {
+                self.prices.clear();
+                self.resume_at = Some(timestamp + self.config.pause.as_nanos() as Timestamp);
+                Some(reference)
+            }
+            None => {
+                self.prices.push_back((timestamp, price));
+                None
+            }
+        }
+    }
+
+    // Clears the trip once the clock reached the end of the pause
+    pub(crate) fn take_resume(&mut self, now: Timestamp) -> bool {
+        match self.resume_at {
+            Some(resume_at) if resume_at <= now => {
+                self.resume_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EngineEvent;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Price, Quantity, Rejected, Side};
+    use crate::session::SessionState;
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    fn config(action: BreakerAction) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            max_move_pct: 10.0,
+            window: Duration::from_secs(60),
+            pause: Duration::from_secs(30),
+            action,
+        }
+    }
+
+    fn cross(orderbook: &mut OrderBook, first_id: u64, price: Price) -> usize {
+        orderbook.add_order(Order::new(
+            first_id,
+            price,
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook
+            .add_order(Order::new(
+                first_id + 1,
+                price,
+                Quantity(1),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            ))
+            .len()
+    }
+
+    #[test]
+    fn test_window_expires_old_prices() {
+        let mut breaker = CircuitBreaker::new(config(BreakerAction::Halt));
+        assert_eq!(breaker.record_trade(0, Price(100)), None);
+        assert_eq!(breaker.record_trade(10 * SECOND, Price(109)), None);
+        // 100 left the window, 109 -> 118 is within 10%
+        assert_eq!(breaker.record_trade(61 * SECOND, Price(118)), None);
+        assert_eq!(
+            breaker.record_trade(62 * SECOND, Price(90)),
+            Some(Price(109))
+        );
+        assert_eq!(breaker.resume_at(), Some(92 * SECOND));
+        assert!(!breaker.take_resume(91 * SECOND));
+        assert!(breaker.take_resume(92 * SECOND));
+        assert!(!breaker.is_tripped());
+    }
+
+    #[test]
+    fn test_breaker_halts_engine_until_pause_elapsed() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_circuit_breaker(CircuitBreaker::new(config(BreakerAction::Halt)));
+
+        assert_eq!(cross(&mut orderbook, 1, Price(100)), 1);
+        assert_eq!(cross(&mut orderbook, 3, Price(115)), 1);
+        assert_eq!(orderbook.session_state(), SessionState::Halted);
+        assert_eq!(
+            orderbook.drain_events()[0],
+            EngineEvent::CircuitBreakerTriggered {
+                reference_price: Price(100),
+                trade_price: Price(115),
+                timestamp: 0,
+                resume_at: 30 * SECOND,
+            }
+        );
+        assert_eq!(
+            orderbook
+                .place_order(Order::new(
+                    5,
+                    Price(100),
+                    Quantity(1),
+                    OrderType::GoodToCancel,
+                    Side::Buy
+                ))
+                .unwrap_err(),
+            Rejected::SessionNotOpen(SessionState::Halted)
+        );
+
+        orderbook.advance_clock(29 * SECOND);
+        assert_eq!(orderbook.session_state(), SessionState::Halted);
+        orderbook.advance_clock(30 * SECOND);
+        assert_eq!(orderbook.session_state(), SessionState::Open);
+        assert_eq!(cross(&mut orderbook, 6, Price(100)), 1);
+    }
+
+    #[test]
+    fn test_breaker_auction_uncrosses_on_resume() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_circuit_breaker(CircuitBreaker::new(config(BreakerAction::Auction)));
+
+        cross(&mut orderbook, 1, Price(100));
+        cross(&mut orderbook, 3, Price(80));
+        assert_eq!(orderbook.session_state(), SessionState::PreOpen);
+        assert_eq!(cross(&mut orderbook, 5, Price(90)), 0);
+
+        let trades = orderbook.advance_clock(30 * SECOND);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].0, 30 * SECOND);
+        assert_eq!(trades[0].1.bid_trade.price, Price(90));
+        assert_eq!(orderbook.session_state(), SessionState::Open);
+        assert_eq!(orderbook.orderbook_size(), 0);
+    }
+}
@@ -0,0 +1,209 @@
+/// Time sources for stamping book updates.
+/// A `Clock` gives a monotonic reading for measuring intervals and a wall clock reading for
+/// correlating with the outside world, both in nanoseconds. The books use `SystemClock` unless
+/// another clock is injected; tests inject a `MockClock` and move it by hand so stamps are
+/// deterministic. The matching engine's `SimClock` is a `Clock` too, with both readings
+/// following the backtest time.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Nanoseconds, same unit as the engine `Timestamp`
+pub type Nanos = u64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    // Since an arbitrary origin, only differences are meaningful
+    pub monotonic: Nanos,
+    // Since the Unix epoch
+    pub wall: Nanos,
+}
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn monotonic(&self) -> Nanos;
+
+    fn wall(&self) -> Nanos;
+
+    fn stamp(&self) -> Stamp {
+        Stamp {
+            monotonic: self.monotonic(),
+            wall: self.wall(),
+        }
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+// Clock the books start with
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+// Monotonic readings count from the first use in the process, so stamps taken by different
+// books compare
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+fn origin() -> Instant {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    *ORIGIN.get_or_init(Instant::now)
+}
+
+impl Clock for SystemClock {
+    fn monotonic(&self) -> Nanos {
+        origin().elapsed().as_nanos() as Nanos
+    }
+
+    fn wall(&self) -> Nanos {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as Nanos)
+    }
+}
+
+// Moved by hand, clones share the same time so a test keeps a handle on the clock it injected
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new(start: Nanos) -> MockClock {
+        MockClock {
+            now: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    pub fn set(&self, now: Nanos) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, delta: Duration) {
+        self.now
+            .fetch_add(delta.as_nanos() as Nanos, Ordering::SeqCst);
+    }
+}
+
+// Both readings are the mock time
+impl Clock for MockClock {
+    fn monotonic(&self) -> Nanos {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn wall(&self) -> Nanos {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use crate::l3book::L3Book;
+    use crate::orderbook::{self, OrderBook};
+    use crate::orderbookv2::{self, Order, OrderId, OrderType, Price, Quantity, Side, Timestamp};
+
+    #[test]
+    fn test_mock_clock_is_shared_between_clones() {
+        let clock = MockClock::new(5);
+        let injected: SharedClock = Arc::new(clock.clone());
+        clock.advance(Duration::from_nanos(10));
+        assert_eq!(
+            injected.stamp(),
+            Stamp {
+                monotonic: 15,
+                wall: 15
+            }
+        );
+        clock.set(100);
+        assert_eq!(injected.monotonic(), 100);
+    }
+
+    #[test]
+    fn test_system_clock_is_monotonic() {
+        let clock = SystemClock;
+        let first = clock.stamp();
+        let second = clock.stamp();
+        assert!(second.monotonic >= first.monotonic);
+        assert!(first.wall > 0);
+    }
+
+    #[test]
+    fn test_books_are_stamped_with_injected_clock() {
+        let clock = MockClock::new(1_000);
+        let mut book = OrderBook::new("BNBUSDT");
+        book.set_clock(Arc::new(clock.clone()));
+        let mut l3_book = L3Book::new("BNBUSDT");
+        l3_book.set_clock(Arc::new(clock.clone()));
+        assert_eq!(book.last_update(), None);
+
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(25.0, 1.0)],
+            asks: vec![],
+        });
+        assert_eq!(book.last_update().unwrap().monotonic, 1_000);
+        // Stale updates are ignored and keep the old stamp
+        clock.advance(Duration::from_nanos(500));
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![],
+            asks: vec![],
+        });
+        assert_eq!(book.last_update().unwrap().monotonic, 1_000);
+
+        l3_book
+            .add(
+                1,
+                Side::Buy,
+                orderbook::Price(250_000),
+                orderbook::Quantity(10),
+            )
+            .unwrap();
+        clock.advance(Duration::from_nanos(500));
+        l3_book.execute(1, orderbook::Quantity(4), 0).unwrap();
+        assert_eq!(l3_book.last_update().unwrap().wall, 2_000);
+    }
+
+    #[test]
+    fn test_engine_stamps_orders_and_trades() {
+        let mut engine = orderbookv2::OrderBook::new();
+        engine.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        engine.advance_clock(700);
+        let trades = engine.add_order(Order::new(
+            2,
+            Price(100),
+            Quantity(2),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        engine.add_order(Order::new(
+            3,
+            Price(99),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        assert_eq!(trades[0].timestamp, 700);
+        let accepted_at: Vec<(OrderId, Timestamp)> = engine
+            .state()
+            .orders
+            .iter()
+            .map(|order| (order.get_order_id(), order.get_timestamp()))
+            .collect();
+        assert_eq!(accepted_at, vec![(3, 700), (1, 0)]);
+        assert_eq!(engine.clock().stamp().wall, 700);
+    }
+}
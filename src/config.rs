@@ -0,0 +1,604 @@
+/// Endpoints and keys of a Binance environment.
+/// `BinanceConfig` holds the REST and websocket base URLs, the API keys and the receive window
+/// used by the market data, user data and order entry clients. Production and testnet presets
+/// are provided, and `from_env` picks the environment and overrides from `BINANCE_*` variables
+/// so a binary can be pointed at the testnet without code changes.
+/// `EngineConfig` is the TOML file a feed process starts from: the venue, the symbols to follow
+/// and their depth, where instruments and recordings live and which metrics to keep. See
+/// `book.toml` at the repository root for an example.
+use crate::alerts::AlertRules;
+use crate::pipeline::{OverflowPolicy, StageConfig};
+use crate::price_levels::BookBackend;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const ENV_ENVIRONMENT: &str = "BINANCE_ENV";
+pub const ENV_REST_URL: &str = "BINANCE_REST_URL";
+pub const ENV_WS_URL: &str = "BINANCE_WS_URL";
+pub const ENV_API_KEY: &str = "BINANCE_API_KEY";
+pub const ENV_API_SECRET: &str = "BINANCE_API_SECRET";
+pub const ENV_RECV_WINDOW: &str = "BINANCE_RECV_WINDOW";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Production,
+    Testnet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    InvalidEnvironment(String),
+    InvalidRecvWindow(String),
+    // The client needs the API key and secret
+    MissingCredentials,
+    Io(String),
+    Toml(toml::de::Error),
+    // Partial depth streams come in 5, 10 and 20 levels
+    InvalidDepth { symbol: String, depth: u16 },
+    DuplicateSymbol(String),
+    Instruments(String),
+    // A `[metrics]` setting out of range, holds the reason
+    InvalidMetrics(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidEnvironment(value) => write!(
+                f,
+                "invalid {} {:?}, expected production or testnet",
+                ENV_ENVIRONMENT, value
+            ),
+            ConfigError::InvalidRecvWindow(value) => {
+                write!(f, "invalid {} {:?}", ENV_RECV_WINDOW, value)
+            }
+            ConfigError::MissingCredentials => write!(
+                f,
+                "missing credentials, set {} and {}",
+                ENV_API_KEY, ENV_API_SECRET
+            ),
+            ConfigError::Io(error) => write!(f, "cannot read config file: {}", error),
+            ConfigError::Toml(error) => write!(f, "invalid config file: {}", error),
+            ConfigError::InvalidDepth { symbol, depth } => write!(
+                f,
+                "invalid depth {} for {}, expected 5, 10 or 20",
+                depth, symbol
+            ),
+            ConfigError::DuplicateSymbol(symbol) => write!(f, "{} is listed twice", symbol),
+            ConfigError::Instruments(error) => write!(f, "cannot load instruments: {}", error),
+            ConfigError::InvalidMetrics(reason) => write!(f, "invalid metrics: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct BinanceConfig {
+    pub environment: Environment,
+    pub rest_url: String,
+    // Combined stream endpoint, streams are subscribed to on the connection
+    pub ws_url: String,
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    // Milliseconds a signed request stays valid, Binance defaults to 5000
+    pub recv_window: Option<u64>,
+}
+
+// Keeps the secret out of the logs
+impl fmt::Debug for BinanceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinanceConfig")
+            .field("environment", &self.environment)
+            .field("rest_url", &self.rest_url)
+            .field("ws_url", &self.ws_url)
+            .field("api_key", &self.api_key)
+            .field("api_secret", &self.api_secret.as_ref().map(|_| "***"))
+            .field("recv_window", &self.recv_window)
+            .finish()
+    }
+}
+
+impl Default for BinanceConfig {
+    fn default() -> Self {
+        BinanceConfig::production()
+    }
+}
+
+impl BinanceConfig {
+    pub fn production() -> BinanceConfig {
+        BinanceConfig::preset(Environment::Production)
+    }
+
+    pub fn testnet() -> BinanceConfig {
+        BinanceConfig::preset(Environment::Testnet)
+    }
+
+    pub fn preset(environment: Environment) -> BinanceConfig {
+        let (rest_url, ws_url) = match environment {
+            Environment::Production => (
+                "https://api.binance.com",
+                "wss://stream.binance.com:9443/stream",
+            ),
+            Environment::Testnet => (
+                "https://testnet.binance.vision",
+                "wss://stream.testnet.binance.vision/stream",
+            ),
+        };
+        BinanceConfig {
+            environment,
+            rest_url: rest_url.to_string(),
+            ws_url: ws_url.to_string(),
+            api_key: None,
+            api_secret: None,
+            recv_window: None,
+        }
+    }
+
+    pub fn with_credentials(
+        mut self,
+        api_key: impl Into<String>,
+        api_secret: impl Into<String>,
+    ) -> BinanceConfig {
+        self.api_key = Some(api_key.into());
+        self.api_secret = Some(api_secret.into());
+        self
+    }
+
+    pub fn with_recv_window(mut self, recv_window: u64) -> BinanceConfig {
+        self.recv_window = Some(recv_window);
+        self
+    }
+
+    // Preset of `BINANCE_ENV` (production when unset), then the URL, key and receive window
+    // variables on top of it
+    pub fn from_env() -> Result<BinanceConfig, ConfigError> {
+        BinanceConfig::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<BinanceConfig, ConfigError> {
+        let environment = match var(ENV_ENVIRONMENT) {
+            None => Environment::Production,
+            Some(value) => match value.to_ascii_lowercase().as_str() {
+                "" | "prod" | "production" => Environment::Production,
+                "test" | "testnet" => Environment::Testnet,
+                _ => return Err(ConfigError::InvalidEnvironment(value)),
+            },
+        };
+        let mut config = BinanceConfig::preset(environment);
+        if let Some(rest_url) = var(ENV_REST_URL) {
+            config.rest_url = rest_url;
+        }
+        if let Some(ws_url) = var(ENV_WS_URL) {
+            config.ws_url = ws_url;
+        }
+        config.api_key = var(ENV_API_KEY);
+        config.api_secret = var(ENV_API_SECRET);
+        if let Some(value) = var(ENV_RECV_WINDOW) {
+            let recv_window = value
+                .parse()
+                .map_err(|_| ConfigError::InvalidRecvWindow(value))?;
+            config.recv_window = Some(recv_window);
+        }
+        Ok(config)
+    }
+
+    // HMAC credentials for the signed endpoints
+    #[cfg(feature = "native")]
+    pub fn credentials(
+        &self,
+    ) -> Result<binance_spot_connector_rust::http::Credentials, ConfigError> {
+        match (&self.api_key, &self.api_secret) {
+            (Some(api_key), Some(api_secret)) => Ok(
+                binance_spot_connector_rust::http::Credentials::from_hmac(api_key, api_secret),
+            ),
+            _ => Err(ConfigError::MissingCredentials),
+        }
+    }
+}
+
+pub const DEFAULT_DEPTH: u16 = 20;
+
+fn default_depth() -> u16 {
+    DEFAULT_DEPTH
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngineConfig {
+    #[serde(default)]
+    pub venue: VenueConfig,
+    // Depth of the symbols that do not set their own
+    #[serde(default = "default_depth")]
+    pub depth: u16,
+    #[serde(default)]
+    pub backend: BookBackend,
+    #[serde(default)]
+    pub symbols: Vec<SymbolConfig>,
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+}
+
+// The preset of `environment` with the URLs given here on top of it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VenueConfig {
+    #[serde(default)]
+    pub environment: Environment,
+    pub rest_url: Option<String>,
+    pub ws_url: Option<String>,
+    pub recv_window: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolConfig {
+    pub symbol: String,
+    pub depth: Option<u16>,
+    #[serde(default = "enabled")]
+    pub book_ticker: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PersistenceConfig {
+    // Instrument file, see `InstrumentRegistry::load_toml`. Symbols without an entry keep the
+    // default converter.
+    pub instruments: Option<PathBuf>,
+    // Every payload received is appended to this recording, see `recording`
+    pub recording: Option<PathBuf>,
+    // One line per change of a book level, see `book_audit`
+    pub audit_log: Option<AuditLogConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogConfig {
+    pub path: PathBuf,
+    // Size at which the file is rotated, 100 MiB by default
+    pub max_bytes: Option<u64>,
+    // Rotated files kept, 5 by default
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    // Feed latency, see `latency`
+    #[serde(default)]
+    pub latency: bool,
+    pub latency_capacity: Option<usize>,
+    // Rolling returns and volatility of the mid price of every symbol, see `stats`
+    #[serde(default)]
+    pub volatility: bool,
+    // Defaults to 1 and 5 minutes
+    pub volatility_windows_secs: Option<Vec<u64>>,
+    pub ewma_lambda: Option<f64>,
+    // Order flow imbalance of the top of book summed over these windows, see `signals`. No
+    // signals without windows.
+    pub order_flow_windows_secs: Option<Vec<u64>>,
+    // Seconds between two metrics reports in the log
+    pub report_interval_secs: Option<u64>,
+    // Price move, spread and depth alerts of every symbol, see `alerts`
+    pub alerts: Option<AlertsConfig>,
+}
+
+// Each rule is off until its threshold is set
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+    // Mid move in basis points within the window, 60 seconds by default
+    pub mid_move_bps: Option<f64>,
+    pub mid_move_window_secs: Option<u64>,
+    // Spread in basis points of the mid
+    pub max_spread_bps: Option<f64>,
+    // Fraction of the peak depth of a side lost within the window, over the best `depth_levels`
+    // levels, 10 levels and 60 seconds by default
+    pub depth_drop: Option<f64>,
+    pub depth_levels: Option<usize>,
+    pub depth_window_secs: Option<u64>,
+}
+
+impl AlertsConfig {
+    pub fn rules(&self) -> AlertRules {
+        let window = |secs: Option<u64>| Duration::from_secs(secs.unwrap_or(60));
+        let mut rules = AlertRules::new();
+        if let Some(bps) = self.mid_move_bps {
+            rules = rules.with_mid_move(bps, window(self.mid_move_window_secs));
+        }
+        if let Some(bps) = self.max_spread_bps {
+            rules = rules.with_max_spread(bps);
+        }
+        if let Some(drop) = self.depth_drop {
+            rules = rules.with_depth_collapse(
+                self.depth_levels.unwrap_or(10),
+                drop,
+                window(self.depth_window_secs),
+            );
+        }
+        rules
+    }
+}
+
+// Queues between the stages of the binary, see `pipeline`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    // Socket payloads waiting for the books
+    #[serde(default)]
+    pub feed: StageConfig,
+    // Depth snapshots waiting for the display, a newer snapshot of a symbol replaces the
+    // queued one
+    #[serde(default = "conflated_stage")]
+    pub snapshots: StageConfig,
+}
+
+fn conflated_stage() -> StageConfig {
+    StageConfig {
+        policy: OverflowPolicy::Conflate,
+        ..StageConfig::default()
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            feed: StageConfig::default(),
+            snapshots: conflated_stage(),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn from_toml(contents: &str) -> Result<EngineConfig, ConfigError> {
+        let config: EngineConfig = toml::from_str(contents).map_err(ConfigError::Toml)?;
+        let mut seen = HashSet::new();
+        for symbol in &config.symbols {
+            let depth = config.depth_for(symbol);
+            if ![5, 10, 20].contains(&depth) {
+                return Err(ConfigError::InvalidDepth {
+                    symbol: symbol.symbol.clone(),
+                    depth,
+                });
+            }
+            if !seen.insert(symbol.symbol.to_ascii_uppercase()) {
+                return Err(ConfigError::DuplicateSymbol(symbol.symbol.clone()));
+            }
+        }
+        if let Some(lambda) = config.metrics.ewma_lambda {
+            if !(0.0..1.0).contains(&lambda) {
+                return Err(ConfigError::InvalidMetrics(format!(
+                    "ewma_lambda {} is not in [0, 1)",
+                    lambda
+                )));
+            }
+        }
+        let metrics = &config.metrics;
+        for windows in [
+            &metrics.volatility_windows_secs,
+            &metrics.order_flow_windows_secs,
+        ] {
+            if windows.as_ref().is_some_and(|windows| windows.contains(&0)) {
+                return Err(ConfigError::InvalidMetrics(
+                    "windows must be at least a second".to_string(),
+                ));
+            }
+        }
+        if let Some(alerts) = &metrics.alerts {
+            if [alerts.mid_move_window_secs, alerts.depth_window_secs].contains(&Some(0)) {
+                return Err(ConfigError::InvalidMetrics(
+                    "alert windows must be at least a second".to_string(),
+                ));
+            }
+            if let Some(drop) = alerts
+                .depth_drop
+                .filter(|drop| !(*drop > 0.0 && *drop < 1.0))
+            {
+                return Err(ConfigError::InvalidMetrics(format!(
+                    "depth_drop {} is not in (0, 1)",
+                    drop
+                )));
+            }
+            if alerts.depth_levels == Some(0) {
+                return Err(ConfigError::InvalidMetrics(
+                    "depth_levels must be at least 1".to_string(),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<EngineConfig, ConfigError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|error| ConfigError::Io(format!("{}: {}", path.display(), error)))?;
+        EngineConfig::from_toml(&contents)
+    }
+
+    pub fn depth_for(&self, symbol: &SymbolConfig) -> u16 {
+        symbol.depth.unwrap_or(self.depth)
+    }
+
+    // No credentials, signed clients add them with `with_credentials`
+    pub fn binance(&self) -> BinanceConfig {
+        let venue = &self.venue;
+        let mut config = BinanceConfig::preset(venue.environment);
+        if let Some(rest_url) = &venue.rest_url {
+            config.rest_url.clone_from(rest_url);
+        }
+        if let Some(ws_url) = &venue.ws_url {
+            config.ws_url.clone_from(ws_url);
+        }
+        config.recv_window = venue.recv_window;
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<BinanceConfig, ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        BinanceConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(from_vars(&[]).unwrap(), BinanceConfig::production());
+
+        let config = from_vars(&[
+            (ENV_ENVIRONMENT, "Testnet"),
+            (ENV_WS_URL, "wss://testnet.binance.vision/stream"),
+            (ENV_API_KEY, "key"),
+            (ENV_API_SECRET, "hunter2"),
+            (ENV_RECV_WINDOW, "10000"),
+        ])
+        .unwrap();
+        assert_eq!(config.environment, Environment::Testnet);
+        assert_eq!(config.rest_url, "https://testnet.binance.vision");
+        assert_eq!(config.ws_url, "wss://testnet.binance.vision/stream");
+        assert_eq!(config.recv_window, Some(10000));
+        assert!(!format!("{:?}", config).contains("hunter2"));
+
+        assert_eq!(
+            from_vars(&[(ENV_ENVIRONMENT, "staging")]),
+            Err(ConfigError::InvalidEnvironment("staging".to_string()))
+        );
+        assert_eq!(
+            from_vars(&[(ENV_RECV_WINDOW, "5s")]),
+            Err(ConfigError::InvalidRecvWindow("5s".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_engine_config_from_toml() {
+        let config = EngineConfig::from_toml(
+            r#"
+            depth = 10
+
+            [venue]
+            environment = "testnet"
+            ws_url = "wss://localhost:9443/stream"
+
+            [[symbols]]
+            symbol = "ETHUSDC"
+
+            [[symbols]]
+            symbol = "BTCUSDT"
+            depth = 5
+            book_ticker = false
+
+            [persistence]
+            recording = "recordings/session.jsonl"
+            audit_log = { path = "recordings/book-audit.log", max_files = 3 }
+
+            [metrics]
+            latency = true
+            report_interval_secs = 60
+
+            [metrics.alerts]
+            mid_move_bps = 25
+            max_spread_bps = 10
+            depth_drop = 0.8
+            depth_window_secs = 5
+
+            [pipeline.feed]
+            capacity = 256
+            policy = "drop_oldest"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.depth_for(&config.symbols[0]), 10);
+        assert_eq!(config.depth_for(&config.symbols[1]), 5);
+        assert!(config.symbols[0].book_ticker && !config.symbols[1].book_ticker);
+        assert_eq!(config.backend, BookBackend::BTree);
+        assert_eq!(config.persistence.instruments, None);
+        assert_eq!(
+            config.persistence.audit_log,
+            Some(AuditLogConfig {
+                path: PathBuf::from("recordings/book-audit.log"),
+                max_bytes: None,
+                max_files: Some(3)
+            })
+        );
+        assert!(config.metrics.latency);
+        let rules = config.metrics.alerts.as_ref().unwrap().rules();
+        assert_eq!(rules.mid_move.unwrap().window, Duration::from_secs(60));
+        assert_eq!(rules.max_spread_bps, Some(10.0));
+        let collapse = rules.depth_collapse.unwrap();
+        assert_eq!(
+            (collapse.levels, collapse.drop, collapse.window),
+            (10, 0.8, Duration::from_secs(5))
+        );
+        assert_eq!(
+            config.pipeline.feed,
+            StageConfig {
+                capacity: 256,
+                policy: OverflowPolicy::DropOldest
+            }
+        );
+        assert_eq!(config.pipeline.snapshots.policy, OverflowPolicy::Conflate);
+
+        let binance = config.binance();
+        assert_eq!(binance.environment, Environment::Testnet);
+        assert_eq!(binance.rest_url, "https://testnet.binance.vision");
+        assert_eq!(binance.ws_url, "wss://localhost:9443/stream");
+
+        assert_eq!(
+            EngineConfig::from_toml("[[symbols]]\nsymbol = \"ETHUSDC\"\ndepth = 50"),
+            Err(ConfigError::InvalidDepth {
+                symbol: "ETHUSDC".to_string(),
+                depth: 50
+            })
+        );
+        assert_eq!(
+            EngineConfig::from_toml("[[symbols]]\nsymbol = \"a\"\n[[symbols]]\nsymbol = \"A\""),
+            Err(ConfigError::DuplicateSymbol("A".to_string()))
+        );
+        assert!(matches!(
+            EngineConfig::from_toml("[metrics]\newma_lambda = 1.5"),
+            Err(ConfigError::InvalidMetrics(_))
+        ));
+        assert!(matches!(
+            EngineConfig::from_toml("[metrics.alerts]\ndepth_drop = 1.0"),
+            Err(ConfigError::InvalidMetrics(_))
+        ));
+        assert!(matches!(
+            EngineConfig::from_toml("levels = 20"),
+            Err(ConfigError::Toml(_))
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn test_credentials() {
+        assert_eq!(
+            BinanceConfig::testnet().credentials().err(),
+            Some(ConfigError::MissingCredentials)
+        );
+        let credentials = BinanceConfig::testnet()
+            .with_credentials("key", "secret")
+            .credentials()
+            .unwrap();
+        assert_eq!(credentials.api_key, "key");
+    }
+}
@@ -0,0 +1,261 @@
+/// Conflating fan-out of the engine market data for slow consumers.
+/// Every subscriber keeps at most one pending entry per price level, the latest trade print and
+/// the latest snapshot, so a subscriber that falls behind costs memory proportional to the book
+/// rather than to the message rate. Pending updates are handed out at most once per
+/// subscriber interval, in sequence order, and applying them leaves a consumer with the same
+/// book as applying every message.
+use crate::market_data::{BookSnapshot, LevelDelta, MarketDataMessage, TradePrint};
+use crate::orderbookv2::{Price, Side, Timestamp};
+use std::collections::{BTreeMap, HashMap};
+
+pub type SubscriberId = u64;
+
+#[derive(Debug, Clone, Default)]
+struct Pending {
+    snapshot: Option<BookSnapshot>,
+    bids: BTreeMap<Price, LevelDelta>,
+    asks: BTreeMap<Price, LevelDelta>,
+    trade: Option<TradePrint>,
+}
+
+impl Pending {
+    fn push(&mut self, message: &MarketDataMessage) {
+        match message {
+            // Everything pending is superseded by the new snapshot
+            MarketDataMessage::Snapshot(snapshot) => {
+                *self = Pending {
+                    snapshot: Some(snapshot.clone()),
+                    ..Pending::default()
+                }
+            }
+            MarketDataMessage::Delta(delta) => {
+                let levels = match delta.side {
+                    Side::Buy => &mut self.bids,
+                    Side::Sell => &mut self.asks,
+                };
+                levels.insert(delta.price, *delta);
+            }
+            MarketDataMessage::Trade(trade) => self.trade = Some(*trade),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.snapshot.iter().count() + self.bids.len() + self.asks.len() + self.trade.iter().count()
+    }
+
+    fn take(&mut self) -> Vec<MarketDataMessage> {
+        let pending = std::mem::take(self);
+        let mut messages: Vec<MarketDataMessage> = pending
+            .snapshot
+            .map(MarketDataMessage::Snapshot)
+            .into_iter()
+            .chain(pending.trade.map(MarketDataMessage::Trade))
+            .chain(
+                pending
+                    .bids
+                    .into_values()
+                    .chain(pending.asks.into_values())
+                    .map(MarketDataMessage::Delta),
+            )
+            .collect();
+        messages.sort_by_key(sequence);
+        messages
+    }
+}
+
+fn sequence(message: &MarketDataMessage) -> u64 {
+    match message {
+        MarketDataMessage::Snapshot(snapshot) => snapshot.sequence,
+        MarketDataMessage::Delta(delta) => delta.sequence,
+        MarketDataMessage::Trade(trade) => trade.sequence,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Subscriber {
+    // Minimum time between two deliveries, zero delivers on every poll
+    interval: Timestamp,
+    last_delivery: Option<Timestamp>,
+    pending: Pending,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConflatingQueue {
+    next_id: SubscriberId,
+    subscribers: HashMap<SubscriberId, Subscriber>,
+}
+
+impl ConflatingQueue {
+    pub fn new() -> ConflatingQueue {
+        ConflatingQueue::default()
+    }
+
+    // New subscribers only see messages published after they joined, start them from
+    // `MarketDataPublisher::snapshot`
+    pub fn subscribe(&mut self, interval: Timestamp) -> SubscriberId {
+        self.next_id += 1;
+        self.subscribers.insert(
+            self.next_id,
+            Subscriber {
+                interval,
+                last_delivery: None,
+                pending: Pending::default(),
+            },
+        );
+        self.next_id
+    }
+
+    pub fn unsubscribe(&mut self, subscriber: SubscriberId) {
+        self.subscribers.remove(&subscriber);
+    }
+
+    pub fn set_interval(&mut self, subscriber: SubscriberId, interval: Timestamp) {
+        if let Some(subscriber) = self.subscribers.get_mut(&subscriber) {
+            subscriber.interval = interval;
+        }
+    }
+
+    pub fn publish(&mut self, message: &MarketDataMessage) {
+        for subscriber in self.subscribers.values_mut() {
+            subscriber.pending.push(message);
+        }
+    }
+
+    // Number of conflated entries waiting for the subscriber
+    pub fn pending(&self, subscriber: SubscriberId) -> usize {
+        self.subscribers
+            .get(&subscriber)
+            .map_or(0, |subscriber| subscriber.pending.len())
+    }
+
+    // Conflated updates since the last delivery, empty until the subscriber interval has passed
+    pub fn poll(&mut self, subscriber: SubscriberId, now: Timestamp) -> Vec<MarketDataMessage> {
+        let Some(subscriber) = self.subscribers.get_mut(&subscriber) else {
+            return Vec::new();
+        };
+        if subscriber
+            .last_delivery
+            .is_some_and(|last| now < last.saturating_add(subscriber.interval))
+        {
+            return Vec::new();
+        }
+        subscriber.last_delivery = Some(now);
+        subscriber.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Price, Quantity};
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_slow_subscriber_gets_latest_level_state() {
+        let mut engine = OrderBook::new();
+        engine.enable_market_data();
+        let mut queue = ConflatingQueue::new();
+        let fast = queue.subscribe(0);
+        let slow = queue.subscribe(SECOND);
+
+        let mut fast_book = orderbook::OrderBook::new("SIM".to_string());
+        let mut slow_book = orderbook::OrderBook::new("SIM".to_string());
+        let mut fast_messages = 0;
+
+        for order_id in 1..=50u64 {
+            let side = if order_id % 2 == 0 {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            let price = 100 + (order_id as i32 % 5) * if side == Side::Buy { -1 } else { 1 };
+            engine.add_order(Order::new(
+                order_id,
+                Price(price),
+                Quantity(1),
+                OrderType::GoodToCancel,
+                side,
+            ));
+            if order_id % 3 == 0 {
+                engine.cancel_order(order_id - 1).unwrap();
+            }
+            for message in engine.drain_market_data() {
+                queue.publish(&message);
+            }
+
+            let now = order_id * SECOND / 10;
+            for message in queue.poll(fast, now) {
+                fast_messages += 1;
+                fast_book.apply_market_data(&message);
+            }
+            for message in queue.poll(slow, now) {
+                slow_book.apply_market_data(&message);
+            }
+            // Never more than the snapshot, one trade and the ten levels in use
+            assert!(queue.pending(slow) <= 12);
+        }
+        for message in queue.poll(slow, 10 * SECOND) {
+            slow_book.apply_market_data(&message);
+        }
+
+        assert!(fast_messages > 50);
+        assert_eq!(queue.pending(slow), 0);
+        assert_eq!(
+            slow_book.bids().collect::<Vec<_>>(),
+            fast_book.bids().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            slow_book.asks().collect::<Vec<_>>(),
+            fast_book.asks().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_interval_and_snapshot_reset() {
+        let mut queue = ConflatingQueue::new();
+        let subscriber = queue.subscribe(SECOND);
+        let delta = |sequence, price, quantity| {
+            MarketDataMessage::Delta(LevelDelta {
+                sequence,
+                side: Side::Buy,
+                price,
+                quantity,
+            })
+        };
+
+        queue.publish(&delta(2, Price(100), Quantity(5)));
+        queue.publish(&delta(3, Price(100), Quantity(0)));
+        queue.publish(&delta(4, Price(99), Quantity(1)));
+        assert_eq!(
+            queue.poll(subscriber, 0),
+            vec![
+                delta(3, Price(100), Quantity(0)),
+                delta(4, Price(99), Quantity(1))
+            ]
+        );
+
+        queue.publish(&delta(5, Price(98), Quantity(1)));
+        assert!(queue.poll(subscriber, SECOND / 2).is_empty());
+
+        let snapshot = BookSnapshot {
+            sequence: 6,
+            bids: vec![],
+            asks: vec![],
+        };
+        queue.publish(&MarketDataMessage::Snapshot(snapshot.clone()));
+        queue.publish(&delta(7, Price(97), Quantity(2)));
+        assert_eq!(
+            queue.poll(subscriber, SECOND),
+            vec![
+                MarketDataMessage::Snapshot(snapshot),
+                delta(7, Price(97), Quantity(2))
+            ]
+        );
+
+        queue.unsubscribe(subscriber);
+        queue.publish(&delta(8, Price(97), Quantity(0)));
+        assert_eq!(queue.pending(subscriber), 0);
+    }
+}
@@ -0,0 +1,350 @@
+/// Health of the websocket connection to Binance.
+/// Binance pings every connection and closes it when no pong comes back in time, and it
+/// disconnects every connection after 24 hours. `ConnectionMonitor` is told about the socket
+/// activity (messages, pings, pongs, connects and disconnects) and `check` turns it into a
+/// `ConnectionHealth`. Health changes and reconnect requests are buffered as
+/// `ConnectionEvent`s for the orchestration code, a reconnect is requested ahead of the 24 hour
+/// limit so the feed can be moved to a fresh connection before Binance drops it.
+use crate::clock::{self, Nanos, SharedClock};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    // Binance closes the connection when a ping is not answered within a minute
+    pub pong_timeout: Duration,
+    // No message for this long degrades the health
+    pub quiet_after: Duration,
+    // No message for this long and the stream is considered dead
+    pub stale_after: Duration,
+    pub max_connection_age: Duration,
+    // How long before `max_connection_age` the reconnect is requested
+    pub reconnect_margin: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            pong_timeout: Duration::from_secs(60),
+            quiet_after: Duration::from_secs(5),
+            stale_after: Duration::from_secs(30),
+            max_connection_age: Duration::from_secs(24 * 60 * 60),
+            reconnect_margin: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthIssue {
+    // Nothing received for `quiet_after`
+    Quiet,
+    // A ping is still waiting for its pong
+    PongOverdue,
+    // The connection is about to hit the 24 hour limit
+    ConnectionExpiring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionHealth {
+    Disconnected,
+    Healthy,
+    Degraded(HealthIssue),
+    // Nothing received for `stale_after`
+    Stale,
+}
+
+impl ConnectionHealth {
+    // Whether the connection should be replaced
+    pub fn needs_reconnect(&self) -> bool {
+        match self {
+            ConnectionHealth::Healthy | ConnectionHealth::Degraded(HealthIssue::Quiet) => false,
+            ConnectionHealth::Disconnected
+            | ConnectionHealth::Stale
+            | ConnectionHealth::Degraded(HealthIssue::PongOverdue)
+            | ConnectionHealth::Degraded(HealthIssue::ConnectionExpiring) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectReason {
+    Closed,
+    Stale,
+    PongOverdue,
+    ConnectionExpiring,
+}
+
+// Timestamps are monotonic clock readings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected {
+        timestamp: Nanos,
+    },
+    HealthChanged {
+        health: ConnectionHealth,
+        timestamp: Nanos,
+    },
+    ReconnectRequested {
+        reason: ReconnectReason,
+        timestamp: Nanos,
+    },
+}
+
+#[derive(Debug)]
+pub struct ConnectionMonitor {
+    config: HeartbeatConfig,
+    clock: SharedClock,
+    connected_at: Option<Nanos>,
+    last_message: Nanos,
+    // Oldest ping that has not been answered yet
+    pending_ping: Option<Nanos>,
+    health: ConnectionHealth,
+    reconnects: u64,
+    events: Vec<ConnectionEvent>,
+}
+
+impl Default for ConnectionMonitor {
+    fn default() -> Self {
+        ConnectionMonitor::new(HeartbeatConfig::default())
+    }
+}
+
+impl ConnectionMonitor {
+    pub fn new(config: HeartbeatConfig) -> ConnectionMonitor {
+        ConnectionMonitor {
+            config,
+            clock: clock::system(),
+            connected_at: None,
+            last_message: 0,
+            pending_ping: None,
+            health: ConnectionHealth::Disconnected,
+            reconnects: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    pub fn config(&self) -> &HeartbeatConfig {
+        &self.config
+    }
+
+    // Health as of the last `check`
+    pub fn health(&self) -> ConnectionHealth {
+        self.health
+    }
+
+    // Number of reconnects requested so far
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    pub fn connection_age(&self) -> Option<Duration> {
+        self.connected_at
+            .map(|connected_at| Duration::from_nanos(self.clock.monotonic() - connected_at))
+    }
+
+    pub fn on_connected(&mut self) {
+        let now = self.clock.monotonic();
+        self.connected_at = Some(now);
+        self.last_message = now;
+        self.pending_ping = None;
+        self.events
+            .push(ConnectionEvent::Connected { timestamp: now });
+        self.set_health(ConnectionHealth::Healthy, now);
+    }
+
+    pub fn on_disconnected(&mut self) {
+        if self.connected_at.take().is_none() {
+            return;
+        }
+        let now = self.clock.monotonic();
+        self.pending_ping = None;
+        self.set_health(ConnectionHealth::Disconnected, now);
+    }
+
+    // Any data frame from the stream
+    pub fn on_message(&mut self) {
+        self.last_message = self.clock.monotonic();
+    }
+
+    pub fn on_ping(&mut self) {
+        let now = self.clock.monotonic();
+        self.last_message = now;
+        self.pending_ping.get_or_insert(now);
+    }
+
+    pub fn on_pong_sent(&mut self) {
+        self.pending_ping = None;
+    }
+
+    // Evaluates the health at the current time, to be called periodically
+    pub fn check(&mut self) -> ConnectionHealth {
+        let now = self.clock.monotonic();
+        let health = self.evaluate(now);
+        self.set_health(health, now);
+        health
+    }
+
+    pub fn drain_events(&mut self) -> Vec<ConnectionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn evaluate(&self, now: Nanos) -> ConnectionHealth {
+        let Some(connected_at) = self.connected_at else {
+            return ConnectionHealth::Disconnected;
+        };
+        let elapsed = |since: Nanos| Duration::from_nanos(now.saturating_sub(since));
+
+        if elapsed(self.last_message) >= self.config.stale_after {
+            return ConnectionHealth::Stale;
+        }
+        if self
+            .pending_ping
+            .is_some_and(|ping| elapsed(ping) >= self.config.pong_timeout)
+        {
+            return ConnectionHealth::Degraded(HealthIssue::PongOverdue);
+        }
+        let expires_in = self
+            .config
+            .max_connection_age
+            .saturating_sub(elapsed(connected_at));
+        if expires_in <= self.config.reconnect_margin {
+            return ConnectionHealth::Degraded(HealthIssue::ConnectionExpiring);
+        }
+        if elapsed(self.last_message) >= self.config.quiet_after {
+            return ConnectionHealth::Degraded(HealthIssue::Quiet);
+        }
+        ConnectionHealth::Healthy
+    }
+
+    // Emits the change, and a reconnect request when the connection just became unusable
+    fn set_health(&mut self, health: ConnectionHealth, now: Nanos) {
+        if health == self.health {
+            return;
+        }
+        let was_usable = !self.health.needs_reconnect();
+        self.health = health;
+        self.events.push(ConnectionEvent::HealthChanged {
+            health,
+            timestamp: now,
+        });
+
+        let reason = match health {
+            ConnectionHealth::Healthy | ConnectionHealth::Degraded(HealthIssue::Quiet) => None,
+            ConnectionHealth::Disconnected => Some(ReconnectReason::Closed),
+            ConnectionHealth::Stale => Some(ReconnectReason::Stale),
+            ConnectionHealth::Degraded(HealthIssue::PongOverdue) => {
+                Some(ReconnectReason::PongOverdue)
+            }
+            ConnectionHealth::Degraded(HealthIssue::ConnectionExpiring) => {
+                Some(ReconnectReason::ConnectionExpiring)
+            }
+        };
+        if let (true, Some(reason)) = (was_usable, reason) {
+            self.reconnects += 1;
+            self.events.push(ConnectionEvent::ReconnectRequested {
+                reason,
+                timestamp: now,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use std::sync::Arc;
+
+    const SECOND: Nanos = 1_000_000_000;
+
+    fn monitor(clock: &MockClock) -> ConnectionMonitor {
+        let mut monitor = ConnectionMonitor::default();
+        monitor.set_clock(Arc::new(clock.clone()));
+        monitor
+    }
+
+    #[test]
+    fn test_liveness_and_pong() {
+        let clock = MockClock::new(0);
+        let mut monitor = monitor(&clock);
+        assert_eq!(monitor.check(), ConnectionHealth::Disconnected);
+
+        monitor.on_connected();
+        clock.set(6 * SECOND);
+        assert_eq!(
+            monitor.check(),
+            ConnectionHealth::Degraded(HealthIssue::Quiet)
+        );
+        monitor.on_ping();
+        assert_eq!(monitor.check(), ConnectionHealth::Healthy);
+
+        // The ping goes unanswered while messages keep flowing
+        for second in 7..=66 {
+            clock.set(second * SECOND);
+            monitor.on_message();
+        }
+        assert_eq!(
+            monitor.check(),
+            ConnectionHealth::Degraded(HealthIssue::PongOverdue)
+        );
+        monitor.on_pong_sent();
+        assert_eq!(monitor.check(), ConnectionHealth::Healthy);
+
+        clock.set(100 * SECOND);
+        assert_eq!(monitor.check(), ConnectionHealth::Stale);
+
+        let events = monitor.drain_events();
+        assert_eq!(events[0], ConnectionEvent::Connected { timestamp: 0 });
+        assert_eq!(
+            events.last(),
+            Some(&ConnectionEvent::ReconnectRequested {
+                reason: ReconnectReason::Stale,
+                timestamp: 100 * SECOND,
+            })
+        );
+        assert_eq!(monitor.reconnects(), 2);
+    }
+
+    #[test]
+    fn test_reconnect_ahead_of_connection_limit() {
+        let clock = MockClock::new(0);
+        let mut monitor = monitor(&clock);
+        monitor.on_connected();
+        monitor.drain_events();
+
+        clock.set((24 * 60 - 6) * 60 * SECOND);
+        monitor.on_message();
+        assert_eq!(monitor.check(), ConnectionHealth::Healthy);
+        clock.advance(Duration::from_secs(60));
+        monitor.on_message();
+        assert_eq!(
+            monitor.check(),
+            ConnectionHealth::Degraded(HealthIssue::ConnectionExpiring)
+        );
+        // Requested once, not on every check
+        monitor.check();
+        monitor.on_disconnected();
+        let timestamp = clock.monotonic();
+        assert_eq!(
+            monitor.drain_events(),
+            vec![
+                ConnectionEvent::HealthChanged {
+                    health: ConnectionHealth::Degraded(HealthIssue::ConnectionExpiring),
+                    timestamp,
+                },
+                ConnectionEvent::ReconnectRequested {
+                    reason: ReconnectReason::ConnectionExpiring,
+                    timestamp,
+                },
+                ConnectionEvent::HealthChanged {
+                    health: ConnectionHealth::Disconnected,
+                    timestamp,
+                },
+            ]
+        );
+        assert_eq!(monitor.connection_age(), None);
+    }
+}
@@ -0,0 +1,399 @@
+/// Bracket orders on top of the matching engine.
+/// A bracket is a parent order with a take-profit and a stop-loss attached. The engine knows
+/// nothing about the relationship, the manager keeps it: every fill of the parent, partial or
+/// full, submits a take-profit limit order and a stop-loss stop order of the opposite side
+/// sized to that fill, and cancelling the parent cancels whatever it has left open together
+/// with all of its children. The take-profit and the stop-loss of a fill are one-cancels-other:
+/// the first fill of either cancels the other, and so does cancelling either. The manager has
+/// to see every trade of the book to notice fills of resting parents and children, so callers
+/// pass the trades of their own engine calls to `on_trades` and report children they cancel
+/// themselves to `on_cancelled`.
+use crate::orderbookv2::{
+    AccountId, Order, OrderBook, OrderId, OrderType, Price, Quantity, Rejected, Side, Trade,
+};
+use crate::stops::{StopOrder, StopTrigger};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bracket {
+    pub take_profit: Price,
+    pub stop_price: Price,
+    // Limit of the order the stop enters, past the stop price to make sure it trades
+    pub stop_limit: Price,
+    pub stop_trigger: StopTrigger,
+}
+
+impl Bracket {
+    // The stop-loss enters at its stop price and triggers on the last trade price
+    pub fn new(take_profit: Price, stop_price: Price) -> Bracket {
+        Bracket {
+            take_profit,
+            stop_price,
+            stop_limit: stop_price,
+            stop_trigger: StopTrigger::LastTrade,
+        }
+    }
+
+    pub fn with_stop_limit(mut self, stop_limit: Price) -> Bracket {
+        self.stop_limit = stop_limit;
+        self
+    }
+
+    pub fn with_stop_trigger(mut self, stop_trigger: StopTrigger) -> Bracket {
+        self.stop_trigger = stop_trigger;
+        self
+    }
+}
+
+// Child orders submitted for one fill of a parent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildOrders {
+    pub take_profit: OrderId,
+    pub stop_loss: OrderId,
+}
+
+#[derive(Debug, Clone)]
+struct Parent {
+    bracket: Bracket,
+    side: Side,
+    account_id: AccountId,
+    children: Vec<ChildOrders>,
+}
+
+#[derive(Debug, Default)]
+pub struct ContingentOrders {
+    parents: HashMap<OrderId, Parent>,
+    // Both children of a fill point at each other until one of them fills or is cancelled
+    siblings: HashMap<OrderId, OrderId>,
+}
+
+impl ContingentOrders {
+    pub fn new() -> ContingentOrders {
+        ContingentOrders::default()
+    }
+
+    // Places the parent and brackets whatever it filled on entry. The returned trades include
+    // the ones of the children.
+    pub fn place_bracket(
+        &mut self,
+        book: &mut OrderBook,
+        parent: Order,
+        bracket: Bracket,
+    ) -> Result<Vec<Trade>, Rejected> {
+        let parent_id = parent.get_order_id();
+        // Replacing the entry would lose the children of a live bracket
+        if self.parents.contains_key(&parent_id) {
+            return Err(Rejected::DuplicateOrderId(parent_id));
+        }
+        self.parents.insert(
+            parent_id,
+            Parent {
+                bracket,
+                side: parent.get_side(),
+                account_id: parent.get_account_id(),
+                children: Vec::new(),
+            },
+        );
+
+        match book.place_order(parent) {
+            Ok(trades) => Ok(self.on_trades(book, trades)),
+            Err(rejected) => {
+                self.parents.remove(&parent_id);
+                Err(rejected)
+            }
+        }
+    }
+
+    // Submits the children for parent fills among `trades`. Children can trade on entry and
+    // fill other parents in turn, everything that traded is returned, `trades` first.
+    pub fn on_trades(&mut self, book: &mut OrderBook, trades: Vec<Trade>) -> Vec<Trade> {
+        let mut all_trades = Vec::new();
+        let mut pending = trades;
+        while !pending.is_empty() {
+            let mut child_trades = Vec::new();
+            for trade in &pending {
+                for leg in [&trade.bid_trade, &trade.ask_trade] {
+                    self.cancel_sibling(book, leg.order_id);
+                    child_trades.extend(self.submit_children(book, leg.order_id, leg.quantity));
+                }
+            }
+            all_trades.append(&mut pending);
+            pending = child_trades;
+        }
+        all_trades
+    }
+
+    fn submit_children(
+        &mut self,
+        book: &mut OrderBook,
+        parent_id: OrderId,
+        quantity: Quantity,
+    ) -> Vec<Trade> {
+        let Some(parent) = self.parents.get(&parent_id) else {
+            return vec![];
+        };
+        let (bracket, account_id) = (parent.bracket, parent.account_id);
+        let side = match parent.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+
+        let children = ChildOrders {
+            take_profit: book.next_order_id(),
+            stop_loss: book.next_order_id(),
+        };
+        let child = |order_id, price| {
+            Order::new(order_id, price, quantity, OrderType::GoodToCancel, side)
+                .with_account(account_id)
+        };
+        let stop = StopOrder::new(
+            child(children.stop_loss, bracket.stop_limit),
+            bracket.stop_price,
+        )
+        .with_trigger(bracket.stop_trigger);
+
+        let mut trades = Vec::new();
+        let take_profit = match book.place_order(child(children.take_profit, bracket.take_profit)) {
+            Ok(take_profit_trades) => {
+                trades.extend(take_profit_trades);
+                true
+            }
+            Err(rejected) => {
+                log::warn!("Take-profit of order {} rejected: {}", parent_id, rejected);
+                false
+            }
+        };
+        let stop_loss = match book.place_stop_order(stop) {
+            Ok(stop_trades) => {
+                trades.extend(stop_trades);
+                true
+            }
+            Err(rejected) => {
+                log::warn!("Stop-loss of order {} rejected: {}", parent_id, rejected);
+                false
+            }
+        };
+        // The children entered the book before they were linked, their fills on entry are
+        // among `trades` and cancel the sibling on the next round of `on_trades`
+        if take_profit && stop_loss {
+            self.siblings
+                .insert(children.take_profit, children.stop_loss);
+            self.siblings
+                .insert(children.stop_loss, children.take_profit);
+        }
+
+        if let Some(parent) = self.parents.get_mut(&parent_id) {
+            parent.children.push(children);
+        }
+        trades
+    }
+
+    // Cancels the sibling of a child the caller cancelled on the book, nothing for other orders
+    pub fn on_cancelled(&mut self, book: &mut OrderBook, order_id: OrderId) {
+        self.cancel_sibling(book, order_id);
+    }
+
+    fn cancel_sibling(&mut self, book: &mut OrderBook, order_id: OrderId) {
+        let Some(sibling) = self.siblings.remove(&order_id) else {
+            return;
+        };
+        self.siblings.remove(&sibling);
+        cancel_child(book, sibling);
+    }
+
+    // Cancels what is left of the parent and every child it has open. Returns the children,
+    // None for an unknown parent.
+    pub fn cancel(&mut self, book: &mut OrderBook, parent_id: OrderId) -> Option<Vec<ChildOrders>> {
+        let parent = self.parents.remove(&parent_id)?;
+        book.submit_cancel(parent_id);
+        for children in &parent.children {
+            for order_id in [children.take_profit, children.stop_loss] {
+                self.siblings.remove(&order_id);
+                cancel_child(book, order_id);
+            }
+        }
+        Some(parent.children)
+    }
+
+    pub fn children(&self, parent_id: OrderId) -> Option<&[ChildOrders]> {
+        self.parents
+            .get(&parent_id)
+            .map(|parent| parent.children.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+// A stop-loss is still waiting for its trigger or already entered the book
+fn cancel_child(book: &mut OrderBook, order_id: OrderId) {
+    if book.cancel_stop_order(order_id).is_none() {
+        book.submit_cancel(order_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionState;
+
+    fn order(order_id: OrderId, side: Side, price: i32, quantity: u32) -> Order {
+        Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            OrderType::GoodToCancel,
+            side,
+        )
+    }
+
+    #[test]
+    fn test_bracket_children_follow_parent_fills() {
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        book.place_order(order(1, Side::Sell, 100, 4)).unwrap();
+
+        // Buys 4 of 10 on entry, the rest rests
+        let trades = brackets
+            .place_bracket(
+                &mut book,
+                order(10, Side::Buy, 100, 10),
+                Bracket::new(Price(110), Price(95)),
+            )
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        let first = brackets.children(10).unwrap()[0];
+        assert!(book.stop_orders().contains(first.stop_loss));
+        assert_eq!(
+            book.get_orderbook_level_infos().get_asks()[0].price,
+            Price(110)
+        );
+        assert_eq!(
+            book.get_orderbook_level_infos().get_asks()[0].quantity,
+            Quantity(4)
+        );
+
+        // Someone else fills 6 more of the resting parent
+        let trades = book.place_order(order(2, Side::Sell, 100, 6)).unwrap();
+        let trades = brackets.on_trades(&mut book, trades);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(brackets.children(10).unwrap().len(), 2);
+        assert_eq!(
+            book.get_orderbook_level_infos().get_asks()[0].quantity,
+            Quantity(10)
+        );
+        assert_eq!(book.stop_orders().len(), 2);
+
+        let children = brackets.cancel(&mut book, 10).unwrap();
+        assert_eq!(children.len(), 2);
+        assert!(book.get_orderbook_level_infos().get_asks().is_empty());
+        assert!(book.stop_orders().is_empty());
+        assert!(brackets.is_empty());
+        assert!(brackets.cancel(&mut book, 10).is_none());
+    }
+
+    #[test]
+    fn test_rejected_parent_is_not_tracked() {
+        let mut book = OrderBook::new();
+        book.set_session_state(SessionState::Halted);
+        let mut brackets = ContingentOrders::new();
+        assert!(brackets
+            .place_bracket(
+                &mut book,
+                order(1, Side::Buy, 100, 1),
+                Bracket::new(Price(110), Price(95))
+            )
+            .is_err());
+        assert!(brackets.is_empty());
+    }
+
+    // Buys 2 at 100, bracketed by a sell at 110 and a sell stop at 95
+    fn bracketed_fill(book: &mut OrderBook, brackets: &mut ContingentOrders) -> ChildOrders {
+        book.place_order(order(1, Side::Sell, 100, 2)).unwrap();
+        brackets
+            .place_bracket(
+                book,
+                order(10, Side::Buy, 100, 2),
+                Bracket::new(Price(110), Price(95)),
+            )
+            .unwrap();
+        brackets.children(10).unwrap()[0]
+    }
+
+    #[test]
+    fn test_take_profit_fill_cancels_stop_loss() {
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        let children = bracketed_fill(&mut book, &mut brackets);
+
+        let trades = book.place_order(order(2, Side::Buy, 110, 1)).unwrap();
+        brackets.on_trades(&mut book, trades);
+        assert!(book.stop_orders().is_empty());
+        // The rest of the take-profit stays, only the stop-loss is gone
+        assert_eq!(
+            book.get_orderbook_level_infos().get_asks()[0].quantity,
+            Quantity(1)
+        );
+
+        brackets.on_cancelled(&mut book, children.take_profit);
+        assert_eq!(book.get_orderbook_level_infos().get_asks().len(), 1);
+    }
+
+    #[test]
+    fn test_stop_loss_fill_cancels_take_profit() {
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        let children = bracketed_fill(&mut book, &mut brackets);
+
+        // A trade at 95 triggers the stop-loss into the bid resting there
+        book.place_order(order(3, Side::Buy, 95, 3)).unwrap();
+        let trades = book.place_order(order(4, Side::Sell, 95, 1)).unwrap();
+        let trades = brackets.on_trades(&mut book, trades);
+        assert!(trades
+            .iter()
+            .any(|trade| trade.ask_trade.order_id == children.stop_loss));
+        assert!(book.get_orderbook_level_infos().get_asks().is_empty());
+        assert!(book.stop_orders().is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_child_cancels_its_sibling() {
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        let children = bracketed_fill(&mut book, &mut brackets);
+        book.cancel_order(children.take_profit).unwrap();
+        brackets.on_cancelled(&mut book, children.take_profit);
+        assert!(book.stop_orders().is_empty());
+
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        let children = bracketed_fill(&mut book, &mut brackets);
+        book.cancel_stop_order(children.stop_loss).unwrap();
+        brackets.on_cancelled(&mut book, children.stop_loss);
+        assert!(book.get_orderbook_level_infos().get_asks().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_parent_keeps_the_live_bracket() {
+        let mut book = OrderBook::new();
+        let mut brackets = ContingentOrders::new();
+        let children = bracketed_fill(&mut book, &mut brackets);
+
+        assert_eq!(
+            brackets
+                .place_bracket(
+                    &mut book,
+                    order(10, Side::Buy, 90, 1),
+                    Bracket::new(Price(120), Price(80)),
+                )
+                .unwrap_err(),
+            Rejected::DuplicateOrderId(10)
+        );
+        assert_eq!(brackets.children(10), Some(&[children][..]));
+    }
+}
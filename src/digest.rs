@@ -0,0 +1,46 @@
+/// Canonical digests of book state.
+/// Golden-file tests and cross checks against other implementations (e.g. a Python replay of
+/// the same feed) need a digest that depends on nothing but the state itself. The books write
+/// their state as canonical text, one line per level or order, best prices first, orders in
+/// time priority, fields separated by '|' and all numbers as integers in internal units, and
+/// the digest is the hex SHA-256 of that text. Any implementation producing the same text
+/// produces the same digest.
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+// Appends one canonical line
+pub fn push_line(text: &mut String, fields: &[&dyn std::fmt::Display]) {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            text.push('|');
+        }
+        let _ = write!(text, "{}", field);
+    }
+    text.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_lines_and_digest() {
+        let mut text = String::new();
+        push_line(&mut text, &[&"bid", &100, &7]);
+        push_line(&mut text, &[&"last_trade", &"-"]);
+        assert_eq!(text, "bid|100|7\nlast_trade|-\n");
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+/// Market data engine assembled from an `EngineConfig`.
+/// `Engine::from_config` follows every configured symbol in an `OrderBookManager`, with the
+/// depth, book ticker stream, converter and level storage the file asks for, and attaches the
+/// recorder, book audit log and latency recorder when they are enabled. The engine does not own the
+/// connection: the caller connects to `binance().ws_url`, sends the subscription frames and
+/// hands every payload to `on_payload`. `close` drains the engine on shutdown.
+use crate::alerts::{Alert, AlertMonitor};
+use crate::book_audit::{self, BookAuditLog};
+use crate::clock::{self, SharedClock, Stamp};
+use crate::config::{AlertsConfig, BinanceConfig, ConfigError, EngineConfig};
+use crate::instruments::InstrumentRegistry;
+use crate::latency::{LatencyRecorder, LatencySample, DEFAULT_LATENCY_CAPACITY};
+use crate::manager::OrderBookManager;
+use crate::orderbook::DepthSnapshot;
+use crate::recording::Recorder;
+use crate::signals::{FlowSignal, FlowSignals};
+use crate::stats::{ReturnStatistics, DEFAULT_EWMA_LAMBDA, DEFAULT_WINDOWS};
+use crate::subscriptions::{StreamKind, SubscriptionFrame};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Engine {
+    config: EngineConfig,
+    manager: OrderBookManager,
+    depths: HashMap<Symbol, usize>,
+    frames: Vec<SubscriptionFrame>,
+    recorder: Option<Recorder<BufWriter<File>>>,
+    audit_log: Option<BookAuditLog>,
+    latency: Option<LatencyRecorder>,
+    // Per symbol, when `metrics.volatility` is on
+    returns: HashMap<Symbol, ReturnStatistics>,
+    // Per symbol, when `metrics.order_flow_windows_secs` is set
+    signals: HashMap<Symbol, FlowSignals>,
+    pending_signals: Vec<(Symbol, FlowSignal)>,
+    // Per symbol, when `metrics.alerts` is set
+    alerts: HashMap<Symbol, AlertMonitor>,
+    pending_alerts: Vec<(Symbol, Alert)>,
+    clock: SharedClock,
+    closed: bool,
+}
+
+impl Engine {
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Engine, ConfigError> {
+        Engine::new(EngineConfig::load(path)?)
+    }
+
+    pub fn new(config: EngineConfig) -> Result<Engine, ConfigError> {
+        let instruments = match &config.persistence.instruments {
+            Some(path) => InstrumentRegistry::load_toml(path)
+                .map_err(|error| ConfigError::Instruments(error.to_string()))?,
+            None => InstrumentRegistry::new(),
+        };
+        let recorder = match &config.persistence.recording {
+            Some(path) => Some(
+                Recorder::open(path)
+                    .map_err(|error| ConfigError::Io(format!("{}: {}", path.display(), error)))?,
+            ),
+            None => None,
+        };
+        let audit_log = match &config.persistence.audit_log {
+            Some(audit) => Some(
+                BookAuditLog::open(
+                    &audit.path,
+                    audit.max_bytes.unwrap_or(book_audit::DEFAULT_MAX_BYTES),
+                    audit.max_files.unwrap_or(book_audit::DEFAULT_MAX_FILES),
+                )
+                .map_err(|error| ConfigError::Io(format!("{}: {}", audit.path.display(), error)))?,
+            ),
+            None => None,
+        };
+        let latency = config.metrics.latency.then(|| {
+            LatencyRecorder::new(
+                config
+                    .metrics
+                    .latency_capacity
+                    .unwrap_or(DEFAULT_LATENCY_CAPACITY),
+            )
+        });
+
+        let windows = match &config.metrics.volatility_windows_secs {
+            Some(windows) => windows.iter().copied().map(Duration::from_secs).collect(),
+            None => DEFAULT_WINDOWS.to_vec(),
+        };
+        let lambda = config.metrics.ewma_lambda.unwrap_or(DEFAULT_EWMA_LAMBDA);
+        let mut returns = HashMap::new();
+        let flow_windows: Option<Vec<_>> = config
+            .metrics
+            .order_flow_windows_secs
+            .as_ref()
+            .map(|windows| windows.iter().copied().map(Duration::from_secs).collect());
+        let mut signals = HashMap::new();
+        let alert_rules = config.metrics.alerts.as_ref().map(AlertsConfig::rules);
+        let mut alerts = HashMap::new();
+
+        let mut manager = OrderBookManager::with_backend(config.backend);
+        let mut depths = HashMap::new();
+        let mut frames = Vec::new();
+        for entry in &config.symbols {
+            let symbol = Symbol::intern(&entry.symbol.to_ascii_uppercase());
+            let depth = config.depth_for(entry);
+            let mut kinds = vec![StreamKind::PartialDepth { levels: depth }];
+            if entry.book_ticker {
+                kinds.push(StreamKind::BookTicker);
+            }
+            frames.extend(manager.subscribe(symbol, &kinds));
+            if let Some(instrument) = instruments.get(symbol) {
+                manager.add_instrument(instrument);
+            }
+            if audit_log.is_some() {
+                if let Some(book) = manager.book_mut(symbol) {
+                    book.record_mutations();
+                }
+            }
+            depths.insert(symbol, depth.into());
+            if config.metrics.volatility {
+                returns.insert(symbol, ReturnStatistics::new(&windows, lambda));
+            }
+            if let Some(windows) = &flow_windows {
+                signals.insert(symbol, FlowSignals::new(windows));
+            }
+            if let Some(rules) = alert_rules {
+                alerts.insert(symbol, AlertMonitor::new(rules));
+            }
+        }
+
+        Ok(Engine {
+            config,
+            manager,
+            depths,
+            frames,
+            recorder,
+            audit_log,
+            latency,
+            returns,
+            signals,
+            pending_signals: Vec::new(),
+            alerts,
+            pending_alerts: Vec::new(),
+            clock: clock::system(),
+            closed: false,
+        })
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn binance(&self) -> BinanceConfig {
+        self.config.binance()
+    }
+
+    pub fn manager(&self) -> &OrderBookManager {
+        &self.manager
+    }
+
+    // Frames subscribing the configured streams, to send once connected
+    pub fn take_subscription_frames(&mut self) -> Vec<SubscriptionFrame> {
+        std::mem::take(&mut self.frames)
+    }
+
+    pub fn latency(&self) -> Option<&LatencyRecorder> {
+        self.latency.as_ref()
+    }
+
+    pub fn returns(&self, symbol: Symbol) -> Option<&ReturnStatistics> {
+        self.returns.get(&symbol)
+    }
+
+    // Order flow signals of the payloads applied since the last call, in order
+    pub fn drain_signals(&mut self) -> Vec<(Symbol, FlowSignal)> {
+        std::mem::take(&mut self.pending_signals)
+    }
+
+    pub fn alerts(&self, symbol: Symbol) -> Option<&AlertMonitor> {
+        self.alerts.get(&symbol)
+    }
+
+    // Alerts fired by the payloads applied since the last call, in order
+    pub fn drain_alerts(&mut self) -> Vec<(Symbol, Alert)> {
+        std::mem::take(&mut self.pending_alerts)
+    }
+
+    pub fn report_interval(&self) -> Option<Duration> {
+        self.config
+            .metrics
+            .report_interval_secs
+            .map(Duration::from_secs)
+    }
+
+    // Records and applies a payload, returns the configured depth of the book it changed.
+    // A failed write is logged and does not stop the feed.
+    pub fn on_payload(&mut self, received: Stamp, payload: &[u8]) -> Option<DepthSnapshot> {
+        if self.closed {
+            return None;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(error) = recorder.record(received, &String::from_utf8_lossy(payload)) {
+                log::error!("Cannot record payload: {}", error);
+            }
+        }
+
+        let symbol = self.manager.apply_payload(payload)?;
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            let book = self.manager.book_mut(symbol)?;
+            let converter = book.converter();
+            for mutation in book.drain_mutations() {
+                if let Err(error) = audit_log.log(received.wall, symbol, converter, &mutation) {
+                    log::error!("Cannot write the book audit log: {}", error);
+                    break;
+                }
+            }
+        }
+        let book = self.manager.book(symbol)?;
+        if let Some(latency) = self.latency.as_mut() {
+            latency.record(LatencySample {
+                event_time: book.last_event_time(),
+                received,
+                applied: self.clock.stamp(),
+            });
+        }
+        if let Some(returns) = self.returns.get_mut(&symbol) {
+            returns.on_book(received.wall, book);
+        }
+        if let Some(signals) = self.signals.get_mut(&symbol) {
+            if let Some(signal) = signals.on_book(received.wall, book) {
+                self.pending_signals.push((symbol, signal));
+            }
+        }
+        if let Some(alerts) = self.alerts.get_mut(&symbol) {
+            let fired = alerts.on_book(received.wall, book);
+            self.pending_alerts
+                .extend(fired.into_iter().map(|alert| (symbol, alert)));
+        }
+        Some(book.snapshot(self.depths.get(&symbol).copied().unwrap_or_default()))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.flush()?;
+        }
+        match self.recorder.as_mut() {
+            Some(recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Stops taking payloads and flushes the recording and the audit log. Returns the final snapshot of every
+    // book, in the order of the config file.
+    pub fn close(&mut self) -> std::io::Result<Vec<DepthSnapshot>> {
+        self.closed = true;
+        self.flush()?;
+        Ok(self
+            .config
+            .symbols
+            .iter()
+            .filter_map(|entry| {
+                let symbol = Symbol::lookup(&entry.symbol.to_ascii_uppercase())?;
+                let depth = self.depths.get(&symbol).copied()?;
+                Some(self.manager.book(symbol)?.snapshot(depth))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latency::LatencyStage;
+    use crate::recording;
+
+    #[test]
+    fn test_engine_from_config() {
+        let dir = std::env::temp_dir().join(format!("engine-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let recording = dir.join("session.jsonl");
+        let audit_log = dir.join("book-audit.log");
+        let path = dir.join("book.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                [[symbols]]
+                symbol = "ethusdc"
+                depth = 5
+
+                [[symbols]]
+                symbol = "BTCUSDT"
+                book_ticker = false
+
+                [persistence]
+                recording = {:?}
+                audit_log = {{ path = {:?} }}
+
+                [metrics]
+                latency = true
+                volatility = true
+                order_flow_windows_secs = [1, 60]
+
+                [metrics.alerts]
+                max_spread_bps = 50
+                "#,
+                recording.display().to_string(),
+                audit_log.display().to_string()
+            ),
+        )
+        .unwrap();
+
+        let mut engine = Engine::from_config(&path).unwrap();
+        let frames = engine.take_subscription_frames();
+        let streams: Vec<_> = frames.iter().flat_map(|frame| &frame.params).collect();
+        assert_eq!(
+            streams,
+            vec![
+                "ethusdc@depth5@100ms",
+                "ethusdc@bookTicker",
+                "btcusdt@depth20@100ms"
+            ]
+        );
+        assert!(engine.take_subscription_frames().is_empty());
+
+        let payload = r#"{"stream":"ethusdc@depth5@100ms","data":{"lastUpdateId":1,"bids":[["100.0","1.0"],["99.0","1.0"],["98.0","1.0"],["97.0","1.0"],["96.0","1.0"],["95.0","1.0"]],"asks":[]}}"#;
+        let snapshot = engine
+            .on_payload(Stamp::default(), payload.as_bytes())
+            .unwrap();
+        assert_eq!(snapshot.bids.len(), 5);
+        assert!(engine
+            .on_payload(Stamp::default(), b"{\"result\":null,\"id\":1}")
+            .is_none());
+        assert_eq!(engine.latency().unwrap().len(LatencyStage::FeedHandler), 1);
+        // One side only, no mid yet
+        let returns = engine.returns(Symbol::intern("ETHUSDC")).unwrap();
+        assert!(returns.last_price().is_none());
+        assert!(engine.drain_signals().is_empty());
+        assert!(engine.drain_alerts().is_empty());
+
+        let snapshots = engine.close().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].bids.len(), 5);
+        assert!(engine
+            .on_payload(Stamp::default(), payload.as_bytes())
+            .is_none());
+        assert_eq!(recording::read_recording(&recording).unwrap().len(), 2);
+        // One line per level of the first depth payload
+        let audit = std::fs::read_to_string(&audit_log).unwrap();
+        assert_eq!(audit.lines().count(), 6);
+        assert!(audit
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("symbol=ETHUSDC update_id=1 side=bid price=100 old_qty=0 new_qty=1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            Engine::from_config(dir.join("missing.toml")),
+            Err(ConfigError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_alerts() {
+        let config = EngineConfig::from_toml(
+            "[[symbols]]\nsymbol = \"ETHUSDC\"\n[metrics.alerts]\nmax_spread_bps = 50",
+        )
+        .unwrap();
+        let mut engine = Engine::new(config).unwrap();
+        let ticker = |update_id, ask| {
+            format!(
+                r#"{{"stream":"ethusdc@bookTicker","data":{{"u":{},"s":"ETHUSDC","b":"100.0","B":"1.0","a":"{}","A":"1.0"}}}}"#,
+                update_id, ask
+            )
+        };
+        // About 100 bps wide, one alert for as long as it stays wide
+        engine.on_payload(Stamp::default(), ticker(1, "101.0").as_bytes());
+        let alerts = engine.drain_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, Symbol::intern("ETHUSDC"));
+        engine.on_payload(Stamp::default(), ticker(2, "101.0").as_bytes());
+        assert!(engine.drain_alerts().is_empty());
+        let alerts = engine.alerts(Symbol::intern("ETHUSDC")).unwrap();
+        assert_eq!(alerts.counts().wide_spreads, 1);
+    }
+}
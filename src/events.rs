@@ -0,0 +1,57 @@
+/// Events emitted by the matching engine besides the trades returned from order entry.
+/// The engine buffers them until the caller drains them with `OrderBook::drain_events`.
+use crate::candles::Candle;
+use crate::orderbookv2::{OrderId, Price, Timestamp};
+use crate::session::{SessionState, SessionStatistics};
+use crate::stops::StopTrigger;
+use crate::surveillance::SurveillanceAlert;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EngineEvent {
+    SessionStateChanged {
+        from: SessionState,
+        to: SessionState,
+        timestamp: Timestamp,
+    },
+    CircuitBreakerTriggered {
+        reference_price: Price,
+        trade_price: Price,
+        timestamp: Timestamp,
+        resume_at: Timestamp,
+    },
+    // A Day order was cancelled by the end of the session
+    OrderExpired {
+        order_id: OrderId,
+        timestamp: Timestamp,
+    },
+    // `roll_session` ended a session, with the statistics it closed with
+    SessionRolled {
+        statistics: SessionStatistics,
+        timestamp: Timestamp,
+    },
+    // See `OrderBook::set_candle_builder`
+    CandleClosed(Candle),
+    // The stop order was entered into the book with its own order id
+    StopTriggered {
+        order_id: OrderId,
+        trigger: StopTrigger,
+        reference_price: Price,
+        timestamp: Timestamp,
+    },
+    // The order of a triggered stop failed the checks of `OrderBook::place_order`
+    StopRejected {
+        order_id: OrderId,
+        reason: String,
+        timestamp: Timestamp,
+    },
+    // An order entered with `OrderBook::add_order` or amended with `OrderBook::match_order`
+    // was not accepted, `place_order` and `replace_order` return the reason instead
+    OrderRejected {
+        order_id: OrderId,
+        reason: String,
+        timestamp: Timestamp,
+    },
+    // See `OrderBook::set_surveillance`
+    SurveillanceAlert(SurveillanceAlert),
+}
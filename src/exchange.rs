@@ -0,0 +1,400 @@
+/// Simulated exchange hosting one matching engine per instrument.
+/// Orders are routed to the book of their symbol, cancels and amendments by the order id.
+/// Order ids come from one allocator and client order ids from one mapping, so both are
+/// unique across the exchange. Risk checks run here before an order reaches its book, with
+/// the open orders of the account counted over all books. Accounts stay with the books, an
+/// account holds a base and a quote balance of one instrument. Trades and engine events of
+/// every book end up in a single stream tagged with their symbol.
+use crate::events::EngineEvent;
+use crate::ids::{ClientOrderIds, OrderIdAllocator};
+use crate::instruments::Instrument;
+use crate::orderbookv2::{
+    NewOrder, Order, OrderAck, OrderBook, OrderCommand, OrderId, Rejected, Timestamp, Trade,
+};
+use crate::risk::{RiskContext, RiskManager};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum ExchangeEvent {
+    Trade { symbol: Symbol, trade: Trade },
+    Engine { symbol: Symbol, event: EngineEvent },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExchangeError {
+    UnknownSymbol(Symbol),
+    UnknownOrder(OrderId),
+    Rejected { symbol: Symbol, reason: Rejected },
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::UnknownSymbol(symbol) => write!(f, "unknown symbol {}", symbol),
+            ExchangeError::UnknownOrder(order_id) => write!(f, "unknown order {}", order_id),
+            ExchangeError::Rejected { symbol, reason } => {
+                write!(f, "order on {} rejected: {}", symbol, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {}
+
+#[derive(Debug, Default)]
+pub struct Exchange {
+    books: HashMap<Symbol, OrderBook>,
+    // Listing order, books are visited in it so the event stream is deterministic
+    symbols: Vec<Symbol>,
+    order_symbols: HashMap<OrderId, Symbol>,
+    order_ids: OrderIdAllocator,
+    client_order_ids: ClientOrderIds,
+    risk: Option<RiskManager>,
+    events: Vec<ExchangeEvent>,
+}
+
+impl Exchange {
+    pub fn new() -> Exchange {
+        Exchange::default()
+    }
+
+    // Replaces the book already listed under the symbol
+    pub fn add_book(&mut self, symbol: impl Into<Symbol>, book: OrderBook) {
+        let symbol = symbol.into();
+        if self.books.insert(symbol, book).is_none() {
+            self.symbols.push(symbol);
+        }
+    }
+
+    // Prices and quantities of its orders are then in units of the instrument's tick and lot
+    pub fn add_instrument(&mut self, instrument: Instrument) {
+        let symbol = instrument.symbol;
+        let mut book = OrderBook::new();
+        book.set_instrument(instrument);
+        self.add_book(symbol, book);
+    }
+
+    pub fn book(&self, symbol: impl Into<Symbol>) -> Option<&OrderBook> {
+        self.books.get(&symbol.into())
+    }
+
+    pub fn book_mut(&mut self, symbol: impl Into<Symbol>) -> Option<&mut OrderBook> {
+        self.books.get_mut(&symbol.into())
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // Applies to the orders of every book
+    pub fn set_risk_manager(&mut self, risk: RiskManager) {
+        self.risk = Some(risk);
+    }
+
+    pub fn risk_manager_mut(&mut self) -> Option<&mut RiskManager> {
+        self.risk.as_mut()
+    }
+
+    pub fn next_order_id(&mut self) -> OrderId {
+        self.order_ids.allocate()
+    }
+
+    pub fn symbol_of(&self, order_id: OrderId) -> Option<Symbol> {
+        self.order_symbols.get(&order_id).copied()
+    }
+
+    pub fn order_id_for(&self, client_order_id: &str) -> Option<OrderId> {
+        self.client_order_ids.order_id(client_order_id)
+    }
+
+    // New orders go to `symbol`, amendments and cancels to the book holding the order whatever
+    // `symbol` says
+    pub fn apply(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        command: OrderCommand,
+    ) -> Result<Vec<Trade>, ExchangeError> {
+        let symbol = symbol.into();
+        match command {
+            OrderCommand::New(order) => self.place_order(symbol, order),
+            OrderCommand::Modify(order_modify) => {
+                let symbol = self.routed(order_modify.order_id)?;
+                self.with_book(symbol, |book| book.replace_order(order_modify))
+            }
+            OrderCommand::Cancel(order_id) => {
+                self.cancel_order(order_id)?;
+                Ok(vec![])
+            }
+        }
+    }
+
+    pub fn place_order(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        order: Order,
+    ) -> Result<Vec<Trade>, ExchangeError> {
+        let symbol = symbol.into();
+        let order_id = order.get_order_id();
+        if !self.books.contains_key(&symbol) {
+            return Err(ExchangeError::UnknownSymbol(symbol));
+        }
+        if self.order_symbols.contains_key(&order_id) {
+            return Err(ExchangeError::Rejected {
+                symbol,
+                reason: Rejected::DuplicateOrderId(order_id),
+            });
+        }
+        self.check_risk(symbol, &order)?;
+
+        self.order_ids.observe(order_id);
+        let trades = self.with_book(symbol, |book| book.place_order(order))?;
+        self.order_symbols.insert(order_id, symbol);
+        Ok(trades)
+    }
+
+    // Allocates the exchange order id, client order ids are unique across all books
+    pub fn add_client_order(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        request: NewOrder,
+    ) -> Result<OrderAck, ExchangeError> {
+        let symbol = symbol.into();
+        let rejected = |reason| ExchangeError::Rejected { symbol, reason };
+        if self.client_order_ids.contains(&request.client_order_id) {
+            return Err(rejected(Rejected::DuplicateClientOrderId(
+                request.client_order_id,
+            )));
+        }
+
+        let order = Order::builder()
+            .order_id(self.order_ids.allocate())
+            .price(request.price)
+            .quantity(request.quantity)
+            .order_type(request.order_type)
+            .side(request.side)
+            .account(request.account_id)
+            .build()
+            .map_err(|error| rejected(Rejected::InvalidOrder(error)))?;
+        let order_id = order.get_order_id();
+        let trades = self.place_order(symbol, order)?;
+        self.client_order_ids
+            .insert(&request.client_order_id, order_id);
+
+        Ok(OrderAck { order_id, trades })
+    }
+
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<(), ExchangeError> {
+        let symbol = self.routed(order_id)?;
+        self.with_book(symbol, |book| book.cancel_order(order_id).map(|()| vec![]))
+            .map(|_| ())
+    }
+
+    // Moves the clock of every book, see `OrderBook::advance_clock`
+    pub fn advance_clock(&mut self, to: Timestamp) -> Vec<(Symbol, Timestamp, Trade)> {
+        let mut trades = Vec::new();
+        for symbol in self.symbols.clone() {
+            let book = self.books.get_mut(&symbol).expect("listed symbol");
+            let book_trades = book.advance_clock(to);
+            for (timestamp, trade) in &book_trades {
+                self.events.push(ExchangeEvent::Trade {
+                    symbol,
+                    trade: trade.clone(),
+                });
+                trades.push((symbol, *timestamp, trade.clone()));
+            }
+            self.collect_events(symbol);
+        }
+        trades
+    }
+
+    pub fn drain_events(&mut self) -> Vec<ExchangeEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn routed(&self, order_id: OrderId) -> Result<Symbol, ExchangeError> {
+        self.order_symbols
+            .get(&order_id)
+            .copied()
+            .ok_or(ExchangeError::UnknownOrder(order_id))
+    }
+
+    fn check_risk(&self, symbol: Symbol, order: &Order) -> Result<(), ExchangeError> {
+        let Some(risk) = self.risk.as_ref() else {
+            return Ok(());
+        };
+        let account_id = order.get_account_id();
+        let ctx = RiskContext {
+            open_orders: self
+                .books
+                .values()
+                .map(|book| book.open_orders_for(account_id).count())
+                .sum(),
+            last_trade_price: self.books[&symbol].last_trade_price(),
+            // Positions are per instrument, only the book of the order counts
+            position: self.books[&symbol].position(account_id),
+            open_quantity: self.books[&symbol]
+                .open_orders_for(account_id)
+                .filter(|open| open.get_side() == order.get_side())
+                .map(|open| u64::from(open.get_remaining_quantity()))
+                .sum(),
+        };
+        risk.check(order, &ctx)
+            .map_err(|violation| ExchangeError::Rejected {
+                symbol,
+                reason: Rejected::Risk(violation),
+            })
+    }
+
+    // Runs the call on the book and moves its trades and events to the exchange stream
+    fn with_book(
+        &mut self,
+        symbol: Symbol,
+        call: impl FnOnce(&mut OrderBook) -> Result<Vec<Trade>, Rejected>,
+    ) -> Result<Vec<Trade>, ExchangeError> {
+        let book = self
+            .books
+            .get_mut(&symbol)
+            .ok_or(ExchangeError::UnknownSymbol(symbol))?;
+        let result = call(book);
+        if let Ok(trades) = &result {
+            self.events
+                .extend(trades.iter().map(|trade| ExchangeEvent::Trade {
+                    symbol,
+                    trade: trade.clone(),
+                }));
+        }
+        self.collect_events(symbol);
+        result.map_err(|reason| ExchangeError::Rejected { symbol, reason })
+    }
+
+    fn collect_events(&mut self, symbol: Symbol) {
+        if let Some(book) = self.books.get_mut(&symbol) {
+            self.events.extend(
+                book.drain_events()
+                    .into_iter()
+                    .map(|event| ExchangeEvent::Engine { symbol, event }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{OrderModify, OrderType, Price, Quantity, Side};
+    use crate::risk::RiskLimits;
+
+    fn order(order_id: OrderId, side: Side, price: i32, quantity: u32) -> Order {
+        Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            OrderType::GoodToCancel,
+            side,
+        )
+        .with_account(7)
+    }
+
+    #[test]
+    fn test_routes_by_symbol_and_order_id() {
+        let mut exchange = Exchange::new();
+        exchange.add_book("BTCUSDT", OrderBook::new());
+        exchange.add_book("ETHUSDT", OrderBook::new());
+
+        exchange
+            .place_order("BTCUSDT", order(1, Side::Sell, 100, 5))
+            .unwrap();
+        exchange
+            .place_order("ETHUSDT", order(2, Side::Sell, 100, 5))
+            .unwrap();
+        // Ids are unique across the books
+        assert_eq!(
+            exchange
+                .place_order("ETHUSDT", order(1, Side::Buy, 90, 1))
+                .unwrap_err(),
+            ExchangeError::Rejected {
+                symbol: Symbol::intern("ETHUSDT"),
+                reason: Rejected::DuplicateOrderId(1)
+            }
+        );
+        assert_eq!(
+            exchange
+                .place_order("SOLUSDT", order(3, Side::Buy, 90, 1))
+                .unwrap_err(),
+            ExchangeError::UnknownSymbol(Symbol::intern("SOLUSDT"))
+        );
+
+        let ack = exchange
+            .add_client_order(
+                "ETHUSDT",
+                NewOrder {
+                    client_order_id: "a".to_string(),
+                    account_id: 8,
+                    price: Price(100),
+                    quantity: Quantity(2),
+                    order_type: OrderType::GoodToCancel,
+                    side: Side::Buy,
+                },
+            )
+            .unwrap();
+        assert!(ack.order_id > 2);
+        assert_eq!(ack.trades.len(), 1);
+        assert_eq!(exchange.order_id_for("a"), Some(ack.order_id));
+
+        // The amendment lands on the ETH book whatever symbol comes with it
+        let modify = OrderModify::new(2, Side::Sell, Price(101), Quantity(1));
+        exchange
+            .apply("BTCUSDT", OrderCommand::Modify(modify))
+            .unwrap();
+        let eth = exchange
+            .book("ETHUSDT")
+            .unwrap()
+            .get_orderbook_level_infos();
+        assert_eq!(eth.get_asks()[0].price, Price(101));
+        exchange.apply("", OrderCommand::Cancel(1)).unwrap();
+        assert!(exchange
+            .book("BTCUSDT")
+            .unwrap()
+            .get_orderbook_level_infos()
+            .get_asks()
+            .is_empty());
+        assert_eq!(
+            exchange.cancel_order(42),
+            Err(ExchangeError::UnknownOrder(42))
+        );
+
+        let trades: Vec<_> = exchange
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                ExchangeEvent::Trade { symbol, .. } => Some(symbol),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trades, vec![Symbol::intern("ETHUSDT")]);
+    }
+
+    #[test]
+    fn test_risk_counts_open_orders_over_all_books() {
+        let mut exchange = Exchange::new();
+        exchange.add_book("BTCUSDT", OrderBook::new());
+        exchange.add_book("ETHUSDT", OrderBook::new());
+        exchange.set_risk_manager(RiskManager::new(RiskLimits {
+            max_open_orders: Some(1),
+            ..RiskLimits::default()
+        }));
+
+        exchange
+            .place_order("BTCUSDT", order(1, Side::Buy, 90, 1))
+            .unwrap();
+        assert!(matches!(
+            exchange.place_order("ETHUSDT", order(2, Side::Buy, 90, 1)),
+            Err(ExchangeError::Rejected {
+                reason: Rejected::Risk(_),
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,288 @@
+/// Arrow and Parquet export of recorded sessions, enabled with the `export` feature.
+/// Each record type maps to one flat table so the files load directly into Polars or pandas.
+///
+/// Depth snapshots, one row per level:
+///   symbol: utf8, last_update_id: uint64, side: utf8 ("bid" | "ask"),
+///   level: uint32 (0 is the best level), price: float64, quantity: float64
+/// Level deltas from the matching engine:
+///   sequence: uint64, side: utf8, price: int32, quantity: uint32 (0 removes the level)
+/// Matching engine trades:
+///   timestamp: uint64 (ns), price: int32, quantity: uint32, aggressor: utf8 ("buy" | "sell"),
+///   bid_order_id: uint64, ask_order_id: uint64, bid_account_id: uint64,
+///   ask_account_id: uint64, bid_fee: float64, ask_fee: float64
+/// Depth heatmap cells, in the units of the sampled book:
+///   timestamp: uint64 (ns), side: utf8, price: int64 (bucket start), quantity: uint64
+use crate::heatmap::HeatmapRecorder;
+use crate::market_data::LevelDelta;
+use crate::orderbook::DepthSnapshot;
+use crate::orderbookv2::{Liquidity, Side, Timestamp, Trade};
+use arrow::array::{
+    ArrayRef, Float64Array, Int32Array, Int64Array, StringArray, UInt32Array, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "bid",
+        Side::Sell => "ask",
+    }
+}
+
+pub fn depth_snapshot_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("last_update_id", DataType::UInt64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("level", DataType::UInt32, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("quantity", DataType::Float64, false),
+    ])
+}
+
+pub fn level_delta_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("sequence", DataType::UInt64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("price", DataType::Int32, false),
+        Field::new("quantity", DataType::UInt32, false),
+    ])
+}
+
+pub fn trade_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("price", DataType::Int32, false),
+        Field::new("quantity", DataType::UInt32, false),
+        Field::new("aggressor", DataType::Utf8, false),
+        Field::new("bid_order_id", DataType::UInt64, false),
+        Field::new("ask_order_id", DataType::UInt64, false),
+        Field::new("bid_account_id", DataType::UInt64, false),
+        Field::new("ask_account_id", DataType::UInt64, false),
+        Field::new("bid_fee", DataType::Float64, false),
+        Field::new("ask_fee", DataType::Float64, false),
+    ])
+}
+
+pub fn heatmap_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("price", DataType::Int64, false),
+        Field::new("quantity", DataType::UInt64, false),
+    ])
+}
+
+// Prices and quantities are converted back from the book internal units
+pub fn depth_snapshots_to_batch(snapshots: &[DepthSnapshot]) -> Result<RecordBatch, ArrowError> {
+    let mut symbols = Vec::new();
+    let mut update_ids = Vec::new();
+    let mut sides = Vec::new();
+    let mut levels = Vec::new();
+    let mut prices = Vec::new();
+    let mut quantities = Vec::new();
+
+    for snapshot in snapshots {
+        for (side, book_side) in [(Side::Buy, &snapshot.bids), (Side::Sell, &snapshot.asks)] {
+            for (level, (price, quantity)) in book_side.iter().enumerate() {
+                symbols.push(snapshot.symbol.as_str());
+                update_ids.push(snapshot.last_update_id);
+                sides.push(side_name(side));
+                levels.push(level as u32);
+                prices.push(snapshot.converter.to_f64(*price));
+                quantities.push(snapshot.converter.to_f64(*quantity));
+            }
+        }
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(symbols)),
+        Arc::new(UInt64Array::from(update_ids)),
+        Arc::new(StringArray::from(sides)),
+        Arc::new(UInt32Array::from(levels)),
+        Arc::new(Float64Array::from(prices)),
+        Arc::new(Float64Array::from(quantities)),
+    ];
+    RecordBatch::try_new(Arc::new(depth_snapshot_schema()), columns)
+}
+
+pub fn level_deltas_to_batch(deltas: &[LevelDelta]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            deltas.iter().map(|d| d.sequence),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            deltas.iter().map(|d| side_name(d.side)),
+        )),
+        Arc::new(Int32Array::from_iter_values(
+            deltas.iter().map(|d| d.price.0),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            deltas.iter().map(|d| d.quantity.0),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(level_delta_schema()), columns)
+}
+
+pub fn trades_to_batch(trades: &[(Timestamp, Trade)]) -> Result<RecordBatch, ArrowError> {
+    let bids = || trades.iter().map(|(_, trade)| &trade.bid_trade);
+    let asks = || trades.iter().map(|(_, trade)| &trade.ask_trade);
+    let aggressor = trades
+        .iter()
+        .map(|(_, trade)| match trade.bid_trade.liquidity {
+            Liquidity::Taker => "buy",
+            Liquidity::Maker => "sell",
+        });
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(trades.iter().map(|t| t.0))),
+        Arc::new(Int32Array::from_iter_values(bids().map(|leg| leg.price.0))),
+        Arc::new(UInt32Array::from_iter_values(
+            bids().map(|leg| leg.quantity.0),
+        )),
+        Arc::new(StringArray::from_iter_values(aggressor)),
+        Arc::new(UInt64Array::from_iter_values(
+            bids().map(|leg| leg.order_id),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            asks().map(|leg| leg.order_id),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            bids().map(|leg| leg.account_id),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            asks().map(|leg| leg.account_id),
+        )),
+        Arc::new(Float64Array::from_iter_values(bids().map(|leg| leg.fee))),
+        Arc::new(Float64Array::from_iter_values(asks().map(|leg| leg.fee))),
+    ];
+    RecordBatch::try_new(Arc::new(trade_schema()), columns)
+}
+
+pub fn heatmap_to_batch(heatmap: &HeatmapRecorder) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(heatmap.timestamps().to_vec())),
+        Arc::new(StringArray::from_iter_values(
+            heatmap.sides().iter().map(|side| side_name(*side)),
+        )),
+        Arc::new(Int64Array::from(heatmap.prices().to_vec())),
+        Arc::new(UInt64Array::from(heatmap.quantities().to_vec())),
+    ];
+    RecordBatch::try_new(Arc::new(heatmap_schema()), columns)
+}
+
+// Uncompressed single row group file
+pub fn write_parquet(path: impl AsRef<Path>, batch: &RecordBatch) -> parquet::errors::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Price, Quantity};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_depth_snapshot_rows() {
+        let snapshot = DepthSnapshot {
+            symbol: "BNBUSDT".into(),
+            last_update_id: 7,
+            bids: vec![
+                (orderbook::Price(250_000), orderbook::Quantity(10_000)),
+                (orderbook::Price(249_000), orderbook::Quantity(20_000)),
+            ],
+            asks: vec![(orderbook::Price(251_000), orderbook::Quantity(5_000))],
+            converter: Default::default(),
+        };
+        let batch = depth_snapshots_to_batch(&[snapshot]).unwrap();
+
+        assert_eq!(batch.num_rows(), 3);
+        let prices = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(prices.values(), &[25.0, 24.9, 25.1]);
+        let levels = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(levels.values(), &[0, 1, 0]);
+    }
+
+    #[test]
+    fn test_heatmap_columns() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(99),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(101),
+            Quantity(3),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        let mut heatmap = HeatmapRecorder::new(std::time::Duration::from_secs(1), 5, 1);
+        heatmap.sample_engine(&orderbook);
+
+        let batch = heatmap_to_batch(&heatmap).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let prices = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(prices.values(), &[99, 101]);
+    }
+
+    #[test]
+    fn test_trades_parquet_round_trip() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        let trades: Vec<(Timestamp, Trade)> = orderbook
+            .add_order(Order::new(
+                2,
+                Price(101),
+                Quantity(3),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            ))
+            .into_iter()
+            .map(|trade| (42, trade))
+            .collect();
+        let batch = trades_to_batch(&trades).unwrap();
+
+        let path = std::env::temp_dir().join(format!("trades-{}.parquet", std::process::id()));
+        write_parquet(&path, &batch).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches, vec![batch]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+/// Order book features for machine learning models.
+/// `FeatureSampler` takes `OrderBook::feature_vector` every interval and keeps the last
+/// `window` samples, `matrix` hands them out as a (time x features) matrix in row-major order,
+/// oldest row first, the input shape of sequence models over the book.
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::Timestamp;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Row-major (time x features) matrix
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureMatrix {
+    pub rows: usize,
+    pub columns: usize,
+    // Sampling time of every row
+    pub timestamps: Vec<Timestamp>,
+    pub data: Vec<f64>,
+}
+
+impl FeatureMatrix {
+    pub fn row(&self, index: usize) -> &[f64] {
+        &self.data[index * self.columns..(index + 1) * self.columns]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureSampler {
+    interval: Timestamp,
+    levels: usize,
+    window: usize,
+    next_sample: Option<Timestamp>,
+    samples: VecDeque<(Timestamp, Vec<f64>)>,
+}
+
+impl FeatureSampler {
+    // Features of the top `levels` every `interval`, the last `window` samples are kept
+    pub fn new(interval: Duration, levels: usize, window: usize) -> FeatureSampler {
+        assert!(window > 0, "window must hold a sample");
+        FeatureSampler {
+            interval: interval.as_nanos() as Timestamp,
+            levels,
+            window,
+            next_sample: None,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    // Samples the book unless the last sample is less than an interval old, returns whether
+    // it did
+    pub fn sample(&mut self, timestamp: Timestamp, book: &OrderBook) -> bool {
+        if self.next_sample.is_some_and(|next| timestamp < next) {
+            return false;
+        }
+        self.next_sample = Some(timestamp + self.interval);
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back((timestamp, book.feature_vector(self.levels)));
+        true
+    }
+
+    pub fn columns(&self) -> usize {
+        self.levels * 4
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Whether the window is full
+    pub fn is_ready(&self) -> bool {
+        self.samples.len() == self.window
+    }
+
+    // None until the window is full
+    pub fn matrix(&self) -> Option<FeatureMatrix> {
+        if !self.is_ready() {
+            return None;
+        }
+        Some(FeatureMatrix {
+            rows: self.window,
+            columns: self.columns(),
+            timestamps: self
+                .samples
+                .iter()
+                .map(|(timestamp, _)| *timestamp)
+                .collect(),
+            data: self
+                .samples
+                .iter()
+                .flat_map(|(_, features)| features.iter().copied())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    fn book(bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> OrderBook {
+        let mut orderbook = OrderBook::new("ETHUSDC");
+        orderbook.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids,
+            asks,
+        });
+        orderbook
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_feature_vector() {
+        let orderbook = book(vec![(99.0, 1.0), (98.0, 3.0)], vec![(101.0, 2.0)]);
+        let features = orderbook.feature_vector(3);
+        assert_eq!(features.len(), 12);
+        // Mid 100, average size 2
+        assert_close(&features[..4], &[-0.01, 0.5, 0.01, 1.0]);
+        assert_close(&features[4..8], &[-0.02, 1.5, 0.0, 0.0]);
+        assert_eq!(&features[8..], &[0.0; 4]);
+
+        let one_sided = book(vec![(99.0, 1.0)], vec![]);
+        assert_eq!(one_sided.feature_vector(2), vec![0.0; 8]);
+    }
+
+    #[test]
+    fn test_sampler_window() {
+        let mut sampler = FeatureSampler::new(Duration::from_nanos(10), 1, 2);
+        let orderbook = book(vec![(99.0, 1.0)], vec![(101.0, 1.0)]);
+        assert!(sampler.sample(0, &orderbook));
+        assert!(!sampler.sample(5, &orderbook));
+        assert!(sampler.matrix().is_none());
+
+        let moved = book(vec![(100.0, 1.0)], vec![(102.0, 3.0)]);
+        assert!(sampler.sample(10, &moved));
+        assert!(sampler.sample(25, &moved));
+        let matrix = sampler.matrix().unwrap();
+        assert_eq!((matrix.rows, matrix.columns), (2, 4));
+        assert_eq!(matrix.timestamps, vec![10, 25]);
+        assert_eq!(matrix.row(1), moved.feature_vector(1).as_slice());
+        assert_eq!(matrix.data.len(), 8);
+    }
+}
@@ -0,0 +1,120 @@
+/// Maker/taker fee schedule for the matching engine.
+/// Fees are charged in quote currency on every trade leg, negative values are rebates.
+use crate::orderbookv2::{Liquidity, Price, Quantity};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fee {
+    // Basis points of the traded notional (price * quantity)
+    Bps(f64),
+    // Flat amount per trade leg
+    Fixed(f64),
+}
+
+impl Fee {
+    pub fn amount(&self, price: Price, quantity: Quantity) -> f64 {
+        self.on_notional((price * quantity).to_f64())
+    }
+
+    // Fee of a trade leg worth `notional` in quote currency
+    pub fn on_notional(&self, notional: f64) -> f64 {
+        match *self {
+            Fee::Bps(bps) => notional * bps / 10_000.0,
+            Fee::Fixed(amount) => amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeRates {
+    pub maker: Fee,
+    pub taker: Fee,
+}
+
+impl Default for FeeRates {
+    fn default() -> Self {
+        FeeRates {
+            maker: Fee::Fixed(0.0),
+            taker: Fee::Fixed(0.0),
+        }
+    }
+}
+
+impl FeeRates {
+    pub fn new(maker: Fee, taker: Fee) -> FeeRates {
+        FeeRates { maker, taker }
+    }
+
+    pub fn amount(&self, liquidity: Liquidity, price: Price, quantity: Quantity) -> f64 {
+        match liquidity {
+            Liquidity::Maker => self.maker.amount(price, quantity),
+            Liquidity::Taker => self.taker.amount(price, quantity),
+        }
+    }
+}
+
+// Per symbol rates with a fallback for symbols without explicit configuration
+#[derive(Debug, Clone, Default)]
+pub struct FeeSchedule {
+    default: FeeRates,
+    per_symbol: HashMap<String, FeeRates>,
+}
+
+impl FeeSchedule {
+    pub fn new(default: FeeRates) -> FeeSchedule {
+        FeeSchedule {
+            default,
+            per_symbol: HashMap::new(),
+        }
+    }
+
+    pub fn set_symbol_rates(&mut self, symbol: &str, rates: FeeRates) {
+        self.per_symbol.insert(symbol.to_string(), rates);
+    }
+
+    pub fn rates_for(&self, symbol: &str) -> FeeRates {
+        self.per_symbol.get(symbol).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bps_and_fixed_fees() {
+        assert_eq!(Fee::Bps(10.0).amount(Price(1_000), Quantity(50)), 50.0);
+        assert_eq!(Fee::Bps(-2.0).amount(Price(1_000), Quantity(50)), -10.0);
+        assert_eq!(Fee::Fixed(1.5).amount(Price(1_000), Quantity(50)), 1.5);
+    }
+
+    #[test]
+    fn test_rates_by_liquidity() {
+        let rates = FeeRates::new(Fee::Bps(1.0), Fee::Bps(5.0));
+        assert_eq!(
+            rates.amount(Liquidity::Maker, Price(100), Quantity(100)),
+            1.0
+        );
+        assert_eq!(
+            rates.amount(Liquidity::Taker, Price(100), Quantity(100)),
+            5.0
+        );
+        assert_eq!(
+            FeeRates::default().amount(Liquidity::Taker, Price(100), Quantity(100)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_schedule_falls_back_to_default() {
+        let mut schedule = FeeSchedule::new(FeeRates::new(Fee::Bps(2.0), Fee::Bps(4.0)));
+        let btc = FeeRates::new(Fee::Bps(0.0), Fee::Fixed(1.0));
+        schedule.set_symbol_rates("BTCUSDT", btc);
+
+        assert_eq!(schedule.rates_for("BTCUSDT"), btc);
+        assert_eq!(
+            schedule.rates_for("ETHUSDT"),
+            FeeRates::new(Fee::Bps(2.0), Fee::Bps(4.0))
+        );
+    }
+}
@@ -0,0 +1,522 @@
+/// Backtesting fill simulator.
+/// Replays recorded Binance depth and trade streams and estimates when hypothetical orders
+/// would have been filled. Aggressive orders take the visible liquidity of the replayed book,
+/// passive orders join the back of their price level and get filled once the traded volume
+/// at that price has consumed the quantity queued ahead of them.
+/// Liquidity taken by simulated orders is remembered per level until the next depth update,
+/// so takers arriving between two updates share the visible quantity instead of each filling
+/// against all of it.
+use crate::binance_payloads::{DepthUpdate, TradeUpdate};
+use crate::orderbook::{OrderBook, Price, Quantity};
+use crate::orderbookv2::{Liquidity, OrderId, Side};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimOrderKind {
+    Limit(Price),
+    Market,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedExecution {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub liquidity: Liquidity,
+    // Update id of the depth update or trade id of the trade that caused the fill
+    pub source_id: u64,
+}
+
+#[derive(Debug, Clone)]
+struct RestingOrder {
+    order_id: OrderId,
+    side: Side,
+    price: Price,
+    remaining: Quantity,
+    queue_ahead: Quantity,
+}
+
+#[derive(Debug)]
+pub struct FillSimulator {
+    book: OrderBook,
+    resting: Vec<RestingOrder>,
+    executions: Vec<SimulatedExecution>,
+    // Quantity simulated orders took from each level since the last depth update
+    consumed_bids: HashMap<Price, Quantity>,
+    consumed_asks: HashMap<Price, Quantity>,
+    next_order_id: OrderId,
+    last_source_id: u64,
+}
+
+impl FillSimulator {
+    pub fn new(symbol: impl Into<Symbol>) -> FillSimulator {
+        FillSimulator {
+            book: OrderBook::new(symbol),
+            resting: Vec::new(),
+            executions: Vec::new(),
+            consumed_bids: HashMap::new(),
+            consumed_asks: HashMap::new(),
+            next_order_id: 1,
+            last_source_id: 0,
+        }
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    // Submits a hypothetical order against the current replayed state,
+    // market orders that cannot be filled from the visible depth lose their remainder
+    pub fn submit(&mut self, side: Side, kind: SimOrderKind, quantity: Quantity) -> OrderId {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        self.submit_with_id(order_id, side, kind, quantity);
+        order_id
+    }
+
+    // Same as `submit` with an id chosen by the caller, it is not checked against the ids
+    // `submit` allocates
+    pub fn submit_with_id(
+        &mut self,
+        order_id: OrderId,
+        side: Side,
+        kind: SimOrderKind,
+        quantity: Quantity,
+    ) {
+        let limit = match kind {
+            SimOrderKind::Limit(price) => Some(price),
+            SimOrderKind::Market => None,
+        };
+        let remaining = self.take_liquidity(order_id, side, limit, quantity);
+
+        if let (Some(price), true) = (limit, !remaining.is_zero()) {
+            let queue_ahead = self.level_quantity(side, price);
+            self.resting.push(RestingOrder {
+                order_id,
+                side,
+                price,
+                remaining,
+                queue_ahead,
+            });
+        }
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) -> bool {
+        let before = self.resting.len();
+        self.resting.retain(|order| order.order_id != order_id);
+        before != self.resting.len()
+    }
+
+    pub fn on_depth(&mut self, update: &DepthUpdate) {
+        self.book.update_depth(update);
+        self.last_source_id = update.last_update_id;
+        // The new depth already reflects whatever the market did since the previous one
+        self.consumed_bids.clear();
+        self.consumed_asks.clear();
+
+        let bids: Vec<(Price, Quantity)> = self.book.bids().collect();
+        let asks: Vec<(Price, Quantity)> = self.book.asks().collect();
+
+        let mut crossed = Vec::new();
+        for order in self.resting.iter_mut() {
+            let levels = match order.side {
+                Side::Buy => &bids,
+                Side::Sell => &asks,
+            };
+            // Cancellations are assumed to happen behind us unless the level got smaller than our queue
+            let level_quantity = levels
+                .iter()
+                .find(|(price, _)| *price == order.price)
+                .map_or(Quantity::ZERO, |(_, qty)| *qty);
+            order.queue_ahead = order.queue_ahead.min(level_quantity);
+
+            // The opposite side moved through our price, so the market traded through us
+            let crossing = match order.side {
+                Side::Buy => asks.first().map_or(false, |(ask, _)| *ask <= order.price),
+                Side::Sell => bids.first().map_or(false, |(bid, _)| *bid >= order.price),
+            };
+            if crossing {
+                crossed.push((order.order_id, order.side, order.price, order.remaining));
+            }
+        }
+
+        // Each crossed order only gets the quantity at or through its price that earlier
+        // orders have not taken already
+        for (order_id, side, price, remaining) in crossed {
+            let filled: Quantity = self
+                .consume(side, Some(price), remaining)
+                .into_iter()
+                .map(|(_, quantity)| quantity)
+                .sum();
+            if !filled.is_zero() {
+                self.fill_resting(order_id, filled, self.last_source_id);
+            }
+        }
+    }
+
+    // Trades whose price or quantity cannot be converted to book units are ignored
+    pub fn on_trade(&mut self, trade: &TradeUpdate) {
+        let converter = self.book.converter();
+        let (price, mut volume) = match (
+            converter.to_units(trade.price),
+            converter.to_units(trade.quantity),
+        ) {
+            (Ok(price), Ok(volume)) => (Price(price), Quantity(volume)),
+            (Err(error), _) | (_, Err(error)) => {
+                log::warn!("Ignoring trade {}: {}", trade.trade_id, error);
+                return;
+            }
+        };
+        // A buyer maker trade was a sell hitting the bids
+        let resting_side = if trade.is_buyer_maker {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+
+        let mut fills = Vec::new();
+        for order in self.resting.iter_mut() {
+            if order.side != resting_side {
+                continue;
+            }
+            let traded_through = match order.side {
+                Side::Buy => price < order.price,
+                Side::Sell => price > order.price,
+            };
+
+            if traded_through {
+                fills.push((order.order_id, order.remaining));
+            } else if price == order.price && !volume.is_zero() {
+                let consumed = volume.min(order.queue_ahead);
+                order.queue_ahead -= consumed;
+                volume -= consumed;
+
+                let filled = volume.min(order.remaining);
+                if !filled.is_zero() {
+                    volume -= filled;
+                    fills.push((order.order_id, filled));
+                }
+            }
+        }
+
+        for (order_id, quantity) in fills {
+            self.fill_resting(order_id, quantity, trade.trade_id);
+        }
+    }
+
+    pub fn executions(&self) -> &[SimulatedExecution] {
+        &self.executions
+    }
+
+    pub fn drain_executions(&mut self) -> Vec<SimulatedExecution> {
+        std::mem::take(&mut self.executions)
+    }
+
+    pub fn open_quantity(&self, order_id: OrderId) -> Option<Quantity> {
+        self.resting
+            .iter()
+            .find(|order| order.order_id == order_id)
+            .map(|order| order.remaining)
+    }
+
+    pub fn queue_ahead(&self, order_id: OrderId) -> Option<Quantity> {
+        self.resting
+            .iter()
+            .find(|order| order.order_id == order_id)
+            .map(|order| order.queue_ahead)
+    }
+
+    // Walks the opposite side of the replayed book, returns the quantity left unfilled
+    fn take_liquidity(
+        &mut self,
+        order_id: OrderId,
+        side: Side,
+        limit: Option<Price>,
+        quantity: Quantity,
+    ) -> Quantity {
+        let mut remaining = quantity;
+        for (price, filled) in self.consume(side, limit, quantity) {
+            remaining -= filled;
+            self.executions.push(SimulatedExecution {
+                order_id,
+                side,
+                price,
+                quantity: filled,
+                liquidity: Liquidity::Taker,
+                source_id: self.last_source_id,
+            });
+        }
+        remaining
+    }
+
+    // Takes up to `quantity` from the levels opposite `side` that are acceptable for `limit`,
+    // best first, skipping what was consumed since the last depth update. Returns the
+    // quantity taken per level and records it as consumed.
+    fn consume(
+        &mut self,
+        side: Side,
+        limit: Option<Price>,
+        quantity: Quantity,
+    ) -> Vec<(Price, Quantity)> {
+        let (levels, consumed): (Vec<(Price, Quantity)>, _) = match side {
+            Side::Buy => (self.book.asks().collect(), &mut self.consumed_asks),
+            Side::Sell => (self.book.bids().collect(), &mut self.consumed_bids),
+        };
+
+        let mut remaining = quantity;
+        let mut taken = Vec::new();
+        for (price, visible) in levels {
+            let acceptable = match (side, limit) {
+                (_, None) => true,
+                (Side::Buy, Some(limit)) => price <= limit,
+                (Side::Sell, Some(limit)) => price >= limit,
+            };
+            if !acceptable || remaining.is_zero() {
+                break;
+            }
+
+            let used = consumed.entry(price).or_insert(Quantity::ZERO);
+            let filled = remaining.min(visible.saturating_sub(*used));
+            if filled.is_zero() {
+                continue;
+            }
+            *used += filled;
+            remaining -= filled;
+            taken.push((price, filled));
+        }
+        taken
+    }
+
+    fn fill_resting(&mut self, order_id: OrderId, quantity: Quantity, source_id: u64) {
+        let Some(index) = self
+            .resting
+            .iter()
+            .position(|order| order.order_id == order_id)
+        else {
+            return;
+        };
+
+        let order = &mut self.resting[index];
+        let quantity = quantity.min(order.remaining);
+        order.remaining -= quantity;
+        self.executions.push(SimulatedExecution {
+            order_id,
+            side: order.side,
+            price: order.price,
+            quantity,
+            liquidity: Liquidity::Maker,
+            source_id,
+        });
+
+        if order.remaining.is_zero() {
+            self.resting.remove(index);
+        }
+    }
+
+    fn level_quantity(&self, side: Side, price: Price) -> Quantity {
+        let mut levels: Box<dyn Iterator<Item = (Price, Quantity)>> = match side {
+            Side::Buy => Box::new(self.book.bids()),
+            Side::Sell => Box::new(self.book.asks()),
+        };
+        levels
+            .find(|(level_price, _)| *level_price == price)
+            .map_or(Quantity::ZERO, |(_, qty)| qty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(last_update_id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id,
+            bids,
+            asks,
+        }
+    }
+
+    fn trade(trade_id: u64, price: f64, quantity: f64, is_buyer_maker: bool) -> TradeUpdate {
+        TradeUpdate {
+            event_time: 0,
+            symbol: Symbol::intern("BNBUSDT"),
+            trade_id,
+            price,
+            quantity,
+            trade_time: 0,
+            is_buyer_maker,
+        }
+    }
+
+    fn simulator() -> FillSimulator {
+        let mut simulator = FillSimulator::new("BNBUSDT".to_string());
+        simulator.on_depth(&depth(
+            1,
+            vec![(10.0, 5.0), (9.9, 8.0)],
+            vec![(10.1, 3.0), (10.2, 4.0)],
+        ));
+        simulator
+    }
+
+    #[test]
+    fn test_market_order_walks_visible_depth() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(Side::Buy, SimOrderKind::Market, Quantity(50_000));
+
+        let executions = simulator.drain_executions();
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[0].price, Price(101_000));
+        assert_eq!(executions[0].quantity, Quantity(30_000));
+        assert_eq!(executions[1].price, Price(102_000));
+        assert_eq!(executions[1].quantity, Quantity(20_000));
+        assert!(executions.iter().all(|e| e.liquidity == Liquidity::Taker));
+        assert_eq!(simulator.open_quantity(order_id), None);
+    }
+
+    #[test]
+    fn test_takers_share_visible_level() {
+        let mut simulator = simulator();
+        simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(101_000)),
+            Quantity(20_000),
+        );
+        let second = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(101_000)),
+            Quantity(20_000),
+        );
+
+        let executions = simulator.drain_executions();
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[0].quantity, Quantity(20_000));
+        assert_eq!(executions[1].order_id, second);
+        assert_eq!(executions[1].quantity, Quantity(10_000));
+        assert_eq!(simulator.open_quantity(second), Some(Quantity(10_000)));
+
+        // A new depth update makes the level available again
+        simulator.on_depth(&depth(2, vec![], vec![(10.1, 3.0)]));
+        let third = simulator.submit(Side::Buy, SimOrderKind::Market, Quantity(30_000));
+        assert_eq!(simulator.open_quantity(third), None);
+    }
+
+    #[test]
+    fn test_crossed_resting_order_capped_at_crossing_quantity() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(100_500)),
+            Quantity(50_000),
+        );
+
+        simulator.on_depth(&depth(2, vec![], vec![(10.1, 0.0), (10.05, 2.0)]));
+        let executions = simulator.drain_executions();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].quantity, Quantity(20_000));
+        assert_eq!(executions[0].liquidity, Liquidity::Maker);
+        assert_eq!(simulator.open_quantity(order_id), Some(Quantity(30_000)));
+
+        // The crossing quantity is gone for takers until the next depth update
+        simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(100_500)),
+            Quantity(10_000),
+        );
+        assert!(simulator.executions().is_empty());
+    }
+
+    #[test]
+    fn test_crossing_limit_order_rests_remainder() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(101_000)),
+            Quantity(50_000),
+        );
+
+        assert_eq!(simulator.executions().len(), 1);
+        assert_eq!(simulator.open_quantity(order_id), Some(Quantity(20_000)));
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(0)));
+    }
+
+    #[test]
+    fn test_passive_order_fills_after_queue_ahead_trades() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(100_000)),
+            Quantity(20_000),
+        );
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(50_000)));
+
+        simulator.on_trade(&trade(7, 10.0, 4.0, true));
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(10_000)));
+        assert!(simulator.executions().is_empty());
+
+        // Buyer initiated trades never hit our bid
+        simulator.on_trade(&trade(8, 10.0, 4.0, false));
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(10_000)));
+
+        simulator.on_trade(&trade(9, 10.0, 2.0, true));
+        let executions = simulator.drain_executions();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].quantity, Quantity(10_000));
+        assert_eq!(executions[0].liquidity, Liquidity::Maker);
+        assert_eq!(executions[0].source_id, 9);
+        assert_eq!(simulator.open_quantity(order_id), Some(Quantity(10_000)));
+    }
+
+    #[test]
+    fn test_level_shrinking_moves_us_forward() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(
+            Side::Sell,
+            SimOrderKind::Limit(Price(101_000)),
+            Quantity(10_000),
+        );
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(30_000)));
+
+        simulator.on_depth(&depth(2, vec![], vec![(10.1, 1.0)]));
+        assert_eq!(simulator.queue_ahead(order_id), Some(Quantity(10_000)));
+    }
+
+    #[test]
+    fn test_market_trading_through_fills_everything() {
+        let mut simulator = simulator();
+        let first = simulator.submit(
+            Side::Sell,
+            SimOrderKind::Limit(Price(102_000)),
+            Quantity(10_000),
+        );
+        let second = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(99_000)),
+            Quantity(10_000),
+        );
+
+        simulator.on_trade(&trade(3, 10.25, 1.0, false));
+        assert_eq!(simulator.open_quantity(first), None);
+
+        simulator.on_depth(&depth(3, vec![(9.9, 0.0)], vec![(9.8, 1.0)]));
+        assert_eq!(simulator.open_quantity(second), None);
+        assert_eq!(simulator.executions().len(), 2);
+        assert_eq!(simulator.executions()[1].source_id, 3);
+    }
+
+    #[test]
+    fn test_cancel_resting_order() {
+        let mut simulator = simulator();
+        let order_id = simulator.submit(
+            Side::Buy,
+            SimOrderKind::Limit(Price(99_000)),
+            Quantity(10_000),
+        );
+        assert!(simulator.cancel(order_id));
+        assert!(!simulator.cancel(order_id));
+        simulator.on_trade(&trade(1, 9.8, 5.0, true));
+        assert!(simulator.executions().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+/// Framing of binary messages on byte streams such as TCP or Unix domain sockets.
+/// A frame is an 8 byte header, the payload and a CRC32C of everything before it:
+///
+/// `magic: u16 | kind: u8 | reserved: u8 | length: u32 | payload: [u8; length] | crc32c: u32`
+///
+/// with every integer little endian. The kind tells the receiver how to decode the payload,
+/// framing does not look at it. `FrameDecoder` takes the bytes in whatever pieces the socket
+/// returns them and yields complete frames. A frame whose checksum does not match, or whose
+/// length is above the decoder's limit, is reported and skipped: the decoder resynchronizes on
+/// the next magic, so one damaged frame costs that frame and not the connection.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: u16 = 0xB0C5;
+pub const HEADER_LEN: usize = 8;
+pub const TRAILER_LEN: usize = 4;
+pub const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: u8,
+    pub payload: Vec<u8>,
+}
+
+// Appends the frame to `buffer`
+pub fn encode_frame(kind: u8, payload: &[u8], buffer: &mut Vec<u8>) {
+    let length = u32::try_from(payload.len()).expect("Frame payload above 4 GiB");
+    let start = buffer.len();
+    buffer.extend_from_slice(&MAGIC.to_le_bytes());
+    buffer.push(kind);
+    buffer.push(0);
+    buffer.extend_from_slice(&length.to_le_bytes());
+    buffer.extend_from_slice(payload);
+    let crc = crc32c(&buffer[start..]);
+    buffer.extend_from_slice(&crc.to_le_bytes());
+}
+
+pub fn write_frame(writer: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(HEADER_LEN + payload.len() + TRAILER_LEN);
+    encode_frame(kind, payload, &mut buffer);
+    writer.write_all(&buffer)
+}
+
+// CRC32C (Castagnoli), the checksum of iSCSI and SCTP
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC32C_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    // Bytes dropped while looking for the next magic
+    Garbage { skipped: usize },
+    // Announced payload above the decoder's limit
+    TooLong { length: usize },
+    Checksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Garbage { skipped } => {
+                write!(f, "skipped {} bytes that do not start a frame", skipped)
+            }
+            FrameError::TooLong { length } => write!(f, "frame payload of {} bytes", length),
+            FrameError::Checksum { expected, actual } => write!(
+                f,
+                "frame checksum {:#010x}, expected {:#010x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+    pub frames: u64,
+    pub errors: u64,
+    // Bytes that did not end up in a frame
+    pub skipped_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    // Start of the undecoded bytes in `buffer`
+    position: usize,
+    max_payload: usize,
+    stats: DecoderStats,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder {
+            buffer: Vec::new(),
+            position: 0,
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            stats: DecoderStats::default(),
+        }
+    }
+
+    // Frames announcing a longer payload are treated as corrupted, which also bounds how many
+    // bytes a damaged length field makes the decoder wait for
+    pub fn with_max_payload(mut self, max_payload: usize) -> FrameDecoder {
+        self.max_payload = max_payload;
+        self
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    // Bytes received and not decoded yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        // Decoded bytes are dropped once they make up most of the buffer
+        if self.position > 0 && self.position >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Next frame or error, None until more bytes are pushed
+    pub fn next_frame(&mut self) -> Option<Result<Frame, FrameError>> {
+        let pending = &self.buffer[self.position..];
+        let magic = MAGIC.to_le_bytes();
+        let start = pending.windows(2).position(|window| window == magic);
+        let skipped = match start {
+            Some(start) => start,
+            // A trailing first byte of the magic may be completed by the next push
+            None if pending.last() == Some(&magic[0]) => pending.len() - 1,
+            None => pending.len(),
+        };
+        if skipped > 0 {
+            self.skip(skipped);
+            return Some(Err(FrameError::Garbage { skipped }));
+        }
+        if start.is_none() || pending.len() < HEADER_LEN {
+            return None;
+        }
+
+        let length = u32::from_le_bytes(pending[4..8].try_into().unwrap()) as usize;
+        if length > self.max_payload {
+            self.skip(1);
+            return Some(Err(FrameError::TooLong { length }));
+        }
+        let end = HEADER_LEN + length;
+        if pending.len() < end + TRAILER_LEN {
+            return None;
+        }
+        let expected = u32::from_le_bytes(pending[end..end + TRAILER_LEN].try_into().unwrap());
+        let actual = crc32c(&pending[..end]);
+        if actual != expected {
+            // The magic may have been payload bytes, look for the next one right after it
+            self.skip(1);
+            return Some(Err(FrameError::Checksum { expected, actual }));
+        }
+
+        let frame = Frame {
+            kind: pending[2],
+            payload: pending[HEADER_LEN..end].to_vec(),
+        };
+        self.position += end + TRAILER_LEN;
+        self.stats.frames += 1;
+        Some(Ok(frame))
+    }
+
+    fn skip(&mut self, skipped: usize) {
+        self.position += skipped;
+        self.stats.errors += 1;
+        self.stats.skipped_bytes += skipped as u64;
+    }
+}
+
+// Reads frames from a blocking stream, damaged frames are logged and skipped
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    decoder: FrameDecoder,
+    chunk: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> FrameReader<R> {
+        FrameReader::with_decoder(reader, FrameDecoder::new())
+    }
+
+    pub fn with_decoder(reader: R, decoder: FrameDecoder) -> FrameReader<R> {
+        FrameReader {
+            reader,
+            decoder,
+            chunk: vec![0; 64 * 1024],
+        }
+    }
+
+    pub fn decoder(&self) -> &FrameDecoder {
+        &self.decoder
+    }
+
+    // Next intact frame, None once the stream ended
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            while let Some(result) = self.decoder.next_frame() {
+                match result {
+                    Ok(frame) => return Ok(Some(frame)),
+                    Err(error) => log::warn!("Dropped damaged frame data: {}", error),
+                }
+            }
+            let read = match self.reader.read(&mut self.chunk) {
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            if read == 0 {
+                if self.decoder.buffered() > 0 {
+                    log::warn!(
+                        "Stream ended inside a frame, {} bytes dropped",
+                        self.decoder.buffered()
+                    );
+                }
+                return Ok(None);
+            }
+            self.decoder.push(&self.chunk[..read]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn test_partial_reads_and_damaged_frames() {
+        let mut stream = Vec::new();
+        encode_frame(1, b"first", &mut stream);
+        let damaged = stream.len();
+        encode_frame(2, b"second", &mut stream);
+        encode_frame(3, b"", &mut stream);
+        // Flip a payload bit of the second frame
+        stream[damaged + HEADER_LEN] ^= 0x20;
+        stream.splice(0..0, b"noise".iter().copied());
+
+        // One byte at a time, the worst a socket can do
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        let mut errors = Vec::new();
+        for byte in &stream {
+            decoder.push(std::slice::from_ref(byte));
+            while let Some(result) = decoder.next_frame() {
+                match result {
+                    Ok(frame) => frames.push(frame),
+                    Err(error) => errors.push(error),
+                }
+            }
+        }
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    kind: 1,
+                    payload: b"first".to_vec()
+                },
+                Frame {
+                    kind: 3,
+                    payload: Vec::new()
+                },
+            ]
+        );
+        assert!(matches!(errors[0], FrameError::Garbage { .. }));
+        assert!(errors
+            .iter()
+            .any(|error| matches!(error, FrameError::Checksum { .. })));
+        assert_eq!(decoder.buffered(), 0);
+        assert_eq!(decoder.stats().frames, 2);
+
+        let mut reader = FrameReader::new(&stream[..]);
+        assert_eq!(reader.read_frame().unwrap().unwrap().kind, 1);
+        assert_eq!(reader.read_frame().unwrap().unwrap().kind, 3);
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_length_limit() {
+        let mut stream = Vec::new();
+        encode_frame(7, &[0; 100], &mut stream);
+        encode_frame(8, &[1; 10], &mut stream);
+        let mut decoder = FrameDecoder::new().with_max_payload(50);
+        decoder.push(&stream);
+        assert_eq!(
+            decoder.next_frame(),
+            Some(Err(FrameError::TooLong { length: 100 }))
+        );
+        let frame = std::iter::from_fn(|| decoder.next_frame())
+            .find_map(Result::ok)
+            .unwrap();
+        assert_eq!(frame.kind, 8);
+    }
+}
@@ -0,0 +1,242 @@
+/// USDⓈ-M futures market data.
+/// `FuturesEvent` reads the markPrice, forceOrder and depth streams of the futures combined
+/// stream endpoint, `FuturesBook` keeps the depth of a perpetual together with its mark price,
+/// funding and the latest liquidations. Futures depth updates carry the final update id of
+/// the previous update, a gap in that chain flags the book for a resync from a snapshot.
+use crate::binance_payloads::{
+    ForceOrderUpdate, FuturesDepthUpdate, LiquidationOrder, MarkPriceUpdate,
+};
+use crate::orderbook::OrderBook;
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+// Liquidations kept per book
+const LIQUIDATION_HISTORY: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "e")]
+pub enum FuturesEvent {
+    #[serde(rename = "markPriceUpdate")]
+    MarkPrice(MarkPriceUpdate),
+    #[serde(rename = "forceOrder")]
+    ForceOrder(ForceOrderUpdate),
+    #[serde(rename = "depthUpdate")]
+    Depth(FuturesDepthUpdate),
+}
+
+#[derive(Debug, Deserialize)]
+struct FuturesEnvelope {
+    data: FuturesEvent,
+}
+
+impl FuturesEvent {
+    // Accepts both a combined stream message and a raw event
+    pub fn parse(payload: &[u8]) -> Result<FuturesEvent, serde_json::Error> {
+        match serde_json::from_slice::<FuturesEnvelope>(payload) {
+            Ok(envelope) => Ok(envelope.data),
+            Err(_) => serde_json::from_slice(payload),
+        }
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        match self {
+            FuturesEvent::MarkPrice(update) => update.symbol,
+            FuturesEvent::ForceOrder(update) => update.order.symbol,
+            FuturesEvent::Depth(update) => update.symbol,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FuturesBook {
+    book: OrderBook,
+    mark_price: Option<f64>,
+    index_price: Option<f64>,
+    funding_rate: Option<f64>,
+    next_funding_time: Option<u64>,
+    // Oldest first
+    liquidations: VecDeque<LiquidationOrder>,
+    // Final update id of the last applied depth update
+    last_final_update_id: Option<u64>,
+    needs_resync: bool,
+}
+
+impl FuturesBook {
+    pub fn new(symbol: impl Into<Symbol>) -> FuturesBook {
+        FuturesBook::with_book(OrderBook::new(symbol))
+    }
+
+    // Wraps a book that is already configured, e.g. with a converter or a snapshot
+    pub fn with_book(book: OrderBook) -> FuturesBook {
+        FuturesBook {
+            book,
+            mark_price: None,
+            index_price: None,
+            funding_rate: None,
+            next_funding_time: None,
+            liquidations: VecDeque::new(),
+            last_final_update_id: None,
+            needs_resync: false,
+        }
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.book.symbol()
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn book_mut(&mut self) -> &mut OrderBook {
+        &mut self.book
+    }
+
+    pub fn mark_price(&self) -> Option<f64> {
+        self.mark_price
+    }
+
+    pub fn index_price(&self) -> Option<f64> {
+        self.index_price
+    }
+
+    pub fn funding_rate(&self) -> Option<f64> {
+        self.funding_rate
+    }
+
+    // Milliseconds since the epoch
+    pub fn next_funding_time(&self) -> Option<u64> {
+        self.next_funding_time
+    }
+
+    // Mark price over the index price
+    pub fn premium(&self) -> Option<f64> {
+        Some(self.mark_price? - self.index_price?)
+    }
+
+    pub fn liquidations(&self) -> impl Iterator<Item = &LiquidationOrder> {
+        self.liquidations.iter()
+    }
+
+    // A depth update did not continue the previous one, the book has to be rebuilt from a
+    // snapshot. Cleared by `resync`.
+    pub fn needs_resync(&self) -> bool {
+        self.needs_resync
+    }
+
+    // Restarts the update id chain after the book was reloaded, `last_update_id` being the
+    // id of the snapshot
+    pub fn resync(&mut self, last_update_id: u64) {
+        self.last_final_update_id = Some(last_update_id);
+        self.needs_resync = false;
+    }
+
+    // Events of other symbols are ignored, returns whether the event was applied
+    pub fn apply(&mut self, event: &FuturesEvent) -> bool {
+        if event.symbol() != self.book.symbol() {
+            return false;
+        }
+        match event {
+            FuturesEvent::MarkPrice(update) => {
+                self.mark_price = Some(update.mark_price);
+                self.index_price = Some(update.index_price);
+                self.funding_rate = Some(update.funding_rate);
+                self.next_funding_time = Some(update.next_funding_time);
+                true
+            }
+            FuturesEvent::ForceOrder(update) => {
+                if self.liquidations.len() == LIQUIDATION_HISTORY {
+                    self.liquidations.pop_front();
+                }
+                self.liquidations.push_back(update.order.clone());
+                true
+            }
+            FuturesEvent::Depth(update) => self.apply_depth(update),
+        }
+    }
+
+    fn apply_depth(&mut self, update: &FuturesDepthUpdate) -> bool {
+        if self.needs_resync {
+            return false;
+        }
+        match self.last_final_update_id {
+            // Already covered by the snapshot or an earlier update
+            Some(last) if update.final_update_id <= last => return false,
+            // The first update after a snapshot straddles its id, later ones chain on `pu`
+            Some(last)
+                if update.previous_final_update_id != last && update.first_update_id > last =>
+            {
+                log::warn!(
+                    "{} depth gap: expected pu {}, got {}",
+                    update.symbol,
+                    last,
+                    update.previous_final_update_id
+                );
+                self.needs_resync = true;
+                return false;
+            }
+            _ => {}
+        }
+        self.book.update_depth(&update.to_depth_update());
+        self.last_final_update_id = Some(update.final_update_id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::Side;
+
+    fn depth(first: u64, last: u64, previous: u64, bid: &str) -> Vec<u8> {
+        format!(
+            r#"{{"stream":"btcusdt@depth","data":{{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":{},"u":{},"pu":{},"b":[[{}]],"a":[]}}}}"#,
+            first, last, previous, bid
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_mark_price_and_liquidations() {
+        let mut book = FuturesBook::new("BTCUSDT");
+        let mark = br#"{"stream":"btcusdt@markPrice","data":{"e":"markPriceUpdate","E":1562305380000,"s":"BTCUSDT","p":"11794.15000000","i":"11784.62659091","P":"11784.25641265","r":"0.00038167","T":1562306400000}}"#;
+        assert!(book.apply(&FuturesEvent::parse(mark).unwrap()));
+        assert_eq!(book.mark_price(), Some(11794.15));
+        assert_eq!(book.funding_rate(), Some(0.00038167));
+        assert_eq!(book.next_funding_time(), Some(1562306400000));
+        assert!((book.premium().unwrap() - 9.52340909).abs() < 1e-6);
+
+        let liquidation = br#"{"e":"forceOrder","E":1568014460893,"o":{"s":"BTCUSDT","S":"SELL","o":"LIMIT","f":"IOC","q":"0.014","p":"9910","ap":"9910","X":"FILLED","l":"0.014","z":"0.014","T":1568014460893}}"#;
+        assert!(book.apply(&FuturesEvent::parse(liquidation).unwrap()));
+        let order = book.liquidations().next().unwrap();
+        assert_eq!(order.side, Side::Sell);
+        assert_eq!(order.filled_quantity, 0.014);
+
+        let other =
+            br#"{"e":"markPriceUpdate","E":1,"s":"ETHUSDT","p":"1","i":"1","P":"1","r":"0","T":1}"#;
+        assert!(!book.apply(&FuturesEvent::parse(other).unwrap()));
+    }
+
+    #[test]
+    fn test_depth_gap_requires_resync() {
+        let mut book = FuturesBook::new("BTCUSDT");
+        book.resync(100);
+        let apply = |book: &mut FuturesBook, payload: Vec<u8>| {
+            book.apply(&FuturesEvent::parse(&payload).unwrap())
+        };
+
+        assert!(!apply(&mut book, depth(90, 95, 89, r#""1.0","1.0""#)));
+        assert!(apply(&mut book, depth(98, 105, 97, r#""1.0","1.0""#)));
+        assert!(apply(&mut book, depth(106, 110, 105, r#""1.1","2.0""#)));
+        assert_eq!(book.book().bids().count(), 2);
+
+        assert!(!apply(&mut book, depth(115, 120, 112, r#""1.2","1.0""#)));
+        assert!(book.needs_resync());
+        assert!(!apply(&mut book, depth(121, 125, 120, r#""1.2","1.0""#)));
+
+        book.resync(125);
+        assert!(apply(&mut book, depth(126, 130, 125, r#""1.2","1.0""#)));
+        assert_eq!(book.book().bids().count(), 3);
+    }
+}
@@ -0,0 +1,692 @@
+/// Order entry for the matching engine over a Unix domain socket, for processes that are not
+/// written in Rust. Every message in both directions is a frame of the crate's binary framing
+/// (see `framing`) whose payload is JSON:
+///
+/// - `AUTH` from the client, the payload is the token, and it has to be the first frame
+/// - `COMMAND` from the client, a `GatewayCommand`, e.g.
+///   `{"type":"new","order_id":1,"side":"Buy","price":100,"quantity":5,"order_type":"GoodToCancel"}`
+/// - `REPORT` from the gateway, a `GatewayReport`
+///
+/// The gateway answers every command with `accepted`, `cancelled` or `rejected`, followed by a
+/// `fill` for each trade of the order, a rejected `modify` leaves the original order resting.
+/// Orders are owned by the session that entered them: the resting side of a trade hears about
+/// its fill on its own session, and only the owner can cancel or modify an order. Prices and
+/// quantities are engine ticks and lots.
+///
+/// The engine is not shared with the session threads: they queue the commands they read and the
+/// thread owning the engine runs them with `OrderGateway::process`. The queue is bounded, a
+/// session that outruns the engine stops being read until it drains. At most
+/// `MAX_CONNECTIONS` connections are served at once, and a client that does not authenticate
+/// within `AUTH_TIMEOUT` is disconnected. Reports are written by a thread per session, a session
+/// that stops reading them is disconnected instead of holding up the engine.
+use crate::framing::{write_frame, FrameReader};
+use crate::orderbookv2::{
+    Liquidity, Order, OrderBook, OrderCommand, OrderId, OrderModify, OrderStatus, OrderType, Price,
+    Quantity, Side, Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const AUTH: u8 = 1;
+pub const COMMAND: u8 = 2;
+pub const REPORT: u8 = 3;
+
+// How often the acceptor checks whether the gateway is gone
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(10);
+// Connections served at once, authenticated or not, further ones are closed on accept
+pub const MAX_CONNECTIONS: usize = 64;
+// Time a new connection has to send its AUTH frame
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+// Commands waiting for the engine across all sessions
+const INBOX_CAPACITY: usize = 1024;
+// Reports waiting for a session's writer thread, a session that falls this far behind is dropped
+const OUTBOX_CAPACITY: usize = 1024;
+// Time a single report write may block the writer thread of a session
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayCommand {
+    New {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        order_type: OrderType,
+    },
+    Cancel {
+        order_id: OrderId,
+    },
+    Modify {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    },
+}
+
+impl GatewayCommand {
+    pub fn order_id(&self) -> OrderId {
+        match *self {
+            GatewayCommand::New { order_id, .. }
+            | GatewayCommand::Cancel { order_id }
+            | GatewayCommand::Modify { order_id, .. } => order_id,
+        }
+    }
+
+    fn to_order_command(self) -> OrderCommand {
+        match self {
+            GatewayCommand::New {
+                order_id,
+                side,
+                price,
+                quantity,
+                order_type,
+            } => OrderCommand::New(Order::new(order_id, price, quantity, order_type, side)),
+            GatewayCommand::Cancel { order_id } => OrderCommand::Cancel(order_id),
+            GatewayCommand::Modify {
+                order_id,
+                side,
+                price,
+                quantity,
+            } => OrderCommand::Modify(OrderModify::new(order_id, side, price, quantity)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayReport {
+    Authenticated,
+    // The connection is closed after it
+    AuthFailed,
+    // A new or modified order was entered, fills follow
+    Accepted {
+        order_id: OrderId,
+    },
+    Cancelled {
+        order_id: OrderId,
+    },
+    Rejected {
+        order_id: OrderId,
+        reason: String,
+    },
+    Fill {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+        // The order was resting, it did not take liquidity
+        maker: bool,
+        timestamp: Timestamp,
+    },
+    // A frame that is not a command, nothing was done
+    Invalid {
+        reason: String,
+    },
+}
+
+type SessionId = u64;
+
+// Commands read by the session threads, waiting for the engine
+type Inbox = Receiver<(SessionId, GatewayCommand)>;
+
+// Queue of encoded reports for the writer thread of a session, and its socket
+#[derive(Debug)]
+struct Outbox {
+    reports: SyncSender<Vec<u8>>,
+    stream: UnixStream,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    next_id: SessionId,
+    // Outbox of every authenticated session
+    writers: HashMap<SessionId, Outbox>,
+}
+
+impl Sessions {
+    // Queues the report without waiting on the socket, a session whose queue is full or whose
+    // writer is gone is disconnected
+    fn send(&mut self, session: SessionId, report: &GatewayReport) {
+        let Some(outbox) = self.writers.get(&session) else {
+            return;
+        };
+        let payload = serde_json::to_vec(report).expect("Report serialization failed");
+        if let Err(error) = outbox.reports.try_send(payload) {
+            log::warn!("Dropping gateway session {}: {}", session, error);
+            let _ = outbox.stream.shutdown(Shutdown::Both);
+            self.writers.remove(&session);
+        }
+    }
+}
+
+// Writes the queued reports of a session until the queue is dropped or the socket fails
+fn write_reports(mut stream: UnixStream, reports: Receiver<Vec<u8>>) {
+    for payload in reports {
+        if let Err(error) = write_frame(&mut stream, REPORT, &payload) {
+            log::warn!("Gateway session stopped writing: {}", error);
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    }
+}
+
+// Reads from the socket while `deadline` holds, however the bytes are spread out. Each read
+// only waits for the time left, no deadline means no timeout.
+struct DeadlineReader<'a> {
+    stream: &'a UnixStream,
+    deadline: &'a Cell<Option<Instant>>,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline.get() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(left))?;
+        }
+        self.stream.read(buf)
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderGateway {
+    path: PathBuf,
+    sessions: Arc<Mutex<Sessions>>,
+    inbox: Inbox,
+    // Session that entered every order still in the book
+    owners: HashMap<OrderId, SessionId>,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl OrderGateway {
+    // Listens on `path`, replacing a socket file left behind by an earlier gateway
+    pub fn bind(path: impl AsRef<Path>, token: impl Into<String>) -> io::Result<OrderGateway> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let sessions = Arc::new(Mutex::new(Sessions::default()));
+        let (commands, inbox) = mpsc::sync_channel(INBOX_CAPACITY);
+        let stop = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let token = token.into();
+            let sessions = Arc::clone(&sessions);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || accept(listener, token, sessions, commands, stop))
+        };
+        Ok(OrderGateway {
+            path,
+            sessions,
+            inbox,
+            owners: HashMap::new(),
+            stop,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Authenticated sessions still connected
+    pub fn sessions(&self) -> usize {
+        self.sessions.lock().unwrap().writers.len()
+    }
+
+    // Runs the queued commands through the engine and reports back to the sessions, waiting up
+    // to `timeout` for the first one. Returns how many commands were run.
+    pub fn process(&mut self, engine: &mut OrderBook, timeout: Duration) -> usize {
+        let first = match self.inbox.recv_timeout(timeout) {
+            Ok(first) => first,
+            Err(_) => return 0,
+        };
+        let queued: Vec<_> = std::iter::once(first)
+            .chain(self.inbox.try_iter())
+            .collect();
+        for (session, command) in &queued {
+            self.execute(engine, *session, *command);
+        }
+        queued.len()
+    }
+
+    fn execute(&mut self, engine: &mut OrderBook, session: SessionId, command: GatewayCommand) {
+        let order_id = command.order_id();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        // Orders in the book that no session entered are nobody's to touch
+        let foreign = !matches!(command, GatewayCommand::New { .. })
+            && self.owners.get(&order_id) != Some(&session)
+            && is_live(engine.order_status(order_id));
+        let result = if foreign {
+            Err(format!(
+                "order {} was not entered by this session",
+                order_id
+            ))
+        } else {
+            let mut report = engine.apply_batch(vec![command.to_order_command()]);
+            match report.results.pop() {
+                Some(Err(rejected)) => Err(rejected.to_string()),
+                _ => Ok(report.trades),
+            }
+        };
+        let trades = match result {
+            Ok(trades) => trades,
+            Err(reason) => {
+                sessions.send(session, &GatewayReport::Rejected { order_id, reason });
+                return;
+            }
+        };
+
+        if let GatewayCommand::Cancel { .. } = command {
+            sessions.send(session, &GatewayReport::Cancelled { order_id });
+        } else {
+            self.owners.insert(order_id, session);
+            sessions.send(session, &GatewayReport::Accepted { order_id });
+        }
+        for trade in &trades {
+            for fill in [&trade.bid_trade, &trade.ask_trade] {
+                let Some(&owner) = self.owners.get(&fill.order_id) else {
+                    continue;
+                };
+                sessions.send(
+                    owner,
+                    &GatewayReport::Fill {
+                        order_id: fill.order_id,
+                        price: fill.price,
+                        quantity: fill.quantity,
+                        maker: fill.liquidity == Liquidity::Maker,
+                        timestamp: trade.timestamp,
+                    },
+                );
+            }
+        }
+        // Orders that left the book are no longer owned
+        self.owners
+            .retain(|order_id, _| is_live(engine.order_status(*order_id)));
+    }
+}
+
+fn is_live(status: Option<OrderStatus>) -> bool {
+    matches!(
+        status,
+        Some(OrderStatus::New | OrderStatus::PartiallyFilled)
+    )
+}
+
+// Stops accepting, disconnects every session and removes the socket file
+impl Drop for OrderGateway {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        for outbox in self.sessions.lock().unwrap().writers.values() {
+            let _ = outbox.stream.shutdown(Shutdown::Both);
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Releases a connection slot when the session thread ends
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn accept(
+    listener: UnixListener,
+    token: String,
+    sessions: Arc<Mutex<Sessions>>,
+    commands: SyncSender<(SessionId, GatewayCommand)>,
+    stop: Arc<AtomicBool>,
+) {
+    let token = Arc::new(token);
+    let connections = Arc::new(AtomicUsize::new(0));
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if connections.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+                    log::warn!("Gateway refusing connection, {} served", MAX_CONNECTIONS);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+                connections.fetch_add(1, Ordering::Relaxed);
+                let slot = ConnectionSlot(Arc::clone(&connections));
+                let token = Arc::clone(&token);
+                let sessions = Arc::clone(&sessions);
+                let commands = commands.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(error) = serve(stream, &token, &sessions, &commands) {
+                        log::warn!("Gateway session ended: {}", error);
+                    }
+                });
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL)
+            }
+            Err(error) => log::error!("Gateway cannot accept: {}", error),
+        }
+    }
+}
+
+fn serve(
+    stream: UnixStream,
+    token: &str,
+    sessions: &Mutex<Sessions>,
+    commands: &SyncSender<(SessionId, GatewayCommand)>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    writer.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    // The whole AUTH frame has to arrive within AUTH_TIMEOUT
+    let deadline = Cell::new(Some(Instant::now() + AUTH_TIMEOUT));
+    let mut frames = FrameReader::new(DeadlineReader {
+        stream: &stream,
+        deadline: &deadline,
+    });
+
+    let authenticated = match frames.read_frame()? {
+        Some(frame) => frame.kind == AUTH && frame.payload == token.as_bytes(),
+        None => return Ok(()),
+    };
+    if !authenticated {
+        let payload = serde_json::to_vec(&GatewayReport::AuthFailed)?;
+        write_frame(&mut writer, REPORT, &payload)?;
+        return writer.shutdown(Shutdown::Both);
+    }
+    // Authenticated sessions may stay idle
+    deadline.set(None);
+    stream.set_read_timeout(None)?;
+    let (reports, queued) = mpsc::sync_channel(OUTBOX_CAPACITY);
+    let outbox = Outbox {
+        reports,
+        stream: stream.try_clone()?,
+    };
+    thread::spawn(move || write_reports(writer, queued));
+    let session = {
+        let mut sessions = sessions.lock().unwrap();
+        sessions.next_id += 1;
+        let session = sessions.next_id;
+        sessions.writers.insert(session, outbox);
+        sessions.send(session, &GatewayReport::Authenticated);
+        session
+    };
+
+    let result = (|| {
+        while let Some(frame) = frames.read_frame()? {
+            let command = match frame.kind {
+                COMMAND => serde_json::from_slice::<GatewayCommand>(&frame.payload)
+                    .map_err(|error| error.to_string()),
+                kind => Err(format!("unexpected frame kind {}", kind)),
+            };
+            match command {
+                Ok(command) => {
+                    if commands.send((session, command)).is_err() {
+                        // The gateway is gone
+                        break;
+                    }
+                }
+                Err(reason) => sessions
+                    .lock()
+                    .unwrap()
+                    .send(session, &GatewayReport::Invalid { reason }),
+            }
+        }
+        Ok(())
+    })();
+    sessions.lock().unwrap().writers.remove(&session);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::Frame;
+    use crate::session::SessionState;
+
+    struct Client {
+        writer: UnixStream,
+        reader: FrameReader<UnixStream>,
+    }
+
+    impl Client {
+        fn connect(path: &Path, token: &str) -> Client {
+            let stream = UnixStream::connect(path).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut client = Client {
+                writer: stream.try_clone().unwrap(),
+                reader: FrameReader::new(stream),
+            };
+            write_frame(&mut client.writer, AUTH, token.as_bytes()).unwrap();
+            client
+        }
+
+        fn send(&mut self, command: GatewayCommand) {
+            let payload = serde_json::to_vec(&command).unwrap();
+            write_frame(&mut self.writer, COMMAND, &payload).unwrap();
+        }
+
+        fn report(&mut self) -> Option<GatewayReport> {
+            let Frame { kind, payload } = self.reader.read_frame().unwrap()?;
+            assert_eq!(kind, REPORT);
+            Some(serde_json::from_slice(&payload).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_sessions_trade_through_the_gateway() {
+        let path = std::env::temp_dir().join(format!("gateway-{}.sock", std::process::id()));
+        let mut engine = OrderBook::new();
+        let mut gateway = OrderGateway::bind(&path, "secret").unwrap();
+        let wait = Duration::from_secs(5);
+
+        let mut intruder = Client::connect(&path, "guess");
+        assert_eq!(intruder.report(), Some(GatewayReport::AuthFailed));
+        assert_eq!(intruder.report(), None);
+
+        let mut maker = Client::connect(&path, "secret");
+        let mut taker = Client::connect(&path, "secret");
+        assert_eq!(maker.report(), Some(GatewayReport::Authenticated));
+        assert_eq!(taker.report(), Some(GatewayReport::Authenticated));
+        assert_eq!(gateway.sessions(), 2);
+
+        maker.send(GatewayCommand::New {
+            order_id: 1,
+            side: Side::Buy,
+            price: Price(100),
+            quantity: Quantity(5),
+            order_type: OrderType::GoodToCancel,
+        });
+        assert_eq!(gateway.process(&mut engine, wait), 1);
+        assert_eq!(
+            maker.report(),
+            Some(GatewayReport::Accepted { order_id: 1 })
+        );
+        // A raw frame the way another language would write it
+        let raw = br#"{"type":"new","order_id":2,"side":"Sell","price":100,"quantity":3,"order_type":"GoodToCancel"}"#;
+        write_frame(&mut taker.writer, COMMAND, raw).unwrap();
+        assert_eq!(gateway.process(&mut engine, wait), 1);
+        assert_eq!(
+            taker.report(),
+            Some(GatewayReport::Accepted { order_id: 2 })
+        );
+        let Some(GatewayReport::Fill {
+            order_id: 2,
+            quantity: Quantity(3),
+            maker: false,
+            ..
+        }) = taker.report()
+        else {
+            panic!("taker fill expected");
+        };
+        let Some(GatewayReport::Fill {
+            order_id: 1,
+            price: Price(100),
+            maker: true,
+            ..
+        }) = maker.report()
+        else {
+            panic!("maker fill expected");
+        };
+
+        // Only the owner cancels
+        taker.send(GatewayCommand::Cancel { order_id: 1 });
+        gateway.process(&mut engine, wait);
+        assert!(matches!(
+            taker.report(),
+            Some(GatewayReport::Rejected { order_id: 1, .. })
+        ));
+        maker.send(GatewayCommand::Cancel { order_id: 1 });
+        gateway.process(&mut engine, wait);
+        assert_eq!(
+            maker.report(),
+            Some(GatewayReport::Cancelled { order_id: 1 })
+        );
+        maker.send(GatewayCommand::Cancel { order_id: 1 });
+        gateway.process(&mut engine, wait);
+        assert_eq!(
+            maker.report(),
+            Some(GatewayReport::Rejected {
+                order_id: 1,
+                reason: "unknown order 1".to_string(),
+            })
+        );
+        write_frame(&mut maker.writer, COMMAND, b"{}").unwrap();
+        assert!(matches!(
+            maker.report(),
+            Some(GatewayReport::Invalid { .. })
+        ));
+        assert!(engine.get_orderbook_level_infos().get_bids().is_empty());
+
+        // A rejected amendment leaves the original order resting
+        maker.send(GatewayCommand::New {
+            order_id: 3,
+            side: Side::Buy,
+            price: Price(99),
+            quantity: Quantity(2),
+            order_type: OrderType::GoodToCancel,
+        });
+        gateway.process(&mut engine, wait);
+        assert_eq!(
+            maker.report(),
+            Some(GatewayReport::Accepted { order_id: 3 })
+        );
+        engine.set_session_state(SessionState::Halted);
+        maker.send(GatewayCommand::Modify {
+            order_id: 3,
+            side: Side::Buy,
+            price: Price(98),
+            quantity: Quantity(2),
+        });
+        gateway.process(&mut engine, wait);
+        assert!(matches!(
+            maker.report(),
+            Some(GatewayReport::Rejected { order_id: 3, .. })
+        ));
+        assert_eq!(engine.order_status(3), Some(OrderStatus::New));
+        let bids = engine.get_orderbook_level_infos().get_bids().clone();
+        assert_eq!(bids.len(), 1);
+        assert_eq!((bids[0].price, bids[0].quantity), (Price(99), Quantity(2)));
+
+        // Orders no session entered are not open to any of them
+        engine.set_session_state(SessionState::Open);
+        engine
+            .place_order(Order::new(
+                4,
+                Price(97),
+                Quantity(1),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            ))
+            .unwrap();
+        maker.send(GatewayCommand::Cancel { order_id: 4 });
+        gateway.process(&mut engine, wait);
+        assert!(matches!(
+            maker.report(),
+            Some(GatewayReport::Rejected { order_id: 4, .. })
+        ));
+        assert_eq!(engine.order_status(4), Some(OrderStatus::New));
+
+        drop(gateway);
+        assert!(!path.exists());
+        assert_eq!(maker.report(), None);
+    }
+
+    #[test]
+    fn test_deadline_spans_the_whole_read() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let deadline = Cell::new(Some(Instant::now() + Duration::from_millis(200)));
+        let mut reader = DeadlineReader {
+            stream: &server,
+            deadline: &deadline,
+        };
+        // A byte every 50ms never trips a per read timeout
+        let trickle = thread::spawn(move || {
+            let mut client = client;
+            for _ in 0..20 {
+                if io::Write::write_all(&mut client, b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let started = Instant::now();
+        let mut buf = [0; 1];
+        let error = loop {
+            if let Err(error) = reader.read(&mut buf) {
+                break error;
+            }
+        };
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ));
+        drop(server);
+        trickle.join().unwrap();
+    }
+
+    #[test]
+    fn test_connections_beyond_the_limit_are_closed() {
+        let path = std::env::temp_dir().join(format!("gateway-limit-{}.sock", std::process::id()));
+        let gateway = OrderGateway::bind(&path, "secret").unwrap();
+
+        // Unauthenticated connections hold their slot until AUTH_TIMEOUT
+        let idle: Vec<UnixStream> = (0..MAX_CONNECTIONS)
+            .map(|_| UnixStream::connect(&path).unwrap())
+            .collect();
+        let mut refused = UnixStream::connect(&path).unwrap();
+        refused
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(matches!(io::Read::read(&mut refused, &mut [0; 1]), Ok(0)));
+        assert_eq!(gateway.sessions(), 0);
+
+        drop(idle);
+        drop(gateway);
+    }
+}
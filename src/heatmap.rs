@@ -0,0 +1,244 @@
+/// Depth recorder for order book heatmaps.
+/// Samples the top levels of the L2 book or of the matching engine at a fixed interval and
+/// stores them in columns: one cell per (timestamp, side, price bucket) with the quantity
+/// resting in the bucket. Prices and quantities stay in the units of the sampled book.
+/// `export_csv` writes one row per cell, Parquet export lives in `export::heatmap_to_batch`.
+use crate::orderbook;
+use crate::orderbookv2::{self, Side, Timestamp};
+use crate::price_levels::LevelPrice;
+use serde::Serialize;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeatmapCell {
+    pub timestamp: Timestamp,
+    pub side: Side,
+    // Lowest price of the bucket
+    pub price: i64,
+    pub quantity: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeatmapRecorder {
+    interval: Timestamp,
+    levels: usize,
+    bucket_size: i64,
+    next_sample: Option<Timestamp>,
+    samples: usize,
+    timestamps: Vec<Timestamp>,
+    sides: Vec<Side>,
+    prices: Vec<i64>,
+    quantities: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct CsvCell {
+    timestamp: Timestamp,
+    side: &'static str,
+    price: i64,
+    quantity: u64,
+}
+
+impl HeatmapRecorder {
+    // Top `levels` of each side every `interval`, grouped in buckets of `bucket_size` price
+    // units (1 keeps every price apart)
+    pub fn new(interval: Duration, levels: usize, bucket_size: i64) -> HeatmapRecorder {
+        assert!(bucket_size > 0, "bucket size must be positive");
+        HeatmapRecorder {
+            interval: interval.as_nanos() as Timestamp,
+            levels,
+            bucket_size,
+            next_sample: None,
+            samples: 0,
+            timestamps: Vec::new(),
+            sides: Vec::new(),
+            prices: Vec::new(),
+            quantities: Vec::new(),
+        }
+    }
+
+    // Samples the levels unless the last sample is less than an interval old, returns whether
+    // it did. Levels are expected best first.
+    pub fn sample<P, Q>(
+        &mut self,
+        timestamp: Timestamp,
+        bids: impl Iterator<Item = (P, Q)>,
+        asks: impl Iterator<Item = (P, Q)>,
+    ) -> bool
+    where
+        P: LevelPrice,
+        Q: Into<u64>,
+    {
+        if self.next_sample.is_some_and(|next| timestamp < next) {
+            return false;
+        }
+        self.next_sample = Some(timestamp + self.interval);
+        self.samples += 1;
+
+        self.push_side(timestamp, Side::Buy, bids);
+        self.push_side(timestamp, Side::Sell, asks);
+        true
+    }
+
+    fn push_side<P, Q>(
+        &mut self,
+        timestamp: Timestamp,
+        side: Side,
+        levels: impl Iterator<Item = (P, Q)>,
+    ) where
+        P: LevelPrice,
+        Q: Into<u64>,
+    {
+        let first_cell = self.prices.len();
+        for (price, quantity) in levels.take(self.levels) {
+            let price = price.to_i64();
+            let bucket = price - price.rem_euclid(self.bucket_size);
+            let quantity = quantity.into();
+            // Levels arrive sorted, so equal buckets are adjacent
+            if self.prices.len() > first_cell && self.prices.last() == Some(&bucket) {
+                *self.quantities.last_mut().unwrap() += quantity;
+                continue;
+            }
+            self.timestamps.push(timestamp);
+            self.sides.push(side);
+            self.prices.push(bucket);
+            self.quantities.push(quantity);
+        }
+    }
+
+    pub fn sample_l2(&mut self, timestamp: Timestamp, book: &orderbook::OrderBook) -> bool {
+        self.sample(timestamp, book.bids(), book.asks())
+    }
+
+    // Sampled at the engine clock
+    pub fn sample_engine(&mut self, book: &orderbookv2::OrderBook) -> bool {
+        self.sample(book.clock().now(), book.bids(), book.asks())
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples
+    }
+
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+
+    pub fn timestamps(&self) -> &[Timestamp] {
+        &self.timestamps
+    }
+
+    pub fn sides(&self) -> &[Side] {
+        &self.sides
+    }
+
+    pub fn prices(&self) -> &[i64] {
+        &self.prices
+    }
+
+    pub fn quantities(&self) -> &[u64] {
+        &self.quantities
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = HeatmapCell> + '_ {
+        (0..self.len()).map(|i| HeatmapCell {
+            timestamp: self.timestamps[i],
+            side: self.sides[i],
+            price: self.prices[i],
+            quantity: self.quantities[i],
+        })
+    }
+
+    // `timestamp,side,price,quantity` rows in sampling order
+    pub fn export_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for cell in self.cells() {
+            writer.serialize(CsvCell {
+                timestamp: cell.timestamp,
+                side: match cell.side {
+                    Side::Buy => "bid",
+                    Side::Sell => "ask",
+                },
+                price: cell.price,
+                quantity: cell.quantity,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use crate::orderbookv2::{Order, OrderType, Price, Quantity};
+
+    #[test]
+    fn test_engine_samples_are_bucketed() {
+        let mut book = orderbookv2::OrderBook::new();
+        for (order_id, price, side) in [
+            (1, 99, Side::Buy),
+            (2, 98, Side::Buy),
+            (3, 95, Side::Buy),
+            (4, 101, Side::Sell),
+            (5, 104, Side::Sell),
+        ] {
+            book.add_order(Order::new(
+                order_id,
+                Price(price),
+                Quantity(10),
+                OrderType::GoodToCancel,
+                side,
+            ));
+        }
+
+        let mut recorder = HeatmapRecorder::new(Duration::from_secs(1), 2, 5);
+        assert!(recorder.sample_engine(&book));
+        // Within the interval
+        book.advance_clock(500_000_000);
+        assert!(!recorder.sample_engine(&book));
+        book.advance_clock(1_000_000_000);
+        book.cancel_order(2).unwrap();
+        assert!(recorder.sample_engine(&book));
+
+        assert_eq!(recorder.sample_count(), 2);
+        let cells: Vec<(Timestamp, Side, i64, u64)> = recorder
+            .cells()
+            .map(|cell| (cell.timestamp, cell.side, cell.price, cell.quantity))
+            .collect();
+        assert_eq!(
+            cells,
+            vec![
+                (0, Side::Buy, 95, 20),
+                (0, Side::Sell, 100, 20),
+                (1_000_000_000, Side::Buy, 95, 20),
+                (1_000_000_000, Side::Sell, 100, 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_l2_samples_and_csv() {
+        let mut book = orderbook::OrderBook::new("BNBUSDT");
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(25.0, 1.0)],
+            asks: vec![(25.1, 2.0)],
+        });
+        let mut recorder = HeatmapRecorder::new(Duration::from_millis(100), 10, 1);
+        assert!(recorder.sample_l2(7, &book));
+
+        let mut csv = Vec::new();
+        recorder.export_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "timestamp,side,price,quantity\n7,bid,250000,10000\n7,ask,251000,20000\n"
+        );
+    }
+}
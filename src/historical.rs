@@ -0,0 +1,413 @@
+/// Historical tick data from third party datasets.
+/// A `HistoricalReader` yields the rows of a dataset in time order, `TardisCsvReader` reads the
+/// Tardis.dev CSV exports: `trades` files and `incremental_book_L2` files, told apart by their
+/// header. `replay` rebuilds the book of one symbol from the book rows the way
+/// `recording::replay` does from a recorded session, rows sharing a timestamp are applied as
+/// one depth update and the first row of a snapshot clears the book. The exports are gzipped,
+/// they have to be decompressed before reading.
+use crate::binance_payloads::DepthUpdate;
+use crate::clock::Stamp;
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::Side;
+use crate::recording::ReplayStatistics;
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoricalTrade {
+    pub exchange: String,
+    pub symbol: String,
+    // Microseconds since the Unix epoch, as stamped by the exchange
+    pub timestamp: u64,
+    // Microseconds since the Unix epoch, as stamped by the collector
+    pub local_timestamp: Option<u64>,
+    pub id: Option<String>,
+    // Side of the aggressor, None when the exchange does not publish it
+    pub side: Option<Side>,
+    pub price: f64,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookRow {
+    pub exchange: String,
+    pub symbol: String,
+    pub timestamp: u64,
+    pub local_timestamp: Option<u64>,
+    // Row of a full book snapshot, otherwise an incremental change
+    pub is_snapshot: bool,
+    pub side: Side,
+    pub price: f64,
+    // New quantity of the level, zero removes it
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoricalEvent {
+    Trade(HistoricalTrade),
+    Book(BookRow),
+}
+
+impl HistoricalEvent {
+    pub fn symbol(&self) -> &str {
+        match self {
+            HistoricalEvent::Trade(trade) => &trade.symbol,
+            HistoricalEvent::Book(row) => &row.symbol,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            HistoricalEvent::Trade(trade) => trade.timestamp,
+            HistoricalEvent::Book(row) => row.timestamp,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoricalError {
+    Io(io::Error),
+    Csv(csv::Error),
+    // The header does not match a known export, holds the header
+    UnknownFormat(String),
+    InvalidSide { line: u64, side: String },
+}
+
+impl fmt::Display for HistoricalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoricalError::Io(error) => write!(f, "{}", error),
+            HistoricalError::Csv(error) => write!(f, "{}", error),
+            HistoricalError::UnknownFormat(header) => {
+                write!(f, "unknown dataset header {}", header)
+            }
+            HistoricalError::InvalidSide { line, side } => {
+                write!(f, "invalid side {} on line {}", side, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistoricalError {}
+
+impl From<io::Error> for HistoricalError {
+    fn from(error: io::Error) -> Self {
+        HistoricalError::Io(error)
+    }
+}
+
+impl From<csv::Error> for HistoricalError {
+    fn from(error: csv::Error) -> Self {
+        HistoricalError::Csv(error)
+    }
+}
+
+// Source of historical rows, one implementation per dataset format
+pub trait HistoricalReader {
+    // Next row in time order, None at the end of the dataset
+    fn next_event(&mut self) -> Option<Result<HistoricalEvent, HistoricalError>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TardisDataType {
+    Trades,
+    IncrementalBookL2,
+}
+
+const REQUIRED_COLUMNS: [&str; 6] = ["exchange", "symbol", "timestamp", "side", "price", "amount"];
+
+// Columns of both exports, the ones missing from a file are None
+#[derive(Deserialize)]
+struct TardisRow {
+    exchange: String,
+    symbol: String,
+    timestamp: u64,
+    local_timestamp: Option<u64>,
+    is_snapshot: Option<bool>,
+    id: Option<String>,
+    side: String,
+    price: f64,
+    amount: f64,
+}
+
+#[derive(Debug)]
+pub struct TardisCsvReader<R> {
+    reader: csv::Reader<R>,
+    headers: csv::StringRecord,
+    data_type: TardisDataType,
+}
+
+impl TardisCsvReader<File> {
+    pub fn open(path: impl AsRef<Path>) -> Result<TardisCsvReader<File>, HistoricalError> {
+        TardisCsvReader::new(File::open(path)?)
+    }
+}
+
+impl<R: Read> TardisCsvReader<R> {
+    pub fn new(reader: R) -> Result<TardisCsvReader<R>, HistoricalError> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let has = |column: &str| headers.iter().any(|header| header == column);
+        if !REQUIRED_COLUMNS.iter().all(|column| has(column)) {
+            return Err(HistoricalError::UnknownFormat(
+                headers.iter().collect::<Vec<_>>().join(","),
+            ));
+        }
+        let data_type = if has("is_snapshot") {
+            TardisDataType::IncrementalBookL2
+        } else {
+            TardisDataType::Trades
+        };
+        Ok(TardisCsvReader {
+            reader,
+            headers,
+            data_type,
+        })
+    }
+
+    pub fn data_type(&self) -> TardisDataType {
+        self.data_type
+    }
+
+    fn parse(&self, record: &csv::StringRecord) -> Result<HistoricalEvent, HistoricalError> {
+        let row: TardisRow = record.deserialize(Some(&self.headers))?;
+        let line = record.position().map_or(0, |position| position.line());
+        let invalid_side = || HistoricalError::InvalidSide {
+            line,
+            side: row.side.clone(),
+        };
+        Ok(match self.data_type {
+            TardisDataType::Trades => HistoricalEvent::Trade(HistoricalTrade {
+                side: match row.side.as_str() {
+                    "buy" => Some(Side::Buy),
+                    "sell" => Some(Side::Sell),
+                    "unknown" | "" => None,
+                    _ => return Err(invalid_side()),
+                },
+                exchange: row.exchange,
+                symbol: row.symbol,
+                timestamp: row.timestamp,
+                local_timestamp: row.local_timestamp,
+                id: row.id,
+                price: row.price,
+                amount: row.amount,
+            }),
+            TardisDataType::IncrementalBookL2 => HistoricalEvent::Book(BookRow {
+                side: match row.side.as_str() {
+                    "bid" => Side::Buy,
+                    "ask" => Side::Sell,
+                    _ => return Err(invalid_side()),
+                },
+                exchange: row.exchange,
+                symbol: row.symbol,
+                timestamp: row.timestamp,
+                local_timestamp: row.local_timestamp,
+                is_snapshot: row.is_snapshot.unwrap_or(false),
+                price: row.price,
+                amount: row.amount,
+            }),
+        })
+    }
+}
+
+impl<R: Read> HistoricalReader for TardisCsvReader<R> {
+    fn next_event(&mut self) -> Option<Result<HistoricalEvent, HistoricalError>> {
+        let mut record = csv::StringRecord::new();
+        match self.reader.read_record(&mut record) {
+            Ok(true) => Some(self.parse(&record)),
+            Ok(false) => None,
+            Err(error) => Some(Err(error.into())),
+        }
+    }
+}
+
+// Book rows of one timestamp, applied together
+struct PendingUpdate {
+    timestamp: u64,
+    local_timestamp: Option<u64>,
+    is_snapshot: bool,
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+// Builds the book of `symbol` from the rows of a dataset. The update ids of the book count the
+// applied updates, the statistics count every row as a payload and the rows of other symbols
+// as unrecognized.
+pub fn replay(
+    symbol: impl Into<Symbol>,
+    reader: &mut impl HistoricalReader,
+) -> Result<(OrderBook, ReplayStatistics), HistoricalError> {
+    replay_with(symbol, reader, |_, _| {})
+}
+
+// Same as `replay`, `observe` sees the book after every update
+pub fn replay_with(
+    symbol: impl Into<Symbol>,
+    reader: &mut impl HistoricalReader,
+    mut observe: impl FnMut(Stamp, &OrderBook),
+) -> Result<(OrderBook, ReplayStatistics), HistoricalError> {
+    let mut orderbook = OrderBook::new(symbol);
+    let mut statistics = ReplayStatistics::default();
+    let mut pending: Option<PendingUpdate> = None;
+    let mut update_id = 0;
+
+    while let Some(event) = reader.next_event() {
+        let event = event?;
+        statistics.payloads += 1;
+        if !event
+            .symbol()
+            .eq_ignore_ascii_case(orderbook.symbol().as_str())
+        {
+            statistics.unrecognized += 1;
+            continue;
+        }
+        let row = match event {
+            HistoricalEvent::Trade(_) => {
+                statistics.trades += 1;
+                continue;
+            }
+            HistoricalEvent::Book(row) => row,
+        };
+
+        let same_update = pending.as_ref().is_some_and(|pending| {
+            pending.timestamp == row.timestamp && pending.is_snapshot == row.is_snapshot
+        });
+        if !same_update {
+            if let Some(update) = pending.take() {
+                update_id += 1;
+                let received = apply(&mut orderbook, &mut statistics, update, update_id);
+                observe(received, &orderbook);
+            }
+        }
+        let update = pending.get_or_insert_with(|| PendingUpdate {
+            timestamp: row.timestamp,
+            local_timestamp: row.local_timestamp,
+            is_snapshot: row.is_snapshot,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        });
+        match row.side {
+            Side::Buy => update.bids.push((row.price, row.amount)),
+            Side::Sell => update.asks.push((row.price, row.amount)),
+        }
+    }
+    if let Some(update) = pending {
+        let received = apply(&mut orderbook, &mut statistics, update, update_id + 1);
+        observe(received, &orderbook);
+    }
+
+    Ok((orderbook, statistics))
+}
+
+fn apply(
+    orderbook: &mut OrderBook,
+    statistics: &mut ReplayStatistics,
+    update: PendingUpdate,
+    update_id: u64,
+) -> Stamp {
+    let depth_update = DepthUpdate {
+        event_time: Some(update.timestamp / 1_000),
+        last_update_id: update_id,
+        bids: update.bids,
+        asks: update.asks,
+    };
+    if update.is_snapshot {
+        orderbook.replace_depth(&depth_update);
+    } else {
+        orderbook.update_depth(&depth_update);
+    }
+    let nanos = update.local_timestamp.unwrap_or(update.timestamp) * 1_000;
+    let received = Stamp {
+        monotonic: nanos,
+        wall: nanos,
+    };
+    statistics.sample(orderbook, received);
+    received
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latency::LatencyStage;
+
+    const BOOK: &str = "\
+exchange,symbol,timestamp,local_timestamp,is_snapshot,side,price,amount
+binance,ETHUSDC,1000000,1002000,true,bid,99.0,2.0
+binance,ETHUSDC,1000000,1002000,true,ask,101.0,1.0
+binance,BTCUSDT,1000500,1001000,true,ask,50000.0,1.0
+binance,ETHUSDC,1001000,1004000,false,bid,100.0,3.0
+binance,ETHUSDC,1001000,1004000,false,ask,101.0,0
+binance,ETHUSDC,1001000,1004000,false,ask,102.0,1.0
+binance,ETHUSDC,1002000,1003000,true,bid,98.0,1.0
+binance,ETHUSDC,1002000,1003000,true,ask,99.0,1.0
+";
+
+    #[test]
+    fn test_replay_incremental_book() {
+        let mut reader = TardisCsvReader::new(BOOK.as_bytes()).unwrap();
+        assert_eq!(reader.data_type(), TardisDataType::IncrementalBookL2);
+
+        let (orderbook, statistics) = replay("ETHUSDC", &mut reader).unwrap();
+        assert_eq!(statistics.payloads, 8);
+        assert_eq!(statistics.unrecognized, 1);
+        let spread = statistics.spread.unwrap();
+        assert_eq!((spread.count, spread.min, spread.max), (3, 1.0, 2.0));
+        let network = statistics
+            .latency
+            .statistics(LatencyStage::Network)
+            .unwrap();
+        assert_eq!((network.min, network.max), (1_000_000, 3_000_000));
+
+        // The second snapshot replaced the whole book
+        let snapshot = orderbook.snapshot(5);
+        assert_eq!(snapshot.last_update_id, 3);
+        assert_eq!((snapshot.bids.len(), snapshot.asks.len()), (1, 1));
+        assert_eq!(orderbook.spread(), Some(1.0));
+    }
+
+    #[test]
+    fn test_read_trades() {
+        let trades = "\
+exchange,symbol,timestamp,local_timestamp,id,side,price,amount
+binance,ETHUSDC,1000000,1001000,42,buy,100.5,0.25
+binance,ETHUSDC,1000100,1001100,43,unknown,100.4,1
+";
+        let mut reader = TardisCsvReader::new(trades.as_bytes()).unwrap();
+        assert_eq!(reader.data_type(), TardisDataType::Trades);
+        let Some(Ok(HistoricalEvent::Trade(trade))) = reader.next_event() else {
+            panic!("expected a trade");
+        };
+        assert_eq!(trade.id.as_deref(), Some("42"));
+        assert_eq!(
+            (trade.side, trade.price, trade.amount),
+            (Some(Side::Buy), 100.5, 0.25)
+        );
+        assert!(matches!(reader.next_event(), Some(Ok(event)) if event.timestamp() == 1000100));
+        assert!(reader.next_event().is_none());
+
+        let (orderbook, statistics) = replay(
+            "ETHUSDC",
+            &mut TardisCsvReader::new(trades.as_bytes()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(statistics.trades, 2);
+        assert!(orderbook.best_bid().is_none());
+
+        assert!(matches!(
+            TardisCsvReader::new("symbol,price\nETHUSDC,1".as_bytes()),
+            Err(HistoricalError::UnknownFormat(_))
+        ));
+        let mut invalid = TardisCsvReader::new(
+            "exchange,symbol,timestamp,side,price,amount\nbinance,ETHUSDC,1,short,1,1".as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            invalid.next_event(),
+            Some(Err(HistoricalError::InvalidSide { line: 2, .. }))
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+/// Exchange order id allocation and the client order id mapping.
+/// Allocated ids increase monotonically and skip past any id a caller supplied on its own, so
+/// engine assigned and caller chosen ids never collide.
+use crate::orderbookv2::OrderId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderIdAllocator {
+    next: OrderId,
+}
+
+impl Default for OrderIdAllocator {
+    fn default() -> Self {
+        OrderIdAllocator { next: 1 }
+    }
+}
+
+impl OrderIdAllocator {
+    pub fn new() -> OrderIdAllocator {
+        OrderIdAllocator::default()
+    }
+
+    pub fn allocate(&mut self) -> OrderId {
+        let order_id = self.next;
+        self.next += 1;
+        order_id
+    }
+
+    // Records an id chosen outside the allocator
+    pub fn observe(&mut self, order_id: OrderId) {
+        self.next = self.next.max(order_id + 1);
+    }
+}
+
+// Client order ids stay mapped after the order left the book so fills and cancels can still
+// be looked up, which also keeps them unique for the lifetime of the engine
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientOrderIds {
+    by_client: HashMap<String, OrderId>,
+    by_order: HashMap<OrderId, String>,
+}
+
+impl ClientOrderIds {
+    pub fn new() -> ClientOrderIds {
+        ClientOrderIds::default()
+    }
+
+    pub fn contains(&self, client_order_id: &str) -> bool {
+        self.by_client.contains_key(client_order_id)
+    }
+
+    pub fn insert(&mut self, client_order_id: &str, order_id: OrderId) {
+        self.by_client.insert(client_order_id.to_string(), order_id);
+        self.by_order.insert(order_id, client_order_id.to_string());
+    }
+
+    pub fn order_id(&self, client_order_id: &str) -> Option<OrderId> {
+        self.by_client.get(client_order_id).copied()
+    }
+
+    pub fn client_order_id(&self, order_id: OrderId) -> Option<&str> {
+        self.by_order.get(&order_id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{
+        NewOrder, Order, OrderBook, OrderType, Price, Quantity, Rejected, Side,
+    };
+
+    fn request(client_order_id: &str, price: i32, side: Side) -> NewOrder {
+        NewOrder {
+            client_order_id: client_order_id.to_string(),
+            account_id: 0,
+            price: Price(price),
+            quantity: Quantity(10),
+            order_type: OrderType::GoodToCancel,
+            side,
+        }
+    }
+
+    #[test]
+    fn test_allocator_skips_observed_ids() {
+        let mut allocator = OrderIdAllocator::new();
+        assert_eq!(allocator.allocate(), 1);
+        allocator.observe(10);
+        allocator.observe(4);
+        assert_eq!(allocator.allocate(), 11);
+        assert_eq!(allocator.allocate(), 12);
+    }
+
+    #[test]
+    fn test_engine_assigns_ids_and_maps_client_ids() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            5,
+            Price(90),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        let ack = orderbook
+            .add_client_order(request("bid-1", 100, Side::Buy))
+            .unwrap();
+        assert_eq!(ack.order_id, 6);
+        assert!(ack.trades.is_empty());
+
+        let ack = orderbook
+            .add_client_order(request("ask-1", 100, Side::Sell))
+            .unwrap();
+        assert_eq!(ack.order_id, 7);
+        assert_eq!(ack.trades[0].bid_trade.order_id, 6);
+
+        assert_eq!(orderbook.order_id_for("bid-1"), Some(6));
+        assert_eq!(orderbook.client_order_id(7), Some("ask-1"));
+        assert_eq!(orderbook.order_id_for("missing"), None);
+    }
+
+    #[test]
+    fn test_duplicate_client_order_id_is_rejected() {
+        let mut orderbook = OrderBook::new();
+        orderbook
+            .add_client_order(request("abc", 100, Side::Buy))
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .add_client_order(request("abc", 101, Side::Buy))
+                .unwrap_err(),
+            Rejected::DuplicateClientOrderId("abc".to_string())
+        );
+        assert_eq!(orderbook.orderbook_size(), 1);
+    }
+}
@@ -0,0 +1,575 @@
+/// Instrument reference data: what is traded, on which tick and lot grid and whether it is
+/// trading at all. The `InstrumentRegistry` is loaded from the Binance exchangeInfo response or
+/// from a local TOML file of `[[instrument]]` tables, e.g.
+///
+///   [[instrument]]
+///   symbol = "BTCUSDT"
+///   base = "BTC"
+///   quote = "USDT"
+///   tick_size = 0.01
+///   lot_size = 0.00001
+///   contract_type = "spot"  # spot, perpetual or option
+///   status = "trading"
+///
+/// Books take their `PriceConverter` from the instrument and the matching engine rejects
+/// orders off its grid, both in units of `Instrument::converter`.
+/// Binance European options are named `<underlying>-<YYMMDD>-<strike>-<C|P>`, e.g.
+/// BTC-240628-60000-C, and `OptionContract` is read straight from that name. Expiries are kept
+/// as the YYMMDD number of the symbol, which sorts chronologically.
+use crate::price_converter::{PriceConverter, MAX_SCALE};
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fs, io};
+
+#[derive(Debug)]
+pub enum InstrumentError {
+    InvalidOptionSymbol(String),
+    // Tick or lot size that is not positive or needs more decimals than a converter has
+    InvalidIncrement {
+        symbol: String,
+        increment: f64,
+    },
+    UnknownStatus(String),
+    UnknownContractType(String),
+    MissingFilter {
+        symbol: String,
+        filter: &'static str,
+    },
+    ExchangeInfo(serde_json::Error),
+    Toml(toml::de::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for InstrumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrumentError::InvalidOptionSymbol(symbol) => write!(
+                f,
+                "invalid option symbol {:?}, expected <underlying>-<YYMMDD>-<strike>-<C|P>",
+                symbol
+            ),
+            InstrumentError::InvalidIncrement { symbol, increment } => {
+                write!(f, "invalid increment {} for {}", increment, symbol)
+            }
+            InstrumentError::UnknownStatus(status) => {
+                write!(f, "unknown instrument status {:?}", status)
+            }
+            InstrumentError::UnknownContractType(contract_type) => {
+                write!(f, "unknown contract type {:?}", contract_type)
+            }
+            InstrumentError::MissingFilter { symbol, filter } => {
+                write!(f, "{} has no {} filter", symbol, filter)
+            }
+            InstrumentError::ExchangeInfo(error) => write!(f, "invalid exchangeInfo: {}", error),
+            InstrumentError::Toml(error) => write!(f, "invalid instrument file: {}", error),
+            InstrumentError::Io(error) => write!(f, "cannot read instrument file: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for InstrumentError {}
+
+impl From<io::Error> for InstrumentError {
+    fn from(error: io::Error) -> Self {
+        InstrumentError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionRight {
+    Call,
+    Put,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionContract {
+    pub underlying: String,
+    // YYMMDD
+    pub expiry: u32,
+    pub strike: f64,
+    pub right: OptionRight,
+}
+
+impl FromStr for OptionContract {
+    type Err = InstrumentError;
+
+    fn from_str(symbol: &str) -> Result<OptionContract, InstrumentError> {
+        let invalid = || InstrumentError::InvalidOptionSymbol(symbol.to_string());
+        let parts: Vec<&str> = symbol.split('-').collect();
+        let [underlying, expiry, strike, right] = parts[..] else {
+            return Err(invalid());
+        };
+        if underlying.is_empty() || expiry.len() != 6 {
+            return Err(invalid());
+        }
+        let expiry = expiry.parse().map_err(|_| invalid())?;
+        let strike = strike
+            .parse::<f64>()
+            .ok()
+            .filter(|strike| strike.is_finite() && *strike > 0.0)
+            .ok_or_else(invalid)?;
+        let right = match right {
+            "C" => OptionRight::Call,
+            "P" => OptionRight::Put,
+            _ => return Err(invalid()),
+        };
+        Ok(OptionContract {
+            underlying: underlying.to_string(),
+            expiry,
+            strike,
+            right,
+        })
+    }
+}
+
+impl fmt::Display for OptionContract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let right = match self.right {
+            OptionRight::Call => "C",
+            OptionRight::Put => "P",
+        };
+        write!(
+            f,
+            "{}-{:06}-{}-{}",
+            self.underlying, self.expiry, self.strike, right
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractType {
+    Spot,
+    Perpetual,
+    Option(OptionContract),
+}
+
+impl ContractType {
+    pub fn option(&self) -> Option<&OptionContract> {
+        match self {
+            ContractType::Option(contract) => Some(contract),
+            _ => None,
+        }
+    }
+}
+
+// Binance symbol statuses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstrumentStatus {
+    PreTrading,
+    Trading,
+    PostTrading,
+    EndOfDay,
+    Halt,
+    AuctionMatch,
+    Break,
+}
+
+impl FromStr for InstrumentStatus {
+    type Err = InstrumentError;
+
+    // Accepts the exchangeInfo spelling (`TRADING`) and the lowercase one of the TOML files
+    fn from_str(status: &str) -> Result<InstrumentStatus, InstrumentError> {
+        match status.to_ascii_uppercase().as_str() {
+            "PRE_TRADING" => Ok(InstrumentStatus::PreTrading),
+            "TRADING" => Ok(InstrumentStatus::Trading),
+            "POST_TRADING" => Ok(InstrumentStatus::PostTrading),
+            "END_OF_DAY" => Ok(InstrumentStatus::EndOfDay),
+            "HALT" => Ok(InstrumentStatus::Halt),
+            "AUCTION_MATCH" => Ok(InstrumentStatus::AuctionMatch),
+            "BREAK" => Ok(InstrumentStatus::Break),
+            _ => Err(InstrumentError::UnknownStatus(status.to_string())),
+        }
+    }
+}
+
+// Why an order does not fit the instrument, in units of `Instrument::converter`
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstrumentViolation {
+    NotTrading {
+        symbol: Symbol,
+        status: InstrumentStatus,
+    },
+    OffTick {
+        price: i64,
+        tick_size: u64,
+    },
+    OffLot {
+        quantity: u64,
+        lot_size: u64,
+    },
+}
+
+impl fmt::Display for InstrumentViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrumentViolation::NotTrading { symbol, status } => {
+                write!(f, "{} is not trading ({:?})", symbol, status)
+            }
+            InstrumentViolation::OffTick { price, tick_size } => {
+                write!(
+                    f,
+                    "price {} is not a multiple of the tick size {}",
+                    price, tick_size
+                )
+            }
+            InstrumentViolation::OffLot { quantity, lot_size } => write!(
+                f,
+                "quantity {} is not a multiple of the lot size {}",
+                quantity, lot_size
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub symbol: Symbol,
+    pub base: String,
+    pub quote: String,
+    // Price and quantity increments, as decimals
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub contract_type: ContractType,
+    pub status: InstrumentStatus,
+}
+
+impl Instrument {
+    pub fn is_trading(&self) -> bool {
+        self.status == InstrumentStatus::Trading
+    }
+
+    // Converter with just enough decimals for both the tick and the lot size
+    pub fn converter(&self) -> PriceConverter {
+        PriceConverter::new(decimals(self.tick_size).max(decimals(self.lot_size)))
+    }
+
+    pub fn tick_size_units(&self) -> u64 {
+        self.converter()
+            .to_units(self.tick_size)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn lot_size_units(&self) -> u64 {
+        self.converter().to_units(self.lot_size).unwrap_or(1).max(1)
+    }
+
+    // Price and quantity in units of `converter`
+    pub fn validate(&self, price: i64, quantity: u64) -> Result<(), InstrumentViolation> {
+        if !self.is_trading() {
+            return Err(InstrumentViolation::NotTrading {
+                symbol: self.symbol,
+                status: self.status,
+            });
+        }
+        self.check_grid(price, quantity)
+    }
+
+    // Same as `validate` without the status
+    pub fn check_grid(&self, price: i64, quantity: u64) -> Result<(), InstrumentViolation> {
+        let tick_size = self.tick_size_units();
+        if price % tick_size as i64 != 0 {
+            return Err(InstrumentViolation::OffTick { price, tick_size });
+        }
+        let lot_size = self.lot_size_units();
+        if quantity % lot_size != 0 {
+            return Err(InstrumentViolation::OffLot { quantity, lot_size });
+        }
+        Ok(())
+    }
+
+    fn check_increments(&self) -> Result<(), InstrumentError> {
+        for increment in [self.tick_size, self.lot_size] {
+            if !(increment.is_finite() && increment > 0.0) || decimals(increment) > MAX_SCALE {
+                return Err(InstrumentError::InvalidIncrement {
+                    symbol: self.symbol.to_string(),
+                    increment,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Digits after the decimal point of the shortest representation, 0.00001 has 5
+fn decimals(value: f64) -> u32 {
+    let formatted = format!("{}", value);
+    match formatted.split_once('.') {
+        Some((_, fraction)) => fraction.len() as u32,
+        None => 0,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<ExchangeInfoSymbol>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExchangeInfoSymbol {
+    symbol: String,
+    status: String,
+    base_asset: String,
+    quote_asset: String,
+    // Only on the futures endpoints
+    #[serde(default)]
+    contract_type: Option<String>,
+    filters: Vec<serde_json::Value>,
+}
+
+impl ExchangeInfoSymbol {
+    fn filter(&self, filter_type: &'static str, field: &str) -> Result<f64, InstrumentError> {
+        self.filters
+            .iter()
+            .find(|filter| filter["filterType"] == filter_type)
+            .and_then(|filter| filter[field].as_str())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| InstrumentError::MissingFilter {
+                symbol: self.symbol.clone(),
+                filter: filter_type,
+            })
+    }
+
+    fn to_instrument(&self) -> Result<Instrument, InstrumentError> {
+        let contract_type = match self.contract_type.as_deref() {
+            None => ContractType::Spot,
+            Some("PERPETUAL") => ContractType::Perpetual,
+            Some(other) => return Err(InstrumentError::UnknownContractType(other.to_string())),
+        };
+        Ok(Instrument {
+            symbol: Symbol::intern(&self.symbol),
+            base: self.base_asset.clone(),
+            quote: self.quote_asset.clone(),
+            tick_size: self.filter("PRICE_FILTER", "tickSize")?,
+            lot_size: self.filter("LOT_SIZE", "stepSize")?,
+            contract_type,
+            status: self.status.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InstrumentFile {
+    #[serde(default)]
+    instrument: Vec<InstrumentEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstrumentEntry {
+    symbol: String,
+    base: String,
+    quote: String,
+    tick_size: f64,
+    lot_size: f64,
+    contract_type: String,
+    #[serde(default = "default_status")]
+    status: String,
+}
+
+fn default_status() -> String {
+    "trading".to_string()
+}
+
+impl InstrumentEntry {
+    fn to_instrument(&self) -> Result<Instrument, InstrumentError> {
+        let contract_type = match self.contract_type.to_ascii_lowercase().as_str() {
+            "spot" => ContractType::Spot,
+            "perpetual" => ContractType::Perpetual,
+            // Strike, expiry and right come from the symbol
+            "option" => ContractType::Option(self.symbol.parse()?),
+            _ => {
+                return Err(InstrumentError::UnknownContractType(
+                    self.contract_type.clone(),
+                ))
+            }
+        };
+        Ok(Instrument {
+            symbol: Symbol::intern(&self.symbol),
+            base: self.base.clone(),
+            quote: self.quote.clone(),
+            tick_size: self.tick_size,
+            lot_size: self.lot_size,
+            contract_type,
+            status: self.status.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments: HashMap<Symbol, Instrument>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> InstrumentRegistry {
+        InstrumentRegistry::default()
+    }
+
+    // Symbols with filters this registry does not understand fail the whole load
+    pub fn from_exchange_info(payload: &[u8]) -> Result<InstrumentRegistry, InstrumentError> {
+        let info: ExchangeInfo =
+            serde_json::from_slice(payload).map_err(InstrumentError::ExchangeInfo)?;
+        let mut registry = InstrumentRegistry::new();
+        for symbol in &info.symbols {
+            registry.insert(symbol.to_instrument()?)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<InstrumentRegistry, InstrumentError> {
+        let file: InstrumentFile = toml::from_str(contents).map_err(InstrumentError::Toml)?;
+        let mut registry = InstrumentRegistry::new();
+        for entry in &file.instrument {
+            registry.insert(entry.to_instrument()?)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn load_toml(path: impl AsRef<Path>) -> Result<InstrumentRegistry, InstrumentError> {
+        InstrumentRegistry::from_toml(&fs::read_to_string(path)?)
+    }
+
+    // Replaces the instrument of the same symbol, e.g. on a status change
+    pub fn insert(&mut self, instrument: Instrument) -> Result<(), InstrumentError> {
+        instrument.check_increments()?;
+        self.instruments.insert(instrument.symbol, instrument);
+        Ok(())
+    }
+
+    pub fn get(&self, symbol: impl Into<Symbol>) -> Option<&Instrument> {
+        self.instruments.get(&symbol.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instruments.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_symbols() {
+        let contract: OptionContract = "BTC-240628-60000-C".parse().unwrap();
+        assert_eq!(contract.underlying, "BTC");
+        assert_eq!(contract.expiry, 240628);
+        assert_eq!(contract.strike, 60000.0);
+        assert_eq!(contract.right, OptionRight::Call);
+        assert_eq!(contract.to_string(), "BTC-240628-60000-C");
+
+        let contract: OptionContract = "DOGE-250103-0.35-P".parse().unwrap();
+        assert_eq!(contract.strike, 0.35);
+        assert_eq!(contract.right, OptionRight::Put);
+
+        for symbol in [
+            "BTCUSDT",
+            "BTC-2406-60000-C",
+            "BTC-240628-0-C",
+            "BTC-240628-1-X",
+        ] {
+            assert!(symbol.parse::<OptionContract>().is_err(), "{}", symbol);
+        }
+    }
+
+    #[test]
+    fn test_registry_from_exchange_info() {
+        let payload = br#"{"timezone":"UTC","symbols":[
+            {"symbol":"BTCUSDT","status":"TRADING","baseAsset":"BTC","quoteAsset":"USDT","filters":[
+                {"filterType":"PRICE_FILTER","minPrice":"0.01","maxPrice":"1000000.00","tickSize":"0.01"},
+                {"filterType":"LOT_SIZE","minQty":"0.00001","maxQty":"9000.00","stepSize":"0.00001"}]},
+            {"symbol":"ETHUSDT","status":"HALT","baseAsset":"ETH","quoteAsset":"USDT","contractType":"PERPETUAL","filters":[
+                {"filterType":"PRICE_FILTER","tickSize":"0.10"},
+                {"filterType":"LOT_SIZE","stepSize":"0.001"}]}]}"#;
+        let registry = InstrumentRegistry::from_exchange_info(payload).unwrap();
+        assert_eq!(registry.len(), 2);
+
+        let btc = registry.get("BTCUSDT").unwrap();
+        assert_eq!(btc.base, "BTC");
+        assert_eq!(btc.contract_type, ContractType::Spot);
+        assert_eq!(btc.converter().scale(), 5);
+        assert_eq!(btc.tick_size_units(), 1_000);
+        assert_eq!(btc.validate(6_000_001_000, 3), Ok(()));
+        assert_eq!(
+            btc.validate(6_000_000_500, 3),
+            Err(InstrumentViolation::OffTick {
+                price: 6_000_000_500,
+                tick_size: 1_000
+            })
+        );
+
+        let eth = registry.get("ETHUSDT").unwrap();
+        assert_eq!(eth.contract_type, ContractType::Perpetual);
+        assert_eq!(eth.lot_size_units(), 1);
+        assert!(matches!(
+            eth.validate(100, 1),
+            Err(InstrumentViolation::NotTrading { .. })
+        ));
+
+        let missing_lot = br#"{"symbols":[{"symbol":"X","status":"TRADING","baseAsset":"X","quoteAsset":"Y","filters":[{"filterType":"PRICE_FILTER","tickSize":"1"}]}]}"#;
+        assert!(matches!(
+            InstrumentRegistry::from_exchange_info(missing_lot),
+            Err(InstrumentError::MissingFilter {
+                filter: "LOT_SIZE",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_registry_from_toml() {
+        let contents = r#"
+            [[instrument]]
+            symbol = "BNBUSDT"
+            base = "BNB"
+            quote = "USDT"
+            tick_size = 0.01
+            lot_size = 0.5
+            contract_type = "spot"
+
+            [[instrument]]
+            symbol = "BTC-240628-60000-C"
+            base = "BTC"
+            quote = "USDT"
+            tick_size = 5.0
+            lot_size = 0.01
+            contract_type = "option"
+            status = "break"
+        "#;
+        let registry = InstrumentRegistry::from_toml(contents).unwrap();
+        let bnb = registry.get("BNBUSDT").unwrap();
+        assert!(bnb.is_trading());
+        assert_eq!(bnb.validate(1_001, 100), Ok(()));
+        assert_eq!(
+            bnb.validate(1_001, 125),
+            Err(InstrumentViolation::OffLot {
+                quantity: 125,
+                lot_size: 50
+            })
+        );
+
+        let call = registry.get("BTC-240628-60000-C").unwrap();
+        assert_eq!(call.status, InstrumentStatus::Break);
+        assert_eq!(call.contract_type.option().unwrap().strike, 60000.0);
+        assert_eq!(call.tick_size_units(), 500);
+
+        let invalid = contents.replace("tick_size = 0.01", "tick_size = 0.0");
+        assert!(matches!(
+            InstrumentRegistry::from_toml(&invalid),
+            Err(InstrumentError::InvalidIncrement { .. })
+        ));
+    }
+}
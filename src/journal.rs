@@ -0,0 +1,260 @@
+/// Write-ahead journal for the matching engine.
+/// Every command is appended as a JSON line before it is applied, a full `EngineState`
+/// snapshot is written every `snapshot_interval` commands. Recovery starts from the last
+/// snapshot and replays the commands after it, compaction drops everything before it.
+/// Only the book is journaled, accounts, fees and risk configuration have to be set up again
+/// after recovery.
+use crate::orderbookv2::{BatchReport, EngineState, OrderBook, OrderCommand};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalEntry {
+    Command(OrderCommand),
+    Snapshot(EngineState),
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    snapshot_interval: Option<usize>,
+    commands_since_snapshot: usize,
+}
+
+impl Journal {
+    // Appends to an existing journal
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Journal> {
+        let path = path.as_ref().to_path_buf();
+        let writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?);
+        Ok(Journal {
+            path,
+            writer,
+            snapshot_interval: None,
+            commands_since_snapshot: 0,
+        })
+    }
+
+    pub fn with_snapshot_interval(mut self, commands: usize) -> Journal {
+        self.snapshot_interval = Some(commands);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Journals the commands, then runs them through the engine
+    pub fn apply(
+        &mut self,
+        orderbook: &mut OrderBook,
+        commands: Vec<OrderCommand>,
+    ) -> io::Result<BatchReport> {
+        for command in &commands {
+            self.append(&JournalEntry::Command(command.clone()))?;
+        }
+        self.writer.flush()?;
+
+        self.commands_since_snapshot += commands.len();
+        let report = orderbook.apply_batch(commands);
+
+        if let Some(interval) = self.snapshot_interval {
+            if self.commands_since_snapshot >= interval {
+                self.write_snapshot(orderbook)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn write_snapshot(&mut self, orderbook: &OrderBook) -> io::Result<()> {
+        self.append(&JournalEntry::Snapshot(orderbook.state()))?;
+        self.writer.flush()?;
+        self.commands_since_snapshot = 0;
+        Ok(())
+    }
+
+    // Ends the journal with a snapshot so recovery does not replay anything, the engine should
+    // not take orders anymore
+    pub fn close(mut self, orderbook: &OrderBook) -> io::Result<()> {
+        self.write_snapshot(orderbook)
+    }
+
+    // Rewrites the journal starting at its last snapshot, no-op without any snapshot
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let entries = read_entries(&self.path)?;
+        let Some(start) = last_snapshot(&entries) else {
+            return Ok(());
+        };
+
+        let compacted = self.path.with_extension("compact");
+        {
+            let mut writer = BufWriter::new(File::create(&compacted)?);
+            for entry in &entries[start..] {
+                write_entry(&mut writer, entry)?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&compacted, &self.path)?;
+
+        self.writer = BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        write_entry(&mut self.writer, entry)
+    }
+}
+
+// Rebuilds the book from the last snapshot and the commands journaled after it
+pub fn recover(path: impl AsRef<Path>) -> io::Result<OrderBook> {
+    let entries = read_entries(path.as_ref())?;
+    let start = last_snapshot(&entries).unwrap_or(0);
+
+    let mut orderbook = OrderBook::new();
+    let mut commands = Vec::new();
+    for entry in entries.into_iter().skip(start) {
+        match entry {
+            JournalEntry::Snapshot(state) => orderbook = OrderBook::from_state(state),
+            JournalEntry::Command(command) => commands.push(command),
+        }
+    }
+    orderbook.apply_batch(commands);
+
+    Ok(orderbook)
+}
+
+fn write_entry(writer: &mut impl Write, entry: &JournalEntry) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, entry)?;
+    writer.write_all(b"\n")
+}
+
+fn read_entries(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+fn last_snapshot(entries: &[JournalEntry]) -> Option<usize> {
+    entries
+        .iter()
+        .rposition(|entry| matches!(entry, JournalEntry::Snapshot(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{Order, OrderModify, OrderType, Price, Quantity, Side};
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "orderbook-journal-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn new_order(order_id: u64, price: i32, quantity: u32, side: Side) -> OrderCommand {
+        OrderCommand::New(Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            OrderType::GoodToCancel,
+            side,
+        ))
+    }
+
+    fn run_session(journal: &mut Journal, orderbook: &mut OrderBook) {
+        for order_id in 1..=10 {
+            let side = if order_id % 2 == 0 {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            let price = 100 + (order_id as i32 % 3) * if side == Side::Buy { -1 } else { 1 };
+            journal
+                .apply(orderbook, vec![new_order(order_id, price, 5, side)])
+                .unwrap();
+        }
+        journal
+            .apply(
+                orderbook,
+                vec![
+                    OrderCommand::Cancel(4),
+                    OrderCommand::Modify(OrderModify::new(3, Side::Sell, Price(99), Quantity(8))),
+                    new_order(11, 103, 2, Side::Sell),
+                ],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_recover_replays_tail_after_snapshot() {
+        let path = journal_path("recover");
+        let mut journal = Journal::open(&path).unwrap().with_snapshot_interval(4);
+        let mut orderbook = OrderBook::new();
+        run_session(&mut journal, &mut orderbook);
+
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|entry| matches!(entry, JournalEntry::Snapshot(_)))
+                .count(),
+            3
+        );
+
+        let recovered = recover(&path).unwrap();
+        assert_eq!(
+            recovered.get_orderbook_level_infos(),
+            orderbook.get_orderbook_level_infos()
+        );
+
+        // Closing leaves nothing to replay
+        journal.close(&orderbook).unwrap();
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(last_snapshot(&entries), Some(entries.len() - 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_keeps_recovery_result() {
+        let path = journal_path("compact");
+        let mut journal = Journal::open(&path).unwrap();
+        let mut orderbook = OrderBook::new();
+        run_session(&mut journal, &mut orderbook);
+
+        // Without a snapshot there is nothing to drop
+        journal.compact().unwrap();
+        assert_eq!(read_entries(&path).unwrap().len(), 13);
+
+        journal.write_snapshot(&orderbook).unwrap();
+        journal
+            .apply(&mut orderbook, vec![new_order(12, 98, 1, Side::Buy)])
+            .unwrap();
+        journal.compact().unwrap();
+
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0], JournalEntry::Snapshot(_)));
+
+        // The journal keeps appending after compaction
+        journal
+            .apply(&mut orderbook, vec![OrderCommand::Cancel(12)])
+            .unwrap();
+        let recovered = recover(&path).unwrap();
+        assert_eq!(
+            recovered.get_orderbook_level_infos(),
+            orderbook.get_orderbook_level_infos()
+        );
+        assert_eq!(recovered.orderbook_size(), orderbook.orderbook_size());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,642 @@
+/// Order-level (L3) market data book.
+/// Venues with order-by-order feeds publish every resting order individually,
+/// here we keep them keyed by order id and derive aggregated L2 levels on demand.
+/// The orders are the only state that is written to, the coarser views are derived from them as
+/// they change: every price level caches its aggregated quantity and the book caches the best
+/// level of each side, so `l1()` and `l2(depth)` never walk the orders. Consumers pick the
+/// granularity they need with `l1()`, `l2(depth)` or `l3()`.
+use crate::clock::{self, SharedClock, Stamp};
+use crate::orderbook::{DepthSnapshot, Price, Quantity};
+use crate::orderbookv2::{OrderId, Side};
+use crate::price_converter::PriceConverter;
+use crate::symbol::Symbol;
+use crate::top_of_book::TopOfBook;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L3Order {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum L3BookError {
+    DuplicateOrderId(OrderId),
+    UnknownOrderId(OrderId),
+}
+
+impl fmt::Display for L3BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            L3BookError::DuplicateOrderId(id) => write!(f, "order {} already exists", id),
+            L3BookError::UnknownOrderId(id) => write!(f, "order {} not found", id),
+        }
+    }
+}
+
+impl std::error::Error for L3BookError {}
+
+// Orders at a single price in time priority, with the aggregated quantity cached
+#[derive(Debug, Default)]
+struct Level {
+    orders: VecDeque<OrderId>,
+    quantity: Quantity,
+}
+
+// Executed volume against resting orders of one side, used to estimate how fast queues drain
+#[derive(Debug, Default)]
+struct TradeFlow {
+    first_timestamp: Option<u64>,
+    last_timestamp: u64,
+    volume: Quantity,
+    count: u64,
+}
+
+impl TradeFlow {
+    fn record(&mut self, quantity: Quantity, timestamp: u64) {
+        self.first_timestamp.get_or_insert(timestamp);
+        self.last_timestamp = timestamp;
+        self.volume += quantity;
+        self.count += 1;
+    }
+
+    // (trades per millisecond, average trade size), None until we observed a time span
+    fn rates(&self) -> Option<(f64, f64)> {
+        let span = self.last_timestamp - self.first_timestamp?;
+        if span == 0 || self.count == 0 {
+            return None;
+        }
+        Some((
+            self.count as f64 / span as f64,
+            self.volume.to_f64() / self.count as f64,
+        ))
+    }
+}
+
+// Every resting order of both sides, best price first and in time priority within a price
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L3Snapshot {
+    pub symbol: Symbol,
+    pub last_update_id: u64,
+    pub bids: Vec<L3Order>,
+    pub asks: Vec<L3Order>,
+}
+
+#[derive(Debug)]
+pub struct L3Book {
+    symbol: Symbol,
+    bids: BTreeMap<Price, Level>,
+    asks: BTreeMap<Price, Level>,
+    orders: HashMap<OrderId, L3Order>,
+    bid_flow: TradeFlow,
+    ask_flow: TradeFlow,
+    last_update_id: u64,
+    clock: SharedClock,
+    last_update: Option<Stamp>,
+    // Best level of each side, kept in line by `refresh_best`
+    best_bid: Option<(Price, Quantity)>,
+    best_ask: Option<(Price, Quantity)>,
+}
+
+impl L3Book {
+    pub fn new(symbol: impl Into<Symbol>) -> L3Book {
+        L3Book {
+            symbol: symbol.into(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            bid_flow: TradeFlow::default(),
+            ask_flow: TradeFlow::default(),
+            last_update_id: 0,
+            clock: clock::system(),
+            last_update: None,
+            best_bid: None,
+            best_ask: None,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    // When an order was last added, modified or removed
+    pub fn last_update(&self) -> Option<Stamp> {
+        self.last_update
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.symbol
+    }
+
+    pub fn set_last_update_id(&mut self, update_id: u64) {
+        self.last_update_id = update_id;
+    }
+
+    pub fn add(
+        &mut self,
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<(), L3BookError> {
+        if self.orders.contains_key(&order_id) {
+            return Err(L3BookError::DuplicateOrderId(order_id));
+        }
+
+        let level = self.levels_mut(side).entry(price).or_default();
+        level.orders.push_back(order_id);
+        level.quantity += quantity;
+        self.refresh_best(side, price);
+
+        self.orders.insert(
+            order_id,
+            L3Order {
+                order_id,
+                side,
+                price,
+                quantity,
+            },
+        );
+        self.last_update = Some(self.clock.stamp());
+        Ok(())
+    }
+
+    // Same semantics as most venues: reducing quantity keeps the queue position,
+    // changing price or increasing quantity sends the order to the back of the queue
+    pub fn modify(
+        &mut self,
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<(), L3BookError> {
+        let order = self
+            .orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(L3BookError::UnknownOrderId(order_id))?;
+
+        if price == order.price && quantity <= order.quantity {
+            let level = self
+                .levels_mut(order.side)
+                .get_mut(&price)
+                .expect("Order level not found | unreachable state");
+            level.quantity -= order.quantity - quantity;
+            self.refresh_best(order.side, price);
+            self.orders.get_mut(&order_id).unwrap().quantity = quantity;
+            self.last_update = Some(self.clock.stamp());
+            return Ok(());
+        }
+
+        self.delete(order_id)?;
+        self.add(order_id, order.side, price, quantity)
+    }
+
+    pub fn delete(&mut self, order_id: OrderId) -> Result<L3Order, L3BookError> {
+        let order = self
+            .orders
+            .remove(&order_id)
+            .ok_or(L3BookError::UnknownOrderId(order_id))?;
+
+        let levels = self.levels_mut(order.side);
+        if let Some(level) = levels.get_mut(&order.price) {
+            level.orders.retain(|id| *id != order_id);
+            level.quantity -= order.quantity;
+            // Remove the price level if no orders left
+            if level.orders.is_empty() {
+                levels.remove(&order.price);
+            }
+        }
+        self.refresh_best(order.side, order.price);
+        self.last_update = Some(self.clock.stamp());
+        Ok(order)
+    }
+
+    // A trade against a resting order (timestamp in milliseconds), the order leaves the book when fully filled
+    pub fn execute(
+        &mut self,
+        order_id: OrderId,
+        quantity: Quantity,
+        timestamp: u64,
+    ) -> Result<(), L3BookError> {
+        let order = self
+            .orders
+            .get(&order_id)
+            .cloned()
+            .ok_or(L3BookError::UnknownOrderId(order_id))?;
+        let quantity = quantity.min(order.quantity);
+
+        match order.side {
+            Side::Buy => self.bid_flow.record(quantity, timestamp),
+            Side::Sell => self.ask_flow.record(quantity, timestamp),
+        }
+
+        if quantity == order.quantity {
+            self.delete(order_id)?;
+            Ok(())
+        } else {
+            self.modify(order_id, order.price, order.quantity - quantity)
+        }
+    }
+
+    // Quantity resting ahead of the order at its price level
+    pub fn queue_position(&self, order_id: OrderId) -> Option<Quantity> {
+        let order = self.orders.get(&order_id)?;
+        let ahead = self
+            .orders_at(order.side, order.price)
+            .take_while(|o| o.order_id != order_id)
+            .map(|o| o.quantity)
+            .sum();
+        Some(ahead)
+    }
+
+    // Probability that the order gets completely filled within `horizon`.
+    // Trades against the order's side are modelled as a Poisson process with the rate and
+    // average size observed through `execute`, the order is filled once the traded volume
+    // covers everything ahead of it plus its own quantity.
+    // Returns None for unknown orders or before any trade flow has been observed.
+    pub fn estimated_fill_probability(&self, order_id: OrderId, horizon: Duration) -> Option<f64> {
+        let order = self.orders.get(&order_id)?;
+        let flow = match order.side {
+            Side::Buy => &self.bid_flow,
+            Side::Sell => &self.ask_flow,
+        };
+        let (trade_rate, average_size) = flow.rates()?;
+
+        let required = (self.queue_position(order_id)? + order.quantity).to_f64();
+        let trades_needed = (required / average_size).ceil() as u64;
+        let expected_trades = trade_rate * horizon.as_millis() as f64;
+
+        Some(poisson_at_least(expected_trades, trades_needed))
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Option<&L3Order> {
+        self.orders.get(&order_id)
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    // Aggregated price levels in best-first order (highest bid first)
+    pub fn bids(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(price, level)| (*price, level.quantity))
+    }
+
+    // Aggregated price levels in best-first order (lowest ask first)
+    pub fn asks(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.asks
+            .iter()
+            .map(|(price, level)| (*price, level.quantity))
+    }
+
+    // Resting orders at a price level in time priority
+    pub fn orders_at(&self, side: Side, price: Price) -> impl Iterator<Item = &L3Order> + '_ {
+        self.levels(side)
+            .get(&price)
+            .into_iter()
+            .flat_map(|level| level.orders.iter())
+            .map(|order_id| &self.orders[order_id])
+    }
+
+    pub fn best_bid(&self) -> Option<(Price, Quantity)> {
+        self.best_bid
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, Quantity)> {
+        self.best_ask
+    }
+
+    // Derived L1 view, the best level of each side
+    pub fn l1(&self) -> TopOfBook {
+        TopOfBook {
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bid: self.best_bid,
+            ask: self.best_ask,
+        }
+    }
+
+    // Derived L2 view of the top `depth` price levels
+    pub fn l2(&self, depth: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: self.bids().take(depth).collect(),
+            asks: self.asks().take(depth).collect(),
+            // Orders come with prices in the default book units
+            converter: PriceConverter::default(),
+        }
+    }
+
+    // The orders themselves
+    pub fn l3(&self) -> L3Snapshot {
+        let side = |side: Side| -> Vec<L3Order> {
+            let prices: Box<dyn Iterator<Item = &Price>> = match side {
+                Side::Buy => Box::new(self.bids.keys().rev()),
+                Side::Sell => Box::new(self.asks.keys()),
+            };
+            prices
+                .flat_map(|price| self.orders_at(side, *price))
+                .cloned()
+                .collect()
+        };
+        L3Snapshot {
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: side(Side::Buy),
+            asks: side(Side::Sell),
+        }
+    }
+
+    // Same as `l2`, the name the L2 book uses
+    pub fn snapshot(&self, levels: usize) -> DepthSnapshot {
+        self.l2(levels)
+    }
+
+    // The level at `price` changed, only a change at or ahead of the cached best level can move it
+    fn refresh_best(&mut self, side: Side, price: Price) {
+        let cached = match side {
+            Side::Buy => self.best_bid,
+            Side::Sell => self.best_ask,
+        };
+        let moved = match (cached, side) {
+            (None, _) => true,
+            (Some((best, _)), Side::Buy) => price >= best,
+            (Some((best, _)), Side::Sell) => price <= best,
+        };
+        if !moved {
+            return;
+        }
+        match side {
+            Side::Buy => {
+                self.best_bid = self
+                    .bids
+                    .iter()
+                    .next_back()
+                    .map(|(price, level)| (*price, level.quantity))
+            }
+            Side::Sell => {
+                self.best_ask = self
+                    .asks
+                    .iter()
+                    .next()
+                    .map(|(price, level)| (*price, level.quantity))
+            }
+        }
+    }
+
+    fn levels(&self, side: Side) -> &BTreeMap<Price, Level> {
+        match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        }
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<Price, Level> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+}
+
+// P(N >= k) for N ~ Poisson(mean), terms are accumulated in log space to avoid underflow
+fn poisson_at_least(mean: f64, k: u64) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+    if mean <= 0.0 {
+        return 0.0;
+    }
+
+    let mut log_term = -mean;
+    let mut below = log_term.exp();
+    for i in 1..k {
+        log_term += mean.ln() - (i as f64).ln();
+        below += log_term.exp();
+    }
+    (1.0 - below).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order_ids_at(book: &L3Book, side: Side, price: Price) -> Vec<OrderId> {
+        book.orders_at(side, price).map(|o| o.order_id).collect()
+    }
+
+    #[test]
+    fn test_add_orders_and_derive_levels() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Buy, Price(100), Quantity(5)).unwrap();
+        book.add(3, Side::Buy, Price(99), Quantity(7)).unwrap();
+        book.add(4, Side::Sell, Price(101), Quantity(3)).unwrap();
+
+        assert_eq!(book.order_count(), 4);
+        assert_eq!(
+            book.bids().collect::<Vec<_>>(),
+            vec![(Price(100), Quantity(15)), (Price(99), Quantity(7))]
+        );
+        assert_eq!(
+            book.asks().collect::<Vec<_>>(),
+            vec![(Price(101), Quantity(3))]
+        );
+        assert_eq!(order_ids_at(&book, Side::Buy, Price(100)), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_add_duplicate_order_id() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        assert_eq!(
+            book.add(1, Side::Sell, Price(101), Quantity(10)),
+            Err(L3BookError::DuplicateOrderId(1))
+        );
+    }
+
+    #[test]
+    fn test_delete_removes_empty_level() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Sell, Price(101), Quantity(3)).unwrap();
+        let deleted = book.delete(1).unwrap();
+
+        assert_eq!(deleted.quantity, Quantity(3));
+        assert_eq!(book.asks().next(), None);
+        assert_eq!(book.delete(1), Err(L3BookError::UnknownOrderId(1)));
+    }
+
+    #[test]
+    fn test_modify_reduce_keeps_priority() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Buy, Price(100), Quantity(5)).unwrap();
+        book.modify(1, Price(100), Quantity(4)).unwrap();
+
+        assert_eq!(
+            book.bids().collect::<Vec<_>>(),
+            vec![(Price(100), Quantity(9))]
+        );
+        assert_eq!(order_ids_at(&book, Side::Buy, Price(100)), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_modify_increase_or_reprice_loses_priority() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Buy, Price(100), Quantity(5)).unwrap();
+        book.modify(1, Price(100), Quantity(12)).unwrap();
+        assert_eq!(order_ids_at(&book, Side::Buy, Price(100)), vec![2, 1]);
+
+        book.modify(2, Price(98), Quantity(5)).unwrap();
+        assert_eq!(
+            book.bids().collect::<Vec<_>>(),
+            vec![(Price(100), Quantity(12)), (Price(98), Quantity(5))]
+        );
+        assert_eq!(
+            book.modify(7, Price(98), Quantity(5)),
+            Err(L3BookError::UnknownOrderId(7))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_matches_l2_view() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.set_last_update_id(42);
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Sell, Price(101), Quantity(3)).unwrap();
+        book.add(3, Side::Sell, Price(102), Quantity(4)).unwrap();
+
+        let snapshot = book.snapshot(1);
+        assert_eq!(snapshot.last_update_id, 42);
+        assert_eq!(snapshot.bids, vec![(Price(100), Quantity(10))]);
+        assert_eq!(snapshot.asks, vec![(Price(101), Quantity(3))]);
+    }
+
+    #[test]
+    fn test_views_follow_the_orders() {
+        let mut book = L3Book::new("BNBUSDT");
+        book.set_last_update_id(7);
+        assert_eq!(book.l1().bid, None);
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Buy, Price(99), Quantity(4)).unwrap();
+        book.add(3, Side::Buy, Price(100), Quantity(5)).unwrap();
+        book.add(4, Side::Sell, Price(102), Quantity(3)).unwrap();
+        book.add(5, Side::Sell, Price(101), Quantity(6)).unwrap();
+
+        let l1 = book.l1();
+        assert_eq!(l1.last_update_id, 7);
+        assert_eq!(l1.bid, Some((Price(100), Quantity(15))));
+        assert_eq!(l1.ask, Some((Price(101), Quantity(6))));
+        let l3 = book.l3();
+        assert_eq!(
+            l3.bids.iter().map(|o| o.order_id).collect::<Vec<_>>(),
+            vec![1, 3, 2]
+        );
+        assert_eq!(
+            l3.asks.iter().map(|o| o.order_id).collect::<Vec<_>>(),
+            vec![5, 4]
+        );
+
+        // Changes behind the best level leave it alone, changes at it move it
+        book.modify(2, Price(98), Quantity(4)).unwrap();
+        assert_eq!(book.best_bid(), Some((Price(100), Quantity(15))));
+        book.execute(1, Quantity(10), 0).unwrap();
+        assert_eq!(book.best_bid(), Some((Price(100), Quantity(5))));
+        book.delete(3).unwrap();
+        assert_eq!(book.best_bid(), Some((Price(98), Quantity(4))));
+        book.modify(4, Price(100), Quantity(3)).unwrap();
+        assert_eq!(book.best_ask(), Some((Price(100), Quantity(3))));
+        book.delete(4).unwrap();
+        book.delete(5).unwrap();
+        assert_eq!(book.best_ask(), None);
+
+        // Every view agrees with the orders
+        book.add(6, Side::Sell, Price(103), Quantity(2)).unwrap();
+        let l2 = book.l2(usize::MAX);
+        assert_eq!(
+            (l2.bids.first().copied(), l2.asks.first().copied()),
+            (book.l1().bid, book.l1().ask)
+        );
+        for (levels, orders) in [(&l2.bids, &book.l3().bids), (&l2.asks, &book.l3().asks)] {
+            let mut aggregated: Vec<(Price, Quantity)> = Vec::new();
+            for order in orders {
+                match aggregated.last_mut() {
+                    Some((price, quantity)) if *price == order.price => *quantity += order.quantity,
+                    _ => aggregated.push((order.price, order.quantity)),
+                }
+            }
+            assert_eq!(levels, &aggregated);
+        }
+    }
+
+    #[test]
+    fn test_queue_position_tracks_cancels_and_trades() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Buy, Price(100), Quantity(10)).unwrap();
+        book.add(2, Side::Buy, Price(100), Quantity(5)).unwrap();
+        book.add(3, Side::Buy, Price(100), Quantity(8)).unwrap();
+        assert_eq!(book.queue_position(1), Some(Quantity(0)));
+        assert_eq!(book.queue_position(3), Some(Quantity(15)));
+
+        book.execute(1, Quantity(4), 1_000).unwrap();
+        assert_eq!(book.queue_position(3), Some(Quantity(11)));
+
+        book.delete(2).unwrap();
+        assert_eq!(book.queue_position(3), Some(Quantity(6)));
+
+        book.execute(1, Quantity(6), 2_000).unwrap();
+        assert_eq!(book.get_order(1), None);
+        assert_eq!(book.queue_position(3), Some(Quantity(0)));
+        assert_eq!(book.queue_position(42), None);
+    }
+
+    #[test]
+    fn test_estimated_fill_probability() {
+        let mut book = L3Book::new("BNBUSDT".to_string());
+        book.add(1, Side::Sell, Price(101), Quantity(100)).unwrap();
+        book.add(2, Side::Sell, Price(101), Quantity(10)).unwrap();
+        book.add(3, Side::Sell, Price(101), Quantity(1_000))
+            .unwrap();
+
+        // No trade flow observed yet
+        assert_eq!(
+            book.estimated_fill_probability(2, Duration::from_secs(1)),
+            None
+        );
+
+        // 10 lots traded per second on the ask side
+        book.execute(1, Quantity(10), 0).unwrap();
+        book.execute(1, Quantity(10), 1_000).unwrap();
+        book.execute(1, Quantity(10), 2_000).unwrap();
+
+        let near = book
+            .estimated_fill_probability(2, Duration::from_secs(60))
+            .unwrap();
+        let short = book
+            .estimated_fill_probability(2, Duration::from_secs(1))
+            .unwrap();
+        let far = book
+            .estimated_fill_probability(3, Duration::from_secs(60))
+            .unwrap();
+        assert!(near > 0.99);
+        assert!(short < 0.01);
+        assert!(far < near);
+    }
+
+    #[test]
+    fn test_poisson_at_least() {
+        assert_eq!(poisson_at_least(3.0, 0), 1.0);
+        assert_eq!(poisson_at_least(0.0, 1), 0.0);
+        assert!((poisson_at_least(1.0, 1) - (1.0 - (-1.0f64).exp())).abs() < 1e-12);
+        assert!(poisson_at_least(2_000.0, 10) > 0.999);
+    }
+}
@@ -0,0 +1,297 @@
+/// Latency of the market data path.
+/// Every applied update gives three times: the exchange event time (Binance `E`, when the
+/// stream sends it), the time the payload was read from the socket and the time it was applied
+/// to the book. The recorder keeps the most recent samples of each stage and reports
+/// percentiles over them. Network latency compares the exchange clock with the local wall clock
+/// so it includes their skew and may even be negative, feed handler latency only uses the
+/// monotonic clock.
+use crate::clock::Stamp;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+pub const DEFAULT_LATENCY_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyStage {
+    // Exchange event time to socket receive
+    Network,
+    // Socket receive to book apply
+    FeedHandler,
+    // Exchange event time to book apply
+    Total,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySample {
+    // Milliseconds since the Unix epoch, as sent by the exchange
+    pub event_time: Option<u64>,
+    pub received: Stamp,
+    pub applied: Stamp,
+}
+
+impl LatencySample {
+    // Signed nanoseconds, `None` when the update had no event time
+    pub fn latency(&self, stage: LatencyStage) -> Option<i64> {
+        let feed_handler = self.applied.monotonic as i64 - self.received.monotonic as i64;
+        let network = self
+            .event_time
+            .map(|event_time| self.received.wall as i64 - (event_time * 1_000_000) as i64);
+        match stage {
+            LatencyStage::Network => network,
+            LatencyStage::FeedHandler => Some(feed_handler),
+            LatencyStage::Total => network.map(|network| network + feed_handler),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStatistics {
+    pub count: usize,
+    pub min: i64,
+    pub max: i64,
+    pub mean: f64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub p999: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencyRecorder {
+    capacity: usize,
+    network: VecDeque<i64>,
+    feed_handler: VecDeque<i64>,
+    total: VecDeque<i64>,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        LatencyRecorder::new(DEFAULT_LATENCY_CAPACITY)
+    }
+}
+
+#[derive(Serialize)]
+struct CsvBucket {
+    bucket_start_ns: i64,
+    bucket_end_ns: i64,
+    count: usize,
+}
+
+impl LatencyRecorder {
+    // Keeps the last `capacity` samples of each stage
+    pub fn new(capacity: usize) -> LatencyRecorder {
+        LatencyRecorder {
+            capacity,
+            network: VecDeque::new(),
+            feed_handler: VecDeque::new(),
+            total: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, sample: LatencySample) {
+        for stage in [
+            LatencyStage::Network,
+            LatencyStage::FeedHandler,
+            LatencyStage::Total,
+        ] {
+            if let Some(latency) = sample.latency(stage) {
+                let capacity = self.capacity;
+                let samples = self.samples_mut(stage);
+                if samples.len() == capacity {
+                    samples.pop_front();
+                }
+                if capacity > 0 {
+                    samples.push_back(latency);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.network.clear();
+        self.feed_handler.clear();
+        self.total.clear();
+    }
+
+    pub fn len(&self, stage: LatencyStage) -> usize {
+        self.samples(stage).len()
+    }
+
+    pub fn is_empty(&self, stage: LatencyStage) -> bool {
+        self.samples(stage).is_empty()
+    }
+
+    fn samples(&self, stage: LatencyStage) -> &VecDeque<i64> {
+        match stage {
+            LatencyStage::Network => &self.network,
+            LatencyStage::FeedHandler => &self.feed_handler,
+            LatencyStage::Total => &self.total,
+        }
+    }
+
+    fn samples_mut(&mut self, stage: LatencyStage) -> &mut VecDeque<i64> {
+        match stage {
+            LatencyStage::Network => &mut self.network,
+            LatencyStage::FeedHandler => &mut self.feed_handler,
+            LatencyStage::Total => &mut self.total,
+        }
+    }
+
+    fn sorted(&self, stage: LatencyStage) -> Vec<i64> {
+        let mut sorted: Vec<i64> = self.samples(stage).iter().copied().collect();
+        sorted.sort_unstable();
+        sorted
+    }
+
+    // Nearest rank percentile, `quantile` between 0.0 and 1.0
+    pub fn percentile(&self, stage: LatencyStage, quantile: f64) -> Option<i64> {
+        nearest_rank(&self.sorted(stage), quantile)
+    }
+
+    pub fn statistics(&self, stage: LatencyStage) -> Option<LatencyStatistics> {
+        let sorted = self.sorted(stage);
+        let percentile = |quantile| nearest_rank(&sorted, quantile);
+        Some(LatencyStatistics {
+            count: sorted.len(),
+            min: *sorted.first()?,
+            max: *sorted.last()?,
+            mean: sorted.iter().map(|&latency| latency as f64).sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5)?,
+            p90: percentile(0.9)?,
+            p99: percentile(0.99)?,
+            p999: percentile(0.999)?,
+        })
+    }
+
+    // Sample counts per `bucket_width` wide bucket, from the lowest to the highest bucket with
+    // samples, empty buckets included
+    pub fn histogram(&self, stage: LatencyStage, bucket_width: Duration) -> Vec<(i64, usize)> {
+        let width = bucket_width.as_nanos() as i64;
+        assert!(width > 0, "bucket width must not be zero");
+        let sorted = self.sorted(stage);
+        let (Some(first), Some(last)) = (sorted.first(), sorted.last()) else {
+            return Vec::new();
+        };
+        let bucket = |latency: i64| latency.div_euclid(width);
+        let first_bucket = bucket(*first);
+        let mut counts = vec![0; (bucket(*last) - first_bucket) as usize + 1];
+        for latency in &sorted {
+            counts[(bucket(*latency) - first_bucket) as usize] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| ((first_bucket + i as i64) * width, count))
+            .collect()
+    }
+
+    // `bucket_start_ns,bucket_end_ns,count` rows, the end is exclusive
+    pub fn export_histogram_csv<W: io::Write>(
+        &self,
+        stage: LatencyStage,
+        bucket_width: Duration,
+        writer: W,
+    ) -> csv::Result<()> {
+        let width = bucket_width.as_nanos() as i64;
+        let mut writer = csv::Writer::from_writer(writer);
+        for (bucket_start_ns, count) in self.histogram(stage, bucket_width) {
+            writer.serialize(CsvBucket {
+                bucket_start_ns,
+                bucket_end_ns: bucket_start_ns + width,
+                count,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn nearest_rank(sorted: &[i64], quantile: f64) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (quantile.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Nanos;
+
+    fn sample(event_time: Option<u64>, received: Nanos, applied: Nanos) -> LatencySample {
+        // Monotonic time counts from the exchange event at 1_700_000_000_000 ms
+        let wall_origin = 1_700_000_000_000 * 1_000_000;
+        LatencySample {
+            event_time,
+            received: Stamp {
+                monotonic: received,
+                wall: wall_origin + received,
+            },
+            applied: Stamp {
+                monotonic: applied,
+                wall: wall_origin + applied,
+            },
+        }
+    }
+
+    #[test]
+    fn test_stage_latencies() {
+        let with_event_time = sample(Some(1_700_000_000_000), 3_000_000, 3_250_000);
+        assert_eq!(
+            with_event_time.latency(LatencyStage::Network),
+            Some(3_000_000)
+        );
+        assert_eq!(
+            with_event_time.latency(LatencyStage::FeedHandler),
+            Some(250_000)
+        );
+        assert_eq!(
+            with_event_time.latency(LatencyStage::Total),
+            Some(3_250_000)
+        );
+
+        let mut recorder = LatencyRecorder::default();
+        recorder.record(sample(None, 0, 100));
+        assert!(recorder.is_empty(LatencyStage::Network));
+        assert_eq!(recorder.len(LatencyStage::FeedHandler), 1);
+    }
+
+    #[test]
+    fn test_percentiles_and_histogram() {
+        let mut recorder = LatencyRecorder::new(100);
+        for apply in 1..=200 {
+            recorder.record(sample(None, 0, apply * 1_000));
+        }
+        // Only the last 100 samples are kept
+        let statistics = recorder.statistics(LatencyStage::FeedHandler).unwrap();
+        assert_eq!(statistics.count, 100);
+        assert_eq!((statistics.min, statistics.max), (101_000, 200_000));
+        assert_eq!(statistics.p50, 150_000);
+        assert_eq!(statistics.p99, 199_000);
+        assert_eq!(statistics.mean, 150_500.0);
+        assert_eq!(
+            recorder.percentile(LatencyStage::FeedHandler, 0.0),
+            Some(101_000)
+        );
+        assert_eq!(recorder.statistics(LatencyStage::Network), None);
+
+        let histogram = recorder.histogram(LatencyStage::FeedHandler, Duration::from_micros(50));
+        assert_eq!(histogram, vec![(100_000, 49), (150_000, 50), (200_000, 1)]);
+
+        let mut csv = Vec::new();
+        recorder
+            .export_histogram_csv(
+                LatencyStage::FeedHandler,
+                Duration::from_micros(50),
+                &mut csv,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "bucket_start_ns,bucket_end_ns,count\n100000,150000,49\n150000,200000,50\n200000,250000,1\n"
+        );
+    }
+}
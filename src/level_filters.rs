@@ -0,0 +1,334 @@
+/// Liquidity filters evaluated by the L2 book as it updates.
+/// A `LevelFilter` measures the resting quantity of one side at a single price, inside a fixed
+/// price band or within some basis points of the mid, and compares it with a threshold, e.g.
+/// "bid liquidity within 5 bps of the mid below 10 units". The book keeps the measured quantity
+/// of every filter up to date from the level changes of each payload, and only walks the levels
+/// again when a band around the mid moves with it. A filter fires when its condition becomes
+/// true after a payload, not again while it stays true, so a consumer is told about each
+/// crossing instead of polling the book. Prices and quantities are in book units, see
+/// `OrderBook::converter`.
+use crate::orderbook::{OrderBook, Price, Quantity};
+use crate::orderbookv2::Side;
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type FilterId = u64;
+
+// Which levels of the side a filter sums up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Band {
+    Level(Price),
+    // Both ends included
+    Range { low: Price, high: Price },
+    // Levels at most `bps` basis points away from the mid, on the side of the filter. Without
+    // both a bid and an ask there is no mid and the band holds nothing. Above 10_000 bps a bid
+    // band reaches down to zero.
+    NearMid { bps: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Threshold {
+    Below(Quantity),
+    Above(Quantity),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelFilter {
+    pub side: Side,
+    pub band: Band,
+    pub threshold: Threshold,
+}
+
+impl LevelFilter {
+    pub fn new(side: Side, band: Band, threshold: Threshold) -> LevelFilter {
+        LevelFilter {
+            side,
+            band,
+            threshold,
+        }
+    }
+
+    fn is_met(&self, liquidity: u64) -> bool {
+        match self.threshold {
+            Threshold::Below(quantity) => liquidity < quantity.get(),
+            Threshold::Above(quantity) => liquidity > quantity.get(),
+        }
+    }
+
+    // Lowest and highest price of the band, none without a mid
+    fn bounds(&self, book: &OrderBook) -> Option<(Price, Price)> {
+        match self.band {
+            Band::Level(price) => Some((price, price)),
+            Band::Range { low, high } => Some((low, high)),
+            Band::NearMid { bps } => {
+                let (bid, _) = book.best_bid()?;
+                let (ask, _) = book.best_ask()?;
+                let mid = (u128::from(bid.get()) + u128::from(ask.get())) / 2;
+                let offset = mid * u128::from(bps) / 10_000;
+                // Both ends stay within the u64 prices they were derived from, or saturate
+                let clamp = |price: u128| Price(u64::try_from(price).unwrap_or(u64::MAX));
+                Some(match self.side {
+                    Side::Buy => (clamp(mid.saturating_sub(offset)), clamp(mid)),
+                    Side::Sell => (clamp(mid), clamp(mid + offset)),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterAlert {
+    pub filter_id: FilterId,
+    pub symbol: Symbol,
+    pub last_update_id: u64,
+    // Quantity in the band that met the threshold
+    pub liquidity: u64,
+}
+
+type Callback = Box<dyn FnMut(&FilterAlert) + Send + Sync>;
+
+struct Watched {
+    filter: LevelFilter,
+    callback: Callback,
+    bounds: Option<(Price, Price)>,
+    liquidity: u64,
+    met: bool,
+    changed: bool,
+}
+
+impl Watched {
+    fn recompute(&mut self, book: &OrderBook) {
+        self.bounds = self.filter.bounds(book);
+        self.liquidity = match self.bounds {
+            Some((low, high)) => {
+                let in_band = |&(price, _): &(Price, Quantity)| price >= low && price <= high;
+                let levels: Box<dyn Iterator<Item = (Price, Quantity)>> = match self.filter.side {
+                    Side::Buy => Box::new(book.bids().skip_while(|(price, _)| *price > high)),
+                    Side::Sell => Box::new(book.asks().skip_while(|(price, _)| *price < low)),
+                };
+                levels
+                    .take_while(in_band)
+                    .fold(0u64, |total, (_, quantity)| {
+                        total.saturating_add(quantity.get())
+                    })
+            }
+            None => 0,
+        };
+    }
+}
+
+// Filters of one book. Like the top of book subscribers they are not copied with the book.
+#[derive(Default)]
+pub(crate) struct LevelFilters {
+    filters: BTreeMap<FilterId, Watched>,
+    next_id: FilterId,
+    // The levels changed without going through `on_level`, every filter is measured again
+    stale: bool,
+}
+
+impl LevelFilters {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        filter: LevelFilter,
+        callback: Callback,
+        book: &OrderBook,
+    ) -> FilterId {
+        let filter_id = self.next_id;
+        self.next_id += 1;
+        let mut watched = Watched {
+            filter,
+            callback,
+            bounds: None,
+            liquidity: 0,
+            met: false,
+            changed: false,
+        };
+        watched.recompute(book);
+        watched.met = filter.is_met(watched.liquidity);
+        self.filters.insert(filter_id, watched);
+        filter_id
+    }
+
+    pub(crate) fn remove(&mut self, filter_id: FilterId) -> bool {
+        self.filters.remove(&filter_id).is_some()
+    }
+
+    // Measured quantity and whether the threshold is met, as of the last payload
+    pub(crate) fn state(&self, filter_id: FilterId) -> Option<(u64, bool)> {
+        self.filters
+            .get(&filter_id)
+            .map(|watched| (watched.liquidity, watched.met))
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    // A level of the book changed from `old` to `new`
+    pub(crate) fn on_level(&mut self, side: Side, price: Price, old: Quantity, new: Quantity) {
+        for watched in self.filters.values_mut() {
+            let Some((low, high)) = watched.bounds else {
+                continue;
+            };
+            if watched.filter.side != side || price < low || price > high {
+                continue;
+            }
+            watched.liquidity = watched
+                .liquidity
+                .saturating_sub(old.get())
+                .saturating_add(new.get());
+            watched.changed = true;
+        }
+    }
+
+    // Called once the book applied a payload
+    pub(crate) fn evaluate(&mut self, book: &OrderBook) {
+        let stale = std::mem::take(&mut self.stale);
+        for (&filter_id, watched) in self.filters.iter_mut() {
+            if stale || watched.filter.bounds(book) != watched.bounds {
+                watched.recompute(book);
+            } else if !std::mem::take(&mut watched.changed) {
+                continue;
+            }
+            watched.changed = false;
+
+            let met = watched.filter.is_met(watched.liquidity);
+            if met && !watched.met {
+                (watched.callback)(&FilterAlert {
+                    filter_id,
+                    symbol: book.symbol(),
+                    last_update_id: book.last_update_id(),
+                    liquidity: watched.liquidity,
+                });
+            }
+            watched.met = met;
+        }
+    }
+}
+
+impl Clone for LevelFilters {
+    fn clone(&self) -> Self {
+        LevelFilters::default()
+    }
+}
+
+impl fmt::Debug for LevelFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LevelFilters")
+            .field("filters", &self.filters.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use std::sync::{Arc, Mutex};
+
+    fn update(last_update_id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id,
+            bids,
+            asks,
+        }
+    }
+
+    // Book units of the default converter
+    fn units(value: f64) -> u64 {
+        (value * 10_000.0) as u64
+    }
+
+    #[test]
+    fn test_filters_fire_on_crossing() {
+        let mut book = OrderBook::new("ETHUSDC");
+        book.update_depth(&update(
+            1,
+            vec![(100.0, 5.0), (99.96, 5.0), (99.0, 50.0)],
+            vec![(100.02, 1.0)],
+        ));
+
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let record = |alerts: &Arc<Mutex<Vec<FilterAlert>>>| {
+            let alerts = Arc::clone(alerts);
+            move |alert: &FilterAlert| alerts.lock().unwrap().push(*alert)
+        };
+        // Bids within 5 bps of the mid, 99.96 and up at a mid of 100.01
+        let thin = book.add_level_filter(
+            LevelFilter::new(
+                Side::Buy,
+                Band::NearMid { bps: 5 },
+                Threshold::Below(Quantity(units(8.0))),
+            ),
+            record(&alerts),
+        );
+        let wall = book.add_level_filter(
+            LevelFilter::new(
+                Side::Sell,
+                Band::Level(Price(units(100.5))),
+                Threshold::Above(Quantity(units(20.0))),
+            ),
+            record(&alerts),
+        );
+        assert_eq!(book.level_filter_state(thin), Some((units(10.0), false)));
+
+        // Pulled below the threshold
+        book.update_depth(&update(2, vec![(100.0, 2.0)], vec![]));
+        // Still below, no second alert
+        book.update_depth(&update(3, vec![(99.96, 4.0)], vec![]));
+        // The mid moves up to 100.23 with the ask, no bid is left in the band
+        book.update_depth(&update(
+            4,
+            vec![(100.0, 0.0)],
+            vec![(100.02, 0.0), (100.5, 25.0)],
+        ));
+        assert_eq!(
+            alerts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|alert| (alert.filter_id, alert.last_update_id, alert.liquidity))
+                .collect::<Vec<_>>(),
+            vec![(thin, 2, units(7.0)), (wall, 4, units(25.0))]
+        );
+        assert_eq!(book.level_filter_state(thin), Some((0, true)));
+
+        // Back above the threshold and below it again after a snapshot
+        book.update_depth(&update(5, vec![(100.4, 9.0)], vec![]));
+        assert_eq!(book.level_filter_state(thin), Some((units(9.0), false)));
+        book.replace_depth(&update(6, vec![(100.4, 1.0)], vec![(100.5, 1.0)]));
+        assert_eq!(alerts.lock().unwrap().last().unwrap().last_update_id, 6);
+        assert_eq!(book.level_filter_state(thin), Some((units(1.0), true)));
+
+        assert!(book.remove_level_filter(thin));
+        assert_eq!(book.level_filter_state(thin), None);
+    }
+
+    #[test]
+    fn test_near_mid_band_wider_than_the_mid() {
+        let mut book = OrderBook::new("ETHUSDC");
+        book.update_depth(&update(1, vec![(1.0, 3.0), (0.5, 2.0)], vec![(3.0, 1.0)]));
+
+        let filter = |bps| {
+            LevelFilter::new(
+                Side::Buy,
+                Band::NearMid { bps },
+                Threshold::Below(Quantity(0)),
+            )
+        };
+        let everything = book.add_level_filter(filter(u32::MAX), |_| {});
+        let boundary = book.add_level_filter(filter(10_000), |_| {});
+        assert_eq!(
+            book.level_filter_state(everything),
+            Some((units(5.0), false))
+        );
+        assert_eq!(book.level_filter_state(boundary), Some((units(5.0), false)));
+    }
+}
@@ -0,0 +1,157 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod accounts;
+#[cfg(feature = "std")]
+pub mod alerts;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod binance_payloads;
+#[cfg(feature = "std")]
+pub mod book_audit;
+pub mod book_core;
+#[cfg(feature = "native")]
+pub mod book_stream;
+#[cfg(feature = "std")]
+pub mod candles;
+#[cfg(feature = "std")]
+pub mod capacity;
+#[cfg(feature = "std")]
+pub mod checksum;
+#[cfg(feature = "std")]
+pub mod circuit_breaker;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod conflation;
+#[cfg(feature = "std")]
+pub mod connection;
+#[cfg(feature = "std")]
+pub mod contingent;
+#[cfg(feature = "std")]
+pub mod digest;
+#[cfg(feature = "native")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod exchange;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod features;
+#[cfg(feature = "std")]
+pub mod fees;
+#[cfg(feature = "std")]
+pub mod fill_simulator;
+#[cfg(feature = "std")]
+pub mod framing;
+#[cfg(feature = "std")]
+pub mod futures;
+#[cfg(all(feature = "std", unix))]
+pub mod gateway;
+#[cfg(feature = "std")]
+pub mod heatmap;
+#[cfg(feature = "native")]
+pub mod historical;
+#[cfg(feature = "std")]
+pub mod ids;
+#[cfg(feature = "std")]
+pub mod instruments;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "std")]
+pub mod l3book;
+#[cfg(feature = "std")]
+pub mod latency;
+#[cfg(feature = "std")]
+pub mod level_filters;
+#[cfg(feature = "std")]
+pub mod liquidity;
+#[cfg(feature = "native")]
+pub mod listen_key;
+#[cfg(feature = "std")]
+pub mod manager;
+#[cfg(feature = "std")]
+pub mod market_data;
+#[cfg(feature = "std")]
+pub mod market_quality;
+#[cfg(feature = "multicast")]
+pub mod multicast;
+#[cfg(feature = "std")]
+pub mod options;
+#[cfg(feature = "std")]
+pub mod order_flow;
+#[cfg(feature = "std")]
+pub mod orderbook;
+#[cfg(feature = "std")]
+pub mod orderbookv2;
+#[cfg(feature = "std")]
+pub mod paper;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod portfolio;
+#[cfg(feature = "std")]
+pub mod price_converter;
+pub mod price_levels;
+#[cfg(feature = "std")]
+pub mod rate_limit;
+#[cfg(feature = "native")]
+pub mod recording;
+#[cfg(feature = "redis")]
+pub mod redis_sink;
+#[cfg(feature = "std")]
+pub mod risk;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "std")]
+pub mod scenario;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod shared_book;
+#[cfg(feature = "shm")]
+pub mod shm;
+#[cfg(feature = "native")]
+pub mod shutdown;
+#[cfg(feature = "std")]
+pub mod signals;
+#[cfg(feature = "std")]
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod stops;
+#[cfg(feature = "std")]
+pub mod strategies;
+#[cfg(feature = "std")]
+pub mod strategy;
+#[cfg(feature = "std")]
+pub mod subscriptions;
+#[cfg(feature = "std")]
+pub mod surveillance;
+#[cfg(feature = "std")]
+pub mod symbol;
+#[cfg(feature = "std")]
+pub mod synthetic;
+#[cfg(feature = "native")]
+pub mod testkit;
+#[cfg(feature = "std")]
+pub mod top_of_book;
+#[cfg(feature = "std")]
+pub mod trade_tape;
+#[cfg(feature = "trading")]
+pub mod trading;
+pub mod units;
+#[cfg(feature = "std")]
+pub mod user_data;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,334 @@
+/// Liquidity profile of an L2 book over a window of updates.
+/// `LiquidityProfiler` observes the book after every update of a replay or a live feed and
+/// `report` summarizes the window: average spread, average depth within bands around the mid,
+/// how long the best prices stay at the touch, how often the top of book changes and how long
+/// the price levels of the top `levels` live before they are removed. Averages are taken over
+/// the observations, not weighted by time. The report serializes to JSON or to `metric,value`
+/// CSV rows.
+use crate::orderbook::{OrderBook, Price};
+use crate::orderbookv2::Timestamp;
+use crate::symbol::Symbol;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+
+pub const DEFAULT_BANDS_BPS: [u32; 3] = [1, 5, 10];
+pub const DEFAULT_LIFETIME_LEVELS: usize = 10;
+
+// Distribution of durations in nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DurationSummary {
+    pub count: usize,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl DurationSummary {
+    fn from_durations(durations: &[u64]) -> Option<DurationSummary> {
+        if durations.is_empty() {
+            return None;
+        }
+        let mut sorted = durations.to_vec();
+        sorted.sort_unstable();
+        // Nearest rank
+        let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).max(1) - 1];
+        Some(DurationSummary {
+            count: sorted.len(),
+            mean: sorted.iter().map(|&d| d as f64).sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+// Average base quantity resting within `bps` of the mid
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BandDepth {
+    pub bps: u32,
+    pub bid: f64,
+    pub ask: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiquidityReport {
+    pub symbol: Symbol,
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+    pub observations: u64,
+    pub average_spread: Option<f64>,
+    pub average_spread_bps: Option<f64>,
+    pub depth: Vec<BandDepth>,
+    // Time a best bid or best ask price stayed at the touch
+    pub time_at_touch: Option<DurationSummary>,
+    // Changes of the best prices or sizes per second of the window
+    pub touch_updates_per_second: Option<f64>,
+    pub quote_lifetime: Option<DurationSummary>,
+}
+
+impl LiquidityReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // One `metric,value` row per figure, empty values for the ones the window did not have
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut rows: Vec<(String, Option<f64>)> = vec![
+            ("observations".to_string(), Some(self.observations as f64)),
+            ("average_spread".to_string(), self.average_spread),
+            ("average_spread_bps".to_string(), self.average_spread_bps),
+        ];
+        for band in &self.depth {
+            rows.push((format!("depth_bid_{}bps", band.bps), Some(band.bid)));
+            rows.push((format!("depth_ask_{}bps", band.bps), Some(band.ask)));
+        }
+        rows.push((
+            "touch_updates_per_second".to_string(),
+            self.touch_updates_per_second,
+        ));
+        for (name, summary) in [
+            ("time_at_touch", self.time_at_touch),
+            ("quote_lifetime", self.quote_lifetime),
+        ] {
+            rows.push((format!("{}_count", name), summary.map(|s| s.count as f64)));
+            rows.push((format!("{}_mean_ns", name), summary.map(|s| s.mean)));
+            rows.push((format!("{}_p50_ns", name), summary.map(|s| s.p50 as f64)));
+            rows.push((format!("{}_p90_ns", name), summary.map(|s| s.p90 as f64)));
+            rows.push((format!("{}_p99_ns", name), summary.map(|s| s.p99 as f64)));
+            rows.push((format!("{}_max_ns", name), summary.map(|s| s.max as f64)));
+        }
+
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["metric", "value"])?;
+        for (metric, value) in rows {
+            let value = value.map(|value| value.to_string()).unwrap_or_default();
+            writer.write_record([metric.as_str(), value.as_str()])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LiquidityProfiler {
+    bands_bps: Vec<u32>,
+    levels: usize,
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+    observations: u64,
+    spreads: u64,
+    spread_sum: f64,
+    spread_bps_sum: f64,
+    // Bid and ask sums per band
+    depth_sums: Vec<(f64, f64)>,
+    // Best price of each side and since when
+    touch: [Option<(Price, Timestamp)>; 2],
+    top_of_book: Option<((Price, u64), (Price, u64))>,
+    touch_updates: u64,
+    time_at_touch: Vec<u64>,
+    // Levels of the top of each side and since when, bids first
+    quotes: [HashMap<Price, Timestamp>; 2],
+    quote_lifetimes: Vec<u64>,
+}
+
+impl Default for LiquidityProfiler {
+    fn default() -> Self {
+        LiquidityProfiler::new(&DEFAULT_BANDS_BPS, DEFAULT_LIFETIME_LEVELS)
+    }
+}
+
+impl LiquidityProfiler {
+    // Depth within each of `bands_bps` basis points of the mid, lifetimes of the top `levels`
+    pub fn new(bands_bps: &[u32], levels: usize) -> LiquidityProfiler {
+        LiquidityProfiler {
+            bands_bps: bands_bps.to_vec(),
+            levels,
+            start: None,
+            end: None,
+            observations: 0,
+            spreads: 0,
+            spread_sum: 0.0,
+            spread_bps_sum: 0.0,
+            depth_sums: vec![(0.0, 0.0); bands_bps.len()],
+            touch: [None, None],
+            top_of_book: None,
+            touch_updates: 0,
+            time_at_touch: Vec::new(),
+            quotes: [HashMap::new(), HashMap::new()],
+            quote_lifetimes: Vec::new(),
+        }
+    }
+
+    pub fn observe(&mut self, timestamp: Timestamp, book: &OrderBook) {
+        self.start.get_or_insert(timestamp);
+        self.end = Some(timestamp);
+        self.observations += 1;
+
+        if let (Some(spread), Some(mid)) = (book.spread(), book.mid_price()) {
+            self.spreads += 1;
+            self.spread_sum += spread;
+            if mid > 0.0 {
+                self.spread_bps_sum += spread / mid * 10_000.0;
+            }
+            let converter = book.converter();
+            for (band, sums) in self.bands_bps.iter().zip(&mut self.depth_sums) {
+                let offset = mid * f64::from(*band) / 10_000.0;
+                let quantity = |(_, qty)| converter.to_f64(qty);
+                sums.0 += book
+                    .bids()
+                    .take_while(|(price, _)| converter.to_f64(*price) >= mid - offset)
+                    .map(quantity)
+                    .sum::<f64>();
+                sums.1 += book
+                    .asks()
+                    .take_while(|(price, _)| converter.to_f64(*price) <= mid + offset)
+                    .map(quantity)
+                    .sum::<f64>();
+            }
+        }
+
+        let best = [book.best_bid(), book.best_ask()];
+        for (touch, level) in self.touch.iter_mut().zip(best) {
+            let price = level.map(|(price, _)| price);
+            match (*touch, price) {
+                (Some((current, _)), Some(price)) if current == price => {}
+                (previous, price) => {
+                    if let Some((_, since)) = previous {
+                        self.time_at_touch.push(timestamp.saturating_sub(since));
+                    }
+                    *touch = price.map(|price| (price, timestamp));
+                }
+            }
+        }
+        let top = best[0]
+            .zip(best[1])
+            .map(|((bid, bid_qty), (ask, ask_qty))| {
+                ((bid, u64::from(bid_qty)), (ask, u64::from(ask_qty)))
+            });
+        if top.is_some() && self.top_of_book.is_some() && top != self.top_of_book {
+            self.touch_updates += 1;
+        }
+        self.top_of_book = top;
+
+        let top_levels = [
+            book.bids().take(self.levels).collect::<Vec<_>>(),
+            book.asks().take(self.levels).collect(),
+        ];
+        for (quotes, levels) in self.quotes.iter_mut().zip(top_levels) {
+            let alive: HashMap<_, _> = levels
+                .into_iter()
+                .map(|(price, _)| (price, quotes.get(&price).copied().unwrap_or(timestamp)))
+                .collect();
+            for (price, since) in quotes.iter() {
+                if !alive.contains_key(price) {
+                    self.quote_lifetimes.push(timestamp.saturating_sub(*since));
+                }
+            }
+            *quotes = alive;
+        }
+    }
+
+    pub fn observations(&self) -> u64 {
+        self.observations
+    }
+
+    pub fn report(&self, symbol: Symbol) -> LiquidityReport {
+        let average = |sum: f64| (self.spreads > 0).then(|| sum / self.spreads as f64);
+        let elapsed = match (self.start, self.end) {
+            (Some(start), Some(end)) if end > start => Some((end - start) as f64 / 1e9),
+            _ => None,
+        };
+        LiquidityReport {
+            symbol,
+            start: self.start,
+            end: self.end,
+            observations: self.observations,
+            average_spread: average(self.spread_sum),
+            average_spread_bps: average(self.spread_bps_sum),
+            depth: self
+                .bands_bps
+                .iter()
+                .zip(&self.depth_sums)
+                .map(|(&bps, &(bid, ask))| BandDepth {
+                    bps,
+                    bid: average(bid).unwrap_or_default(),
+                    ask: average(ask).unwrap_or_default(),
+                })
+                .collect(),
+            time_at_touch: DurationSummary::from_durations(&self.time_at_touch),
+            touch_updates_per_second: elapsed.map(|seconds| self.touch_updates as f64 / seconds),
+            quote_lifetime: DurationSummary::from_durations(&self.quote_lifetimes),
+        }
+    }
+
+    // Starts a new window, the levels in the book now start their lifetime over
+    pub fn reset(&mut self) {
+        *self = LiquidityProfiler::new(&self.bands_bps, self.levels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    fn update(book: &mut OrderBook, id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) {
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: id,
+            bids,
+            asks,
+        });
+    }
+
+    #[test]
+    fn test_liquidity_report() {
+        let mut book = OrderBook::new("ETHUSDC");
+        let mut profiler = LiquidityProfiler::new(&[1, 10], 2);
+
+        update(
+            &mut book,
+            1,
+            vec![(999.95, 1.0), (999.5, 2.0)],
+            vec![(1000.05, 3.0)],
+        );
+        profiler.observe(0, &book);
+        // Size change at the touch, then the bid moves up
+        update(&mut book, 2, vec![(999.95, 2.0)], vec![]);
+        profiler.observe(SECOND, &book);
+        update(&mut book, 3, vec![(999.95, 0.0), (1000.0, 1.0)], vec![]);
+        profiler.observe(3 * SECOND, &book);
+
+        let report = profiler.report(book.symbol());
+        assert_eq!(report.observations, 3);
+        let spread = report.average_spread.unwrap();
+        assert!((spread - (0.1 + 0.1 + 0.05) / 3.0).abs() < 1e-9);
+        // Mid 1000, 1 bp is 0.1 on each side
+        assert_eq!(report.depth[0].bps, 1);
+        assert_eq!(report.depth[0].ask, 3.0);
+        assert!((report.depth[1].bid - (3.0 + 4.0 + 3.0) / 3.0).abs() < 1e-9);
+        assert_eq!(report.touch_updates_per_second, Some(2.0 / 3.0));
+        let touch = report.time_at_touch.unwrap();
+        assert_eq!((touch.count, touch.max), (1, 3 * SECOND));
+        // 999.95 left the book, 999.5 is still in the top 2
+        let lifetime = report.quote_lifetime.unwrap();
+        assert_eq!((lifetime.count, lifetime.p50), (1, 3 * SECOND));
+
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"symbol\": \"ETHUSDC\""));
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("metric,value\nobservations,3\n"));
+        assert!(csv.contains("depth_ask_1bps,3\n"));
+
+        profiler.reset();
+        assert_eq!(profiler.report(book.symbol()).average_spread, None);
+    }
+}
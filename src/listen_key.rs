@@ -0,0 +1,123 @@
+/// Listen key of the Binance user data stream.
+/// The key is created with `POST /api/v3/userDataStream` and expires 60 minutes after the last
+/// keepalive, Binance recommends a keepalive every 30 minutes. The requests only need the API
+/// key, they go through the REST client of the connector.
+use crate::clock::{self, Nanos, SharedClock};
+use crate::config::{BinanceConfig, ConfigError};
+use binance_spot_connector_rust::http::Credentials;
+use binance_spot_connector_rust::stream;
+use binance_spot_connector_rust::ureq::{self, BinanceHttpClient};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug)]
+pub enum ListenKeyError {
+    Http(Box<ureq::Error>),
+    InvalidResponse(serde_json::Error),
+    NotStarted,
+}
+
+impl fmt::Display for ListenKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenKeyError::Http(error) => write!(f, "listen key request failed: {:?}", error),
+            ListenKeyError::InvalidResponse(error) => {
+                write!(f, "invalid listen key response: {}", error)
+            }
+            ListenKeyError::NotStarted => write!(f, "no listen key, the stream is not started"),
+        }
+    }
+}
+
+impl std::error::Error for ListenKeyError {}
+
+impl From<Box<ureq::Error>> for ListenKeyError {
+    fn from(error: Box<ureq::Error>) -> Self {
+        ListenKeyError::Http(error)
+    }
+}
+
+#[derive(Deserialize)]
+struct ListenKeyResponse {
+    #[serde(rename = "listenKey")]
+    listen_key: String,
+}
+
+pub struct ListenKey {
+    client: BinanceHttpClient,
+    credentials: Credentials,
+    clock: SharedClock,
+    key: Option<String>,
+    last_keepalive: Nanos,
+}
+
+impl ListenKey {
+    // `base_url` of the REST API, e.g. https://api.binance.com
+    pub fn new(base_url: &str, credentials: Credentials) -> ListenKey {
+        ListenKey {
+            client: BinanceHttpClient::with_url(base_url),
+            credentials,
+            clock: clock::system(),
+            key: None,
+            last_keepalive: 0,
+        }
+    }
+
+    pub fn from_config(config: &BinanceConfig) -> Result<ListenKey, ConfigError> {
+        Ok(ListenKey::new(&config.rest_url, config.credentials()?))
+    }
+
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    // Current key, the stream name to subscribe to
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    // Creates the key, Binance returns the active one when the account already has one
+    pub fn start(&mut self) -> Result<&str, ListenKeyError> {
+        let body = self
+            .client
+            .send(stream::new_listen_key().credentials(&self.credentials))?
+            .into_body_str()?;
+        let response: ListenKeyResponse =
+            serde_json::from_str(&body).map_err(ListenKeyError::InvalidResponse)?;
+        self.last_keepalive = self.clock.monotonic();
+        Ok(self.key.insert(response.listen_key))
+    }
+
+    pub fn keepalive_due(&self) -> bool {
+        self.key.is_some()
+            && Duration::from_nanos(self.clock.monotonic() - self.last_keepalive)
+                >= KEEPALIVE_INTERVAL
+    }
+
+    pub fn keepalive(&mut self) -> Result<(), ListenKeyError> {
+        let key = self.key.as_deref().ok_or(ListenKeyError::NotStarted)?;
+        self.client
+            .send(stream::renew_listen_key(key).credentials(&self.credentials))?;
+        self.last_keepalive = self.clock.monotonic();
+        Ok(())
+    }
+
+    // Sends the keepalive when it is due, to be called periodically
+    pub fn keepalive_if_due(&mut self) -> Result<bool, ListenKeyError> {
+        if !self.keepalive_due() {
+            return Ok(false);
+        }
+        self.keepalive()?;
+        Ok(true)
+    }
+
+    pub fn close(&mut self) -> Result<(), ListenKeyError> {
+        let key = self.key.take().ok_or(ListenKeyError::NotStarted)?;
+        self.client
+            .send(stream::close_listen_key(&key).credentials(&self.credentials))?;
+        Ok(())
+    }
+}
@@ -1,72 +1,195 @@
-use binance_spot_connector_rust::{
-    market_stream::book_ticker::BookTickerStream, market_stream::partial_depth::PartialDepthStream,
-    tokio_tungstenite::BinanceWebSocketClient,
-};
+use binance_orderbook::clock;
+use binance_orderbook::connection::{ConnectionEvent, ConnectionMonitor};
+use binance_orderbook::engine::Engine;
+use binance_orderbook::latency::LatencyStage;
+use binance_orderbook::orderbook::DepthSnapshot;
+use binance_orderbook::pipeline::{self, QueueMonitor};
+use binance_orderbook::shutdown::Shutdown;
+use binance_spot_connector_rust::tokio_tungstenite::BinanceWebSocketClient;
 use env_logger::Builder;
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
+#[cfg(feature = "demo-output")]
+use std::io::{self, Write};
+use std::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
 
-mod binance_payloads;
-mod orderbook;
-mod orderbookv2;
-
-const INSTRUMENT: &str = "ETHUSDC";
-const LEVELS: u16 = 20;
+const DEFAULT_CONFIG: &str = "book.toml";
 
 #[tokio::main]
 async fn main() {
     Builder::from_default_env().init();
 
-    let mut orderbook = orderbook::OrderBook::new(INSTRUMENT.to_string());
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_CONFIG.to_string());
+    let mut engine = Engine::from_config(&path).expect("Invalid configuration");
+    let config = engine.binance();
+    log::info!("{:?}", config);
 
     // Establish connection
-    let (mut conn, _) = BinanceWebSocketClient::connect_async_default()
+    let (mut conn, _) = BinanceWebSocketClient::connect_async(&config.ws_url)
         .await
         .expect("Failed to connect");
 
-    // Subscribe to streams
-    conn.subscribe(vec![
-        &PartialDepthStream::from_100ms(INSTRUMENT, LEVELS).into(),
-        &BookTickerStream::from_symbol(INSTRUMENT).into(),
-    ])
-    .await;
-
-    // Read messages
-    while let Some(message) = conn.as_mut().next().await {
-        match message {
-            Ok(message) => {
-                let binary_data = message.into_data();
-                let payload = std::str::from_utf8(&binary_data).expect("Failed to parse message");
-                log::debug!("{:?}", payload);
+    // Subscribe to the configured streams, the replies are matched by the engine
+    for frame in engine.take_subscription_frames() {
+        conn.as_mut()
+            .send(Message::Text(frame.to_json()))
+            .await
+            .expect("Failed to subscribe");
+    }
 
-                handle_payload(payload, &mut orderbook);
-                log::info!("{:?}", orderbook);
+    // Ctrl-C stops the socket reader, the queued payloads and the engine are drained before
+    // the process exits
+    let shutdown = Shutdown::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                log::info!("Shutting down");
+                shutdown.trigger();
             }
-            Err(_) => {
-                log::error!("Broken message received from the socket, stopping execution");
+        }
+    });
+
+    // socket reader -> feed -> engine -> snapshots -> display, every queue is bounded
+    let stages = engine.config().pipeline.clone();
+    let (feed, mut payloads) =
+        pipeline::conflating_channel("feed", stages.feed, |payload: &Vec<u8>| {
+            pipeline::stream_key(payload)
+        });
+    let (display, mut snapshots) =
+        pipeline::conflating_channel("snapshots", stages.snapshots, |snapshot: &DepthSnapshot| {
+            Some(snapshot.symbol.id().into())
+        });
+    let queues = [payloads.monitor(), snapshots.monitor()];
+
+    let socket = shutdown.register("socket");
+    tokio::spawn(async move {
+        let mut monitor = ConnectionMonitor::default();
+        monitor.on_connected();
+        loop {
+            let message = tokio::select! {
+                message = conn.as_mut().next() => message,
+                _ = socket.signal().triggered() => None,
+            };
+            let Some(message) = message else {
                 break;
+            };
+            let payload = match message {
+                // tungstenite queues the pong itself
+                Ok(message) if message.is_ping() => {
+                    monitor.on_ping();
+                    monitor.on_pong_sent();
+                    None
+                }
+                Ok(message) => {
+                    monitor.on_message();
+                    let binary_data = message.into_data();
+                    log::debug!("{:?}", String::from_utf8_lossy(&binary_data));
+                    Some(binary_data)
+                }
+                Err(_) => {
+                    log::error!("Broken message received from the socket, stopping execution");
+                    monitor.on_disconnected();
+                    break;
+                }
+            };
+            monitor.check();
+            for event in monitor.drain_events() {
+                match event {
+                    ConnectionEvent::ReconnectRequested { reason, .. } => {
+                        log::warn!("Reconnect requested: {:?}", reason)
+                    }
+                    event => log::info!("{:?}", event),
+                }
+            }
+            // Waits here while a Block feed is full, i.e. the socket is not read any further
+            if let Some(payload) = payload {
+                if feed.send(payload).await.is_err() {
+                    break;
+                }
             }
         }
-    }
+        // Ends the engine loop once the queued payloads are applied
+        drop(feed);
+        if let Err(error) = conn.close().await {
+            log::warn!("Failed to disconnect: {}", error);
+        }
+        drop(socket);
+    });
 
-    // Disconnect
-    conn.close().await.expect("Failed to disconnect");
-}
+    let screen = shutdown.register("display");
+    tokio::spawn(async move {
+        while let Some(snapshot) = snapshots.recv().await {
+            show(&snapshot);
+        }
+        drop(screen);
+    });
 
-// EXTENSION: It should be easy to create multiplexed stream with subscription on different pairs and handle here,
-// by extending DepthUpdateEnvelope struct to understand what stream it is operating on.
-fn handle_payload(payload: &str, orderbook: &mut orderbook::OrderBook) {
-    match serde_json::from_str::<binance_payloads::DepthUpdateEnvelope>(payload) {
-        Ok(depth_update) => {
-            log::debug!("{:?}", depth_update);
-            orderbook.update_depth(&depth_update.data);
+    let clock = clock::system();
+    let mut last_report = Instant::now();
+    while let Some(payload) = payloads.recv().await {
+        if let Some(snapshot) = engine.on_payload(clock.stamp(), &payload) {
+            let _ = display.send(snapshot).await;
+        }
+        for (symbol, signal) in engine.drain_signals() {
+            log::debug!("{} order flow {:?}", symbol, signal);
         }
-        Err(_) => match serde_json::from_str::<binance_payloads::BookTickerUpdateEnvelope>(payload)
+        for (symbol, alert) in engine.drain_alerts() {
+            log::warn!("{} {}", symbol, alert.kind);
+        }
+        if engine
+            .report_interval()
+            .is_some_and(|interval| last_report.elapsed() >= interval)
         {
-            Ok(book_ticker_update) => {
-                log::debug!("{:?}", book_ticker_update);
-                orderbook.update_book_ticker(&book_ticker_update.data);
+            last_report = Instant::now();
+            report(&engine, &queues);
+        }
+    }
+    match engine.close() {
+        Ok(final_snapshots) => {
+            for snapshot in final_snapshots {
+                let _ = display.send(snapshot).await;
             }
-            Err(_) => log::error!("Unrecognized websocket message"),
-        },
+        }
+        Err(error) => log::error!("Cannot flush the recording: {}", error),
+    }
+    report(&engine, &queues);
+    drop(display);
+    shutdown.shutdown().await;
+}
+
+fn report(engine: &Engine, queues: &[QueueMonitor]) {
+    for metrics in queues.iter().filter_map(QueueMonitor::metrics) {
+        log::info!("Queue {}", metrics);
+    }
+    for symbol in engine.manager().symbols() {
+        if let Some(returns) = engine.returns(symbol) {
+            log::info!("{} {}", symbol, returns);
+        }
+        if let Some(alerts) = engine.alerts(symbol) {
+            log::info!("{} {}", symbol, alerts.counts());
+        }
+    }
+    let Some(latency) = engine.latency() else {
+        return;
     };
+    for stage in [LatencyStage::Network, LatencyStage::FeedHandler] {
+        if let Some(statistics) = latency.statistics(stage) {
+            log::info!("{:?} latency (ns): {:?}", stage, statistics);
+        }
+    }
+}
+
+// Clears the terminal and prints the ladder from the top left corner
+#[cfg(feature = "demo-output")]
+fn show(snapshot: &DepthSnapshot) {
+    print!("\x1B[2J\x1B[H{}", snapshot);
+    let _ = io::stdout().flush();
+}
+
+#[cfg(not(feature = "demo-output"))]
+fn show(snapshot: &DepthSnapshot) {
+    log::info!("\n{}", snapshot);
 }
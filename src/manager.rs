@@ -0,0 +1,498 @@
+/// Books for several symbols fed from one combined websocket connection.
+/// Depth payloads carry no symbol, they are routed by the stream name of the combined stream
+/// envelope (`<symbol>@depth...`), book ticker payloads by their symbol field. Payloads for
+/// symbols the manager does not follow are ignored. Streams can be subscribed and unsubscribed
+/// at runtime, the manager follows the symbols that have a depth or book ticker stream.
+///
+/// `persist(dir)` writes every book to `<dir>/<SYMBOL>.json` and the streams and sync metadata
+/// to `<dir>/manager.json`, `load(dir)` restores them after a restart together with the frames
+/// that subscribe to the same streams again. A restored book keeps serving its persisted levels
+/// until the first payload of its symbol arrives, which is taken as a fresh exchange snapshot:
+/// the book is compared with it, the differences are reported as a `Reconciliation` and the book
+/// continues from the snapshot.
+use crate::binance_payloads::{BookTickerUpdateEnvelopeRef, DepthUpdate, DepthUpdateEnvelopeRef};
+use crate::instruments::Instrument;
+use crate::orderbook::{OrderBook, Price, Quantity};
+use crate::orderbookv2::Side;
+use crate::price_levels::BookBackend;
+use crate::subscriptions::{StreamKind, SubscriptionFrame, Subscriptions};
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const MANIFEST: &str = "manager.json";
+
+#[derive(Debug, Default)]
+pub struct OrderBookManager {
+    books: HashMap<Symbol, OrderBook>,
+    backend: BookBackend,
+    subscriptions: Subscriptions,
+    // Restored books waiting for their first fresh payload
+    restored: HashSet<Symbol>,
+    reconciliations: Vec<Reconciliation>,
+}
+
+// Sync state of one persisted book, the levels are in the book's own file
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedBook {
+    symbol: Symbol,
+    file: String,
+    last_update_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    backend: BookBackend,
+    streams: Vec<String>,
+    books: Vec<PersistedBook>,
+}
+
+// A restored book compared with the first exchange snapshot after the restart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    pub symbol: Symbol,
+    pub persisted_update_id: u64,
+    pub snapshot_update_id: u64,
+    // Levels within the price range of the snapshot that differ, as (side, price, persisted
+    // quantity, snapshot quantity) with a zero quantity for an absent level
+    pub diverged: Vec<(Side, Price, Quantity, Quantity)>,
+}
+
+impl Reconciliation {
+    pub fn is_consistent(&self) -> bool {
+        self.diverged.is_empty()
+    }
+}
+
+impl OrderBookManager {
+    pub fn new() -> OrderBookManager {
+        OrderBookManager::default()
+    }
+
+    // Level storage of the books added from now on
+    pub fn with_backend(backend: BookBackend) -> OrderBookManager {
+        OrderBookManager {
+            books: HashMap::new(),
+            backend,
+            subscriptions: Subscriptions::new(),
+            restored: HashSet::new(),
+            reconciliations: Vec::new(),
+        }
+    }
+
+    // Writes every book and the streams to `dir`, which is created when missing. Each file is
+    // replaced in one rename, the manifest last, so a crash leaves the previous state loadable.
+    pub fn persist(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut books = Vec::new();
+        for (symbol, book) in &self.books {
+            let file = format!("{}.json", symbol);
+            write_json(&dir.join(&file), book)?;
+            books.push(PersistedBook {
+                symbol: *symbol,
+                file,
+                last_update_id: book.last_update_id(),
+            });
+        }
+        books.sort_by(|a, b| a.symbol.as_str().cmp(b.symbol.as_str()));
+        let manifest = Manifest {
+            backend: self.backend,
+            streams: self.subscriptions.active().map(str::to_string).collect(),
+            books,
+        };
+        write_json(&dir.join(MANIFEST), &manifest)
+    }
+
+    // Restores a manager written by `persist`, with the frames to send on the new connection
+    // to subscribe to the persisted streams again
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<(OrderBookManager, Vec<SubscriptionFrame>)> {
+        let dir = dir.as_ref();
+        let manifest: Manifest = read_json(&dir.join(MANIFEST))?;
+        let mut manager = OrderBookManager::with_backend(manifest.backend);
+        for persisted in manifest.books {
+            let book: OrderBook = read_json(&dir.join(&persisted.file))?;
+            if book.symbol() != persisted.symbol
+                || book.last_update_id() != persisted.last_update_id
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not match the manifest", persisted.file),
+                ));
+            }
+            manager.restored.insert(persisted.symbol);
+            manager.books.insert(persisted.symbol, book);
+        }
+
+        // One frame per symbol, like the subscriptions that were made originally
+        let mut streams: BTreeMap<&str, Vec<StreamKind>> = BTreeMap::new();
+        for stream in &manifest.streams {
+            match StreamKind::parse(stream) {
+                Some((symbol, kind)) => streams.entry(symbol.as_str()).or_default().push(kind),
+                None => log::error!("Cannot resubscribe to unknown stream {}", stream),
+            }
+        }
+        let frames = streams
+            .into_iter()
+            .filter_map(|(symbol, kinds)| manager.subscribe(symbol, &kinds))
+            .collect();
+        // Books whose streams are gone were already dropped by `subscribe`
+        let followed: HashSet<Symbol> = manager.books.keys().copied().collect();
+        manager.restored.retain(|symbol| followed.contains(symbol));
+        Ok((manager, frames))
+    }
+
+    // Restored books that did not see a fresh payload yet
+    pub fn awaiting_reconciliation(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.restored.iter().copied()
+    }
+
+    // Reconciliations of restored books since the last call
+    pub fn drain_reconciliations(&mut self) -> Vec<Reconciliation> {
+        std::mem::take(&mut self.reconciliations)
+    }
+
+    // Starts following the symbol, keeps the book when it is already followed
+    pub fn add_symbol(&mut self, symbol: impl Into<Symbol>) -> &mut OrderBook {
+        let symbol = symbol.into();
+        let backend = self.backend;
+        self.books
+            .entry(symbol)
+            .or_insert_with(|| OrderBook::with_backend(symbol, backend))
+    }
+
+    // Same as `add_symbol`, with the book's prices and quantities on the instrument's grid
+    pub fn add_instrument(&mut self, instrument: &Instrument) -> &mut OrderBook {
+        let book = self.add_symbol(instrument.symbol);
+        book.set_converter(instrument.converter());
+        book
+    }
+
+    pub fn remove_symbol(&mut self, symbol: Symbol) -> Option<OrderBook> {
+        self.books.remove(&symbol)
+    }
+
+    pub fn book(&self, symbol: Symbol) -> Option<&OrderBook> {
+        self.books.get(&symbol)
+    }
+
+    pub fn book_mut(&mut self, symbol: Symbol) -> Option<&mut OrderBook> {
+        self.books.get_mut(&symbol)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.books.keys().copied()
+    }
+
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    // Frame to send on the connection, the book is followed right away so the first payloads
+    // after the reply are not dropped
+    pub fn subscribe(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        kinds: &[StreamKind],
+    ) -> Option<SubscriptionFrame> {
+        let frame = self.subscriptions.subscribe(symbol.into(), kinds)?;
+        self.sync_book(frame.symbol);
+        Some(frame)
+    }
+
+    // The book is dropped with the last stream that feeds it
+    pub fn unsubscribe(
+        &mut self,
+        symbol: impl Into<Symbol>,
+        kinds: &[StreamKind],
+    ) -> Option<SubscriptionFrame> {
+        let frame = self.subscriptions.unsubscribe(symbol.into(), kinds)?;
+        self.sync_book(frame.symbol);
+        Some(frame)
+    }
+
+    fn sync_book(&mut self, symbol: Symbol) {
+        if self.subscriptions.feeds_book(symbol) {
+            self.add_symbol(symbol);
+        } else {
+            self.remove_symbol(symbol);
+        }
+    }
+
+    // Stream names are the lowercase symbol followed by the stream type
+    pub fn symbol_for_stream(&self, stream: &str) -> Option<Symbol> {
+        let name = stream.split('@').next()?;
+        self.symbols()
+            .find(|symbol| symbol.as_str().eq_ignore_ascii_case(name))
+    }
+
+    // Applies a combined stream payload, returns the symbol whose book changed
+    pub fn apply_payload(&mut self, payload: &[u8]) -> Option<Symbol> {
+        if let Ok(envelope) = serde_json::from_slice::<DepthUpdateEnvelopeRef>(payload) {
+            let symbol = self.symbol_for_stream(envelope.stream)?;
+            let depth_update = match envelope.data.to_owned() {
+                Ok(depth_update) => depth_update,
+                Err(error) => {
+                    log::error!("Invalid depth update for {}: {}", symbol, error);
+                    return None;
+                }
+            };
+            let book = self.books.get_mut(&symbol)?;
+            if self.restored.remove(&symbol) {
+                let mut fresh = OrderBook::new(symbol);
+                fresh.set_converter(book.converter());
+                fresh.update_depth(&depth_update);
+                self.reconciliations
+                    .push(reconcile(book, &fresh, depth_update.last_update_id));
+                book.replace_depth(&depth_update);
+            } else {
+                book.update_depth(&depth_update);
+            }
+            return Some(symbol);
+        }
+
+        match serde_json::from_slice::<BookTickerUpdateEnvelopeRef>(payload) {
+            Ok(envelope) => {
+                let symbol = Symbol::lookup(envelope.data.symbol)?;
+                let book = self.books.get_mut(&symbol)?;
+                if self.restored.remove(&symbol) {
+                    // Only the top of each side can be checked against a ticker
+                    let mut fresh = OrderBook::new(symbol);
+                    fresh.set_converter(book.converter());
+                    // the persisted levels are dropped and the book continues from the ticker
+                    if fresh.update_book_ticker_ref(&envelope.data).is_ok() {
+                        self.reconciliations
+                            .push(reconcile(book, &fresh, envelope.data.update_id));
+                        book.replace_depth(&DepthUpdate {
+                            event_time: None,
+                            last_update_id: book.last_update_id(),
+                            bids: Vec::new(),
+                            asks: Vec::new(),
+                        });
+                    }
+                }
+                match book.update_book_ticker_ref(&envelope.data) {
+                    Ok(_) => Some(symbol),
+                    Err(error) => {
+                        log::error!("Invalid book ticker update for {}: {}", symbol, error);
+                        None
+                    }
+                }
+            }
+            Err(_) => {
+                match self.subscriptions.on_response(payload) {
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => {
+                        log::error!("{}", error);
+                        self.sync_book(error.frame.symbol);
+                    }
+                    None => log::error!("Unrecognized websocket message"),
+                }
+                None
+            }
+        }
+    }
+}
+
+// Levels of `book` that differ from `fresh` within the price range `fresh` covers
+fn reconcile(book: &OrderBook, fresh: &OrderBook, snapshot_update_id: u64) -> Reconciliation {
+    let mut diverged = Vec::new();
+    for side in [Side::Buy, Side::Sell] {
+        let (persisted, snapshot): (Vec<_>, Vec<_>) = match side {
+            Side::Buy => (book.bids().collect(), fresh.bids().collect()),
+            Side::Sell => (book.asks().collect(), fresh.asks().collect()),
+        };
+        let Some(&(worst, _)) = snapshot.last() else {
+            continue;
+        };
+        let in_range = |price: Price| match side {
+            Side::Buy => price >= worst,
+            Side::Sell => price <= worst,
+        };
+        let mut levels: BTreeMap<Price, (Quantity, Quantity)> = BTreeMap::new();
+        for (price, quantity) in persisted.into_iter().filter(|(price, _)| in_range(*price)) {
+            levels.entry(price).or_insert((Quantity(0), Quantity(0))).0 = quantity;
+        }
+        for (price, quantity) in snapshot {
+            levels.entry(price).or_insert((Quantity(0), Quantity(0))).1 = quantity;
+        }
+        diverged.extend(
+            levels
+                .into_iter()
+                .filter(|(_, (persisted, snapshot))| persisted != snapshot)
+                .map(|(price, (persisted, snapshot))| (side, price, persisted, snapshot)),
+        );
+    }
+    Reconciliation {
+        symbol: book.symbol(),
+        persisted_update_id: book.last_update_id(),
+        snapshot_update_id,
+        diverged,
+    }
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let partial = path.with_extension("json.partial");
+    {
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        serde_json::to_writer(&mut writer, value)?;
+        writer.flush()?;
+    }
+    fs::rename(&partial, path)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Price, Quantity};
+
+    #[test]
+    fn test_payloads_are_routed_by_symbol() {
+        let mut manager = OrderBookManager::new();
+        let bnb = Symbol::intern("BNBUSDT");
+        let eth = Symbol::intern("ETHUSDC");
+        manager.add_symbol(bnb);
+        manager.add_symbol("ETHUSDC");
+
+        let depth = br#"{"stream":"ethusdc@depth5@100ms","data":{"lastUpdateId":7,"bids":[["3456.78","1.5"]],"asks":[]}}"#;
+        assert_eq!(manager.apply_payload(depth), Some(eth));
+        let ticker = br#"{"stream":"bnbusdt@bookTicker","data":{"u":1,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}}"#;
+        assert_eq!(manager.apply_payload(ticker), Some(bnb));
+
+        assert_eq!(
+            manager.book(eth).unwrap().bids().collect::<Vec<_>>(),
+            vec![(Price(34_567_800), Quantity(15_000))]
+        );
+        assert_eq!(
+            manager.book(bnb).unwrap().snapshot(1).asks,
+            vec![(Price(253_600), Quantity(406_600))]
+        );
+
+        // Not followed
+        let other = br#"{"stream":"btcusdt@depth5","data":{"lastUpdateId":1,"bids":[],"asks":[]}}"#;
+        assert_eq!(manager.apply_payload(other), None);
+        assert_eq!(manager.apply_payload(b"{}"), None);
+
+        assert!(manager.remove_symbol(eth).is_some());
+        assert_eq!(manager.apply_payload(depth), None);
+        assert_eq!(manager.symbols().collect::<Vec<_>>(), vec![bnb]);
+    }
+
+    #[test]
+    fn test_books_follow_subscriptions() {
+        let mut manager = OrderBookManager::new();
+        let sol = Symbol::intern("SOLUSDT");
+        let depth = StreamKind::PartialDepth { levels: 5 };
+
+        let frame = manager.subscribe(sol, &[StreamKind::Trade]).unwrap();
+        assert_eq!(frame.params, vec!["solusdt@trade"]);
+        assert!(manager.book(sol).is_none());
+        assert_eq!(manager.apply_payload(br#"{"result":null,"id":1}"#), None);
+        assert_eq!(manager.subscriptions().pending(), 0);
+
+        manager
+            .subscribe(sol, &[depth, StreamKind::BookTicker])
+            .unwrap();
+        let payload = br#"{"stream":"solusdt@depth5@100ms","data":{"lastUpdateId":3,"bids":[["150.1","2"]],"asks":[]}}"#;
+        assert_eq!(manager.apply_payload(payload), Some(sol));
+
+        manager.unsubscribe(sol, &[depth]).unwrap();
+        assert!(manager.book(sol).is_some());
+        // Rejected, the book ticker stream is active again
+        manager.unsubscribe(sol, &[StreamKind::BookTicker]).unwrap();
+        assert!(manager.book(sol).is_none());
+        manager.apply_payload(br#"{"error":{"code":3,"msg":"Invalid JSON"},"id":4}"#);
+        assert!(manager
+            .subscriptions()
+            .is_active(sol, StreamKind::BookTicker));
+        assert!(manager.book(sol).is_some());
+    }
+
+    #[test]
+    fn test_persisted_books_are_reconciled_after_load() {
+        let dir = std::env::temp_dir().join(format!("manager-{}", std::process::id()));
+        let eth = Symbol::intern("ETHUSDC");
+        let bnb = Symbol::intern("BNBUSDT");
+        let mut manager = OrderBookManager::new();
+        manager
+            .subscribe(
+                eth,
+                &[StreamKind::PartialDepth { levels: 5 }, StreamKind::Trade],
+            )
+            .unwrap();
+        manager.subscribe(bnb, &[StreamKind::BookTicker]).unwrap();
+        let depth = br#"{"stream":"ethusdc@depth5@100ms","data":{"lastUpdateId":7,"bids":[["3000.5","1.5"],["3000","2"]],"asks":[["3001","1"],["3002","4"]]}}"#;
+        manager.apply_payload(depth);
+        let ticker = br#"{"stream":"bnbusdt@bookTicker","data":{"u":5,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}}"#;
+        manager.apply_payload(ticker);
+        manager.persist(&dir).unwrap();
+
+        let (mut restored, frames) = OrderBookManager::load(&dir).unwrap();
+        let mut params: Vec<String> = frames.into_iter().flat_map(|frame| frame.params).collect();
+        params.sort();
+        assert_eq!(
+            params,
+            vec![
+                "bnbusdt@bookTicker",
+                "ethusdc@depth5@100ms",
+                "ethusdc@trade"
+            ]
+        );
+        for symbol in [eth, bnb] {
+            assert_eq!(
+                restored.book(symbol).unwrap().snapshot(usize::MAX),
+                manager.book(symbol).unwrap().snapshot(usize::MAX)
+            );
+        }
+        let mut awaiting: Vec<&str> = restored
+            .awaiting_reconciliation()
+            .map(|symbol| symbol.as_str())
+            .collect();
+        awaiting.sort();
+        assert_eq!(awaiting, vec!["BNBUSDT", "ETHUSDC"]);
+
+        // The best bid was lifted while the process was down, 3002 is out of the snapshot's range
+        let fresh = br#"{"stream":"ethusdc@depth5@100ms","data":{"lastUpdateId":40,"bids":[["3000","2"]],"asks":[["3001","1.2"]]}}"#;
+        assert_eq!(restored.apply_payload(fresh), Some(eth));
+        let same = br#"{"stream":"bnbusdt@bookTicker","data":{"u":41,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}}"#;
+        assert_eq!(restored.apply_payload(same), Some(bnb));
+        let reconciliations = restored.drain_reconciliations();
+        assert_eq!(
+            reconciliations[0],
+            Reconciliation {
+                symbol: eth,
+                persisted_update_id: 7,
+                snapshot_update_id: 40,
+                diverged: vec![
+                    (Side::Buy, Price(30_005_000), Quantity(15_000), Quantity(0)),
+                    (
+                        Side::Sell,
+                        Price(30_010_000),
+                        Quantity(10_000),
+                        Quantity(12_000)
+                    ),
+                ],
+            }
+        );
+        assert!(reconciliations[1].is_consistent());
+        assert_eq!(restored.awaiting_reconciliation().count(), 0);
+
+        // The books continue from the snapshot
+        assert_eq!(
+            restored.book(eth).unwrap().snapshot(usize::MAX).asks,
+            vec![(Price(30_010_000), Quantity(12_000))]
+        );
+        assert_eq!(
+            restored.book(bnb).unwrap().snapshot(usize::MAX).bids.len(),
+            1
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+/// Market data published by the matching engine.
+/// Consumers start from a `BookSnapshot` and apply the `LevelDelta`s that follow it in
+/// sequence order. A delta carries the new aggregate quantity of a level, zero removes it,
+/// which is the same model the L2 `orderbook::OrderBook` applies depth updates with.
+use crate::binance_payloads::DepthUpdate;
+use crate::orderbookv2::{LevelInfo, Liquidity, Price, Quantity, Side, Timestamp, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub sequence: u64,
+    // best first on both sides
+    pub bids: Vec<LevelInfo>,
+    pub asks: Vec<LevelInfo>,
+}
+
+// Engine ticks and lots become whole units of price and quantity in the depth update
+impl BookSnapshot {
+    pub fn to_depth_update(&self) -> DepthUpdate {
+        let levels = |levels: &[LevelInfo]| {
+            levels
+                .iter()
+                .map(|level| (level.price.to_f64(), level.quantity.to_f64()))
+                .collect()
+        };
+        DepthUpdate {
+            event_time: None,
+            last_update_id: self.sequence,
+            bids: levels(&self.bids),
+            asks: levels(&self.asks),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelDelta {
+    pub sequence: u64,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+impl LevelDelta {
+    pub fn to_depth_update(&self) -> DepthUpdate {
+        let level = vec![(self.price.to_f64(), self.quantity.to_f64())];
+        let (bids, asks) = match self.side {
+            Side::Buy => (level, vec![]),
+            Side::Sell => (vec![], level),
+        };
+        DepthUpdate {
+            event_time: None,
+            last_update_id: self.sequence,
+            bids,
+            asks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradePrint {
+    pub sequence: u64,
+    pub timestamp: Timestamp,
+    pub price: Price,
+    pub quantity: Quantity,
+    // Side of the incoming order, None for auction trades
+    pub aggressor: Option<Side>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketDataMessage {
+    Snapshot(BookSnapshot),
+    Delta(LevelDelta),
+    // Published ahead of the deltas the trade caused
+    Trade(TradePrint),
+}
+
+impl MarketDataMessage {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            MarketDataMessage::Snapshot(snapshot) => snapshot.sequence,
+            MarketDataMessage::Delta(delta) => delta.sequence,
+            MarketDataMessage::Trade(trade) => trade.sequence,
+        }
+    }
+}
+
+// Remembers the last published state of the book and turns every change into deltas
+#[derive(Debug, Clone, Default)]
+pub struct MarketDataPublisher {
+    sequence: u64,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+    messages: Vec<MarketDataMessage>,
+}
+
+impl MarketDataPublisher {
+    pub fn new() -> MarketDataPublisher {
+        MarketDataPublisher::default()
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // State as of the last published message, for consumers joining late
+    pub fn snapshot(&self) -> BookSnapshot {
+        let level = |(&price, &quantity)| LevelInfo { price, quantity };
+        BookSnapshot {
+            sequence: self.sequence,
+            bids: self.bids.iter().rev().map(level).collect(),
+            asks: self.asks.iter().map(level).collect(),
+        }
+    }
+
+    // Takes the current state of the book, the first call queues a snapshot and every
+    // following one the deltas against the previous state
+    pub fn publish(
+        &mut self,
+        bids: impl Iterator<Item = (Price, Quantity)>,
+        asks: impl Iterator<Item = (Price, Quantity)>,
+    ) {
+        let bids: BTreeMap<Price, Quantity> = bids.collect();
+        let asks: BTreeMap<Price, Quantity> = asks.collect();
+
+        if self.sequence == 0 {
+            self.sequence = 1;
+            self.bids = bids;
+            self.asks = asks;
+            self.messages
+                .push(MarketDataMessage::Snapshot(self.snapshot()));
+            return;
+        }
+
+        let previous_bids = std::mem::replace(&mut self.bids, bids);
+        let previous_asks = std::mem::replace(&mut self.asks, asks);
+        self.diff(Side::Buy, &previous_bids);
+        self.diff(Side::Sell, &previous_asks);
+    }
+
+    fn diff(&mut self, side: Side, previous: &BTreeMap<Price, Quantity>) {
+        let current = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+
+        let mut changes: Vec<(Price, Quantity)> = previous
+            .keys()
+            .filter(|price| !current.contains_key(price))
+            .map(|&price| (price, Quantity::ZERO))
+            .collect();
+        changes.extend(
+            current
+                .iter()
+                .filter(|(price, quantity)| previous.get(price) != Some(quantity))
+                .map(|(&price, &quantity)| (price, quantity)),
+        );
+        changes.sort_unstable_by_key(|&(price, _)| price);
+
+        for (price, quantity) in changes {
+            self.sequence += 1;
+            self.messages.push(MarketDataMessage::Delta(LevelDelta {
+                sequence: self.sequence,
+                side,
+                price,
+                quantity,
+            }));
+        }
+    }
+
+    pub fn publish_trade(&mut self, timestamp: Timestamp, trade: &Trade) {
+        let aggressor = match (trade.bid_trade.liquidity, trade.ask_trade.liquidity) {
+            (Liquidity::Taker, _) => Some(Side::Buy),
+            (_, Liquidity::Taker) => Some(Side::Sell),
+            _ => None,
+        };
+        self.sequence += 1;
+        self.messages.push(MarketDataMessage::Trade(TradePrint {
+            sequence: self.sequence,
+            timestamp,
+            price: trade.bid_trade.price,
+            quantity: trade.bid_trade.quantity,
+            aggressor,
+        }));
+    }
+
+    pub fn drain(&mut self) -> Vec<MarketDataMessage> {
+        std::mem::take(&mut self.messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{Order, OrderBook, OrderType};
+
+    fn delta(sequence: u64, side: Side, price: Price, quantity: Quantity) -> MarketDataMessage {
+        MarketDataMessage::Delta(LevelDelta {
+            sequence,
+            side,
+            price,
+            quantity,
+        })
+    }
+
+    #[test]
+    fn test_snapshot_then_deltas() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.enable_market_data();
+
+        assert_eq!(
+            orderbook.drain_market_data(),
+            vec![MarketDataMessage::Snapshot(BookSnapshot {
+                sequence: 1,
+                bids: vec![LevelInfo {
+                    price: Price(100),
+                    quantity: Quantity(5)
+                }],
+                asks: vec![],
+            })]
+        );
+
+        orderbook.add_order(Order::new(
+            2,
+            Price(100),
+            Quantity(3),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(102),
+            Quantity(4),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            4,
+            Price(99),
+            Quantity(10),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.cancel_order(3).unwrap();
+
+        assert_eq!(
+            orderbook.drain_market_data(),
+            vec![
+                delta(2, Side::Buy, Price(100), Quantity(8)),
+                delta(3, Side::Sell, Price(102), Quantity(4)),
+                MarketDataMessage::Trade(TradePrint {
+                    sequence: 4,
+                    timestamp: 0,
+                    price: Price(100),
+                    quantity: Quantity(5),
+                    aggressor: Some(Side::Sell),
+                }),
+                MarketDataMessage::Trade(TradePrint {
+                    sequence: 5,
+                    timestamp: 0,
+                    price: Price(100),
+                    quantity: Quantity(3),
+                    aggressor: Some(Side::Sell),
+                }),
+                delta(6, Side::Buy, Price(100), Quantity(0)),
+                delta(7, Side::Sell, Price(99), Quantity(2)),
+                delta(8, Side::Sell, Price(102), Quantity(0)),
+            ]
+        );
+        assert_eq!(orderbook.drain_market_data(), vec![]);
+    }
+
+    #[test]
+    fn test_snapshot_for_late_consumers() {
+        let mut orderbook = OrderBook::new();
+        orderbook.enable_market_data();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(101),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(103),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        let snapshot = orderbook.market_data_snapshot().unwrap();
+        assert_eq!(snapshot.sequence, 4);
+        assert_eq!(
+            snapshot.bids,
+            orderbook.get_orderbook_level_infos().get_bids().clone()
+        );
+        assert_eq!(
+            snapshot.asks,
+            orderbook.get_orderbook_level_infos().get_asks().clone()
+        );
+    }
+}
@@ -0,0 +1,322 @@
+/// Surveillance of the L2 update stream for quote stuffing and flickering levels.
+/// `MarketQualityMonitor` observes the book after every update and compares its top levels with
+/// the previous observation. Within a rolling window it flags:
+/// - a rate of level additions and removals above `max_level_changes_per_second` (stuffing),
+/// - a price level added and removed again more than `max_level_toggles` times (flicker),
+/// - more than `max_quote_reversals` back and forth moves of a best price, each within
+///   `oscillation_bps` of the price (oscillation).
+/// Each condition raises one `MarketQualityAlert` when it starts and is armed again once it
+/// clears, so a burst yields a single alert instead of one per update.
+use crate::orderbook::{OrderBook, Price};
+use crate::orderbookv2::{Side, Timestamp};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityThresholds {
+    pub window: Duration,
+    // Levels of each side compared between observations
+    pub levels: usize,
+    pub max_level_changes_per_second: f64,
+    pub max_level_toggles: usize,
+    pub max_quote_reversals: usize,
+    pub oscillation_bps: f64,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        QualityThresholds {
+            window: Duration::from_secs(1),
+            levels: 10,
+            max_level_changes_per_second: 500.0,
+            max_level_toggles: 10,
+            max_quote_reversals: 10,
+            oscillation_bps: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum MarketQualityAlert {
+    QuoteStuffing {
+        timestamp: Timestamp,
+        changes_per_second: f64,
+    },
+    FlickeringLevel {
+        timestamp: Timestamp,
+        side: Side,
+        price: f64,
+        toggles: usize,
+    },
+    QuoteOscillation {
+        timestamp: Timestamp,
+        side: Side,
+        reversals: usize,
+    },
+}
+
+// Drops the events that left the window
+fn evict(events: &mut VecDeque<Timestamp>, timestamp: Timestamp, window: Timestamp) {
+    while events
+        .front()
+        .is_some_and(|&oldest| timestamp.saturating_sub(oldest) >= window)
+    {
+        events.pop_front();
+    }
+}
+
+// State of one side of the book, bids first in the arrays of the monitor
+#[derive(Debug, Clone, Default)]
+struct SideState {
+    levels: HashSet<Price>,
+    toggles: HashMap<Price, VecDeque<Timestamp>>,
+    flickering: HashSet<Price>,
+    best: Option<Price>,
+    direction: Option<Ordering>,
+    reversals: VecDeque<Timestamp>,
+    oscillating: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MarketQualityMonitor {
+    thresholds: QualityThresholds,
+    sides: [SideState; 2],
+    changes: VecDeque<Timestamp>,
+    stuffing: bool,
+    alerts: usize,
+}
+
+impl Default for MarketQualityMonitor {
+    fn default() -> Self {
+        MarketQualityMonitor::new(QualityThresholds::default())
+    }
+}
+
+impl MarketQualityMonitor {
+    pub fn new(thresholds: QualityThresholds) -> MarketQualityMonitor {
+        MarketQualityMonitor {
+            thresholds,
+            sides: Default::default(),
+            changes: VecDeque::new(),
+            stuffing: false,
+            alerts: 0,
+        }
+    }
+
+    pub fn thresholds(&self) -> &QualityThresholds {
+        &self.thresholds
+    }
+
+    // Alerts raised since the start
+    pub fn alert_count(&self) -> usize {
+        self.alerts
+    }
+
+    // Alerts that started with this update
+    pub fn observe(&mut self, timestamp: Timestamp, book: &OrderBook) -> Vec<MarketQualityAlert> {
+        let thresholds = self.thresholds;
+        let window = thresholds.window.as_nanos() as Timestamp;
+        let converter = book.converter();
+        let mut alerts = Vec::new();
+
+        for (state, side) in self.sides.iter_mut().zip([Side::Buy, Side::Sell]) {
+            let levels: HashSet<Price> = match side {
+                Side::Buy => book
+                    .bids()
+                    .take(thresholds.levels)
+                    .map(|(p, _)| p)
+                    .collect(),
+                Side::Sell => book
+                    .asks()
+                    .take(thresholds.levels)
+                    .map(|(p, _)| p)
+                    .collect(),
+            };
+            // The first observation of a side sets the reference
+            let changed: Vec<Price> = if state.levels.is_empty() && state.best.is_none() {
+                Vec::new()
+            } else {
+                state
+                    .levels
+                    .symmetric_difference(&levels)
+                    .copied()
+                    .collect()
+            };
+            for price in changed {
+                self.changes.push_back(timestamp);
+                state.toggles.entry(price).or_default().push_back(timestamp);
+            }
+            state.levels = levels;
+
+            state.toggles.retain(|price, toggles| {
+                evict(toggles, timestamp, window);
+                if toggles.len() <= thresholds.max_level_toggles {
+                    state.flickering.remove(price);
+                }
+                !toggles.is_empty()
+            });
+            for (price, toggles) in &state.toggles {
+                if toggles.len() > thresholds.max_level_toggles && state.flickering.insert(*price) {
+                    alerts.push(MarketQualityAlert::FlickeringLevel {
+                        timestamp,
+                        side,
+                        price: converter.to_f64(*price),
+                        toggles: toggles.len(),
+                    });
+                }
+            }
+
+            let best = match side {
+                Side::Buy => book.best_bid(),
+                Side::Sell => book.best_ask(),
+            }
+            .map(|(price, _)| price);
+            if let (Some(previous), Some(current)) = (state.best, best) {
+                if previous != current {
+                    let direction = current.cmp(&previous);
+                    let (low, high) = (previous.min(current), previous.max(current));
+                    let step = converter.to_f64(high) - converter.to_f64(low);
+                    let small =
+                        step <= converter.to_f64(low) * thresholds.oscillation_bps / 10_000.0;
+                    if small && state.direction.is_some_and(|last| last != direction) {
+                        state.reversals.push_back(timestamp);
+                    }
+                    state.direction = Some(direction);
+                }
+            }
+            if best.is_some() {
+                state.best = best;
+            }
+            evict(&mut state.reversals, timestamp, window);
+            let reversals = state.reversals.len();
+            match (
+                reversals > thresholds.max_quote_reversals,
+                state.oscillating,
+            ) {
+                (true, false) => {
+                    state.oscillating = true;
+                    alerts.push(MarketQualityAlert::QuoteOscillation {
+                        timestamp,
+                        side,
+                        reversals,
+                    });
+                }
+                (false, true) => state.oscillating = false,
+                _ => {}
+            }
+        }
+
+        evict(&mut self.changes, timestamp, window);
+        let changes_per_second = self.changes.len() as f64 / thresholds.window.as_secs_f64();
+        match (
+            changes_per_second > thresholds.max_level_changes_per_second,
+            self.stuffing,
+        ) {
+            (true, false) => {
+                self.stuffing = true;
+                alerts.push(MarketQualityAlert::QuoteStuffing {
+                    timestamp,
+                    changes_per_second,
+                });
+            }
+            (false, true) => self.stuffing = false,
+            _ => {}
+        }
+
+        self.alerts += alerts.len();
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    const MILLISECOND: Timestamp = 1_000_000;
+
+    fn update(book: &mut OrderBook, id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) {
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: id,
+            bids,
+            asks,
+        });
+    }
+
+    #[test]
+    fn test_flickering_level_and_stuffing() {
+        let mut monitor = MarketQualityMonitor::new(QualityThresholds {
+            max_level_changes_per_second: 8.0,
+            max_level_toggles: 4,
+            ..QualityThresholds::default()
+        });
+        let mut book = OrderBook::new("ETHUSDC");
+        update(&mut book, 1, vec![(100.0, 1.0)], vec![(101.0, 1.0)]);
+        assert!(monitor.observe(0, &book).is_empty());
+
+        let mut alerts = Vec::new();
+        for id in 2..12 {
+            let quantity = if id % 2 == 0 { 1.0 } else { 0.0 };
+            update(&mut book, id, vec![(99.0, quantity)], vec![]);
+            alerts.extend(monitor.observe(id * 10 * MILLISECOND, &book));
+        }
+        // One alert each, however long the burst lasts
+        assert_eq!(alerts.len(), 2);
+        assert!(matches!(
+            alerts[0],
+            MarketQualityAlert::FlickeringLevel { side: Side::Buy, price, toggles: 5, .. } if price == 99.0
+        ));
+        assert!(matches!(
+            alerts[1],
+            MarketQualityAlert::QuoteStuffing { changes_per_second, .. } if changes_per_second == 9.0
+        ));
+
+        // Quiet for a window, the conditions clear and can alert again
+        assert!(monitor.observe(5_000 * MILLISECOND, &book).is_empty());
+        update(&mut book, 20, vec![(99.0, 1.0)], vec![]);
+        assert!(monitor.observe(5_001 * MILLISECOND, &book).is_empty());
+        assert_eq!(monitor.alert_count(), 2);
+    }
+
+    #[test]
+    fn test_quote_oscillation() {
+        let mut monitor = MarketQualityMonitor::new(QualityThresholds {
+            max_quote_reversals: 3,
+            ..QualityThresholds::default()
+        });
+        let mut book = OrderBook::new("ETHUSDC");
+        update(&mut book, 1, vec![(1000.0, 1.0)], vec![(1001.0, 1.0)]);
+        monitor.observe(0, &book);
+
+        let mut alerts = Vec::new();
+        for id in 2..8 {
+            // The best bid flips between 1000.0 and 1000.05, half a basis point
+            let quantity = if id % 2 == 0 { 1.0 } else { 0.0 };
+            update(&mut book, id, vec![(1000.05, quantity)], vec![]);
+            alerts.extend(monitor.observe(id * MILLISECOND, &book));
+        }
+        assert_eq!(
+            alerts,
+            vec![MarketQualityAlert::QuoteOscillation {
+                timestamp: 6 * MILLISECOND,
+                side: Side::Buy,
+                reversals: 4
+            }]
+        );
+
+        // Large moves are not oscillation
+        let mut monitor = MarketQualityMonitor::new(QualityThresholds {
+            max_quote_reversals: 0,
+            ..QualityThresholds::default()
+        });
+        update(&mut book, 10, vec![(1000.05, 0.0), (990.0, 1.0)], vec![]);
+        monitor.observe(0, &book);
+        update(&mut book, 11, vec![(1000.0, 0.0)], vec![]);
+        monitor.observe(MILLISECOND, &book);
+        update(&mut book, 12, vec![(1000.0, 1.0)], vec![]);
+        assert!(monitor.observe(2 * MILLISECOND, &book).is_empty());
+    }
+}
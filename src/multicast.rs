@@ -0,0 +1,507 @@
+/// UDP multicast distribution of the normalized market data, enabled with the `multicast`
+/// feature. The publisher sends every delta and trade as one datagram to a multicast group, so
+/// any number of consumers on the LAN receive the same feed for the cost of one send, the way
+/// exchanges distribute their own feeds. Datagrams are JSON encoded `FeedPacket`s. Snapshots
+/// can outgrow a datagram, they are only served over TCP.
+///
+/// UDP loses and reorders datagrams, so consumers check the sequence of every message per
+/// symbol. Old and duplicate messages are dropped. On a gap, or on the first message of a
+/// symbol, the consumer asks the publisher's snapshot server over TCP
+/// for the current state of the book, rebuilds it from the snapshot and continues after it. The
+/// server speaks one line per request: the symbol, answered with the `BookSnapshot` as JSON or
+/// `null` for a symbol that was never published. Every connection is answered on its own thread
+/// and stays open until the consumer closes it or the publisher is dropped. A unicast address
+/// works in place of the group and reaches a single consumer.
+use crate::market_data::{BookSnapshot, MarketDataMessage};
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::{LevelInfo, Price, Quantity, Side};
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Largest datagram a consumer reads, far above any delta or trade, snapshots are not multicast
+const MAX_PACKET: usize = 64 * 1024;
+// Time a consumer waits on the snapshot server to connect, take a request or answer it
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the snapshot server checks whether the publisher is gone
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedPacket {
+    pub symbol: Symbol,
+    pub message: MarketDataMessage,
+}
+
+// Levels of one symbol as of its last published message
+#[derive(Debug, Default)]
+struct Mirror {
+    sequence: u64,
+    bids: BTreeMap<Price, Quantity>,
+    asks: BTreeMap<Price, Quantity>,
+}
+
+impl Mirror {
+    fn apply(&mut self, message: &MarketDataMessage) {
+        match message {
+            MarketDataMessage::Snapshot(snapshot) => {
+                let levels = |levels: &[LevelInfo]| {
+                    levels
+                        .iter()
+                        .map(|level| (level.price, level.quantity))
+                        .collect()
+                };
+                self.bids = levels(&snapshot.bids);
+                self.asks = levels(&snapshot.asks);
+            }
+            MarketDataMessage::Delta(delta) => {
+                let levels = match delta.side {
+                    Side::Buy => &mut self.bids,
+                    Side::Sell => &mut self.asks,
+                };
+                if delta.quantity == Quantity::ZERO {
+                    levels.remove(&delta.price);
+                } else {
+                    levels.insert(delta.price, delta.quantity);
+                }
+            }
+            MarketDataMessage::Trade(_) => {}
+        }
+        self.sequence = message.sequence();
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        let level = |(&price, &quantity)| LevelInfo { price, quantity };
+        BookSnapshot {
+            sequence: self.sequence,
+            bids: self.bids.iter().rev().map(level).collect(),
+            asks: self.asks.iter().map(level).collect(),
+        }
+    }
+}
+
+type Mirrors = Arc<Mutex<HashMap<Symbol, Mirror>>>;
+
+#[derive(Debug)]
+pub struct MulticastPublisher {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    mirrors: Mirrors,
+    snapshot_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl MulticastPublisher {
+    // Sends to `group` and serves snapshots on `snapshot_addr`, port 0 picks a free one
+    pub fn bind(
+        group: SocketAddrV4,
+        snapshot_addr: impl Into<SocketAddr>,
+    ) -> io::Result<MulticastPublisher> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        if group.ip().is_multicast() {
+            // Stays on the LAN
+            socket.set_multicast_ttl_v4(1)?;
+            socket.set_multicast_loop_v4(true)?;
+        }
+        let listener = TcpListener::bind(snapshot_addr.into())?;
+        listener.set_nonblocking(true)?;
+        let snapshot_addr = listener.local_addr()?;
+
+        let mirrors = Mirrors::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let mirrors = Arc::clone(&mirrors);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || serve_snapshots(listener, mirrors, stop))
+        };
+        Ok(MulticastPublisher {
+            socket,
+            group,
+            mirrors,
+            snapshot_addr,
+            stop,
+            server: Some(server),
+        })
+    }
+
+    pub fn snapshot_addr(&self) -> SocketAddr {
+        self.snapshot_addr
+    }
+
+    // Snapshots only update the snapshot server, other messages once they are sent. The
+    // server waits for the mirror while a message is being sent, so a consumer recovering from a
+    // gap never gets a snapshot older than a message it already received.
+    pub fn publish(&self, symbol: Symbol, messages: &[MarketDataMessage]) -> io::Result<()> {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        let mirror = mirrors.entry(symbol).or_default();
+        for message in messages {
+            if !matches!(message, MarketDataMessage::Snapshot(_)) {
+                let packet = serde_json::to_vec(&FeedPacket {
+                    symbol,
+                    message: message.clone(),
+                })?;
+                self.socket.send_to(&packet, self.group)?;
+            }
+            mirror.apply(message);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MulticastPublisher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+fn serve_snapshots(listener: TcpListener, mirrors: Mirrors, stop: Arc<AtomicBool>) {
+    // Consumers still connected, shut down with the publisher so their threads end
+    let peers = Arc::new(Mutex::new(HashMap::<u64, TcpStream>::new()));
+    let mut next_peer = 0;
+    let mut workers: Vec<JoinHandle<()>> = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let peer = next_peer;
+                next_peer += 1;
+                match stream.try_clone() {
+                    Ok(clone) => peers.lock().unwrap().insert(peer, clone),
+                    Err(error) => {
+                        log::warn!("Snapshot request failed: {}", error);
+                        continue;
+                    }
+                };
+                let mirrors = Arc::clone(&mirrors);
+                let peers = Arc::clone(&peers);
+                workers.retain(|worker| !worker.is_finished());
+                workers.push(thread::spawn(move || {
+                    if let Err(error) = answer(stream, &mirrors) {
+                        log::warn!("Snapshot request failed: {}", error);
+                    }
+                    peers.lock().unwrap().remove(&peer);
+                }));
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL)
+            }
+            Err(error) => log::error!("Snapshot server cannot accept: {}", error),
+        }
+    }
+
+    for stream in peers.lock().unwrap().values() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+// Answers every request line until the consumer closes the connection
+fn answer(stream: TcpStream, mirrors: &Mirrors) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let snapshot = Symbol::lookup(line?.trim())
+            .and_then(|symbol| mirrors.lock().unwrap().get(&symbol).map(Mirror::snapshot));
+        serde_json::to_writer(&mut writer, &snapshot)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedEvent {
+    // Applied to the book of the symbol
+    Message(FeedPacket),
+    // The book was rebuilt from a snapshot after message `received` arrived instead of
+    // `expected`, None when the consumer had not seen the symbol before
+    Recovered {
+        symbol: Symbol,
+        expected: Option<u64>,
+        received: u64,
+        snapshot_sequence: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeedStats {
+    pub received: u64,
+    // Older than the next expected sequence, dropped
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub snapshots_requested: u64,
+}
+
+// Rebuilds one L2 book per symbol from the feed
+#[derive(Debug)]
+pub struct MulticastConsumer {
+    socket: UdpSocket,
+    snapshot_addr: SocketAddr,
+    snapshots: Option<(TcpStream, BufReader<TcpStream>)>,
+    books: HashMap<Symbol, OrderBook>,
+    // Next sequence per symbol
+    expected: HashMap<Symbol, u64>,
+    stats: FeedStats,
+    buffer: Vec<u8>,
+}
+
+impl MulticastConsumer {
+    // Listens on the port of `group` and joins it when it is a multicast address
+    pub fn join(
+        group: SocketAddrV4,
+        snapshot_addr: impl Into<SocketAddr>,
+    ) -> io::Result<MulticastConsumer> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(*group.ip(), group.port()))
+            .or_else(|_| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port())))?;
+        if group.ip().is_multicast() {
+            socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        }
+        Ok(MulticastConsumer {
+            socket,
+            snapshot_addr: snapshot_addr.into(),
+            snapshots: None,
+            books: HashMap::new(),
+            expected: HashMap::new(),
+            stats: FeedStats::default(),
+            buffer: vec![0; MAX_PACKET],
+        })
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(&Symbol::lookup(symbol)?)
+    }
+
+    pub fn books(&self) -> &HashMap<Symbol, OrderBook> {
+        &self.books
+    }
+
+    pub fn stats(&self) -> FeedStats {
+        self.stats
+    }
+
+    // Handles the next datagram, None when nothing arrived within the timeout or it was dropped
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<Option<FeedEvent>> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let len = match self.socket.recv(&mut self.buffer) {
+            Ok(len) => len,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error),
+        };
+        let packet: FeedPacket = match serde_json::from_slice(&self.buffer[..len]) {
+            Ok(packet) => packet,
+            Err(error) => {
+                log::error!("Invalid feed packet: {}", error);
+                return Ok(None);
+            }
+        };
+        self.stats.received += 1;
+        self.on_packet(packet)
+    }
+
+    fn on_packet(&mut self, packet: FeedPacket) -> io::Result<Option<FeedEvent>> {
+        let symbol = packet.symbol;
+        let sequence = packet.message.sequence();
+        let expected = self.expected.get(&symbol).copied();
+        match expected {
+            Some(next) if sequence < next => {
+                self.stats.duplicates += 1;
+                return Ok(None);
+            }
+            Some(next) if sequence == next => {}
+            _ if matches!(packet.message, MarketDataMessage::Snapshot(_)) => {}
+            _ => return self.recover(symbol, expected, sequence),
+        }
+        self.apply(symbol, &packet.message);
+        Ok(Some(FeedEvent::Message(packet)))
+    }
+
+    fn recover(
+        &mut self,
+        symbol: Symbol,
+        expected: Option<u64>,
+        received: u64,
+    ) -> io::Result<Option<FeedEvent>> {
+        if expected.is_some() {
+            self.stats.gaps += 1;
+        }
+        let Some(snapshot) = self.request_snapshot(symbol)? else {
+            return Ok(None);
+        };
+        let snapshot_sequence = snapshot.sequence;
+        // The server only answers once a message is sent and applied, the one that revealed the
+        // gap is part of the snapshot
+        self.apply(symbol, &MarketDataMessage::Snapshot(snapshot));
+        Ok(Some(FeedEvent::Recovered {
+            symbol,
+            expected,
+            received,
+            snapshot_sequence,
+        }))
+    }
+
+    fn apply(&mut self, symbol: Symbol, message: &MarketDataMessage) {
+        self.books
+            .entry(symbol)
+            .or_insert_with(|| OrderBook::new(symbol))
+            .apply_market_data(message);
+        self.expected.insert(symbol, message.sequence() + 1);
+    }
+
+    // The connection stays open for the next request
+    fn request_snapshot(&mut self, symbol: Symbol) -> io::Result<Option<BookSnapshot>> {
+        self.stats.snapshots_requested += 1;
+        if self.snapshots.is_none() {
+            let stream = TcpStream::connect_timeout(&self.snapshot_addr, SNAPSHOT_TIMEOUT)?;
+            stream.set_read_timeout(Some(SNAPSHOT_TIMEOUT))?;
+            stream.set_write_timeout(Some(SNAPSHOT_TIMEOUT))?;
+            let reader = BufReader::new(stream.try_clone()?);
+            self.snapshots = Some((stream, reader));
+        }
+        let (stream, reader) = self.snapshots.as_mut().unwrap();
+        let result = writeln!(stream, "{}", symbol).and_then(|_| {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(serde_json::from_str(&line)?)
+        });
+        if result.is_err() {
+            // Reconnect on the next request
+            self.snapshots = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook;
+    use crate::orderbookv2::{self, Order, OrderType};
+
+    #[test]
+    fn test_consumer_recovers_lost_packets_from_snapshots() {
+        let mut engine = orderbookv2::OrderBook::new();
+        engine.enable_market_data();
+        let mut add = |order_id, price, side| {
+            engine.add_order(Order::new(
+                order_id,
+                Price(price),
+                Quantity(5),
+                OrderType::GoodToCancel,
+                side,
+            ));
+            engine.drain_market_data()
+        };
+        let opening = add(1, 100, Side::Buy);
+        let first = add(2, 99, Side::Buy);
+        let lost = add(3, 102, Side::Sell);
+        let last = add(4, 101, Side::Sell);
+
+        // A unicast port on loopback, so the test does not depend on a multicast route
+        let probe = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let group = match probe.local_addr().unwrap() {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        drop(probe);
+        let publisher = MulticastPublisher::bind(group, (Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut consumer = MulticastConsumer::join(group, publisher.snapshot_addr()).unwrap();
+        let sim = Symbol::intern("SIM");
+        let next = |consumer: &mut MulticastConsumer| {
+            consumer
+                .poll(Duration::from_secs(5))
+                .unwrap()
+                .expect("no packet")
+        };
+
+        // The snapshot taken when market data was enabled only reaches the snapshot server,
+        // the first delta makes the consumer ask for it
+        publisher.publish(sim, &opening).unwrap();
+        assert_eq!(
+            next(&mut consumer),
+            FeedEvent::Recovered {
+                symbol: sim,
+                expected: None,
+                received: 2,
+                snapshot_sequence: 2,
+            }
+        );
+        publisher.publish(sim, &first).unwrap();
+        assert!(matches!(next(&mut consumer), FeedEvent::Message(_)));
+
+        // The mirror sees the lost delta, the consumer does not
+        publisher
+            .mirrors
+            .lock()
+            .unwrap()
+            .get_mut(&sim)
+            .unwrap()
+            .apply(&lost[0]);
+        publisher.publish(sim, &last).unwrap();
+        assert_eq!(
+            next(&mut consumer),
+            FeedEvent::Recovered {
+                symbol: sim,
+                expected: Some(4),
+                received: 5,
+                snapshot_sequence: 5,
+            }
+        );
+        assert_eq!(
+            consumer.book("SIM").unwrap().asks().collect::<Vec<_>>(),
+            vec![
+                (orderbook::Price(1_010_000), orderbook::Quantity(50_000)),
+                (orderbook::Price(1_020_000), orderbook::Quantity(50_000)),
+            ]
+        );
+
+        // Delivered twice
+        publisher.publish(sim, &last).unwrap();
+        assert_eq!(consumer.poll(Duration::from_secs(5)).unwrap(), None);
+        assert_eq!(
+            consumer.stats(),
+            FeedStats {
+                received: 4,
+                duplicates: 1,
+                gaps: 1,
+                snapshots_requested: 2,
+            }
+        );
+
+        // A second consumer is answered while the first keeps its connection open
+        let mut other = TcpStream::connect(publisher.snapshot_addr()).unwrap();
+        other
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        writeln!(other, "SIM").unwrap();
+        let mut line = String::new();
+        BufReader::new(other.try_clone().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let snapshot: Option<BookSnapshot> = serde_json::from_str(&line).unwrap();
+        assert_eq!(snapshot.unwrap().sequence, 5);
+
+        // Shutting down does not wait for the consumers to disconnect
+        drop(publisher);
+        line.clear();
+        assert_eq!(BufReader::new(other).read_line(&mut line).unwrap(), 0);
+        drop(consumer);
+    }
+}
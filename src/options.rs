@@ -0,0 +1,269 @@
+/// Books of an option chain, one book per listed option of an underlying.
+/// The books live in an `OrderBookManager`, so they are subscribed and fed like any other
+/// symbol, and the chain indexes them by expiry and strike. Prices are decimals in the quote
+/// currency. The parity report compares each strike's synthetic forward, call minus put plus
+/// the discounted strike, with the underlying price: a forward that can be bought below or sold
+/// above the underlying is a put-call parity violation, usually a stale or crossed quote.
+use crate::instruments::{InstrumentError, OptionContract, OptionRight};
+use crate::manager::OrderBookManager;
+use crate::orderbook::OrderBook;
+use crate::price_converter::PriceConverter;
+use crate::symbol::Symbol;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BestQuote {
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+}
+
+impl BestQuote {
+    fn of(book: Option<&OrderBook>) -> BestQuote {
+        let Some(book) = book else {
+            return BestQuote::default();
+        };
+        let converter = book.converter();
+        BestQuote {
+            bid: book.bids().next().map(|(price, _)| converter.to_f64(price)),
+            ask: book.asks().next().map(|(price, _)| converter.to_f64(price)),
+        }
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.bid? + self.ask?) / 2.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrikeQuote {
+    pub strike: f64,
+    pub call: BestQuote,
+    pub put: BestQuote,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParityCheck {
+    pub strike: f64,
+    // Selling the call and buying the put, i.e. selling the synthetic forward
+    pub synthetic_bid: Option<f64>,
+    pub synthetic_ask: Option<f64>,
+    // Synthetic mid over the underlying
+    pub deviation: Option<f64>,
+    // The synthetic can be bought below or sold above the underlying
+    pub violated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParityReport {
+    pub expiry: u32,
+    pub underlying_price: f64,
+    pub checks: Vec<ParityCheck>,
+}
+
+impl ParityReport {
+    pub fn violations(&self) -> impl Iterator<Item = &ParityCheck> {
+        self.checks.iter().filter(|check| check.violated)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Strike {
+    strike: f64,
+    call: Option<Symbol>,
+    put: Option<Symbol>,
+}
+
+#[derive(Debug)]
+pub struct OptionChainBooks {
+    underlying: String,
+    manager: OrderBookManager,
+    // Keyed by expiry and strike in units of the default converter
+    strikes: BTreeMap<(u32, u64), Strike>,
+}
+
+impl OptionChainBooks {
+    pub fn new(underlying: impl Into<String>) -> OptionChainBooks {
+        OptionChainBooks {
+            underlying: underlying.into(),
+            manager: OrderBookManager::new(),
+            strikes: BTreeMap::new(),
+        }
+    }
+
+    pub fn underlying(&self) -> &str {
+        &self.underlying
+    }
+
+    // Starts following the option, the symbol has to be an option on the chain's underlying
+    pub fn add_option(&mut self, symbol: &str) -> Result<&mut OrderBook, InstrumentError> {
+        let contract: OptionContract = symbol.parse()?;
+        let invalid = || InstrumentError::InvalidOptionSymbol(symbol.to_string());
+        if contract.underlying != self.underlying {
+            return Err(invalid());
+        }
+        let strike_units = PriceConverter::default()
+            .to_units(contract.strike)
+            .map_err(|_| invalid())?;
+
+        let symbol = Symbol::intern(symbol);
+        let entry = self
+            .strikes
+            .entry((contract.expiry, strike_units))
+            .or_insert(Strike {
+                strike: contract.strike,
+                ..Strike::default()
+            });
+        match contract.right {
+            OptionRight::Call => entry.call = Some(symbol),
+            OptionRight::Put => entry.put = Some(symbol),
+        }
+        Ok(self.manager.add_symbol(symbol))
+    }
+
+    pub fn manager(&self) -> &OrderBookManager {
+        &self.manager
+    }
+
+    // For subscriptions, books added through the manager are not part of the chain
+    pub fn manager_mut(&mut self) -> &mut OrderBookManager {
+        &mut self.manager
+    }
+
+    pub fn book(&self, symbol: Symbol) -> Option<&OrderBook> {
+        self.manager.book(symbol)
+    }
+
+    // See `OrderBookManager::apply_payload`
+    pub fn apply_payload(&mut self, payload: &[u8]) -> Option<Symbol> {
+        self.manager.apply_payload(payload)
+    }
+
+    // Soonest first
+    pub fn expiries(&self) -> Vec<u32> {
+        let mut expiries: Vec<u32> = self.strikes.keys().map(|(expiry, _)| *expiry).collect();
+        expiries.dedup();
+        expiries
+    }
+
+    // Best bid and ask of the call and the put of every strike of the expiry, lowest strike
+    // first
+    pub fn quotes(&self, expiry: u32) -> Vec<StrikeQuote> {
+        self.strikes
+            .range((expiry, 0)..=(expiry, u64::MAX))
+            .map(|(_, strike)| StrikeQuote {
+                strike: strike.strike,
+                call: BestQuote::of(strike.call.and_then(|symbol| self.book(symbol))),
+                put: BestQuote::of(strike.put.and_then(|symbol| self.book(symbol))),
+            })
+            .collect()
+    }
+
+    // `discount_factor` discounts the strike to today, 1.0 to ignore rates
+    pub fn parity_report(
+        &self,
+        expiry: u32,
+        underlying_price: f64,
+        discount_factor: f64,
+    ) -> ParityReport {
+        let checks = self
+            .quotes(expiry)
+            .into_iter()
+            .map(|quote| {
+                let strike = quote.strike * discount_factor;
+                let synthetic_bid = quote
+                    .call
+                    .bid
+                    .zip(quote.put.ask)
+                    .map(|(call, put)| call - put + strike);
+                let synthetic_ask = quote
+                    .call
+                    .ask
+                    .zip(quote.put.bid)
+                    .map(|(call, put)| call - put + strike);
+                let deviation = quote
+                    .call
+                    .mid()
+                    .zip(quote.put.mid())
+                    .map(|(call, put)| call - put + strike - underlying_price);
+                let violated = synthetic_bid.is_some_and(|bid| bid > underlying_price)
+                    || synthetic_ask.is_some_and(|ask| ask < underlying_price);
+                ParityCheck {
+                    strike: quote.strike,
+                    synthetic_bid,
+                    synthetic_ask,
+                    deviation,
+                    violated,
+                }
+            })
+            .collect();
+        ParityReport {
+            expiry,
+            underlying_price,
+            checks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    fn quote(book: &mut OrderBook, bid: f64, ask: f64) {
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(bid, 1.0)],
+            asks: vec![(ask, 1.0)],
+        });
+    }
+
+    #[test]
+    fn test_chain_quotes_and_parity() {
+        let mut chain = OptionChainBooks::new("BTC");
+        quote(
+            chain.add_option("BTC-240628-60000-C").unwrap(),
+            5100.0,
+            5200.0,
+        );
+        quote(
+            chain.add_option("BTC-240628-60000-P").unwrap(),
+            3000.0,
+            3100.0,
+        );
+        quote(
+            chain.add_option("BTC-240628-70000-C").unwrap(),
+            2000.0,
+            2100.0,
+        );
+        quote(
+            chain.add_option("BTC-240628-70000-P").unwrap(),
+            9000.0,
+            9100.0,
+        );
+        chain.add_option("BTC-240927-60000-C").unwrap();
+        assert!(chain.add_option("ETH-240628-3000-C").is_err());
+        assert!(chain.add_option("BTCUSDT").is_err());
+
+        assert_eq!(chain.expiries(), vec![240628, 240927]);
+        let quotes = chain.quotes(240628);
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].strike, 60000.0);
+        assert_eq!(quotes[0].call.bid, Some(5100.0));
+        assert_eq!(quotes[1].put.ask, Some(9100.0));
+        assert_eq!(chain.quotes(240927)[0].put, BestQuote::default());
+
+        // Synthetic forwards: 62000..62200 at 60000, 62900..63100 at 70000
+        let report = chain.parity_report(240628, 62000.0, 1.0);
+        assert_eq!(report.checks[0].synthetic_bid, Some(62000.0));
+        assert_eq!(report.checks[0].synthetic_ask, Some(62200.0));
+        assert_eq!(report.checks[0].deviation, Some(100.0));
+        assert!(!report.checks[0].violated);
+        let violations: Vec<f64> = report.violations().map(|check| check.strike).collect();
+        assert_eq!(violations, vec![70000.0]);
+
+        let report = chain.parity_report(240927, 62000.0, 1.0);
+        assert_eq!(report.checks[0].deviation, None);
+        assert!(!report.checks[0].violated);
+    }
+}
@@ -0,0 +1,149 @@
+/// Historical order flow loaded from CSV files and replayed through the matching engine.
+/// Files need `timestamp,side,price,quantity` columns, `side` is buy or sell (b/s and any
+/// casing accepted) and the optional `order_type` column takes limit (the default) or ioc.
+/// Timestamps are in nanoseconds on the engine clock.
+use crate::orderbookv2::{Order, OrderBook, OrderType, Price, Quantity, Side, Timestamp, Trade};
+use serde::{Deserialize, Deserializer};
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OrderFlowRecord {
+    pub timestamp: Timestamp,
+    #[serde(deserialize_with = "deserialize_side")]
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    #[serde(
+        default = "default_order_type",
+        deserialize_with = "deserialize_order_type"
+    )]
+    pub order_type: OrderType,
+}
+
+fn default_order_type() -> OrderType {
+    OrderType::GoodToCancel
+}
+
+fn deserialize_side<'de, D>(deserializer: D) -> Result<Side, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let side = String::deserialize(deserializer)?;
+    match side.to_ascii_lowercase().as_str() {
+        "buy" | "b" | "bid" => Ok(Side::Buy),
+        "sell" | "s" | "ask" => Ok(Side::Sell),
+        _ => Err(serde::de::Error::custom(format!("unknown side {}", side))),
+    }
+}
+
+fn deserialize_order_type<'de, D>(deserializer: D) -> Result<OrderType, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let order_type = String::deserialize(deserializer)?;
+    match order_type.to_ascii_lowercase().as_str() {
+        "" | "limit" | "gtc" => Ok(OrderType::GoodToCancel),
+        "ioc" | "fak" => Ok(OrderType::FillAndKill),
+        "day" => Ok(OrderType::Day),
+        _ => Err(serde::de::Error::custom(format!(
+            "unknown order type {}",
+            order_type
+        ))),
+    }
+}
+
+pub fn read_order_flow<R: io::Read>(reader: R) -> csv::Result<Vec<OrderFlowRecord>> {
+    csv::Reader::from_reader(reader).deserialize().collect()
+}
+
+// Orders enter the engine in timestamp order with engine allocated ids, the trades are
+// reported with the time they matched at
+pub fn replay_order_flow(
+    orderbook: &mut OrderBook,
+    records: &[OrderFlowRecord],
+) -> Vec<(Timestamp, Trade)> {
+    let mut records: Vec<&OrderFlowRecord> = records.iter().collect();
+    records.sort_by_key(|record| record.timestamp);
+
+    let mut trades = Vec::new();
+    for record in records {
+        trades.extend(orderbook.advance_clock(record.timestamp));
+        let order = Order::new(
+            orderbook.next_order_id(),
+            record.price,
+            record.quantity,
+            record.order_type,
+            record.side,
+        );
+        let now = orderbook.clock().now();
+        trades.extend(
+            orderbook
+                .submit_order(order)
+                .into_iter()
+                .map(|trade| (now, trade)),
+        );
+    }
+
+    trades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOW: &str = "\
+timestamp,side,price,quantity,order_type
+100,sell,101,5,
+300,BUY,101,2,ioc
+200,s,102,5,limit
+400,b,103,10,
+";
+
+    #[test]
+    fn test_read_order_flow() {
+        let records = read_order_flow(FLOW.as_bytes()).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[1],
+            OrderFlowRecord {
+                timestamp: 300,
+                side: Side::Buy,
+                price: Price(101),
+                quantity: Quantity(2),
+                order_type: OrderType::FillAndKill,
+            }
+        );
+        assert_eq!(records[0].order_type, OrderType::GoodToCancel);
+
+        let without_type = "timestamp,side,price,quantity\n1,sell,10,1\n";
+        assert_eq!(read_order_flow(without_type.as_bytes()).unwrap().len(), 1);
+        assert!(
+            read_order_flow("timestamp,side,price,quantity\n1,hold,10,1\n".as_bytes()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_replay_order_flow() {
+        let records = read_order_flow(FLOW.as_bytes()).unwrap();
+        let mut orderbook = OrderBook::new();
+        let trades = replay_order_flow(&mut orderbook, &records);
+
+        let fills: Vec<(Timestamp, Price, Quantity)> = trades
+            .iter()
+            .map(|(timestamp, trade)| (*timestamp, trade.ask_trade.price, trade.ask_trade.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (300, Price(101), Quantity(2)),
+                (400, Price(101), Quantity(3)),
+                (400, Price(102), Quantity(5)),
+            ]
+        );
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(103), Quantity(2))]
+        );
+        assert_eq!(orderbook.clock().now(), 400);
+    }
+}
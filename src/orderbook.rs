@@ -1,119 +1,932 @@
 use crate::binance_payloads;
-use std::collections::BTreeMap;
+use crate::checksum::ChecksumStyle;
+use crate::clock::{self, SharedClock, Stamp};
+use crate::digest;
+use crate::level_filters::{FilterAlert, FilterId, LevelFilter, LevelFilters};
+use crate::market_data::MarketDataMessage;
+use crate::orderbookv2::Side;
+use crate::price_converter::{ConversionError, PriceConverter};
+use crate::price_levels::{self, BookBackend, LevelStore, PriceLevels};
+use crate::symbol::Symbol;
+use crate::top_of_book::{TopOfBook, TopOfBookReceiver, TopOfBookWatch};
+use crate::units;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
 
-// Additional types and traits
-type Price = u64;
-type Quantity = u64;
+// Additional types and traits, both in `PriceConverter` units
+units::unit!(Price(u64));
+units::unit!(Quantity(u64));
+units::additive!(Quantity);
+units::unit!(
+    // Price times quantity in converter units squared, u64 times u64 always fits
+    Notional(u128)
+);
+units::additive!(Notional);
+units::notional!(Price * Quantity = Notional(u128));
 
-const CONVERSION_FACTOR: f64 = 10000.0;
+// Raw converter units, e.g. for `PriceConverter::to_f64` or depth consumers such as the heatmap
+impl From<Price> for u64 {
+    fn from(price: Price) -> u64 {
+        price.0
+    }
+}
+
+impl From<Quantity> for u64 {
+    fn from(quantity: Quantity) -> u64 {
+        quantity.0
+    }
+}
+
+// Factor of the default `PriceConverter`
+pub const CONVERSION_FACTOR: f64 = 10000.0;
+
+// One side of the L2 book with its best level cached, the highest bid (`BID`) or the lowest
+// ask. Every change goes through `insert`, `remove` and `clear`, which keep the cache in step,
+// so top-of-book queries never walk the levels. Only removing the best level looks up the next
+// one. Serialized as the bare levels, the cache is rebuilt on deserialization.
+#[derive(Debug, Clone, PartialEq)]
+struct BookSide<const BID: bool> {
+    levels: PriceLevels<Price, Quantity>,
+    best: Option<(Price, Quantity)>,
+}
+
+impl<const BID: bool> BookSide<BID> {
+    fn new(backend: BookBackend) -> BookSide<BID> {
+        BookSide::from_levels(PriceLevels::new(backend))
+    }
+
+    fn from_levels(levels: PriceLevels<Price, Quantity>) -> BookSide<BID> {
+        let mut side = BookSide { levels, best: None };
+        side.refresh_best();
+        side
+    }
+
+    fn best(&self) -> Option<(Price, Quantity)> {
+        self.best
+    }
+
+    fn is_better(price: Price, than: Price) -> bool {
+        if BID {
+            price > than
+        } else {
+            price < than
+        }
+    }
+
+    fn refresh_best(&mut self) {
+        let best = if BID {
+            self.levels.last()
+        } else {
+            self.levels.first()
+        };
+        self.best = best.map(|(price, quantity)| (price, *quantity));
+    }
+
+    fn insert(&mut self, price: Price, quantity: Quantity) {
+        self.levels.insert(price, quantity);
+        match self.best {
+            Some((best, _)) if best != price && !Self::is_better(price, best) => {}
+            _ => self.best = Some((price, quantity)),
+        }
+    }
+
+    fn remove(&mut self, price: Price) {
+        if self.levels.remove(price).is_some() && self.best.is_some_and(|(best, _)| best == price) {
+            self.refresh_best();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.levels.clear();
+        self.best = None;
+    }
+
+    fn backend(&self) -> BookBackend {
+        self.levels.backend()
+    }
+
+    fn get(&self, price: Price) -> Option<Quantity> {
+        self.levels.get(price).copied()
+    }
+
+    // Lowest price first
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (Price, Quantity)> + '_ {
+        self.levels
+            .iter()
+            .map(|(price, quantity)| (price, *quantity))
+    }
+}
 
-trait ToU64 {
-    fn to_u64(self) -> u64;
+impl<const BID: bool> Serialize for BookSide<BID> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.levels.serialize(serializer)
+    }
 }
 
-impl ToU64 for f64 {
-    #[inline]
-    fn to_u64(self) -> u64 {
-        (self * CONVERSION_FACTOR).round() as u64
+impl<'de, const BID: bool> Deserialize<'de> for BookSide<BID> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        PriceLevels::deserialize(deserializer).map(BookSide::from_levels)
     }
 }
 
 // Binance orderbook implementation
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
-    #[allow(dead_code)]
-    symbol: String,
-    bids: BTreeMap<Price, Quantity>,
-    asks: BTreeMap<Price, Quantity>,
+    symbol: Symbol,
+    bids: BookSide<true>,
+    asks: BookSide<false>,
     last_update_id: u64,
+    #[serde(default)]
+    converter: PriceConverter,
+    #[serde(skip, default = "clock::system")]
+    clock: SharedClock,
+    // When the last update was applied
+    #[serde(default)]
+    last_update: Option<Stamp>,
+    // Exchange event time of the last update, in milliseconds
+    #[serde(default)]
+    last_event_time: Option<u64>,
+    // Level changes not drained yet, while `record_mutations` is on
+    #[serde(skip)]
+    mutations: Option<Vec<LevelMutation>>,
+    // Tickers are checked against each other, they skip the depth updates in between
+    #[serde(default)]
+    last_ticker_update_id: Option<u64>,
+    #[serde(default)]
+    stats: UpdateStats,
+    #[serde(skip)]
+    top_of_book: TopOfBookWatch,
+    #[serde(skip)]
+    level_filters: LevelFilters,
 }
 
 impl OrderBook {
-    pub fn new(symbol: String) -> OrderBook {
+    pub fn new(symbol: impl Into<Symbol>) -> OrderBook {
+        OrderBook::with_backend(symbol, BookBackend::BTree)
+    }
+
+    pub fn with_backend(symbol: impl Into<Symbol>, backend: BookBackend) -> OrderBook {
         OrderBook {
-            symbol,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
+            symbol: symbol.into(),
+            bids: BookSide::new(backend),
+            asks: BookSide::new(backend),
             last_update_id: 0,
+            converter: PriceConverter::default(),
+            clock: clock::system(),
+            last_update: None,
+            last_event_time: None,
+            mutations: None,
+            last_ticker_update_id: None,
+            stats: UpdateStats::default(),
+            top_of_book: TopOfBookWatch::default(),
+            level_filters: LevelFilters::default(),
         }
     }
 
-    pub fn update_book_ticker(&mut self, data: &binance_payloads::BookTickerUpdate) {
-        self.bids.insert(
-            data.best_bid_price.to_u64() as Price,
-            data.best_bid_quantity.to_u64() as Quantity,
-        );
-        self.asks.insert(
-            data.best_ask_price.to_u64() as Price,
-            data.best_ask_quantity.to_u64() as Quantity,
-        );
+    // Time source for `last_update`, the system clock by default
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    pub fn last_update(&self) -> Option<Stamp> {
+        self.last_update
     }
 
-    pub fn update_depth(&mut self, data: &binance_payloads::DepthUpdate) {
-        if data.last_update_id <= self.last_update_id {
-            return;
+    pub fn last_event_time(&self) -> Option<u64> {
+        self.last_event_time
+    }
+
+    fn stamp_update(&mut self) {
+        self.last_update = Some(self.clock.stamp());
+    }
+
+    pub fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+
+    // Calls `callback` after every payload that changed the best price or size of either side
+    pub fn on_top_of_book(&mut self, callback: impl FnMut(&TopOfBook) + Send + Sync + 'static) {
+        self.top_of_book.add_callback(Box::new(callback));
+    }
+
+    // Same as `on_top_of_book`, the receiver keeps the latest change it has not read yet
+    pub fn subscribe_top_of_book(&mut self) -> TopOfBookReceiver {
+        self.top_of_book.add_receiver()
+    }
+
+    pub fn top_of_book(&self) -> TopOfBook {
+        TopOfBook::from_book(self)
+    }
+
+    // Calls `callback` whenever the filter's condition becomes true after a payload
+    pub fn add_level_filter(
+        &mut self,
+        filter: LevelFilter,
+        callback: impl FnMut(&FilterAlert) + Send + Sync + 'static,
+    ) -> FilterId {
+        let mut filters = std::mem::take(&mut self.level_filters);
+        let filter_id = filters.add(filter, Box::new(callback), self);
+        self.level_filters = filters;
+        filter_id
+    }
+
+    pub fn remove_level_filter(&mut self, filter_id: FilterId) -> bool {
+        self.level_filters.remove(filter_id)
+    }
+
+    // Quantity the filter measures and whether its condition holds
+    pub fn level_filter_state(&self, filter_id: FilterId) -> Option<(u64, bool)> {
+        self.level_filters.state(filter_id)
+    }
+
+    // Top of book subscribers and level filters, once per applied payload
+    fn notify_subscribers(&mut self) {
+        let mut watch = std::mem::take(&mut self.top_of_book);
+        watch.update(self);
+        self.top_of_book = watch;
+        if !self.level_filters.is_empty() {
+            let mut filters = std::mem::take(&mut self.level_filters);
+            filters.evaluate(self);
+            self.level_filters = filters;
         }
+    }
+
+    // Scale and rounding of incoming prices and quantities. Levels already in the book are not
+    // rescaled, so set it before the first update.
+    pub fn set_converter(&mut self, converter: PriceConverter) {
+        self.converter = converter;
+    }
+
+    pub fn converter(&self) -> PriceConverter {
+        self.converter
+    }
+
+    pub fn backend(&self) -> BookBackend {
+        self.bids.backend()
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        self.symbol
+    }
+
+    // What became of the updates since the book was created
+    pub fn stats(&self) -> UpdateStats {
+        self.stats
+    }
+
+    // Keeps every level change of the updates applied from now on, see `drain_mutations`
+    pub fn record_mutations(&mut self) {
+        self.mutations.get_or_insert_with(Vec::new);
+    }
+
+    // Level changes since the last call, in the order they were applied. Empty unless
+    // `record_mutations` is on.
+    pub fn drain_mutations(&mut self) -> Vec<LevelMutation> {
+        self.mutations
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
 
-        for (price, qty) in &data.bids {
-            let price_u64 = price.to_u64() as Price;
-            let qty_u64 = qty.to_u64() as Quantity;
-            if qty_u64 == 0 {
-                self.bids.remove(&price_u64);
-            } else {
-                self.bids.insert(price_u64, qty_u64);
+    // Sets or, with a zero quantity, removes a level
+    fn set_level(&mut self, update_id: u64, side: Side, price: Price, quantity: Quantity) {
+        let old_quantity = match side {
+            Side::Buy => self.bids.get(price),
+            Side::Sell => self.asks.get(price),
+        }
+        .unwrap_or_default();
+        match (side, quantity.is_zero()) {
+            (Side::Buy, true) => self.bids.remove(price),
+            (Side::Buy, false) => self.bids.insert(price, quantity),
+            (Side::Sell, true) => self.asks.remove(price),
+            (Side::Sell, false) => self.asks.insert(price, quantity),
+        }
+        if !self.level_filters.is_empty() && old_quantity != quantity {
+            self.level_filters
+                .on_level(side, price, old_quantity, quantity);
+        }
+        if let Some(mutations) = self.mutations.as_mut() {
+            if old_quantity != quantity {
+                mutations.push(LevelMutation {
+                    update_id,
+                    side,
+                    price,
+                    old_quantity,
+                    new_quantity: quantity,
+                });
             }
         }
+    }
+
+    // Nothing is applied when a value cannot be converted. A ticker never counts as a gap, the
+    // stream only carries the updates that change the top of the book.
+    pub fn update_book_ticker(
+        &mut self,
+        data: &binance_payloads::BookTickerUpdate,
+    ) -> Result<UpdateOutcome, ConversionError> {
+        let bid_price = Price(self.converter.to_units(data.best_bid_price)?);
+        let bid_quantity = Quantity(self.converter.to_units(data.best_bid_quantity)?);
+        let ask_price = Price(self.converter.to_units(data.best_ask_price)?);
+        let ask_quantity = Quantity(self.converter.to_units(data.best_ask_quantity)?);
+        Ok(self.apply_book_ticker(
+            data.update_id,
+            data.event_time,
+            (bid_price, bid_quantity),
+            (ask_price, ask_quantity),
+        ))
+    }
+
+    // Same as `update_book_ticker` on a borrowed payload, the decimals are converted exactly
+    pub fn update_book_ticker_ref(
+        &mut self,
+        data: &binance_payloads::BookTickerUpdateRef,
+    ) -> Result<UpdateOutcome, ConversionError> {
+        let bid_price = Price(self.converter.parse(data.best_bid_price)?);
+        let bid_quantity = Quantity(self.converter.parse(data.best_bid_quantity)?);
+        let ask_price = Price(self.converter.parse(data.best_ask_price)?);
+        let ask_quantity = Quantity(self.converter.parse(data.best_ask_quantity)?);
+        Ok(self.apply_book_ticker(
+            data.update_id,
+            data.event_time,
+            (bid_price, bid_quantity),
+            (ask_price, ask_quantity),
+        ))
+    }
+
+    fn apply_book_ticker(
+        &mut self,
+        update_id: u64,
+        event_time: Option<u64>,
+        (bid_price, bid_quantity): (Price, Quantity),
+        (ask_price, ask_quantity): (Price, Quantity),
+    ) -> UpdateOutcome {
+        let outcome = match self.last_ticker_update_id {
+            Some(last) if update_id == last => UpdateOutcome::Duplicate,
+            Some(last) if update_id < last => UpdateOutcome::Stale,
+            _ => UpdateOutcome::Applied,
+        };
+        self.stats.record(outcome);
+        if outcome != UpdateOutcome::Applied {
+            return outcome;
+        }
 
-        for (price, qty) in &data.asks {
-            let price_u64 = price.to_u64() as Price;
-            let qty_u64 = qty.to_u64() as Quantity;
-            if qty_u64 == 0 {
-                self.asks.remove(&price_u64);
-            } else {
-                self.asks.insert(price_u64, qty_u64);
+        self.set_level(update_id, Side::Buy, bid_price, bid_quantity);
+        self.set_level(update_id, Side::Sell, ask_price, ask_quantity);
+        self.last_ticker_update_id = Some(update_id);
+        self.last_event_time = event_time;
+        self.stamp_update();
+        self.notify_subscribers();
+        outcome
+    }
+
+    // Levels whose price or quantity cannot be converted are skipped. Update ids of the
+    // payloads this is fed with do not follow each other, so no update is reported as a gap.
+    pub fn update_depth(&mut self, data: &binance_payloads::DepthUpdate) -> UpdateOutcome {
+        self.apply_depth(data, false)
+    }
+
+    // `contiguous` when every update id of the source is used, e.g. the engine feed, only then
+    // does a skipped id mean a lost update
+    fn apply_depth(
+        &mut self,
+        data: &binance_payloads::DepthUpdate,
+        contiguous: bool,
+    ) -> UpdateOutcome {
+        let outcome = self.classify(data.last_update_id, contiguous);
+        self.stats.record(outcome);
+        if !outcome.is_applied() {
+            return outcome;
+        }
+
+        for (side, levels) in [(Side::Buy, &data.bids), (Side::Sell, &data.asks)] {
+            for (price, qty) in levels {
+                let (level_price, level_qty) = match (
+                    self.converter.to_units(*price),
+                    self.converter.to_units(*qty),
+                ) {
+                    (Ok(price_u64), Ok(qty_u64)) => (Price(price_u64), Quantity(qty_u64)),
+                    (Err(error), _) | (_, Err(error)) => {
+                        log::warn!("Skipping level {} @ {}: {}", qty, price, error);
+                        continue;
+                    }
+                };
+                self.set_level(data.last_update_id, side, level_price, level_qty);
             }
         }
 
         self.last_update_id = data.last_update_id;
+        self.last_event_time = data.event_time;
+        self.stamp_update();
+        self.notify_subscribers();
+        outcome
+    }
+
+    // Drops every level before applying the update, whatever its update id
+    pub fn replace_depth(&mut self, data: &binance_payloads::DepthUpdate) -> UpdateOutcome {
+        if let Some(mutations) = self.mutations.as_mut() {
+            let update_id = data.last_update_id;
+            let removed = |side, (price, old_quantity)| LevelMutation {
+                update_id,
+                side,
+                price,
+                old_quantity,
+                new_quantity: Quantity::ZERO,
+            };
+            mutations.extend(
+                self.bids
+                    .iter()
+                    .rev()
+                    .map(|level| removed(Side::Buy, level)),
+            );
+            mutations.extend(self.asks.iter().map(|level| removed(Side::Sell, level)));
+        }
+        self.bids.clear();
+        self.asks.clear();
+        self.level_filters.invalidate();
+        self.last_update_id = 0;
+        self.update_depth(data)
+    }
+
+    // Consumes the matching engine feed, a snapshot replaces the whole book. The feed numbers
+    // every message, so a delta that skips a sequence is reported as a gap.
+    pub fn apply_market_data(&mut self, message: &MarketDataMessage) {
+        match message {
+            MarketDataMessage::Snapshot(snapshot) => {
+                self.replace_depth(&snapshot.to_depth_update());
+            }
+            MarketDataMessage::Delta(delta) => {
+                self.apply_depth(&delta.to_depth_update(), true);
+            }
+            // Trades do not change the levels, the deltas following them do, but they use up a
+            // sequence number
+            MarketDataMessage::Trade(trade) => {
+                if let UpdateOutcome::Gap { missed } = self.classify(trade.sequence, true) {
+                    self.stats.gaps += 1;
+                    self.stats.missed += missed;
+                }
+                self.last_update_id = self.last_update_id.max(trade.sequence);
+            }
+        }
+    }
+
+    fn classify(&self, update_id: u64, contiguous: bool) -> UpdateOutcome {
+        match update_id {
+            id if id == self.last_update_id => UpdateOutcome::Duplicate,
+            id if id < self.last_update_id => UpdateOutcome::Stale,
+            id if contiguous && self.last_update_id != 0 && id > self.last_update_id + 1 => {
+                UpdateOutcome::Gap {
+                    missed: id - self.last_update_id - 1,
+                }
+            }
+            _ => UpdateOutcome::Applied,
+        }
+    }
+
+    // Price levels in best-first order (highest bid first), in internal units
+    pub fn bids(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.bids.iter().rev()
+    }
+
+    // Price levels in best-first order (lowest ask first), in internal units
+    pub fn asks(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.asks.iter()
+    }
+
+    // Top `levels` price levels of each side, best first
+    pub fn snapshot(&self, levels: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: self.bids().take(levels).collect(),
+            asks: self.asks().take(levels).collect(),
+            converter: self.converter,
+        }
+    }
+
+    // All levels as canonical text, see `digest`: a `bid` or `ask` line per level best first,
+    // then the last update id
+    pub fn canonical_state(&self) -> String {
+        let mut text = String::new();
+        for (price, quantity) in self.bids() {
+            digest::push_line(&mut text, &[&"bid", &price, &quantity]);
+        }
+        for (price, quantity) in self.asks() {
+            digest::push_line(&mut text, &[&"ask", &price, &quantity]);
+        }
+        digest::push_line(&mut text, &[&"last_update_id", &self.last_update_id]);
+        text
+    }
+
+    // Hex SHA-256 of `canonical_state`
+    pub fn state_digest(&self) -> String {
+        digest::sha256_hex(self.canonical_state().as_bytes())
+    }
+
+    // CRC32 of the top of the book the way the exchange computes it, see `ChecksumStyle`. A
+    // mismatch with the exchange's value means the book has to be resynced.
+    pub fn checksum(&self, style: ChecksumStyle) -> u32 {
+        let bids: Vec<_> = self.bids().take(style.depth()).collect();
+        let asks: Vec<_> = self.asks().take(style.depth()).collect();
+        style.checksum(&bids, &asks, self.converter.scale())
+    }
+
+    // All levels grouped into price buckets of `bucket_size` internal units, e.g. 5_000 groups
+    // by 0.5 with the default converter. See `price_levels::aggregate_levels`.
+    pub fn aggregated_depth(&self, bucket_size: Price) -> DepthSnapshot {
+        let bucket_size = bucket_size.0 as i64;
+        let aggregate = |levels: Vec<(Price, u64)>| {
+            levels
+                .into_iter()
+                .map(|(price, quantity)| (price, Quantity(quantity)))
+                .collect()
+        };
+        let raw = |(price, quantity): (Price, Quantity)| (price, quantity.0);
+        DepthSnapshot {
+            symbol: self.symbol,
+            last_update_id: self.last_update_id,
+            bids: aggregate(price_levels::aggregate_levels(
+                self.bids().map(raw),
+                bucket_size,
+                false,
+            )),
+            asks: aggregate(price_levels::aggregate_levels(
+                self.asks().map(raw),
+                bucket_size,
+                true,
+            )),
+            converter: self.converter,
+        }
+    }
+
+    // Best bid and best ask in internal units, O(1) from the cached best levels
+    pub fn best_bid(&self) -> Option<(Price, Quantity)> {
+        self.bids.best()
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, Quantity)> {
+        self.asks.best()
+    }
+
+    pub fn mid_price(&self) -> Option<f64> {
+        match (self.bids.best(), self.asks.best()) {
+            // Averaged in f64, `bid + ask` can overflow
+            (Some((bid, _)), Some((ask, _))) => {
+                Some((self.converter.to_f64(bid) + self.converter.to_f64(ask)) / 2.0)
+            }
+            _ => None,
+        }
+    }
+
+    // Fixed size input for models: 4 values per level, best first, bid price, bid size, ask
+    // price, ask size. Prices are the relative distance to the mid, sizes are divided by the
+    // average size of the levels taken. Missing levels are zeros, so is the whole vector
+    // while a side is empty.
+    pub fn feature_vector(&self, levels: usize) -> Vec<f64> {
+        let mut features = vec![0.0; levels * 4];
+        let Some(mid) = self.mid_price().filter(|mid| *mid > 0.0) else {
+            return features;
+        };
+        let bids: Vec<_> = self.bids().take(levels).collect();
+        let asks: Vec<_> = self.asks().take(levels).collect();
+        let sizes = bids
+            .iter()
+            .chain(&asks)
+            .map(|(_, qty)| self.converter.to_f64(*qty));
+        let average_size = sizes.sum::<f64>() / (bids.len() + asks.len()) as f64;
+
+        for (offset, side) in [(0, &bids), (2, &asks)] {
+            for (level, (price, qty)) in side.iter().enumerate() {
+                features[level * 4 + offset] = self.converter.to_f64(*price) / mid - 1.0;
+                if average_size > 0.0 {
+                    features[level * 4 + offset + 1] = self.converter.to_f64(*qty) / average_size;
+                }
+            }
+        }
+        features
+    }
+
+    // Best ask minus best bid, negative while the book is crossed
+    pub fn spread(&self) -> Option<f64> {
+        match (self.bids.best(), self.asks.best()) {
+            (Some((bid, _)), Some((ask, _))) => {
+                Some(self.converter.to_f64(ask) - self.converter.to_f64(bid))
+            }
+            _ => None,
+        }
+    }
+
+    // One `side,price,quantity` row per level, bids best first then asks best first
+    pub fn export_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let rows = self
+            .bids()
+            .map(|level| ("bid", level))
+            .chain(self.asks().map(|level| ("ask", level)));
+        for (side, (price, qty)) in rows {
+            writer.serialize(CsvLevel {
+                side: side.to_string(),
+                price: self.converter.to_f64(price),
+                quantity: self.converter.to_f64(qty),
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Replaces the levels with the rows of an `export_csv` file, symbol and last update id
+    // are kept. Rows with an unknown side are skipped, values that cannot be converted fail the
+    // import.
+    pub fn import_csv<R: io::Read>(&mut self, reader: R) -> csv::Result<()> {
+        let mut bids = PriceLevels::new(self.bids.backend());
+        let mut asks = PriceLevels::new(self.asks.backend());
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let level: CsvLevel = row?;
+            let side = match level.side.as_str() {
+                "bid" => &mut bids,
+                "ask" => &mut asks,
+                _ => continue,
+            };
+            let convert = |value| {
+                self.converter
+                    .to_units(value)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+            };
+            side.insert(
+                Price(convert(level.price)?),
+                Quantity(convert(level.quantity)?),
+            );
+        }
+        self.bids = BookSide::from_levels(bids);
+        self.asks = BookSide::from_levels(asks);
+        self.stamp_update();
+        Ok(())
     }
 
     // TODO: Use better types ((BID_PRICE, BID_QUANTITY), (ASK_PRICE, ASK_QUANTITY))
-    #[allow(dead_code)]
-    fn get_best_bid_ask(&self) -> Option<((f64, f64), (f64, f64))> {
-        match (self.bids.iter().next_back(), self.asks.iter().next()) {
+    pub fn get_best_bid_ask(&self) -> Option<((f64, f64), (f64, f64))> {
+        match (self.bids.best(), self.asks.best()) {
             (Some(best_bid), Some(best_ask)) => Some((
                 (
-                    *best_bid.0 as f64 / CONVERSION_FACTOR,
-                    *best_bid.1 as f64 / CONVERSION_FACTOR,
+                    self.converter.to_f64(best_bid.0),
+                    self.converter.to_f64(best_bid.1),
                 ),
                 (
-                    *best_ask.0 as f64 / CONVERSION_FACTOR,
-                    *best_ask.1 as f64 / CONVERSION_FACTOR,
+                    self.converter.to_f64(best_ask.0),
+                    self.converter.to_f64(best_ask.1),
                 ),
             )),
             _ => None,
         }
     }
 
-    #[allow(dead_code)]
-    fn get_volume_at_price(&self, price: f64) -> f64 {
-        let price_u64 = price.to_u64() as Price;
-        (self.bids.get(&price_u64).unwrap_or(&0) + self.asks.get(&price_u64).unwrap_or(&0)) as f64
-            / CONVERSION_FACTOR
+    // Resting quantity at the price on both sides, 0.0 when the price cannot be converted
+    pub fn get_volume_at_price(&self, price: f64) -> f64 {
+        let Ok(price_u64) = self.converter.to_units(price) else {
+            return 0.0;
+        };
+        // Summed in f64, the two sides can add up to more than `u64::MAX`
+        let volume =
+            |quantity: Option<Quantity>| self.converter.to_f64(quantity.unwrap_or_default());
+        volume(self.bids.get(Price(price_u64))) + volume(self.asks.get(Price(price_u64)))
+    }
+}
+
+impl fmt::Display for OrderBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot(usize::MAX).fmt(f)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvLevel {
+    side: String,
+    price: f64,
+    quantity: f64,
+}
+
+// Point-in-time copy of the book levels, in internal units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub symbol: Symbol,
+    pub last_update_id: u64,
+    pub bids: Vec<(Price, Quantity)>,
+    pub asks: Vec<(Price, Quantity)>,
+    // Units of the levels, the converter of the book the snapshot was taken from
+    #[serde(default)]
+    pub converter: PriceConverter,
+}
+
+// Change of one level of the book by the update `update_id`, a zero quantity is an absent level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelMutation {
+    pub update_id: u64,
+    pub side: Side,
+    pub price: Price,
+    pub old_quantity: Quantity,
+    pub new_quantity: Quantity,
+}
+
+// What the book made of an update. Gaps are only reported for the engine feed, see
+// `OrderBook::apply_market_data`, the update ids of Binance depth payloads skip numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpdateOutcome {
+    Applied,
+    // Same update id as the last one applied, e.g. a payload delivered twice. Nothing changes.
+    Duplicate,
+    // Older than the last update applied, nothing changes
+    Stale,
+    // Applied, but `missed` update ids since the last one never arrived
+    Gap { missed: u64 },
+}
+
+impl UpdateOutcome {
+    pub fn is_applied(self) -> bool {
+        matches!(self, UpdateOutcome::Applied | UpdateOutcome::Gap { .. })
+    }
+}
+
+// Totals of `UpdateOutcome`, see `OrderBook::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateStats {
+    // Including the updates after a gap
+    pub applied: u64,
+    pub duplicates: u64,
+    pub stale: u64,
+    pub gaps: u64,
+    // Update ids skipped by all the gaps
+    pub missed: u64,
+}
+
+impl UpdateStats {
+    fn record(&mut self, outcome: UpdateOutcome) {
+        match outcome {
+            UpdateOutcome::Applied => self.applied += 1,
+            UpdateOutcome::Duplicate => self.duplicates += 1,
+            UpdateOutcome::Stale => self.stale += 1,
+            UpdateOutcome::Gap { missed } => {
+                self.applied += 1;
+                self.gaps += 1;
+                self.missed += missed;
+            }
+        }
+    }
+}
+
+// Change of one level between two snapshots, a zero quantity removes the level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelDelta {
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+impl DepthSnapshot {
+    // Minimal set of level changes turning `self` into `other`, bids then asks, each best
+    // first. Snapshots only hold their top levels, so a level that fell out of the top of
+    // `other` shows up as removed.
+    pub fn diff(&self, other: &DepthSnapshot) -> Vec<LevelDelta> {
+        let mut deltas = Vec::new();
+        diff_side(Side::Buy, &self.bids, &other.bids, &mut deltas);
+        diff_side(Side::Sell, &self.asks, &other.asks, &mut deltas);
+        deltas
+    }
+
+    // Applies `diff` output, levels stay in best-first order
+    pub fn apply_deltas(&mut self, deltas: &[LevelDelta]) {
+        for delta in deltas {
+            let levels = match delta.side {
+                Side::Buy => &mut self.bids,
+                Side::Sell => &mut self.asks,
+            };
+            let position = levels.binary_search_by(|level| match delta.side {
+                Side::Buy => delta.price.cmp(&level.0),
+                Side::Sell => level.0.cmp(&delta.price),
+            });
+            match (position, delta.quantity) {
+                (Ok(index), Quantity::ZERO) => {
+                    levels.remove(index);
+                }
+                (Ok(index), quantity) => levels[index].1 = quantity,
+                (Err(_), Quantity::ZERO) => {}
+                (Err(index), quantity) => levels.insert(index, (delta.price, quantity)),
+            }
+        }
+    }
+}
+
+// Merge walk over two sides in the same best-first order
+fn diff_side(
+    side: Side,
+    from: &[(Price, Quantity)],
+    to: &[(Price, Quantity)],
+    deltas: &mut Vec<LevelDelta>,
+) {
+    // Whether `a` comes before `b` in best-first order
+    let order = |a: Price, b: Price| match side {
+        Side::Buy => b.cmp(&a),
+        Side::Sell => a.cmp(&b),
+    };
+    let mut from = from.iter().peekable();
+    let mut to = to.iter().peekable();
+    loop {
+        let delta = match (from.peek(), to.peek()) {
+            (None, None) => break,
+            (Some(&&(price, _)), None) => {
+                from.next();
+                (price, Quantity::ZERO)
+            }
+            (None, Some(&&level)) => {
+                to.next();
+                level
+            }
+            (Some(&&(old_price, old_quantity)), Some(&&(price, quantity))) => {
+                match order(old_price, price) {
+                    Ordering::Equal => {
+                        from.next();
+                        to.next();
+                        if old_quantity == quantity {
+                            continue;
+                        }
+                        (price, quantity)
+                    }
+                    Ordering::Less => {
+                        from.next();
+                        (old_price, Quantity::ZERO)
+                    }
+                    Ordering::Greater => {
+                        to.next();
+                        (price, quantity)
+                    }
+                }
+            }
+        };
+        deltas.push(LevelDelta {
+            side,
+            price: delta.0,
+            quantity: delta.1,
+        });
     }
 }
 
+// Ladder view: asks on top (worst to best), then bids (best to worst)
+impl fmt::Display for DepthSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (last update id {})",
+            self.symbol, self.last_update_id
+        )?;
+        for (price, qty) in self.asks.iter().rev() {
+            write_ladder_row(f, &self.converter, "ASK", *price, *qty)?;
+        }
+        writeln!(f, "{}", "-".repeat(LADDER_WIDTH))?;
+        for (price, qty) in &self.bids {
+            write_ladder_row(f, &self.converter, "BID", *price, *qty)?;
+        }
+        Ok(())
+    }
+}
+
+const LADDER_WIDTH: usize = 35;
+
+// Shows as many decimals as the converter keeps
+fn write_ladder_row(
+    f: &mut fmt::Formatter<'_>,
+    converter: &PriceConverter,
+    side: &str,
+    price: Price,
+    qty: Quantity,
+) -> fmt::Result {
+    let decimals = converter.scale() as usize;
+    writeln!(
+        f,
+        "{} {:>14.*} | {:>14.*}",
+        side,
+        decimals,
+        converter.to_f64(price),
+        decimals,
+        converter.to_f64(qty)
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::binance_payloads;
+    use crate::price_converter::RoundingMode;
 
     #[test]
     fn test_new_order_book() {
         let orderbook = OrderBook::new("BNBUSDT".to_string());
         assert_eq!(orderbook.symbol, "BNBUSDT");
-        assert!(orderbook.bids.is_empty());
-        assert!(orderbook.asks.is_empty());
+        assert!(orderbook.bids.levels.is_empty());
+        assert!(orderbook.asks.levels.is_empty());
         assert_eq!(orderbook.last_update_id, 0);
     }
 
@@ -121,35 +934,63 @@ mod tests {
     fn test_update_book_ticker() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         let book_ticker_update = binance_payloads::BookTickerUpdate {
+            event_time: None,
             update_id: 400900217,
-            symbol: "BNBUSDT".to_string(),
+            symbol: Symbol::intern("BNBUSDT"),
             best_bid_price: 25.3519,
             best_bid_quantity: 31.21,
             best_ask_price: 25.3652,
             best_ask_quantity: 40.66,
         };
-        orderbook.update_book_ticker(&book_ticker_update);
-        assert_eq!(orderbook.bids.len(), 1);
-        assert_eq!(orderbook.asks.len(), 1);
-        assert_eq!(*orderbook.bids.get(&253519).unwrap(), 312100);
-        assert_eq!(*orderbook.asks.get(&253652).unwrap(), 406600);
+        orderbook.update_book_ticker(&book_ticker_update).unwrap();
+        assert_eq!(orderbook.bids.levels.len(), 1);
+        assert_eq!(orderbook.asks.levels.len(), 1);
+        assert_eq!(orderbook.bids.get(Price(253519)).unwrap(), Quantity(312100));
+        assert_eq!(orderbook.asks.get(Price(253652)).unwrap(), Quantity(406600));
+    }
+
+    #[test]
+    fn test_update_book_ticker_ref() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        let mut book_ticker_update = binance_payloads::BookTickerUpdateRef {
+            event_time: None,
+            update_id: 400900217,
+            symbol: "BNBUSDT",
+            best_bid_price: "25.35190000",
+            best_bid_quantity: "31.21000000",
+            best_ask_price: "25.36520000",
+            best_ask_quantity: "40.66000000",
+        };
+        orderbook
+            .update_book_ticker_ref(&book_ticker_update)
+            .unwrap();
+        assert_eq!(orderbook.bids.get(Price(253519)).unwrap(), Quantity(312100));
+        assert_eq!(orderbook.asks.get(Price(253652)).unwrap(), Quantity(406600));
+
+        book_ticker_update.best_bid_price = "25.3520";
+        book_ticker_update.best_ask_quantity = "n/a";
+        assert!(orderbook
+            .update_book_ticker_ref(&book_ticker_update)
+            .is_err());
+        assert_eq!(orderbook.bids.levels.len(), 1);
     }
 
     #[test]
     fn test_update_depth() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 160,
             bids: vec![(0.0024, 10.0), (0.0025, 20.0)],
             asks: vec![(0.0026, 100.0), (0.0027, 200.0)],
         };
         orderbook.update_depth(&depth_update);
-        assert_eq!(orderbook.bids.len(), 2);
-        assert_eq!(orderbook.asks.len(), 2);
-        assert_eq!(*orderbook.bids.get(&24).unwrap(), 100000);
-        assert_eq!(*orderbook.bids.get(&25).unwrap(), 200000);
-        assert_eq!(*orderbook.asks.get(&26).unwrap(), 1000000);
-        assert_eq!(*orderbook.asks.get(&27).unwrap(), 2000000);
+        assert_eq!(orderbook.bids.levels.len(), 2);
+        assert_eq!(orderbook.asks.levels.len(), 2);
+        assert_eq!(orderbook.bids.get(Price(24)).unwrap(), Quantity(100000));
+        assert_eq!(orderbook.bids.get(Price(25)).unwrap(), Quantity(200000));
+        assert_eq!(orderbook.asks.get(Price(26)).unwrap(), Quantity(1000000));
+        assert_eq!(orderbook.asks.get(Price(27)).unwrap(), Quantity(2000000));
         assert_eq!(orderbook.last_update_id, 160);
     }
 
@@ -158,20 +999,120 @@ mod tests {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         orderbook.last_update_id = 200;
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 150,
             bids: vec![(0.0024, 10.0)],
             asks: vec![(0.0026, 100.0)],
         };
         orderbook.update_depth(&depth_update);
-        assert!(orderbook.bids.is_empty());
-        assert!(orderbook.asks.is_empty());
+        assert!(orderbook.bids.levels.is_empty());
+        assert!(orderbook.asks.levels.is_empty());
         assert_eq!(orderbook.last_update_id, 200);
     }
 
+    #[test]
+    fn test_update_outcomes_and_stats() {
+        let mut orderbook = OrderBook::new("BNBUSDT");
+        let depth = |last_update_id, quantity| binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id,
+            bids: vec![(0.0024, quantity)],
+            asks: vec![],
+        };
+        assert_eq!(
+            orderbook.update_depth(&depth(10, 1.0)),
+            UpdateOutcome::Applied
+        );
+        assert_eq!(
+            orderbook.update_depth(&depth(11, 2.0)),
+            UpdateOutcome::Applied
+        );
+        assert_eq!(
+            orderbook.update_depth(&depth(11, 3.0)),
+            UpdateOutcome::Duplicate
+        );
+        assert_eq!(orderbook.update_depth(&depth(9, 4.0)), UpdateOutcome::Stale);
+        // Partial depth payloads skip update ids
+        assert_eq!(
+            orderbook.update_depth(&depth(15, 5.0)),
+            UpdateOutcome::Applied
+        );
+        assert_eq!(orderbook.get_volume_at_price(0.0024), 5.0);
+
+        let ticker = |update_id, quantity| binance_payloads::BookTickerUpdate {
+            event_time: None,
+            update_id,
+            symbol: Symbol::intern("BNBUSDT"),
+            best_bid_price: 0.0025,
+            best_bid_quantity: quantity,
+            best_ask_price: 0.0026,
+            best_ask_quantity: 1.0,
+        };
+        let mut outcome = |update_id, quantity| {
+            orderbook
+                .update_book_ticker(&ticker(update_id, quantity))
+                .unwrap()
+        };
+        assert_eq!(outcome(20, 1.0), UpdateOutcome::Applied);
+        assert_eq!(outcome(30, 2.0), UpdateOutcome::Applied);
+        assert_eq!(outcome(30, 3.0), UpdateOutcome::Duplicate);
+        assert_eq!(outcome(25, 4.0), UpdateOutcome::Stale);
+        assert_eq!(orderbook.get_volume_at_price(0.0025), 2.0);
+
+        assert_eq!(
+            orderbook.stats(),
+            UpdateStats {
+                applied: 5,
+                duplicates: 2,
+                stale: 2,
+                gaps: 0,
+                missed: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_engine_feed_gaps() {
+        use crate::market_data::{BookSnapshot, LevelDelta, TradePrint};
+        use crate::orderbookv2;
+
+        let mut orderbook = OrderBook::new("SIM");
+        let delta = |sequence| {
+            MarketDataMessage::Delta(LevelDelta {
+                sequence,
+                side: Side::Buy,
+                price: orderbookv2::Price(100),
+                quantity: orderbookv2::Quantity(sequence as u32),
+            })
+        };
+        orderbook.apply_market_data(&MarketDataMessage::Snapshot(BookSnapshot {
+            sequence: 1,
+            bids: vec![],
+            asks: vec![],
+        }));
+        orderbook.apply_market_data(&delta(2));
+        orderbook.apply_market_data(&MarketDataMessage::Trade(TradePrint {
+            sequence: 3,
+            timestamp: 0,
+            price: orderbookv2::Price(100),
+            quantity: orderbookv2::Quantity(1),
+            aggressor: Some(Side::Sell),
+        }));
+        // The trade took sequence 3
+        orderbook.apply_market_data(&delta(4));
+        assert_eq!(orderbook.stats().gaps, 0);
+
+        orderbook.apply_market_data(&delta(7));
+        assert_eq!(orderbook.get_volume_at_price(100.0), 7.0);
+        assert_eq!(orderbook.stats().gaps, 1);
+        assert_eq!(orderbook.stats().missed, 2);
+    }
+
     #[test]
     fn test_update_depth_with_zero_quantity() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 160,
             bids: vec![(0.0024, 10.0), (0.0025, 0.0)],
             asks: vec![(0.0026, 0.0), (0.0027, 200.0)],
@@ -181,16 +1122,50 @@ mod tests {
         let ((bid_price, _bid_amount), (ask_price, _ask_amount)) =
             orderbook.get_best_bid_ask().unwrap();
 
-        assert_eq!(orderbook.bids.len(), 1);
-        assert_eq!(orderbook.asks.len(), 1);
+        assert_eq!(orderbook.bids.levels.len(), 1);
+        assert_eq!(orderbook.asks.levels.len(), 1);
         assert_eq!(bid_price, 0.0024);
         assert_eq!(ask_price, 0.0027);
     }
 
+    #[test]
+    fn test_converter_scale_and_invalid_levels() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        orderbook.set_converter(PriceConverter::new(2).with_rounding(RoundingMode::Floor));
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(10.129, 1.0), (f64::NAN, 1.0), (9.0, -1.0)],
+            asks: vec![(10.5, 1e30)],
+        });
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(1012), Quantity(100))]
+        );
+        assert_eq!(orderbook.asks().count(), 0);
+        assert_eq!(orderbook.get_best_bid_ask(), None);
+
+        let book_ticker_update = binance_payloads::BookTickerUpdate {
+            event_time: None,
+            update_id: 2,
+            symbol: Symbol::intern("BNBUSDT"),
+            best_bid_price: 10.2,
+            best_bid_quantity: 1.0,
+            best_ask_price: 10.5,
+            best_ask_quantity: -2.0,
+        };
+        assert_eq!(
+            orderbook.update_book_ticker(&book_ticker_update),
+            Err(ConversionError::Negative)
+        );
+        assert_eq!(orderbook.bids().count(), 1);
+    }
+
     #[test]
     fn test_get_best_bid_ask() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 160,
             bids: vec![(0.0024, 10.0), (0.0025, 20.0)],
             asks: vec![(0.0026, 100.0), (0.0027, 200.0)],
@@ -207,10 +1182,97 @@ mod tests {
         assert_eq!(best_bid_ask, None);
     }
 
+    #[test]
+    fn test_mid_price() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        assert_eq!(orderbook.mid_price(), None);
+
+        let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(10.0, 1.0)],
+            asks: vec![(10.5, 1.0)],
+        };
+        orderbook.update_depth(&depth_update);
+        assert_eq!(orderbook.mid_price(), Some(10.25));
+    }
+
+    #[test]
+    fn test_cached_best_levels() {
+        for backend in [
+            BookBackend::BTree,
+            BookBackend::Dense { tick_size: 1 },
+            BookBackend::SkipList,
+        ] {
+            let mut orderbook = OrderBook::with_backend("BNBUSDT", backend);
+            let update = |orderbook: &mut OrderBook, last_update_id, bids, asks| {
+                orderbook.update_depth(&binance_payloads::DepthUpdate {
+                    event_time: None,
+                    last_update_id,
+                    bids,
+                    asks,
+                });
+                let tree_bid = orderbook.bids.levels.last().map(|(p, q)| (p, *q));
+                let tree_ask = orderbook.asks.levels.first().map(|(p, q)| (p, *q));
+                assert_eq!(orderbook.best_bid(), tree_bid);
+                assert_eq!(orderbook.best_ask(), tree_ask);
+                (orderbook.best_bid(), orderbook.best_ask())
+            };
+
+            update(
+                &mut orderbook,
+                1,
+                vec![(10.0, 1.0), (9.0, 2.0)],
+                vec![(11.0, 3.0), (12.0, 4.0)],
+            );
+            // A better bid, a new quantity at the best ask, a level behind the best
+            let (bid, ask) = update(
+                &mut orderbook,
+                2,
+                vec![(10.5, 1.0), (8.0, 1.0)],
+                vec![(11.0, 5.0)],
+            );
+            assert_eq!(bid, Some((Price(105_000), Quantity(10_000))));
+            assert_eq!(ask, Some((Price(110_000), Quantity(50_000))));
+            // Removing the best levels falls back to the next ones
+            let (bid, ask) = update(&mut orderbook, 3, vec![(10.5, 0.0)], vec![(11.0, 0.0)]);
+            assert_eq!(bid, Some((Price(100_000), Quantity(10_000))));
+            assert_eq!(ask, Some((Price(120_000), Quantity(40_000))));
+            // Removing other levels keeps them
+            update(
+                &mut orderbook,
+                4,
+                vec![(8.0, 0.0), (7.0, 0.0)],
+                vec![(12.5, 0.0)],
+            );
+            let (bid, ask) = update(&mut orderbook, 5, vec![], vec![(12.0, 0.0)]);
+            assert_eq!(bid, Some((Price(100_000), Quantity(10_000))));
+            assert_eq!(ask, None);
+            assert_eq!(orderbook.spread(), None);
+
+            update(&mut orderbook, 6, vec![], vec![(10.25, 2.0)]);
+            assert_eq!(orderbook.spread(), Some(0.25));
+            let json = serde_json::to_string(&orderbook).unwrap();
+            let restored: OrderBook = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.best_bid(), orderbook.best_bid());
+            assert_eq!(restored.best_ask(), orderbook.best_ask());
+
+            orderbook
+                .import_csv("side,price,quantity\nask,13.0,1.0\n".as_bytes())
+                .unwrap();
+            assert_eq!(orderbook.best_bid(), None);
+            assert_eq!(
+                orderbook.best_ask(),
+                Some((Price(130_000), Quantity(10_000)))
+            );
+        }
+    }
+
     #[test]
     fn test_get_volume_at_price() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 160,
             bids: vec![(0.0024, 10.0), (0.0025, 20.0)],
             asks: vec![(0.0024, 100.0), (0.0027, 200.0)],
@@ -223,31 +1285,369 @@ mod tests {
         assert_eq!(orderbook.get_volume_at_price(0.0028), 0.0);
     }
 
+    #[test]
+    fn test_aggregation_at_u64_boundary() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        orderbook.set_converter(PriceConverter::new(0));
+        let price = 1 << 62;
+        orderbook.bids.insert(Price(price), Quantity(u64::MAX));
+        orderbook.asks.insert(Price(price), Quantity(u64::MAX));
+        orderbook.asks.insert(Price(u64::MAX), Quantity(1));
+
+        assert_eq!(
+            orderbook.get_volume_at_price(price as f64),
+            2.0 * u64::MAX as f64
+        );
+        assert_eq!(orderbook.get_volume_at_price(u64::MAX as f64), 0.0);
+        assert_eq!(orderbook.mid_price(), Some(price as f64));
+
+        orderbook.bids.insert(Price(u64::MAX - 1), Quantity(1));
+        orderbook.asks.remove(Price(price));
+        assert_eq!(orderbook.mid_price(), Some(u64::MAX as f64));
+    }
+
     #[test]
     fn test_get_volume_at_price_with_empty_orderbook() {
         let orderbook = OrderBook::new("BNBUSDT".to_string());
         assert_eq!(orderbook.get_volume_at_price(0.0024), 0.0);
     }
 
-    // If you want to see an extra output here:
-    // add display feature when running `cargo test`
+    #[test]
+    fn test_csv_round_trip() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(25.35, 10.0), (25.34, 2.5)],
+            asks: vec![(25.36, 1.25)],
+        });
+
+        let mut buffer = Vec::new();
+        orderbook.export_csv(&mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            "side,price,quantity\nbid,25.35,10.0\nbid,25.34,2.5\nask,25.36,1.25\n"
+        );
+
+        let mut restored = OrderBook::new("BNBUSDT".to_string());
+        restored.import_csv(buffer.as_slice()).unwrap();
+        assert_eq!(restored.bids, orderbook.bids);
+        assert_eq!(restored.asks, orderbook.asks);
+        assert!(restored
+            .import_csv("side,price\nbid,1.0\n".as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn test_apply_market_data() {
+        use crate::market_data::{BookSnapshot, LevelDelta};
+        use crate::orderbookv2::{self, LevelInfo, Side};
+
+        let mut orderbook = OrderBook::new("SIM".to_string());
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 500,
+            bids: vec![(1.0, 1.0)],
+            asks: vec![],
+        });
+
+        orderbook.apply_market_data(&MarketDataMessage::Snapshot(BookSnapshot {
+            sequence: 1,
+            bids: vec![LevelInfo {
+                price: orderbookv2::Price(100),
+                quantity: orderbookv2::Quantity(5),
+            }],
+            asks: vec![LevelInfo {
+                price: orderbookv2::Price(101),
+                quantity: orderbookv2::Quantity(2),
+            }],
+        }));
+        orderbook.apply_market_data(&MarketDataMessage::Delta(LevelDelta {
+            sequence: 2,
+            side: Side::Sell,
+            price: orderbookv2::Price(101),
+            quantity: orderbookv2::Quantity(0),
+        }));
+
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(1_000_000), Quantity(50_000))]
+        );
+        assert_eq!(orderbook.asks().count(), 0);
+        assert_eq!(orderbook.last_update_id, 2);
+    }
+
+    #[test]
+    fn test_bids_and_asks_iterate_best_first() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(0.0024, 10.0), (0.0025, 20.0), (0.0023, 5.0)],
+            asks: vec![(0.0027, 200.0), (0.0026, 100.0), (0.0028, 50.0)],
+        };
+        orderbook.update_depth(&depth_update);
+
+        let bids: Vec<(Price, Quantity)> = orderbook.bids().collect();
+        let asks: Vec<(Price, Quantity)> = orderbook.asks().collect();
+        assert_eq!(
+            bids,
+            vec![
+                (Price(25), Quantity(200000)),
+                (Price(24), Quantity(100000)),
+                (Price(23), Quantity(50000))
+            ]
+        );
+        assert_eq!(
+            asks,
+            vec![
+                (Price(26), Quantity(1000000)),
+                (Price(27), Quantity(2000000)),
+                (Price(28), Quantity(500000))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bids_and_asks_with_empty_book() {
+        let orderbook = OrderBook::new("BNBUSDT".to_string());
+        assert_eq!(orderbook.bids().next(), None);
+        assert_eq!(orderbook.asks().next(), None);
+    }
+
+    #[test]
+    fn test_snapshot_takes_top_levels() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(0.0024, 10.0), (0.0025, 20.0), (0.0023, 5.0)],
+            asks: vec![(0.0027, 200.0), (0.0026, 100.0), (0.0028, 50.0)],
+        };
+        orderbook.update_depth(&depth_update);
+
+        let snapshot = orderbook.snapshot(2);
+        assert_eq!(snapshot.symbol, "BNBUSDT");
+        assert_eq!(snapshot.last_update_id, 160);
+        assert_eq!(
+            snapshot.bids,
+            vec![(Price(25), Quantity(200000)), (Price(24), Quantity(100000))]
+        );
+        assert_eq!(
+            snapshot.asks,
+            vec![
+                (Price(26), Quantity(1000000)),
+                (Price(27), Quantity(2000000))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregated_depth() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(25.3, 1.0), (25.1, 2.0), (24.9, 3.0)],
+            asks: vec![(25.4, 1.0), (25.5, 2.0), (25.6, 4.0)],
+        });
+
+        let grouped = orderbook.aggregated_depth(Price(5_000));
+        assert_eq!(grouped.last_update_id, 160);
+        assert_eq!(
+            grouped.bids,
+            vec![
+                (Price(250_000), Quantity(30_000)),
+                (Price(245_000), Quantity(30_000))
+            ]
+        );
+        assert_eq!(
+            grouped.asks,
+            vec![
+                (Price(255_000), Quantity(30_000)),
+                (Price(260_000), Quantity(40_000))
+            ]
+        );
+        assert_eq!(orderbook.aggregated_depth(Price(1)).bids.len(), 3);
+    }
+
+    #[test]
+    fn test_dense_backend_matches_btree() {
+        let mut tree = OrderBook::new("BNBUSDT".to_string());
+        let mut dense =
+            OrderBook::with_backend("BNBUSDT".to_string(), BookBackend::Dense { tick_size: 100 });
+        let updates = [
+            binance_payloads::DepthUpdate {
+                event_time: None,
+                last_update_id: 1,
+                bids: vec![(25.35, 1.0), (25.33, 2.0)],
+                asks: vec![(25.36, 3.0), (25.40, 1.5)],
+            },
+            binance_payloads::DepthUpdate {
+                event_time: None,
+                last_update_id: 2,
+                bids: vec![(25.35, 0.0), (25.20, 4.0)],
+                asks: vec![(25.36, 0.0), (25.37, 2.0)],
+            },
+        ];
+        for update in &updates {
+            tree.update_depth(update);
+            dense.update_depth(update);
+            assert_eq!(dense.snapshot(10), tree.snapshot(10));
+            assert_eq!(dense.get_best_bid_ask(), tree.get_best_bid_ask());
+        }
+
+        assert_eq!(dense.backend(), BookBackend::Dense { tick_size: 100 });
+        let json = serde_json::to_string(&dense).unwrap();
+        let restored: OrderBook = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.backend(), dense.backend());
+        assert_eq!(restored.snapshot(10), tree.snapshot(10));
+    }
+
+    #[test]
+    fn test_skip_list_backend_matches_btree() {
+        let mut tree = OrderBook::new("BNBUSDT".to_string());
+        let mut skip_list = OrderBook::with_backend("BNBUSDT".to_string(), BookBackend::SkipList);
+        for last_update_id in 1..=50u64 {
+            let price = 25.0 + (last_update_id * 7 % 11) as f64 / 100.0;
+            let quantity = (last_update_id % 3) as f64;
+            let update = binance_payloads::DepthUpdate {
+                event_time: None,
+                last_update_id,
+                bids: vec![(price - 0.11, quantity)],
+                asks: vec![(price, quantity)],
+            };
+            tree.update_depth(&update);
+            skip_list.update_depth(&update);
+            assert_eq!(skip_list.snapshot(20), tree.snapshot(20));
+        }
+    }
+
+    #[test]
+    fn test_orderbook_serde_roundtrip() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(0.0024, 10.0)],
+            asks: vec![(0.0026, 100.0)],
+        };
+        orderbook.update_depth(&depth_update);
+
+        let json = serde_json::to_string(&orderbook).unwrap();
+        let restored: OrderBook = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.snapshot(10), orderbook.snapshot(10));
+
+        let snapshot = orderbook.snapshot(10);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DepthSnapshot>(&json).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(10.0, 1.0), (9.0, 2.0), (8.0, 3.0)],
+            asks: vec![(11.0, 1.0), (12.0, 2.0)],
+        });
+        let before = orderbook.snapshot(10);
+        assert!(before.diff(&before).is_empty());
+
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 2,
+            bids: vec![(9.5, 4.0), (9.0, 0.0), (8.0, 5.0)],
+            asks: vec![(11.0, 0.0), (13.0, 1.0)],
+        });
+        let after = orderbook.snapshot(10);
+
+        let level = |side, price, quantity| LevelDelta {
+            side,
+            price,
+            quantity,
+        };
+        let deltas = before.diff(&after);
+        assert_eq!(
+            deltas,
+            vec![
+                level(Side::Buy, Price(95_000), Quantity(40_000)),
+                level(Side::Buy, Price(90_000), Quantity(0)),
+                level(Side::Buy, Price(80_000), Quantity(50_000)),
+                level(Side::Sell, Price(110_000), Quantity(0)),
+                level(Side::Sell, Price(130_000), Quantity(10_000)),
+            ]
+        );
+        assert_eq!(after.diff(&before).len(), deltas.len());
+
+        // The deltas rebuild `after` from `before`
+        let mut rebuilt = before.clone();
+        rebuilt.apply_deltas(&deltas);
+        assert_eq!((rebuilt.bids, rebuilt.asks), (after.bids, after.asks));
+    }
+
+    #[test]
+    fn test_display_ladder() {
+        let mut orderbook = OrderBook::new("BNBUSDT".to_string());
+        let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 160,
+            bids: vec![(0.0024, 10.0), (0.0025, 20.0)],
+            asks: vec![(0.0026, 100.0), (0.0027, 200.0)],
+        };
+        orderbook.update_depth(&depth_update);
+
+        let expected = "\
+BNBUSDT (last update id 160)
+ASK         0.0027 |       200.0000
+ASK         0.0026 |       100.0000
+-----------------------------------
+BID         0.0025 |        20.0000
+BID         0.0024 |        10.0000
+";
+        assert_eq!(orderbook.to_string(), expected);
+
+        // Levels are read with the book's own converter
+        let mut orderbook = OrderBook::new("BTCUSDT");
+        orderbook.set_converter(PriceConverter::new(8));
+        orderbook.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 7,
+            bids: vec![(64000.5, 0.00012345)],
+            asks: vec![],
+        });
+        let expected = "\
+BTCUSDT (last update id 7)
+-----------------------------------
+BID 64000.50000000 |     0.00012345
+";
+        assert_eq!(orderbook.to_string(), expected);
+    }
+
+    // Prints the book as it goes with `cargo test --features demo-output -- --nocapture`
     #[test]
     fn flow_test() {
         let mut orderbook = OrderBook::new("BNBUSDT".to_string());
 
         // Update with Book Ticker data
         let book_ticker_update = binance_payloads::BookTickerUpdate {
+            event_time: None,
             update_id: 400900217,
-            symbol: "BNBUSDT".to_string(),
+            symbol: Symbol::intern("BNBUSDT"),
             best_bid_price: 25.3519,
             best_bid_quantity: 31.21,
             best_ask_price: 25.3652,
             best_ask_quantity: 40.66,
         };
-        orderbook.update_book_ticker(&book_ticker_update);
+        orderbook.update_book_ticker(&book_ticker_update).unwrap();
 
         // Update with Partial Book Depth data
         let depth_update = binance_payloads::DepthUpdate {
+            event_time: None,
             last_update_id: 160,
             bids: vec![(0.0024, 10.0)],
             asks: vec![(0.0026, 100.0)],
@@ -255,14 +1655,14 @@ mod tests {
         orderbook.update_depth(&depth_update);
 
         // Get the best bid and ask prices and quantities
-        if let Some(((bid_price, bid_qty), (ask_price, ask_qty))) = orderbook.get_best_bid_ask() {
-            println!("Best Bid: Price - {}, Quantity - {}", bid_price, bid_qty);
-            println!("Best Ask: Price - {}, Quantity - {}", ask_price, ask_qty);
-        }
+        let ((bid_price, bid_qty), (ask_price, ask_qty)) = orderbook.get_best_bid_ask().unwrap();
+        // The depth levels are merged into the ticker levels, which leaves the book crossed
+        assert_eq!((bid_price, bid_qty), (25.3519, 31.21));
+        assert_eq!((ask_price, ask_qty), (0.0026, 100.0));
 
         // Get the total volume at a given price level
         let price = 0.0024;
         let volume = orderbook.get_volume_at_price(price);
-        println!("Volume at price {}: {}", price, volume);
+        assert_eq!(volume, 10.0);
     }
 }
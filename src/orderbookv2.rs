@@ -1,9 +1,33 @@
 /// This implementation supports a more detailed view on orders and order management
 /// In this implementation we support
+use crate::accounts::Accounts;
+use crate::audit::{AuditTrail, CancelReason, OrderAuditRecord, OrderEvent};
+pub use crate::book_core::{AccountId, Notional, OrderId, OrderType, Price, Quantity, Side};
+use crate::candles::CandleBuilder;
+use crate::capacity::{CapacityLimits, CapacityViolation};
+use crate::circuit_breaker::{BreakerAction, CircuitBreaker};
+use crate::clock::{Clock, Nanos};
+use crate::digest;
+use crate::events::EngineEvent;
+use crate::fees::FeeRates;
+use crate::ids::{ClientOrderIds, OrderIdAllocator};
+use crate::instruments::{Instrument, InstrumentViolation};
+use crate::market_data::{BookSnapshot, MarketDataMessage, MarketDataPublisher};
+use crate::price_levels::{self, BookBackend, LevelStore, PriceLevels};
+use crate::rate_limit::RateLimiter;
+use crate::risk::{RiskContext, RiskManager, RiskViolation};
+use crate::session::{SessionState, SessionStatistics};
+use crate::stops::{StopOrder, StopOrders, TriggerPrices};
+use crate::surveillance::Surveillance;
+use crate::trade_tape::{MarketStatistics, TapeTrade, TradeTape};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
-    cell::RefCell,
+    cell::{Ref, RefCell},
     collections::{btree_map, HashMap, VecDeque},
+    fmt,
     rc::Rc,
+    time::Duration,
 };
 
 // FOK type of order
@@ -17,68 +41,178 @@ use std::{
 // Give Up - An order to be given to another member firm in the clearing system, an allocation. An order executed by clearing firm A and given to clearing firm B where it will be cleared and processed. Give up order indicator is "GU" populated in the F-Ex field.
 // Good Till Cancel (GTC) Order - GTC orders remain open until they are completely executed or cancelled.
 // Good till Date (GTD) Order - GTD orders expire either at a specified date or when the security expires.
+// Day Order - Day orders rest like GTC orders but are cancelled when the trading session closes.
+
+// Lifecycle of an order as the engine saw it, an amendment starts over at New
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum OrderType {
-    GoodToCancel,
-    FillAndKill,
+pub enum Liquidity {
+    Maker,
+    Taker,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum Side {
-    Buy,
-    Sell,
+// Reasons for the engine to refuse an order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejected {
+    UnknownAccount(AccountId),
+    // Lots for base, notional for quote
+    InsufficientBalance {
+        account_id: AccountId,
+        required: i64,
+        available: i64,
+    },
+    BalanceOverflow(AccountId),
+    Risk(RiskViolation),
+    Instrument(InstrumentViolation),
+    Capacity(CapacityViolation),
+    InvalidOrder(OrderValidationError),
+    RateLimited {
+        account_id: AccountId,
+    },
+    SessionNotOpen(SessionState),
+    DuplicateClientOrderId(String),
+    DuplicateOrderId(OrderId),
+    UnknownOrder(OrderId),
+    // Outside an auction a Fill and Kill order has to cross the book on entry
+    NoLiquidity(OrderId),
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::UnknownAccount(account_id) => write!(f, "unknown account {}", account_id),
+            Rejected::InsufficientBalance {
+                account_id,
+                required,
+                available,
+            } => write!(
+                f,
+                "insufficient balance on account {}: required {}, available {}",
+                account_id, required, available
+            ),
+            Rejected::BalanceOverflow(account_id) => {
+                write!(f, "balance of account {} would overflow", account_id)
+            }
+            Rejected::Risk(violation) => write!(f, "risk check failed: {}", violation),
+            Rejected::Instrument(violation) => write!(f, "{}", violation),
+            Rejected::Capacity(violation) => write!(f, "book capacity exceeded: {}", violation),
+            Rejected::InvalidOrder(error) => write!(f, "invalid order: {}", error),
+            Rejected::RateLimited { account_id } => {
+                write!(f, "account {} exceeded its order rate", account_id)
+            }
+            Rejected::SessionNotOpen(state) => write!(f, "trading session is {}", state),
+            Rejected::DuplicateClientOrderId(client_order_id) => {
+                write!(f, "client order id {} already used", client_order_id)
+            }
+            Rejected::DuplicateOrderId(order_id) => write!(f, "order id {} already used", order_id),
+            Rejected::UnknownOrder(order_id) => write!(f, "unknown order {}", order_id),
+            Rejected::NoLiquidity(order_id) => {
+                write!(f, "fill and kill order {} cannot match", order_id)
+            }
+        }
+    }
 }
 
-type Price = i32;
-type Quantity = u32;
-type OrderId = u64;
+// Total resting quantity of a price level does not fit a `Quantity`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantityOverflow {
+    pub side: Side,
+    pub price: Price,
+}
 
-#[derive(Debug)]
-struct LevelInfo {
-    price: Price,
-    quantity: Quantity,
+impl fmt::Display for QuantityOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} level at {} holds more than {} units",
+            self.side,
+            self.price,
+            Quantity::MAX
+        )
+    }
 }
 
-#[derive(Debug)]
-struct OrderBookLevelInfos {
+impl std::error::Error for QuantityOverflow {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelInfo {
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderBookLevelInfos {
     bids: Vec<LevelInfo>,
     asks: Vec<LevelInfo>,
 }
 
 impl OrderBookLevelInfos {
-    fn new(bids: Vec<LevelInfo>, asks: Vec<LevelInfo>) -> OrderBookLevelInfos {
+    pub fn new(bids: Vec<LevelInfo>, asks: Vec<LevelInfo>) -> OrderBookLevelInfos {
         OrderBookLevelInfos { bids, asks }
     }
 
-    fn from_existing() -> OrderBookLevelInfos {
+    pub fn from_existing() -> OrderBookLevelInfos {
         OrderBookLevelInfos {
             bids: Vec::new(),
             asks: Vec::new(),
         }
     }
 
-    fn get_bids(&self) -> &Vec<LevelInfo> {
+    pub fn get_bids(&self) -> &Vec<LevelInfo> {
         &self.bids
     }
 
-    fn get_asks(&self) -> &Vec<LevelInfo> {
+    pub fn get_asks(&self) -> &Vec<LevelInfo> {
         &self.asks
     }
 }
 
-#[derive(Debug, Clone)]
-struct Order {
+// Ladder view: asks on top (worst to best), then bids (best to worst)
+impl fmt::Display for OrderBookLevelInfos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for level in self.asks.iter().rev() {
+            writeln!(f, "ASK {:>10} | {:>10}", level.price, level.quantity)?;
+        }
+        writeln!(f, "{}", "-".repeat(27))?;
+        for level in &self.bids {
+            writeln!(f, "BID {:>10} | {:>10}", level.price, level.quantity)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
     order_id: OrderId,
     price: Price,
     remaining_quantity: Quantity,
     initial_quantity: Quantity,
     order_type: OrderType,
     side: Side,
+    account_id: AccountId,
+    // Engine time the order was accepted at
+    #[serde(default)]
+    timestamp: Timestamp,
+    // Smallest quantity the order accepts in a single match, see `minimum_fill`
+    #[serde(default)]
+    min_fill_quantity: Option<Quantity>,
+    #[serde(default)]
+    all_or_none: bool,
 }
 
 impl Order {
-    fn new(
+    pub fn builder<'a>() -> OrderBuilder<'a> {
+        OrderBuilder::new()
+    }
+
+    pub fn new(
         order_id: OrderId,
         price: Price,
         quantity: Quantity,
@@ -92,13 +226,87 @@ impl Order {
             initial_quantity: quantity,
             order_type,
             side,
+            account_id: 0,
+            timestamp: 0,
+            min_fill_quantity: None,
+            all_or_none: false,
         }
     }
 
-    fn get_fill_quantity(&self) -> Quantity {
+    pub fn with_account(mut self, account_id: AccountId) -> Order {
+        self.account_id = account_id;
+        self
+    }
+
+    pub fn with_min_fill_quantity(mut self, quantity: Quantity) -> Order {
+        self.min_fill_quantity = Some(quantity);
+        self
+    }
+
+    // Keeps the order intact until its whole remaining quantity can trade in one match
+    pub fn with_all_or_none(mut self) -> Order {
+        self.all_or_none = true;
+        self
+    }
+
+    pub fn get_account_id(&self) -> AccountId {
+        self.account_id
+    }
+
+    pub fn get_timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn get_order_id(&self) -> OrderId {
+        self.order_id
+    }
+
+    pub fn get_price(&self) -> Price {
+        self.price
+    }
+
+    pub fn get_remaining_quantity(&self) -> Quantity {
+        self.remaining_quantity
+    }
+
+    pub fn get_initial_quantity(&self) -> Quantity {
+        self.initial_quantity
+    }
+
+    pub fn get_order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn get_side(&self) -> Side {
+        self.side
+    }
+
+    pub fn get_fill_quantity(&self) -> Quantity {
         self.initial_quantity - self.remaining_quantity
     }
 
+    pub fn get_min_fill_quantity(&self) -> Option<Quantity> {
+        self.min_fill_quantity
+    }
+
+    pub fn is_all_or_none(&self) -> bool {
+        self.all_or_none
+    }
+
+    // The matcher skips the order for smaller matches. A minimum above the remaining
+    // quantity is lowered to it, so a partially filled order can still complete.
+    pub fn minimum_fill(&self) -> Quantity {
+        match (self.all_or_none, self.min_fill_quantity) {
+            (true, _) => self.remaining_quantity,
+            (false, Some(quantity)) => quantity.min(self.remaining_quantity),
+            (false, None) => Quantity::ZERO,
+        }
+    }
+
+    fn has_fill_constraint(&self) -> bool {
+        self.all_or_none || self.min_fill_quantity.is_some()
+    }
+
     fn fill(&mut self, quantity: Quantity) {
         if quantity > self.remaining_quantity {
             panic!("Cannot fill more than the order quantity");
@@ -107,24 +315,170 @@ impl Order {
         self.remaining_quantity -= quantity;
     }
 
-    fn is_filled(&self) -> bool {
-        self.remaining_quantity == 0
+    pub fn is_filled(&self) -> bool {
+        self.remaining_quantity.is_zero()
+    }
+}
+
+// Why `OrderBuilder` refused to build an order
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderValidationError {
+    MissingField(&'static str),
+    NonPositivePrice(Price),
+    ZeroQuantity,
+    MinFillAboveQuantity {
+        min_fill_quantity: Quantity,
+        quantity: Quantity,
+    },
+    Instrument(InstrumentViolation),
+}
+
+impl fmt::Display for OrderValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderValidationError::MissingField(field) => write!(f, "missing {}", field),
+            OrderValidationError::NonPositivePrice(price) => {
+                write!(f, "price {} is not positive", price)
+            }
+            OrderValidationError::ZeroQuantity => write!(f, "quantity is zero"),
+            OrderValidationError::MinFillAboveQuantity {
+                min_fill_quantity,
+                quantity,
+            } => write!(
+                f,
+                "minimum fill {} is above the quantity {}",
+                min_fill_quantity, quantity
+            ),
+            OrderValidationError::Instrument(violation) => write!(f, "{}", violation),
+        }
+    }
+}
+
+impl std::error::Error for OrderValidationError {}
+
+// Checked construction of an `Order`, `Order::new` takes its arguments as they are. Every
+// order type is a limit order, so all of them need an id, a side, a price and a quantity.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBuilder<'a> {
+    order_id: Option<OrderId>,
+    price: Option<Price>,
+    quantity: Option<Quantity>,
+    order_type: Option<OrderType>,
+    side: Option<Side>,
+    account_id: AccountId,
+    min_fill_quantity: Option<Quantity>,
+    all_or_none: bool,
+    instrument: Option<&'a Instrument>,
+}
+
+impl<'a> OrderBuilder<'a> {
+    pub fn new() -> OrderBuilder<'a> {
+        OrderBuilder::default()
+    }
+
+    pub fn order_id(mut self, order_id: OrderId) -> Self {
+        self.order_id = Some(order_id);
+        self
+    }
+
+    pub fn price(mut self, price: Price) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    pub fn quantity(mut self, quantity: Quantity) -> Self {
+        self.quantity = Some(quantity);
+        self
+    }
+
+    pub fn order_type(mut self, order_type: OrderType) -> Self {
+        self.order_type = Some(order_type);
+        self
+    }
+
+    pub fn side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn account(mut self, account_id: AccountId) -> Self {
+        self.account_id = account_id;
+        self
+    }
+
+    pub fn min_fill_quantity(mut self, quantity: Quantity) -> Self {
+        self.min_fill_quantity = Some(quantity);
+        self
+    }
+
+    pub fn all_or_none(mut self) -> Self {
+        self.all_or_none = true;
+        self
+    }
+
+    // Price and quantity have to be on the instrument's tick and lot grid, its trading status
+    // is left to the engine
+    pub fn instrument(mut self, instrument: &'a Instrument) -> Self {
+        self.instrument = Some(instrument);
+        self
+    }
+
+    pub fn build(self) -> Result<Order, OrderValidationError> {
+        let order_id = self
+            .order_id
+            .ok_or(OrderValidationError::MissingField("order id"))?;
+        let side = self
+            .side
+            .ok_or(OrderValidationError::MissingField("side"))?;
+        let order_type = self
+            .order_type
+            .ok_or(OrderValidationError::MissingField("order type"))?;
+        let price = self
+            .price
+            .ok_or(OrderValidationError::MissingField("price"))?;
+        let quantity = self
+            .quantity
+            .ok_or(OrderValidationError::MissingField("quantity"))?;
+
+        if price <= Price::ZERO {
+            return Err(OrderValidationError::NonPositivePrice(price));
+        }
+        if quantity.is_zero() {
+            return Err(OrderValidationError::ZeroQuantity);
+        }
+        if let Some(min_fill_quantity) = self.min_fill_quantity.filter(|min| *min > quantity) {
+            return Err(OrderValidationError::MinFillAboveQuantity {
+                min_fill_quantity,
+                quantity,
+            });
+        }
+        if let Some(instrument) = self.instrument {
+            instrument
+                .check_grid(price.into(), quantity.into())
+                .map_err(OrderValidationError::Instrument)?;
+        }
+
+        Ok(Order {
+            min_fill_quantity: self.min_fill_quantity,
+            all_or_none: self.all_or_none,
+            ..Order::new(order_id, price, quantity, order_type, side).with_account(self.account_id)
+        })
     }
 }
 
 type OrderPointer = Rc<RefCell<Order>>;
 type OrderList = VecDeque<OrderPointer>;
 
-#[derive(Debug, Clone)]
-struct OrderModify {
-    order_id: OrderId,
-    side: Side,
-    price: Price,
-    quantity: Quantity,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderModify {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
 }
 
 impl OrderModify {
-    fn new(order_id: OrderId, side: Side, price: Price, quantity: Quantity) -> OrderModify {
+    pub fn new(order_id: OrderId, side: Side, price: Price, quantity: Quantity) -> OrderModify {
         OrderModify {
             order_id,
             side,
@@ -134,42 +488,623 @@ impl OrderModify {
     }
 }
 
+// Commands accepted by `apply_batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderCommand {
+    New(Order),
+    Cancel(OrderId),
+    Modify(OrderModify),
+}
+
 #[derive(Debug, Clone)]
-struct TradeInfo {
-    order_id: OrderId,
-    price: Price,
-    quantity: Quantity,
+pub struct BatchReport {
+    // One entry per command, in submission order
+    pub results: Vec<Result<(), Rejected>>,
+    // Trades of the whole batch in execution order
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineState {
+    pub timestamp: Timestamp,
+    pub last_trade_price: Option<Price>,
+    pub orders: Vec<Order>,
+}
+
+// Checkpoint of a running engine, see `OrderBook::snapshot`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    // Resting orders in time priority
+    pub book: EngineState,
+    mark_price: Option<Price>,
+    stops: StopOrders,
+    closed_orders: Vec<(OrderId, OrderStatus)>,
+    session: SessionState,
+    session_statistics: SessionStatistics,
+    in_auction: bool,
+    fees_by_account: Vec<(AccountId, f64)>,
+    #[serde(default)]
+    positions: Vec<(AccountId, i64)>,
+    order_ids: OrderIdAllocator,
+    client_order_ids: ClientOrderIds,
+    pending: Vec<(Timestamp, u64, PendingCommand)>,
+    next_sequence: u64,
+    latency_seed: u64,
+    latency_samples: u64,
+}
+
+// Order entry without an exchange order id, the engine allocates one, see `add_client_order`
+#[derive(Debug, Clone)]
+pub struct NewOrder {
+    pub client_order_id: String,
+    pub account_id: AccountId,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub order_type: OrderType,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderAck {
+    pub order_id: OrderId,
+    pub trades: Vec<Trade>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeInfo {
+    pub order_id: OrderId,
+    pub account_id: AccountId,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub liquidity: Liquidity,
+    pub fee: f64,
 }
 
 #[derive(Debug, Clone)]
-struct Trade {
-    bid_trade: TradeInfo,
-    ask_trade: TradeInfo,
+pub struct Trade {
+    pub bid_trade: TradeInfo,
+    pub ask_trade: TradeInfo,
+    pub timestamp: Timestamp,
+}
+
+// Simulated time in nanoseconds
+pub type Timestamp = u64;
+
+// Clock driven by the backtest instead of wall time, it only moves forward
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimClock {
+    now: Timestamp,
+}
+
+impl SimClock {
+    pub fn new(start: Timestamp) -> SimClock {
+        SimClock { now: start }
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    pub fn advance(&mut self, delta: Duration) {
+        self.now += delta.as_nanos() as Timestamp;
+    }
+
+    pub fn advance_to(&mut self, timestamp: Timestamp) {
+        self.now = self.now.max(timestamp);
+    }
+}
+
+// Backtest time for both readings, a copy of the engine clock can be injected into the market
+// data books so their stamps line up with the engine
+impl Clock for SimClock {
+    fn monotonic(&self) -> Nanos {
+        self.now
+    }
+
+    fn wall(&self) -> Nanos {
+        self.now
+    }
+}
+
+// Delay between an order leaving the participant and reaching the matcher
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyModel {
+    Fixed(Duration),
+    Uniform { min: Duration, max: Duration },
+    // Constant network part plus an exponentially distributed queueing tail
+    Exponential { base: Duration, mean_tail: Duration },
+}
+
+impl LatencyModel {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            LatencyModel::Fixed(latency) => latency,
+            LatencyModel::Uniform { min, max } => {
+                if max <= min {
+                    return min;
+                }
+                min + Duration::from_nanos(rng.gen_range(0..=(max - min).as_nanos() as u64))
+            }
+            LatencyModel::Exponential { base, mean_tail } => {
+                let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+                base + mean_tail.mul_f64(-uniform.ln())
+            }
+        }
+    }
+}
+
+// Requests travelling towards the matcher
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum PendingCommand {
+    Add(Order),
+    Cancel(OrderId),
+}
+
+// How the matcher prices the trades it generates
+#[derive(Debug, Clone, Copy)]
+enum Matching {
+    // Incoming order against resting liquidity, trades print at the resting price
+    Continuous { taker: OrderId },
+    // Auction uncross, every trade prints at the equilibrium price
+    Uncross { price: Price },
+}
+
+// How an incoming order is shared among the orders resting at a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MatchingPolicy {
+    // First come first served within the level
+    #[default]
+    PriceTime,
+    // In proportion to the resting quantities, rounded down. Shares below `min_allocation`
+    // are dropped, with `top_order_priority` the oldest order is filled in full first, and
+    // whatever rounding leaves over goes out in time priority.
+    ProRata {
+        min_allocation: Quantity,
+        top_order_priority: bool,
+    },
+}
+
+// Bid, ask and the quantity they traded
+type Fill = (OrderPointer, OrderPointer, Quantity);
+
+// The first pair in time priority that can trade, orders whose minimum fill is not met by
+// the match are skipped. Without minimums that is always the two fronts.
+fn next_match(bids: &OrderList, asks: &OrderList) -> Option<(usize, usize, Quantity)> {
+    bids.iter().enumerate().find_map(|(bid_index, bid)| {
+        let bid = bid.borrow();
+        asks.iter().enumerate().find_map(|(ask_index, ask)| {
+            let ask = ask.borrow();
+            let quantity = std::cmp::min(bid.remaining_quantity, ask.remaining_quantity);
+            (quantity >= bid.minimum_fill() && quantity >= ask.minimum_fill())
+                .then_some((bid_index, ask_index, quantity))
+        })
+    })
+}
+
+// Matches two crossed levels in time priority, filled orders leave their level
+fn fill_price_time(bids: &mut OrderList, asks: &mut OrderList) -> Vec<Fill> {
+    let mut fills = Vec::new();
+    while let Some((bid_index, ask_index, quantity)) = next_match(bids, asks) {
+        let (bid, ask) = (bids[bid_index].clone(), asks[ask_index].clone());
+        bid.borrow_mut().fill(quantity);
+        ask.borrow_mut().fill(quantity);
+
+        if bid.borrow().is_filled() {
+            bids.remove(bid_index);
+        }
+        if ask.borrow().is_filled() {
+            asks.remove(ask_index);
+        }
+        fills.push((bid, ask, quantity));
+    }
+    fills
+}
+
+// Shares the aggressing level over the passive level pro-rata, see `MatchingPolicy::ProRata`.
+// The fills come out as (aggressor, passive, quantity).
+fn fill_pro_rata(
+    aggressors: &mut OrderList,
+    passive: &mut OrderList,
+    min_allocation: Quantity,
+    top_order_priority: bool,
+) -> Vec<Fill> {
+    let open = |orders: &OrderList| -> Vec<u64> {
+        orders
+            .iter()
+            .map(|order| u64::from(order.borrow().remaining_quantity))
+            .collect()
+    };
+    let resting = open(passive);
+    let volume = open(aggressors)
+        .iter()
+        .sum::<u64>()
+        .min(resting.iter().sum());
+
+    let mut allocations = vec![0u64; resting.len()];
+    let mut left = volume;
+    if top_order_priority && !resting.is_empty() {
+        allocations[0] = left.min(resting[0]);
+        left -= allocations[0];
+    }
+    let rest: u64 = resting.iter().zip(&allocations).map(|(q, a)| q - a).sum();
+    if left > 0 && rest > 0 {
+        let base = left;
+        for (quantity, allocation) in resting.iter().zip(allocations.iter_mut()) {
+            let share = ((base as u128 * (quantity - *allocation) as u128) / rest as u128) as u64;
+            if share > 0 && share >= u64::from(min_allocation) {
+                *allocation += share;
+                left -= share;
+            }
+        }
+    }
+    for (quantity, allocation) in resting.iter().zip(allocations.iter_mut()) {
+        let extra = left.min(quantity - *allocation);
+        *allocation += extra;
+        left -= extra;
+    }
+
+    let mut fills = Vec::new();
+    let mut aggressor_orders = aggressors.iter();
+    let mut aggressor = aggressor_orders.next();
+    for (order, mut allocation) in passive.iter().zip(allocations) {
+        while allocation > 0 {
+            let Some(current) = aggressor else { break };
+            let quantity = {
+                let (mut taker, mut maker) = (current.borrow_mut(), order.borrow_mut());
+                // Both fit a `Quantity`, the allocation is at most the resting quantity
+                let quantity = taker.remaining_quantity.min(Quantity(allocation as u32));
+                taker.fill(quantity);
+                maker.fill(quantity);
+                quantity
+            };
+            allocation -= u64::from(quantity);
+            fills.push((current.clone(), order.clone(), quantity));
+            if current.borrow().is_filled() {
+                aggressor = aggressor_orders.next();
+            }
+        }
+    }
+
+    aggressors.retain(|order| !order.borrow().is_filled());
+    passive.retain(|order| !order.borrow().is_filled());
+    fills
 }
 
 #[derive(Debug)]
-struct OrderBook {
-    bids: btree_map::BTreeMap<std::cmp::Reverse<Price>, OrderList>,
-    asks: btree_map::BTreeMap<Price, OrderList>,
+pub struct OrderBook {
+    // Both sides in ascending price order, the best bid is the last level
+    bids: PriceLevels<Price, OrderList>,
+    asks: PriceLevels<Price, OrderList>,
     orders: HashMap<OrderId, OrderPointer>,
+    // Final status of the orders that left the book
+    closed_orders: HashMap<OrderId, OrderStatus>,
+    clock: SimClock,
+    latency: Option<LatencyModel>,
+    rng: StdRng,
+    // The rng is rebuilt from these on `restore`
+    latency_seed: u64,
+    latency_samples: u64,
+    // keyed by (arrival time, submission sequence) so equal arrivals keep submission order
+    pending: btree_map::BTreeMap<(Timestamp, u64), PendingCommand>,
+    next_sequence: u64,
+    fees: FeeRates,
+    fees_by_account: HashMap<AccountId, f64>,
+    // Net traded lots per account, positive when long
+    positions: HashMap<AccountId, i64>,
+    accounts: Option<Accounts>,
+    risk: Option<RiskManager>,
+    instrument: Option<Instrument>,
+    rate_limiter: Option<RateLimiter>,
+    capacity: Option<CapacityLimits>,
+    matching_policy: MatchingPolicy,
+    last_trade_price: Option<Price>,
+    mark_price: Option<Price>,
+    stops: StopOrders,
+    // Orders only accumulate while the auction is running, see `uncross`
+    in_auction: bool,
+    session: SessionState,
+    session_statistics: SessionStatistics,
+    trade_tape: TradeTape,
+    candles: Option<CandleBuilder>,
+    circuit_breaker: Option<CircuitBreaker>,
+    order_ids: OrderIdAllocator,
+    client_order_ids: ClientOrderIds,
+    market_data: Option<MarketDataPublisher>,
+    audit: Option<AuditTrail>,
+    surveillance: Option<Surveillance>,
+    events: Vec<EngineEvent>,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OrderBook {
-    fn new() -> OrderBook {
+    pub fn new() -> OrderBook {
+        OrderBook::with_backend(BookBackend::BTree)
+    }
+
+    // Level storage for both sides, see `price_levels`
+    pub fn with_backend(backend: BookBackend) -> OrderBook {
         OrderBook {
-            bids: btree_map::BTreeMap::new(),
-            asks: btree_map::BTreeMap::new(),
+            bids: PriceLevels::new(backend),
+            asks: PriceLevels::new(backend),
             orders: HashMap::new(),
+            closed_orders: HashMap::new(),
+            clock: SimClock::default(),
+            latency: None,
+            rng: StdRng::seed_from_u64(0),
+            latency_seed: 0,
+            latency_samples: 0,
+            pending: btree_map::BTreeMap::new(),
+            next_sequence: 0,
+            fees: FeeRates::default(),
+            fees_by_account: HashMap::new(),
+            positions: HashMap::new(),
+            accounts: None,
+            risk: None,
+            instrument: None,
+            rate_limiter: None,
+            capacity: None,
+            matching_policy: MatchingPolicy::default(),
+            last_trade_price: None,
+            mark_price: None,
+            stops: StopOrders::new(),
+            in_auction: false,
+            session: SessionState::default(),
+            session_statistics: SessionStatistics::default(),
+            trade_tape: TradeTape::default(),
+            candles: None,
+            circuit_breaker: None,
+            order_ids: OrderIdAllocator::new(),
+            client_order_ids: ClientOrderIds::new(),
+            market_data: None,
+            audit: None,
+            surveillance: None,
+            events: Vec::new(),
+        }
+    }
+
+    // Orders sent through `submit_order`/`submit_cancel` reach the matcher after a sampled delay,
+    // the seed keeps backtests reproducible
+    pub fn with_latency(latency: LatencyModel, seed: u64) -> OrderBook {
+        OrderBook {
+            latency: Some(latency),
+            rng: StdRng::seed_from_u64(seed),
+            latency_seed: seed,
+            ..OrderBook::new()
+        }
+    }
+
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    // Rates applied to trades generated from now on, see `FeeSchedule::rates_for`
+    pub fn set_fees(&mut self, fees: FeeRates) {
+        self.fees = fees;
+    }
+
+    // Total fees charged to the account, negative when rebates dominate
+    pub fn fees_paid(&self, account_id: AccountId) -> f64 {
+        self.fees_by_account
+            .get(&account_id)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub fn fee_totals(&self) -> &HashMap<AccountId, f64> {
+        &self.fees_by_account
+    }
+
+    // Lots the account bought minus the lots it sold
+    pub fn position(&self, account_id: AccountId) -> i64 {
+        self.positions.get(&account_id).copied().unwrap_or(0)
+    }
+
+    // From now on every order needs an account able to fund it, see `place_order`
+    pub fn enable_accounts(&mut self, accounts: Accounts) {
+        self.accounts = Some(accounts);
+    }
+
+    pub fn accounts(&self) -> Option<&Accounts> {
+        self.accounts.as_ref()
+    }
+
+    pub fn accounts_mut(&mut self) -> Option<&mut Accounts> {
+        self.accounts.as_mut()
+    }
+
+    // Every order goes through the pre-trade checks before it can rest or match
+    pub fn set_risk_manager(&mut self, risk: RiskManager) {
+        self.risk = Some(risk);
+    }
+
+    pub fn risk_manager_mut(&mut self) -> Option<&mut RiskManager> {
+        self.risk.as_mut()
+    }
+
+    // Orders have to be on the instrument's grid, prices and quantities are then in units of
+    // `Instrument::converter`, and are refused while it is not trading
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(instrument);
+    }
+
+    pub fn instrument(&self) -> Option<&Instrument> {
+        self.instrument.as_ref()
+    }
+
+    // Orders are throttled per account on the engine clock
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    // Bounds the resting orders and levels, see `capacity`. Orders already resting are kept
+    // when the limits are lowered below the current usage.
+    pub fn set_capacity_limits(&mut self, limits: CapacityLimits) {
+        self.capacity = Some(limits);
+    }
+
+    pub fn capacity_limits(&self) -> Option<&CapacityLimits> {
+        self.capacity.as_ref()
+    }
+
+    // Applies to orders matched from now on, auctions uncross in time priority regardless
+    pub fn set_matching_policy(&mut self, policy: MatchingPolicy) {
+        self.matching_policy = policy;
+    }
+
+    pub fn matching_policy(&self) -> MatchingPolicy {
+        self.matching_policy
+    }
+
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
+    }
+
+    // Reference for stops triggered by `StopTrigger::Mark`, returns the trades of the stops
+    // it triggered
+    pub fn set_mark_price(&mut self, price: Price) -> Vec<Trade> {
+        self.mark_price = Some(price);
+        self.trigger_stops()
+    }
+
+    pub fn mark_price(&self) -> Option<Price> {
+        self.mark_price
+    }
+
+    // The stop waits outside the book and is checked right away, so a stop placed past its
+    // trigger is entered immediately. Order checks run when the stop triggers.
+    pub fn place_stop_order(&mut self, stop: StopOrder) -> Result<Vec<Trade>, Rejected> {
+        if !self.session.accepts_orders() {
+            return Err(Rejected::SessionNotOpen(self.session));
         }
+        let order_id = stop.order_id();
+        if self.orders.contains_key(&order_id) || self.stops.contains(order_id) {
+            return Err(Rejected::DuplicateOrderId(order_id));
+        }
+        self.order_ids.observe(order_id);
+        self.stops.insert(stop);
+        Ok(self.trigger_stops())
+    }
+
+    pub fn cancel_stop_order(&mut self, order_id: OrderId) -> Option<StopOrder> {
+        self.stops.remove(order_id)
+    }
+
+    pub fn stop_orders(&self) -> &StopOrders {
+        &self.stops
+    }
+
+    fn trigger_prices(&self) -> TriggerPrices {
+        TriggerPrices {
+            last_trade: self.last_trade_price,
+            best_bid: self.bids.last().map(|(price, _)| price),
+            best_ask: self.asks.first().map(|(price, _)| price),
+            mark: self.mark_price,
+        }
+    }
+
+    // Stops stay put while the book does not trade continuously. Orders entered by a stop may
+    // trigger further stops, those trades are returned as well.
+    fn trigger_stops(&mut self) -> Vec<Trade> {
+        if self.stops.is_empty() || self.in_auction || !self.session.accepts_orders() {
+            return vec![];
+        }
+
+        let mut trades = Vec::new();
+        for (stop, reference_price) in self.stops.take_triggered(&self.trigger_prices()) {
+            let order_id = stop.order_id();
+            self.events.push(EngineEvent::StopTriggered {
+                order_id,
+                trigger: stop.trigger(),
+                reference_price,
+                timestamp: self.clock.now(),
+            });
+            match self.place_order(stop.into_order()) {
+                Ok(stop_trades) => trades.extend(stop_trades),
+                Err(rejected) => self.events.push(EngineEvent::StopRejected {
+                    order_id,
+                    reason: rejected.to_string(),
+                    timestamp: self.clock.now(),
+                }),
+            }
+        }
+        trades
     }
 
-    fn cancel_order(&mut self, order_id: OrderId) {
-        // FIXME: This is very error prone impelmentation,
-        // we should not do this conversion here and we should not panic!
-        if !self.orders.contains_key(&order_id) {
-            panic!("Order not found");
+    // Resting orders, bids best first then asks best first, each level in time priority. The
+    // engine trades a single instrument, so these are the open orders of its symbol.
+    pub fn open_orders(&self) -> impl Iterator<Item = Ref<'_, Order>> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .chain(self.asks.iter())
+            .flat_map(|(_, orders)| orders.iter().map(|order| order.borrow()))
+    }
+
+    pub fn open_orders_for(
+        &self,
+        account_id: AccountId,
+    ) -> impl Iterator<Item = Ref<'_, Order>> + '_ {
+        self.open_orders()
+            .filter(move |order| order.account_id == account_id)
+    }
+
+    // None for an order the engine never accepted
+    pub fn order_status(&self, order_id: OrderId) -> Option<OrderStatus> {
+        match self.orders.get(&order_id) {
+            Some(order) if order.borrow().get_fill_quantity().is_zero() => Some(OrderStatus::New),
+            Some(_) => Some(OrderStatus::PartiallyFilled),
+            None => self.closed_orders.get(&order_id).copied(),
+        }
+    }
+
+    // Orders that are not resting, filled, cancelled or never accepted, are `UnknownOrder`
+    pub fn cancel_order(&mut self, order_id: OrderId) -> Result<(), Rejected> {
+        self.cancel_order_with_reason(order_id, CancelReason::User)
+    }
+
+    // For cancels made on behalf of the owner, e.g. by a risk or self-trade check
+    pub fn cancel_order_with_reason(
+        &mut self,
+        order_id: OrderId,
+        reason: CancelReason,
+    ) -> Result<(), Rejected> {
+        let Some(order) = self.orders.get(&order_id) else {
+            return Err(Rejected::UnknownOrder(order_id));
+        };
+
+        let remaining = order.borrow().remaining_quantity;
+        if let (Some(surveillance), CancelReason::User) = (self.surveillance.as_mut(), reason) {
+            let order = order.borrow();
+            surveillance.on_cancel(
+                self.clock.now(),
+                order.account_id,
+                order_id,
+                order.side,
+                order.price,
+                remaining,
+            );
+        }
+        self.audit(order_id, OrderEvent::Cancelled { reason, remaining });
+        self.remove_order(order_id);
+        self.closed_orders.insert(order_id, OrderStatus::Cancelled);
+        Ok(())
+    }
+
+    // Takes the order out of its level, leaving the audit trail to the caller
+    fn remove_order(&mut self, order_id: OrderId) {
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.release(order_id);
         }
+        self.unlink_order(order_id);
+    }
 
+    // Takes the order out of its level and leaves its reservation alone
+    fn unlink_order(&mut self, order_id: OrderId) {
         // Find the order first
         let order_price = self
             .orders
@@ -183,26 +1118,27 @@ impl OrderBook {
 
             match order.side {
                 Side::Sell => {
-                    if let Some(orders) = self.asks.get_mut(&price) {
+                    if let Some(orders) = self.asks.get_mut(price) {
                         orders.retain(|o| o.borrow().order_id != order_id);
                         // Remove the price level if no orders left
                         if orders.is_empty() {
-                            self.asks.remove(&price);
+                            self.asks.remove(price);
                         }
                     }
                 }
                 Side::Buy => {
-                    let reverse_price = std::cmp::Reverse(price);
-                    if let Some(orders) = self.bids.get_mut(&reverse_price) {
+                    if let Some(orders) = self.bids.get_mut(price) {
                         orders.retain(|o| o.borrow().order_id != order_id);
                         // Remove the price level if no orders left
                         if orders.is_empty() {
-                            self.bids.remove(&reverse_price);
+                            self.bids.remove(price);
                         }
                     }
                 }
             }
         }
+
+        self.publish_market_data();
     }
 
     fn can_match(&self, price: Price, side: Side) -> bool {
@@ -212,132 +1148,428 @@ impl OrderBook {
                     return false;
                 }
 
-                let best_ask = self
-                    .asks
-                    .iter()
-                    .next()
-                    .expect("No ask found | unreachable state");
-                price >= *best_ask.0
+                let best_ask = self.asks.first().expect("No ask found | unreachable state");
+                price >= best_ask.0
             }
             Side::Sell => {
                 if self.bids.is_empty() {
                     return false;
                 }
 
-                let best_bid = self
-                    .bids
-                    .iter()
-                    .next()
-                    .expect("No bid found | unreachable state");
-                price <= best_bid.0 .0
+                let best_bid = self.bids.last().expect("No bid found | unreachable state");
+                price <= best_bid.0
             }
         }
     }
 
-    fn match_order(&mut self, order_modify: OrderModify) -> Vec<Trade> {
-        if !self.orders.contains_key(&order_modify.order_id) {
-            return vec![];
+    // Same as `replace_order`, a rejection is reported as an `EngineEvent::OrderRejected`
+    pub fn match_order(&mut self, order_modify: OrderModify) -> Vec<Trade> {
+        let order_id = order_modify.order_id;
+        let result = self.replace_order(order_modify);
+        self.report_rejection(order_id, result)
+    }
+
+    // The modified order keeps its id, type, account and minimum fill but loses its time
+    // priority
+    // The amend is checked in full before the original leaves the book, a rejected amend leaves
+    // it resting untouched
+    pub fn replace_order(&mut self, order_modify: OrderModify) -> Result<Vec<Trade>, Rejected> {
+        let original = match self.orders.get(&order_modify.order_id) {
+            Some(order) => order.borrow().clone(),
+            None => return Err(Rejected::UnknownOrder(order_modify.order_id)),
+        };
+
+        let amended = Order {
+            min_fill_quantity: original.min_fill_quantity,
+            all_or_none: original.all_or_none,
+            ..Order::new(
+                order_modify.order_id,
+                order_modify.price,
+                order_modify.quantity,
+                original.order_type,
+                order_modify.side,
+            )
+            .with_account(original.account_id)
+        };
+        self.check_order(&amended, Some(&original))?;
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.replace(&amended)?;
         }
-        let order_pointer = self.orders.get(&order_modify.order_id).unwrap().clone();
-        let order = order_pointer.borrow();
-        self.cancel_order(order.order_id);
-        self.add_order(order.clone())
+
+        self.unlink_order(order_modify.order_id);
+        Ok(self.admit_order(amended, true))
     }
 
-    fn match_orders(&mut self) -> Vec<Trade> {
-        let mut trades = Vec::new();
+    // Commands run one after another, each one matching before the next is applied
+    pub fn apply_batch(&mut self, commands: Vec<OrderCommand>) -> BatchReport {
+        let mut report = BatchReport {
+            results: Vec::with_capacity(commands.len()),
+            trades: Vec::new(),
+        };
+
+        for command in commands {
+            let result = match command {
+                OrderCommand::New(order) => self.place_order(order),
+                OrderCommand::Modify(order_modify) => self.replace_order(order_modify),
+                OrderCommand::Cancel(order_id) => self.cancel_order(order_id).map(|()| vec![]),
+            };
+            report
+                .results
+                .push(result.map(|trades| report.trades.extend(trades)));
+        }
 
-        loop {
-            if self.bids.is_empty() || self.asks.is_empty() {
-                break;
+        report
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        self.session
+    }
+
+    // Moving to PreOpen starts the opening auction which uncrosses when the session opens,
+    // closing the session cancels the remaining Day orders
+    pub fn set_session_state(&mut self, state: SessionState) -> Vec<Trade> {
+        let from = self.session;
+        if from == state {
+            return vec![];
+        }
+        self.session = state;
+
+        let mut trades = vec![];
+        match state {
+            SessionState::PreOpen => self.start_auction(),
+            SessionState::Open if self.in_auction => trades = self.uncross(),
+            SessionState::Closed => {
+                self.expire_day_orders(CancelReason::SessionClose);
             }
+            SessionState::Open | SessionState::Halted => {}
+        }
 
-            let (bids_level_to_remove, asks_level_to_remove) = {
-                let bids = self
-                    .bids
-                    .iter_mut()
-                    .next()
-                    .expect("No bid found | unreachable state");
-                let asks = self
-                    .asks
-                    .iter_mut()
-                    .next()
-                    .expect("No ask found | unreachable state");
-
-                // Nothing to match in orderbook
-                if bids.0 .0 < *asks.0 {
-                    break;
-                }
+        self.events.push(EngineEvent::SessionStateChanged {
+            from,
+            to: state,
+            timestamp: self.clock.now(),
+        });
+        trades
+    }
 
-                // internal loop to match orders, will be stopped when bids or asks are empty
-                while !bids.1.is_empty() && !asks.1.is_empty() {
-                    let ((bid_is_filled, bid_order_id), (ask_is_filled, ask_order_id), quantity) = {
-                        let mut bid = bids.1.front().unwrap().borrow_mut();
-                        let mut ask = asks.1.front().unwrap().borrow_mut();
-                        let quantity =
-                            std::cmp::min(bid.remaining_quantity, ask.remaining_quantity);
-
-                        bid.fill(quantity);
-                        ask.fill(quantity);
-
-                        (
-                            (bid.is_filled(), bid.order_id),
-                            (ask.is_filled(), ask.order_id),
-                            quantity,
-                        )
-                    };
+    pub fn session_statistics(&self) -> &SessionStatistics {
+        &self.session_statistics
+    }
 
-                    if bid_is_filled {
-                        bids.1.pop_front();
-                        self.orders.remove(&bid_order_id);
-                    }
+    // Number of recent trades kept for `trades_in_window`
+    pub fn set_trade_tape_capacity(&mut self, capacity: usize) {
+        self.trade_tape.set_capacity(capacity);
+    }
 
-                    if ask_is_filled {
-                        asks.1.pop_front();
-                        self.orders.remove(&ask_order_id);
-                    }
+    pub fn trade_tape(&self) -> &TradeTape {
+        &self.trade_tape
+    }
 
-                    trades.push(Trade {
-                        bid_trade: TradeInfo {
-                            order_id: bids.1.front().unwrap().borrow().order_id,
-                            price: bids.0 .0,
-                            quantity,
-                        },
-                        ask_trade: TradeInfo {
-                            order_id: asks.1.front().unwrap().borrow().order_id,
-                            price: *asks.0,
-                            quantity,
-                        },
-                    });
-                }
+    // Trades of the last `window` on the engine clock, as far back as the tape goes
+    pub fn trades_in_window(
+        &self,
+        window: Duration,
+    ) -> impl DoubleEndedIterator<Item = &TapeTrade> + '_ {
+        self.trade_tape.trades_in_window(self.clock.now(), window)
+    }
 
-                // remove the level if it is empty
-                let bids_level_to_remove = if bids.1.is_empty() {
-                    Some(bids.0 .0)
-                } else {
-                    None
-                };
+    // Trades feed the builder, candles are emitted as `EngineEvent::CandleClosed` once their
+    // interval is over
+    pub fn set_candle_builder(&mut self, builder: CandleBuilder) {
+        self.candles = Some(builder);
+    }
 
-                let asks_level_to_remove = if asks.1.is_empty() {
-                    Some(*asks.0)
-                } else {
-                    None
+    pub fn candle_builder(&self) -> Option<&CandleBuilder> {
+        self.candles.as_ref()
+    }
+
+    fn flush_candles(&mut self) {
+        if let Some(builder) = self.candles.as_mut() {
+            let closed = builder.flush(self.clock.now());
+            self.events
+                .extend(closed.into_iter().map(EngineEvent::CandleClosed));
+        }
+    }
+
+    pub fn market_statistics(&self) -> MarketStatistics {
+        MarketStatistics::new(self.last_trade_price, &self.session_statistics)
+    }
+
+    // End of day sweep: expires the remaining Day orders and starts new session statistics.
+    // The session state is not changed. Returns the expired order ids.
+    pub fn roll_session(&mut self) -> Vec<OrderId> {
+        let expired = self.expire_day_orders(CancelReason::Expired);
+        let now = self.clock.now();
+        let statistics =
+            std::mem::replace(&mut self.session_statistics, SessionStatistics::new(now));
+        self.events.push(EngineEvent::SessionRolled {
+            statistics,
+            timestamp: now,
+        });
+        expired
+    }
+
+    fn expire_day_orders(&mut self, reason: CancelReason) -> Vec<OrderId> {
+        let mut day_orders: Vec<OrderId> = self
+            .orders
+            .values()
+            .map(|order| order.borrow())
+            .filter(|order| order.order_type == OrderType::Day)
+            .map(|order| order.order_id)
+            .collect();
+        day_orders.sort_unstable();
+
+        let now = self.clock.now();
+        for &order_id in &day_orders {
+            let _ = self.cancel_order_with_reason(order_id, reason);
+            self.session_statistics.expired_orders += 1;
+            self.events.push(EngineEvent::OrderExpired {
+                order_id,
+                timestamp: now,
+            });
+        }
+        day_orders
+    }
+
+    // Trades from incoming orders feed the breaker, see `CircuitBreaker::record_trade`
+    pub fn set_circuit_breaker(&mut self, circuit_breaker: CircuitBreaker) {
+        self.circuit_breaker = Some(circuit_breaker);
+    }
+
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    fn check_circuit_breaker(&mut self, trades: &[Trade]) {
+        let now = self.clock.now();
+        let Some(breaker) = self.circuit_breaker.as_mut() else {
+            return;
+        };
+
+        for trade in trades {
+            let trade_price = trade.bid_trade.price;
+            if let Some(reference_price) = breaker.record_trade(now, trade_price) {
+                let state = match breaker.config().action {
+                    BreakerAction::Halt => SessionState::Halted,
+                    BreakerAction::Auction => SessionState::PreOpen,
                 };
+                self.events.push(EngineEvent::CircuitBreakerTriggered {
+                    reference_price,
+                    trade_price,
+                    timestamp: now,
+                    resume_at: breaker.resume_at().unwrap_or(now),
+                });
+                self.set_session_state(state);
+                return;
+            }
+        }
+    }
+
+    // Back to continuous trading when the interruption is over, uncrossing a breaker auction
+    fn resume_after_circuit_breaker(&mut self) -> Vec<Trade> {
+        let now = self.clock.now();
+        let resume = self
+            .circuit_breaker
+            .as_mut()
+            .is_some_and(|breaker| breaker.take_resume(now));
+        if !resume {
+            return vec![];
+        }
+        self.set_session_state(SessionState::Open)
+    }
+
+    // Publishes a snapshot of the book now, then trades and level deltas as they happen
+    pub fn enable_market_data(&mut self) {
+        self.market_data = Some(MarketDataPublisher::new());
+        self.publish_market_data();
+    }
+
+    pub fn market_data_snapshot(&self) -> Option<BookSnapshot> {
+        self.market_data
+            .as_ref()
+            .map(|publisher| publisher.snapshot())
+    }
 
-                (bids_level_to_remove, asks_level_to_remove)
+    pub fn drain_market_data(&mut self) -> Vec<MarketDataMessage> {
+        self.market_data
+            .as_mut()
+            .map(|publisher| publisher.drain())
+            .unwrap_or_default()
+    }
+
+    // Records the history of every order accepted from now on, see `order_history`
+    pub fn enable_audit_trail(&mut self) {
+        self.audit = Some(AuditTrail::new());
+    }
+
+    pub fn audit_trail(&self) -> Option<&AuditTrail> {
+        self.audit.as_ref()
+    }
+
+    // Empty when the audit trail is disabled or the order is unknown
+    pub fn order_history(&self, order_id: OrderId) -> &[OrderAuditRecord] {
+        self.audit
+            .as_ref()
+            .map(|audit| audit.history(order_id))
+            .unwrap_or_default()
+    }
+
+    // Wash trade and spoofing alerts are emitted as `EngineEvent::SurveillanceAlert`
+    pub fn set_surveillance(&mut self, surveillance: Surveillance) {
+        self.surveillance = Some(surveillance);
+    }
+
+    pub fn surveillance(&self) -> Option<&Surveillance> {
+        self.surveillance.as_ref()
+    }
+
+    fn audit(&mut self, order_id: OrderId, event: OrderEvent) {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(order_id, self.clock.now(), event);
+        }
+    }
+
+    fn publish_market_data(&mut self) {
+        if let Some(mut publisher) = self.market_data.take() {
+            publisher.publish(self.bids(), self.asks());
+            self.market_data = Some(publisher);
+        }
+    }
+
+    pub fn drain_events(&mut self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Stops continuous matching, orders rest in the book until `uncross` is called
+    pub fn start_auction(&mut self) {
+        self.in_auction = true;
+    }
+
+    pub fn in_auction(&self) -> bool {
+        self.in_auction
+    }
+
+    // Price the book would uncross at right now, None when nothing crosses
+    pub fn indicative_price(&self) -> Option<Price> {
+        self.equilibrium().map(|(price, _)| price)
+    }
+
+    // Maximum executable volume price and that volume. Ties are broken by the smallest
+    // imbalance between buy and sell volume at the price, then by the lowest price.
+    fn equilibrium(&self) -> Option<(Price, Quantity)> {
+        let bids: Vec<(Price, Quantity)> = self.bids().collect();
+        let asks: Vec<(Price, Quantity)> = self.asks().collect();
+
+        let mut candidates: Vec<Price> = bids.iter().chain(asks.iter()).map(|l| l.0).collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut best: Option<(Price, Quantity, u64)> = None;
+        for price in candidates {
+            // Summed in u64, a side can hold more than `Quantity::MAX` in total
+            let demand: u64 = bids
+                .iter()
+                .filter(|l| l.0 >= price)
+                .map(|l| u64::from(l.1))
+                .sum();
+            let supply: u64 = asks
+                .iter()
+                .filter(|l| l.0 <= price)
+                .map(|l| u64::from(l.1))
+                .sum();
+            let volume = Quantity::saturating_from(demand.min(supply));
+            let imbalance = demand.abs_diff(supply);
+            if volume.is_zero() {
+                continue;
+            }
+            match best {
+                Some((_, best_volume, best_imbalance))
+                    if volume < best_volume
+                        || (volume == best_volume && imbalance >= best_imbalance) => {}
+                _ => best = Some((price, volume, imbalance)),
+            }
+        }
+
+        best.map(|(price, volume, _)| (price, volume))
+    }
+
+    // Executes everything that crosses at the single equilibrium price and switches back to
+    // continuous trading. Fill and Kill orders left over after the uncross are cancelled.
+    pub fn uncross(&mut self) -> Vec<Trade> {
+        self.in_auction = false;
+
+        let mut trades = match self.indicative_price() {
+            Some(price) => self.match_orders(Matching::Uncross { price }),
+            None => vec![],
+        };
+
+        let leftover_fak: Vec<OrderId> = self
+            .orders
+            .values()
+            .map(|order| order.borrow())
+            .filter(|order| order.order_type == OrderType::FillAndKill)
+            .map(|order| order.order_id)
+            .collect();
+        for order_id in leftover_fak {
+            let _ = self.cancel_order_with_reason(order_id, CancelReason::FillAndKillRemainder);
+        }
+        self.publish_market_data();
+
+        trades.extend(self.trigger_stops());
+        trades
+    }
+
+    fn match_orders(&mut self, matching: Matching) -> Vec<Trade> {
+        let mut trades = Vec::new();
+
+        loop {
+            let Some((fills, bid_price, ask_price)) = self.next_fills(matching) else {
+                break;
             };
 
-            if let Some(price) = bids_level_to_remove {
-                self.bids.remove(&std::cmp::Reverse(price));
+            // remove the levels if they are empty
+            if self
+                .bids
+                .get(bid_price)
+                .is_some_and(|level| level.is_empty())
+            {
+                self.bids.remove(bid_price);
+            }
+            if self
+                .asks
+                .get(ask_price)
+                .is_some_and(|level| level.is_empty())
+            {
+                self.asks.remove(ask_price);
+            }
+
+            for (bid, ask, quantity) in fills {
+                for order in [&bid, &ask] {
+                    let order = order.borrow();
+                    if order.is_filled() {
+                        self.orders.remove(&order.order_id);
+                        self.closed_orders
+                            .insert(order.order_id, OrderStatus::Filled);
+                    }
+                }
+                let trade = self.record_trade(
+                    &bid.borrow(),
+                    &ask.borrow(),
+                    quantity,
+                    matching,
+                    (bid_price, ask_price),
+                );
+                trades.push(trade);
             }
 
-            if let Some(price) = asks_level_to_remove {
-                self.asks.remove(&price);
+            // Leftover Fill and Kill orders are cancelled once the whole auction has uncrossed
+            if matches!(matching, Matching::Uncross { .. }) {
+                continue;
             }
 
             if !self.bids.is_empty() {
                 let need_cancelation = {
-                    let (_, bids) = self.bids.iter_mut().next().unwrap();
+                    let (_, bids) = self.bids.last().unwrap();
                     let first_order = bids.front().unwrap().borrow();
                     if first_order.order_type == OrderType::FillAndKill {
                         Some(first_order.order_id)
@@ -347,13 +1579,14 @@ impl OrderBook {
                 };
 
                 if let Some(order_id) = need_cancelation {
-                    self.cancel_order(order_id);
+                    let _ =
+                        self.cancel_order_with_reason(order_id, CancelReason::FillAndKillRemainder);
                 }
             }
 
             if !self.asks.is_empty() {
                 let need_cancelation = {
-                    let (_, asks) = self.asks.iter_mut().next().unwrap();
+                    let (_, asks) = self.asks.first().unwrap();
                     let first_order = asks.front().unwrap().borrow();
                     if first_order.order_type == OrderType::FillAndKill {
                         Some(first_order.order_id)
@@ -363,51 +1596,606 @@ impl OrderBook {
                 };
 
                 if let Some(order_id) = need_cancelation {
-                    self.cancel_order(order_id);
+                    let _ =
+                        self.cancel_order_with_reason(order_id, CancelReason::FillAndKillRemainder);
                 }
             }
         }
 
-        // just a dummy implementation to make the code compile
-        return trades;
+        // A Fill and Kill taker queued behind orders it could not trade with is not at the
+        // front of its level
+        if let Matching::Continuous { taker } = matching {
+            let is_fak = self
+                .orders
+                .get(&taker)
+                .is_some_and(|order| order.borrow().order_type == OrderType::FillAndKill);
+            if is_fak {
+                let _ = self.cancel_order_with_reason(taker, CancelReason::FillAndKillRemainder);
+            }
+        }
+
+        trades
     }
 
+    // Fills of the first pair of crossed levels that can trade, best prices first. Minimum
+    // fills can leave the top of the book crossed while deeper levels still match.
+    fn next_fills(&mut self, matching: Matching) -> Option<(Vec<Fill>, Price, Price)> {
+        let (best_bid, _) = self.bids.last()?;
+        let (best_ask, _) = self.asks.first()?;
+        let bid_prices: Vec<Price> = self
+            .bids
+            .iter()
+            .rev()
+            .map(|(price, _)| price)
+            .take_while(|price| *price >= best_ask)
+            .collect();
+        let ask_prices: Vec<Price> = self
+            .asks
+            .iter()
+            .map(|(price, _)| price)
+            .take_while(|price| *price <= best_bid)
+            .collect();
+
+        for bid_price in bid_prices {
+            for &ask_price in ask_prices.iter().take_while(|price| **price <= bid_price) {
+                let bids = self.bids.get_mut(bid_price).expect("crossed bid level");
+                let asks = self.asks.get_mut(ask_price).expect("crossed ask level");
+                let constrained = bids
+                    .iter()
+                    .chain(asks.iter())
+                    .any(|order| order.borrow().has_fill_constraint());
+
+                // Auctions and levels with minimum fills match in time priority
+                let fills = match (self.matching_policy, matching, constrained) {
+                    (
+                        MatchingPolicy::ProRata {
+                            min_allocation,
+                            top_order_priority,
+                        },
+                        Matching::Continuous { taker },
+                        false,
+                    ) => {
+                        let taker_is_bid = bids
+                            .front()
+                            .is_some_and(|order| order.borrow().order_id == taker);
+                        if taker_is_bid {
+                            fill_pro_rata(bids, asks, min_allocation, top_order_priority)
+                        } else {
+                            fill_pro_rata(asks, bids, min_allocation, top_order_priority)
+                                .into_iter()
+                                .map(|(ask, bid, quantity)| (bid, ask, quantity))
+                                .collect()
+                        }
+                    }
+                    _ => fill_price_time(bids, asks),
+                };
+                if !fills.is_empty() {
+                    return Some((fills, bid_price, ask_price));
+                }
+            }
+        }
+        None
+    }
+
+    // Books a fill between two orders: fees, accounts, market data, tape and candles
+    fn record_trade(
+        &mut self,
+        bid: &Order,
+        ask: &Order,
+        quantity: Quantity,
+        matching: Matching,
+        (bid_price, ask_price): (Price, Price),
+    ) -> Trade {
+        // In continuous trading both sides trade at the price of the resting order,
+        // auction orders were all resting when the book uncrossed
+        let (bid_liquidity, ask_liquidity, price) = match matching {
+            Matching::Continuous { taker } if bid.order_id == taker => {
+                (Liquidity::Taker, Liquidity::Maker, ask_price)
+            }
+            Matching::Continuous { .. } => (Liquidity::Maker, Liquidity::Taker, bid_price),
+            Matching::Uncross { price } => (Liquidity::Maker, Liquidity::Maker, price),
+        };
+        let bid_fee = self.fees.amount(bid_liquidity, price, quantity);
+        let ask_fee = self.fees.amount(ask_liquidity, price, quantity);
+        *self.fees_by_account.entry(bid.account_id).or_default() += bid_fee;
+        *self.fees_by_account.entry(ask.account_id).or_default() += ask_fee;
+        *self.positions.entry(bid.account_id).or_default() += i64::from(quantity);
+        *self.positions.entry(ask.account_id).or_default() -= i64::from(quantity);
+
+        let trade = Trade {
+            bid_trade: TradeInfo {
+                order_id: bid.order_id,
+                account_id: bid.account_id,
+                price,
+                quantity,
+                liquidity: bid_liquidity,
+                fee: bid_fee,
+            },
+            ask_trade: TradeInfo {
+                order_id: ask.order_id,
+                account_id: ask.account_id,
+                price,
+                quantity,
+                liquidity: ask_liquidity,
+                fee: ask_fee,
+            },
+            timestamp: self.clock.now(),
+        };
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.settle(Side::Buy, &trade.bid_trade);
+            accounts.settle(Side::Sell, &trade.ask_trade);
+        }
+        for order in [bid, ask] {
+            let remaining = order.remaining_quantity;
+            self.audit(
+                order.order_id,
+                OrderEvent::Filled {
+                    price,
+                    quantity,
+                    remaining,
+                },
+            );
+        }
+        if let Some(publisher) = self.market_data.as_mut() {
+            publisher.publish_trade(self.clock.now(), &trade);
+        }
+        self.last_trade_price = Some(price);
+        self.session_statistics.record_trade(price, quantity);
+        self.trade_tape.record(TapeTrade {
+            timestamp: self.clock.now(),
+            price,
+            quantity,
+        });
+        if let Some(builder) = self.candles.as_mut() {
+            let closed = builder.on_trade(self.clock.now(), price, quantity);
+            self.events
+                .extend(closed.into_iter().map(EngineEvent::CandleClosed));
+        }
+        if let Some(surveillance) = self.surveillance.as_mut() {
+            let alerts = surveillance.on_trade(&trade);
+            self.events
+                .extend(alerts.into_iter().map(EngineEvent::SurveillanceAlert));
+        }
+        trade
+    }
+
+    // Same as `place_order`, a rejection is reported as an `EngineEvent::OrderRejected`
     pub fn add_order(&mut self, order: Order) -> Vec<Trade> {
-        if self.orders.contains_key(&order.order_id) {
-            // this is too much, but as an initial implementation, we can just panic
-            println!("Order already exists");
-            return vec![];
+        let order_id = order.order_id;
+        let result = self.place_order(order);
+        self.report_rejection(order_id, result)
+    }
+
+    fn report_rejection(
+        &mut self,
+        order_id: OrderId,
+        result: Result<Vec<Trade>, Rejected>,
+    ) -> Vec<Trade> {
+        result.unwrap_or_else(|rejected| {
+            self.events.push(EngineEvent::OrderRejected {
+                order_id,
+                reason: rejected.to_string(),
+                timestamp: self.clock.now(),
+            });
+            vec![]
+        })
+    }
+
+    // Allocates the exchange order id and returns it with the trades of the order
+    pub fn add_client_order(&mut self, request: NewOrder) -> Result<OrderAck, Rejected> {
+        if self.client_order_ids.contains(&request.client_order_id) {
+            return Err(Rejected::DuplicateClientOrderId(request.client_order_id));
         }
 
-        if order.order_type == OrderType::FillAndKill {
-            if !self.can_match(order.price, order.side) {
-                println!("Cannot match this Fill and Kill order");
-                return vec![];
+        let order_id = self.order_ids.allocate();
+        let order = Order::builder()
+            .order_id(order_id)
+            .price(request.price)
+            .quantity(request.quantity)
+            .order_type(request.order_type)
+            .side(request.side)
+            .account(request.account_id)
+            .build()
+            .map_err(Rejected::InvalidOrder)?;
+        let trades = self.place_order(order)?;
+        self.client_order_ids
+            .insert(&request.client_order_id, order_id);
+
+        Ok(OrderAck { order_id, trades })
+    }
+
+    // Fresh id that no order seen by the engine uses
+    pub fn next_order_id(&mut self) -> OrderId {
+        self.order_ids.allocate()
+    }
+
+    pub fn order_id_for(&self, client_order_id: &str) -> Option<OrderId> {
+        self.client_order_ids.order_id(client_order_id)
+    }
+
+    pub fn client_order_id(&self, order_id: OrderId) -> Option<&str> {
+        self.client_order_ids.client_order_id(order_id)
+    }
+
+    // Same as `add_order`, but reports why the order was not accepted
+    pub fn place_order(&mut self, order: Order) -> Result<Vec<Trade>, Rejected> {
+        self.enter_order(order, false)
+    }
+
+    fn enter_order(&mut self, order: Order, amended: bool) -> Result<Vec<Trade>, Rejected> {
+        self.check_order(&order, None)?;
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.reserve(&order)?;
+        }
+        Ok(self.admit_order(order, amended))
+    }
+
+    // Every check but the balance one, which reserves funds as it passes. `replacing` is the
+    // resting order an amend takes the place of, it is counted as already gone.
+    fn check_order(&mut self, order: &Order, replacing: Option<&Order>) -> Result<(), Rejected> {
+        if !self.session.accepts_orders() {
+            return Err(Rejected::SessionNotOpen(self.session));
+        }
+
+        if let Some(instrument) = self.instrument.as_ref() {
+            instrument
+                .validate(order.price.into(), order.initial_quantity.into())
+                .map_err(Rejected::Instrument)?;
+        }
+
+        if replacing.is_none() && self.orders.contains_key(&order.order_id) {
+            return Err(Rejected::DuplicateOrderId(order.order_id));
+        }
+
+        if order.order_type == OrderType::FillAndKill
+            && !self.in_auction
+            && !self.can_match(order.price, order.side)
+        {
+            return Err(Rejected::NoLiquidity(order.order_id));
+        }
+
+        // Fill and Kill orders never rest
+        if let (Some(limits), false) = (
+            self.capacity.as_ref(),
+            order.order_type == OrderType::FillAndKill,
+        ) {
+            let levels = self.side_levels(order.side);
+            let mut level_count = levels.len();
+            let mut level_orders = levels.get(order.price).map(|orders| orders.len());
+            if let Some(original) = replacing.filter(|original| original.side == order.side) {
+                if levels.get(original.price).map(|orders| orders.len()) == Some(1) {
+                    level_count -= 1;
+                }
+                if original.price == order.price {
+                    level_orders = level_orders
+                        .map(|queued| queued - 1)
+                        .filter(|&queued| queued > 0);
+                }
             }
+            limits
+                .check(
+                    order.side,
+                    order.price,
+                    self.orders.len() - usize::from(replacing.is_some()),
+                    level_count,
+                    level_orders,
+                )
+                .map_err(Rejected::Capacity)?;
         }
 
+        if let Some(rate_limiter) = self.rate_limiter.as_mut() {
+            if !rate_limiter.try_acquire(order.account_id, self.clock.now()) {
+                return Err(Rejected::RateLimited {
+                    account_id: order.account_id,
+                });
+            }
+        }
+
+        if let Some(risk) = self.risk.as_ref() {
+            let replaced = replacing.map(|original| original.order_id);
+            let mut ctx = RiskContext {
+                last_trade_price: self.last_trade_price,
+                position: self.position(order.account_id),
+                ..RiskContext::default()
+            };
+            for open in self
+                .open_orders_for(order.account_id)
+                .filter(|open| Some(open.order_id) != replaced)
+            {
+                ctx.open_orders += 1;
+                if open.side == order.side {
+                    ctx.open_quantity += u64::from(open.remaining_quantity);
+                }
+            }
+            risk.check(order, &ctx).map_err(Rejected::Risk)?;
+        }
+
+        Ok(())
+    }
+
+    // Books an order that passed its checks, an amended order is only audited differently
+    fn admit_order(&mut self, mut order: Order, amended: bool) -> Vec<Trade> {
+        self.order_ids.observe(order.order_id);
+
+        order.timestamp = self.clock.now();
+        let order_id = order.order_id;
+        let (price, quantity) = (order.price, order.remaining_quantity);
+        self.audit(
+            order_id,
+            if amended {
+                OrderEvent::Amended { price, quantity }
+            } else {
+                OrderEvent::Accepted { price, quantity }
+            },
+        );
+        self.insert_order(order);
+
+        if self.in_auction {
+            self.publish_market_data();
+            return vec![];
+        }
+
+        let mut trades = self.match_orders(Matching::Continuous { taker: order_id });
+        self.publish_market_data();
+        self.check_circuit_breaker(&trades);
+        trades.extend(self.trigger_stops());
+        trades
+    }
+
+    // Queues the order at the back of its level, without any checks or matching
+    fn insert_order(&mut self, order: Order) {
+        let order_id = order.order_id;
         let side = order.side;
         let price = order.price;
-        let order_pointer = Rc::new(RefCell::new(order.clone()));
+        let order_pointer = Rc::new(RefCell::new(order));
 
         match side {
             Side::Buy => {
                 self.bids
-                    .entry(std::cmp::Reverse(price))
-                    .or_insert(OrderList::new())
+                    .get_or_insert_with(price, OrderList::new)
                     .push_back(Rc::clone(&order_pointer));
             }
             Side::Sell => {
                 self.asks
-                    .entry(price)
-                    .or_insert(OrderList::new())
+                    .get_or_insert_with(price, OrderList::new)
                     .push_back(Rc::clone(&order_pointer));
             }
         }
 
-        self.orders.insert(order.order_id, order_pointer);
+        self.orders.insert(order_id, order_pointer);
+    }
+
+    // Resting orders in time priority, enough to rebuild the book with `from_state`
+    pub fn state(&self) -> EngineState {
+        let orders = self
+            .bid_levels()
+            .flat_map(|(_, orders)| orders.map(|order| order.clone()).collect::<Vec<_>>())
+            .chain(
+                self.ask_levels()
+                    .flat_map(|(_, orders)| orders.map(|order| order.clone()).collect::<Vec<_>>()),
+            )
+            .collect();
+
+        EngineState {
+            timestamp: self.clock.now(),
+            last_trade_price: self.last_trade_price,
+            orders,
+        }
+    }
+
+    // The resting orders as canonical text, see `digest`: a `bid` or `ask` line per level with
+    // its total quantity, followed by an `order` line per order in time priority, and the last
+    // trade price at the end. Timestamps are left out, the order of the lines is the priority.
+    pub fn canonical_state(&self) -> String {
+        let mut text = String::new();
+        let levels = [
+            ("bid", self.bids.iter().rev().collect::<Vec<_>>()),
+            ("ask", self.asks.iter().collect()),
+        ];
+        for (side, levels) in levels {
+            for (price, orders) in levels {
+                digest::push_line(&mut text, &[&side, &price, &Self::level_volume(orders)]);
+                for order in orders {
+                    let order = order.borrow();
+                    let min_fill = order
+                        .min_fill_quantity
+                        .map_or("-".to_string(), |quantity| quantity.to_string());
+                    digest::push_line(
+                        &mut text,
+                        &[
+                            &"order",
+                            &order.order_id,
+                            &order.account_id,
+                            &format!("{:?}", order.order_type),
+                            &order.remaining_quantity,
+                            &order.initial_quantity,
+                            &min_fill,
+                            &u8::from(order.all_or_none),
+                        ],
+                    );
+                }
+            }
+        }
+        let last_trade = self
+            .last_trade_price
+            .map_or("-".to_string(), |price| price.to_string());
+        digest::push_line(&mut text, &[&"last_trade", &last_trade]);
+        text
+    }
+
+    // Hex SHA-256 of `canonical_state`, stable across runs and platforms
+    pub fn state_digest(&self) -> String {
+        digest::sha256_hex(self.canonical_state().as_bytes())
+    }
+
+    // Everything the matcher needs to carry on exactly where it stopped: the resting and stop
+    // orders, in-flight requests, id allocation, session and the latency rng. The optional
+    // subsystems (accounts, risk, candles, market data, ...) and the configuration keep their
+    // own state and are not included.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let mut closed_orders: Vec<_> = self.closed_orders.iter().map(|(k, v)| (*k, *v)).collect();
+        closed_orders.sort_unstable_by_key(|(order_id, _)| *order_id);
+        let mut fees_by_account: Vec<_> =
+            self.fees_by_account.iter().map(|(k, v)| (*k, *v)).collect();
+        fees_by_account.sort_unstable_by_key(|(account_id, _)| *account_id);
+        let mut positions: Vec<_> = self.positions.iter().map(|(k, v)| (*k, *v)).collect();
+        positions.sort_unstable_by_key(|(account_id, _)| *account_id);
+
+        EngineSnapshot {
+            book: self.state(),
+            mark_price: self.mark_price,
+            stops: self.stops.clone(),
+            closed_orders,
+            session: self.session,
+            session_statistics: self.session_statistics,
+            in_auction: self.in_auction,
+            fees_by_account,
+            positions,
+            order_ids: self.order_ids.clone(),
+            client_order_ids: self.client_order_ids.clone(),
+            pending: self
+                .pending
+                .iter()
+                .map(|(&(arrival, sequence), command)| (arrival, sequence, command.clone()))
+                .collect(),
+            next_sequence: self.next_sequence,
+            latency_seed: self.latency_seed,
+            latency_samples: self.latency_samples,
+        }
+    }
 
-        self.match_orders()
+    // Replaces the matcher state with the snapshot, keeping the configuration of this engine.
+    // The latency rng is replayed from its seed, which reproduces it as long as the latency
+    // model is the one the snapshot was taken with.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) {
+        self.bids = PriceLevels::new(self.bids.backend());
+        self.asks = PriceLevels::new(self.asks.backend());
+        self.orders.clear();
+        self.clock = SimClock::new(snapshot.book.timestamp);
+        self.last_trade_price = snapshot.book.last_trade_price;
+        for order in snapshot.book.orders {
+            self.insert_order(order);
+        }
+
+        self.mark_price = snapshot.mark_price;
+        self.stops = snapshot.stops;
+        self.closed_orders = snapshot.closed_orders.into_iter().collect();
+        self.session = snapshot.session;
+        self.session_statistics = snapshot.session_statistics;
+        self.in_auction = snapshot.in_auction;
+        self.fees_by_account = snapshot.fees_by_account.into_iter().collect();
+        self.positions = snapshot.positions.into_iter().collect();
+        self.order_ids = snapshot.order_ids;
+        self.client_order_ids = snapshot.client_order_ids;
+        self.pending = snapshot
+            .pending
+            .into_iter()
+            .map(|(arrival, sequence, command)| ((arrival, sequence), command))
+            .collect();
+        self.next_sequence = snapshot.next_sequence;
+
+        self.latency_seed = snapshot.latency_seed;
+        self.latency_samples = snapshot.latency_samples;
+        self.rng = StdRng::seed_from_u64(self.latency_seed);
+        if let Some(model) = self.latency {
+            for _ in 0..self.latency_samples {
+                model.sample(&mut self.rng);
+            }
+        }
+        self.publish_market_data();
+    }
+
+    // Accounts, fees and the other optional subsystems are not part of the state and start
+    // out disabled
+    pub fn from_state(state: EngineState) -> OrderBook {
+        let mut orderbook = OrderBook::new();
+        orderbook.clock.advance_to(state.timestamp);
+        orderbook.last_trade_price = state.last_trade_price;
+        for order in state.orders {
+            orderbook.order_ids.observe(order.order_id);
+            orderbook.insert_order(order);
+        }
+        orderbook
+    }
+
+    // Simulated order entry: without a latency model the order is matched right away,
+    // otherwise it is queued and matched when the clock reaches its arrival time
+    pub fn submit_order(&mut self, order: Order) -> Vec<Trade> {
+        match self.latency {
+            None => self.add_order(order),
+            Some(_) => {
+                self.schedule(PendingCommand::Add(order));
+                vec![]
+            }
+        }
+    }
+
+    pub fn submit_cancel(&mut self, order_id: OrderId) {
+        match self.latency {
+            // The order may have been filled already
+            None => {
+                let _ = self.cancel_order(order_id);
+            }
+            Some(_) => self.schedule(PendingCommand::Cancel(order_id)),
+        }
+    }
+
+    // Moves the clock forward, processing every request that arrived in the meantime
+    // and returning the generated trades together with their matching time
+    pub fn advance_clock(&mut self, to: Timestamp) -> Vec<(Timestamp, Trade)> {
+        let mut trades = Vec::new();
+
+        while let Some(entry) = self.pending.first_entry() {
+            let arrival = entry.key().0;
+            if arrival > to {
+                break;
+            }
+            let command = entry.remove();
+            self.clock.advance_to(arrival);
+            trades.extend(
+                self.resume_after_circuit_breaker()
+                    .into_iter()
+                    .map(|trade| (arrival, trade)),
+            );
+
+            match command {
+                PendingCommand::Add(order) => trades.extend(
+                    self.add_order(order)
+                        .into_iter()
+                        .map(|trade| (arrival, trade)),
+                ),
+                // The order may have been filled while the cancel was in flight
+                PendingCommand::Cancel(order_id) => {
+                    let _ = self.cancel_order(order_id);
+                }
+            }
+        }
+
+        self.clock.advance_to(to);
+        self.flush_candles();
+        trades.extend(
+            self.resume_after_circuit_breaker()
+                .into_iter()
+                .map(|trade| (to, trade)),
+        );
+        trades
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn schedule(&mut self, command: PendingCommand) {
+        let latency = match self.latency {
+            Some(model) => {
+                self.latency_samples += 1;
+                model.sample(&mut self.rng)
+            }
+            None => Duration::ZERO,
+        };
+        let arrival = self.clock.now() + latency.as_nanos() as Timestamp;
+        self.pending.insert((arrival, self.next_sequence), command);
+        self.next_sequence += 1;
     }
 
     // Analytical methods to get some information about orderbook state
@@ -416,31 +2204,39 @@ impl OrderBook {
         self.orders.len()
     }
 
+    // Levels holding more than `Quantity::MAX` report `Quantity::MAX`, use
+    // `try_get_orderbook_level_infos` to detect them
     pub fn get_orderbook_level_infos(&self) -> OrderBookLevelInfos {
-        let bids = self
-            .bids
-            .iter()
-            .map(|(price, orders)| LevelInfo {
-                price: price.0,
-                quantity: orders.iter().map(|o| o.borrow().remaining_quantity).sum(),
-            })
-            .collect();
+        let level = |(price, quantity)| LevelInfo { price, quantity };
+        OrderBookLevelInfos::new(
+            self.bids().map(level).collect(),
+            self.asks().map(level).collect(),
+        )
+    }
 
-        let asks = self
-            .asks
-            .iter()
-            .map(|(price, orders)| LevelInfo {
-                price: *price,
-                quantity: orders.iter().map(|o| o.borrow().remaining_quantity).sum(),
-            })
-            .collect();
+    pub fn try_get_orderbook_level_infos(&self) -> Result<OrderBookLevelInfos, QuantityOverflow> {
+        Ok(OrderBookLevelInfos::new(
+            Self::checked_level_infos(Side::Buy, self.bids.iter().rev())?,
+            Self::checked_level_infos(Side::Sell, self.asks.iter())?,
+        ))
+    }
 
-        OrderBookLevelInfos::new(bids, asks)
+    fn checked_level_infos<'a>(
+        side: Side,
+        levels: impl Iterator<Item = (Price, &'a OrderList)>,
+    ) -> Result<Vec<LevelInfo>, QuantityOverflow> {
+        levels
+            .map(|(price, orders)| {
+                Self::checked_level_quantity(orders)
+                    .map(|quantity| LevelInfo { price, quantity })
+                    .ok_or(QuantityOverflow { side, price })
+            })
+            .collect()
     }
 
     pub fn get_best_bid_ask(&self) -> Option<(Price, Price)> {
-        let best_bid = self.bids.iter().next().map(|(price, _)| price.0);
-        let best_ask = self.asks.iter().next().map(|(price, _)| *price);
+        let best_bid = self.bids.last().map(|(price, _)| price);
+        let best_ask = self.asks.first().map(|(price, _)| price);
 
         match (best_bid, best_ask) {
             (Some(best_bid), Some(best_ask)) => Some((best_bid, best_ask)),
@@ -448,13 +2244,111 @@ impl OrderBook {
         }
     }
 
-    // TODO: Not sure if we should only count bids here (maybe we should count asks too?)
-    pub fn get_volume_at_price(&self, price: Price) -> Quantity {
-        let bids = self.bids.get(&std::cmp::Reverse(price)).unwrap();
-        bids.iter().fold(0, |total_quantity, bid| {
-            bid.borrow().remaining_quantity + total_quantity
+    // Aggregated price levels in best-first order (highest bid first)
+    pub fn bids(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(price, orders)| (price, Self::level_quantity(orders)))
+    }
+
+    // Aggregated price levels in best-first order (lowest ask first)
+    pub fn asks(&self) -> impl Iterator<Item = (Price, Quantity)> + '_ {
+        self.asks
+            .iter()
+            .map(|(price, orders)| (price, Self::level_quantity(orders)))
+    }
+
+    // Price levels with their resting orders in time priority
+    pub fn bid_levels(
+        &self,
+    ) -> impl Iterator<Item = (Price, impl Iterator<Item = Ref<'_, Order>> + '_)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(price, orders)| (price, orders.iter().map(|o| o.borrow())))
+    }
+
+    pub fn ask_levels(
+        &self,
+    ) -> impl Iterator<Item = (Price, impl Iterator<Item = Ref<'_, Order>> + '_)> + '_ {
+        self.asks
+            .iter()
+            .map(|(price, orders)| (price, orders.iter().map(|o| o.borrow())))
+    }
+
+    // Saturates at `Quantity::MAX`
+    fn level_quantity(orders: &OrderList) -> Quantity {
+        orders.iter().fold(Quantity::ZERO, |total, o| {
+            total.saturating_add(o.borrow().remaining_quantity)
+        })
+    }
+
+    // Not limited to `Quantity::MAX`
+    fn level_volume(orders: &OrderList) -> u64 {
+        orders
+            .iter()
+            .map(|o| u64::from(o.borrow().remaining_quantity))
+            .sum()
+    }
+
+    fn checked_level_quantity(orders: &OrderList) -> Option<Quantity> {
+        orders.iter().try_fold(Quantity::ZERO, |total, o| {
+            total.checked_add(o.borrow().remaining_quantity)
         })
     }
+
+    // Levels grouped into price buckets of `bucket_size` ticks, bids labelled with the lowest
+    // and asks with the highest price of their bucket. Buckets holding more than
+    // `Quantity::MAX` report `Quantity::MAX`.
+    pub fn aggregated_depth(&self, bucket_size: Price) -> OrderBookLevelInfos {
+        let levels = |levels: Vec<(Price, u64)>| {
+            levels
+                .into_iter()
+                .map(|(price, quantity)| LevelInfo {
+                    price,
+                    quantity: Quantity::saturating_from(quantity),
+                })
+                .collect()
+        };
+        let bucket_size = i64::from(bucket_size);
+        let bids = self
+            .bids
+            .iter()
+            .rev()
+            .map(|(price, orders)| (price, Self::level_volume(orders)));
+        let asks = self
+            .asks
+            .iter()
+            .map(|(price, orders)| (price, Self::level_volume(orders)));
+        OrderBookLevelInfos::new(
+            levels(price_levels::aggregate_levels(bids, bucket_size, false)),
+            levels(price_levels::aggregate_levels(asks, bucket_size, true)),
+        )
+    }
+
+    // Resting quantity at the price on one side, 0 when there is no level
+    pub fn get_volume_at(&self, side: Side, price: Price) -> Quantity {
+        self.side_levels(side)
+            .get(price)
+            .map_or(Quantity::ZERO, Self::level_quantity)
+    }
+
+    // Resting quantity over all levels of one side, saturates at `Quantity::MAX`
+    pub fn get_total_volume(&self, side: Side) -> Quantity {
+        self.side_levels(side)
+            .iter()
+            .fold(Quantity::ZERO, |total, (_, orders)| {
+                total.saturating_add(Self::level_quantity(orders))
+            })
+    }
+
+    fn side_levels(&self, side: Side) -> &PriceLevels<Price, OrderList> {
+        match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -463,9 +2357,25 @@ mod tests {
 
     #[test]
     fn test_orderbook() {
-        let Price = 10;
+        let price: Price = Price(10);
 
-        assert_eq!(Price, 10);
+        assert_eq!(price, Price(10));
+    }
+
+    #[test]
+    fn test_unit_arithmetic() {
+        assert_eq!(Price(25) * Quantity(4), Notional(100));
+        assert_eq!(Quantity(4) * Price(-25), Notional(-100));
+        assert_eq!(Quantity(7) - Quantity(3) + Quantity(1), Quantity(5));
+        assert_eq!(Price(100) - Price(103), Price(-3));
+        assert_eq!(Price(100).midpoint(Price(103)), Price(101));
+        assert_eq!(Quantity::MAX.checked_add(Quantity(1)), None);
+        assert_eq!(Quantity::saturating_from(u64::MAX), Quantity::MAX);
+        assert_eq!(
+            [Quantity(1), Quantity(2)].into_iter().sum::<Quantity>(),
+            Quantity(3)
+        );
+        assert_eq!(serde_json::to_string(&Price(42)).unwrap(), "42");
     }
 
     #[test]
@@ -479,11 +2389,17 @@ mod tests {
     #[test]
     fn test_filling_an_order() {
         let initial_quantity = 100;
-        let mut order = Order::new(1, 10, initial_quantity, OrderType::GoodToCancel, Side::Buy);
+        let mut order = Order::new(
+            1,
+            Price(10),
+            Quantity(initial_quantity),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        );
 
-        order.fill(50);
+        order.fill(Quantity(50));
 
-        assert_eq!(order.get_fill_quantity(), 50);
+        assert_eq!(order.get_fill_quantity(), Quantity(50));
     }
 
     #[test]
@@ -491,15 +2407,15 @@ mod tests {
         let mut orderlist = OrderList::new();
         orderlist.push_back(Rc::new(RefCell::new(Order::new(
             1,
-            10,
-            100,
+            Price(10),
+            Quantity(100),
             OrderType::GoodToCancel,
             Side::Buy,
         ))));
         orderlist.push_back(Rc::new(RefCell::new(Order::new(
             2,
-            20,
-            200,
+            Price(20),
+            Quantity(200),
             OrderType::GoodToCancel,
             Side::Buy,
         ))));
@@ -507,25 +2423,115 @@ mod tests {
         assert_eq!(orderlist.len(), 2);
     }
 
+    #[test]
+    fn test_aggregated_depth() {
+        let mut orderbook = OrderBook::new();
+        for (order_id, price, side) in [
+            (1, 99, Side::Buy),
+            (2, 97, Side::Buy),
+            (3, 94, Side::Buy),
+            (4, 101, Side::Sell),
+            (5, 105, Side::Sell),
+            (6, 106, Side::Sell),
+        ] {
+            orderbook.add_order(Order::new(
+                order_id,
+                Price(price),
+                Quantity(10),
+                OrderType::GoodToCancel,
+                side,
+            ));
+        }
+        orderbook.add_order(Order::new(
+            7,
+            Price(99),
+            Quantity::MAX,
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        let level = |price, quantity| LevelInfo { price, quantity };
+        assert_eq!(
+            orderbook.aggregated_depth(Price(5)),
+            OrderBookLevelInfos::new(
+                vec![
+                    level(Price(95), Quantity::MAX),
+                    level(Price(90), Quantity(10))
+                ],
+                vec![
+                    level(Price(105), Quantity(20)),
+                    level(Price(110), Quantity(10))
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn test_volume_per_side() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(100),
+            Quantity(3),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(99),
+            Quantity(4),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            4,
+            Price(102),
+            Quantity(7),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        assert_eq!(orderbook.get_volume_at(Side::Buy, Price(100)), Quantity(8));
+        assert_eq!(orderbook.get_volume_at(Side::Sell, Price(102)), Quantity(7));
+        // Ask-only and empty prices
+        assert_eq!(orderbook.get_volume_at(Side::Buy, Price(102)), Quantity(0));
+        assert_eq!(orderbook.get_volume_at(Side::Sell, Price(101)), Quantity(0));
+
+        assert_eq!(orderbook.get_total_volume(Side::Buy), Quantity(12));
+        assert_eq!(orderbook.get_total_volume(Side::Sell), Quantity(7));
+        assert_eq!(OrderBook::new().get_total_volume(Side::Sell), Quantity(0));
+    }
+
     #[test]
     fn test_can_match() {
         let mut orderbook = OrderBook::new();
 
-        orderbook
-            .bids
-            .insert(std::cmp::Reverse(10), OrderList::new());
-        orderbook.asks.insert(20, OrderList::new());
+        orderbook.bids.insert(Price(10), OrderList::new());
+        orderbook.asks.insert(Price(20), OrderList::new());
 
-        assert_eq!(orderbook.can_match(10, Side::Buy), false);
-        assert_eq!(orderbook.can_match(20, Side::Buy), true);
-        assert_eq!(orderbook.can_match(10, Side::Sell), true);
-        assert_eq!(orderbook.can_match(20, Side::Sell), false);
+        assert!(!orderbook.can_match(Price(10), Side::Buy));
+        assert!(orderbook.can_match(Price(20), Side::Buy));
+        assert!(orderbook.can_match(Price(10), Side::Sell));
+        assert!(!orderbook.can_match(Price(20), Side::Sell));
     }
 
     #[test]
     fn test_add_order_to_orderbook() {
         let mut orderbook = OrderBook::new();
-        let order = Order::new(1, 10, 100, OrderType::GoodToCancel, Side::Buy);
+        let order = Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        );
 
         orderbook.add_order(order);
 
@@ -535,11 +2541,1061 @@ mod tests {
     #[test]
     fn test_cancel_order() {
         let mut orderbook = OrderBook::new();
-        let order = Order::new(1, 10, 100, OrderType::GoodToCancel, Side::Buy);
+        let order = Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        );
 
         orderbook.add_order(order);
-        orderbook.cancel_order(1);
+        orderbook.cancel_order(1).unwrap();
 
         assert_eq!(orderbook.orders.len(), 0);
+        assert_eq!(orderbook.cancel_order(1), Err(Rejected::UnknownOrder(1)));
+        assert_eq!(orderbook.cancel_order(2), Err(Rejected::UnknownOrder(2)));
+    }
+
+    #[test]
+    fn test_fees_are_charged_by_liquidity_and_account() {
+        use crate::fees::Fee;
+
+        let mut orderbook = OrderBook::new();
+        orderbook.set_fees(FeeRates::new(Fee::Bps(-1.0), Fee::Bps(5.0)));
+        orderbook.add_order(
+            Order::new(
+                1,
+                Price(100),
+                Quantity(100),
+                OrderType::GoodToCancel,
+                Side::Sell,
+            )
+            .with_account(7),
+        );
+        let trades = orderbook.add_order(
+            Order::new(
+                2,
+                Price(100),
+                Quantity(100),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )
+            .with_account(8),
+        );
+
+        assert_eq!(trades[0].ask_trade.liquidity, Liquidity::Maker);
+        assert_eq!(trades[0].ask_trade.fee, -1.0);
+        assert_eq!(trades[0].bid_trade.liquidity, Liquidity::Taker);
+        assert_eq!(trades[0].bid_trade.fee, 5.0);
+        assert_eq!(orderbook.fees_paid(7), -1.0);
+        assert_eq!(orderbook.fees_paid(8), 5.0);
+        assert_eq!(orderbook.fees_paid(9), 0.0);
+    }
+
+    #[test]
+    fn test_trade_reports_filled_order_ids() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        let trades = orderbook.add_order(Order::new(
+            2,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].bid_trade.order_id, 2);
+        assert_eq!(trades[0].ask_trade.order_id, 1);
+        assert_eq!(orderbook.orderbook_size(), 0);
+    }
+
+    #[test]
+    fn test_bids_and_asks_iterate_best_first() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(11),
+            Quantity(50),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(10),
+            Quantity(25),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            4,
+            Price(13),
+            Quantity(70),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            5,
+            Price(12),
+            Quantity(30),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        let bids: Vec<(Price, Quantity)> = orderbook.bids().collect();
+        let asks: Vec<(Price, Quantity)> = orderbook.asks().collect();
+        assert_eq!(
+            bids,
+            vec![(Price(11), Quantity(50)), (Price(10), Quantity(125))]
+        );
+        assert_eq!(
+            asks,
+            vec![(Price(12), Quantity(30)), (Price(13), Quantity(70))]
+        );
+    }
+
+    #[test]
+    fn test_level_order_iterators_keep_time_priority() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(10),
+            Quantity(25),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(12),
+            Quantity(30),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        let bid_levels: Vec<(Price, Vec<OrderId>)> = orderbook
+            .bid_levels()
+            .map(|(price, orders)| (price, orders.map(|o| o.get_order_id()).collect()))
+            .collect();
+        let ask_levels: Vec<(Price, Vec<OrderId>)> = orderbook
+            .ask_levels()
+            .map(|(price, orders)| (price, orders.map(|o| o.get_order_id()).collect()))
+            .collect();
+        assert_eq!(bid_levels, vec![(Price(10), vec![1, 2])]);
+        assert_eq!(ask_levels, vec![(Price(12), vec![3])]);
+    }
+
+    #[test]
+    fn test_level_infos_serde_and_display() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(9),
+            Quantity(40),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(12),
+            Quantity(30),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        let level_infos = orderbook.get_orderbook_level_infos();
+        let json = serde_json::to_string(&level_infos).unwrap();
+        assert_eq!(
+            serde_json::from_str::<OrderBookLevelInfos>(&json).unwrap(),
+            level_infos
+        );
+
+        let expected = "\
+ASK         12 |         30
+---------------------------
+BID         10 |        100
+BID          9 |         40
+";
+        assert_eq!(level_infos.to_string(), expected);
+    }
+
+    #[test]
+    fn test_sim_clock_only_moves_forward() {
+        let mut clock = SimClock::new(100);
+        clock.advance(Duration::from_nanos(50));
+        assert_eq!(clock.now(), 150);
+        clock.advance_to(120);
+        assert_eq!(clock.now(), 150);
+        clock.advance_to(200);
+        assert_eq!(clock.now(), 200);
+    }
+
+    #[test]
+    fn test_submit_without_latency_matches_immediately() {
+        let mut orderbook = OrderBook::new();
+        orderbook.submit_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        let trades = orderbook.submit_order(Order::new(
+            2,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(orderbook.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_fixed_latency_delays_matching() {
+        let latency = Duration::from_micros(500);
+        let mut orderbook = OrderBook::with_latency(LatencyModel::Fixed(latency), 7);
+        orderbook.submit_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.submit_order(Order::new(
+            2,
+            Price(10),
+            Quantity(40),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+
+        assert_eq!(orderbook.orderbook_size(), 0);
+        assert!(orderbook.advance_clock(499_999).is_empty());
+        assert_eq!(orderbook.pending_count(), 2);
+
+        let trades = orderbook.advance_clock(1_000_000);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].0, 500_000);
+        assert_eq!(trades[0].1.ask_trade.quantity, Quantity(40));
+        assert_eq!(orderbook.clock().now(), 1_000_000);
+    }
+
+    #[test]
+    fn test_cancel_in_flight_after_fill_is_ignored() {
+        let mut orderbook =
+            OrderBook::with_latency(LatencyModel::Fixed(Duration::from_nanos(10)), 7);
+        orderbook.submit_order(Order::new(
+            1,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.advance_clock(10);
+        orderbook.submit_order(Order::new(
+            2,
+            Price(10),
+            Quantity(100),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.submit_cancel(1);
+
+        orderbook.advance_clock(100);
+        assert_eq!(orderbook.orderbook_size(), 0);
+        assert_eq!(orderbook.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_sampled_latency_is_bounded_and_reproducible() {
+        let model = LatencyModel::Uniform {
+            min: Duration::from_micros(100),
+            max: Duration::from_micros(200),
+        };
+        let mut first = StdRng::seed_from_u64(42);
+        let mut second = StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let latency = model.sample(&mut first);
+            assert!(latency >= Duration::from_micros(100));
+            assert!(latency <= Duration::from_micros(200));
+            assert_eq!(latency, model.sample(&mut second));
+        }
+
+        let model = LatencyModel::Exponential {
+            base: Duration::from_micros(50),
+            mean_tail: Duration::from_micros(20),
+        };
+        assert!(model.sample(&mut first) >= Duration::from_micros(50));
+    }
+
+    #[test]
+    fn test_level_quantity_overflow() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity::MAX,
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(100),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(101),
+            Quantity::MAX - Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            4,
+            Price(101),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(100), Quantity::MAX)]
+        );
+        assert_eq!(
+            orderbook.get_volume_at(Side::Buy, Price(100)),
+            Quantity::MAX
+        );
+        assert_eq!(orderbook.get_total_volume(Side::Sell), Quantity::MAX);
+        assert_eq!(
+            orderbook.get_orderbook_level_infos(),
+            OrderBookLevelInfos::new(
+                vec![LevelInfo {
+                    price: Price(100),
+                    quantity: Quantity::MAX
+                }],
+                vec![LevelInfo {
+                    price: Price(101),
+                    quantity: Quantity::MAX
+                }],
+            )
+        );
+        assert_eq!(
+            orderbook.try_get_orderbook_level_infos(),
+            Err(QuantityOverflow {
+                side: Side::Buy,
+                price: Price(100)
+            })
+        );
+
+        orderbook.cancel_order(2).unwrap();
+        let level_infos = orderbook.try_get_orderbook_level_infos().unwrap();
+        assert_eq!(level_infos, orderbook.get_orderbook_level_infos());
+    }
+
+    #[test]
+    fn test_auction_volume_above_quantity_max() {
+        let mut orderbook = OrderBook::new();
+        orderbook.start_auction();
+        for order_id in 1..=2 {
+            orderbook.add_order(Order::new(
+                order_id,
+                Price(100),
+                Quantity::MAX,
+                OrderType::GoodToCancel,
+                Side::Buy,
+            ));
+            orderbook.add_order(Order::new(
+                order_id + 2,
+                Price(100),
+                Quantity::MAX,
+                OrderType::GoodToCancel,
+                Side::Sell,
+            ));
+        }
+        assert_eq!(orderbook.indicative_price(), Some(Price(100)));
+    }
+
+    #[test]
+    fn test_auction_uncrosses_at_maximum_volume_price() {
+        let mut orderbook = OrderBook::new();
+        orderbook.start_auction();
+        assert!(orderbook.in_auction());
+
+        let orders = [
+            (1, 102, 3, Side::Buy),
+            (2, 101, 4, Side::Buy),
+            (3, 100, 5, Side::Buy),
+            (4, 99, 2, Side::Sell),
+            (5, 100, 4, Side::Sell),
+            (6, 101, 6, Side::Sell),
+        ];
+        for (order_id, price, quantity, side) in orders {
+            let trades = orderbook.add_order(Order::new(
+                order_id,
+                Price(price),
+                Quantity(quantity),
+                OrderType::GoodToCancel,
+                side,
+            ));
+            assert!(trades.is_empty());
+        }
+        orderbook.add_order(Order::new(
+            7,
+            Price(98),
+            Quantity(1),
+            OrderType::FillAndKill,
+            Side::Buy,
+        ));
+        assert_eq!(orderbook.orderbook_size(), 7);
+        assert_eq!(orderbook.indicative_price(), Some(Price(101)));
+
+        let trades = orderbook.uncross();
+        assert!(!orderbook.in_auction());
+        assert!(trades
+            .iter()
+            .all(|t| t.bid_trade.price == Price(101) && t.ask_trade.price == Price(101)));
+        assert_eq!(
+            trades
+                .iter()
+                .map(|t| t.bid_trade.quantity)
+                .sum::<Quantity>(),
+            Quantity(7)
+        );
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(100), Quantity(5))]
+        );
+        assert_eq!(
+            orderbook.asks().collect::<Vec<_>>(),
+            vec![(Price(101), Quantity(5))]
+        );
+        assert_eq!(orderbook.indicative_price(), None);
+
+        // Continuous trading resumes at the resting price
+        let trades = orderbook.add_order(Order::new(
+            8,
+            Price(100),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        assert_eq!(trades[0].ask_trade.price, Price(100));
+    }
+
+    #[test]
+    fn test_modify_applies_new_price_and_quantity() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(Order::new(
+            1,
+            Price(100),
+            Quantity(10),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(105),
+            Quantity(4),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+
+        let trades = orderbook.match_order(OrderModify::new(1, Side::Buy, Price(105), Quantity(6)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].bid_trade.quantity, Quantity(4));
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(105), Quantity(2))]
+        );
+        assert!(orderbook
+            .match_order(OrderModify::new(9, Side::Buy, Price(1), Quantity(1)))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apply_batch_reports_per_command_results() {
+        let mut orderbook = OrderBook::new();
+        let report = orderbook.apply_batch(vec![
+            OrderCommand::New(Order::new(
+                1,
+                Price(100),
+                Quantity(5),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )),
+            OrderCommand::New(Order::new(
+                2,
+                Price(101),
+                Quantity(5),
+                OrderType::GoodToCancel,
+                Side::Sell,
+            )),
+            OrderCommand::Cancel(7),
+            OrderCommand::Modify(OrderModify::new(2, Side::Sell, Price(100), Quantity(3))),
+            OrderCommand::Cancel(1),
+        ]);
+
+        assert_eq!(
+            report.results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(Rejected::UnknownOrder(7)),
+                Ok(()),
+                Ok(())
+            ]
+        );
+        assert_eq!(report.trades.len(), 1);
+        assert_eq!(report.trades[0].ask_trade.order_id, 2);
+        assert_eq!(report.trades[0].ask_trade.quantity, Quantity(3));
+        assert_eq!(orderbook.orderbook_size(), 0);
+    }
+
+    #[test]
+    fn test_rejections_are_reported() {
+        let mut orderbook = OrderBook::new();
+        let order = |order_id, order_type| {
+            Order::new(order_id, Price(100), Quantity(5), order_type, Side::Buy)
+        };
+        assert!(orderbook
+            .place_order(order(1, OrderType::GoodToCancel))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            orderbook
+                .place_order(order(1, OrderType::GoodToCancel))
+                .unwrap_err(),
+            Rejected::DuplicateOrderId(1)
+        );
+        // Nothing on the ask side to take
+        assert_eq!(
+            orderbook
+                .place_order(order(2, OrderType::FillAndKill))
+                .unwrap_err(),
+            Rejected::NoLiquidity(2)
+        );
+
+        orderbook.advance_clock(5);
+        assert!(orderbook
+            .add_order(order(3, OrderType::FillAndKill))
+            .is_empty());
+        assert!(orderbook
+            .match_order(OrderModify::new(9, Side::Buy, Price(99), Quantity(1)))
+            .is_empty());
+        assert_eq!(
+            orderbook.drain_events(),
+            vec![
+                EngineEvent::OrderRejected {
+                    order_id: 3,
+                    reason: "fill and kill order 3 cannot match".to_string(),
+                    timestamp: 5,
+                },
+                EngineEvent::OrderRejected {
+                    order_id: 9,
+                    reason: "unknown order 9".to_string(),
+                    timestamp: 5,
+                },
+            ]
+        );
+        assert_eq!(orderbook.orderbook_size(), 1);
+    }
+
+    #[test]
+    fn test_order_builder_validates() {
+        use crate::instruments::{ContractType, InstrumentStatus};
+
+        let order = Order::builder()
+            .order_id(1)
+            .price(Price(100))
+            .quantity(Quantity(5))
+            .order_type(OrderType::Day)
+            .side(Side::Sell)
+            .account(7)
+            .build()
+            .unwrap();
+        assert_eq!(order.get_order_type(), OrderType::Day);
+        assert_eq!(order.get_account_id(), 7);
+        assert_eq!(order.get_remaining_quantity(), Quantity(5));
+
+        let builder = Order::builder()
+            .order_id(2)
+            .order_type(OrderType::GoodToCancel)
+            .side(Side::Buy);
+        assert_eq!(
+            builder.clone().quantity(Quantity(5)).build().err(),
+            Some(OrderValidationError::MissingField("price"))
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .price(Price(0))
+                .quantity(Quantity(5))
+                .build()
+                .err(),
+            Some(OrderValidationError::NonPositivePrice(Price(0)))
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .price(Price(100))
+                .quantity(Quantity(0))
+                .build()
+                .err(),
+            Some(OrderValidationError::ZeroQuantity)
+        );
+
+        // Off the grid, the halt is for the engine to enforce
+        let instrument = Instrument {
+            symbol: "BNBUSDT".into(),
+            base: "BNB".to_string(),
+            quote: "USDT".to_string(),
+            tick_size: 0.05,
+            lot_size: 0.1,
+            contract_type: ContractType::Spot,
+            status: InstrumentStatus::Halt,
+        };
+        let builder = builder.instrument(&instrument);
+        assert!(builder
+            .clone()
+            .price(Price(105))
+            .quantity(Quantity(20))
+            .build()
+            .is_ok());
+        assert_eq!(
+            builder
+                .price(Price(105))
+                .quantity(Quantity(25))
+                .build()
+                .err(),
+            Some(OrderValidationError::Instrument(
+                InstrumentViolation::OffLot {
+                    quantity: 25,
+                    lot_size: 10
+                }
+            ))
+        );
+
+        let mut orderbook = OrderBook::new();
+        let rejected = orderbook.add_client_order(NewOrder {
+            client_order_id: "zero".to_string(),
+            account_id: 0,
+            price: Price(100),
+            quantity: Quantity(0),
+            order_type: OrderType::GoodToCancel,
+            side: Side::Buy,
+        });
+        assert_eq!(
+            rejected.err(),
+            Some(Rejected::InvalidOrder(OrderValidationError::ZeroQuantity))
+        );
+        assert_eq!(orderbook.order_id_for("zero"), None);
+    }
+
+    #[test]
+    fn test_instrument_grid_and_status() {
+        use crate::instruments::{ContractType, InstrumentStatus};
+
+        let mut instrument = Instrument {
+            symbol: "BNBUSDT".into(),
+            base: "BNB".to_string(),
+            quote: "USDT".to_string(),
+            tick_size: 0.05,
+            lot_size: 0.1,
+            contract_type: ContractType::Spot,
+            status: InstrumentStatus::Trading,
+        };
+        let mut orderbook = OrderBook::new();
+        orderbook.set_instrument(instrument.clone());
+
+        let order = |order_id, price, quantity| {
+            Order::new(
+                order_id,
+                price,
+                quantity,
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )
+        };
+        assert!(orderbook
+            .place_order(order(1, Price(1_005), Quantity(30)))
+            .is_ok());
+        assert_eq!(
+            orderbook
+                .place_order(order(2, Price(1_002), Quantity(30)))
+                .err(),
+            Some(Rejected::Instrument(InstrumentViolation::OffTick {
+                price: 1_002,
+                tick_size: 5
+            }))
+        );
+        assert!(orderbook
+            .place_order(order(3, Price(1_005), Quantity(35)))
+            .is_err());
+
+        instrument.status = InstrumentStatus::Halt;
+        orderbook.set_instrument(instrument);
+        assert!(matches!(
+            orderbook.place_order(order(4, Price(1_005), Quantity(30))),
+            Err(Rejected::Instrument(InstrumentViolation::NotTrading { .. }))
+        ));
+        assert_eq!(orderbook.orderbook_size(), 1);
+    }
+
+    #[test]
+    fn test_backends_produce_same_book() {
+        let run = |backend| {
+            let mut orderbook = OrderBook::with_backend(backend);
+            let mut trades = 0;
+            for order_id in 1..=200u64 {
+                let side = if order_id % 2 == 0 {
+                    Side::Buy
+                } else {
+                    Side::Sell
+                };
+                let price = Price(100 + (order_id * 7 % 13) as i32 - 6);
+                let order_type = if order_id % 5 == 0 {
+                    OrderType::FillAndKill
+                } else {
+                    OrderType::GoodToCancel
+                };
+                trades += orderbook
+                    .add_order(Order::new(order_id, price, Quantity(3), order_type, side))
+                    .len();
+                if order_id % 7 == 0 {
+                    orderbook.apply_batch(vec![OrderCommand::Cancel(order_id - 3)]);
+                }
+            }
+            (trades, orderbook.get_orderbook_level_infos())
+        };
+
+        let expected = run(BookBackend::BTree);
+        assert!(expected.0 > 0);
+        assert_eq!(run(BookBackend::Dense { tick_size: 1 }), expected);
+        assert_eq!(run(BookBackend::SkipList), expected);
+    }
+
+    #[test]
+    fn test_pro_rata_matching_policy() {
+        let gtc = OrderType::GoodToCancel;
+        let fills = |policy: MatchingPolicy, side: Side, resting: &[u32], incoming: u32| {
+            let mut orderbook = OrderBook::new();
+            orderbook.set_matching_policy(policy);
+            for (order_id, quantity) in (1..).zip(resting) {
+                orderbook
+                    .place_order(Order::new(
+                        order_id,
+                        Price(100),
+                        Quantity(*quantity),
+                        gtc,
+                        side,
+                    ))
+                    .unwrap();
+            }
+            let taker_side = match side {
+                Side::Buy => Side::Sell,
+                Side::Sell => Side::Buy,
+            };
+            let taker = Order::new(99, Price(100), Quantity(incoming), gtc, taker_side);
+            orderbook
+                .place_order(taker)
+                .unwrap()
+                .iter()
+                .map(|trade| {
+                    let maker = match side {
+                        Side::Buy => &trade.bid_trade,
+                        Side::Sell => &trade.ask_trade,
+                    };
+                    (maker.order_id, maker.quantity.0)
+                })
+                .collect::<Vec<_>>()
+        };
+        let pro_rata = |min_allocation: u32, top_order_priority: bool| MatchingPolicy::ProRata {
+            min_allocation: Quantity(min_allocation),
+            top_order_priority,
+        };
+
+        assert_eq!(
+            fills(MatchingPolicy::PriceTime, Side::Sell, &[10, 20, 30], 30),
+            vec![(1, 10), (2, 20)]
+        );
+        assert_eq!(
+            fills(pro_rata(0, false), Side::Sell, &[10, 20, 30], 30),
+            vec![(1, 5), (2, 10), (3, 15)]
+        );
+        assert_eq!(
+            fills(pro_rata(0, true), Side::Sell, &[10, 20, 30], 30),
+            vec![(1, 10), (2, 8), (3, 12)]
+        );
+        // Shares of 2 and 4 are dropped and go to the oldest order
+        assert_eq!(
+            fills(pro_rata(6, false), Side::Sell, &[10, 20, 30], 12),
+            vec![(1, 6), (3, 6)]
+        );
+        assert_eq!(
+            fills(pro_rata(0, false), Side::Buy, &[10, 30], 20),
+            vec![(1, 5), (2, 15)]
+        );
+    }
+
+    #[test]
+    fn test_minimum_fill_and_all_or_none() {
+        let mut orderbook = OrderBook::new();
+        let gtc = OrderType::GoodToCancel;
+        let traded = |trades: Vec<Trade>| -> Vec<(OrderId, OrderId, Price, Quantity)> {
+            trades
+                .iter()
+                .map(|trade| {
+                    (
+                        trade.bid_trade.order_id,
+                        trade.ask_trade.order_id,
+                        trade.bid_trade.price,
+                        trade.bid_trade.quantity,
+                    )
+                })
+                .collect()
+        };
+
+        let aon = Order::new(1, Price(100), Quantity(10), gtc, Side::Sell).with_all_or_none();
+        orderbook.place_order(aon).unwrap();
+        orderbook
+            .place_order(Order::new(2, Price(101), Quantity(5), gtc, Side::Sell))
+            .unwrap();
+
+        // Too small for the all-or-none order, trades with the next level instead
+        let trades = orderbook
+            .place_order(Order::new(3, Price(102), Quantity(6), gtc, Side::Buy))
+            .unwrap();
+        assert_eq!(traded(trades), vec![(3, 2, Price(101), Quantity(5))]);
+        // The book stays crossed until a bid can take all of it
+        assert_eq!(orderbook.orderbook_size(), 2);
+        let trades = orderbook
+            .place_order(Order::new(4, Price(100), Quantity(10), gtc, Side::Buy))
+            .unwrap();
+        assert_eq!(traded(trades), vec![(4, 1, Price(100), Quantity(10))]);
+        orderbook.cancel_order(3).unwrap();
+
+        let min_fill = Order::new(5, Price(99), Quantity(20), gtc, Side::Buy)
+            .with_min_fill_quantity(Quantity(8));
+        orderbook.place_order(min_fill).unwrap();
+        let fak = Order::new(
+            6,
+            Price(99),
+            Quantity(5),
+            OrderType::FillAndKill,
+            Side::Sell,
+        );
+        assert!(orderbook.place_order(fak).unwrap().is_empty());
+        let trades = orderbook
+            .place_order(Order::new(7, Price(99), Quantity(8), gtc, Side::Sell))
+            .unwrap();
+        assert_eq!(traded(trades), vec![(5, 7, Price(99), Quantity(8))]);
+        assert_eq!(orderbook.orderbook_size(), 1);
+
+        assert_eq!(
+            Order::builder()
+                .order_id(8)
+                .price(Price(99))
+                .quantity(Quantity(5))
+                .order_type(gtc)
+                .side(Side::Buy)
+                .min_fill_quantity(Quantity(6))
+                .build()
+                .unwrap_err(),
+            OrderValidationError::MinFillAboveQuantity {
+                min_fill_quantity: Quantity(6),
+                quantity: Quantity(5)
+            }
+        );
+    }
+
+    #[test]
+    fn test_open_orders_and_status() {
+        let mut orderbook = OrderBook::new();
+        let order = |order_id, side, price, account_id| {
+            Order::new(
+                order_id,
+                Price(price),
+                Quantity(10),
+                OrderType::GoodToCancel,
+                side,
+            )
+            .with_account(account_id)
+        };
+        orderbook.add_order(order(1, Side::Sell, 101, 1));
+        orderbook.add_order(order(2, Side::Buy, 99, 1));
+        orderbook.add_order(order(3, Side::Buy, 100, 2));
+        orderbook.add_order(order(4, Side::Sell, 102, 2));
+        let ids = |orders: Vec<Ref<'_, Order>>| -> Vec<OrderId> {
+            orders.iter().map(|order| order.get_order_id()).collect()
+        };
+        assert_eq!(ids(orderbook.open_orders().collect()), vec![3, 2, 1, 4]);
+        assert_eq!(ids(orderbook.open_orders_for(2).collect()), vec![3, 4]);
+
+        orderbook.add_order(
+            Order::new(
+                5,
+                Price(101),
+                Quantity(4),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )
+            .with_account(3),
+        );
+        orderbook.cancel_order(2).unwrap();
+        assert_eq!(
+            orderbook.order_status(1),
+            Some(OrderStatus::PartiallyFilled)
+        );
+        assert_eq!(orderbook.order_status(2), Some(OrderStatus::Cancelled));
+        assert_eq!(orderbook.order_status(3), Some(OrderStatus::New));
+        assert_eq!(orderbook.order_status(5), Some(OrderStatus::Filled));
+        assert_eq!(orderbook.order_status(6), None);
+        assert_eq!(orderbook.open_orders().count(), 3);
+    }
+
+    #[test]
+    fn test_state_digest_is_canonical() {
+        let build = |backend| {
+            let mut orderbook = OrderBook::with_backend(backend);
+            let gtc = OrderType::GoodToCancel;
+            orderbook.add_order(Order::new(1, Price(100), Quantity(5), gtc, Side::Buy));
+            orderbook
+                .add_order(Order::new(2, Price(100), Quantity(3), gtc, Side::Buy).with_account(7));
+            orderbook.add_order(
+                Order::new(3, Price(103), Quantity(4), gtc, Side::Sell)
+                    .with_min_fill_quantity(Quantity(2)),
+            );
+            // Too small for the minimum fill, the book stays crossed
+            orderbook.add_order(Order::new(4, Price(103), Quantity(1), gtc, Side::Buy));
+            orderbook
+        };
+
+        let orderbook = build(BookBackend::BTree);
+        assert_eq!(
+            orderbook.canonical_state(),
+            "bid|103|1\n\
+             order|4|0|GoodToCancel|1|1|-|0\n\
+             bid|100|8\n\
+             order|1|0|GoodToCancel|5|5|-|0\n\
+             order|2|7|GoodToCancel|3|3|-|0\n\
+             ask|103|4\n\
+             order|3|0|GoodToCancel|4|4|2|0\n\
+             last_trade|-\n"
+        );
+        assert_eq!(
+            build(BookBackend::SkipList).state_digest(),
+            orderbook.state_digest()
+        );
+        assert_eq!(
+            OrderBook::from_state(orderbook.state()).state_digest(),
+            orderbook.state_digest()
+        );
+
+        let mut traded = build(BookBackend::BTree);
+        traded.add_order(Order::new(
+            5,
+            Price(103),
+            Quantity(2),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        assert_ne!(traded.state_digest(), orderbook.state_digest());
+    }
+
+    #[test]
+    fn test_snapshot_restore_resumes_identically() {
+        let model = LatencyModel::Uniform {
+            min: Duration::from_micros(100),
+            max: Duration::from_micros(900),
+        };
+        let order = |order_id: OrderId, side, price| {
+            Order::new(
+                order_id,
+                Price(price),
+                Quantity(3),
+                OrderType::GoodToCancel,
+                side,
+            )
+        };
+        let run = |orderbook: &mut OrderBook, from: OrderId, to: OrderId| {
+            let mut trades = Vec::new();
+            for order_id in from..to {
+                let side = if order_id % 2 == 0 {
+                    Side::Buy
+                } else {
+                    Side::Sell
+                };
+                orderbook.submit_order(order(order_id, side, 100 + (order_id % 5) as i32));
+                let now = orderbook.clock().now();
+                trades.extend(orderbook.advance_clock(now + 300_000));
+            }
+            trades
+        };
+
+        let mut original = OrderBook::with_latency(model, 7);
+        run(&mut original, 1, 20);
+        original.submit_order(order(20, Side::Buy, 104));
+        original
+            .place_stop_order(StopOrder::new(order(21, Side::Buy, 110), Price(105)))
+            .unwrap();
+
+        let encoded = serde_json::to_string(&original.snapshot()).unwrap();
+        let mut restored = OrderBook::with_latency(model, 7);
+        restored.restore(serde_json::from_str(&encoded).unwrap());
+        assert_eq!(
+            serde_json::to_string(&restored.snapshot()).unwrap(),
+            encoded
+        );
+
+        let trades = |trades: Vec<(Timestamp, Trade)>| {
+            trades
+                .into_iter()
+                .map(|(timestamp, trade)| {
+                    (
+                        timestamp,
+                        trade.bid_trade.order_id,
+                        trade.ask_trade.order_id,
+                        trade.bid_trade.quantity,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = trades(run(&mut original, 22, 40));
+        assert!(!expected.is_empty());
+        assert_eq!(trades(run(&mut restored, 22, 40)), expected);
+        assert_eq!(restored.state_digest(), original.state_digest());
+        assert_eq!(restored.next_order_id(), original.next_order_id());
     }
 }
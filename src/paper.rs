@@ -0,0 +1,408 @@
+/// Paper trading against live Binance market data.
+/// `PaperExchange` takes orders through the same calls as the matching engine (`place_order`,
+/// `add_client_order`, `cancel_order`) but fills them against the live L2 book and trade
+/// stream with the `FillSimulator`: orders crossing the spread fill immediately from the
+/// visible depth, resting orders fill once the market trades through their price or the
+/// traded volume has consumed the queue ahead of them. Every state change is reported as a
+/// Binance `executionReport`, so an `OrderTracker` follows paper orders like live ones.
+/// Engine prices and quantities are read in the units of the book's `PriceConverter`.
+use crate::binance_payloads::{DepthUpdate, TradeUpdate};
+use crate::clock::{self, SharedClock};
+use crate::fees::FeeRates;
+use crate::fill_simulator::{FillSimulator, SimOrderKind, SimulatedExecution};
+use crate::ids::{ClientOrderIds, OrderIdAllocator};
+use crate::orderbook;
+use crate::orderbookv2::{
+    Liquidity, NewOrder, Order, OrderAck, OrderId, OrderType, Price, Quantity, Rejected, Side,
+    Trade, TradeInfo,
+};
+use crate::symbol::Symbol;
+use crate::user_data::{ExecutionReport, ExecutionType, OrderStatus};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+struct OpenOrder {
+    order: Order,
+    filled: Quantity,
+}
+
+#[derive(Debug)]
+pub struct PaperExchange {
+    simulator: FillSimulator,
+    clock: SharedClock,
+    fees: FeeRates,
+    orders: HashMap<OrderId, OpenOrder>,
+    order_ids: OrderIdAllocator,
+    client_order_ids: ClientOrderIds,
+    next_trade_id: i64,
+    reports: Vec<ExecutionReport>,
+}
+
+impl PaperExchange {
+    pub fn new(symbol: impl Into<Symbol>) -> PaperExchange {
+        PaperExchange {
+            simulator: FillSimulator::new(symbol),
+            clock: clock::system(),
+            fees: FeeRates::default(),
+            orders: HashMap::new(),
+            order_ids: OrderIdAllocator::new(),
+            client_order_ids: ClientOrderIds::new(),
+            next_trade_id: 1,
+            reports: Vec::new(),
+        }
+    }
+
+    // Time source of the report and trade timestamps, the system clock by default
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    pub fn set_fees(&mut self, fees: FeeRates) {
+        self.fees = fees;
+    }
+
+    // Live book the orders are filled against
+    pub fn book(&self) -> &orderbook::OrderBook {
+        self.simulator.book()
+    }
+
+    pub fn order(&self, order_id: OrderId) -> Option<&Order> {
+        self.orders.get(&order_id).map(|open| &open.order)
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &Order> + '_ {
+        self.orders.values().map(|open| &open.order)
+    }
+
+    pub fn open_quantity(&self, order_id: OrderId) -> Option<Quantity> {
+        self.orders
+            .get(&order_id)
+            .map(|open| open.order.get_initial_quantity() - open.filled)
+    }
+
+    pub fn next_order_id(&mut self) -> OrderId {
+        self.order_ids.allocate()
+    }
+
+    pub fn order_id_for(&self, client_order_id: &str) -> Option<OrderId> {
+        self.client_order_ids.order_id(client_order_id)
+    }
+
+    pub fn client_order_id(&self, order_id: OrderId) -> Option<&str> {
+        self.client_order_ids.client_order_id(order_id)
+    }
+
+    pub fn drain_reports(&mut self) -> Vec<ExecutionReport> {
+        std::mem::take(&mut self.reports)
+    }
+
+    pub fn add_client_order(&mut self, request: NewOrder) -> Result<OrderAck, Rejected> {
+        if self.client_order_ids.contains(&request.client_order_id) {
+            return Err(Rejected::DuplicateClientOrderId(request.client_order_id));
+        }
+
+        let order_id = self.order_ids.allocate();
+        let order = Order::builder()
+            .order_id(order_id)
+            .price(request.price)
+            .quantity(request.quantity)
+            .order_type(request.order_type)
+            .side(request.side)
+            .account(request.account_id)
+            .build()
+            .map_err(Rejected::InvalidOrder)?;
+        self.client_order_ids
+            .insert(&request.client_order_id, order_id);
+        let trades = self.place_order(order)?;
+
+        Ok(OrderAck { order_id, trades })
+    }
+
+    // Takes the visible liquidity the order crosses and rests the remainder, Fill and Kill
+    // remainders expire. Day orders rest like Good to Cancel, there is no session here.
+    pub fn place_order(&mut self, order: Order) -> Result<Vec<Trade>, Rejected> {
+        let order_id = order.get_order_id();
+        if self.orders.contains_key(&order_id) {
+            log::warn!("Order {} already exists", order_id);
+            return Ok(vec![]);
+        }
+        self.order_ids.observe(order_id);
+
+        let open = OpenOrder {
+            order,
+            filled: Quantity::ZERO,
+        };
+        self.report(&open, ExecutionType::New, None);
+        self.orders.insert(order_id, open.clone());
+
+        self.simulator.submit_with_id(
+            order_id,
+            open.order.get_side(),
+            SimOrderKind::Limit(orderbook::Price(open.order.get_price().0.max(0) as u64)),
+            orderbook::Quantity(open.order.get_initial_quantity().into()),
+        );
+        let trades = self.apply_executions();
+
+        if open.order.get_order_type() == OrderType::FillAndKill {
+            self.simulator.cancel(order_id);
+            if let Some(open) = self.orders.remove(&order_id) {
+                self.report(&open, ExecutionType::Expired, None);
+            }
+        }
+        Ok(trades)
+    }
+
+    pub fn cancel_order(&mut self, order_id: OrderId) {
+        self.simulator.cancel(order_id);
+        if let Some(open) = self.orders.remove(&order_id) {
+            self.report(&open, ExecutionType::Canceled, None);
+        }
+    }
+
+    // Partial depth snapshot or diff of the live book, returns the fills of resting orders
+    pub fn on_depth(&mut self, update: &DepthUpdate) -> Vec<Trade> {
+        self.simulator.on_depth(update);
+        self.apply_executions()
+    }
+
+    pub fn on_trade(&mut self, trade: &TradeUpdate) -> Vec<Trade> {
+        self.simulator.on_trade(trade);
+        self.apply_executions()
+    }
+
+    fn apply_executions(&mut self) -> Vec<Trade> {
+        let executions = self.simulator.drain_executions();
+        executions
+            .into_iter()
+            .filter_map(|execution| self.apply_execution(execution))
+            .collect()
+    }
+
+    fn apply_execution(&mut self, execution: SimulatedExecution) -> Option<Trade> {
+        let open = self.orders.get_mut(&execution.order_id)?;
+        let quantity = Quantity(execution.quantity.0 as u32);
+        let price = Price(execution.price.0 as i32);
+        open.filled += quantity;
+        let open = open.clone();
+        if open.filled >= open.order.get_initial_quantity() {
+            self.orders.remove(&execution.order_id);
+        }
+
+        let fee = self.fees.amount(execution.liquidity, price, quantity);
+        self.report(&open, ExecutionType::Trade, Some((&execution, fee)));
+
+        // The other leg is the market, it has no order or account on our side
+        let ours = TradeInfo {
+            order_id: execution.order_id,
+            account_id: open.order.get_account_id(),
+            price,
+            quantity,
+            liquidity: execution.liquidity,
+            fee,
+        };
+        let market = TradeInfo {
+            order_id: 0,
+            account_id: 0,
+            price,
+            quantity,
+            liquidity: match execution.liquidity {
+                Liquidity::Maker => Liquidity::Taker,
+                Liquidity::Taker => Liquidity::Maker,
+            },
+            fee: 0.0,
+        };
+        let (bid_trade, ask_trade) = match execution.side {
+            Side::Buy => (ours, market),
+            Side::Sell => (market, ours),
+        };
+        Some(Trade {
+            bid_trade,
+            ask_trade,
+            timestamp: self.clock.monotonic(),
+        })
+    }
+
+    fn report(
+        &mut self,
+        open: &OpenOrder,
+        execution_type: ExecutionType,
+        execution: Option<(&SimulatedExecution, f64)>,
+    ) {
+        let order = &open.order;
+        let converter = self.simulator.book().converter();
+        let to_f64 = |units: i64| converter.to_f64(units.max(0) as u64);
+        let filled = open.filled;
+        let order_status = match execution_type {
+            ExecutionType::Canceled => OrderStatus::Canceled,
+            ExecutionType::Expired => OrderStatus::Expired,
+            _ if filled >= order.get_initial_quantity() => OrderStatus::Filled,
+            _ if !filled.is_zero() => OrderStatus::PartiallyFilled,
+            _ => OrderStatus::New,
+        };
+        let trade_id = match execution {
+            Some(_) => {
+                self.next_trade_id += 1;
+                self.next_trade_id - 1
+            }
+            None => -1,
+        };
+        // Milliseconds like the exchange timestamps
+        let now = self.clock.wall() / 1_000_000;
+
+        self.reports.push(ExecutionReport {
+            event_time: now,
+            symbol: self.simulator.book().symbol(),
+            client_order_id: self
+                .client_order_ids
+                .client_order_id(order.get_order_id())
+                .map_or_else(|| format!("paper-{}", order.get_order_id()), String::from),
+            side: order.get_side(),
+            order_type: "LIMIT".to_string(),
+            time_in_force: match order.get_order_type() {
+                OrderType::FillAndKill => "IOC",
+                OrderType::GoodToCancel | OrderType::Day => "GTC",
+            }
+            .to_string(),
+            quantity: to_f64(order.get_initial_quantity().into()),
+            price: to_f64(order.get_price().into()),
+            execution_type,
+            order_status,
+            reject_reason: "NONE".to_string(),
+            order_id: order.get_order_id(),
+            last_executed_quantity: execution
+                .map_or(0.0, |(execution, _)| to_f64(execution.quantity.0 as i64)),
+            cumulative_filled_quantity: to_f64(filled.into()),
+            last_executed_price: execution
+                .map_or(0.0, |(execution, _)| to_f64(execution.price.0 as i64)),
+            commission: execution.map_or(0.0, |(_, fee)| fee),
+            commission_asset: None,
+            transaction_time: now,
+            trade_id,
+            is_maker: execution
+                .is_some_and(|(execution, _)| execution.liquidity == Liquidity::Maker),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::user_data::{OrderTracker, UserDataEvent};
+    use std::sync::Arc;
+
+    fn exchange() -> PaperExchange {
+        let mut exchange = PaperExchange::new("BNBUSDT");
+        exchange.set_clock(Arc::new(MockClock::new(1_700_000_000_000_000_000)));
+        exchange.on_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(10.0, 5.0), (9.9, 8.0)],
+            asks: vec![(10.1, 3.0), (10.2, 4.0)],
+        });
+        exchange
+    }
+
+    fn trade(trade_id: u64, price: f64, quantity: f64, is_buyer_maker: bool) -> TradeUpdate {
+        TradeUpdate {
+            event_time: 0,
+            symbol: Symbol::intern("BNBUSDT"),
+            trade_id,
+            price,
+            quantity,
+            trade_time: 0,
+            is_buyer_maker,
+        }
+    }
+
+    fn new_order(client_order_id: &str, side: Side, price: i32, quantity: u32) -> NewOrder {
+        NewOrder {
+            client_order_id: client_order_id.to_string(),
+            account_id: 1,
+            price: Price(price),
+            quantity: Quantity(quantity),
+            order_type: OrderType::GoodToCancel,
+            side,
+        }
+    }
+
+    #[test]
+    fn test_crossing_order_fills_from_depth() {
+        let mut exchange = exchange();
+        let ack = exchange
+            .add_client_order(new_order("take", Side::Buy, 102_000, 50_000))
+            .unwrap();
+        assert_eq!(ack.trades.len(), 2);
+        assert_eq!(ack.trades[0].bid_trade.order_id, ack.order_id);
+        assert_eq!(ack.trades[0].bid_trade.liquidity, Liquidity::Taker);
+        assert_eq!(ack.trades[1].ask_trade.price, Price(102_000));
+        assert_eq!(exchange.open_quantity(ack.order_id), None);
+
+        let reports = exchange.drain_reports();
+        let statuses: Vec<OrderStatus> = reports.iter().map(|r| r.order_status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                OrderStatus::New,
+                OrderStatus::PartiallyFilled,
+                OrderStatus::Filled
+            ]
+        );
+        assert_eq!(reports[2].client_order_id, "take");
+        assert_eq!(reports[2].last_executed_price, 10.2);
+        assert_eq!(reports[2].cumulative_filled_quantity, 5.0);
+        assert_eq!(reports[2].event_time, 1_700_000_000_000);
+
+        assert_eq!(
+            exchange
+                .add_client_order(new_order("take", Side::Buy, 99_000, 1))
+                .err(),
+            Some(Rejected::DuplicateClientOrderId("take".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_passive_order_fills_when_market_trades_through() {
+        let mut exchange = exchange();
+        let mut tracker = OrderTracker::new();
+        let ack = exchange
+            .add_client_order(new_order("rest", Side::Sell, 102_000, 10_000))
+            .unwrap();
+        assert!(ack.trades.is_empty());
+
+        assert!(exchange.on_trade(&trade(1, 10.15, 1.0, false)).is_empty());
+        let trades = exchange.on_trade(&trade(2, 10.25, 2.0, false));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].ask_trade.liquidity, Liquidity::Maker);
+
+        for report in exchange.drain_reports() {
+            tracker.apply(&UserDataEvent::ExecutionReport(report.clone()));
+            assert!(report.execution_type != ExecutionType::Trade || report.is_maker);
+        }
+        assert_eq!(tracker.open_orders().count(), 0);
+
+        // Fill and Kill remainders expire instead of resting
+        let ack = exchange
+            .add_client_order(NewOrder {
+                order_type: OrderType::FillAndKill,
+                ..new_order("ioc", Side::Buy, 101_000, 40_000)
+            })
+            .unwrap();
+        assert_eq!(ack.trades[0].bid_trade.quantity, Quantity(30_000));
+        assert_eq!(exchange.order(ack.order_id).map(Order::get_order_id), None);
+        let reports = exchange.drain_reports();
+        assert_eq!(reports.last().unwrap().order_status, OrderStatus::Expired);
+        assert_eq!(reports.last().unwrap().time_in_force, "IOC");
+
+        let order_id = exchange
+            .add_client_order(new_order("cancel", Side::Buy, 99_000, 10_000))
+            .unwrap()
+            .order_id;
+        exchange.cancel_order(order_id);
+        assert!(exchange.on_trade(&trade(3, 9.8, 5.0, true)).is_empty());
+        assert_eq!(
+            exchange.drain_reports().last().unwrap().order_status,
+            OrderStatus::Canceled
+        );
+    }
+}
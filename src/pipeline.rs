@@ -0,0 +1,513 @@
+/// Bounded channels between the stages of the feed pipeline.
+/// Every stage, e.g. socket reader to books or books to strategy, is a bounded queue with an
+/// explicit overflow policy, so a slow consumer costs at most `capacity` items of memory: Block
+/// makes the producer wait, DropOldest discards the oldest queued item, Conflate replaces the
+/// queued item with the same conflation key and falls back to DropOldest when nothing matches.
+/// Conflation suits items that carry the full state of their key, like partial depth payloads
+/// per stream or depth snapshots per symbol. Every queue counts its depth, high water mark and
+/// drops for the metrics report.
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "native")]
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "native")]
+use std::sync::{Arc, Mutex, Weak};
+#[cfg(feature = "native")]
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    #[default]
+    Block,
+    DropOldest,
+    Conflate,
+}
+
+pub const DEFAULT_STAGE_CAPACITY: usize = 1024;
+
+fn default_capacity() -> usize {
+    DEFAULT_STAGE_CAPACITY
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageConfig {
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub policy: OverflowPolicy,
+}
+
+impl Default for StageConfig {
+    fn default() -> Self {
+        StageConfig {
+            capacity: DEFAULT_STAGE_CAPACITY,
+            policy: OverflowPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    pub name: String,
+    pub capacity: usize,
+    pub depth: usize,
+    pub high_water_mark: usize,
+    pub sent: u64,
+    pub received: u64,
+    // Discarded by DropOldest, or by Conflate without a matching key
+    pub dropped: u64,
+    // Replaced by a newer item of the same key
+    pub conflated: u64,
+    // Sends that had to wait for room
+    pub blocked: u64,
+}
+
+impl fmt::Display for QueueMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: depth {}/{} (max {}), sent {}, received {}, dropped {}, conflated {}, blocked {}",
+            self.name,
+            self.depth,
+            self.capacity,
+            self.high_water_mark,
+            self.sent,
+            self.received,
+            self.dropped,
+            self.conflated,
+            self.blocked
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamName<'a> {
+    stream: &'a str,
+}
+
+// Conflation key of a combined stream payload, the hash of its stream name. None for replies
+// and other payloads without a stream, those are never conflated.
+pub fn stream_key(payload: &[u8]) -> Option<u64> {
+    let envelope = serde_json::from_slice::<StreamName>(payload).ok()?;
+    let mut hasher = DefaultHasher::new();
+    envelope.stream.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+#[cfg(feature = "native")]
+type KeyFn<T> = Box<dyn Fn(&T) -> Option<u64> + Send + Sync>;
+
+#[cfg(feature = "native")]
+struct State<T> {
+    queue: VecDeque<(Option<u64>, T)>,
+    senders: usize,
+    receiver_dropped: bool,
+    metrics: QueueMetrics,
+}
+
+#[cfg(feature = "native")]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    policy: OverflowPolicy,
+    key: Option<KeyFn<T>>,
+    readable: Notify,
+    writable: Notify,
+}
+
+#[cfg(feature = "native")]
+impl<T> Shared<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<T>> {
+        self.state.lock().expect("pipeline queue lock poisoned")
+    }
+}
+
+// Single consumer queue, the sender can be cloned. Without a key function Conflate behaves
+// like DropOldest.
+#[cfg(feature = "native")]
+pub fn channel<T>(name: impl Into<String>, config: StageConfig) -> (Sender<T>, Receiver<T>) {
+    new_channel(name.into(), config, None)
+}
+
+// Items with the same key conflate, items without a key never do
+#[cfg(feature = "native")]
+pub fn conflating_channel<T>(
+    name: impl Into<String>,
+    config: StageConfig,
+    key: impl Fn(&T) -> Option<u64> + Send + Sync + 'static,
+) -> (Sender<T>, Receiver<T>) {
+    new_channel(name.into(), config, Some(Box::new(key)))
+}
+
+#[cfg(feature = "native")]
+fn new_channel<T>(
+    name: String,
+    config: StageConfig,
+    key: Option<KeyFn<T>>,
+) -> (Sender<T>, Receiver<T>) {
+    let capacity = config.capacity.max(1);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_dropped: false,
+            metrics: QueueMetrics {
+                name,
+                capacity,
+                ..QueueMetrics::default()
+            },
+        }),
+        policy: config.policy,
+        key,
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+#[cfg(feature = "native")]
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[cfg(feature = "native")]
+impl<T> Sender<T> {
+    // Returns the item when the receiver is gone. Only Block ever waits.
+    pub async fn send(&self, item: T) -> Result<(), T> {
+        let key = self.shared.key.as_ref().and_then(|key| key(&item));
+        let mut item = Some(item);
+        let mut waited = false;
+        loop {
+            let writable = self.shared.writable.notified();
+            tokio::pin!(writable);
+            {
+                let mut state = self.shared.lock();
+                let pending = item.take().expect("item is sent once");
+                match self.try_push(&mut state, key, pending) {
+                    Ok(()) => break,
+                    Err(Full(pending)) if !state.receiver_dropped => {
+                        if !waited {
+                            state.metrics.blocked += 1;
+                            waited = true;
+                        }
+                        item = Some(pending);
+                        writable.as_mut().enable();
+                    }
+                    Err(Full(pending)) => return Err(pending),
+                }
+            }
+            writable.await;
+        }
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+
+    // Same as `send` without waiting, a full Block queue returns the item
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        let key = self.shared.key.as_ref().and_then(|key| key(&item));
+        let pushed = {
+            let mut state = self.shared.lock();
+            if state.receiver_dropped {
+                return Err(item);
+            }
+            self.try_push(&mut state, key, item)
+        };
+        match pushed {
+            Ok(()) => {
+                self.shared.readable.notify_one();
+                Ok(())
+            }
+            Err(Full(item)) => Err(item),
+        }
+    }
+
+    fn try_push(&self, state: &mut State<T>, key: Option<u64>, item: T) -> Result<(), Full<T>> {
+        if state.receiver_dropped {
+            return Err(Full(item));
+        }
+        if self.shared.policy == OverflowPolicy::Conflate && key.is_some() {
+            if let Some(queued) = state.queue.iter_mut().find(|(queued, _)| *queued == key) {
+                queued.1 = item;
+                state.metrics.sent += 1;
+                state.metrics.conflated += 1;
+                return Ok(());
+            }
+        }
+        if state.queue.len() >= state.metrics.capacity {
+            match self.shared.policy {
+                OverflowPolicy::Block => return Err(Full(item)),
+                OverflowPolicy::DropOldest | OverflowPolicy::Conflate => {
+                    state.queue.pop_front();
+                    state.metrics.dropped += 1;
+                }
+            }
+        }
+        state.queue.push_back((key, item));
+        state.metrics.sent += 1;
+        state.metrics.depth = state.queue.len();
+        state.metrics.high_water_mark = state.metrics.high_water_mark.max(state.queue.len());
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.lock().metrics.clone()
+    }
+
+    // Reads the metrics without keeping the queue open
+    pub fn monitor(&self) -> QueueMonitor
+    where
+        T: Send + 'static,
+    {
+        QueueMonitor::new(&self.shared)
+    }
+}
+
+#[cfg(feature = "native")]
+struct Full<T>(T);
+
+#[cfg(feature = "native")]
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.readable.notify_waiters();
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+#[cfg(feature = "native")]
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[cfg(feature = "native")]
+impl<T> Receiver<T> {
+    // None once every sender is dropped and the queue is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let readable = self.shared.readable.notified();
+            tokio::pin!(readable);
+            {
+                let mut state = self.shared.lock();
+                if let Some(item) = Self::pop(&mut state) {
+                    drop(state);
+                    self.shared.writable.notify_one();
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+                readable.as_mut().enable();
+            }
+            readable.await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        let item = Self::pop(&mut self.shared.lock());
+        if item.is_some() {
+            self.shared.writable.notify_one();
+        }
+        item
+    }
+
+    fn pop(state: &mut State<T>) -> Option<T> {
+        let (_, item) = state.queue.pop_front()?;
+        state.metrics.received += 1;
+        state.metrics.depth = state.queue.len();
+        Some(item)
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.lock().metrics.clone()
+    }
+
+    pub fn monitor(&self) -> QueueMonitor
+    where
+        T: Send + 'static,
+    {
+        QueueMonitor::new(&self.shared)
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_dropped = true;
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(feature = "native")]
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+#[cfg(feature = "native")]
+trait Metered: Send + Sync {
+    fn metrics(&self) -> QueueMetrics;
+}
+
+#[cfg(feature = "native")]
+impl<T: Send> Metered for Shared<T> {
+    fn metrics(&self) -> QueueMetrics {
+        self.lock().metrics.clone()
+    }
+}
+
+// Metrics of a queue whatever its item type, so the stages report together
+#[cfg(feature = "native")]
+#[derive(Clone)]
+pub struct QueueMonitor {
+    shared: Weak<dyn Metered>,
+}
+
+#[cfg(feature = "native")]
+impl QueueMonitor {
+    fn new<T: Send + 'static>(shared: &Arc<Shared<T>>) -> QueueMonitor {
+        let shared: Arc<dyn Metered> = shared.clone();
+        QueueMonitor {
+            shared: Arc::downgrade(&shared),
+        }
+    }
+
+    // None once both ends of the queue are gone
+    pub fn metrics(&self) -> Option<QueueMetrics> {
+        Some(self.shared.upgrade()?.metrics())
+    }
+}
+
+#[cfg(feature = "native")]
+impl fmt::Debug for QueueMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueMonitor")
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn stage(capacity: usize, policy: OverflowPolicy) -> StageConfig {
+        StageConfig { capacity, policy }
+    }
+
+    fn drain<T>(receiver: &mut Receiver<T>) -> Vec<T> {
+        std::iter::from_fn(|| receiver.try_recv()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_and_conflate() {
+        let (sender, mut receiver) = channel("depth", stage(2, OverflowPolicy::DropOldest));
+        for item in 1..=4 {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(drain(&mut receiver), vec![3, 4]);
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.sent, metrics.dropped, metrics.depth), (4, 2, 0));
+        assert_eq!(metrics.high_water_mark, 2);
+
+        // Keyed by symbol, the item keeps the place of the one it replaces, a new symbol on a
+        // full queue drops the oldest one
+        let (sender, mut receiver) = conflating_channel(
+            "snapshots",
+            stage(2, OverflowPolicy::Conflate),
+            |(symbol, _): &(u64, u32)| Some(*symbol),
+        );
+        for item in [(1, 10), (2, 20), (1, 11), (1, 12), (3, 30)] {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(drain(&mut receiver), vec![(2, 20), (3, 30)]);
+        let metrics = sender.metrics();
+        assert_eq!((metrics.conflated, metrics.dropped), (2, 1));
+    }
+
+    #[test]
+    fn test_stream_key() {
+        let depth = |id: u64| {
+            format!(
+                r#"{{"stream":"ethusdc@depth5@100ms","data":{{"lastUpdateId":{},"bids":[],"asks":[]}}}}"#,
+                id
+            )
+        };
+        let key = stream_key(depth(1).as_bytes());
+        assert!(key.is_some());
+        assert_eq!(stream_key(depth(2).as_bytes()), key);
+        assert_ne!(
+            stream_key(br#"{"stream":"ethusdc@bookTicker","data":{}}"#),
+            key
+        );
+        assert_eq!(stream_key(br#"{"result":null,"id":1}"#), None);
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_room() {
+        let (sender, mut receiver) = channel("feed", stage(1, OverflowPolicy::Block));
+        sender.send(1).await.unwrap();
+        assert_eq!(sender.try_send(2), Err(2));
+
+        let producer = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send(2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!producer.is_finished());
+        assert_eq!(receiver.recv().await, Some(1));
+        producer.await.unwrap().unwrap();
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(sender.metrics().blocked, 1);
+
+        let monitor = receiver.monitor();
+        drop(sender);
+        assert_eq!(receiver.recv().await, None);
+        assert_eq!(monitor.metrics().unwrap().received, 2);
+        drop(receiver);
+        assert!(monitor.metrics().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_fails_without_receiver() {
+        let (sender, receiver) = channel("feed", stage(1, OverflowPolicy::Block));
+        sender.send(1).await.unwrap();
+        let blocked = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send(2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(receiver);
+        assert_eq!(blocked.await.unwrap(), Err(2));
+        assert_eq!(sender.send(3).await, Err(3));
+    }
+}
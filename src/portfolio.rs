@@ -0,0 +1,451 @@
+/// Position and PnL tracking.
+/// Positions consume executions from the matching engine or the fill simulator and keep
+/// net quantity, average entry price and realized PnL in integer units, per interned symbol.
+/// Unrealized PnL is marked to the last known mid price of the instrument.
+use crate::fill_simulator::SimulatedExecution;
+use crate::orderbook;
+use crate::orderbookv2::{OrderId, Side, Trade, TradeInfo};
+use crate::price_converter::{ConversionError, PriceConverter};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+// Engine trades are in ticks and lots, units without decimals
+fn engine_units() -> PriceConverter {
+    PriceConverter::new(0)
+}
+
+// `units` with `from` decimals in `to` decimals, dropped decimals round half away from zero
+fn rescale(units: i128, from: u32, to: u32) -> i128 {
+    if to >= from {
+        return units * 10i128.pow(to - from);
+    }
+    let divisor = 10i128.pow(from - to);
+    let (quotient, remainder) = (units / divisor, units % divisor);
+    if remainder.abs() * 2 >= divisor {
+        quotient + units.signum()
+    } else {
+        quotient
+    }
+}
+
+// Fills are kept in integer units of the position's converter: lots, and price times lots for
+// the cost and the realized PnL, so opening and closing never pick up floating point error.
+// Prices and quantities share the scale, as in the L2 book.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    converter: PriceConverter,
+    // Signed: positive for long, negative for short
+    lots: i64,
+    // Paid for the open lots, always positive
+    cost: i128,
+    realized_pnl: i128,
+    fees: f64,
+    mark_price: Option<f64>,
+}
+
+impl Position {
+    pub fn new(converter: PriceConverter) -> Position {
+        Position {
+            converter,
+            ..Position::default()
+        }
+    }
+
+    pub fn converter(&self) -> &PriceConverter {
+        &self.converter
+    }
+
+    // Price and quantity in the units of the position's converter
+    pub fn apply_fill(&mut self, side: Side, price: i64, quantity: u64) {
+        let quantity = i64::try_from(quantity).expect("Fill quantity overflow");
+        let signed_fill = match side {
+            Side::Buy => quantity,
+            Side::Sell => -quantity,
+        };
+        let notional = |lots: i64| i128::from(price) * i128::from(lots);
+
+        if self.lots == 0 || self.lots.signum() == signed_fill.signum() {
+            self.cost += notional(quantity);
+            self.lots += signed_fill;
+            return;
+        }
+
+        // Reducing (and possibly flipping) the position realizes PnL on the closed part. Its
+        // share of the cost rounds down, the remainder stays with the open lots, so closing
+        // the whole position realizes exactly what it made.
+        let open = self.lots.abs();
+        let closed = quantity.min(open);
+        let closed_cost = self.cost * i128::from(closed) / i128::from(open);
+        self.realized_pnl += (notional(closed) - closed_cost) * i128::from(self.lots.signum());
+        self.cost -= closed_cost;
+        self.lots += signed_fill;
+
+        if self.lots.signum() == signed_fill.signum() {
+            self.cost = notional(self.lots.abs());
+        }
+    }
+
+    pub fn apply_fee(&mut self, fee: f64) {
+        self.fees += fee;
+    }
+
+    pub fn mark(&mut self, price: f64) {
+        self.mark_price = Some(price);
+    }
+
+    // Signed quantity in the units of the converter
+    pub fn lots(&self) -> i64 {
+        self.lots
+    }
+
+    pub fn quantity(&self) -> f64 {
+        self.lots as f64 / self.converter.factor()
+    }
+
+    pub fn average_entry_price(&self) -> f64 {
+        if self.lots == 0 {
+            return 0.0;
+        }
+        self.cost as f64 / self.lots.abs() as f64 / self.converter.factor()
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.realized_pnl as f64 / (self.converter.factor() * self.converter.factor())
+    }
+
+    pub fn mark_price(&self) -> Option<f64> {
+        self.mark_price
+    }
+
+    // Zero until the position has been marked at least once
+    pub fn unrealized_pnl(&self) -> f64 {
+        match self.mark_price {
+            Some(mark) => (mark - self.average_entry_price()) * self.quantity(),
+            None => 0.0,
+        }
+    }
+
+    pub fn fees(&self) -> f64 {
+        self.fees
+    }
+
+    pub fn total_pnl(&self) -> f64 {
+        self.realized_pnl() + self.unrealized_pnl()
+    }
+
+    pub fn net_pnl(&self) -> f64 {
+        self.total_pnl() - self.fees
+    }
+
+    // Fills in units of another scale are converted to the one of the position
+    fn apply_units(&mut self, side: Side, price: i64, quantity: u64, units: &PriceConverter) {
+        let (from, to) = (units.scale(), self.converter.scale());
+        let price = rescale(i128::from(price), from, to);
+        let quantity = rescale(i128::from(quantity), from, to);
+        self.apply_fill(
+            side,
+            i64::try_from(price).expect("Fill price overflow"),
+            u64::try_from(quantity).expect("Fill quantity overflow"),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PortfolioSummary {
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub total_pnl: f64,
+    pub fees: f64,
+    pub net_pnl: f64,
+    // Sum of absolute position values at the mark price
+    pub gross_exposure: f64,
+}
+
+// A position takes the converter of the first fill of its symbol, later fills in other units
+// are converted to it
+#[derive(Debug, Default)]
+pub struct Portfolio {
+    positions: HashMap<Symbol, Position>,
+}
+
+impl Portfolio {
+    pub fn new() -> Portfolio {
+        Portfolio::default()
+    }
+
+    // Decimal price and quantity, a new position keeps them in the default book units
+    pub fn apply_fill(
+        &mut self,
+        symbol: Symbol,
+        side: Side,
+        price: f64,
+        quantity: f64,
+    ) -> Result<(), ConversionError> {
+        let position = self.position_mut(symbol, &PriceConverter::default());
+        let converter = position.converter;
+        let price =
+            i64::try_from(converter.to_units(price)?).map_err(|_| ConversionError::Overflow)?;
+        position.apply_fill(side, price, converter.to_units(quantity)?);
+        Ok(())
+    }
+
+    // Engine trades carry both counterparties, only the legs of our own orders are applied
+    pub fn apply_trade(&mut self, symbol: Symbol, trade: &Trade, is_own: impl Fn(OrderId) -> bool) {
+        if is_own(trade.bid_trade.order_id) {
+            self.apply_trade_leg(symbol, Side::Buy, &trade.bid_trade);
+        }
+        if is_own(trade.ask_trade.order_id) {
+            self.apply_trade_leg(symbol, Side::Sell, &trade.ask_trade);
+        }
+    }
+
+    fn apply_trade_leg(&mut self, symbol: Symbol, side: Side, leg: &TradeInfo) {
+        let units = engine_units();
+        let position = self.position_mut(symbol, &units);
+        position.apply_units(
+            side,
+            i64::from(leg.price.get()),
+            u64::from(leg.quantity),
+            &units,
+        );
+        position.apply_fee(leg.fee);
+    }
+
+    // Simulated executions are in the internal units of the replayed book, `converter` is the
+    // one of that book, see `FillSimulator::book`
+    pub fn apply_execution(
+        &mut self,
+        symbol: Symbol,
+        execution: &SimulatedExecution,
+        converter: &PriceConverter,
+    ) {
+        let price = i64::try_from(execution.price.get()).expect("Fill price overflow");
+        self.position_mut(symbol, converter).apply_units(
+            execution.side,
+            price,
+            execution.quantity.get(),
+            converter,
+        );
+    }
+
+    pub fn mark(&mut self, symbol: Symbol, price: f64) {
+        self.position_mut(symbol, &PriceConverter::default())
+            .mark(price);
+    }
+
+    // Marks the book's symbol to its current mid price, no-op when one side is empty
+    pub fn mark_to_book(&mut self, book: &orderbook::OrderBook) {
+        if let Some(mid) = book.mid_price() {
+            self.position_mut(book.symbol(), &book.converter())
+                .mark(mid);
+        }
+    }
+
+    pub fn position(&self, symbol: Symbol) -> Option<&Position> {
+        self.positions.get(&symbol)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = (Symbol, &Position)> {
+        self.positions
+            .iter()
+            .map(|(symbol, position)| (*symbol, position))
+    }
+
+    pub fn summary(&self) -> PortfolioSummary {
+        self.positions
+            .values()
+            .fold(PortfolioSummary::default(), |summary, position| {
+                let mark = position
+                    .mark_price
+                    .unwrap_or_else(|| position.average_entry_price());
+                PortfolioSummary {
+                    realized_pnl: summary.realized_pnl + position.realized_pnl(),
+                    unrealized_pnl: summary.unrealized_pnl + position.unrealized_pnl(),
+                    total_pnl: summary.total_pnl + position.total_pnl(),
+                    fees: summary.fees + position.fees(),
+                    net_pnl: summary.net_pnl + position.net_pnl(),
+                    gross_exposure: summary.gross_exposure + (position.quantity() * mark).abs(),
+                }
+            })
+    }
+
+    fn position_mut(&mut self, symbol: Symbol, converter: &PriceConverter) -> &mut Position {
+        self.positions
+            .entry(symbol)
+            .or_insert_with(|| Position::new(*converter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads;
+    use crate::orderbookv2::{Liquidity, Price, Quantity};
+
+    fn position() -> Position {
+        Position::new(PriceConverter::new(0))
+    }
+
+    #[test]
+    fn test_average_entry_price_on_increase() {
+        let mut position = position();
+        position.apply_fill(Side::Buy, 10, 2);
+        position.apply_fill(Side::Buy, 13, 1);
+
+        assert_eq!(position.lots(), 3);
+        assert_eq!(position.quantity(), 3.0);
+        assert_eq!(position.average_entry_price(), 11.0);
+        assert_eq!(position.realized_pnl(), 0.0);
+    }
+
+    #[test]
+    fn test_realized_pnl_on_reduce_and_close() {
+        let mut position = position();
+        position.apply_fill(Side::Buy, 10, 4);
+        position.apply_fill(Side::Sell, 12, 1);
+        assert_eq!(position.quantity(), 3.0);
+        assert_eq!(position.realized_pnl(), 2.0);
+        assert_eq!(position.average_entry_price(), 10.0);
+
+        position.apply_fill(Side::Sell, 9, 3);
+        assert_eq!(position.lots(), 0);
+        assert_eq!(position.realized_pnl(), -1.0);
+        assert_eq!(position.average_entry_price(), 0.0);
+    }
+
+    #[test]
+    fn test_position_flip_and_short_pnl() {
+        let mut position = position();
+        position.apply_fill(Side::Buy, 10, 1);
+        position.apply_fill(Side::Sell, 11, 3);
+        assert_eq!(position.lots(), -2);
+        assert_eq!(position.realized_pnl(), 1.0);
+        assert_eq!(position.average_entry_price(), 11.0);
+
+        position.mark(10.5);
+        assert_eq!(position.unrealized_pnl(), 1.0);
+        assert_eq!(position.total_pnl(), 2.0);
+    }
+
+    #[test]
+    fn test_closing_in_parts_realizes_exactly() {
+        // An average entry of 32 / 3 has no exact binary or decimal form
+        let mut position = position();
+        position.apply_fill(Side::Buy, 10, 1);
+        position.apply_fill(Side::Buy, 11, 2);
+        position.apply_fill(Side::Sell, 12, 1);
+        assert_eq!(position.realized_pnl(), 2.0);
+        position.apply_fill(Side::Sell, 12, 2);
+        assert_eq!(position.lots(), 0);
+        assert_eq!(position.realized_pnl(), 4.0);
+
+        // Decimal fills in book units, 0.1 + 0.2 is exact in lots
+        let mut portfolio = Portfolio::new();
+        let eth = Symbol::intern("ETHUSDC");
+        portfolio.apply_fill(eth, Side::Buy, 0.3, 0.1).unwrap();
+        portfolio.apply_fill(eth, Side::Buy, 0.3, 0.2).unwrap();
+        portfolio.apply_fill(eth, Side::Sell, 0.4, 0.3).unwrap();
+        let position = portfolio.position(eth).unwrap();
+        assert_eq!(position.lots(), 0);
+        assert_eq!(position.realized_pnl(), 0.03);
+        assert_eq!(
+            portfolio.apply_fill(eth, Side::Buy, -1.0, 1.0),
+            Err(ConversionError::Negative)
+        );
+    }
+
+    #[test]
+    fn test_fills_in_other_units_are_rescaled() {
+        let mut portfolio = Portfolio::new();
+        let sim = Symbol::intern("SIM");
+        portfolio.apply_fill(sim, Side::Buy, 10.5, 2.0).unwrap();
+        portfolio.apply_execution(
+            sim,
+            &SimulatedExecution {
+                order_id: 1,
+                side: Side::Sell,
+                price: orderbook::Price(1_150),
+                quantity: orderbook::Quantity(100),
+                liquidity: Liquidity::Maker,
+                source_id: 1,
+            },
+            &PriceConverter::new(2),
+        );
+        let position = portfolio.position(sim).unwrap();
+        assert_eq!(position.converter().scale(), 4);
+        assert_eq!(position.quantity(), 1.0);
+        assert_eq!(position.realized_pnl(), 1.0);
+    }
+
+    #[test]
+    fn test_apply_engine_trade_uses_own_legs_only() {
+        let mut portfolio = Portfolio::new();
+        let trade = Trade {
+            bid_trade: TradeInfo {
+                order_id: 1,
+                account_id: 0,
+                price: Price(100),
+                quantity: Quantity(5),
+                liquidity: Liquidity::Taker,
+                fee: 0.5,
+            },
+            ask_trade: TradeInfo {
+                order_id: 2,
+                account_id: 0,
+                price: Price(100),
+                quantity: Quantity(5),
+                liquidity: Liquidity::Maker,
+                fee: 0.25,
+            },
+            timestamp: 0,
+        };
+        let sim = Symbol::intern("SIM");
+        portfolio.apply_trade(sim, &trade, |order_id| order_id == 2);
+
+        let position = portfolio.position(sim).unwrap();
+        assert_eq!(position.quantity(), -5.0);
+        assert_eq!(position.average_entry_price(), 100.0);
+        assert_eq!(position.fees(), 0.25);
+        assert_eq!(position.net_pnl(), -0.25);
+    }
+
+    #[test]
+    fn test_mark_to_book_and_summary() {
+        let mut portfolio = Portfolio::new();
+        let (bnb, eth) = (Symbol::intern("BNBUSDT"), Symbol::intern("ETHUSDC"));
+        portfolio.apply_execution(
+            bnb,
+            &SimulatedExecution {
+                order_id: 1,
+                side: Side::Buy,
+                price: orderbook::Price(100_000),
+                quantity: orderbook::Quantity(20_000),
+                liquidity: Liquidity::Taker,
+                source_id: 1,
+            },
+            &PriceConverter::default(),
+        );
+        portfolio.apply_fill(eth, Side::Sell, 2000.0, 0.5).unwrap();
+        portfolio.apply_fill(eth, Side::Buy, 1900.0, 0.25).unwrap();
+
+        let mut book = orderbook::OrderBook::new("BNBUSDT".to_string());
+        book.update_depth(&binance_payloads::DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(11.0, 1.0)],
+            asks: vec![(12.0, 1.0)],
+        });
+        portfolio.mark_to_book(&book);
+
+        let position = portfolio.position(bnb).unwrap();
+        assert_eq!(position.mark_price(), Some(11.5));
+        assert_eq!(position.unrealized_pnl(), 3.0);
+
+        let summary = portfolio.summary();
+        assert_eq!(summary.realized_pnl, 25.0);
+        assert_eq!(summary.unrealized_pnl, 3.0);
+        assert_eq!(summary.total_pnl, 28.0);
+        assert_eq!(summary.gross_exposure, 23.0 + 500.0);
+        assert_eq!(portfolio.positions().count(), 2);
+    }
+}
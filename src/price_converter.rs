@@ -0,0 +1,243 @@
+/// Conversion of decimal prices and quantities to the integer units stored in the L2 book.
+/// A `PriceConverter` scales by `10^scale` and rounds the digits past the scale with the
+/// selected `RoundingMode`. Decimal strings (as sent by Binance) are converted digit by digit,
+/// so they never pick up binary floating point error. `f64` inputs are scaled in floating
+/// point, a product within a few ulps of an integer or of a tie is treated as exact so that
+/// e.g. 25.3519 floors to 253519 and not 253518. Values that are negative, not finite or do
+/// not fit a `u64` are rejected instead of saturating.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Largest scale whose factor fits a u64
+pub const MAX_SCALE: u32 = 19;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundingMode {
+    // Ties go away from zero, same as `f64::round`
+    #[default]
+    HalfAwayFromZero,
+    // Ties go to the even unit (banker's rounding)
+    HalfEven,
+    Floor,
+    Ceil,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionError {
+    Negative,
+    NotFinite,
+    Overflow,
+    Malformed,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::Negative => write!(f, "negative value"),
+            ConversionError::NotFinite => write!(f, "value is not finite"),
+            ConversionError::Overflow => write!(f, "value does not fit the book units"),
+            ConversionError::Malformed => write!(f, "malformed decimal"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceConverter {
+    scale: u32,
+    rounding: RoundingMode,
+}
+
+// Four decimals rounded half away from zero, the historical book units
+impl Default for PriceConverter {
+    fn default() -> Self {
+        PriceConverter::new(4)
+    }
+}
+
+impl PriceConverter {
+    pub fn new(scale: u32) -> PriceConverter {
+        assert!(scale <= MAX_SCALE, "scale {} is above {}", scale, MAX_SCALE);
+        PriceConverter {
+            scale,
+            rounding: RoundingMode::default(),
+        }
+    }
+
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> PriceConverter {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn rounding(&self) -> RoundingMode {
+        self.rounding
+    }
+
+    pub fn factor(&self) -> f64 {
+        10u64.pow(self.scale) as f64
+    }
+
+    pub fn to_units(&self, value: f64) -> Result<u64, ConversionError> {
+        if !value.is_finite() {
+            return Err(ConversionError::NotFinite);
+        }
+        if value < 0.0 {
+            return Err(ConversionError::Negative);
+        }
+
+        let scaled = value * self.factor();
+        let tolerance = scaled * f64::EPSILON * 4.0;
+        let nearest = scaled.round();
+        let units = if (scaled - nearest).abs() <= tolerance {
+            nearest
+        } else {
+            let floor = scaled.floor();
+            let is_tie = (scaled - floor - 0.5).abs() <= tolerance;
+            match self.rounding {
+                RoundingMode::Floor => floor,
+                RoundingMode::Ceil => floor + 1.0,
+                RoundingMode::HalfAwayFromZero if is_tie => floor + 1.0,
+                RoundingMode::HalfEven if is_tie => floor + floor % 2.0,
+                RoundingMode::HalfAwayFromZero | RoundingMode::HalfEven => nearest,
+            }
+        };
+
+        // u64::MAX as f64 rounds up to 2^64, which is already out of range
+        if units >= u64::MAX as f64 {
+            return Err(ConversionError::Overflow);
+        }
+        Ok(units as u64)
+    }
+
+    // Exact conversion of a plain decimal string such as "25.35190000"
+    pub fn parse(&self, decimal: &str) -> Result<u64, ConversionError> {
+        if decimal.starts_with('-') {
+            return Err(ConversionError::Negative);
+        }
+        let (integer, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+        let is_digits = |digits: &str| digits.bytes().all(|digit| digit.is_ascii_digit());
+        if (integer.is_empty() && fraction.is_empty())
+            || !is_digits(integer)
+            || !is_digits(fraction)
+        {
+            return Err(ConversionError::Malformed);
+        }
+
+        let (kept, dropped) = fraction.split_at(fraction.len().min(self.scale as usize));
+        let mut units: u64 = 0;
+        for digit in integer.bytes().chain(kept.bytes()) {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add((digit - b'0') as u64))
+                .ok_or(ConversionError::Overflow)?;
+        }
+        for _ in kept.len()..self.scale as usize {
+            units = units.checked_mul(10).ok_or(ConversionError::Overflow)?;
+        }
+
+        let mut dropped = dropped.bytes();
+        let round_up = match (self.rounding, dropped.next()) {
+            (_, None) | (RoundingMode::Floor, _) => false,
+            (RoundingMode::Ceil, Some(first)) => first != b'0' || dropped.any(|d| d != b'0'),
+            (RoundingMode::HalfAwayFromZero, Some(first)) => first >= b'5',
+            (RoundingMode::HalfEven, Some(first)) => {
+                first > b'5' || (first == b'5' && (dropped.any(|d| d != b'0') || units % 2 == 1))
+            }
+        };
+        if round_up {
+            units = units.checked_add(1).ok_or(ConversionError::Overflow)?;
+        }
+        Ok(units)
+    }
+
+    // Takes the book's `Price` and `Quantity` as well as raw units
+    pub fn to_f64(&self, units: impl Into<u64>) -> f64 {
+        units.into() as f64 / self.factor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding_modes() {
+        let converter = PriceConverter::new(2);
+        let floor = converter.with_rounding(RoundingMode::Floor);
+        let ceil = converter.with_rounding(RoundingMode::Ceil);
+        let half_even = converter.with_rounding(RoundingMode::HalfEven);
+
+        assert_eq!(converter.to_units(0.125), Ok(13));
+        assert_eq!(half_even.to_units(0.125), Ok(12));
+        assert_eq!(half_even.to_units(0.135), Ok(14));
+        assert_eq!(floor.to_units(0.129), Ok(12));
+        assert_eq!(ceil.to_units(0.121), Ok(13));
+
+        assert_eq!(converter.parse("0.125"), Ok(13));
+        assert_eq!(half_even.parse("0.125"), Ok(12));
+        assert_eq!(half_even.parse("0.1251"), Ok(13));
+        assert_eq!(half_even.parse("0.135"), Ok(14));
+        assert_eq!(floor.parse("0.129"), Ok(12));
+        assert_eq!(ceil.parse("0.1201"), Ok(13));
+        assert_eq!(ceil.parse("0.1200"), Ok(12));
+    }
+
+    #[test]
+    fn test_exact_products_are_not_rounded_away() {
+        // 25.3519 * 10000.0 is 253518.99999999997 in floating point
+        let floor = PriceConverter::new(4).with_rounding(RoundingMode::Floor);
+        assert_eq!(floor.to_units(25.3519), Ok(253519));
+        let ceil = PriceConverter::new(4).with_rounding(RoundingMode::Ceil);
+        assert_eq!(ceil.to_units(0.0003), Ok(3));
+    }
+
+    #[test]
+    fn test_parse_binance_decimals() {
+        let converter = PriceConverter::default();
+        assert_eq!(converter.parse("25.35190000"), Ok(253519));
+        assert_eq!(converter.parse("40.66"), Ok(406600));
+        assert_eq!(converter.parse("7"), Ok(70000));
+        assert_eq!(converter.parse(".5"), Ok(5000));
+        assert_eq!(converter.parse("0.00000000"), Ok(0));
+        assert_eq!(PriceConverter::new(8).parse("0.00000001"), Ok(1));
+        assert_eq!(converter.to_f64(253519u64), 25.3519);
+    }
+
+    #[test]
+    fn test_invalid_values_are_errors() {
+        let converter = PriceConverter::default();
+        assert_eq!(converter.to_units(-1.0), Err(ConversionError::Negative));
+        assert_eq!(
+            converter.to_units(f64::NAN),
+            Err(ConversionError::NotFinite)
+        );
+        assert_eq!(
+            converter.to_units(f64::INFINITY),
+            Err(ConversionError::NotFinite)
+        );
+        assert_eq!(converter.to_units(1e16), Err(ConversionError::Overflow));
+
+        assert_eq!(converter.parse("-1.0"), Err(ConversionError::Negative));
+        assert_eq!(converter.parse("n/a"), Err(ConversionError::Malformed));
+        assert_eq!(converter.parse("1.2.3"), Err(ConversionError::Malformed));
+        assert_eq!(converter.parse(""), Err(ConversionError::Malformed));
+        assert_eq!(converter.parse("1e5"), Err(ConversionError::Malformed));
+        assert_eq!(
+            converter.parse("18446744073709551615"),
+            Err(ConversionError::Overflow)
+        );
+        assert_eq!(
+            PriceConverter::new(0).parse("18446744073709551615"),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            PriceConverter::new(0).parse("18446744073709551615.9"),
+            Err(ConversionError::Overflow)
+        );
+    }
+}
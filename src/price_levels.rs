@@ -0,0 +1,1075 @@
+/// Price level storage shared by the L2 book and the matching engine.
+/// `LevelStore` is the interface both books use to keep one side of the book, a value per price
+/// in ascending price order. Three implementations cover different performance profiles:
+///
+///   `BTreeLevels`     `BTreeMap` backed, suits any price range, the default
+///   `DenseLevels`     contiguous array indexed by tick offset from the lowest live price,
+///                     trimmed to the live range after every removal so the window follows the
+///                     price as it drifts. Lookups are index computations and the best level
+///                     sits at one end of the array, which pays off for hot symbols quoted over
+///                     a narrow range of ticks. Memory grows with the distance between the
+///                     lowest and the highest level, so it is a poor fit for sparse books.
+///                     Prices off the tick grid or beyond `MAX_DENSE_SPAN` ticks from the
+///                     other levels are refused, and a `PriceLevels` falls back to a
+///                     `BTreeLevels` holding the same levels when that happens.
+///   `SkipListLevels`  arena backed skip list, cheap inserts in the middle of deep books
+///
+/// The books pick the implementation at runtime from a `BookBackend`.
+use crate::book_core;
+#[cfg(feature = "std")]
+use crate::orderbook;
+use alloc::collections::{btree_map, vec_deque, BTreeMap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Enumerate;
+use core::marker::PhantomData;
+use core::mem;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BookBackend {
+    #[default]
+    BTree,
+    // Every price has to be a multiple of `tick_size`, in the book price units
+    Dense {
+        tick_size: u64,
+    },
+    SkipList,
+}
+
+// Widest window a `DenseLevels` keeps, in ticks from its lowest to its highest level
+pub const MAX_DENSE_SPAN: usize = 1 << 16;
+
+// Prices a `DenseLevels` cannot keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenseError {
+    OffGrid { price: i64, tick_size: u64 },
+    SpanExceeded { price: i64, max_span: usize },
+}
+
+impl fmt::Display for DenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DenseError::OffGrid { price, tick_size } => {
+                write!(
+                    f,
+                    "price {} is not a multiple of the tick size {}",
+                    price, tick_size
+                )
+            }
+            DenseError::SpanExceeded { price, max_span } => write!(
+                f,
+                "price {} would widen the dense window beyond {} ticks",
+                price, max_span
+            ),
+        }
+    }
+}
+
+// Prices the stores can be keyed by, the dense store does its tick arithmetic in i64
+pub trait LevelPrice: Copy + Ord + fmt::Debug {
+    fn to_i64(self) -> i64;
+    fn from_i64(value: i64) -> Self;
+}
+
+impl LevelPrice for u64 {
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+
+    fn from_i64(value: i64) -> Self {
+        value as u64
+    }
+}
+
+impl LevelPrice for i32 {
+    fn to_i64(self) -> i64 {
+        self as i64
+    }
+
+    fn from_i64(value: i64) -> Self {
+        value as i32
+    }
+}
+
+#[cfg(feature = "std")]
+impl LevelPrice for orderbook::Price {
+    fn to_i64(self) -> i64 {
+        self.0 as i64
+    }
+
+    fn from_i64(value: i64) -> Self {
+        orderbook::Price(value as u64)
+    }
+}
+
+impl LevelPrice for book_core::Price {
+    fn to_i64(self) -> i64 {
+        self.0 as i64
+    }
+
+    fn from_i64(value: i64) -> Self {
+        book_core::Price(value as i32)
+    }
+}
+
+// Groups best-first levels into buckets of `bucket_size` price units and sums their quantities,
+// like the "group by" of exchange depth views. Bid buckets are labelled with their lowest price
+// and ask buckets with their highest (`round_up`), so a grouped price is never better than the
+// levels it contains. Quantities are summed in u64, saturating.
+pub fn aggregate_levels<P: LevelPrice>(
+    levels: impl Iterator<Item = (P, u64)>,
+    bucket_size: i64,
+    round_up: bool,
+) -> Vec<(P, u64)> {
+    assert!(bucket_size > 0, "bucket size must be positive");
+    let mut buckets: Vec<(P, u64)> = Vec::new();
+    for (price, quantity) in levels {
+        let price = price.to_i64();
+        let offset = price.rem_euclid(bucket_size);
+        let bucket = if round_up && offset != 0 {
+            price - offset + bucket_size
+        } else {
+            price - offset
+        };
+        let bucket = P::from_i64(bucket);
+        match buckets.last_mut() {
+            Some((last, total)) if *last == bucket => *total = total.saturating_add(quantity),
+            _ => buckets.push((bucket, quantity)),
+        }
+    }
+    buckets
+}
+
+pub trait LevelStore<P: LevelPrice, V> {
+    // Lowest price first
+    type Iter<'a>: DoubleEndedIterator<Item = (P, &'a V)>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn get(&self, price: P) -> Option<&V>;
+    fn get_mut(&mut self, price: P) -> Option<&mut V>;
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, price: P, default: F) -> &mut V;
+    fn insert(&mut self, price: P, value: V) -> Option<V>;
+    fn remove(&mut self, price: P) -> Option<V>;
+    fn clear(&mut self);
+    fn len(&self) -> usize;
+    fn iter(&self) -> Self::Iter<'_>;
+    // Lowest and highest price level
+    fn first_mut(&mut self) -> Option<(P, &mut V)>;
+    fn last_mut(&mut self) -> Option<(P, &mut V)>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn first(&self) -> Option<(P, &V)> {
+        self.iter().next()
+    }
+
+    fn last(&self) -> Option<(P, &V)> {
+        self.iter().next_back()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BTreeLevels<P, V> {
+    levels: BTreeMap<P, V>,
+}
+
+impl<P, V> Default for BTreeLevels<P, V> {
+    fn default() -> Self {
+        BTreeLevels {
+            levels: BTreeMap::new(),
+        }
+    }
+}
+
+impl<P, V> BTreeLevels<P, V> {
+    pub fn new() -> BTreeLevels<P, V> {
+        BTreeLevels::default()
+    }
+}
+
+type BTreeIter<'a, P, V> =
+    core::iter::Map<btree_map::Iter<'a, P, V>, fn((&'a P, &'a V)) -> (P, &'a V)>;
+
+impl<P: LevelPrice, V> LevelStore<P, V> for BTreeLevels<P, V> {
+    type Iter<'a> = BTreeIter<'a, P, V> where Self: 'a, V: 'a;
+
+    fn get(&self, price: P) -> Option<&V> {
+        self.levels.get(&price)
+    }
+
+    fn get_mut(&mut self, price: P) -> Option<&mut V> {
+        self.levels.get_mut(&price)
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, price: P, default: F) -> &mut V {
+        self.levels.entry(price).or_insert_with(default)
+    }
+
+    fn insert(&mut self, price: P, value: V) -> Option<V> {
+        self.levels.insert(price, value)
+    }
+
+    fn remove(&mut self, price: P) -> Option<V> {
+        self.levels.remove(&price)
+    }
+
+    fn clear(&mut self) {
+        self.levels.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.levels.iter().map(|(price, value)| (*price, value))
+    }
+
+    fn first_mut(&mut self) -> Option<(P, &mut V)> {
+        self.levels
+            .iter_mut()
+            .next()
+            .map(|(price, value)| (*price, value))
+    }
+
+    fn last_mut(&mut self) -> Option<(P, &mut V)> {
+        self.levels
+            .iter_mut()
+            .next_back()
+            .map(|(price, value)| (*price, value))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DenseLevels<P, V> {
+    tick_size: i64,
+    // Price of the first slot
+    base: i64,
+    // The first and the last slot are never empty
+    slots: VecDeque<Option<V>>,
+    len: usize,
+    max_span: usize,
+    price: PhantomData<P>,
+}
+
+impl<P: LevelPrice, V> DenseLevels<P, V> {
+    pub fn new(tick_size: u64) -> DenseLevels<P, V> {
+        assert!(tick_size > 0, "tick size has to be positive");
+        DenseLevels {
+            tick_size: tick_size as i64,
+            base: 0,
+            slots: VecDeque::new(),
+            len: 0,
+            max_span: MAX_DENSE_SPAN,
+            price: PhantomData,
+        }
+    }
+
+    pub fn with_max_span(mut self, max_span: usize) -> DenseLevels<P, V> {
+        self.max_span = max_span;
+        self
+    }
+
+    pub fn tick_size(&self) -> u64 {
+        self.tick_size as u64
+    }
+
+    // Whether the price can be added to the window, see `try_insert`
+    pub fn check(&self, price: P) -> Result<(), DenseError> {
+        let price = price.to_i64();
+        if price.rem_euclid(self.tick_size) != 0 {
+            return Err(DenseError::OffGrid {
+                price,
+                tick_size: self.tick_size as u64,
+            });
+        }
+        if self.slots.is_empty() {
+            return Ok(());
+        }
+        let last = self.base + (self.slots.len() as i64 - 1) * self.tick_size;
+        let span =
+            (price.max(last) as i128 - price.min(self.base) as i128) / self.tick_size as i128 + 1;
+        if span > self.max_span as i128 {
+            return Err(DenseError::SpanExceeded {
+                price,
+                max_span: self.max_span,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn try_insert(&mut self, price: P, value: V) -> Result<Option<V>, DenseError> {
+        self.check(price)?;
+        let index = self.slot(price);
+        let previous = self.slots[index].replace(value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        Ok(previous)
+    }
+
+    pub fn try_get_or_insert_with<F: FnOnce() -> V>(
+        &mut self,
+        price: P,
+        default: F,
+    ) -> Result<&mut V, DenseError> {
+        self.check(price)?;
+        let index = self.slot(price);
+        if self.slots[index].is_none() {
+            self.len += 1;
+        }
+        Ok(self.slots[index].get_or_insert_with(default))
+    }
+
+    // Off-grid prices have no slot
+    fn index(&self, price: P) -> Option<usize> {
+        let offset = price.to_i64() - self.base;
+        if offset < 0 || offset % self.tick_size != 0 {
+            return None;
+        }
+        let index = (offset / self.tick_size) as usize;
+        (index < self.slots.len()).then_some(index)
+    }
+
+    // Grows the window to cover a price that passed `check`, rebasing it when the price is
+    // below it
+    fn slot(&mut self, price: P) -> usize {
+        let price = price.to_i64();
+
+        if self.slots.is_empty() {
+            self.base = price;
+        } else if price < self.base {
+            for _ in 0..(self.base - price) / self.tick_size {
+                self.slots.push_front(None);
+            }
+            self.base = price;
+        }
+        let index = ((price - self.base) / self.tick_size) as usize;
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        index
+    }
+
+    fn trim(&mut self) {
+        while matches!(self.slots.front(), Some(None)) {
+            self.slots.pop_front();
+            self.base += self.tick_size;
+        }
+        while matches!(self.slots.back(), Some(None)) {
+            self.slots.pop_back();
+        }
+    }
+
+    fn price_at(&self, index: usize) -> P {
+        P::from_i64(self.base + index as i64 * self.tick_size)
+    }
+
+    fn into_levels(self) -> impl Iterator<Item = (P, V)> {
+        let (base, tick_size) = (self.base, self.tick_size);
+        self.slots
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(index, slot)| {
+                Some((P::from_i64(base + index as i64 * tick_size), slot?))
+            })
+    }
+}
+
+pub struct DenseIter<'a, P, V> {
+    base: i64,
+    tick_size: i64,
+    slots: Enumerate<vec_deque::Iter<'a, Option<V>>>,
+    price: PhantomData<P>,
+}
+
+impl<'a, P: LevelPrice, V> Iterator for DenseIter<'a, P, V> {
+    type Item = (P, &'a V);
+
+    fn next(&mut self) -> Option<(P, &'a V)> {
+        let (base, tick_size) = (self.base, self.tick_size);
+        self.slots.find_map(|(index, slot)| {
+            let value = slot.as_ref()?;
+            Some((P::from_i64(base + index as i64 * tick_size), value))
+        })
+    }
+}
+
+impl<'a, P: LevelPrice, V> DoubleEndedIterator for DenseIter<'a, P, V> {
+    fn next_back(&mut self) -> Option<(P, &'a V)> {
+        let (base, tick_size) = (self.base, self.tick_size);
+        self.slots.by_ref().rev().find_map(|(index, slot)| {
+            let value = slot.as_ref()?;
+            Some((P::from_i64(base + index as i64 * tick_size), value))
+        })
+    }
+}
+
+impl<P: LevelPrice, V> LevelStore<P, V> for DenseLevels<P, V> {
+    type Iter<'a> = DenseIter<'a, P, V> where Self: 'a, V: 'a;
+
+    fn get(&self, price: P) -> Option<&V> {
+        self.index(price)
+            .and_then(|index| self.slots[index].as_ref())
+    }
+
+    fn get_mut(&mut self, price: P) -> Option<&mut V> {
+        self.index(price)
+            .and_then(|index| self.slots[index].as_mut())
+    }
+
+    // Panics on a price the window cannot keep, `try_get_or_insert_with` reports it instead
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, price: P, default: F) -> &mut V {
+        self.try_get_or_insert_with(price, default)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    // Panics on a price the window cannot keep, `try_insert` reports it instead
+    fn insert(&mut self, price: P, value: V) -> Option<V> {
+        self.try_insert(price, value)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn remove(&mut self, price: P) -> Option<V> {
+        let index = self.index(price)?;
+        let value = self.slots[index].take()?;
+        self.len -= 1;
+        self.trim();
+        Some(value)
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.len = 0;
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        DenseIter {
+            base: self.base,
+            tick_size: self.tick_size,
+            slots: self.slots.iter().enumerate(),
+            price: PhantomData,
+        }
+    }
+
+    fn first_mut(&mut self) -> Option<(P, &mut V)> {
+        let price = self.price_at(0);
+        let value = self.slots.front_mut()?.as_mut()?;
+        Some((price, value))
+    }
+
+    fn last_mut(&mut self) -> Option<(P, &mut V)> {
+        let price = self.price_at(self.slots.len().checked_sub(1)?);
+        let value = self.slots.back_mut()?.as_mut()?;
+        Some((price, value))
+    }
+}
+
+const MAX_HEIGHT: usize = 16;
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct SkipNode<P, V> {
+    price: P,
+    // None once the node is on the free list
+    value: Option<V>,
+    // Forward links, one per level of the node
+    next: Vec<usize>,
+    prev: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SkipListLevels<P, V> {
+    nodes: Vec<SkipNode<P, V>>,
+    free: Vec<usize>,
+    head: [usize; MAX_HEIGHT],
+    tail: usize,
+    height: usize,
+    len: usize,
+    // xorshift state for the node heights, fixed seed keeps the layout reproducible
+    rng: u64,
+}
+
+impl<P, V> Default for SkipListLevels<P, V> {
+    fn default() -> Self {
+        SkipListLevels {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: [NIL; MAX_HEIGHT],
+            tail: NIL,
+            height: 1,
+            len: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+impl<P: LevelPrice, V> SkipListLevels<P, V> {
+    pub fn new() -> SkipListLevels<P, V> {
+        SkipListLevels::default()
+    }
+
+    fn next(&self, node: usize, level: usize) -> usize {
+        if node == NIL {
+            self.head[level]
+        } else {
+            self.nodes[node].next[level]
+        }
+    }
+
+    fn set_next(&mut self, node: usize, level: usize, next: usize) {
+        if node == NIL {
+            self.head[level] = next;
+        } else {
+            self.nodes[node].next[level] = next;
+        }
+    }
+
+    // Last node before the price on every level, NIL standing for the head
+    fn predecessors(&self, price: P) -> [usize; MAX_HEIGHT] {
+        let mut predecessors = [NIL; MAX_HEIGHT];
+        let mut node = NIL;
+        for level in (0..self.height).rev() {
+            loop {
+                let next = self.next(node, level);
+                if next == NIL || self.nodes[next].price >= price {
+                    break;
+                }
+                node = next;
+            }
+            predecessors[level] = node;
+        }
+        predecessors
+    }
+
+    fn find(&self, price: P) -> Option<usize> {
+        let node = self.next(self.predecessors(price)[0], 0);
+        (node != NIL && self.nodes[node].price == price).then_some(node)
+    }
+
+    fn random_height(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng.trailing_ones() as usize + 1).min(MAX_HEIGHT)
+    }
+
+    fn link(&mut self, price: P, value: V) -> usize {
+        let predecessors = self.predecessors(price);
+        let height = self.random_height();
+        self.height = self.height.max(height);
+
+        let node = SkipNode {
+            price,
+            value: Some(value),
+            next: vec![NIL; height],
+            prev: predecessors[0],
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for (level, &predecessor) in predecessors.iter().enumerate().take(height) {
+            let next = self.next(predecessor, level);
+            self.nodes[index].next[level] = next;
+            self.set_next(predecessor, level, index);
+        }
+        match self.nodes[index].next[0] {
+            NIL => self.tail = index,
+            next => self.nodes[next].prev = index,
+        }
+        self.len += 1;
+        index
+    }
+}
+
+pub struct SkipListIter<'a, P, V> {
+    nodes: &'a [SkipNode<P, V>],
+    front: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a, P: LevelPrice, V> Iterator for SkipListIter<'a, P, V> {
+    type Item = (P, &'a V);
+
+    fn next(&mut self) -> Option<(P, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.front];
+        self.front = node.next[0];
+        self.remaining -= 1;
+        Some((node.price, node.value.as_ref()?))
+    }
+}
+
+impl<'a, P: LevelPrice, V> DoubleEndedIterator for SkipListIter<'a, P, V> {
+    fn next_back(&mut self) -> Option<(P, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.back];
+        self.back = node.prev;
+        self.remaining -= 1;
+        Some((node.price, node.value.as_ref()?))
+    }
+}
+
+impl<P: LevelPrice, V> LevelStore<P, V> for SkipListLevels<P, V> {
+    type Iter<'a> = SkipListIter<'a, P, V> where Self: 'a, V: 'a;
+
+    fn get(&self, price: P) -> Option<&V> {
+        self.nodes[self.find(price)?].value.as_ref()
+    }
+
+    fn get_mut(&mut self, price: P) -> Option<&mut V> {
+        let node = self.find(price)?;
+        self.nodes[node].value.as_mut()
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, price: P, default: F) -> &mut V {
+        let node = match self.find(price) {
+            Some(node) => node,
+            None => self.link(price, default()),
+        };
+        self.nodes[node].value.as_mut().unwrap()
+    }
+
+    fn insert(&mut self, price: P, value: V) -> Option<V> {
+        match self.find(price) {
+            Some(node) => self.nodes[node].value.replace(value),
+            None => {
+                self.link(price, value);
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, price: P) -> Option<V> {
+        let predecessors = self.predecessors(price);
+        let node = self.next(predecessors[0], 0);
+        if node == NIL || self.nodes[node].price != price {
+            return None;
+        }
+
+        for (level, &predecessor) in predecessors.iter().enumerate().take(self.height) {
+            if self.next(predecessor, level) == node {
+                let next = self.nodes[node].next[level];
+                self.set_next(predecessor, level, next);
+            }
+        }
+        match self.nodes[node].next[0] {
+            NIL => self.tail = predecessors[0],
+            next => self.nodes[next].prev = predecessors[0],
+        }
+        while self.height > 1 && self.head[self.height - 1] == NIL {
+            self.height -= 1;
+        }
+
+        self.free.push(node);
+        self.len -= 1;
+        self.nodes[node].value.take()
+    }
+
+    fn clear(&mut self) {
+        *self = SkipListLevels {
+            rng: self.rng,
+            ..SkipListLevels::default()
+        };
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        SkipListIter {
+            nodes: &self.nodes,
+            front: self.head[0],
+            back: self.tail,
+            remaining: self.len,
+        }
+    }
+
+    fn first_mut(&mut self) -> Option<(P, &mut V)> {
+        let node = self.nodes.get_mut(self.head[0])?;
+        Some((node.price, node.value.as_mut()?))
+    }
+
+    fn last_mut(&mut self) -> Option<(P, &mut V)> {
+        let node = self.nodes.get_mut(self.tail)?;
+        Some((node.price, node.value.as_mut()?))
+    }
+}
+
+// Store picked at runtime from a `BookBackend`, what the books hold their sides in
+#[derive(Debug, Clone)]
+pub enum PriceLevels<P, V> {
+    BTree(BTreeLevels<P, V>),
+    Dense(DenseLevels<P, V>),
+    SkipList(SkipListLevels<P, V>),
+}
+
+impl<P: LevelPrice, V> PriceLevels<P, V> {
+    pub fn new(backend: BookBackend) -> PriceLevels<P, V> {
+        match backend {
+            BookBackend::BTree => PriceLevels::BTree(BTreeLevels::new()),
+            BookBackend::Dense { tick_size } => PriceLevels::Dense(DenseLevels::new(tick_size)),
+            BookBackend::SkipList => PriceLevels::SkipList(SkipListLevels::new()),
+        }
+    }
+
+    pub fn backend(&self) -> BookBackend {
+        match self {
+            PriceLevels::BTree(_) => BookBackend::BTree,
+            PriceLevels::Dense(levels) => BookBackend::Dense {
+                tick_size: levels.tick_size(),
+            },
+            PriceLevels::SkipList(_) => BookBackend::SkipList,
+        }
+    }
+
+    // Moves the levels of a dense store that cannot take the price into a `BTreeLevels`
+    fn fit(&mut self, price: P) {
+        let PriceLevels::Dense(dense) = self else {
+            return;
+        };
+        let Err(error) = dense.check(price) else {
+            return;
+        };
+        log::warn!("Dense price levels fall back to a BTree: {}", error);
+        if let PriceLevels::Dense(dense) =
+            mem::replace(self, PriceLevels::BTree(BTreeLevels::new()))
+        {
+            *self = PriceLevels::BTree(BTreeLevels {
+                levels: dense.into_levels().collect(),
+            });
+        }
+    }
+}
+
+pub enum PriceLevelsIter<'a, P: LevelPrice, V> {
+    BTree(BTreeIter<'a, P, V>),
+    Dense(DenseIter<'a, P, V>),
+    SkipList(SkipListIter<'a, P, V>),
+}
+
+impl<'a, P: LevelPrice, V> Iterator for PriceLevelsIter<'a, P, V> {
+    type Item = (P, &'a V);
+
+    fn next(&mut self) -> Option<(P, &'a V)> {
+        match self {
+            PriceLevelsIter::BTree(iter) => iter.next(),
+            PriceLevelsIter::Dense(iter) => iter.next(),
+            PriceLevelsIter::SkipList(iter) => iter.next(),
+        }
+    }
+}
+
+impl<'a, P: LevelPrice, V> DoubleEndedIterator for PriceLevelsIter<'a, P, V> {
+    fn next_back(&mut self) -> Option<(P, &'a V)> {
+        match self {
+            PriceLevelsIter::BTree(iter) => iter.next_back(),
+            PriceLevelsIter::Dense(iter) => iter.next_back(),
+            PriceLevelsIter::SkipList(iter) => iter.next_back(),
+        }
+    }
+}
+
+// Forwards every call to the selected store
+macro_rules! dispatch {
+    ($levels:expr, $store:ident => $call:expr) => {
+        match $levels {
+            PriceLevels::BTree($store) => $call,
+            PriceLevels::Dense($store) => $call,
+            PriceLevels::SkipList($store) => $call,
+        }
+    };
+}
+
+impl<P: LevelPrice, V> LevelStore<P, V> for PriceLevels<P, V> {
+    type Iter<'a> = PriceLevelsIter<'a, P, V> where Self: 'a, V: 'a;
+
+    fn get(&self, price: P) -> Option<&V> {
+        dispatch!(self, store => store.get(price))
+    }
+
+    fn get_mut(&mut self, price: P) -> Option<&mut V> {
+        dispatch!(self, store => store.get_mut(price))
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, price: P, default: F) -> &mut V {
+        self.fit(price);
+        dispatch!(self, store => store.get_or_insert_with(price, default))
+    }
+
+    fn insert(&mut self, price: P, value: V) -> Option<V> {
+        self.fit(price);
+        dispatch!(self, store => store.insert(price, value))
+    }
+
+    fn remove(&mut self, price: P) -> Option<V> {
+        dispatch!(self, store => store.remove(price))
+    }
+
+    fn clear(&mut self) {
+        dispatch!(self, store => store.clear())
+    }
+
+    fn len(&self) -> usize {
+        dispatch!(self, store => store.len())
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        match self {
+            PriceLevels::BTree(store) => PriceLevelsIter::BTree(store.iter()),
+            PriceLevels::Dense(store) => PriceLevelsIter::Dense(store.iter()),
+            PriceLevels::SkipList(store) => PriceLevelsIter::SkipList(store.iter()),
+        }
+    }
+
+    fn first_mut(&mut self) -> Option<(P, &mut V)> {
+        dispatch!(self, store => store.first_mut())
+    }
+
+    fn last_mut(&mut self) -> Option<(P, &mut V)> {
+        dispatch!(self, store => store.last_mut())
+    }
+}
+
+// Same levels, whatever the backend
+impl<P: LevelPrice, V: PartialEq> PartialEq for PriceLevels<P, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<P: LevelPrice, V: Eq> Eq for PriceLevels<P, V> {}
+
+// Levels are serialized as a price map next to the backend they were kept in
+#[derive(Serialize, Deserialize)]
+struct LevelsRepr<L> {
+    #[serde(default)]
+    backend: BookBackend,
+    levels: L,
+}
+
+impl<P: LevelPrice + Serialize, V: Serialize> Serialize for PriceLevels<P, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        LevelsRepr {
+            backend: self.backend(),
+            levels: self.iter().collect::<BTreeMap<P, &V>>(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, P, V> Deserialize<'de> for PriceLevels<P, V>
+where
+    P: LevelPrice + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = LevelsRepr::<BTreeMap<P, V>>::deserialize(deserializer)?;
+        let mut levels = PriceLevels::new(repr.backend);
+        for (price, value) in repr.levels {
+            levels.insert(price, value);
+        }
+        Ok(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_levels() {
+        let bids = [(105, 1), (104, 2), (100, 3), (99, 4)];
+        assert_eq!(
+            aggregate_levels(bids.into_iter(), 5, false),
+            vec![(105, 1), (100, 5), (95, 4)]
+        );
+        let asks = [(101, 1), (104, 2), (105, 3), (106, u64::MAX)];
+        assert_eq!(
+            aggregate_levels(asks.into_iter(), 5, true),
+            vec![(105, 6), (110, u64::MAX)]
+        );
+        // Negative engine prices
+        let bids = [(-1i32, 1), (-5, 2), (-6, 3)];
+        assert_eq!(
+            aggregate_levels(bids.into_iter(), 5, false),
+            vec![(-5, 3), (-10, 3)]
+        );
+    }
+
+    // Runs the same pseudo random operations against the store and a BTreeMap
+    fn check_conformance<S: LevelStore<u64, u64>>(mut store: S) {
+        let mut reference = BTreeMap::new();
+        let mut state: u64 = 7;
+        for step in 0..2_000u64 {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            let price = 1_000 + (state >> 33) % 64 * 5;
+            match (state >> 20) % 4 {
+                0 => assert_eq!(store.remove(price), reference.remove(&price)),
+                1 => {
+                    *store.get_or_insert_with(price, || 0) += step;
+                    *reference.entry(price).or_insert(0) += step;
+                }
+                2 => {
+                    if let Some(value) = store.get_mut(price) {
+                        *value += 1;
+                    }
+                    if let Some(value) = reference.get_mut(&price) {
+                        *value += 1;
+                    }
+                }
+                _ => assert_eq!(store.insert(price, step), reference.insert(price, step)),
+            }
+
+            assert_eq!(store.len(), reference.len());
+            assert_eq!(store.get(price), reference.get(&price));
+            assert!(store.iter().eq(reference.iter().map(|(p, v)| (*p, v))));
+            assert!(store
+                .iter()
+                .rev()
+                .eq(reference.iter().rev().map(|(p, v)| (*p, v))));
+            assert_eq!(
+                store.first_mut().map(|(p, v)| (p, *v)),
+                reference.first_key_value().map(|(p, v)| (*p, *v))
+            );
+            assert_eq!(
+                store.last_mut().map(|(p, v)| (p, *v)),
+                reference.last_key_value().map(|(p, v)| (*p, *v))
+            );
+        }
+
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(store.first(), None);
+        store.insert(50_000, 1);
+        assert_eq!(store.last(), Some((50_000, &1)));
+    }
+
+    #[test]
+    fn test_btree_conformance() {
+        check_conformance(BTreeLevels::new());
+    }
+
+    #[test]
+    fn test_dense_conformance() {
+        check_conformance(DenseLevels::new(5));
+    }
+
+    #[test]
+    fn test_skip_list_conformance() {
+        check_conformance(SkipListLevels::new());
+    }
+
+    #[test]
+    fn test_price_levels_conformance() {
+        for backend in [
+            BookBackend::BTree,
+            BookBackend::Dense { tick_size: 5 },
+            BookBackend::SkipList,
+        ] {
+            check_conformance(PriceLevels::new(backend));
+        }
+    }
+
+    #[test]
+    fn test_dense_window_follows_price() {
+        let mut levels: DenseLevels<i32, u64> = DenseLevels::new(1);
+        levels.insert(-3, 1);
+        levels.insert(2, 2);
+        assert_eq!(levels.slots.len(), 6);
+
+        levels.remove(-3);
+        assert_eq!(levels.slots.len(), 1);
+        levels.insert(10_000, 3);
+        levels.remove(2);
+        assert_eq!(levels.slots.len(), 1);
+        assert_eq!(levels.first(), Some((10_000, &3)));
+    }
+
+    #[test]
+    fn test_dense_refuses_prices_it_cannot_keep() {
+        let mut levels: DenseLevels<i32, u64> = DenseLevels::new(5).with_max_span(4);
+        assert_eq!(
+            levels.try_insert(12, 1),
+            Err(DenseError::OffGrid {
+                price: 12,
+                tick_size: 5
+            })
+        );
+        levels.try_insert(-5, 1).unwrap();
+        levels.try_insert(10, 2).unwrap();
+        assert_eq!(
+            levels.try_insert(15, 3),
+            Err(DenseError::SpanExceeded {
+                price: 15,
+                max_span: 4
+            })
+        );
+        assert_eq!(
+            levels.try_insert(-10, 3).unwrap_err().to_string(),
+            "price -10 would widen the dense window beyond 4 ticks"
+        );
+        // Off-grid lookups miss instead of landing on the slot below
+        assert_eq!(levels.get(-4), None);
+        assert_eq!(levels.get(12), None);
+        assert_eq!(levels.len(), 2);
+    }
+
+    #[test]
+    fn test_price_levels_fall_back_from_dense() {
+        let mut levels: PriceLevels<u64, u64> =
+            PriceLevels::new(BookBackend::Dense { tick_size: 10 });
+        levels.insert(100, 1);
+        levels.insert(120, 2);
+        levels.insert(125, 3);
+        assert_eq!(levels.backend(), BookBackend::BTree);
+        assert!(levels.iter().eq([(100, &1), (120, &2), (125, &3)]));
+
+        let mut levels: PriceLevels<u64, u64> =
+            PriceLevels::new(BookBackend::Dense { tick_size: 1 });
+        levels.insert(1, 1);
+        *levels.get_or_insert_with(MAX_DENSE_SPAN as u64 + 1, || 0) += 2;
+        assert_eq!(levels.backend(), BookBackend::BTree);
+        assert_eq!(levels.len(), 2);
+    }
+
+    #[test]
+    fn test_serde_keeps_backend() {
+        let mut levels: PriceLevels<u64, u64> = PriceLevels::new(BookBackend::SkipList);
+        levels.insert(10, 1);
+        levels.insert(20, 2);
+
+        let json = serde_json::to_string(&levels).unwrap();
+        let restored: PriceLevels<u64, u64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.backend(), BookBackend::SkipList);
+        assert_eq!(restored, levels);
+    }
+}
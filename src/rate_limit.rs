@@ -0,0 +1,176 @@
+/// Per account order entry throttling.
+/// Every account gets a token bucket that refills continuously on the engine clock, each order
+/// consumes one token. Buckets start full so an account can burst up to the bucket capacity.
+use crate::orderbookv2::{AccountId, Timestamp};
+use std::collections::HashMap;
+
+const NANOS_PER_SECOND: f64 = 1_000_000_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    // Largest burst of messages accepted at once
+    pub burst: u32,
+    // Sustained message rate
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: f64) -> RateLimit {
+        RateLimit { burst, per_second }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Timestamp,
+}
+
+impl TokenBucket {
+    fn try_take(&mut self, limit: &RateLimit, now: Timestamp) -> bool {
+        let elapsed = now.saturating_sub(self.last_refill) as f64 / NANOS_PER_SECOND;
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.last_refill = self.last_refill.max(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    default: RateLimit,
+    per_account: HashMap<AccountId, RateLimit>,
+    buckets: HashMap<AccountId, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(default: RateLimit) -> RateLimiter {
+        RateLimiter {
+            default,
+            per_account: HashMap::new(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn set_account_limit(&mut self, account_id: AccountId, limit: RateLimit) {
+        self.per_account.insert(account_id, limit);
+    }
+
+    pub fn limit_for(&self, account_id: AccountId) -> RateLimit {
+        self.per_account
+            .get(&account_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    // Consumes a token for the account, false when the message has to be throttled
+    pub fn try_acquire(&mut self, account_id: AccountId, now: Timestamp) -> bool {
+        let limit = self.limit_for(account_id);
+        self.buckets
+            .entry(account_id)
+            .or_insert(TokenBucket {
+                tokens: limit.burst as f64,
+                last_refill: now,
+            })
+            .try_take(&limit, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{
+        Order, OrderBook, OrderModify, OrderStatus, OrderType, Price, Quantity, Rejected, Side,
+    };
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let mut limiter = RateLimiter::new(RateLimit::new(2, 4.0));
+
+        assert!(limiter.try_acquire(1, 0));
+        assert!(limiter.try_acquire(1, 0));
+        assert!(!limiter.try_acquire(1, 0));
+        // 4 tokens per second, one is back after 250ms
+        assert!(!limiter.try_acquire(1, SECOND / 5));
+        assert!(limiter.try_acquire(1, SECOND / 4));
+        // Never refills above the burst size
+        assert!(limiter.try_acquire(1, 10 * SECOND));
+        assert!(limiter.try_acquire(1, 10 * SECOND));
+        assert!(!limiter.try_acquire(1, 10 * SECOND));
+    }
+
+    #[test]
+    fn test_accounts_are_throttled_independently() {
+        let mut limiter = RateLimiter::new(RateLimit::new(1, 1.0));
+        limiter.set_account_limit(2, RateLimit::new(3, 1.0));
+
+        assert!(limiter.try_acquire(1, 0));
+        assert!(!limiter.try_acquire(1, 0));
+        assert!(limiter.try_acquire(2, 0));
+        assert!(limiter.try_acquire(2, 0));
+        assert!(limiter.try_acquire(2, 0));
+        assert!(!limiter.try_acquire(2, 0));
+    }
+
+    #[test]
+    fn test_engine_rejects_throttled_orders() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_rate_limiter(RateLimiter::new(RateLimit::new(1, 1.0)));
+
+        let order = |order_id| {
+            Order::new(
+                order_id,
+                Price(100),
+                Quantity(1),
+                OrderType::GoodToCancel,
+                Side::Buy,
+            )
+            .with_account(7)
+        };
+        orderbook.place_order(order(1)).unwrap();
+        assert_eq!(
+            orderbook.place_order(order(2)).unwrap_err(),
+            Rejected::RateLimited { account_id: 7 }
+        );
+
+        orderbook.advance_clock(SECOND);
+        orderbook.place_order(order(3)).unwrap();
+        assert_eq!(orderbook.orderbook_size(), 2);
+    }
+
+    #[test]
+    fn test_throttled_modify_leaves_the_order_resting() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_rate_limiter(RateLimiter::new(RateLimit::new(1, 1.0)));
+
+        orderbook
+            .place_order(
+                Order::new(
+                    1,
+                    Price(100),
+                    Quantity(5),
+                    OrderType::GoodToCancel,
+                    Side::Buy,
+                )
+                .with_account(7),
+            )
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .replace_order(OrderModify::new(1, Side::Buy, Price(101), Quantity(3)))
+                .unwrap_err(),
+            Rejected::RateLimited { account_id: 7 }
+        );
+
+        assert_eq!(orderbook.order_status(1), Some(OrderStatus::New));
+        let bids = orderbook.get_orderbook_level_infos().get_bids().clone();
+        assert_eq!(bids.len(), 1);
+        assert_eq!((bids[0].price, bids[0].quantity), (Price(100), Quantity(5)));
+    }
+}
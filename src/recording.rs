@@ -0,0 +1,233 @@
+/// Recorded market data streams.
+/// A recording is a JSON lines file holding the raw websocket payloads of a session, each
+/// stamped with the time it was read from the socket. Replaying it through the same code the
+/// live feed uses rebuilds the L2 book exactly as it was, and the replay keeps a summary of the
+/// spread, the top of book imbalance and the network latency seen along the way.
+use crate::book_stream::apply_payload;
+use crate::clock::Stamp;
+use crate::latency::{LatencyRecorder, LatencySample};
+use crate::orderbook::OrderBook;
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedPayload {
+    pub received: Stamp,
+    pub payload: String,
+}
+
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    writer: W,
+    len: usize,
+}
+
+impl Recorder<BufWriter<File>> {
+    // Appends to an existing recording
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Recorder<BufWriter<File>>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W) -> Recorder<W> {
+        Recorder { writer, len: 0 }
+    }
+
+    pub fn record(&mut self, received: Stamp, payload: &str) -> io::Result<()> {
+        let entry = RecordedPayload {
+            received,
+            payload: payload.to_string(),
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // Payloads recorded by this recorder, not counting what the file held before
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+pub fn read_recording(path: impl AsRef<Path>) -> io::Result<Vec<RecordedPayload>> {
+    parse_recording(BufReader::new(File::open(path)?))
+}
+
+pub fn parse_recording<R: BufRead>(reader: R) -> io::Result<Vec<RecordedPayload>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+// Count, minimum, maximum and mean of a series
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeriesSummary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl SeriesSummary {
+    fn push(summary: &mut Option<SeriesSummary>, value: f64) {
+        let Some(summary) = summary else {
+            *summary = Some(SeriesSummary {
+                count: 1,
+                min: value,
+                max: value,
+                mean: value,
+            });
+            return;
+        };
+        summary.count += 1;
+        summary.min = summary.min.min(value);
+        summary.max = summary.max.max(value);
+        summary.mean += (value - summary.mean) / summary.count as f64;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayStatistics {
+    pub payloads: usize,
+    // Payloads that were not depth or book ticker updates, or rows of another symbol in a
+    // historical dataset
+    pub unrecognized: usize,
+    // Trades of the symbol in a historical dataset, they do not change the book
+    pub trades: usize,
+    // Sampled after every applied payload while both sides have a level
+    pub spread: Option<SeriesSummary>,
+    // (bid quantity - ask quantity) / (bid quantity + ask quantity) at the touch, from -1 to 1
+    pub imbalance: Option<SeriesSummary>,
+    // Only the network stage is meaningful, the replay does not reproduce the feed handler time
+    pub latency: LatencyRecorder,
+}
+
+impl ReplayStatistics {
+    // Samples the book after an update that changed it
+    pub(crate) fn sample(&mut self, orderbook: &OrderBook, received: Stamp) {
+        self.latency.record(LatencySample {
+            event_time: orderbook.last_event_time(),
+            received,
+            applied: received,
+        });
+        if let Some(spread) = orderbook.spread() {
+            SeriesSummary::push(&mut self.spread, spread);
+        }
+        if let (Some((_, bid)), Some((_, ask))) = (orderbook.best_bid(), orderbook.best_ask()) {
+            let (bid, ask) = (bid.0 as f64, ask.0 as f64);
+            if bid + ask > 0.0 {
+                SeriesSummary::push(&mut self.imbalance, (bid - ask) / (bid + ask));
+            }
+        }
+    }
+}
+
+// Applies the payloads in order to a fresh book
+pub fn replay(
+    symbol: impl Into<Symbol>,
+    payloads: impl IntoIterator<Item = RecordedPayload>,
+) -> (OrderBook, ReplayStatistics) {
+    replay_with(symbol, payloads, |_, _| {})
+}
+
+// Same as `replay`, `observe` sees the book after every payload that changed it
+pub fn replay_with(
+    symbol: impl Into<Symbol>,
+    payloads: impl IntoIterator<Item = RecordedPayload>,
+    mut observe: impl FnMut(Stamp, &OrderBook),
+) -> (OrderBook, ReplayStatistics) {
+    let mut orderbook = OrderBook::new(symbol);
+    let mut statistics = ReplayStatistics::default();
+
+    for recorded in payloads {
+        statistics.payloads += 1;
+        let previous = orderbook.last_update();
+        if !apply_payload(&mut orderbook, recorded.payload.as_bytes()) {
+            statistics.unrecognized += 1;
+            continue;
+        }
+        // Stale updates leave the stamp alone
+        if orderbook.last_update() == previous {
+            continue;
+        }
+
+        statistics.sample(&orderbook, recorded.received);
+        observe(recorded.received, &orderbook);
+    }
+
+    (orderbook, statistics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latency::LatencyStage;
+
+    fn depth(last_update_id: u64, event_time: u64, bid: &str, ask: &str) -> String {
+        format!(
+            r#"{{"stream":"ethusdc@depth20@100ms","data":{{"E":{},"lastUpdateId":{},"bids":[{}],"asks":[{}]}}}}"#,
+            event_time, last_update_id, bid, ask
+        )
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let wall = |millis: u64| Stamp {
+            monotonic: 0,
+            wall: millis * 1_000_000,
+        };
+        let mut recorder = Recorder::new(Vec::new());
+        recorder
+            .record(
+                wall(1_005),
+                &depth(1, 1_000, r#"["100.0","3.0"]"#, r#"["101.0","1.0"]"#),
+            )
+            .unwrap();
+        recorder.record(wall(1_006), "not a payload").unwrap();
+        recorder
+            .record(
+                wall(1_012),
+                &depth(2, 1_010, r#"["100.0","1.0"]"#, r#"["100.5","1.0"]"#),
+            )
+            .unwrap();
+        // Stale, already applied
+        recorder
+            .record(wall(1_013), &depth(2, 1_010, r#"["99.0","1.0"]"#, ""))
+            .unwrap();
+        assert_eq!(recorder.len(), 4);
+
+        let payloads = parse_recording(recorder.writer.as_slice()).unwrap();
+        assert_eq!(payloads[1].payload, "not a payload");
+        let (orderbook, statistics) = replay("ETHUSDC", payloads);
+
+        assert_eq!(statistics.payloads, 4);
+        assert_eq!(statistics.unrecognized, 1);
+        let spread = statistics.spread.unwrap();
+        assert_eq!(spread.count, 2);
+        assert_eq!((spread.min, spread.max, spread.mean), (0.5, 1.0, 0.75));
+        let imbalance = statistics.imbalance.unwrap();
+        assert_eq!((imbalance.min, imbalance.max), (0.0, 0.5));
+        let network = statistics
+            .latency
+            .statistics(LatencyStage::Network)
+            .unwrap();
+        assert_eq!((network.min, network.max), (2_000_000, 5_000_000));
+        assert_eq!(orderbook.snapshot(1).last_update_id, 2);
+    }
+}
@@ -0,0 +1,104 @@
+/// Redis integration for the L2 book, enabled with the `redis` feature.
+/// Every `publish` sends the top of book and an N-level snapshot to the symbol's pub/sub
+/// channels and stores the snapshot under the symbol's key, so services can either follow the
+/// channels or read the latest state with a single GET. Payloads are JSON in book internal
+/// units, snapshots carry the converter to read them with (`DepthSnapshot::converter`).
+///
+///   {prefix}:{symbol}:top       channel, `TopOfBook`
+///   {prefix}:{symbol}:depth     channel, `DepthSnapshot`
+///   {prefix}:{symbol}:snapshot  key, latest `DepthSnapshot`
+use crate::orderbook::{DepthSnapshot, OrderBook};
+pub use crate::top_of_book::TopOfBook;
+use redis::{Client, Connection, ErrorKind, RedisError, RedisResult};
+
+fn json_error(error: serde_json::Error) -> RedisError {
+    RedisError::from((ErrorKind::TypeError, "invalid json", error.to_string()))
+}
+
+pub struct RedisPublisher {
+    connection: Connection,
+    prefix: String,
+    levels: usize,
+}
+
+impl RedisPublisher {
+    pub fn connect(url: &str, prefix: &str, levels: usize) -> RedisResult<RedisPublisher> {
+        Ok(RedisPublisher {
+            connection: Client::open(url)?.get_connection()?,
+            prefix: prefix.to_string(),
+            levels,
+        })
+    }
+
+    pub fn top_of_book_channel(&self, symbol: &str) -> String {
+        format!("{}:{}:top", self.prefix, symbol)
+    }
+
+    pub fn depth_channel(&self, symbol: &str) -> String {
+        format!("{}:{}:depth", self.prefix, symbol)
+    }
+
+    pub fn snapshot_key(&self, symbol: &str) -> String {
+        format!("{}:{}:snapshot", self.prefix, symbol)
+    }
+
+    pub fn publish(&mut self, book: &OrderBook) -> RedisResult<()> {
+        let top = serde_json::to_string(&TopOfBook::from_book(book)).map_err(json_error)?;
+        let depth = serde_json::to_string(&book.snapshot(self.levels)).map_err(json_error)?;
+        let symbol = book.symbol().as_str();
+
+        redis::pipe()
+            .atomic()
+            .cmd("PUBLISH")
+            .arg(self.top_of_book_channel(symbol))
+            .arg(&top)
+            .ignore()
+            .cmd("PUBLISH")
+            .arg(self.depth_channel(symbol))
+            .arg(&depth)
+            .ignore()
+            .cmd("SET")
+            .arg(self.snapshot_key(symbol))
+            .arg(&depth)
+            .ignore()
+            .query(&mut self.connection)
+    }
+
+    pub fn latest_snapshot(&mut self, symbol: &str) -> RedisResult<Option<DepthSnapshot>> {
+        let key = self.snapshot_key(symbol);
+        let payload: Option<String> = redis::cmd("GET").arg(key).query(&mut self.connection)?;
+        payload
+            .map(|payload| serde_json::from_str(&payload).map_err(json_error))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use crate::orderbook::{Price, Quantity};
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn test_top_of_book() {
+        let mut book = OrderBook::new("BNBUSDT".to_string());
+        assert_eq!(TopOfBook::from_book(&book).bid, None);
+
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 3,
+            bids: vec![(10.0, 1.0), (9.0, 2.0)],
+            asks: vec![(11.0, 0.5)],
+        });
+        assert_eq!(
+            TopOfBook::from_book(&book),
+            TopOfBook {
+                symbol: Symbol::intern("BNBUSDT"),
+                last_update_id: 3,
+                bid: Some((Price(100_000), Quantity(10_000))),
+                ask: Some((Price(110_000), Quantity(5_000))),
+            }
+        );
+    }
+}
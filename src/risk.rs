@@ -0,0 +1,405 @@
+/// Pre-trade risk checks run before an order reaches the matching engine.
+/// Limits are configured per account with a default for accounts without explicit limits,
+/// every limit is optional.
+use crate::orderbookv2::{AccountId, Order, Price, Quantity, Side};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<Quantity>,
+    // price * quantity
+    pub max_notional: Option<f64>,
+    pub max_open_orders: Option<usize>,
+    // Maximum distance of the order price from the last trade price, in basis points
+    pub price_collar_bps: Option<f64>,
+    // Largest net position in lots, long or short, the account can reach if its open orders
+    // on the side of the order and the order itself all fill
+    pub max_position: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskViolation {
+    MaxOrderQuantity {
+        limit: Quantity,
+        requested: Quantity,
+    },
+    MaxNotional {
+        limit: f64,
+        requested: f64,
+    },
+    MaxOpenOrders {
+        limit: usize,
+    },
+    PriceCollar {
+        price: Price,
+        reference: Price,
+        limit_bps: f64,
+    },
+    MaxPosition {
+        limit: u64,
+        // Net position in lots once everything on the side of the order filled
+        resulting: i64,
+    },
+}
+
+impl fmt::Display for RiskViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskViolation::MaxOrderQuantity { limit, requested } => {
+                write!(f, "order quantity {} above limit {}", requested, limit)
+            }
+            RiskViolation::MaxNotional { limit, requested } => {
+                write!(f, "order notional {} above limit {}", requested, limit)
+            }
+            RiskViolation::MaxOpenOrders { limit } => {
+                write!(f, "open orders limit {} reached", limit)
+            }
+            RiskViolation::PriceCollar {
+                price,
+                reference,
+                limit_bps,
+            } => write!(
+                f,
+                "price {} more than {} bps away from last trade {}",
+                price, limit_bps, reference
+            ),
+            RiskViolation::MaxPosition { limit, resulting } => {
+                write!(f, "position {} would exceed limit {}", resulting, limit)
+            }
+        }
+    }
+}
+
+// Engine state the checks depend on
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RiskContext {
+    pub open_orders: usize,
+    pub last_trade_price: Option<Price>,
+    // Net traded lots of the account, positive when long
+    pub position: i64,
+    // Lots the account has open on the side of the order
+    pub open_quantity: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RiskManager {
+    default: RiskLimits,
+    per_account: HashMap<AccountId, RiskLimits>,
+}
+
+impl RiskManager {
+    pub fn new(default: RiskLimits) -> RiskManager {
+        RiskManager {
+            default,
+            per_account: HashMap::new(),
+        }
+    }
+
+    pub fn set_account_limits(&mut self, account_id: AccountId, limits: RiskLimits) {
+        self.per_account.insert(account_id, limits);
+    }
+
+    pub fn limits_for(&self, account_id: AccountId) -> RiskLimits {
+        self.per_account
+            .get(&account_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    pub fn check(&self, order: &Order, ctx: &RiskContext) -> Result<(), RiskViolation> {
+        let limits = self.limits_for(order.get_account_id());
+        let quantity = order.get_remaining_quantity();
+
+        if let Some(limit) = limits.max_order_quantity {
+            if quantity > limit {
+                return Err(RiskViolation::MaxOrderQuantity {
+                    limit,
+                    requested: quantity,
+                });
+            }
+        }
+
+        if let Some(limit) = limits.max_notional {
+            let requested = (order.get_price() * quantity).to_f64();
+            if requested > limit {
+                return Err(RiskViolation::MaxNotional { limit, requested });
+            }
+        }
+
+        if let Some(limit) = limits.max_open_orders {
+            if ctx.open_orders >= limit {
+                return Err(RiskViolation::MaxOpenOrders { limit });
+            }
+        }
+
+        // Only the side of the order is counted, orders on the other side may never fill
+        if let Some(limit) = limits.max_position {
+            let pending = (ctx.open_quantity + u64::from(quantity)) as i64;
+            let resulting = match order.get_side() {
+                Side::Buy => ctx.position.saturating_add(pending),
+                Side::Sell => ctx.position.saturating_sub(pending),
+            };
+            let growing = match order.get_side() {
+                Side::Buy => resulting > 0,
+                Side::Sell => resulting < 0,
+            };
+            if growing && resulting.unsigned_abs() > limit {
+                return Err(RiskViolation::MaxPosition { limit, resulting });
+            }
+        }
+
+        // Without any trade yet there is no reference price to collar around
+        if let (Some(limit_bps), Some(reference)) = (limits.price_collar_bps, ctx.last_trade_price)
+        {
+            let distance = (order.get_price() - reference).abs().to_f64();
+            if distance * 10_000.0 > limit_bps * reference.abs().to_f64() {
+                return Err(RiskViolation::PriceCollar {
+                    price: order.get_price(),
+                    reference,
+                    limit_bps,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{OrderBook, OrderModify, OrderType, Rejected};
+
+    fn order(order_id: u64, price: Price, quantity: Quantity) -> Order {
+        Order::new(
+            order_id,
+            price,
+            quantity,
+            OrderType::GoodToCancel,
+            Side::Buy,
+        )
+        .with_account(1)
+    }
+
+    #[test]
+    fn test_quantity_and_notional_limits() {
+        let manager = RiskManager::new(RiskLimits {
+            max_order_quantity: Some(Quantity(100)),
+            max_notional: Some(5_000.0),
+            ..RiskLimits::default()
+        });
+        let ctx = RiskContext::default();
+
+        assert_eq!(
+            manager.check(&order(1, Price(10), Quantity(100)), &ctx),
+            Ok(())
+        );
+        assert_eq!(
+            manager.check(&order(1, Price(10), Quantity(101)), &ctx),
+            Err(RiskViolation::MaxOrderQuantity {
+                limit: Quantity(100),
+                requested: Quantity(101)
+            })
+        );
+        assert_eq!(
+            manager.check(&order(1, Price(60), Quantity(100)), &ctx),
+            Err(RiskViolation::MaxNotional {
+                limit: 5_000.0,
+                requested: 6_000.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_price_collar_around_last_trade() {
+        let manager = RiskManager::new(RiskLimits {
+            price_collar_bps: Some(500.0),
+            ..RiskLimits::default()
+        });
+        let ctx = RiskContext {
+            last_trade_price: Some(Price(100)),
+            ..RiskContext::default()
+        };
+
+        assert_eq!(
+            manager.check(&order(1, Price(105), Quantity(1)), &ctx),
+            Ok(())
+        );
+        assert_eq!(
+            manager.check(&order(1, Price(95), Quantity(1)), &ctx),
+            Ok(())
+        );
+        assert!(manager
+            .check(&order(1, Price(106), Quantity(1)), &ctx)
+            .is_err());
+        assert_eq!(
+            manager.check(&order(1, Price(106), Quantity(1)), &RiskContext::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_position_limit_counts_open_orders_on_the_same_side() {
+        let manager = RiskManager::new(RiskLimits {
+            max_position: Some(10),
+            ..RiskLimits::default()
+        });
+        let long = RiskContext {
+            position: 6,
+            open_quantity: 2,
+            ..RiskContext::default()
+        };
+
+        assert_eq!(
+            manager.check(&order(1, Price(10), Quantity(2)), &long),
+            Ok(())
+        );
+        assert_eq!(
+            manager.check(&order(1, Price(10), Quantity(3)), &long),
+            Err(RiskViolation::MaxPosition {
+                limit: 10,
+                resulting: 11
+            })
+        );
+        // Selling out of a long position and into a short one
+        let sell = |quantity| {
+            Order::new(
+                1,
+                Price(10),
+                Quantity(quantity),
+                OrderType::GoodToCancel,
+                Side::Sell,
+            )
+            .with_account(1)
+        };
+        let long = RiskContext {
+            position: 6,
+            ..RiskContext::default()
+        };
+        assert_eq!(manager.check(&sell(16), &long), Ok(()));
+        assert_eq!(
+            manager.check(&sell(17), &long),
+            Err(RiskViolation::MaxPosition {
+                limit: 10,
+                resulting: -11
+            })
+        );
+        // A position already past the limit can still be reduced
+        let over = RiskContext {
+            position: 15,
+            ..RiskContext::default()
+        };
+        assert_eq!(manager.check(&sell(5), &over), Ok(()));
+    }
+
+    #[test]
+    fn test_per_account_limits_override_default() {
+        let mut manager = RiskManager::new(RiskLimits {
+            max_order_quantity: Some(Quantity(10)),
+            ..RiskLimits::default()
+        });
+        manager.set_account_limits(1, RiskLimits::default());
+
+        assert_eq!(
+            manager.check(&order(1, Price(10), Quantity(50)), &RiskContext::default()),
+            Ok(())
+        );
+        let other = order(2, Price(10), Quantity(50)).with_account(2);
+        assert!(manager.check(&other, &RiskContext::default()).is_err());
+    }
+
+    #[test]
+    fn test_engine_rejects_orders_breaking_limits() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_risk_manager(RiskManager::new(RiskLimits {
+            max_open_orders: Some(2),
+            price_collar_bps: Some(1_000.0),
+            ..RiskLimits::default()
+        }));
+
+        orderbook
+            .place_order(order(1, Price(100), Quantity(5)))
+            .unwrap();
+        orderbook
+            .place_order(order(2, Price(99), Quantity(5)))
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .place_order(order(3, Price(98), Quantity(5)))
+                .unwrap_err(),
+            (Rejected::Risk(RiskViolation::MaxOpenOrders { limit: 2 }))
+        );
+
+        let sell = Order::new(
+            4,
+            Price(100),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        )
+        .with_account(2);
+        orderbook.place_order(sell).unwrap();
+        assert_eq!(orderbook.last_trade_price(), Some(Price(100)));
+
+        let far = Order::new(
+            5,
+            Price(120),
+            Quantity(5),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        )
+        .with_account(2);
+        assert!(matches!(
+            orderbook.place_order(far),
+            Err(Rejected::Risk(RiskViolation::PriceCollar { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_engine_tracks_positions_for_the_limit() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_risk_manager(RiskManager::new(RiskLimits {
+            max_position: Some(8),
+            ..RiskLimits::default()
+        }));
+        let sell = |order_id, quantity| {
+            Order::new(
+                order_id,
+                Price(100),
+                Quantity(quantity),
+                OrderType::GoodToCancel,
+                Side::Sell,
+            )
+            .with_account(2)
+        };
+
+        orderbook.place_order(sell(1, 5)).unwrap();
+        orderbook
+            .place_order(order(2, Price(100), Quantity(5)))
+            .unwrap();
+        assert_eq!(orderbook.position(1), 5);
+        assert_eq!(orderbook.position(2), -5);
+
+        // 5 bought and 2 resting, a third lot would reach 9
+        orderbook
+            .place_order(order(3, Price(99), Quantity(2)))
+            .unwrap();
+        assert_eq!(
+            orderbook
+                .place_order(order(4, Price(98), Quantity(2)))
+                .unwrap_err(),
+            Rejected::Risk(RiskViolation::MaxPosition {
+                limit: 8,
+                resulting: 9
+            })
+        );
+        // An amend does not count the order it replaces
+        orderbook
+            .replace_order(OrderModify::new(3, Side::Buy, Price(99), Quantity(3)))
+            .unwrap();
+        assert!(orderbook
+            .replace_order(OrderModify::new(3, Side::Buy, Price(99), Quantity(4)))
+            .is_err());
+    }
+}
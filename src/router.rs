@@ -0,0 +1,402 @@
+/// Smart order routing across the books of several venues.
+/// `ConsolidatedBook` keeps one L2 book per venue for the same instrument. The router splits a
+/// target quantity across them by walking the levels of every venue in order of their price
+/// after the venue's taker fee, so a cheaper level on a more expensive venue can lose to a
+/// slightly worse price elsewhere. Venues whose share ends up below their minimum order size
+/// are dropped and the split is computed again without them. Fixed fees do not depend on the
+/// size and are not part of the ranking, they are added to the cost of the plan.
+/// The books have to use the same `PriceConverter` so prices compare across venues.
+/// `RoutingSimulator` executes plans against recorded depth with a `FillSimulator` per venue.
+use crate::binance_payloads::{DepthUpdate, TradeUpdate};
+use crate::fees::Fee;
+use crate::fill_simulator::{FillSimulator, SimOrderKind};
+use crate::orderbook::{OrderBook, Price, Quantity};
+use crate::orderbookv2::Side;
+use crate::symbol::Symbol;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Venue {
+    pub name: String,
+    pub taker_fee: Fee,
+    // Smallest order the venue accepts, in book units
+    pub min_quantity: Quantity,
+}
+
+impl Venue {
+    pub fn new(name: impl Into<String>, taker_fee: Fee) -> Venue {
+        Venue {
+            name: name.into(),
+            taker_fee,
+            min_quantity: Quantity::ZERO,
+        }
+    }
+
+    pub fn with_min_quantity(mut self, min_quantity: Quantity) -> Venue {
+        self.min_quantity = min_quantity;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteLeg {
+    pub venue: String,
+    pub quantity: Quantity,
+    // Worst level the leg takes, the limit price of the child order
+    pub limit_price: Price,
+    // Quote currency, before fees
+    pub notional: f64,
+    pub fee: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingPlan {
+    pub side: Side,
+    pub quantity: Quantity,
+    // One leg per venue that gets a share, in venue order
+    pub legs: Vec<RouteLeg>,
+    // Part of the target the visible liquidity could not cover
+    pub unfilled: Quantity,
+}
+
+impl RoutingPlan {
+    pub fn routed_quantity(&self) -> Quantity {
+        self.quantity - self.unfilled
+    }
+
+    // Notional plus fees for a buy, minus fees for a sell
+    pub fn total_cost(&self) -> f64 {
+        self.legs
+            .iter()
+            .map(|leg| match self.side {
+                Side::Buy => leg.notional + leg.fee,
+                Side::Sell => leg.notional - leg.fee,
+            })
+            .sum()
+    }
+}
+
+fn plan(venues: &[(&Venue, &OrderBook)], side: Side, quantity: Quantity) -> RoutingPlan {
+    let mut excluded = vec![false; venues.len()];
+    loop {
+        // (fee adjusted price, price, quantity, venue)
+        let mut levels: Vec<(f64, Price, Quantity, usize)> = Vec::new();
+        for (index, (venue, book)) in venues.iter().enumerate() {
+            if excluded[index] {
+                continue;
+            }
+            let converter = book.converter();
+            let fee_rate = match venue.taker_fee {
+                Fee::Bps(bps) => bps / 10_000.0,
+                Fee::Fixed(_) => 0.0,
+            };
+            let book_levels: Box<dyn Iterator<Item = (Price, Quantity)>> = match side {
+                Side::Buy => Box::new(book.asks()),
+                Side::Sell => Box::new(book.bids()),
+            };
+            levels.extend(book_levels.map(|(price, available)| {
+                let price_f64 = converter.to_f64(price);
+                let effective = match side {
+                    Side::Buy => price_f64 * (1.0 + fee_rate),
+                    Side::Sell => price_f64 * (1.0 - fee_rate),
+                };
+                (effective, price, available, index)
+            }));
+        }
+        levels.sort_by(|a, b| match side {
+            Side::Buy => a.0.total_cmp(&b.0),
+            Side::Sell => b.0.total_cmp(&a.0),
+        });
+
+        let mut allocations: Vec<Option<(Quantity, Price, f64)>> = vec![None; venues.len()];
+        let mut remaining = quantity;
+        for (_, price, available, index) in levels {
+            if remaining.is_zero() {
+                break;
+            }
+            let taken = remaining.min(available);
+            remaining -= taken;
+            let converter = venues[index].1.converter();
+            let notional = converter.to_f64(price) * converter.to_f64(taken);
+            let allocation = allocations[index].get_or_insert((Quantity::ZERO, price, 0.0));
+            allocation.0 += taken;
+            allocation.1 = price;
+            allocation.2 += notional;
+        }
+
+        // Drop the smallest share below its venue minimum and split again
+        let too_small = allocations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, allocation)| {
+                let (allocated, _, _) = (*allocation)?;
+                (allocated < venues[index].0.min_quantity).then_some((allocated, index))
+            })
+            .min();
+        if let Some((_, index)) = too_small {
+            excluded[index] = true;
+            continue;
+        }
+
+        let legs = allocations
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, allocation)| {
+                let (quantity, limit_price, notional) = allocation?;
+                let venue = venues[index].0;
+                Some(RouteLeg {
+                    venue: venue.name.clone(),
+                    quantity,
+                    limit_price,
+                    notional,
+                    fee: venue.taker_fee.on_notional(notional),
+                })
+            })
+            .collect();
+        return RoutingPlan {
+            side,
+            quantity,
+            legs,
+            unfilled: remaining,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsolidatedBook {
+    symbol: Symbol,
+    venues: Vec<(Venue, OrderBook)>,
+}
+
+impl ConsolidatedBook {
+    pub fn new(symbol: impl Into<Symbol>) -> ConsolidatedBook {
+        ConsolidatedBook {
+            symbol: symbol.into(),
+            venues: Vec::new(),
+        }
+    }
+
+    // Book the venue's feed is applied to, replaces the configuration of a known venue
+    pub fn add_venue(&mut self, venue: Venue) -> &mut OrderBook {
+        let index = match self.venues.iter().position(|(v, _)| v.name == venue.name) {
+            Some(index) => {
+                self.venues[index].0 = venue;
+                index
+            }
+            None => {
+                self.venues.push((venue, OrderBook::new(self.symbol)));
+                self.venues.len() - 1
+            }
+        };
+        &mut self.venues[index].1
+    }
+
+    pub fn venues(&self) -> impl Iterator<Item = &Venue> + '_ {
+        self.venues.iter().map(|(venue, _)| venue)
+    }
+
+    pub fn book(&self, venue: &str) -> Option<&OrderBook> {
+        self.venues
+            .iter()
+            .find(|(v, _)| v.name == venue)
+            .map(|(_, book)| book)
+    }
+
+    pub fn book_mut(&mut self, venue: &str) -> Option<&mut OrderBook> {
+        self.venues
+            .iter_mut()
+            .find(|(v, _)| v.name == venue)
+            .map(|(_, book)| book)
+    }
+
+    // Best price across the venues with the venue quoting it
+    pub fn best_bid(&self) -> Option<(Price, &str)> {
+        self.venues
+            .iter()
+            .filter_map(|(venue, book)| Some((book.bids().next()?.0, venue.name.as_str())))
+            .max_by_key(|(price, _)| *price)
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, &str)> {
+        self.venues
+            .iter()
+            .filter_map(|(venue, book)| Some((book.asks().next()?.0, venue.name.as_str())))
+            .min_by_key(|(price, _)| *price)
+    }
+
+    pub fn route(&self, side: Side, quantity: Quantity) -> RoutingPlan {
+        let venues: Vec<(&Venue, &OrderBook)> = self
+            .venues
+            .iter()
+            .map(|(venue, book)| (venue, book))
+            .collect();
+        plan(&venues, side, quantity)
+    }
+}
+
+// What a leg of a plan got when it was sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegExecution {
+    pub venue: String,
+    pub planned: Quantity,
+    pub filled: Quantity,
+    pub notional: f64,
+}
+
+#[derive(Debug)]
+pub struct RoutingSimulator {
+    venues: Vec<(Venue, FillSimulator)>,
+}
+
+impl RoutingSimulator {
+    pub fn new(symbol: impl Into<Symbol>, venues: Vec<Venue>) -> RoutingSimulator {
+        let symbol = symbol.into();
+        RoutingSimulator {
+            venues: venues
+                .into_iter()
+                .map(|venue| (venue, FillSimulator::new(symbol)))
+                .collect(),
+        }
+    }
+
+    // Replays recorded depth of a venue, unknown venues are ignored
+    pub fn on_depth(&mut self, venue: &str, update: &DepthUpdate) {
+        if let Some(simulator) = self.simulator_mut(venue) {
+            simulator.on_depth(update);
+        }
+    }
+
+    pub fn on_trade(&mut self, venue: &str, trade: &TradeUpdate) {
+        if let Some(simulator) = self.simulator_mut(venue) {
+            simulator.on_trade(trade);
+        }
+    }
+
+    fn simulator_mut(&mut self, venue: &str) -> Option<&mut FillSimulator> {
+        self.venues
+            .iter_mut()
+            .find(|(v, _)| v.name == venue)
+            .map(|(_, simulator)| simulator)
+    }
+
+    // Plan against the replayed books
+    pub fn route(&self, side: Side, quantity: Quantity) -> RoutingPlan {
+        let venues: Vec<(&Venue, &OrderBook)> = self
+            .venues
+            .iter()
+            .map(|(venue, simulator)| (venue, simulator.book()))
+            .collect();
+        plan(&venues, side, quantity)
+    }
+
+    // Sends every leg as a Fill and Kill order at its limit price, the books may have moved
+    // since the plan was made
+    pub fn execute(&mut self, plan: &RoutingPlan) -> Vec<LegExecution> {
+        plan.legs
+            .iter()
+            .filter_map(|leg| {
+                let simulator = self.simulator_mut(&leg.venue)?;
+                let converter = simulator.book().converter();
+                let order_id = simulator.submit(
+                    plan.side,
+                    SimOrderKind::Limit(leg.limit_price),
+                    leg.quantity,
+                );
+                simulator.cancel(order_id);
+                let mut execution = LegExecution {
+                    venue: leg.venue.clone(),
+                    planned: leg.quantity,
+                    filled: Quantity::ZERO,
+                    notional: 0.0,
+                };
+                for fill in simulator.drain_executions() {
+                    execution.filled += fill.quantity;
+                    execution.notional +=
+                        converter.to_f64(fill.price) * converter.to_f64(fill.quantity);
+                }
+                Some(execution)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(9.0, 10.0)],
+            asks,
+        }
+    }
+
+    fn consolidated() -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new("BNBUSDT");
+        book.add_venue(Venue::new("cheap", Fee::Bps(10.0)))
+            .update_depth(&depth(vec![(10.0, 1.0), (10.2, 5.0)]));
+        book.add_venue(Venue::new("free", Fee::Bps(0.0)).with_min_quantity(Quantity(20_000)))
+            .update_depth(&depth(vec![(10.005, 2.0), (10.1, 5.0)]));
+        book
+    }
+
+    #[test]
+    fn test_split_ranks_levels_after_fees() {
+        let book = consolidated();
+        assert_eq!(book.best_ask(), Some((Price(100_000), "cheap")));
+
+        // 10.0 + 10 bps is worse than 10.005 without fees
+        let plan = book.route(Side::Buy, Quantity(40_000));
+        assert_eq!(plan.unfilled, Quantity(0));
+        assert_eq!(plan.legs.len(), 2);
+        assert_eq!(plan.legs[0].venue, "cheap");
+        assert_eq!(plan.legs[0].quantity, Quantity(10_000));
+        assert_eq!(plan.legs[1].venue, "free");
+        assert_eq!(plan.legs[1].quantity, Quantity(30_000));
+        assert_eq!(plan.legs[1].limit_price, Price(101_000));
+        assert!((plan.legs[0].fee - 0.01).abs() < 1e-9);
+        assert!((plan.total_cost() - (10.0 + 0.01 + 2.0 * 10.005 + 10.1)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_minimum_size_moves_the_share_elsewhere() {
+        let book = consolidated();
+        // "free" would get one unit, below its minimum of two
+        let plan = book.route(Side::Buy, Quantity(10_000));
+        assert_eq!(plan.legs.len(), 1);
+        assert_eq!(plan.legs[0].venue, "cheap");
+        assert_eq!(plan.routed_quantity(), Quantity(10_000));
+
+        let plan = book.route(Side::Buy, Quantity(200_000));
+        assert_eq!(plan.unfilled, Quantity(70_000));
+    }
+
+    #[test]
+    fn test_simulated_execution_against_replayed_depth() {
+        let mut simulator = RoutingSimulator::new(
+            "BNBUSDT",
+            vec![
+                Venue::new("a", Fee::Bps(0.0)),
+                Venue::new("b", Fee::Bps(0.0)),
+            ],
+        );
+        simulator.on_depth("a", &depth(vec![(10.0, 1.0)]));
+        simulator.on_depth("b", &depth(vec![(10.1, 1.0)]));
+        let plan = simulator.route(Side::Buy, Quantity(20_000));
+        assert_eq!(plan.legs.len(), 2);
+
+        // Venue b moved away before the order arrived
+        simulator.on_depth(
+            "b",
+            &DepthUpdate {
+                last_update_id: 2,
+                ..depth(vec![(10.1, 0.0), (10.3, 1.0)])
+            },
+        );
+        let executions = simulator.execute(&plan);
+        assert_eq!(executions[0].filled, Quantity(10_000));
+        assert_eq!(executions[1].planned, Quantity(10_000));
+        assert_eq!(executions[1].filled, Quantity(0));
+    }
+}
@@ -0,0 +1,224 @@
+/// Step by step scenarios for matching engine tests.
+/// `scenario()` starts from an empty book, every step runs against the engine right away and
+/// every expectation asserts on the spot, so a failure points at its line:
+/// `scenario().buy(100, 10).sell(50, 10).expect_trade(50, 10).expect_book(&[(50, 10)], &[])`
+/// reads as buy 100 @ 10, sell 50 @ 10, expect a trade of 50 @ 10 and 50 left on the bid.
+/// Quantities come before prices throughout, as in `100 @ 10`. Orders get the ids 1, 2, 3...
+/// in the order they are entered. Trades are queued as the steps produce them and every
+/// `expect_trade` takes the oldest one.
+use crate::orderbookv2::{AccountId, Order, OrderBook, OrderId, OrderType, Price, Quantity, Side};
+use std::collections::VecDeque;
+
+pub fn scenario() -> Scenario {
+    Scenario::new(OrderBook::new())
+}
+
+#[derive(Debug)]
+pub struct Scenario {
+    book: OrderBook,
+    next_order_id: OrderId,
+    account_id: AccountId,
+    // Trades not expected yet, as (bid order id, ask order id, quantity, price)
+    trades: VecDeque<(OrderId, OrderId, Quantity, Price)>,
+}
+
+impl Scenario {
+    // Runs on a configured engine, e.g. with a matching policy or fees
+    pub fn new(book: OrderBook) -> Scenario {
+        Scenario {
+            book,
+            next_order_id: 1,
+            account_id: 0,
+            trades: VecDeque::new(),
+        }
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn into_book(self) -> OrderBook {
+        self.book
+    }
+
+    // Account of the orders entered from now on
+    pub fn account(mut self, account_id: AccountId) -> Scenario {
+        self.account_id = account_id;
+        self
+    }
+
+    // Good to cancel limit orders
+    #[track_caller]
+    pub fn buy(self, quantity: u32, price: i32) -> Scenario {
+        self.limit(Side::Buy, quantity, price, OrderType::GoodToCancel)
+    }
+
+    #[track_caller]
+    pub fn sell(self, quantity: u32, price: i32) -> Scenario {
+        self.limit(Side::Sell, quantity, price, OrderType::GoodToCancel)
+    }
+
+    #[track_caller]
+    pub fn limit(self, side: Side, quantity: u32, price: i32, order_type: OrderType) -> Scenario {
+        let order_id = self.next_order_id;
+        self.order(Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            order_type,
+            side,
+        ))
+    }
+
+    // Enters the order as given, with the account of the scenario
+    #[track_caller]
+    pub fn order(mut self, order: Order) -> Scenario {
+        let order = order.with_account(self.account_id);
+        self.next_order_id = self.next_order_id.max(order.get_order_id() + 1);
+        let trades = self.book.add_order(order);
+        self.trades.extend(trades.iter().map(|trade| {
+            (
+                trade.bid_trade.order_id,
+                trade.ask_trade.order_id,
+                trade.bid_trade.quantity,
+                trade.bid_trade.price,
+            )
+        }));
+        self
+    }
+
+    #[track_caller]
+    pub fn cancel(mut self, order_id: OrderId) -> Scenario {
+        if let Err(rejected) = self.book.cancel_order(order_id) {
+            panic!("cannot cancel order {}: {}", order_id, rejected);
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_trade(mut self, quantity: u32, price: i32) -> Scenario {
+        match self.trades.pop_front() {
+            Some((_, _, traded, at)) => assert_eq!(
+                (traded, at),
+                (Quantity(quantity), Price(price)),
+                "expected a trade of {} @ {}, got {} @ {}",
+                quantity,
+                price,
+                traded.0,
+                at.0
+            ),
+            None => panic!("expected a trade of {} @ {}, got none", quantity, price),
+        }
+        self
+    }
+
+    // Same as `expect_trade`, also checks which orders traded
+    #[track_caller]
+    pub fn expect_trade_between(
+        mut self,
+        bid_order_id: OrderId,
+        ask_order_id: OrderId,
+        quantity: u32,
+        price: i32,
+    ) -> Scenario {
+        let trade = self.trades.front().copied();
+        self = self.expect_trade(quantity, price);
+        let (bid, ask, _, _) = trade.expect("the trade was checked");
+        assert_eq!(
+            (bid, ask),
+            (bid_order_id, ask_order_id),
+            "expected orders {} and {} to trade",
+            bid_order_id,
+            ask_order_id
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_no_trades(self) -> Scenario {
+        assert!(
+            self.trades.is_empty(),
+            "expected no more trades, got {:?}",
+            self.trades
+        );
+        self
+    }
+
+    // Aggregated levels of both sides as (quantity, price), best first
+    #[track_caller]
+    pub fn expect_book(self, bids: &[(u32, i32)], asks: &[(u32, i32)]) -> Scenario {
+        let levels = |levels: &[(u32, i32)]| -> Vec<(Quantity, Price)> {
+            levels
+                .iter()
+                .map(|&(quantity, price)| (Quantity(quantity), Price(price)))
+                .collect()
+        };
+        let actual =
+            |levels: &mut dyn Iterator<Item = (Price, Quantity)>| -> Vec<(Quantity, Price)> {
+                levels.map(|(price, quantity)| (quantity, price)).collect()
+            };
+        assert_eq!(actual(&mut self.book.bids()), levels(bids), "bids differ");
+        assert_eq!(actual(&mut self.book.asks()), levels(asks), "asks differ");
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::MatchingPolicy;
+
+    #[test]
+    fn test_partial_fill_and_time_priority() {
+        scenario()
+            .buy(100, 10)
+            .sell(50, 10)
+            .expect_trade(50, 10)
+            .expect_book(&[(50, 10)], &[])
+            .buy(20, 10)
+            .sell(60, 9)
+            .expect_trade_between(1, 4, 50, 10)
+            .expect_trade_between(3, 4, 10, 10)
+            .expect_no_trades()
+            .expect_book(&[(10, 10)], &[])
+            .cancel(3)
+            .expect_book(&[], &[]);
+    }
+
+    #[test]
+    fn test_fill_and_kill_remainder() {
+        scenario()
+            .sell(5, 101)
+            .sell(3, 101)
+            .limit(Side::Buy, 12, 101, OrderType::FillAndKill)
+            .expect_trade_between(3, 1, 5, 101)
+            .expect_trade_between(3, 2, 3, 101)
+            .expect_no_trades()
+            .expect_book(&[], &[]);
+    }
+
+    #[test]
+    fn test_configured_engine() {
+        let mut book = OrderBook::new();
+        book.set_matching_policy(MatchingPolicy::ProRata {
+            min_allocation: Quantity(1),
+            top_order_priority: false,
+        });
+        Scenario::new(book)
+            .account(1)
+            .sell(30, 100)
+            .account(2)
+            .sell(10, 100)
+            .account(3)
+            .buy(20, 100)
+            .expect_trade_between(3, 1, 15, 100)
+            .expect_trade_between(3, 2, 5, 100)
+            .expect_book(&[], &[(20, 100)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a trade of 10 @ 10, got none")]
+    fn test_missing_trade() {
+        scenario().buy(10, 10).sell(10, 11).expect_trade(10, 10);
+    }
+}
@@ -0,0 +1,236 @@
+/// Trading session states of the matching engine.
+/// PreOpen collects orders for the opening auction, Open is continuous trading, Halted and
+/// Closed refuse new orders. Cancels are accepted in every state.
+/// `OrderBook::roll_session` ends the trading day: Day orders expire and the session
+/// statistics start over, Good To Cancel orders carry over to the next session.
+use crate::orderbookv2::{Price, Quantity, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SessionState {
+    PreOpen,
+    #[default]
+    Open,
+    Halted,
+    Closed,
+}
+
+impl SessionState {
+    pub fn accepts_orders(&self) -> bool {
+        matches!(self, SessionState::PreOpen | SessionState::Open)
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SessionState::PreOpen => "pre-open",
+            SessionState::Open => "open",
+            SessionState::Halted => "halted",
+            SessionState::Closed => "closed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Counters since the last `roll_session`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SessionStatistics {
+    pub started_at: Timestamp,
+    pub trade_count: u64,
+    // Summed in u64, see `Quantity`
+    pub traded_volume: u64,
+    // Sum of price * quantity, for the VWAP
+    pub traded_notional: i128,
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    pub expired_orders: u64,
+}
+
+impl SessionStatistics {
+    pub fn new(started_at: Timestamp) -> SessionStatistics {
+        SessionStatistics {
+            started_at,
+            ..SessionStatistics::default()
+        }
+    }
+
+    pub(crate) fn record_trade(&mut self, price: Price, quantity: Quantity) {
+        self.trade_count += 1;
+        self.traded_volume += u64::from(quantity);
+        self.traded_notional += (price * quantity).get() as i128;
+        self.high = Some(self.high.map_or(price, |high| high.max(price)));
+        self.low = Some(self.low.map_or(price, |low| low.min(price)));
+    }
+
+    pub fn vwap(&self) -> Option<f64> {
+        (self.traded_volume > 0).then(|| self.traded_notional as f64 / self.traded_volume as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EngineEvent;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Rejected, Side};
+
+    fn order(order_id: u64, price: i32, order_type: OrderType, side: Side) -> Order {
+        Order::new(order_id, Price(price), Quantity(10), order_type, side)
+    }
+
+    #[test]
+    fn test_pre_open_collects_orders_and_uncrosses_on_open() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_session_state(SessionState::PreOpen);
+
+        assert!(orderbook
+            .place_order(order(1, 101, OrderType::GoodToCancel, Side::Buy))
+            .unwrap()
+            .is_empty());
+        assert!(orderbook
+            .place_order(order(2, 99, OrderType::GoodToCancel, Side::Sell))
+            .unwrap()
+            .is_empty());
+
+        let trades = orderbook.set_session_state(SessionState::Open);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(orderbook.orderbook_size(), 0);
+    }
+
+    #[test]
+    fn test_halted_and_closed_reject_orders() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(order(1, 100, OrderType::GoodToCancel, Side::Buy));
+
+        orderbook.set_session_state(SessionState::Halted);
+        assert_eq!(
+            orderbook
+                .place_order(order(2, 100, OrderType::GoodToCancel, Side::Sell))
+                .unwrap_err(),
+            Rejected::SessionNotOpen(SessionState::Halted)
+        );
+        // Cancels still go through while halted
+        orderbook.cancel_order(1).unwrap();
+
+        orderbook.set_session_state(SessionState::Open);
+        assert!(orderbook
+            .place_order(order(3, 100, OrderType::GoodToCancel, Side::Sell))
+            .is_ok());
+
+        orderbook.set_session_state(SessionState::Closed);
+        assert!(orderbook
+            .place_order(order(4, 100, OrderType::GoodToCancel, Side::Sell))
+            .is_err());
+    }
+
+    #[test]
+    fn test_close_cancels_day_orders_only() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(order(1, 100, OrderType::Day, Side::Buy));
+        orderbook.add_order(order(2, 99, OrderType::GoodToCancel, Side::Buy));
+        orderbook.add_order(order(3, 105, OrderType::Day, Side::Sell));
+
+        orderbook.set_session_state(SessionState::Closed);
+        assert_eq!(orderbook.orderbook_size(), 1);
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(99), Quantity(10))]
+        );
+    }
+
+    #[test]
+    fn test_roll_session_expires_day_orders_and_resets_statistics() {
+        let mut orderbook = OrderBook::new();
+        orderbook.add_order(order(1, 100, OrderType::Day, Side::Buy));
+        orderbook.add_order(order(2, 99, OrderType::GoodToCancel, Side::Buy));
+        orderbook.add_order(order(3, 105, OrderType::Day, Side::Sell));
+        orderbook.add_order(Order::new(
+            4,
+            Price(100),
+            Quantity(4),
+            OrderType::FillAndKill,
+            Side::Sell,
+        ));
+        assert_eq!(
+            *orderbook.session_statistics(),
+            SessionStatistics {
+                started_at: 0,
+                trade_count: 1,
+                traded_volume: 4,
+                traded_notional: 400,
+                high: Some(Price(100)),
+                low: Some(Price(100)),
+                expired_orders: 0,
+            }
+        );
+        orderbook.drain_events();
+
+        let now = 60_000_000_000;
+        orderbook.advance_clock(now);
+        assert_eq!(orderbook.roll_session(), vec![1, 3]);
+        assert_eq!(
+            orderbook.bids().collect::<Vec<_>>(),
+            vec![(Price(99), Quantity(10))]
+        );
+        assert_eq!(orderbook.asks().count(), 0);
+        // The session state is left alone, trading goes on in the new session
+        assert_eq!(orderbook.session_state(), SessionState::Open);
+
+        assert_eq!(
+            orderbook.drain_events(),
+            vec![
+                EngineEvent::OrderExpired {
+                    order_id: 1,
+                    timestamp: now,
+                },
+                EngineEvent::OrderExpired {
+                    order_id: 3,
+                    timestamp: now,
+                },
+                EngineEvent::SessionRolled {
+                    statistics: SessionStatistics {
+                        started_at: 0,
+                        trade_count: 1,
+                        traded_volume: 4,
+                        traded_notional: 400,
+                        high: Some(Price(100)),
+                        low: Some(Price(100)),
+                        expired_orders: 2,
+                    },
+                    timestamp: now,
+                },
+            ]
+        );
+        assert_eq!(*orderbook.session_statistics(), SessionStatistics::new(now));
+
+        // Nothing left to expire
+        assert!(orderbook.roll_session().is_empty());
+    }
+
+    #[test]
+    fn test_transitions_emit_events() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_session_state(SessionState::Halted);
+        orderbook.set_session_state(SessionState::Halted);
+        orderbook.set_session_state(SessionState::Open);
+
+        assert_eq!(orderbook.session_state(), SessionState::Open);
+        assert_eq!(
+            orderbook.drain_events(),
+            vec![
+                EngineEvent::SessionStateChanged {
+                    from: SessionState::Open,
+                    to: SessionState::Halted,
+                    timestamp: 0,
+                },
+                EngineEvent::SessionStateChanged {
+                    from: SessionState::Halted,
+                    to: SessionState::Open,
+                    timestamp: 0,
+                },
+            ]
+        );
+        assert!(orderbook.drain_events().is_empty());
+    }
+}
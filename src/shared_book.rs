@@ -0,0 +1,160 @@
+/// Single writer, multi reader access to the L2 book.
+/// The writer applies updates to its own copy and publishes a new version after each one through
+/// an `ArcSwap`. Readers load the latest version without locking and keep a consistent view of it
+/// for as long as they hold on to it, so they never see a half applied update and never block
+/// the writer.
+use crate::binance_payloads::{BookTickerUpdate, DepthUpdate};
+use crate::market_data::MarketDataMessage;
+use crate::orderbook::{DepthSnapshot, OrderBook, UpdateOutcome};
+use crate::price_converter::ConversionError;
+use crate::symbol::Symbol;
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+// Owned by the thread applying updates, hand out `reader()`s to the others
+#[derive(Debug)]
+pub struct SharedOrderBook {
+    book: OrderBook,
+    published: Arc<ArcSwap<OrderBook>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookReader {
+    published: Arc<ArcSwap<OrderBook>>,
+}
+
+impl SharedOrderBook {
+    pub fn new(symbol: impl Into<Symbol>) -> SharedOrderBook {
+        let book = OrderBook::new(symbol);
+        SharedOrderBook {
+            published: Arc::new(ArcSwap::from_pointee(book.clone())),
+            book,
+        }
+    }
+
+    pub fn reader(&self) -> BookReader {
+        BookReader {
+            published: Arc::clone(&self.published),
+        }
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    // Applies the change to the writer copy, then publishes it as one version
+    pub fn update(&mut self, apply: impl FnOnce(&mut OrderBook)) {
+        apply(&mut self.book);
+        self.published.store(Arc::new(self.book.clone()));
+    }
+
+    // Nothing is published for duplicate and stale updates
+    pub fn update_depth(&mut self, data: &DepthUpdate) -> UpdateOutcome {
+        let outcome = self.book.update_depth(data);
+        if outcome.is_applied() {
+            self.published.store(Arc::new(self.book.clone()));
+        }
+        outcome
+    }
+
+    // Nothing is published when the update is rejected, a duplicate or stale
+    pub fn update_book_ticker(
+        &mut self,
+        data: &BookTickerUpdate,
+    ) -> Result<UpdateOutcome, ConversionError> {
+        let outcome = self.book.update_book_ticker(data)?;
+        if outcome.is_applied() {
+            self.published.store(Arc::new(self.book.clone()));
+        }
+        Ok(outcome)
+    }
+
+    pub fn apply_market_data(&mut self, message: &MarketDataMessage) {
+        self.update(|book| book.apply_market_data(message));
+    }
+}
+
+impl BookReader {
+    // Latest published version, unaffected by later updates
+    pub fn load(&self) -> Arc<OrderBook> {
+        self.published.load_full()
+    }
+
+    pub fn snapshot(&self, levels: usize) -> DepthSnapshot {
+        self.published.load().snapshot(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Price, Quantity};
+    use std::thread;
+
+    // Every update sets both sides to quantities derived from its id, a reader seeing a mix of
+    // two updates would find them out of line
+    fn update(last_update_id: u64) -> DepthUpdate {
+        let quantity = last_update_id as f64;
+        DepthUpdate {
+            event_time: None,
+            last_update_id,
+            bids: vec![(10.0, quantity), (9.0, quantity)],
+            asks: vec![(11.0, quantity), (12.0, quantity)],
+        }
+    }
+
+    #[test]
+    fn test_readers_see_consistent_versions() {
+        let mut shared = SharedOrderBook::new("BNBUSDT".to_string());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = shared.reader();
+                thread::spawn(move || {
+                    let mut last_seen = 0;
+                    for _ in 0..10_000 {
+                        let snapshot = reader.snapshot(5);
+                        assert!(snapshot.last_update_id >= last_seen);
+                        last_seen = snapshot.last_update_id;
+                        if last_seen == 0 {
+                            continue;
+                        }
+                        let quantity = last_seen * 10_000;
+                        assert!(snapshot
+                            .bids
+                            .iter()
+                            .chain(&snapshot.asks)
+                            .all(|&(_, qty)| qty == Quantity(quantity)));
+                    }
+                })
+            })
+            .collect();
+
+        for last_update_id in 1..=2_000 {
+            shared.update_depth(&update(last_update_id));
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(shared.reader().snapshot(5), shared.book().snapshot(5));
+    }
+
+    #[test]
+    fn test_loaded_version_is_not_affected_by_updates() {
+        let mut shared = SharedOrderBook::new("BNBUSDT".to_string());
+        let reader = shared.reader();
+        shared.update_depth(&update(1));
+
+        let loaded = reader.load();
+        shared.update_depth(&update(2));
+
+        assert_eq!(
+            loaded.snapshot(1).bids,
+            vec![(Price(100_000), Quantity(10_000))]
+        );
+        assert_eq!(
+            reader.snapshot(1).bids,
+            vec![(Price(100_000), Quantity(20_000))]
+        );
+    }
+}
@@ -0,0 +1,578 @@
+/// Shared memory ring buffer of normalized book deltas and trades, enabled with the `shm`
+/// feature. One process publishes into a file mapped in memory, typically under `/dev/shm`, and
+/// any number of processes on the same host map the same file and read it, without a socket or
+/// a syscall on either side.
+///
+/// The file starts with a 64 byte header (magic, capacity in slots, next sequence to publish)
+/// followed by `capacity` slots of 64 bytes. A record lives in slot `sequence % capacity` and
+/// every word of it is an atomic, the first one a version that is odd while the publisher writes
+/// the slot and `2 * sequence + 2` once it is done. A reader copies the slot and checks that the
+/// version did not move, so the publisher never waits for anyone. A reader that falls more than
+/// `capacity` records behind is lapped: it is told how many records it missed and continues with
+/// the oldest one still in the ring, where a consumer would resynchronize from a snapshot.
+/// Prices and quantities are in book units, see `OrderBook::converter`.
+use crate::binance_payloads::TradeUpdate;
+use crate::orderbook::{LevelMutation, Price, Quantity};
+use crate::orderbookv2::Side;
+use crate::price_converter::{ConversionError, PriceConverter};
+use crate::symbol::Symbol;
+use memmap2::{Mmap, MmapMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"OBSHMv01");
+const HEADER: usize = 64;
+const SLOT: usize = 64;
+// Header words
+const CAPACITY: usize = 8;
+const WRITE_SEQUENCE: usize = 16;
+// Longest symbol name a record holds
+pub const MAX_SYMBOL_LEN: usize = 16;
+
+const DELTA: u64 = 1;
+const TRADE: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmEvent {
+    // New quantity of a level, zero removes it
+    Delta {
+        symbol: Symbol,
+        update_id: u64,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    },
+    Trade {
+        symbol: Symbol,
+        trade_id: u64,
+        // Milliseconds
+        trade_time: u64,
+        price: Price,
+        quantity: Quantity,
+        // Side of the incoming order
+        aggressor: Side,
+    },
+}
+
+impl ShmEvent {
+    pub fn delta(symbol: Symbol, mutation: &LevelMutation) -> ShmEvent {
+        ShmEvent::Delta {
+            symbol,
+            update_id: mutation.update_id,
+            side: mutation.side,
+            price: mutation.price,
+            quantity: mutation.new_quantity,
+        }
+    }
+
+    pub fn trade(
+        trade: &TradeUpdate,
+        converter: &PriceConverter,
+    ) -> Result<ShmEvent, ConversionError> {
+        Ok(ShmEvent::Trade {
+            symbol: trade.symbol,
+            trade_id: trade.trade_id,
+            trade_time: trade.trade_time,
+            price: Price(converter.to_units(trade.price)?),
+            quantity: Quantity(converter.to_units(trade.quantity)?),
+            aggressor: if trade.is_buyer_maker {
+                Side::Sell
+            } else {
+                Side::Buy
+            },
+        })
+    }
+
+    pub fn symbol(&self) -> Symbol {
+        match self {
+            ShmEvent::Delta { symbol, .. } | ShmEvent::Trade { symbol, .. } => *symbol,
+        }
+    }
+
+    // Kind and side, symbol, then three words depending on the kind
+    fn encode(&self) -> Result<[u64; 7], ShmError> {
+        let name = self.symbol().as_str().as_bytes();
+        if name.len() > MAX_SYMBOL_LEN {
+            return Err(ShmError::SymbolTooLong(self.symbol()));
+        }
+        let mut padded = [0u8; MAX_SYMBOL_LEN];
+        padded[..name.len()].copy_from_slice(name);
+        let (low, high) = padded.split_at(8);
+        let low = u64::from_le_bytes(low.try_into().unwrap());
+        let high = u64::from_le_bytes(high.try_into().unwrap());
+
+        let side = |side: Side| match side {
+            Side::Buy => 0,
+            Side::Sell => 1,
+        };
+        Ok(match *self {
+            ShmEvent::Delta {
+                update_id,
+                side: level_side,
+                price,
+                quantity,
+                ..
+            } => [
+                DELTA | side(level_side) << 8,
+                low,
+                high,
+                update_id,
+                price.get(),
+                quantity.get(),
+                0,
+            ],
+            ShmEvent::Trade {
+                trade_id,
+                trade_time,
+                price,
+                quantity,
+                aggressor,
+                ..
+            } => [
+                TRADE | side(aggressor) << 8,
+                low,
+                high,
+                trade_id,
+                price.get(),
+                quantity.get(),
+                trade_time,
+            ],
+        })
+    }
+
+    fn decode(words: &[u64; 7], symbol: Symbol) -> Option<ShmEvent> {
+        let side = match words[0] >> 8 & 0xff {
+            0 => Side::Buy,
+            1 => Side::Sell,
+            _ => return None,
+        };
+        match words[0] & 0xff {
+            DELTA => Some(ShmEvent::Delta {
+                symbol,
+                update_id: words[3],
+                side,
+                price: Price(words[4]),
+                quantity: Quantity(words[5]),
+            }),
+            TRADE => Some(ShmEvent::Trade {
+                symbol,
+                trade_id: words[3],
+                trade_time: words[6],
+                price: Price(words[4]),
+                quantity: Quantity(words[5]),
+                aggressor: side,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShmRecord {
+    pub sequence: u64,
+    pub event: ShmEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmError {
+    // Longer than `MAX_SYMBOL_LEN`, nothing was published
+    SymbolTooLong(Symbol),
+    // The publisher overwrote `missed` records before the reader got to them
+    Lapped { missed: u64 },
+    // A slot that does not hold a record, the file is not written by `ShmPublisher`
+    Corrupt { sequence: u64 },
+}
+
+impl fmt::Display for ShmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShmError::SymbolTooLong(symbol) => write!(
+                f,
+                "symbol {} is longer than {} bytes",
+                symbol, MAX_SYMBOL_LEN
+            ),
+            ShmError::Lapped { missed } => write!(f, "reader lapped, {} records missed", missed),
+            ShmError::Corrupt { sequence } => write!(f, "record {} cannot be decoded", sequence),
+        }
+    }
+}
+
+impl std::error::Error for ShmError {}
+
+// The words of a mapped ring. Other processes write the mapping while it is mapped, so it is
+// only ever accessed through atomics formed from its raw pointer, never through a `&[u8]`.
+struct Ring {
+    base: *const AtomicU64,
+    len: usize,
+}
+
+// The pointer is into a mapping owned next to the ring, it moves with it
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(base: *const u8, len: usize) -> Ring {
+        Ring {
+            base: base as *const AtomicU64,
+            len,
+        }
+    }
+
+    // Word at `offset`, offsets are multiples of 8 and mappings are page aligned. The reference
+    // borrows the ring, so it cannot outlive the mapping.
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        assert!(offset % 8 == 0 && offset + 8 <= self.len);
+        // In bounds and aligned, and the bytes are only ever accessed atomically
+        unsafe { &*self.base.add(offset / 8) }
+    }
+}
+
+fn slot_offset(sequence: u64, capacity: u64) -> usize {
+    HEADER + (sequence % capacity) as usize * SLOT
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The single writer of a ring
+pub struct ShmPublisher {
+    // Only kept mapped, `ring` points into it
+    _map: MmapMut,
+    ring: Ring,
+    capacity: u64,
+    next_sequence: u64,
+}
+
+impl ShmPublisher {
+    // Creates the file at `path` with room for `capacity` records. An existing file is refused
+    // rather than truncated, readers may still have it mapped and would fault on their next read.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<ShmPublisher> {
+        let len = capacity
+            .checked_mul(SLOT)
+            .and_then(|slots| slots.checked_add(HEADER))
+            .filter(|_| capacity != 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot create a ring of {} records", capacity),
+                )
+            })?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.set_len(len as u64)?;
+        // The file is ours, readers only ever load from it
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let ring = Ring::new(map.as_mut_ptr(), map.len());
+        ring.word(CAPACITY)
+            .store(capacity as u64, Ordering::Relaxed);
+        ring.word(WRITE_SEQUENCE).store(0, Ordering::Relaxed);
+        // Readers check the magic last
+        ring.word(0).store(MAGIC, Ordering::Release);
+        Ok(ShmPublisher {
+            _map: map,
+            ring,
+            capacity: capacity as u64,
+            next_sequence: 0,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    // Sequence the next record gets
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    // Returns the record's sequence
+    pub fn publish(&mut self, event: &ShmEvent) -> Result<u64, ShmError> {
+        let words = event.encode()?;
+        let sequence = self.next_sequence;
+        let slot = slot_offset(sequence, self.capacity);
+
+        let version = self.ring.word(slot);
+        version.store(2 * sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (index, value) in words.iter().enumerate() {
+            self.ring
+                .word(slot + 8 + index * 8)
+                .store(*value, Ordering::Relaxed);
+        }
+        version.store(2 * sequence + 2, Ordering::Release);
+        self.ring
+            .word(WRITE_SEQUENCE)
+            .store(sequence + 1, Ordering::Release);
+
+        self.next_sequence += 1;
+        Ok(sequence)
+    }
+
+    // Level changes drained from `OrderBook::drain_mutations`, returns the last sequence
+    pub fn publish_mutations(
+        &mut self,
+        symbol: Symbol,
+        mutations: &[LevelMutation],
+    ) -> Result<Option<u64>, ShmError> {
+        let mut last = None;
+        for mutation in mutations {
+            last = Some(self.publish(&ShmEvent::delta(symbol, mutation))?);
+        }
+        Ok(last)
+    }
+}
+
+impl fmt::Debug for ShmPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShmPublisher")
+            .field("capacity", &self.capacity)
+            .field("next_sequence", &self.next_sequence)
+            .finish()
+    }
+}
+
+// One of any number of readers of a ring, each with its own position
+pub struct ShmReader {
+    // Only kept mapped, `ring` points into it
+    _map: Mmap,
+    ring: Ring,
+    capacity: u64,
+    next_sequence: u64,
+    // Names seen so far, so a record does not take the interner's lock
+    symbols: HashMap<[u8; MAX_SYMBOL_LEN], Symbol>,
+}
+
+impl ShmReader {
+    // Maps the ring at `path`, reading starts with the next record published
+    pub fn open(path: impl AsRef<Path>) -> io::Result<ShmReader> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let ring = Ring::new(map.as_ptr(), map.len());
+        if map.len() < HEADER || ring.word(0).load(Ordering::Acquire) != MAGIC {
+            return Err(invalid("not a shared memory ring".to_string()));
+        }
+        let capacity = ring.word(CAPACITY).load(Ordering::Relaxed);
+        let len = usize::try_from(capacity)
+            .ok()
+            .and_then(|capacity| capacity.checked_mul(SLOT))
+            .and_then(|slots| slots.checked_add(HEADER));
+        if capacity == 0 || len.map_or(true, |len| map.len() < len) {
+            return Err(invalid(format!(
+                "ring of {} bytes cannot hold {} records",
+                map.len(),
+                capacity
+            )));
+        }
+        let mut reader = ShmReader {
+            _map: map,
+            ring,
+            capacity,
+            next_sequence: 0,
+            symbols: HashMap::new(),
+        };
+        reader.next_sequence = reader.published();
+        Ok(reader)
+    }
+
+    // Continues with the oldest record still in the ring
+    pub fn rewind(&mut self) {
+        self.next_sequence = self.published().saturating_sub(self.capacity);
+    }
+
+    // Sequence of the next record `try_next` returns
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    // Records published so far
+    pub fn published(&self) -> u64 {
+        self.ring.word(WRITE_SEQUENCE).load(Ordering::Acquire)
+    }
+
+    // Next record, None until it is published
+    pub fn try_next(&mut self) -> Result<Option<ShmRecord>, ShmError> {
+        let sequence = self.next_sequence;
+        let slot = slot_offset(sequence, self.capacity);
+        let expected = 2 * sequence + 2;
+
+        let version = self.ring.word(slot);
+        let before = version.load(Ordering::Acquire);
+        if before < expected {
+            return Ok(None);
+        }
+        let mut words = [0u64; 7];
+        for (index, value) in words.iter_mut().enumerate() {
+            *value = self.ring.word(slot + 8 + index * 8).load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        if before != expected || version.load(Ordering::Relaxed) != expected {
+            let oldest = self.published().saturating_sub(self.capacity);
+            self.next_sequence = oldest.max(sequence + 1);
+            return Err(ShmError::Lapped {
+                missed: self.next_sequence - sequence,
+            });
+        }
+
+        self.next_sequence += 1;
+        let symbol = self
+            .symbol(words[1], words[2])
+            .ok_or(ShmError::Corrupt { sequence })?;
+        let event = ShmEvent::decode(&words, symbol).ok_or(ShmError::Corrupt { sequence })?;
+        Ok(Some(ShmRecord { sequence, event }))
+    }
+
+    fn symbol(&mut self, low: u64, high: u64) -> Option<Symbol> {
+        let mut name = [0u8; MAX_SYMBOL_LEN];
+        name[..8].copy_from_slice(&low.to_le_bytes());
+        name[8..].copy_from_slice(&high.to_le_bytes());
+        if let Some(symbol) = self.symbols.get(&name) {
+            return Some(*symbol);
+        }
+        let len = name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(name.len());
+        let symbol = Symbol::intern(std::str::from_utf8(&name[..len]).ok()?);
+        self.symbols.insert(name, symbol);
+        Some(symbol)
+    }
+}
+
+impl fmt::Debug for ShmReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShmReader")
+            .field("capacity", &self.capacity)
+            .field("next_sequence", &self.next_sequence)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+    use crate::orderbook::OrderBook;
+
+    #[test]
+    fn test_readers_follow_the_publisher() {
+        let path = std::env::temp_dir().join(format!("shm-ring-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut publisher = ShmPublisher::create(&path, 4).unwrap();
+        // The ring is not truncated under its readers
+        assert_eq!(
+            ShmPublisher::create(&path, 4).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        let mut first = ShmReader::open(&path).unwrap();
+        let mut second = ShmReader::open(&path).unwrap();
+        assert_eq!(first.try_next(), Ok(None));
+
+        let mut book = OrderBook::new("ETHUSDC");
+        book.record_mutations();
+        book.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 7,
+            bids: vec![(3000.5, 1.5)],
+            asks: vec![(3001.0, 2.0)],
+        });
+        assert_eq!(
+            publisher.publish_mutations(book.symbol(), &book.drain_mutations()),
+            Ok(Some(1))
+        );
+        let trade = TradeUpdate {
+            event_time: 1,
+            symbol: Symbol::intern("ETHUSDC"),
+            trade_id: 12,
+            price: 3001.0,
+            quantity: 0.5,
+            trade_time: 1_700_000_000_000,
+            is_buyer_maker: false,
+        };
+        let trade = ShmEvent::trade(&trade, &book.converter()).unwrap();
+        assert_eq!(publisher.publish(&trade), Ok(2));
+
+        let mut records = Vec::new();
+        while let Some(record) = first.try_next().unwrap() {
+            records.push(record);
+        }
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].event,
+            ShmEvent::Delta {
+                symbol: book.symbol(),
+                update_id: 7,
+                side: Side::Buy,
+                price: Price(30_005_000),
+                quantity: Quantity(15_000),
+            }
+        );
+        assert_eq!(
+            records[2],
+            ShmRecord {
+                sequence: 2,
+                event: trade
+            }
+        );
+        // Readers are independent
+        assert_eq!(second.try_next().unwrap().unwrap(), records[0]);
+
+        // Six more records overwrite the ones the second reader did not read yet
+        for _ in 0..6 {
+            publisher.publish(&trade).unwrap();
+        }
+        assert_eq!(second.try_next(), Err(ShmError::Lapped { missed: 4 }));
+        assert_eq!(second.next_sequence(), 5);
+        assert_eq!(second.try_next().unwrap().unwrap().sequence, 5);
+        second.rewind();
+        assert_eq!(second.next_sequence(), 5);
+
+        let long = ShmEvent::Delta {
+            symbol: Symbol::intern("AVERYLONGSYMBOLNAME"),
+            update_id: 1,
+            side: Side::Sell,
+            price: Price(1),
+            quantity: Quantity(1),
+        };
+        assert!(matches!(
+            publisher.publish(&long),
+            Err(ShmError::SymbolTooLong(_))
+        ));
+        assert_eq!(publisher.next_sequence(), 9);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_capacity_beyond_the_file_is_refused() {
+        let path = std::env::temp_dir().join(format!("shm-ring-huge-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        drop(ShmPublisher::create(&path, 1).unwrap());
+        {
+            let mut map = unsafe {
+                MmapMut::map_mut(&File::options().read(true).write(true).open(&path).unwrap())
+                    .unwrap()
+            };
+            let ring = Ring::new(map.as_mut_ptr(), map.len());
+            // Wraps around to a small length when multiplied without checks
+            ring.word(CAPACITY)
+                .store(u64::MAX / SLOT as u64 + 1, Ordering::Relaxed);
+        }
+        assert_eq!(
+            ShmReader::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            ShmPublisher::create(path.with_extension("zero"), 0)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+/// Coordinated shutdown of the async subsystems.
+/// Every task that has to stop with the process watches a `ShutdownSignal`, every task whose
+/// stop has to be waited for also holds a `DrainGuard`. `Shutdown::shutdown` raises the signal,
+/// after which feed clients stop reading and servers stop accepting, and resolves once every
+/// guard is dropped, i.e. every subsystem has flushed its journal or recorder and emitted its
+/// final snapshots. A subsystem drains in its own task and drops its guard last.
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    triggered: Arc<watch::Sender<bool>>,
+    // Names of the guards still alive, a name appears once per guard
+    pending: Arc<watch::Sender<Vec<String>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            triggered: Arc::new(watch::Sender::new(false)),
+            pending: Arc::new(watch::Sender::new(Vec::new())),
+        }
+    }
+
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.triggered.subscribe(),
+        }
+    }
+
+    // `shutdown` waits for the guard to be dropped
+    pub fn register(&self, name: impl Into<String>) -> DrainGuard {
+        let name = name.into();
+        self.pending
+            .send_modify(|pending| pending.push(name.clone()));
+        DrainGuard {
+            name,
+            signal: self.signal(),
+            pending: self.pending.clone(),
+        }
+    }
+
+    // Raises the signal without waiting, e.g. from a Ctrl-C handler
+    pub fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    pub fn pending(&self) -> Vec<String> {
+        self.pending.borrow().clone()
+    }
+
+    // Raises the signal and resolves once every subsystem has drained
+    pub async fn shutdown(&self) {
+        self.trigger();
+        let mut pending = self.pending.subscribe();
+        // The sender lives in `self`, waiting cannot fail
+        let _ = pending.wait_for(Vec::is_empty).await;
+    }
+
+    // Same as `shutdown`, gives up after `timeout` and returns the subsystems still draining
+    pub async fn shutdown_timeout(&self, timeout: Duration) -> Result<(), Vec<String>> {
+        tokio::time::timeout(timeout, self.shutdown())
+            .await
+            .map_err(|_| self.pending())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Resolves once the shutdown starts, right away when it already has
+    pub async fn triggered(&self) {
+        let mut receiver = self.receiver.clone();
+        // A dropped `Shutdown` never triggers, wait forever like an untriggered one
+        if receiver.wait_for(|triggered| *triggered).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DrainGuard {
+    name: String,
+    signal: ShutdownSignal,
+    pending: Arc<watch::Sender<Vec<String>>>,
+}
+
+impl DrainGuard {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn signal(&self) -> &ShutdownSignal {
+        &self.signal
+    }
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.pending.send_modify(|pending| {
+            if let Some(index) = pending.iter().position(|name| *name == self.name) {
+                pending.swap_remove(index);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_subsystems_to_drain() {
+        let shutdown = Shutdown::new();
+        let (flushed, mut flushes) = mpsc::unbounded_channel();
+
+        for name in ["feed", "journal"] {
+            let guard = shutdown.register(name);
+            let flushed = flushed.clone();
+            tokio::spawn(async move {
+                guard.signal().triggered().await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                flushed.send(guard.name().to_string()).unwrap();
+            });
+        }
+        assert_eq!(shutdown.pending().len(), 2);
+        assert!(!shutdown.signal().is_triggered());
+
+        shutdown.shutdown().await;
+        assert!(shutdown.is_triggered());
+        assert!(shutdown.pending().is_empty());
+        let mut drained = vec![flushes.recv().await.unwrap(), flushes.recv().await.unwrap()];
+        drained.sort();
+        assert_eq!(drained, vec!["feed", "journal"]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout_reports_pending() {
+        let shutdown = Shutdown::new();
+        let _stuck = shutdown.register("server");
+        assert_eq!(
+            shutdown.shutdown_timeout(Duration::from_millis(10)).await,
+            Err(vec!["server".to_string()])
+        );
+        // Late subscribers see the shutdown right away
+        shutdown.signal().triggered().await;
+    }
+}
@@ -0,0 +1,229 @@
+/// Order flow imbalance and trade flow signals.
+/// `FlowSignals` follows the top of book of one instrument and computes the order flow
+/// imbalance (Cont, Kukanov and Stoikov) of every change of the best levels: bid size added at
+/// an unchanged or better bid counts positive, bid size removed at an unchanged or worse bid
+/// negative, and the reverse on the ask side. Trades are signed by their aggressor. Both are
+/// summed over rolling time windows, every book change or trade yields a `FlowSignal` with the
+/// event and the sums of every window. Sizes are in base units.
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::{Side, Timestamp};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct FlowEvent {
+    ofi: f64,
+    buy_volume: f64,
+    sell_volume: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowFlow {
+    pub window: Duration,
+    pub ofi: f64,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+}
+
+impl WindowFlow {
+    // (buy - sell) / (buy + sell), from -1 to 1, None without trades
+    pub fn trade_imbalance(&self) -> Option<f64> {
+        let total = self.buy_volume + self.sell_volume;
+        (total > 0.0).then(|| (self.buy_volume - self.sell_volume) / total)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowSignal {
+    pub timestamp: Timestamp,
+    // Contribution of this book change, zero for a trade
+    pub ofi: f64,
+    // In the order the windows were configured
+    pub windows: Vec<WindowFlow>,
+}
+
+#[derive(Debug, Clone)]
+struct RollingFlow {
+    length: Duration,
+    events: VecDeque<(Timestamp, FlowEvent)>,
+    total: FlowEvent,
+}
+
+impl RollingFlow {
+    fn push(&mut self, timestamp: Timestamp, event: FlowEvent) {
+        self.events.push_back((timestamp, event));
+        self.total.ofi += event.ofi;
+        self.total.buy_volume += event.buy_volume;
+        self.total.sell_volume += event.sell_volume;
+        let length = self.length.as_nanos() as Timestamp;
+        while let Some(&(oldest, event)) = self.events.front() {
+            if timestamp.saturating_sub(oldest) < length {
+                break;
+            }
+            self.events.pop_front();
+            self.total.ofi -= event.ofi;
+            self.total.buy_volume -= event.buy_volume;
+            self.total.sell_volume -= event.sell_volume;
+        }
+        // Running sums drift, an empty window starts over from zero
+        if self.events.is_empty() {
+            self.total = FlowEvent::default();
+        }
+    }
+
+    fn flow(&self) -> WindowFlow {
+        WindowFlow {
+            window: self.length,
+            ofi: self.total.ofi,
+            buy_volume: self.total.buy_volume.max(0.0),
+            sell_volume: self.total.sell_volume.max(0.0),
+        }
+    }
+}
+
+// Best bid and best ask with their sizes
+type Touch = ((f64, f64), (f64, f64));
+
+#[derive(Debug, Clone)]
+pub struct FlowSignals {
+    windows: Vec<RollingFlow>,
+    touch: Option<Touch>,
+}
+
+impl FlowSignals {
+    pub fn new(windows: &[Duration]) -> FlowSignals {
+        FlowSignals {
+            windows: windows
+                .iter()
+                .map(|&length| RollingFlow {
+                    length,
+                    events: VecDeque::new(),
+                    total: FlowEvent::default(),
+                })
+                .collect(),
+            touch: None,
+        }
+    }
+
+    // Signal of a change of the best levels, None when they did not change or while a side
+    // is empty. The first complete touch only sets the reference.
+    pub fn on_book(&mut self, timestamp: Timestamp, book: &OrderBook) -> Option<FlowSignal> {
+        let converter = book.converter();
+        let level = |(price, qty): (_, _)| (converter.to_f64(price), converter.to_f64(qty));
+        let touch = (level(book.best_bid()?), level(book.best_ask()?));
+        self.on_touch(timestamp, touch)
+    }
+
+    // Same as `on_book` from the best bid and ask, (price, size) each
+    pub fn on_touch(&mut self, timestamp: Timestamp, touch: Touch) -> Option<FlowSignal> {
+        let previous = self.touch.replace(touch)?;
+        if previous == touch {
+            return None;
+        }
+        let ofi = order_flow_imbalance(previous, touch);
+        Some(self.push(
+            timestamp,
+            FlowEvent {
+                ofi,
+                ..FlowEvent::default()
+            },
+        ))
+    }
+
+    // A trade of `size` at the side of its aggressor
+    pub fn on_trade(&mut self, timestamp: Timestamp, aggressor: Side, size: f64) -> FlowSignal {
+        let event = match aggressor {
+            Side::Buy => FlowEvent {
+                buy_volume: size,
+                ..FlowEvent::default()
+            },
+            Side::Sell => FlowEvent {
+                sell_volume: size,
+                ..FlowEvent::default()
+            },
+        };
+        self.push(timestamp, event)
+    }
+
+    // Window sums as of the last event
+    pub fn windows(&self) -> Vec<WindowFlow> {
+        self.windows.iter().map(RollingFlow::flow).collect()
+    }
+
+    fn push(&mut self, timestamp: Timestamp, event: FlowEvent) -> FlowSignal {
+        for window in &mut self.windows {
+            window.push(timestamp, event);
+        }
+        FlowSignal {
+            timestamp,
+            ofi: event.ofi,
+            windows: self.windows(),
+        }
+    }
+}
+
+fn order_flow_imbalance(previous: Touch, current: Touch) -> f64 {
+    let ((previous_bid, previous_bid_size), (previous_ask, previous_ask_size)) = previous;
+    let ((bid, bid_size), (ask, ask_size)) = current;
+    let mut ofi = 0.0;
+    if bid >= previous_bid {
+        ofi += bid_size;
+    }
+    if bid <= previous_bid {
+        ofi -= previous_bid_size;
+    }
+    if ask <= previous_ask {
+        ofi -= ask_size;
+    }
+    if ask >= previous_ask {
+        ofi += previous_ask_size;
+    }
+    ofi
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_order_flow_imbalance() {
+        let mut signals = FlowSignals::new(&[Duration::from_secs(5), Duration::from_secs(60)]);
+        assert!(signals.on_touch(0, ((100.0, 2.0), (101.0, 3.0))).is_none());
+        assert!(signals
+            .on_touch(SECOND, ((100.0, 2.0), (101.0, 3.0)))
+            .is_none());
+
+        // Bid size added at the same price
+        let signal = signals
+            .on_touch(SECOND, ((100.0, 5.0), (101.0, 3.0)))
+            .unwrap();
+        assert_eq!(signal.ofi, 3.0);
+        // The bid improves, the whole new level counts
+        let signal = signals
+            .on_touch(2 * SECOND, ((100.5, 1.0), (101.0, 3.0)))
+            .unwrap();
+        assert_eq!(signal.ofi, 1.0);
+        // The ask drops to a new level, the whole new level counts against
+        let signal = signals
+            .on_touch(10 * SECOND, ((100.5, 1.0), (100.8, 4.0)))
+            .unwrap();
+        assert_eq!(signal.ofi, -4.0);
+        // The first two events left the 5 second window
+        assert_eq!(signal.windows[0].ofi, -4.0);
+        assert_eq!(signal.windows[1].ofi, 0.0);
+    }
+
+    #[test]
+    fn test_trade_flow() {
+        let mut signals = FlowSignals::new(&[Duration::from_secs(5)]);
+        assert_eq!(signals.windows()[0].trade_imbalance(), None);
+        signals.on_trade(0, Side::Buy, 3.0);
+        let signal = signals.on_trade(SECOND, Side::Sell, 1.0);
+        assert_eq!(signal.ofi, 0.0);
+        assert_eq!(signal.windows[0].trade_imbalance(), Some(0.5));
+        let signal = signals.on_trade(6 * SECOND, Side::Sell, 1.0);
+        assert_eq!(signal.windows[0].trade_imbalance(), Some(-1.0));
+    }
+}
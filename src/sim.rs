@@ -0,0 +1,423 @@
+/// Agent based market simulation on top of the matching engine.
+/// Every tick each agent looks at the book and sends orders, the engine matches them
+/// (respecting its latency model) and the agents get notified about trades and the new book state.
+use crate::events::EngineEvent;
+use crate::orderbookv2::{
+    Order, OrderBook, OrderBookLevelInfos, OrderId, OrderType, Price, Quantity, Side, Timestamp,
+    Trade,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::Duration;
+
+pub trait MarketAgent {
+    fn on_tick(&mut self, ctx: &mut AgentContext);
+
+    fn on_trade(&mut self, _timestamp: Timestamp, _trade: &Trade) {}
+
+    fn on_book_update(&mut self, _timestamp: Timestamp, _book: &OrderBookLevelInfos) {}
+}
+
+#[derive(Debug, Clone)]
+pub enum AgentAction {
+    Submit(Order),
+    Cancel(OrderId),
+}
+
+// Read access to the book plus an outbox for the orders an agent wants to send
+pub struct AgentContext<'a> {
+    book: &'a OrderBook,
+    next_order_id: &'a mut OrderId,
+    actions: Vec<AgentAction>,
+}
+
+impl<'a> AgentContext<'a> {
+    pub fn now(&self) -> Timestamp {
+        self.book.clock().now()
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        self.book
+    }
+
+    pub fn best_bid(&self) -> Option<Price> {
+        self.book.bids().next().map(|(price, _)| price)
+    }
+
+    pub fn best_ask(&self) -> Option<Price> {
+        self.book.asks().next().map(|(price, _)| price)
+    }
+
+    pub fn mid_price(&self) -> Option<Price> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some(bid.midpoint(ask)),
+            (Some(price), None) | (None, Some(price)) => Some(price),
+            (None, None) => None,
+        }
+    }
+
+    pub fn submit(
+        &mut self,
+        price: Price,
+        quantity: Quantity,
+        order_type: OrderType,
+        side: Side,
+    ) -> OrderId {
+        let order_id = *self.next_order_id;
+        *self.next_order_id += 1;
+        self.actions.push(AgentAction::Submit(Order::new(
+            order_id, price, quantity, order_type, side,
+        )));
+        order_id
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) {
+        self.actions.push(AgentAction::Cancel(order_id));
+    }
+}
+
+pub struct Simulation {
+    book: OrderBook,
+    agents: Vec<Box<dyn MarketAgent>>,
+    tick_interval: Duration,
+    next_order_id: OrderId,
+    tape: Vec<(Timestamp, Trade)>,
+    snapshots: Vec<(Timestamp, OrderBookLevelInfos)>,
+}
+
+impl Simulation {
+    pub fn new(book: OrderBook, tick_interval: Duration) -> Simulation {
+        Simulation {
+            book,
+            agents: Vec::new(),
+            tick_interval,
+            next_order_id: 1,
+            tape: Vec::new(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    pub fn add_agent(&mut self, agent: Box<dyn MarketAgent>) {
+        self.agents.push(agent);
+    }
+
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    pub fn step(&mut self) {
+        let first_new_trade = self.tape.len();
+
+        for agent in self.agents.iter_mut() {
+            let mut ctx = AgentContext {
+                book: &self.book,
+                next_order_id: &mut self.next_order_id,
+                actions: Vec::new(),
+            };
+            agent.on_tick(&mut ctx);
+
+            for action in ctx.actions {
+                match action {
+                    AgentAction::Submit(order) => {
+                        let now = self.book.clock().now();
+                        let trades = self.book.submit_order(order);
+                        self.tape
+                            .extend(trades.into_iter().map(|trade| (now, trade)));
+                    }
+                    AgentAction::Cancel(order_id) => self.book.submit_cancel(order_id),
+                }
+            }
+        }
+
+        let next_tick = self.book.clock().now() + self.tick_interval.as_nanos() as Timestamp;
+        let delayed_trades = self.book.advance_clock(next_tick);
+        self.tape.extend(delayed_trades);
+
+        for (timestamp, trade) in &self.tape[first_new_trade..] {
+            for agent in self.agents.iter_mut() {
+                agent.on_trade(*timestamp, trade);
+            }
+        }
+
+        let level_infos = self.book.get_orderbook_level_infos();
+        for agent in self.agents.iter_mut() {
+            agent.on_book_update(next_tick, &level_infos);
+        }
+        self.snapshots.push((next_tick, level_infos));
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    // Engine events of the run so far, e.g. candles when the book has a `CandleBuilder`
+    pub fn drain_events(&mut self) -> Vec<EngineEvent> {
+        self.book.drain_events()
+    }
+
+    pub fn tape(&self) -> &[(Timestamp, Trade)] {
+        &self.tape
+    }
+
+    pub fn snapshots(&self) -> &[(Timestamp, OrderBookLevelInfos)] {
+        &self.snapshots
+    }
+}
+
+// Sends random orders around the current price: mostly passive limit orders,
+// sometimes aggressive Fill and Kill orders crossing the spread
+pub struct NoiseTrader {
+    rng: StdRng,
+    reference_price: Price,
+    order_probability: f64,
+    aggressive_probability: f64,
+    max_quantity: Quantity,
+    max_offset: Price,
+}
+
+impl NoiseTrader {
+    pub fn new(seed: u64, reference_price: Price) -> NoiseTrader {
+        NoiseTrader {
+            rng: StdRng::seed_from_u64(seed),
+            reference_price,
+            order_probability: 0.5,
+            aggressive_probability: 0.3,
+            max_quantity: Quantity(10),
+            max_offset: Price(5),
+        }
+    }
+
+    pub fn with_probabilities(mut self, order: f64, aggressive: f64) -> NoiseTrader {
+        self.order_probability = order;
+        self.aggressive_probability = aggressive;
+        self
+    }
+
+    pub fn with_max_quantity(mut self, max_quantity: Quantity) -> NoiseTrader {
+        self.max_quantity = max_quantity;
+        self
+    }
+}
+
+impl MarketAgent for NoiseTrader {
+    fn on_tick(&mut self, ctx: &mut AgentContext) {
+        if !self.rng.gen_bool(self.order_probability) {
+            return;
+        }
+
+        let side = if self.rng.gen_bool(0.5) {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+        let quantity = Quantity(self.rng.gen_range(1..=self.max_quantity.0));
+
+        if self.rng.gen_bool(self.aggressive_probability) {
+            let touch = match side {
+                Side::Buy => ctx.best_ask(),
+                Side::Sell => ctx.best_bid(),
+            };
+            if let Some(price) = touch {
+                ctx.submit(price, quantity, OrderType::FillAndKill, side);
+            }
+            return;
+        }
+
+        let mid = ctx.mid_price().unwrap_or(self.reference_price);
+        let offset = Price(self.rng.gen_range(1..=self.max_offset.0));
+        let price = match side {
+            Side::Buy => mid - offset,
+            Side::Sell => mid + offset,
+        };
+        if price > Price::ZERO {
+            ctx.submit(price, quantity, OrderType::GoodToCancel, side);
+        }
+    }
+}
+
+// Keeps one bid and one ask around the mid price, re-quoting on every tick
+pub struct MarketMaker {
+    reference_price: Price,
+    half_spread: Price,
+    quantity: Quantity,
+    quotes: Vec<OrderId>,
+}
+
+impl MarketMaker {
+    pub fn new(reference_price: Price, half_spread: Price, quantity: Quantity) -> MarketMaker {
+        MarketMaker {
+            reference_price,
+            half_spread,
+            quantity,
+            quotes: Vec::new(),
+        }
+    }
+}
+
+impl MarketAgent for MarketMaker {
+    fn on_tick(&mut self, ctx: &mut AgentContext) {
+        for order_id in self.quotes.drain(..) {
+            ctx.cancel(order_id);
+        }
+
+        let mid = ctx.mid_price().unwrap_or(self.reference_price);
+        self.reference_price = mid;
+        let bid = ctx.submit(
+            mid - self.half_spread,
+            self.quantity,
+            OrderType::GoodToCancel,
+            Side::Buy,
+        );
+        let ask = ctx.submit(
+            mid + self.half_spread,
+            self.quantity,
+            OrderType::GoodToCancel,
+            Side::Sell,
+        );
+        self.quotes.extend([bid, ask]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candles::{Candle, CandleBuilder};
+    use crate::orderbookv2::LatencyModel;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn simulation() -> Simulation {
+        Simulation::new(OrderBook::new(), Duration::from_millis(100))
+    }
+
+    #[test]
+    fn test_market_maker_quotes_around_reference_price() {
+        let mut sim = simulation();
+        sim.add_agent(Box::new(MarketMaker::new(
+            Price(100),
+            Price(2),
+            Quantity(5),
+        )));
+        sim.run(3);
+
+        assert_eq!(sim.snapshots().len(), 3);
+        assert_eq!(
+            sim.book().bids().collect::<Vec<_>>(),
+            vec![(Price(98), Quantity(5))]
+        );
+        assert_eq!(
+            sim.book().asks().collect::<Vec<_>>(),
+            vec![(Price(102), Quantity(5))]
+        );
+        assert!(sim.tape().is_empty());
+    }
+
+    #[test]
+    fn test_noise_traders_trade_against_market_maker() {
+        let mut sim = simulation();
+        sim.add_agent(Box::new(MarketMaker::new(
+            Price(100),
+            Price(1),
+            Quantity(50),
+        )));
+        sim.add_agent(Box::new(
+            NoiseTrader::new(1, Price(100)).with_probabilities(1.0, 1.0),
+        ));
+        sim.run(20);
+
+        assert!(!sim.tape().is_empty());
+        assert_eq!(sim.snapshots().len(), 20);
+        for (_, trade) in sim.tape() {
+            assert_eq!(trade.bid_trade.quantity, trade.ask_trade.quantity);
+        }
+    }
+
+    #[test]
+    fn test_candles_from_simulated_trades() {
+        let mut book = OrderBook::new();
+        book.set_candle_builder(CandleBuilder::new(&[Duration::from_secs(1)]));
+        let mut sim = Simulation::new(book, Duration::from_millis(100));
+        sim.add_agent(Box::new(MarketMaker::new(
+            Price(100),
+            Price(1),
+            Quantity(50),
+        )));
+        sim.add_agent(Box::new(
+            NoiseTrader::new(1, Price(100)).with_probabilities(1.0, 1.0),
+        ));
+        sim.run(30);
+
+        let candles: Vec<Candle> = sim
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                EngineEvent::CandleClosed(candle) => Some(candle),
+                _ => None,
+            })
+            .collect();
+        assert!(!candles.is_empty());
+        let traded: u64 = sim
+            .tape()
+            .iter()
+            .filter(|(timestamp, _)| *timestamp < candles.last().unwrap().close_time())
+            .map(|(_, trade)| u64::from(trade.bid_trade.quantity))
+            .sum();
+        assert_eq!(candles.iter().map(|c| c.volume).sum::<u64>(), traded);
+    }
+
+    #[test]
+    fn test_simulation_is_reproducible() {
+        let run = || {
+            let mut sim = Simulation::new(
+                OrderBook::with_latency(LatencyModel::Fixed(Duration::from_millis(1)), 3),
+                Duration::from_millis(100),
+            );
+            sim.add_agent(Box::new(MarketMaker::new(
+                Price(100),
+                Price(1),
+                Quantity(20),
+            )));
+            sim.add_agent(Box::new(NoiseTrader::new(5, Price(100))));
+            sim.add_agent(Box::new(NoiseTrader::new(6, Price(100))));
+            sim.run(50);
+            sim.tape()
+                .iter()
+                .map(|(timestamp, trade)| (*timestamp, trade.bid_trade.order_id))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    struct TradeCounter {
+        trades: Rc<RefCell<usize>>,
+    }
+
+    impl MarketAgent for TradeCounter {
+        fn on_tick(&mut self, _ctx: &mut AgentContext) {}
+
+        fn on_trade(&mut self, _timestamp: Timestamp, _trade: &Trade) {
+            *self.trades.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn test_agents_are_notified_about_every_trade() {
+        let trades = Rc::new(RefCell::new(0));
+        let mut sim = simulation();
+        sim.add_agent(Box::new(MarketMaker::new(
+            Price(100),
+            Price(1),
+            Quantity(50),
+        )));
+        sim.add_agent(Box::new(
+            NoiseTrader::new(9, Price(100)).with_probabilities(1.0, 1.0),
+        ));
+        sim.add_agent(Box::new(TradeCounter {
+            trades: Rc::clone(&trades),
+        }));
+        sim.run(10);
+
+        assert_eq!(*trades.borrow(), sim.tape().len());
+    }
+}
@@ -0,0 +1,243 @@
+/// Rolling return and volatility statistics of a price series.
+/// `ReturnStatistics` takes the mid or trade prices of one instrument and keeps the log returns
+/// between consecutive distinct prices in rolling time windows, e.g. 1 and 5 minutes, with
+/// running sums so every getter is O(1) per tick. Realized volatility is the square root of
+/// the sum of the squared returns in a window, not annualized. The EWMA variance weighs every
+/// return with `1 - lambda` and decays the previous estimate by `lambda`, 0.94 is the
+/// RiskMetrics choice.
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::Timestamp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_WINDOWS: [Duration; 2] = [Duration::from_secs(60), Duration::from_secs(300)];
+pub const DEFAULT_EWMA_LAMBDA: f64 = 0.94;
+
+#[derive(Debug, Clone)]
+struct RollingWindow {
+    length: Duration,
+    returns: VecDeque<(Timestamp, f64)>,
+    sum: f64,
+    sum_of_squares: f64,
+}
+
+impl RollingWindow {
+    fn push(&mut self, timestamp: Timestamp, value: f64) {
+        self.returns.push_back((timestamp, value));
+        self.sum += value;
+        self.sum_of_squares += value * value;
+        let length = self.length.as_nanos() as Timestamp;
+        while let Some(&(oldest, value)) = self.returns.front() {
+            if timestamp.saturating_sub(oldest) < length {
+                break;
+            }
+            self.returns.pop_front();
+            self.sum -= value;
+            self.sum_of_squares -= value * value;
+        }
+        // Running sums drift, an empty window starts over from zero
+        if self.returns.is_empty() {
+            self.sum = 0.0;
+            self.sum_of_squares = 0.0;
+        }
+    }
+
+    fn statistics(&self) -> WindowStatistics {
+        let count = self.returns.len();
+        WindowStatistics {
+            window: self.length,
+            count,
+            mean: (count > 0).then(|| self.sum / count as f64),
+            realized_volatility: (count > 0).then(|| self.sum_of_squares.max(0.0).sqrt()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowStatistics {
+    pub window: Duration,
+    // Returns in the window
+    pub count: usize,
+    pub mean: Option<f64>,
+    pub realized_volatility: Option<f64>,
+}
+
+impl fmt::Display for WindowStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s: {} returns", self.window.as_secs(), self.count)?;
+        if let (Some(mean), Some(volatility)) = (self.mean, self.realized_volatility) {
+            write!(f, ", mean {:.3e}, realized vol {:.3e}", mean, volatility)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReturnStatistics {
+    windows: Vec<RollingWindow>,
+    lambda: f64,
+    last_price: Option<f64>,
+    last_return: Option<f64>,
+    ewma_variance: Option<f64>,
+    returns: u64,
+}
+
+impl Default for ReturnStatistics {
+    fn default() -> Self {
+        ReturnStatistics::new(&DEFAULT_WINDOWS, DEFAULT_EWMA_LAMBDA)
+    }
+}
+
+impl ReturnStatistics {
+    pub fn new(windows: &[Duration], lambda: f64) -> ReturnStatistics {
+        assert!(
+            (0.0..1.0).contains(&lambda),
+            "EWMA lambda must be in [0, 1)"
+        );
+        ReturnStatistics {
+            windows: windows
+                .iter()
+                .map(|&length| RollingWindow {
+                    length,
+                    returns: VecDeque::new(),
+                    sum: 0.0,
+                    sum_of_squares: 0.0,
+                })
+                .collect(),
+            lambda,
+            last_price: None,
+            last_return: None,
+            ewma_variance: None,
+            returns: 0,
+        }
+    }
+
+    // Records the return from the previous price, returns it. An unchanged price is not a
+    // tick and is ignored, so are prices that are not positive.
+    pub fn on_price(&mut self, timestamp: Timestamp, price: f64) -> Option<f64> {
+        if price.is_nan() || price <= 0.0 || self.last_price == Some(price) {
+            return None;
+        }
+        let previous = self.last_price.replace(price)?;
+        let value = (price / previous).ln();
+
+        for window in &mut self.windows {
+            window.push(timestamp, value);
+        }
+        let squared = value * value;
+        self.ewma_variance = Some(match self.ewma_variance {
+            Some(variance) => self.lambda * variance + (1.0 - self.lambda) * squared,
+            None => squared,
+        });
+        self.last_return = Some(value);
+        self.returns += 1;
+        Some(value)
+    }
+
+    // Samples the mid price, nothing while a side is empty
+    pub fn on_book(&mut self, timestamp: Timestamp, book: &OrderBook) -> Option<f64> {
+        self.on_price(timestamp, book.mid_price()?)
+    }
+
+    pub fn last_price(&self) -> Option<f64> {
+        self.last_price
+    }
+
+    // Log return of the last tick
+    pub fn last_return(&self) -> Option<f64> {
+        self.last_return
+    }
+
+    // Returns recorded since the start
+    pub fn len(&self) -> u64 {
+        self.returns
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.returns == 0
+    }
+
+    // None for a window that was not configured
+    pub fn window(&self, length: Duration) -> Option<WindowStatistics> {
+        self.windows
+            .iter()
+            .find(|window| window.length == length)
+            .map(RollingWindow::statistics)
+    }
+
+    pub fn windows(&self) -> Vec<WindowStatistics> {
+        self.windows.iter().map(RollingWindow::statistics).collect()
+    }
+
+    pub fn realized_volatility(&self, length: Duration) -> Option<f64> {
+        self.window(length)?.realized_volatility
+    }
+
+    pub fn ewma_variance(&self) -> Option<f64> {
+        self.ewma_variance
+    }
+
+    pub fn ewma_volatility(&self) -> Option<f64> {
+        self.ewma_variance.map(f64::sqrt)
+    }
+}
+
+impl fmt::Display for ReturnStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} returns", self.returns)?;
+        if let Some(volatility) = self.ewma_volatility() {
+            write!(f, ", EWMA vol {:.3e}", volatility)?;
+        }
+        for window in self.windows() {
+            write!(f, "; {}", window)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_rolling_windows_and_ewma() {
+        let mut statistics =
+            ReturnStatistics::new(&[Duration::from_secs(10), Duration::from_secs(60)], 0.5);
+        assert_eq!(statistics.on_price(0, 100.0), None);
+        assert_eq!(statistics.on_price(SECOND, 100.0), None);
+        let up = statistics.on_price(2 * SECOND, 110.0).unwrap();
+        assert!((up - (1.1f64).ln()).abs() < 1e-12);
+        let down = statistics.on_price(20 * SECOND, 100.0).unwrap();
+        assert_eq!(statistics.len(), 2);
+        assert_eq!(statistics.last_return(), Some(down));
+
+        // The first return left the 10 second window
+        let short = statistics.window(Duration::from_secs(10)).unwrap();
+        assert_eq!(short.count, 1);
+        assert!((short.realized_volatility.unwrap() - down.abs()).abs() < 1e-12);
+        let long = statistics.window(Duration::from_secs(60)).unwrap();
+        assert_eq!(long.count, 2);
+        assert!((long.mean.unwrap() - (up + down) / 2.0).abs() < 1e-12);
+        assert!(
+            (statistics
+                .realized_volatility(Duration::from_secs(60))
+                .unwrap()
+                - (up * up + down * down).sqrt())
+            .abs()
+                < 1e-12
+        );
+        assert!(statistics.window(Duration::from_secs(5)).is_none());
+
+        let expected = 0.5 * up * up + 0.5 * down * down;
+        assert!((statistics.ewma_variance().unwrap() - expected).abs() < 1e-12);
+
+        // Windows without returns report no volatility
+        assert_eq!(
+            ReturnStatistics::default().windows()[0].realized_volatility,
+            None
+        );
+    }
+}
@@ -0,0 +1,252 @@
+/// Stop orders for the matching engine.
+/// A stop order waits outside the book until its trigger reference reaches the stop price and
+/// is then entered as the order it carries. Venues and strategies disagree on what that
+/// reference is, so every stop picks its own: the last trade price, the touch it would trade
+/// against, or a mark price fed to the engine from outside, e.g. a futures mark price.
+use crate::orderbookv2::{Order, OrderId, Price, Side};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StopTrigger {
+    #[default]
+    LastTrade,
+    // Buy stops watch the best ask, sell stops the best bid
+    BestBidAsk,
+    // See `OrderBook::set_mark_price`
+    Mark,
+}
+
+// The references known to the engine at the time the stops are checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TriggerPrices {
+    pub last_trade: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    pub mark: Option<Price>,
+}
+
+impl TriggerPrices {
+    pub fn reference(&self, trigger: StopTrigger, side: Side) -> Option<Price> {
+        match (trigger, side) {
+            (StopTrigger::LastTrade, _) => self.last_trade,
+            (StopTrigger::BestBidAsk, Side::Buy) => self.best_ask,
+            (StopTrigger::BestBidAsk, Side::Sell) => self.best_bid,
+            (StopTrigger::Mark, _) => self.mark,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopOrder {
+    order: Order,
+    stop_price: Price,
+    trigger: StopTrigger,
+}
+
+impl StopOrder {
+    // Triggered by the last trade price unless `with_trigger` says otherwise
+    pub fn new(order: Order, stop_price: Price) -> StopOrder {
+        StopOrder {
+            order,
+            stop_price,
+            trigger: StopTrigger::default(),
+        }
+    }
+
+    pub fn with_trigger(mut self, trigger: StopTrigger) -> StopOrder {
+        self.trigger = trigger;
+        self
+    }
+
+    pub fn order(&self) -> &Order {
+        &self.order
+    }
+
+    pub fn order_id(&self) -> OrderId {
+        self.order.get_order_id()
+    }
+
+    pub fn stop_price(&self) -> Price {
+        self.stop_price
+    }
+
+    pub fn trigger(&self) -> StopTrigger {
+        self.trigger
+    }
+
+    // Buy stops trigger at or above the stop price, sell stops at or below it.
+    // Returns the reference price that did it.
+    pub fn triggered_by(&self, prices: &TriggerPrices) -> Option<Price> {
+        let side = self.order.get_side();
+        prices
+            .reference(self.trigger, side)
+            .filter(|reference| match side {
+                Side::Buy => *reference >= self.stop_price,
+                Side::Sell => *reference <= self.stop_price,
+            })
+    }
+
+    pub fn into_order(self) -> Order {
+        self.order
+    }
+}
+
+// Stops waiting for their trigger, in the order they were placed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StopOrders {
+    orders: Vec<StopOrder>,
+}
+
+impl StopOrders {
+    pub fn new() -> StopOrders {
+        StopOrders::default()
+    }
+
+    pub fn insert(&mut self, stop: StopOrder) {
+        self.orders.push(stop);
+    }
+
+    pub fn remove(&mut self, order_id: OrderId) -> Option<StopOrder> {
+        let index = self
+            .orders
+            .iter()
+            .position(|stop| stop.order_id() == order_id)?;
+        Some(self.orders.remove(index))
+    }
+
+    pub fn contains(&self, order_id: OrderId) -> bool {
+        self.orders.iter().any(|stop| stop.order_id() == order_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StopOrder> {
+        self.orders.iter()
+    }
+
+    // Removes the triggered stops and returns them with their reference price
+    pub fn take_triggered(&mut self, prices: &TriggerPrices) -> Vec<(StopOrder, Price)> {
+        let mut triggered = Vec::new();
+        let mut waiting = Vec::with_capacity(self.orders.len());
+        for stop in self.orders.drain(..) {
+            match stop.triggered_by(prices) {
+                Some(reference) => triggered.push((stop, reference)),
+                None => waiting.push(stop),
+            }
+        }
+        self.orders = waiting;
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EngineEvent;
+    use crate::orderbookv2::{OrderBook, OrderType, Quantity};
+
+    fn order(order_id: u64, side: Side, price: i32, quantity: u32) -> Order {
+        Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            OrderType::GoodToCancel,
+            side,
+        )
+    }
+
+    #[test]
+    fn test_trigger_reference_by_side() {
+        let prices = TriggerPrices {
+            last_trade: Some(Price(100)),
+            best_bid: Some(Price(98)),
+            best_ask: Some(Price(102)),
+            mark: None,
+        };
+        let buy =
+            |trigger| StopOrder::new(order(1, Side::Buy, 105, 1), Price(101)).with_trigger(trigger);
+        let sell =
+            |trigger| StopOrder::new(order(2, Side::Sell, 95, 1), Price(99)).with_trigger(trigger);
+
+        assert_eq!(buy(StopTrigger::LastTrade).triggered_by(&prices), None);
+        assert_eq!(
+            buy(StopTrigger::BestBidAsk).triggered_by(&prices),
+            Some(Price(102))
+        );
+        assert_eq!(sell(StopTrigger::LastTrade).triggered_by(&prices), None);
+        assert_eq!(
+            sell(StopTrigger::BestBidAsk).triggered_by(&prices),
+            Some(Price(98))
+        );
+        // No mark price fed yet
+        assert_eq!(buy(StopTrigger::Mark).triggered_by(&prices), None);
+    }
+
+    #[test]
+    fn test_engine_triggers_stops_by_their_reference() {
+        let mut orderbook = OrderBook::new();
+        orderbook.place_order(order(1, Side::Sell, 101, 5)).unwrap();
+        orderbook.place_order(order(2, Side::Sell, 102, 5)).unwrap();
+        orderbook.place_order(order(3, Side::Buy, 95, 5)).unwrap();
+
+        let stop = |order_id, trigger| {
+            StopOrder::new(order(order_id, Side::Buy, 110, 1), Price(101)).with_trigger(trigger)
+        };
+        // The best ask already reaches the stop price
+        let trades = orderbook
+            .place_stop_order(stop(10, StopTrigger::BestBidAsk))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].bid_trade.order_id, 10);
+
+        // Which printed at the stop price
+        let trades = orderbook
+            .place_stop_order(stop(11, StopTrigger::LastTrade))
+            .unwrap();
+        assert_eq!(trades[0].bid_trade.order_id, 11);
+        orderbook
+            .place_stop_order(stop(12, StopTrigger::Mark))
+            .unwrap();
+        assert_eq!(orderbook.stop_orders().len(), 1);
+        assert!(orderbook.stop_orders().contains(12));
+
+        assert!(orderbook.set_mark_price(Price(100)).is_empty());
+        let trades = orderbook.set_mark_price(Price(101));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].bid_trade.order_id, 12);
+        assert!(orderbook.stop_orders().is_empty());
+
+        let triggered: Vec<_> = orderbook
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                EngineEvent::StopTriggered {
+                    order_id, trigger, ..
+                } => Some((order_id, trigger)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            triggered,
+            vec![
+                (10, StopTrigger::BestBidAsk),
+                (11, StopTrigger::LastTrade),
+                (12, StopTrigger::Mark)
+            ]
+        );
+
+        let sell_stop = StopOrder::new(order(20, Side::Sell, 90, 1), Price(90));
+        orderbook.place_stop_order(sell_stop).unwrap();
+        assert_eq!(
+            orderbook.cancel_stop_order(20).map(|stop| stop.order_id()),
+            Some(20)
+        );
+        assert!(orderbook.cancel_stop_order(20).is_none());
+    }
+}
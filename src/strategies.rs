@@ -0,0 +1,224 @@
+/// Reference strategies for the strategy runtime.
+/// `QuotingMarketMaker` quotes both sides around the mid price and shifts its quotes against
+/// the inventory it builds up, `Twap` works a parent order in equal slices over time. Both
+/// only use the `Strategy` callbacks, so they run unchanged on the paper exchange and on the
+/// matching engine. Prices and quantities are in engine units, i.e. the units of the book's
+/// `PriceConverter`.
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::{OrderId, OrderType, Price, Quantity, Side};
+use crate::strategy::{Fill, Strategy, StrategyContext, TimerId};
+use std::time::Duration;
+
+fn best_prices(book: &OrderBook) -> Option<(Price, Price)> {
+    let (bid, _) = book.bids().next()?;
+    let (ask, _) = book.asks().next()?;
+    Some((Price(bid.0 as i32), Price(ask.0 as i32)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketMakerConfig {
+    // Distance of each quote from the mid price
+    pub half_spread: Price,
+    pub quantity: Quantity,
+    // A side is not quoted when a fill there could take the inventory past this
+    pub max_inventory: i64,
+    // Price shift of both quotes per `quantity` of inventory, towards reducing it
+    pub skew: Price,
+}
+
+#[derive(Debug)]
+pub struct QuotingMarketMaker {
+    config: MarketMakerConfig,
+    // Positive when long
+    inventory: i64,
+    quoted_mid: Option<Price>,
+    quotes: Vec<OrderId>,
+}
+
+impl QuotingMarketMaker {
+    pub fn new(config: MarketMakerConfig) -> QuotingMarketMaker {
+        QuotingMarketMaker {
+            config,
+            inventory: 0,
+            quoted_mid: None,
+            quotes: Vec::new(),
+        }
+    }
+
+    pub fn inventory(&self) -> i64 {
+        self.inventory
+    }
+
+    // Resting quotes, bid first when both sides are quoted
+    pub fn quotes(&self) -> &[OrderId] {
+        &self.quotes
+    }
+
+    // Bid and ask around `mid` for the current inventory, `None` for a side that is not quoted
+    pub fn quote_prices(&self, mid: Price) -> (Option<Price>, Option<Price>) {
+        let config = &self.config;
+        let per_quantity = i64::from(config.quantity.max(Quantity(1)));
+        let skew = Price((self.inventory * i64::from(config.skew) / per_quantity) as i32);
+        let center = mid - skew;
+        let quantity = i64::from(config.quantity);
+        let bid = (self.inventory + quantity <= config.max_inventory)
+            .then_some(center - config.half_spread);
+        let ask = (self.inventory - quantity >= -config.max_inventory)
+            .then_some(center + config.half_spread);
+        (bid, ask)
+    }
+
+    fn requote(&mut self, ctx: &mut StrategyContext, mid: Price) {
+        for order_id in self.quotes.drain(..) {
+            ctx.cancel(order_id);
+        }
+        self.quoted_mid = Some(mid);
+
+        let (bid, ask) = self.quote_prices(mid);
+        for (side, price) in [(Side::Buy, bid), (Side::Sell, ask)] {
+            let Some(price) = price else {
+                continue;
+            };
+            match ctx.submit(side, price, self.config.quantity, OrderType::GoodToCancel) {
+                Ok(order_id) => self.quotes.push(order_id),
+                Err(rejected) => log::warn!("{:?} quote rejected: {}", side, rejected),
+            }
+        }
+    }
+}
+
+impl Strategy for QuotingMarketMaker {
+    fn on_book_update(&mut self, ctx: &mut StrategyContext, book: &OrderBook) {
+        let Some((bid, ask)) = best_prices(book) else {
+            return;
+        };
+        let mid = bid.midpoint(ask);
+        if self.quoted_mid != Some(mid) {
+            self.requote(ctx, mid);
+        }
+    }
+
+    fn on_fill(&mut self, ctx: &mut StrategyContext, fill: &Fill) {
+        match fill.side {
+            Side::Buy => self.inventory += i64::from(fill.quantity),
+            Side::Sell => self.inventory -= i64::from(fill.quantity),
+        }
+        // The skew changed, quote again around the last mid
+        if let Some(mid) = self.quoted_mid {
+            self.requote(ctx, mid);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwapConfig {
+    pub side: Side,
+    pub quantity: Quantity,
+    pub slices: u32,
+    pub interval: Duration,
+    // Worst price a slice may trade at
+    pub limit: Price,
+}
+
+// Sends a Fill and Kill slice every `interval`, what a slice could not fill is added to the
+// next one. The first slice goes out on the first book update.
+#[derive(Debug)]
+pub struct Twap {
+    config: TwapConfig,
+    filled: Quantity,
+    slices_sent: u32,
+    timer: Option<TimerId>,
+}
+
+impl Twap {
+    pub fn new(config: TwapConfig) -> Twap {
+        Twap {
+            config,
+            filled: Quantity::ZERO,
+            slices_sent: 0,
+            timer: None,
+        }
+    }
+
+    pub fn filled(&self) -> Quantity {
+        self.filled
+    }
+
+    pub fn remaining(&self) -> Quantity {
+        self.config.quantity - self.filled
+    }
+
+    pub fn slices_sent(&self) -> u32 {
+        self.slices_sent
+    }
+
+    // Everything filled or every slice sent
+    pub fn is_done(&self) -> bool {
+        self.remaining().is_zero() || self.slices_sent >= self.config.slices
+    }
+
+    fn send_slice(&mut self, ctx: &mut StrategyContext) {
+        if self.is_done() {
+            return;
+        }
+        let slices_left = self.config.slices - self.slices_sent;
+        let quantity = Quantity(self.remaining().0.div_ceil(slices_left));
+        self.slices_sent += 1;
+        if let Err(rejected) = ctx.submit(
+            self.config.side,
+            self.config.limit,
+            quantity,
+            OrderType::FillAndKill,
+        ) {
+            log::warn!("TWAP slice rejected: {}", rejected);
+        }
+        if !self.is_done() {
+            self.timer = Some(ctx.schedule(self.config.interval));
+        }
+    }
+}
+
+impl Strategy for Twap {
+    fn on_book_update(&mut self, ctx: &mut StrategyContext, _book: &OrderBook) {
+        if self.slices_sent == 0 {
+            self.send_slice(ctx);
+        }
+    }
+
+    fn on_timer(&mut self, ctx: &mut StrategyContext, timer: TimerId) {
+        if self.timer == Some(timer) {
+            self.send_slice(ctx);
+        }
+    }
+
+    fn on_fill(&mut self, _ctx: &mut StrategyContext, fill: &Fill) {
+        self.filled += fill.quantity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quotes_skew_against_inventory() {
+        let mut maker = QuotingMarketMaker::new(MarketMakerConfig {
+            half_spread: Price(10),
+            quantity: Quantity(100),
+            max_inventory: 200,
+            skew: Price(4),
+        });
+        assert_eq!(
+            maker.quote_prices(Price(1_000)),
+            (Some(Price(990)), Some(Price(1_010)))
+        );
+
+        maker.inventory = 200;
+        assert_eq!(maker.quote_prices(Price(1_000)), (None, Some(Price(1_002))));
+        maker.inventory = -100;
+        assert_eq!(
+            maker.quote_prices(Price(1_000)),
+            (Some(Price(994)), Some(Price(1_014)))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+/// Strategies and the event loop that runs them.
+/// A `Strategy` reacts to book updates, trades, its own timers and the fills of its orders.
+/// `Runtime` owns the L2 book of one symbol, the strategy and an `ExecutionVenue` (the paper
+/// exchange or the matching engine) and dispatches every market event on a single thread:
+/// the event updates the book and the venue, the strategy is called, and the fills its orders
+/// got are delivered before the next event. Timers fire on the runtime's clock once they are
+/// due, checked after every event and on `poll_timers`.
+use crate::binance_payloads::{
+    BookTickerUpdate, BookTickerUpdateEnvelope, DepthUpdate, DepthUpdateEnvelope, TradeUpdate,
+    TradeUpdateEnvelope,
+};
+use crate::clock::{self, Nanos, SharedClock};
+use crate::orderbook::OrderBook;
+use crate::orderbookv2::{
+    self, Liquidity, NewOrder, OrderAck, OrderId, OrderType, Price, Quantity, Rejected, Side,
+    Trade, TradeInfo,
+};
+use crate::paper::PaperExchange;
+use crate::symbol::Symbol;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::time::Duration;
+
+pub type TimerId = u64;
+
+// Execution of one of the strategy's orders, in engine units
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub liquidity: Liquidity,
+    pub fee: f64,
+    // Engine timestamp of the trade
+    pub timestamp: Nanos,
+}
+
+pub trait Strategy {
+    fn on_book_update(&mut self, _ctx: &mut StrategyContext, _book: &OrderBook) {}
+
+    fn on_trade(&mut self, _ctx: &mut StrategyContext, _trade: &TradeUpdate) {}
+
+    fn on_timer(&mut self, _ctx: &mut StrategyContext, _timer: TimerId) {}
+
+    fn on_fill(&mut self, _ctx: &mut StrategyContext, _fill: &Fill) {}
+}
+
+// Where the orders of a strategy go. The paper exchange fills them against the market data
+// it is given, the matching engine matches them against the other participants.
+pub trait ExecutionVenue {
+    fn add_client_order(&mut self, request: NewOrder) -> Result<OrderAck, Rejected>;
+
+    fn cancel_order(&mut self, order_id: OrderId);
+
+    // Fills of resting orders caused by the market data
+    fn on_depth(&mut self, _update: &DepthUpdate) -> Vec<Trade> {
+        vec![]
+    }
+
+    fn on_trade(&mut self, _trade: &TradeUpdate) -> Vec<Trade> {
+        vec![]
+    }
+}
+
+impl ExecutionVenue for PaperExchange {
+    fn add_client_order(&mut self, request: NewOrder) -> Result<OrderAck, Rejected> {
+        PaperExchange::add_client_order(self, request)
+    }
+
+    fn cancel_order(&mut self, order_id: OrderId) {
+        PaperExchange::cancel_order(self, order_id)
+    }
+
+    fn on_depth(&mut self, update: &DepthUpdate) -> Vec<Trade> {
+        PaperExchange::on_depth(self, update)
+    }
+
+    fn on_trade(&mut self, trade: &TradeUpdate) -> Vec<Trade> {
+        PaperExchange::on_trade(self, trade)
+    }
+}
+
+impl ExecutionVenue for orderbookv2::OrderBook {
+    fn add_client_order(&mut self, request: NewOrder) -> Result<OrderAck, Rejected> {
+        orderbookv2::OrderBook::add_client_order(self, request)
+    }
+
+    // Orders that already left the book are skipped
+    fn cancel_order(&mut self, order_id: OrderId) {
+        orderbookv2::OrderBook::submit_cancel(self, order_id)
+    }
+}
+
+// Read access to the book plus order entry and timers, valid for one callback
+pub struct StrategyContext<'a> {
+    book: &'a OrderBook,
+    venue: &'a mut dyn ExecutionVenue,
+    state: &'a mut RuntimeState,
+}
+
+impl<'a> StrategyContext<'a> {
+    // Monotonic reading of the runtime's clock
+    pub fn now(&self) -> Nanos {
+        self.state.clock.monotonic()
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        self.book
+    }
+
+    // Trades of the order are delivered to `on_fill` after the current callback returns
+    pub fn submit(
+        &mut self,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        order_type: OrderType,
+    ) -> Result<OrderId, Rejected> {
+        self.state.next_client_order_id += 1;
+        let ack = self.venue.add_client_order(NewOrder {
+            client_order_id: format!("strategy-{}", self.state.next_client_order_id),
+            account_id: self.state.account_id,
+            price,
+            quantity,
+            order_type,
+            side,
+        })?;
+        self.state.order_ids.insert(ack.order_id);
+        self.state.queue_fills(ack.trades);
+        Ok(ack.order_id)
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) {
+        self.venue.cancel_order(order_id);
+    }
+
+    // One shot timer, `on_timer` is called with the returned id once `after` has elapsed
+    pub fn schedule(&mut self, after: Duration) -> TimerId {
+        self.state.next_timer_id += 1;
+        let due = self.now() + after.as_nanos() as Nanos;
+        self.state
+            .timers
+            .push(Reverse((due, self.state.next_timer_id)));
+        self.state.next_timer_id
+    }
+}
+
+#[derive(Debug)]
+struct RuntimeState {
+    clock: SharedClock,
+    account_id: orderbookv2::AccountId,
+    // Orders sent by the strategy, to tell its legs apart in the trades of the venue
+    order_ids: HashSet<OrderId>,
+    next_client_order_id: u64,
+    timers: BinaryHeap<Reverse<(Nanos, TimerId)>>,
+    next_timer_id: TimerId,
+    fills: Vec<Fill>,
+}
+
+impl RuntimeState {
+    fn queue_fills(&mut self, trades: Vec<Trade>) {
+        for trade in trades {
+            for (side, leg) in [
+                (Side::Buy, &trade.bid_trade),
+                (Side::Sell, &trade.ask_trade),
+            ] {
+                if self.order_ids.contains(&leg.order_id) {
+                    self.fills.push(fill(side, leg, trade.timestamp));
+                }
+            }
+        }
+    }
+}
+
+fn fill(side: Side, leg: &TradeInfo, timestamp: Nanos) -> Fill {
+    Fill {
+        order_id: leg.order_id,
+        side,
+        price: leg.price,
+        quantity: leg.quantity,
+        liquidity: leg.liquidity,
+        fee: leg.fee,
+        timestamp,
+    }
+}
+
+pub struct Runtime<S: Strategy, V: ExecutionVenue> {
+    book: OrderBook,
+    strategy: S,
+    venue: V,
+    state: RuntimeState,
+}
+
+impl<S: Strategy, V: ExecutionVenue> Runtime<S, V> {
+    pub fn new(symbol: impl Into<Symbol>, strategy: S, venue: V) -> Runtime<S, V> {
+        Runtime {
+            book: OrderBook::new(symbol),
+            strategy,
+            venue,
+            state: RuntimeState {
+                clock: clock::system(),
+                account_id: 0,
+                order_ids: HashSet::new(),
+                next_client_order_id: 0,
+                timers: BinaryHeap::new(),
+                next_timer_id: 0,
+                fills: Vec::new(),
+            },
+        }
+    }
+
+    // Account the orders are sent for, matters when the venue is the matching engine
+    pub fn with_account(mut self, account_id: orderbookv2::AccountId) -> Runtime<S, V> {
+        self.state.account_id = account_id;
+        self
+    }
+
+    // Time source of the timers, the system clock by default
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.book.set_clock(clock.clone());
+        self.state.clock = clock;
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    pub fn venue(&self) -> &V {
+        &self.venue
+    }
+
+    pub fn venue_mut(&mut self) -> &mut V {
+        &mut self.venue
+    }
+
+    // Dispatches combined stream payloads until the feed ends
+    pub fn run(&mut self, payloads: impl IntoIterator<Item = Vec<u8>>) {
+        for payload in payloads {
+            self.handle_payload(&payload);
+        }
+    }
+
+    // Depth, trade and book ticker payloads of the combined stream, payloads of other symbols
+    // are ignored
+    pub fn handle_payload(&mut self, payload: &[u8]) {
+        if let Ok(envelope) = serde_json::from_slice::<DepthUpdateEnvelope>(payload) {
+            if self.follows(&envelope.stream) {
+                self.on_depth(&envelope.data);
+            }
+        } else if let Ok(envelope) = serde_json::from_slice::<TradeUpdateEnvelope>(payload) {
+            if self.follows(&envelope.stream) {
+                self.on_trade(&envelope.data);
+            }
+        } else if let Ok(envelope) = serde_json::from_slice::<BookTickerUpdateEnvelope>(payload) {
+            if self.follows(&envelope.stream) {
+                self.on_book_ticker(&envelope.data);
+            }
+        } else {
+            log::debug!("Ignoring websocket message");
+        }
+    }
+
+    fn follows(&self, stream: &str) -> bool {
+        stream
+            .split('@')
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case(self.book.symbol().as_str()))
+    }
+
+    pub fn on_depth(&mut self, update: &DepthUpdate) {
+        self.book.update_depth(update);
+        let trades = self.venue.on_depth(update);
+        self.state.queue_fills(trades);
+        self.dispatch(|strategy, ctx| strategy.on_book_update(ctx, ctx.book));
+    }
+
+    pub fn on_book_ticker(&mut self, update: &BookTickerUpdate) {
+        if let Err(error) = self.book.update_book_ticker(update) {
+            log::error!("Invalid book ticker update: {}", error);
+            return;
+        }
+        self.dispatch(|strategy, ctx| strategy.on_book_update(ctx, ctx.book));
+    }
+
+    pub fn on_trade(&mut self, trade: &TradeUpdate) {
+        let trades = self.venue.on_trade(trade);
+        self.state.queue_fills(trades);
+        self.dispatch(|strategy, ctx| strategy.on_trade(ctx, trade));
+    }
+
+    // Fires the timers that are due, to be called periodically when the feed is quiet
+    pub fn poll_timers(&mut self) {
+        let now = self.state.clock.monotonic();
+        while let Some(&Reverse((due, timer))) = self.state.timers.peek() {
+            if due > now {
+                break;
+            }
+            self.state.timers.pop();
+            self.callback(|strategy, ctx| strategy.on_timer(ctx, timer));
+        }
+    }
+
+    fn dispatch(&mut self, callback: impl FnOnce(&mut S, &mut StrategyContext)) {
+        // Fills caused by the event come before the event itself
+        self.deliver_fills();
+        self.callback(callback);
+        self.poll_timers();
+    }
+
+    fn callback(&mut self, callback: impl FnOnce(&mut S, &mut StrategyContext)) {
+        let mut ctx = StrategyContext {
+            book: &self.book,
+            venue: &mut self.venue,
+            state: &mut self.state,
+        };
+        callback(&mut self.strategy, &mut ctx);
+        self.deliver_fills();
+    }
+
+    // Fills can lead to new orders and fills, they are delivered until none are left
+    fn deliver_fills(&mut self) {
+        while !self.state.fills.is_empty() {
+            for fill in std::mem::take(&mut self.state.fills) {
+                let mut ctx = StrategyContext {
+                    book: &self.book,
+                    venue: &mut self.venue,
+                    state: &mut self.state,
+                };
+                self.strategy.on_fill(&mut ctx, &fill);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::Arc;
+
+    // Joins the best bid once, then crosses the spread after every passive fill
+    #[derive(Default)]
+    struct Joiner {
+        fills: Vec<Fill>,
+        timers: Vec<TimerId>,
+        trades: usize,
+    }
+
+    impl Strategy for Joiner {
+        fn on_book_update(&mut self, ctx: &mut StrategyContext, book: &OrderBook) {
+            if self.timers.is_empty() {
+                let (bid, _) = book.bids().next().unwrap();
+                ctx.submit(
+                    Side::Buy,
+                    Price(bid.0 as i32),
+                    Quantity(10_000),
+                    OrderType::GoodToCancel,
+                )
+                .unwrap();
+                let timer = ctx.schedule(Duration::from_secs(1));
+                self.timers.push(timer);
+            }
+        }
+
+        fn on_trade(&mut self, _ctx: &mut StrategyContext, _trade: &TradeUpdate) {
+            self.trades += 1;
+        }
+
+        fn on_timer(&mut self, _ctx: &mut StrategyContext, timer: TimerId) {
+            self.timers.push(timer);
+        }
+
+        fn on_fill(&mut self, ctx: &mut StrategyContext, fill: &Fill) {
+            if fill.liquidity == Liquidity::Maker {
+                ctx.submit(
+                    Side::Buy,
+                    Price(102_000),
+                    Quantity(10_000),
+                    OrderType::FillAndKill,
+                )
+                .unwrap();
+            }
+            self.fills.push(fill.clone());
+        }
+    }
+
+    #[test]
+    fn test_runtime_with_paper_exchange() {
+        let clock = MockClock::new(0);
+        let mut runtime = Runtime::new("BNBUSDT", Joiner::default(), PaperExchange::new("BNBUSDT"));
+        runtime.set_clock(Arc::new(clock.clone()));
+
+        runtime.run(vec![
+            br#"{"stream":"bnbusdt@depth20@100ms","data":{"lastUpdateId":1,"bids":[["10.0","1.0"]],"asks":[["10.1","3.0"]]}}"#.to_vec(),
+            // Other symbols are ignored
+            br#"{"stream":"ethusdt@trade","data":{"e":"trade","E":1,"s":"ETHUSDT","t":1,"p":"9.0","q":"1.0","T":1,"m":true,"M":true}}"#.to_vec(),
+            br#"{"stream":"bnbusdt@trade","data":{"e":"trade","E":2,"s":"BNBUSDT","t":2,"p":"10.0","q":"2.0","T":2,"m":true,"M":true}}"#.to_vec(),
+        ]);
+        let strategy = runtime.strategy();
+        assert_eq!(strategy.trades, 1);
+        // The passive fill, then the order it triggered
+        assert_eq!(strategy.fills.len(), 2);
+        assert_eq!(strategy.fills[0].price, Price(100_000));
+        assert_eq!(strategy.fills[1].liquidity, Liquidity::Taker);
+        assert_eq!(strategy.fills[1].price, Price(101_000));
+        assert_eq!(strategy.timers.len(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        runtime.poll_timers();
+        assert_eq!(runtime.strategy().timers, vec![1, 1]);
+    }
+
+    #[test]
+    fn test_runtime_with_matching_engine() {
+        let mut engine = orderbookv2::OrderBook::new();
+        engine.add_order(orderbookv2::Order::new(
+            1_000,
+            Price(100_000),
+            Quantity(30_000),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        let mut runtime = Runtime::new("BNBUSDT", Joiner::default(), engine).with_account(7);
+        runtime.on_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids: vec![(10.0, 1.0)],
+            asks: vec![],
+        });
+        // The engine matched the order against the resting sell right away
+        let fills = &runtime.strategy().fills;
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].liquidity, Liquidity::Taker);
+        assert_eq!(fills[0].quantity, Quantity(10_000));
+        assert_eq!(
+            runtime.venue().get_volume_at(Side::Sell, Price(100_000)),
+            Quantity(20_000)
+        );
+    }
+}
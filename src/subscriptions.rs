@@ -0,0 +1,282 @@
+/// Runtime subscriptions of a combined Binance websocket connection.
+/// Streams are added and removed with `SUBSCRIBE` / `UNSUBSCRIBE` frames sent on the open
+/// connection. `Subscriptions` builds the frames, tracks which streams are active and matches
+/// the replies of the server to the requests by id: a rejected request is rolled back so the
+/// active set follows what the server actually streams.
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamKind {
+    // Top `levels` (5, 10 or 20) every 100ms
+    PartialDepth { levels: u16 },
+    BookTicker,
+    Trade,
+}
+
+impl StreamKind {
+    // Whether the stream feeds the L2 book
+    pub fn feeds_book(&self) -> bool {
+        match self {
+            StreamKind::PartialDepth { .. } | StreamKind::BookTicker => true,
+            StreamKind::Trade => false,
+        }
+    }
+
+    // `<symbol>@<stream>` as used in the frames and in the combined stream envelope
+    pub fn stream_name(&self, symbol: Symbol) -> String {
+        let symbol = symbol.as_str().to_ascii_lowercase();
+        match self {
+            StreamKind::PartialDepth { levels } => format!("{}@depth{}@100ms", symbol, levels),
+            StreamKind::BookTicker => format!("{}@bookTicker", symbol),
+            StreamKind::Trade => format!("{}@trade", symbol),
+        }
+    }
+
+    // Inverse of `stream_name`, with the symbol in upper case
+    pub fn parse(stream: &str) -> Option<(Symbol, StreamKind)> {
+        let (symbol, kind) = stream.split_once('@')?;
+        let kind = match kind {
+            "bookTicker" => StreamKind::BookTicker,
+            "trade" => StreamKind::Trade,
+            _ => StreamKind::PartialDepth {
+                levels: kind
+                    .strip_prefix("depth")?
+                    .strip_suffix("@100ms")?
+                    .parse()
+                    .ok()?,
+            },
+        };
+        Some((Symbol::intern(&symbol.to_ascii_uppercase()), kind))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Method {
+    #[serde(rename = "SUBSCRIBE")]
+    Subscribe,
+    #[serde(rename = "UNSUBSCRIBE")]
+    Unsubscribe,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriptionFrame {
+    #[serde(skip)]
+    pub symbol: Symbol,
+    pub method: Method,
+    pub params: Vec<String>,
+    pub id: u64,
+}
+
+impl SubscriptionFrame {
+    // Text frame to send on the connection
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Subscription frame serialization failed")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionError {
+    pub frame: SubscriptionFrame,
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} rejected with code {}: {}",
+            self.frame.method, self.frame.params, self.code, self.message
+        )
+    }
+}
+
+impl std::error::Error for SubscriptionError {}
+
+#[derive(Debug, Deserialize)]
+struct ServerError {
+    code: i64,
+    msg: String,
+}
+
+// `{"result":null,"id":1}` or `{"error":{"code":2,"msg":"..."},"id":1}`
+#[derive(Debug, Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    error: Option<ServerError>,
+}
+
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    active: BTreeSet<String>,
+    pending: HashMap<u64, SubscriptionFrame>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Subscriptions {
+        Subscriptions::default()
+    }
+
+    // Streams requested and not rejected, sorted by name
+    pub fn active(&self) -> impl Iterator<Item = &str> + '_ {
+        self.active.iter().map(String::as_str)
+    }
+
+    pub fn is_active(&self, symbol: Symbol, kind: StreamKind) -> bool {
+        self.active.contains(&kind.stream_name(symbol))
+    }
+
+    // Whether a stream that feeds the book of the symbol is still active
+    pub fn feeds_book(&self, symbol: Symbol) -> bool {
+        let prefix = format!("{}@", symbol.as_str().to_ascii_lowercase());
+        self.active
+            .iter()
+            .filter_map(|stream| stream.strip_prefix(&prefix))
+            .any(|stream| stream.starts_with("depth") || stream == "bookTicker")
+    }
+
+    // Requests sent and not answered yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Frame for the streams that are not active yet, `None` when there is nothing to send
+    pub fn subscribe(&mut self, symbol: Symbol, kinds: &[StreamKind]) -> Option<SubscriptionFrame> {
+        let params: Vec<String> = kinds
+            .iter()
+            .map(|kind| kind.stream_name(symbol))
+            .filter(|stream| !self.active.contains(stream))
+            .collect();
+        self.request(symbol, Method::Subscribe, params)
+    }
+
+    // Frame for the streams that are active, `None` when there is nothing to send
+    pub fn unsubscribe(
+        &mut self,
+        symbol: Symbol,
+        kinds: &[StreamKind],
+    ) -> Option<SubscriptionFrame> {
+        let params: Vec<String> = kinds
+            .iter()
+            .map(|kind| kind.stream_name(symbol))
+            .filter(|stream| self.active.contains(stream))
+            .collect();
+        self.request(symbol, Method::Unsubscribe, params)
+    }
+
+    fn request(
+        &mut self,
+        symbol: Symbol,
+        method: Method,
+        mut params: Vec<String>,
+    ) -> Option<SubscriptionFrame> {
+        params.dedup();
+        if params.is_empty() {
+            return None;
+        }
+        self.next_id += 1;
+        let frame = SubscriptionFrame {
+            symbol,
+            method,
+            params,
+            id: self.next_id,
+        };
+        self.update_active(frame.method, &frame.params);
+        self.pending.insert(frame.id, frame.clone());
+        Some(frame)
+    }
+
+    fn update_active(&mut self, method: Method, streams: &[String]) {
+        for stream in streams {
+            match method {
+                Method::Subscribe => self.active.insert(stream.clone()),
+                Method::Unsubscribe => self.active.remove(stream),
+            };
+        }
+    }
+
+    // Handles a reply to one of our requests. `None` when the payload is not a reply we are
+    // waiting for, otherwise the answered frame or the rejection, which is rolled back.
+    pub fn on_response(
+        &mut self,
+        payload: &[u8],
+    ) -> Option<Result<SubscriptionFrame, SubscriptionError>> {
+        let response: Response = serde_json::from_slice(payload).ok()?;
+        let frame = self.pending.remove(&response.id)?;
+        match response.error {
+            None => Some(Ok(frame)),
+            Some(error) => {
+                let undo = match frame.method {
+                    Method::Subscribe => Method::Unsubscribe,
+                    Method::Unsubscribe => Method::Subscribe,
+                };
+                self.update_active(undo, &frame.params);
+                Some(Err(SubscriptionError {
+                    frame,
+                    code: error.code,
+                    message: error.msg,
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_and_rejections() {
+        let bnb = Symbol::intern("BNBUSDT");
+        let mut subscriptions = Subscriptions::new();
+
+        let frame = subscriptions
+            .subscribe(
+                bnb,
+                &[StreamKind::PartialDepth { levels: 20 }, StreamKind::Trade],
+            )
+            .unwrap();
+        assert_eq!(
+            frame.to_json(),
+            r#"{"method":"SUBSCRIBE","params":["bnbusdt@depth20@100ms","bnbusdt@trade"],"id":1}"#
+        );
+        assert!(subscriptions.feeds_book(bnb));
+        // Already active
+        assert_eq!(subscriptions.subscribe(bnb, &[StreamKind::Trade]), None);
+
+        assert_eq!(
+            subscriptions.on_response(br#"{"result":null,"id":1}"#),
+            Some(Ok(frame))
+        );
+        assert_eq!(
+            subscriptions.on_response(br#"{"result":null,"id":1}"#),
+            None
+        );
+
+        let frame = subscriptions
+            .subscribe(bnb, &[StreamKind::BookTicker])
+            .unwrap();
+        let rejected = subscriptions
+            .on_response(br#"{"error":{"code":2,"msg":"Invalid request"},"id":2}"#)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(rejected.frame, frame);
+        assert_eq!(rejected.code, 2);
+        assert!(!subscriptions.is_active(bnb, StreamKind::BookTicker));
+
+        subscriptions
+            .unsubscribe(bnb, &[StreamKind::PartialDepth { levels: 20 }])
+            .unwrap();
+        assert!(!subscriptions.feeds_book(bnb));
+        assert_eq!(
+            subscriptions.active().collect::<Vec<_>>(),
+            vec!["bnbusdt@trade"]
+        );
+        assert_eq!(subscriptions.pending(), 1);
+    }
+}
@@ -0,0 +1,362 @@
+/// Trade surveillance for the matching engine, for exchange simulations.
+/// `Surveillance` follows the cancels and trades of the engine and raises a
+/// `SurveillanceAlert` for:
+/// - wash trades, both sides of a trade belong to the same account,
+/// - spoofing and layering, an account cancels at least `min_cancelled_quantity` on one side,
+///   `min_cancel_ratio` times its execution or more, within `window` before trading on the
+///   other side. Cancels spread over several price levels are reported as layering.
+/// Only cancels requested by the user count, the engine's own cancels are not a choice of the
+/// account. Orders entered without an account all share account 0 and trade with each other
+/// as wash trades, so the rules are meant for books with accounts.
+use crate::orderbookv2::{AccountId, OrderId, Price, Quantity, Side, Timestamp, Trade};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurveillanceConfig {
+    pub wash_trades: bool,
+    // How long a cancel counts against the executions that follow it
+    pub window: Duration,
+    pub min_cancelled_quantity: Quantity,
+    pub min_cancel_ratio: f64,
+    // Distinct price levels of the cancels that make a spoof a layering pattern
+    pub min_layers: usize,
+}
+
+impl Default for SurveillanceConfig {
+    fn default() -> Self {
+        SurveillanceConfig {
+            wash_trades: true,
+            window: Duration::from_secs(1),
+            min_cancelled_quantity: Quantity(100),
+            min_cancel_ratio: 5.0,
+            min_layers: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SurveillanceAlert {
+    WashTrade {
+        timestamp: Timestamp,
+        account_id: AccountId,
+        bid_order_id: OrderId,
+        ask_order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    // `side` is the side the account executed on, the cancels were on the other one
+    Spoofing {
+        timestamp: Timestamp,
+        account_id: AccountId,
+        side: Side,
+        executed: Quantity,
+        cancelled: Quantity,
+        cancelled_orders: Vec<OrderId>,
+        price_levels: usize,
+        layering: bool,
+    },
+}
+
+impl SurveillanceAlert {
+    pub fn account_id(&self) -> AccountId {
+        match self {
+            SurveillanceAlert::WashTrade { account_id, .. }
+            | SurveillanceAlert::Spoofing { account_id, .. } => *account_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cancel {
+    timestamp: Timestamp,
+    order_id: OrderId,
+    price: Price,
+    quantity: Quantity,
+}
+
+#[derive(Debug, Clone)]
+pub struct Surveillance {
+    config: SurveillanceConfig,
+    // Recent cancels of every account, bids first
+    cancels: HashMap<AccountId, [VecDeque<Cancel>; 2]>,
+    alerts: usize,
+}
+
+impl Default for Surveillance {
+    fn default() -> Self {
+        Surveillance::new(SurveillanceConfig::default())
+    }
+}
+
+fn side_index(side: Side) -> usize {
+    match side {
+        Side::Buy => 0,
+        Side::Sell => 1,
+    }
+}
+
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    }
+}
+
+impl Surveillance {
+    pub fn new(config: SurveillanceConfig) -> Surveillance {
+        Surveillance {
+            config,
+            cancels: HashMap::new(),
+            alerts: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SurveillanceConfig {
+        &self.config
+    }
+
+    // Alerts raised since the start
+    pub fn alert_count(&self) -> usize {
+        self.alerts
+    }
+
+    // A user cancel of the unfilled `quantity` of a resting order
+    pub fn on_cancel(
+        &mut self,
+        timestamp: Timestamp,
+        account_id: AccountId,
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    ) {
+        if quantity.is_zero() {
+            return;
+        }
+        let window = self.config.window.as_nanos() as Timestamp;
+        let cancels = &mut self.cancels.entry(account_id).or_default()[side_index(side)];
+        evict(cancels, timestamp, window);
+        cancels.push_back(Cancel {
+            timestamp,
+            order_id,
+            price,
+            quantity,
+        });
+    }
+
+    // Alerts of the trade, the cancels an alert reports do not count again
+    pub fn on_trade(&mut self, trade: &Trade) -> Vec<SurveillanceAlert> {
+        let (bid, ask) = (&trade.bid_trade, &trade.ask_trade);
+        let mut alerts = Vec::new();
+        if self.config.wash_trades && bid.account_id == ask.account_id {
+            alerts.push(SurveillanceAlert::WashTrade {
+                timestamp: trade.timestamp,
+                account_id: bid.account_id,
+                bid_order_id: bid.order_id,
+                ask_order_id: ask.order_id,
+                price: bid.price,
+                quantity: bid.quantity,
+            });
+        }
+        for (side, info) in [(Side::Buy, bid), (Side::Sell, ask)] {
+            if let Some(alert) =
+                self.check_spoofing(trade.timestamp, info.account_id, side, info.quantity)
+            {
+                alerts.push(alert);
+            }
+        }
+        self.alerts += alerts.len();
+        alerts
+    }
+
+    fn check_spoofing(
+        &mut self,
+        timestamp: Timestamp,
+        account_id: AccountId,
+        side: Side,
+        executed: Quantity,
+    ) -> Option<SurveillanceAlert> {
+        let config = self.config;
+        let window = config.window.as_nanos() as Timestamp;
+        let cancels = &mut self.cancels.get_mut(&account_id)?[side_index(opposite(side))];
+        evict(cancels, timestamp, window);
+
+        let cancelled = cancels
+            .iter()
+            .fold(Quantity::default(), |total, cancel| total + cancel.quantity);
+        let ratio = u64::from(cancelled) as f64 / u64::from(executed).max(1) as f64;
+        if cancelled < config.min_cancelled_quantity || ratio < config.min_cancel_ratio {
+            return None;
+        }
+        let price_levels = cancels
+            .iter()
+            .map(|cancel| cancel.price)
+            .collect::<HashSet<_>>()
+            .len();
+        let cancelled_orders = cancels.drain(..).map(|cancel| cancel.order_id).collect();
+        Some(SurveillanceAlert::Spoofing {
+            timestamp,
+            account_id,
+            side,
+            executed,
+            cancelled,
+            cancelled_orders,
+            price_levels,
+            layering: price_levels >= config.min_layers,
+        })
+    }
+}
+
+fn evict(cancels: &mut VecDeque<Cancel>, timestamp: Timestamp, window: Timestamp) {
+    while cancels
+        .front()
+        .is_some_and(|oldest| timestamp.saturating_sub(oldest.timestamp) > window)
+    {
+        cancels.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EngineEvent;
+    use crate::orderbookv2::{Order, OrderBook, OrderType};
+
+    const MILLISECOND: u64 = 1_000_000;
+
+    fn order(
+        order_id: OrderId,
+        account_id: AccountId,
+        side: Side,
+        price: i32,
+        quantity: u32,
+    ) -> Order {
+        Order::new(
+            order_id,
+            Price(price),
+            Quantity(quantity),
+            OrderType::GoodToCancel,
+            side,
+        )
+        .with_account(account_id)
+    }
+
+    fn alerts(orderbook: &mut OrderBook) -> Vec<SurveillanceAlert> {
+        orderbook
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                EngineEvent::SurveillanceAlert(alert) => Some(alert),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wash_trade() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_surveillance(Surveillance::default());
+        orderbook.add_order(order(1, 7, Side::Sell, 100, 5));
+        orderbook.add_order(order(2, 8, Side::Buy, 100, 2));
+        assert!(alerts(&mut orderbook).is_empty());
+
+        orderbook.add_order(order(3, 7, Side::Buy, 100, 3));
+        assert_eq!(
+            alerts(&mut orderbook),
+            vec![SurveillanceAlert::WashTrade {
+                timestamp: 0,
+                account_id: 7,
+                bid_order_id: 3,
+                ask_order_id: 1,
+                price: Price(100),
+                quantity: Quantity(3),
+            }]
+        );
+        assert_eq!(orderbook.surveillance().unwrap().alert_count(), 1);
+    }
+
+    #[test]
+    fn test_layering_before_opposite_execution() {
+        let mut orderbook = OrderBook::new();
+        orderbook.set_surveillance(Surveillance::new(SurveillanceConfig {
+            min_cancelled_quantity: Quantity(50),
+            ..SurveillanceConfig::default()
+        }));
+        // Account 7 layers bids, then sells into the resting bid of account 8
+        orderbook.add_order(order(1, 8, Side::Buy, 100, 10));
+        for (order_id, price) in [(2, 99), (3, 98), (4, 97)] {
+            orderbook.add_order(order(order_id, 7, Side::Buy, price, 20));
+        }
+        orderbook.advance_clock(100 * MILLISECOND);
+        for order_id in 2..5 {
+            orderbook.cancel_order(order_id).unwrap();
+        }
+        // Engine cancels do not count
+        orderbook.add_order(order(5, 7, Side::Buy, 96, 500));
+        orderbook
+            .cancel_order_with_reason(5, crate::audit::CancelReason::Risk)
+            .unwrap();
+        orderbook.advance_clock(300 * MILLISECOND);
+        orderbook.add_order(order(6, 7, Side::Sell, 100, 2));
+
+        assert_eq!(
+            alerts(&mut orderbook),
+            vec![SurveillanceAlert::Spoofing {
+                timestamp: 300 * MILLISECOND,
+                account_id: 7,
+                side: Side::Sell,
+                executed: Quantity(2),
+                cancelled: Quantity(60),
+                cancelled_orders: vec![2, 3, 4],
+                price_levels: 3,
+                layering: true,
+            }]
+        );
+        // The reported cancels are consumed
+        orderbook.add_order(order(7, 7, Side::Sell, 100, 2));
+        assert!(alerts(&mut orderbook).is_empty());
+    }
+
+    #[test]
+    fn test_cancels_outside_the_window_or_too_small() {
+        let mut surveillance = Surveillance::default();
+        surveillance.on_cancel(0, 7, 1, Side::Buy, Price(99), Quantity(1_000));
+        surveillance.on_cancel(
+            2_000 * MILLISECOND,
+            7,
+            2,
+            Side::Buy,
+            Price(99),
+            Quantity(100),
+        );
+        let trade = |quantity| {
+            let info = |order_id, account_id| crate::orderbookv2::TradeInfo {
+                order_id,
+                account_id,
+                price: Price(100),
+                quantity: Quantity(quantity),
+                liquidity: crate::orderbookv2::Liquidity::Maker,
+                fee: 0.0,
+            };
+            Trade {
+                bid_trade: info(3, 8),
+                ask_trade: info(4, 7),
+                timestamp: 2_500 * MILLISECOND,
+            }
+        };
+        // 100 cancelled against 50 executed is below the ratio
+        assert!(surveillance.on_trade(&trade(50)).is_empty());
+        let alerts = surveillance.on_trade(&trade(20));
+        assert!(matches!(
+            alerts[..],
+            [SurveillanceAlert::Spoofing {
+                cancelled: Quantity(100),
+                price_levels: 1,
+                layering: false,
+                ..
+            }]
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+/// Interned instrument symbols.
+/// A `Symbol` is an index into a process wide table of names, so it is `Copy`, compares and
+/// hashes as a `u32` and costs no allocation once the name has been seen. Names are never
+/// removed from the table, which is fine for the handful of instruments a process follows.
+/// Symbols serialize as their name, so payload and snapshot formats are unchanged.
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(RwLock::default)
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        if let Some(symbol) = Symbol::lookup(name) {
+            return symbol;
+        }
+        let mut interner = interner().write().unwrap();
+        // Another thread may have interned it in between
+        if let Some(symbol) = interner.ids.get(name) {
+            return *symbol;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let symbol = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, symbol);
+        symbol
+    }
+
+    // Interned symbol for the name, without interning it
+    pub fn lookup(name: &str) -> Option<Symbol> {
+        interner().read().unwrap().ids.get(name).copied()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().read().unwrap().names[self.0 as usize]
+    }
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::intern(&name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.as_str())
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+struct SymbolVisitor;
+
+impl<'de> Visitor<'de> for SymbolVisitor {
+    type Value = Symbol;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a symbol name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Symbol, E> {
+        Ok(Symbol::intern(name))
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(SymbolVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning() {
+        let symbol = Symbol::intern("INTERNTEST");
+        assert_eq!(Symbol::from("INTERNTEST"), symbol);
+        assert_eq!(Symbol::from("INTERNTEST".to_string()).id(), symbol.id());
+        assert_ne!(Symbol::intern("INTERNTEST2"), symbol);
+        assert_eq!(symbol, "INTERNTEST");
+        assert_eq!(symbol.to_string(), "INTERNTEST");
+        assert_eq!(Symbol::lookup("NEVERINTERNED"), None);
+
+        let json = serde_json::to_string(&symbol).unwrap();
+        assert_eq!(json, "\"INTERNTEST\"");
+        assert_eq!(serde_json::from_str::<Symbol>(&json).unwrap(), symbol);
+    }
+}
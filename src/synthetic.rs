@@ -0,0 +1,232 @@
+/// Implied order books of instruments derived from two listed ones.
+/// A ratio instrument A/B (ETH/BTC from ETHUSDT and BTCUSDT) is bought by buying A and selling
+/// B, so its implied ask combines the ask of A with the bid of B and its implied bid the bid
+/// of A with the ask of B. A spread A - h * B works the same way with the differences of the
+/// prices. The levels of both legs are walked together so every implied level carries the
+/// quantity both legs can actually fill, in units of A. Prices are decimals since implied
+/// prices are not on the tick grid of either leg.
+use crate::manager::OrderBookManager;
+use crate::orderbook::OrderBook;
+use crate::symbol::Symbol;
+
+const DUST: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticKind {
+    // Price of A in units of B, both quoted in the same currency
+    Ratio,
+    // A minus `hedge_ratio` units of B
+    Spread { hedge_ratio: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyntheticLevel {
+    pub price: f64,
+    // Units of the first leg
+    pub quantity: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticBook {
+    kind: SyntheticKind,
+    leg_a: Symbol,
+    leg_b: Symbol,
+    // Implied levels kept per side
+    depth: usize,
+    bids: Vec<SyntheticLevel>,
+    asks: Vec<SyntheticLevel>,
+}
+
+impl SyntheticBook {
+    pub fn new(
+        kind: SyntheticKind,
+        leg_a: impl Into<Symbol>,
+        leg_b: impl Into<Symbol>,
+        depth: usize,
+    ) -> SyntheticBook {
+        SyntheticBook {
+            kind,
+            leg_a: leg_a.into(),
+            leg_b: leg_b.into(),
+            depth,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntheticKind {
+        self.kind
+    }
+
+    pub fn legs(&self) -> (Symbol, Symbol) {
+        (self.leg_a, self.leg_b)
+    }
+
+    // Best first
+    pub fn bids(&self) -> &[SyntheticLevel] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[SyntheticLevel] {
+        &self.asks
+    }
+
+    pub fn best_bid(&self) -> Option<SyntheticLevel> {
+        self.bids.first().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<SyntheticLevel> {
+        self.asks.first().copied()
+    }
+
+    pub fn mid_price(&self) -> Option<f64> {
+        Some((self.best_bid()?.price + self.best_ask()?.price) / 2.0)
+    }
+
+    // Recomputes from the books of both legs when `changed` is one of them, returns whether
+    // the implied book was recomputed. Meant for the symbol returned by
+    // `OrderBookManager::apply_payload`.
+    pub fn on_book_changed(&mut self, changed: Symbol, manager: &OrderBookManager) -> bool {
+        if changed != self.leg_a && changed != self.leg_b {
+            return false;
+        }
+        match (manager.book(self.leg_a), manager.book(self.leg_b)) {
+            (Some(book_a), Some(book_b)) => {
+                self.update(book_a, book_b);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn update(&mut self, book_a: &OrderBook, book_b: &OrderBook) {
+        let (a_bids, a_asks) = (levels(book_a, true), levels(book_a, false));
+        let (b_bids, b_asks) = (levels(book_b, true), levels(book_b, false));
+        // Selling the synthetic sells A and buys B, buying it the other way around
+        self.bids = self.implied(&a_bids, &b_asks);
+        self.asks = self.implied(&a_asks, &b_bids);
+    }
+
+    fn implied(&self, a: &[(f64, f64)], b: &[(f64, f64)]) -> Vec<SyntheticLevel> {
+        let mut implied: Vec<SyntheticLevel> = Vec::new();
+        let (mut a, mut b) = (a.iter().copied(), b.iter().copied());
+        let (mut level_a, mut level_b) = (a.next(), b.next());
+
+        while let (Some((price_a, quantity_a)), Some((price_b, quantity_b))) = (level_a, level_b) {
+            // Units of B needed per unit of A
+            let b_per_a = match self.kind {
+                SyntheticKind::Ratio => price_a / price_b,
+                SyntheticKind::Spread { hedge_ratio } => hedge_ratio,
+            };
+            let price = match self.kind {
+                SyntheticKind::Ratio => price_a / price_b,
+                SyntheticKind::Spread { hedge_ratio } => price_a - hedge_ratio * price_b,
+            };
+            let quantity = quantity_a.min(quantity_b / b_per_a);
+
+            if let Some(last) = implied.last_mut().filter(|last| last.price == price) {
+                last.quantity += quantity;
+            } else if implied.len() == self.depth {
+                break;
+            } else {
+                implied.push(SyntheticLevel { price, quantity });
+            }
+
+            // The leg that limited the level is used up, up to rounding
+            let rest_a = quantity_a - quantity;
+            let rest_b = quantity_b - quantity * b_per_a;
+            level_a = if rest_a > DUST {
+                Some((price_a, rest_a))
+            } else {
+                a.next()
+            };
+            level_b = if rest_b > DUST {
+                Some((price_b, rest_b))
+            } else {
+                b.next()
+            };
+        }
+        implied
+    }
+}
+
+fn levels(book: &OrderBook, bids: bool) -> Vec<(f64, f64)> {
+    let converter = book.converter();
+    let to_f64 = |(price, quantity)| (converter.to_f64(price), converter.to_f64(quantity));
+    if bids {
+        book.bids().map(to_f64).collect()
+    } else {
+        book.asks().map(to_f64).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    fn depth(bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id: 1,
+            bids,
+            asks,
+        }
+    }
+
+    fn assert_level(level: SyntheticLevel, price: f64, quantity: f64) {
+        assert!((level.price - price).abs() < 1e-9, "{:?}", level);
+        assert!((level.quantity - quantity).abs() < 1e-9, "{:?}", level);
+    }
+
+    #[test]
+    fn test_ratio_book_from_two_legs() {
+        let mut eth = OrderBook::new("ETHUSDT");
+        eth.update_depth(&depth(
+            vec![(2000.0, 1.0), (1990.0, 5.0)],
+            vec![(2010.0, 2.0)],
+        ));
+        let mut btc = OrderBook::new("BTCUSDT");
+        btc.update_depth(&depth(vec![(40000.0, 0.1)], vec![(40100.0, 1.0)]));
+
+        let mut book = SyntheticBook::new(SyntheticKind::Ratio, "ETHUSDT", "BTCUSDT", 10);
+        book.update(&eth, &btc);
+
+        // Selling 1 ETH at 2000 buys 2000 / 40100 BTC
+        assert_eq!(book.bids().len(), 2);
+        assert_level(book.bids()[0], 2000.0 / 40100.0, 1.0);
+        assert_level(book.bids()[1], 1990.0 / 40100.0, 5.0);
+        // Buying ETH is limited by the 0.1 BTC bid: 0.1 * 40000 / 2010 ETH
+        assert_eq!(book.asks().len(), 1);
+        assert_level(book.asks()[0], 2010.0 / 40000.0, 4000.0 / 2010.0);
+    }
+
+    #[test]
+    fn test_spread_follows_leg_updates() {
+        let mut manager = OrderBookManager::new();
+        let a = Symbol::intern("BTCUSDT");
+        let b = Symbol::intern("BTCUSDC");
+        manager
+            .add_symbol(a)
+            .update_depth(&depth(vec![(100.0, 3.0)], vec![(101.0, 1.0)]));
+        manager
+            .add_symbol(b)
+            .update_depth(&depth(vec![(99.0, 2.0)], vec![(100.5, 1.0), (102.0, 4.0)]));
+
+        let mut book = SyntheticBook::new(SyntheticKind::Spread { hedge_ratio: 1.0 }, a, b, 10);
+        assert!(!book.on_book_changed(Symbol::intern("ETHUSDT"), &manager));
+        assert!(book.on_book_changed(b, &manager));
+        assert_level(book.best_bid().unwrap(), -0.5, 1.0);
+        assert_level(book.bids()[1], -2.0, 2.0);
+        assert_level(book.best_ask().unwrap(), 2.0, 1.0);
+        assert!((book.mid_price().unwrap() - 0.75).abs() < 1e-9);
+
+        manager.book_mut(a).unwrap().update_depth(&DepthUpdate {
+            last_update_id: 2,
+            ..depth(vec![(100.0, 0.0), (99.0, 1.0)], vec![])
+        });
+        book.on_book_changed(a, &manager);
+        assert_level(book.best_bid().unwrap(), -1.5, 1.0);
+        assert_eq!(book.bids().len(), 1);
+    }
+}
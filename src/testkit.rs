@@ -0,0 +1,244 @@
+/// Local websocket server speaking the Binance combined stream dialect, for hermetic tests of
+/// the feed clients.
+/// A `Scenario` is the script of one connection: frames to send, pauses, waiting for the
+/// client's SUBSCRIBE request, and how the connection ends. `MockExchange::start` listens on a
+/// local port and plays the scenarios in order, one per accepted connection, so a test can
+/// script a disconnect followed by what the client sees after reconnecting. Connections beyond
+/// the last scenario are refused. Gaps and replays are depth payloads whose update ids skip
+/// ahead or go back, malformed frames are any text that is not a Binance payload.
+use crate::binance_payloads::{
+    BookTickerUpdate, BookTickerUpdateEnvelope, DepthUpdate, DepthUpdateEnvelope,
+};
+use crate::subscriptions::StreamKind;
+use crate::symbol::Symbol;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Text(String),
+    Binary(Vec<u8>),
+    Pause(Duration),
+    // Reads until the client sends a SUBSCRIBE request and acknowledges it
+    AwaitSubscribe,
+    // Drops the TCP connection without a close frame
+    Disconnect,
+    Close,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn new() -> Scenario {
+        Scenario::default()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Scenario {
+        self.steps.push(Step::Text(text.into()));
+        self
+    }
+
+    pub fn binary(mut self, bytes: impl Into<Vec<u8>>) -> Scenario {
+        self.steps.push(Step::Binary(bytes.into()));
+        self
+    }
+
+    // A text frame that is neither a payload nor a response
+    pub fn malformed(self) -> Scenario {
+        self.text("{\"stream\":\"garbage\",\"data\":")
+    }
+
+    pub fn pause(mut self, duration: Duration) -> Scenario {
+        self.steps.push(Step::Pause(duration));
+        self
+    }
+
+    pub fn await_subscribe(mut self) -> Scenario {
+        self.steps.push(Step::AwaitSubscribe);
+        self
+    }
+
+    pub fn disconnect(mut self) -> Scenario {
+        self.steps.push(Step::Disconnect);
+        self
+    }
+
+    pub fn close(mut self) -> Scenario {
+        self.steps.push(Step::Close);
+        self
+    }
+
+    // Partial depth payload of the `<symbol>@depth<levels>@100ms` stream, (price, quantity)
+    // levels best first
+    pub fn depth(
+        self,
+        symbol: impl Into<Symbol>,
+        levels: u16,
+        last_update_id: u64,
+        bids: Vec<(f64, f64)>,
+        asks: Vec<(f64, f64)>,
+    ) -> Scenario {
+        let envelope = DepthUpdateEnvelope {
+            stream: StreamKind::PartialDepth { levels }.stream_name(symbol.into()),
+            data: DepthUpdate {
+                event_time: None,
+                last_update_id,
+                bids,
+                asks,
+            },
+        };
+        self.text(serde_json::to_string(&envelope).expect("Depth payload serialization failed"))
+    }
+
+    // Book ticker payload, best bid and best ask as (price, quantity)
+    pub fn book_ticker(
+        self,
+        symbol: impl Into<Symbol>,
+        update_id: u64,
+        (bid, bid_quantity): (f64, f64),
+        (ask, ask_quantity): (f64, f64),
+    ) -> Scenario {
+        let symbol = symbol.into();
+        let envelope = BookTickerUpdateEnvelope {
+            stream: StreamKind::BookTicker.stream_name(symbol),
+            data: BookTickerUpdate {
+                event_time: None,
+                update_id,
+                symbol,
+                best_bid_price: bid,
+                best_bid_quantity: bid_quantity,
+                best_ask_price: ask,
+                best_ask_quantity: ask_quantity,
+            },
+        };
+        self.text(
+            serde_json::to_string(&envelope).expect("Book ticker payload serialization failed"),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct MockExchange {
+    address: SocketAddr,
+    connections: Arc<Mutex<usize>>,
+    received: Arc<Mutex<Vec<String>>>,
+    server: JoinHandle<()>,
+}
+
+impl MockExchange {
+    // Listens on a free local port
+    pub async fn start(scenarios: Vec<Scenario>) -> io::Result<MockExchange> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let connections = Arc::new(Mutex::new(0));
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = connections.clone();
+        let messages = received.clone();
+        let server = tokio::spawn(async move {
+            let mut scenarios = VecDeque::from(scenarios);
+            while let Ok((stream, peer)) = listener.accept().await {
+                let Some(scenario) = scenarios.pop_front() else {
+                    drop(stream);
+                    continue;
+                };
+                *accepted.lock().unwrap() += 1;
+                let messages = messages.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = play(stream, scenario, messages).await {
+                        log::debug!("Mock exchange connection {} ended: {}", peer, error);
+                    }
+                });
+            }
+        });
+
+        Ok(MockExchange {
+            address,
+            connections,
+            received,
+            server,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Combined stream endpoint, for `BinanceConfig::ws_url`
+    pub fn url(&self) -> String {
+        format!("ws://{}/stream", self.address)
+    }
+
+    // Connections that got a scenario
+    pub fn connections(&self) -> usize {
+        *self.connections.lock().unwrap()
+    }
+
+    // Text frames sent by the clients, in the order they arrived
+    pub fn received(&self) -> Vec<String> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockExchange {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+// Once the script is over the connection stays open, still recording what the client sends,
+// until the client leaves
+async fn play(
+    stream: TcpStream,
+    scenario: Scenario,
+    received: Arc<Mutex<Vec<String>>>,
+) -> Result<(), tungstenite::Error> {
+    let mut websocket = tokio_tungstenite::accept_async(stream).await?;
+    for step in scenario.steps {
+        match step {
+            Step::Text(text) => websocket.send(Message::Text(text)).await?,
+            Step::Binary(bytes) => websocket.send(Message::Binary(bytes)).await?,
+            Step::Pause(duration) => tokio::time::sleep(duration).await,
+            Step::AwaitSubscribe => loop {
+                let Some(message) = websocket.next().await else {
+                    return Ok(());
+                };
+                let Message::Text(text) = message? else {
+                    continue;
+                };
+                received.lock().unwrap().push(text.clone());
+                let request: serde_json::Value = match serde_json::from_str(&text) {
+                    Ok(request) => request,
+                    Err(_) => continue,
+                };
+                if request["method"] == "SUBSCRIBE" {
+                    let response = serde_json::json!({ "result": null, "id": request["id"] });
+                    websocket.send(Message::Text(response.to_string())).await?;
+                    break;
+                }
+            },
+            Step::Disconnect => return Ok(()),
+            Step::Close => return websocket.close(None).await,
+        }
+    }
+    while let Some(message) = websocket.next().await {
+        if let Message::Text(text) = message? {
+            received.lock().unwrap().push(text);
+        }
+    }
+    Ok(())
+}
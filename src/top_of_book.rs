@@ -0,0 +1,199 @@
+/// Top of book change notifications for the L2 book.
+/// Strategies that only act on the best bid and ask subscribe with
+/// `OrderBook::on_top_of_book` (a callback) or `OrderBook::subscribe_top_of_book` (a
+/// `TopOfBookReceiver`) and hear about a payload only when it moved the best price or size of
+/// either side. A payload that touches many levels is one notification of the state it left the
+/// book in, levels changed below the top are never reported. The receiver holds the latest
+/// unread state only, so a consumer that falls behind skips the states in between instead of
+/// working through a backlog.
+use crate::orderbook::{OrderBook, Price, Quantity};
+use crate::symbol::Symbol;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopOfBook {
+    pub symbol: Symbol,
+    pub last_update_id: u64,
+    pub bid: Option<(Price, Quantity)>,
+    pub ask: Option<(Price, Quantity)>,
+}
+
+impl TopOfBook {
+    pub fn from_book(book: &OrderBook) -> TopOfBook {
+        TopOfBook {
+            symbol: book.symbol(),
+            last_update_id: book.last_update_id(),
+            bid: book.best_bid(),
+            ask: book.best_ask(),
+        }
+    }
+}
+
+type Callback = Box<dyn FnMut(&TopOfBook) + Send + Sync>;
+// Best bid and best ask
+type Top = (Option<(Price, Quantity)>, Option<(Price, Quantity)>);
+
+// Latest state not read yet, shared by the book and one receiver
+#[derive(Debug, Default)]
+struct Slot {
+    pending: Mutex<Option<TopOfBook>>,
+    ready: Condvar,
+}
+
+enum Subscriber {
+    Callback(Callback),
+    Channel(Arc<Slot>),
+}
+
+// Subscribers of one book and the top they last heard about. A clone of the book keeps the
+// last top but not the subscribers, copies such as the ones `SharedOrderBook` publishes stay
+// silent.
+#[derive(Default)]
+pub(crate) struct TopOfBookWatch {
+    subscribers: Vec<Subscriber>,
+    last: Top,
+}
+
+impl TopOfBookWatch {
+    pub(crate) fn add_callback(&mut self, callback: Callback) {
+        self.subscribers.push(Subscriber::Callback(callback));
+    }
+
+    pub(crate) fn add_receiver(&mut self) -> TopOfBookReceiver {
+        let slot = Arc::new(Slot::default());
+        self.subscribers
+            .push(Subscriber::Channel(Arc::clone(&slot)));
+        TopOfBookReceiver { slot }
+    }
+
+    // Called once the book applied a payload
+    pub(crate) fn update(&mut self, book: &OrderBook) {
+        let top = (book.best_bid(), book.best_ask());
+        if top == self.last {
+            return;
+        }
+        self.last = top;
+        // Receivers that were dropped unsubscribe
+        self.subscribers.retain(|subscriber| match subscriber {
+            Subscriber::Channel(slot) => Arc::strong_count(slot) > 1,
+            Subscriber::Callback(_) => true,
+        });
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let top = TopOfBook::from_book(book);
+        for subscriber in &mut self.subscribers {
+            match subscriber {
+                Subscriber::Callback(callback) => callback(&top),
+                Subscriber::Channel(slot) => {
+                    *slot.pending.lock().unwrap() = Some(top.clone());
+                    slot.ready.notify_one();
+                }
+            }
+        }
+    }
+}
+
+impl Clone for TopOfBookWatch {
+    fn clone(&self) -> Self {
+        TopOfBookWatch {
+            subscribers: Vec::new(),
+            last: self.last,
+        }
+    }
+}
+
+impl fmt::Debug for TopOfBookWatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopOfBookWatch")
+            .field("subscribers", &self.subscribers.len())
+            .field("last", &self.last)
+            .finish()
+    }
+}
+
+// Dropping the receiver ends the subscription with the next change
+#[derive(Debug)]
+pub struct TopOfBookReceiver {
+    slot: Arc<Slot>,
+}
+
+impl TopOfBookReceiver {
+    // Latest state since the last read, if the top changed since
+    pub fn try_recv(&self) -> Option<TopOfBook> {
+        self.slot.pending.lock().unwrap().take()
+    }
+
+    // Waits up to `timeout` for the top to change
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<TopOfBook> {
+        let pending = self.slot.pending.lock().unwrap();
+        let (mut pending, _) = self
+            .slot
+            .ready
+            .wait_timeout_while(pending, timeout, |pending| pending.is_none())
+            .unwrap();
+        pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_payloads::DepthUpdate;
+
+    fn update(last_update_id: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> DepthUpdate {
+        DepthUpdate {
+            event_time: None,
+            last_update_id,
+            bids,
+            asks,
+        }
+    }
+
+    #[test]
+    fn test_only_top_changes_are_reported() {
+        let mut book = OrderBook::new("ETHUSDC");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        book.on_top_of_book(move |top| log.lock().unwrap().push(top.clone()));
+        let receiver = book.subscribe_top_of_book();
+
+        book.update_depth(&update(
+            1,
+            vec![(100.0, 1.0), (99.0, 2.0)],
+            vec![(101.0, 1.0)],
+        ));
+        // Below the top on both sides
+        book.update_depth(&update(2, vec![(98.0, 5.0)], vec![(102.0, 3.0)]));
+        // The best bid size changes
+        book.update_depth(&update(3, vec![(100.0, 4.0)], vec![]));
+        // The best ask is removed and the next level takes its place
+        book.update_depth(&update(4, vec![], vec![(101.0, 0.0)]));
+
+        let updates: Vec<u64> = seen
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|top| top.last_update_id)
+            .collect();
+        assert_eq!(updates, vec![1, 3, 4]);
+        let last = seen.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last, book.top_of_book());
+        assert_eq!(last.ask, Some((Price(1_020_000), Quantity(30_000))));
+
+        // The receiver only kept the latest state
+        assert_eq!(receiver.try_recv(), Some(last));
+        assert_eq!(receiver.try_recv(), None);
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), None);
+
+        // A clone of the book does not notify
+        let mut copy = book.clone();
+        copy.update_depth(&update(5, vec![(100.5, 1.0)], vec![]));
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        assert_eq!(receiver.try_recv(), None);
+    }
+}
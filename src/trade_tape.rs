@@ -0,0 +1,243 @@
+/// Trade tape of the matching engine.
+/// The tape keeps the most recent trades up to its capacity for windowed queries. Session wide
+/// figures (high, low, volume, VWAP) come from `SessionStatistics` instead, so they cover the
+/// whole session however short the tape is.
+use crate::orderbookv2::{Price, Quantity, Timestamp};
+use crate::session::SessionStatistics;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+pub const DEFAULT_TAPE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapeTrade {
+    pub timestamp: Timestamp,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Clone)]
+pub struct TradeTape {
+    trades: VecDeque<TapeTrade>,
+    capacity: usize,
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        TradeTape::new(DEFAULT_TAPE_CAPACITY)
+    }
+}
+
+impl TradeTape {
+    pub fn new(capacity: usize) -> TradeTape {
+        TradeTape {
+            trades: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Drops the oldest trades that do not fit the new capacity
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.trades.len() > capacity {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, trade: TapeTrade) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.trades.len() == self.capacity {
+            self.trades.pop_front();
+        }
+        self.trades.push_back(trade);
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    pub fn last(&self) -> Option<&TapeTrade> {
+        self.trades.back()
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TapeTrade> + '_ {
+        self.trades.iter()
+    }
+
+    // Trades of the last `window` before `now` that are still on the tape, oldest first
+    pub fn trades_in_window(
+        &self,
+        now: Timestamp,
+        window: Duration,
+    ) -> impl DoubleEndedIterator<Item = &TapeTrade> + '_ {
+        let since = now.saturating_sub(window.as_nanos() as Timestamp);
+        let start = self.trades.partition_point(|trade| trade.timestamp < since);
+        self.trades.range(start..)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketStatistics {
+    pub last_price: Option<Price>,
+    pub session_high: Option<Price>,
+    pub session_low: Option<Price>,
+    pub traded_volume: u64,
+    pub trade_count: u64,
+    // Volume weighted average price since the session started
+    pub vwap: Option<f64>,
+}
+
+impl MarketStatistics {
+    pub fn new(last_price: Option<Price>, session: &SessionStatistics) -> MarketStatistics {
+        MarketStatistics {
+            last_price,
+            session_high: session.high,
+            session_low: session.low,
+            traded_volume: session.traded_volume,
+            trade_count: session.trade_count,
+            vwap: session.vwap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbookv2::{Order, OrderBook, OrderType, Side};
+
+    fn trade(timestamp: Timestamp, price: Price) -> TapeTrade {
+        TapeTrade {
+            timestamp,
+            price,
+            quantity: Quantity(1),
+        }
+    }
+
+    #[test]
+    fn test_tape_is_bounded() {
+        let mut tape = TradeTape::new(3);
+        for timestamp in 1..=5 {
+            tape.record(trade(timestamp, Price(100)));
+        }
+        assert_eq!(tape.len(), 3);
+        assert_eq!(tape.iter().next().unwrap().timestamp, 3);
+        assert_eq!(tape.last().unwrap().timestamp, 5);
+
+        tape.set_capacity(1);
+        assert_eq!(
+            tape.iter().map(|t| t.timestamp).collect::<Vec<_>>(),
+            vec![5]
+        );
+
+        let mut disabled = TradeTape::new(0);
+        disabled.record(trade(1, Price(100)));
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn test_trades_in_window() {
+        let second = 1_000_000_000;
+        let mut tape = TradeTape::default();
+        for timestamp in [0, 2 * second, 5 * second, 6 * second] {
+            tape.record(trade(timestamp, Price(100)));
+        }
+        let window = |now, seconds| {
+            tape.trades_in_window(now, Duration::from_secs(seconds))
+                .map(|t| t.timestamp / second)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(window(6 * second, 1), vec![5, 6]);
+        assert_eq!(window(6 * second, 4), vec![2, 5, 6]);
+        assert_eq!(window(6 * second, 60), vec![0, 2, 5, 6]);
+        assert_eq!(window(10 * second, 1), Vec::<Timestamp>::new());
+    }
+
+    #[test]
+    fn test_engine_market_statistics() {
+        let mut orderbook = OrderBook::new();
+        let empty = orderbook.market_statistics();
+        assert_eq!(empty.last_price, None);
+        assert_eq!(empty.vwap, None);
+
+        orderbook.add_order(Order::new(
+            1,
+            Price(101),
+            Quantity(2),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            2,
+            Price(103),
+            Quantity(2),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+        orderbook.add_order(Order::new(
+            3,
+            Price(103),
+            Quantity(4),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.advance_clock(3_000_000_000);
+        orderbook.add_order(Order::new(
+            4,
+            Price(99),
+            Quantity(1),
+            OrderType::GoodToCancel,
+            Side::Buy,
+        ));
+        orderbook.add_order(Order::new(
+            5,
+            Price(99),
+            Quantity(2),
+            OrderType::FillAndKill,
+            Side::Sell,
+        ));
+
+        assert_eq!(
+            orderbook.market_statistics(),
+            MarketStatistics {
+                last_price: Some(Price(99)),
+                session_high: Some(Price(103)),
+                session_low: Some(Price(99)),
+                traded_volume: 5,
+                trade_count: 3,
+                vwap: Some((101.0 * 2.0 + 103.0 * 2.0 + 99.0) / 5.0),
+            }
+        );
+        assert_eq!(orderbook.trade_tape().len(), 3);
+        assert_eq!(
+            orderbook
+                .trades_in_window(Duration::from_secs(1))
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![TapeTrade {
+                timestamp: 3_000_000_000,
+                price: Price(99),
+                quantity: Quantity(1),
+            }]
+        );
+
+        // A new session starts the statistics over, the tape is kept
+        orderbook.roll_session();
+        let statistics = orderbook.market_statistics();
+        assert_eq!(statistics.last_price, Some(Price(99)));
+        assert_eq!(statistics.session_high, None);
+        assert_eq!(statistics.traded_volume, 0);
+        assert_eq!(orderbook.trade_tape().len(), 3);
+    }
+}
@@ -0,0 +1,319 @@
+/// Order entry on Binance spot through the signed REST API.
+/// Requests are typed here and sent with the REST client of the connector, which adds the
+/// timestamp and the HMAC-SHA256 signature of the query string with the secret of the
+/// `Credentials`. Order statuses are shared with the user data stream so responses and
+/// `executionReport` events can be compared directly.
+use crate::binance_payloads::{deserialize_side, deserialize_string_to_f64};
+use crate::config::{BinanceConfig, ConfigError};
+use crate::orderbookv2::Side;
+use crate::symbol::Symbol;
+use crate::user_data::OrderStatus;
+use binance_spot_connector_rust::http::error::ClientError;
+use binance_spot_connector_rust::http::request::RequestBuilder;
+use binance_spot_connector_rust::http::{Credentials, Method};
+use binance_spot_connector_rust::ureq::{self, BinanceHttpClient};
+use serde::Deserialize;
+use std::fmt;
+
+const ORDER_PATH: &str = "/api/v3/order";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    GoodTillCanceled,
+    ImmediateOrCancel,
+    FillOrKill,
+}
+
+impl TimeInForce {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TimeInForce::GoodTillCanceled => "GTC",
+            TimeInForce::ImmediateOrCancel => "IOC",
+            TimeInForce::FillOrKill => "FOK",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderKind {
+    Limit {
+        price: f64,
+        time_in_force: TimeInForce,
+    },
+    // Rejected instead of matching on arrival
+    LimitMaker {
+        price: f64,
+    },
+    Market,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewOrderRequest {
+    pub symbol: Symbol,
+    pub side: Side,
+    pub kind: OrderKind,
+    pub quantity: f64,
+    pub client_order_id: Option<String>,
+}
+
+impl NewOrderRequest {
+    pub fn limit(symbol: impl Into<Symbol>, side: Side, price: f64, quantity: f64) -> Self {
+        NewOrderRequest {
+            symbol: symbol.into(),
+            side,
+            kind: OrderKind::Limit {
+                price,
+                time_in_force: TimeInForce::GoodTillCanceled,
+            },
+            quantity,
+            client_order_id: None,
+        }
+    }
+
+    pub fn market(symbol: impl Into<Symbol>, side: Side, quantity: f64) -> Self {
+        NewOrderRequest {
+            symbol: symbol.into(),
+            side,
+            kind: OrderKind::Market,
+            quantity,
+            client_order_id: None,
+        }
+    }
+
+    pub fn with_client_order_id(mut self, client_order_id: impl Into<String>) -> Self {
+        self.client_order_id = Some(client_order_id.into());
+        self
+    }
+
+    // Query parameters without the timestamp and signature
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("symbol", self.symbol.as_str().to_string()),
+            ("side", side_param(self.side).to_string()),
+        ];
+        match self.kind {
+            OrderKind::Limit {
+                price,
+                time_in_force,
+            } => {
+                params.push(("type", "LIMIT".to_string()));
+                params.push(("timeInForce", time_in_force.as_str().to_string()));
+                params.push(("price", price.to_string()));
+            }
+            OrderKind::LimitMaker { price } => {
+                params.push(("type", "LIMIT_MAKER".to_string()));
+                params.push(("price", price.to_string()));
+            }
+            OrderKind::Market => params.push(("type", "MARKET".to_string())),
+        }
+        params.push(("quantity", self.quantity.to_string()));
+        if let Some(client_order_id) = &self.client_order_id {
+            params.push(("newClientOrderId", client_order_id.clone()));
+        }
+        // Full response, with the status and executed quantity
+        params.push(("newOrderRespType", "RESULT".to_string()));
+        params
+    }
+}
+
+// Cancel and query address an order by either id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRef {
+    OrderId(u64),
+    ClientOrderId(String),
+}
+
+impl OrderRef {
+    fn param(&self) -> (&'static str, String) {
+        match self {
+            OrderRef::OrderId(order_id) => ("orderId", order_id.to_string()),
+            OrderRef::ClientOrderId(client_order_id) => {
+                ("origClientOrderId", client_order_id.clone())
+            }
+        }
+    }
+}
+
+// Response of new order, cancel and query
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResponse {
+    pub symbol: Symbol,
+    pub order_id: u64,
+    pub client_order_id: String,
+    #[serde(deserialize_with = "deserialize_string_to_f64")]
+    pub price: f64,
+    #[serde(rename = "origQty", deserialize_with = "deserialize_string_to_f64")]
+    pub quantity: f64,
+    #[serde(rename = "executedQty", deserialize_with = "deserialize_string_to_f64")]
+    pub executed_quantity: f64,
+    pub status: OrderStatus,
+    #[serde(rename = "type")]
+    pub order_type: String,
+    #[serde(deserialize_with = "deserialize_side")]
+    pub side: Side,
+    // Milliseconds, only set on new order and cancel responses
+    #[serde(default)]
+    pub transact_time: Option<u64>,
+}
+
+fn side_param(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    }
+}
+
+#[derive(Debug)]
+pub enum TradingError {
+    // Rejected by Binance, see the API error codes
+    Api { code: i16, message: String },
+    Http(Box<ureq::Error>),
+    InvalidResponse(serde_json::Error),
+}
+
+impl fmt::Display for TradingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TradingError::Api { code, message } => {
+                write!(f, "rejected with code {}: {}", code, message)
+            }
+            TradingError::Http(error) => write!(f, "request failed: {:?}", error),
+            TradingError::InvalidResponse(error) => write!(f, "invalid response: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TradingError {}
+
+impl From<Box<ureq::Error>> for TradingError {
+    fn from(error: Box<ureq::Error>) -> Self {
+        match *error {
+            ureq::Error::Client(ClientError::Structured(error)) => TradingError::Api {
+                code: error.data.code,
+                message: error.data.message,
+            },
+            error => TradingError::Http(Box::new(error)),
+        }
+    }
+}
+
+pub struct TradingClient {
+    client: BinanceHttpClient,
+    credentials: Credentials,
+    recv_window: Option<u64>,
+}
+
+impl TradingClient {
+    // `base_url` of the REST API, e.g. https://api.binance.com or https://testnet.binance.vision
+    pub fn new(base_url: &str, credentials: Credentials) -> TradingClient {
+        TradingClient {
+            client: BinanceHttpClient::with_url(base_url),
+            credentials,
+            recv_window: None,
+        }
+    }
+
+    // REST URL, credentials and receive window of the environment
+    pub fn from_config(config: &BinanceConfig) -> Result<TradingClient, ConfigError> {
+        let client = TradingClient::new(&config.rest_url, config.credentials()?);
+        Ok(match config.recv_window {
+            Some(recv_window) => client.with_recv_window(recv_window),
+            None => client,
+        })
+    }
+
+    // Milliseconds the request stays valid after its timestamp
+    pub fn with_recv_window(mut self, recv_window: u64) -> TradingClient {
+        self.recv_window = Some(recv_window);
+        self
+    }
+
+    pub fn new_order(&self, request: &NewOrderRequest) -> Result<OrderResponse, TradingError> {
+        self.send(Method::Post, request.params())
+    }
+
+    pub fn cancel_order(
+        &self,
+        symbol: Symbol,
+        order: &OrderRef,
+    ) -> Result<OrderResponse, TradingError> {
+        let params = vec![("symbol", symbol.as_str().to_string()), order.param()];
+        self.send(Method::Delete, params)
+    }
+
+    pub fn query_order(
+        &self,
+        symbol: Symbol,
+        order: &OrderRef,
+    ) -> Result<OrderResponse, TradingError> {
+        let params = vec![("symbol", symbol.as_str().to_string()), order.param()];
+        self.send(Method::Get, params)
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        mut params: Vec<(&'static str, String)>,
+    ) -> Result<OrderResponse, TradingError> {
+        if let Some(recv_window) = self.recv_window {
+            params.push(("recvWindow", recv_window.to_string()));
+        }
+        let request = RequestBuilder::new(method, ORDER_PATH)
+            .params(params.iter().map(|(key, value)| (*key, value.as_str())))
+            .credentials(self.credentials.clone())
+            .sign();
+        let body = self.client.send(request)?.into_body_str()?;
+        serde_json::from_str(&body).map_err(TradingError::InvalidResponse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_order_params() {
+        let request = NewOrderRequest::limit("BNBUSDT", Side::Buy, 25.35, 1.5)
+            .with_client_order_id("strategy-1");
+        assert_eq!(
+            request.params(),
+            vec![
+                ("symbol", "BNBUSDT".to_string()),
+                ("side", "BUY".to_string()),
+                ("type", "LIMIT".to_string()),
+                ("timeInForce", "GTC".to_string()),
+                ("price", "25.35".to_string()),
+                ("quantity", "1.5".to_string()),
+                ("newClientOrderId", "strategy-1".to_string()),
+                ("newOrderRespType", "RESULT".to_string()),
+            ]
+        );
+
+        let market = NewOrderRequest::market("BNBUSDT", Side::Sell, 0.001).params();
+        assert_eq!(market[2], ("type", "MARKET".to_string()));
+        assert_eq!(market[3], ("quantity", "0.001".to_string()));
+        assert_eq!(
+            OrderRef::ClientOrderId("strategy-1".to_string()).param(),
+            ("origClientOrderId", "strategy-1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_order_response_deserialize() {
+        let body = r#"{"symbol":"BTCUSDT","orderId":28,"orderListId":-1,"clientOrderId":"6gCrw2kRUAF9CvJDGP16IP","transactTime":1507725176595,"price":"0.00000000","origQty":"10.00000000","executedQty":"10.00000000","cummulativeQuoteQty":"10.00000000","status":"FILLED","timeInForce":"GTC","type":"MARKET","side":"SELL","workingTime":1507725176595,"selfTradePreventionMode":"NONE"}"#;
+        let response: OrderResponse = serde_json::from_str(body).unwrap();
+        assert_eq!(response.symbol, "BTCUSDT");
+        assert_eq!(response.order_id, 28);
+        assert_eq!(response.status, OrderStatus::Filled);
+        assert_eq!(response.side, Side::Sell);
+        assert_eq!(response.executed_quantity, 10.0);
+        assert_eq!(response.transact_time, Some(1507725176595));
+
+        // Query responses have no transaction time
+        let body = r#"{"symbol":"LTCBTC","orderId":1,"orderListId":-1,"clientOrderId":"myOrder1","price":"0.1","origQty":"1.0","executedQty":"0.0","cummulativeQuoteQty":"0.0","status":"NEW","timeInForce":"GTC","type":"LIMIT","side":"BUY","stopPrice":"0.0","icebergQty":"0.0","time":1499827319559,"updateTime":1499827319559,"isWorking":true}"#;
+        let response: OrderResponse = serde_json::from_str(body).unwrap();
+        assert_eq!(response.status, OrderStatus::New);
+        assert_eq!(response.transact_time, None);
+    }
+}
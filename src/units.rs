@@ -0,0 +1,139 @@
+/// Price, quantity and notional newtypes for both books.
+/// Prices and quantities are integers of similar width in both books and used to be plain
+/// aliases, so nothing stopped a quantity from being passed where a price was expected. The
+/// newtypes only allow the arithmetic that makes sense: quantities add up and subtract, prices
+/// are offset by price differences, and a price times a quantity is a `Notional`. Anything
+/// else goes through the raw value (`.0` or `get()`) and shows up in review.
+/// The types are generated by the macros below, the matching engine instantiates them over
+/// i32/u32/i64 and the L2 book over its u64 converter units.
+
+// Common part of every unit: ordering, hashing, serde as the raw number, `From` the raw type
+macro_rules! unit {
+    ($(#[$meta:meta])* $name:ident($raw:ty)) => {
+        $(#[$meta])*
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+            Default,
+            serde::Serialize,
+            serde::Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub $raw);
+
+        impl $name {
+            pub const ZERO: $name = $name(0);
+            pub const MAX: $name = $name(<$raw>::MAX);
+
+            pub const fn get(self) -> $raw {
+                self.0
+            }
+
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn to_f64(self) -> f64 {
+                self.0 as f64
+            }
+        }
+
+        impl From<$raw> for $name {
+            fn from(value: $raw) -> Self {
+                $name(value)
+            }
+        }
+
+        impl core::fmt::Display for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+// Amounts of the same unit add up and subtract: quantities, notionals, price offsets
+macro_rules! additive {
+    ($name:ident) => {
+        impl core::ops::Add for $name {
+            type Output = $name;
+
+            fn add(self, other: $name) -> $name {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl core::ops::Sub for $name {
+            type Output = $name;
+
+            fn sub(self, other: $name) -> $name {
+                $name(self.0 - other.0)
+            }
+        }
+
+        impl core::ops::AddAssign for $name {
+            fn add_assign(&mut self, other: $name) {
+                self.0 += other.0;
+            }
+        }
+
+        impl core::ops::SubAssign for $name {
+            fn sub_assign(&mut self, other: $name) {
+                self.0 -= other.0;
+            }
+        }
+
+        impl core::iter::Sum for $name {
+            fn sum<I: Iterator<Item = $name>>(iter: I) -> $name {
+                iter.fold($name::ZERO, |total, value| total + value)
+            }
+        }
+
+        impl $name {
+            pub fn checked_add(self, other: $name) -> Option<$name> {
+                self.0.checked_add(other.0).map($name)
+            }
+
+            pub fn checked_sub(self, other: $name) -> Option<$name> {
+                self.0.checked_sub(other.0).map($name)
+            }
+
+            pub fn saturating_add(self, other: $name) -> $name {
+                $name(self.0.saturating_add(other.0))
+            }
+
+            pub fn saturating_sub(self, other: $name) -> $name {
+                $name(self.0.saturating_sub(other.0))
+            }
+        }
+    };
+}
+
+// Price times quantity, in both orders, computed in the notional's wider type
+macro_rules! notional {
+    ($price:ident * $quantity:ident = $notional:ident($wide:ty)) => {
+        impl core::ops::Mul<$quantity> for $price {
+            type Output = $notional;
+
+            fn mul(self, quantity: $quantity) -> $notional {
+                $notional(self.0 as $wide * quantity.0 as $wide)
+            }
+        }
+
+        impl core::ops::Mul<$price> for $quantity {
+            type Output = $notional;
+
+            fn mul(self, price: $price) -> $notional {
+                price * self
+            }
+        }
+    };
+}
+
+pub(crate) use {additive, notional, unit};
@@ -0,0 +1,312 @@
+/// Binance user data stream: order updates and account events.
+/// `executionReport` and `outboundAccountPosition` events are decoded into typed structs and
+/// fed to an `OrderTracker`, which mirrors the open orders and balances of the account so they
+/// can be read next to the market data book. Orders leave the tracker once they reach a final
+/// status. The listen key the stream is opened with is managed by `listen_key`.
+use crate::binance_payloads::{deserialize_side, deserialize_string_to_f64};
+use crate::orderbookv2::Side;
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExecutionType {
+    New,
+    Canceled,
+    Replaced,
+    Rejected,
+    Trade,
+    Expired,
+    TradePrevention,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    PendingCancel,
+    Rejected,
+    Expired,
+    ExpiredInMatch,
+}
+
+impl OrderStatus {
+    // The order is no longer working on the exchange
+    pub fn is_final(&self) -> bool {
+        match self {
+            OrderStatus::New | OrderStatus::PartiallyFilled | OrderStatus::PendingCancel => false,
+            OrderStatus::Filled
+            | OrderStatus::Canceled
+            | OrderStatus::Rejected
+            | OrderStatus::Expired
+            | OrderStatus::ExpiredInMatch => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExecutionReport {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: Symbol,
+    #[serde(rename = "c")]
+    pub client_order_id: String,
+    #[serde(rename = "S", deserialize_with = "deserialize_side")]
+    pub side: Side,
+    // LIMIT, MARKET, LIMIT_MAKER, ...
+    #[serde(rename = "o")]
+    pub order_type: String,
+    #[serde(rename = "f")]
+    pub time_in_force: String,
+    #[serde(rename = "q", deserialize_with = "deserialize_string_to_f64")]
+    pub quantity: f64,
+    #[serde(rename = "p", deserialize_with = "deserialize_string_to_f64")]
+    pub price: f64,
+    #[serde(rename = "x")]
+    pub execution_type: ExecutionType,
+    #[serde(rename = "X")]
+    pub order_status: OrderStatus,
+    // NONE unless the order was rejected
+    #[serde(rename = "r")]
+    pub reject_reason: String,
+    #[serde(rename = "i")]
+    pub order_id: u64,
+    #[serde(rename = "l", deserialize_with = "deserialize_string_to_f64")]
+    pub last_executed_quantity: f64,
+    #[serde(rename = "z", deserialize_with = "deserialize_string_to_f64")]
+    pub cumulative_filled_quantity: f64,
+    #[serde(rename = "L", deserialize_with = "deserialize_string_to_f64")]
+    pub last_executed_price: f64,
+    #[serde(rename = "n", deserialize_with = "deserialize_string_to_f64")]
+    pub commission: f64,
+    #[serde(rename = "N")]
+    pub commission_asset: Option<String>,
+    #[serde(rename = "T")]
+    pub transaction_time: u64,
+    // -1 unless the event is a trade
+    #[serde(rename = "t")]
+    pub trade_id: i64,
+    #[serde(rename = "m")]
+    pub is_maker: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Balance {
+    #[serde(rename = "f", deserialize_with = "deserialize_string_to_f64")]
+    pub free: f64,
+    #[serde(rename = "l", deserialize_with = "deserialize_string_to_f64")]
+    pub locked: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AssetBalance {
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(flatten)]
+    pub balance: Balance,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OutboundAccountPosition {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "u")]
+    pub last_update_time: u64,
+    // Only the assets the event changed
+    #[serde(rename = "B")]
+    pub balances: Vec<AssetBalance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "e")]
+pub enum UserDataEvent {
+    #[serde(rename = "executionReport")]
+    ExecutionReport(ExecutionReport),
+    #[serde(rename = "outboundAccountPosition")]
+    OutboundAccountPosition(OutboundAccountPosition),
+}
+
+#[derive(Debug, Deserialize)]
+struct UserDataEnvelope {
+    data: UserDataEvent,
+}
+
+impl UserDataEvent {
+    // Accepts the bare event as well as the combined stream envelope
+    pub fn parse(payload: &[u8]) -> Result<UserDataEvent, serde_json::Error> {
+        match serde_json::from_slice::<UserDataEnvelope>(payload) {
+            Ok(envelope) => Ok(envelope.data),
+            Err(_) => serde_json::from_slice(payload),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedOrder {
+    pub order_id: u64,
+    pub client_order_id: String,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub price: f64,
+    pub quantity: f64,
+    pub filled_quantity: f64,
+    pub status: OrderStatus,
+    // Event time of the last update, in milliseconds
+    pub updated_at: u64,
+}
+
+impl TrackedOrder {
+    pub fn remaining_quantity(&self) -> f64 {
+        self.quantity - self.filled_quantity
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct OrderTracker {
+    orders: HashMap<u64, TrackedOrder>,
+    balances: HashMap<String, Balance>,
+}
+
+impl OrderTracker {
+    pub fn new() -> OrderTracker {
+        OrderTracker::default()
+    }
+
+    pub fn apply(&mut self, event: &UserDataEvent) {
+        match event {
+            UserDataEvent::ExecutionReport(report) => self.apply_execution_report(report),
+            UserDataEvent::OutboundAccountPosition(position) => {
+                for asset in &position.balances {
+                    self.balances.insert(asset.asset.clone(), asset.balance);
+                }
+            }
+        }
+    }
+
+    fn apply_execution_report(&mut self, report: &ExecutionReport) {
+        if report.order_status.is_final() {
+            self.orders.remove(&report.order_id);
+            return;
+        }
+        // Events can arrive out of order, an older update does not overwrite a newer one
+        if self
+            .orders
+            .get(&report.order_id)
+            .is_some_and(|order| order.updated_at > report.event_time)
+        {
+            return;
+        }
+        self.orders.insert(
+            report.order_id,
+            TrackedOrder {
+                order_id: report.order_id,
+                client_order_id: report.client_order_id.clone(),
+                symbol: report.symbol,
+                side: report.side,
+                price: report.price,
+                quantity: report.quantity,
+                filled_quantity: report.cumulative_filled_quantity,
+                status: report.order_status,
+                updated_at: report.event_time,
+            },
+        );
+    }
+
+    pub fn order(&self, order_id: u64) -> Option<&TrackedOrder> {
+        self.orders.get(&order_id)
+    }
+
+    pub fn order_by_client_id(&self, client_order_id: &str) -> Option<&TrackedOrder> {
+        self.orders
+            .values()
+            .find(|order| order.client_order_id == client_order_id)
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &TrackedOrder> + '_ {
+        self.orders.values()
+    }
+
+    pub fn open_orders_for(&self, symbol: Symbol) -> impl Iterator<Item = &TrackedOrder> + '_ {
+        self.orders
+            .values()
+            .filter(move |order| order.symbol == symbol)
+    }
+
+    pub fn balance(&self, asset: &str) -> Option<Balance> {
+        self.balances.get(asset).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execution_report(event_time: u64, status: &str, filled: &str) -> String {
+        format!(
+            r#"{{"e":"executionReport","E":{},"s":"BNBUSDT","c":"strategy-1","S":"BUY","o":"LIMIT","f":"GTC","q":"2.00000000","p":"25.35000000","P":"0.00000000","F":"0.00000000","g":-1,"C":"","x":"TRADE","X":"{}","r":"NONE","i":4293153,"l":"1.00000000","z":"{}","L":"25.35000000","n":"0.00100000","N":"BNB","T":{},"t":77,"I":8641984,"w":true,"m":false,"M":false,"O":1499405658657,"Z":"25.35000000","Y":"25.35000000","Q":"0.00000000","W":1499405658657,"V":"NONE"}}"#,
+            event_time, status, filled, event_time
+        )
+    }
+
+    #[test]
+    fn test_execution_report_deserialize() {
+        let payload = format!(
+            r#"{{"stream":"listen-key","data":{}}}"#,
+            execution_report(1499405658658, "PARTIALLY_FILLED", "1.00000000")
+        );
+        let UserDataEvent::ExecutionReport(report) =
+            UserDataEvent::parse(payload.as_bytes()).unwrap()
+        else {
+            panic!("expected an execution report");
+        };
+        assert_eq!(report.symbol, "BNBUSDT");
+        assert_eq!(report.side, Side::Buy);
+        assert_eq!(report.execution_type, ExecutionType::Trade);
+        assert_eq!(report.order_status, OrderStatus::PartiallyFilled);
+        assert_eq!(report.order_id, 4293153);
+        assert_eq!(report.price, 25.35);
+        assert_eq!(report.commission_asset.as_deref(), Some("BNB"));
+        assert_eq!(report.trade_id, 77);
+    }
+
+    #[test]
+    fn test_tracker_mirrors_open_orders_and_balances() {
+        let mut tracker = OrderTracker::new();
+        let bnb = Symbol::intern("BNBUSDT");
+        let event = |payload: String| UserDataEvent::parse(payload.as_bytes()).unwrap();
+
+        tracker.apply(&event(execution_report(
+            2,
+            "PARTIALLY_FILLED",
+            "1.00000000",
+        )));
+        // Older event arriving late
+        tracker.apply(&event(execution_report(1, "NEW", "0.00000000")));
+        let order = tracker.order_by_client_id("strategy-1").unwrap();
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        assert_eq!(order.remaining_quantity(), 1.0);
+        assert_eq!(tracker.open_orders_for(bnb).count(), 1);
+
+        tracker.apply(&event(execution_report(3, "FILLED", "2.00000000")));
+        assert!(tracker.order(4293153).is_none());
+        assert_eq!(tracker.open_orders().count(), 0);
+
+        tracker.apply(&event(
+            r#"{"e":"outboundAccountPosition","E":1564034571105,"u":1564034571073,"B":[{"a":"ETH","f":"10000.000000","l":"0.500000"}]}"#
+                .to_string(),
+        ));
+        assert_eq!(
+            tracker.balance("ETH"),
+            Some(Balance {
+                free: 10000.0,
+                locked: 0.5
+            })
+        );
+        assert_eq!(tracker.balance("BTC"), None);
+    }
+}
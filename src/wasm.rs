@@ -0,0 +1,94 @@
+/// wasm-bindgen bindings for the L2 book, enabled with the `wasm` feature.
+/// Build without the default `native` feature for `wasm32-unknown-unknown`, the dashboard feeds
+/// the raw Binance depth messages it receives over its own WebSocket into `apply_depth_json`.
+use crate::binance_payloads::{DepthUpdate, DepthUpdateEnvelope};
+use crate::orderbook::{OrderBook, Price, Quantity};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WasmOrderBook {
+    book: OrderBook,
+}
+
+// Levels in prices and quantities, best first
+#[derive(Debug, Serialize, PartialEq)]
+struct DepthLevels {
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+// Accepts the combined stream envelope as well as the bare depth payload
+fn parse_depth(json: &str) -> serde_json::Result<DepthUpdate> {
+    match serde_json::from_str::<DepthUpdateEnvelope>(json) {
+        Ok(envelope) => Ok(envelope.data),
+        Err(_) => serde_json::from_str::<DepthUpdate>(json),
+    }
+}
+
+fn depth_levels(book: &OrderBook, levels: usize) -> DepthLevels {
+    let snapshot = book.snapshot(levels);
+    let converter = book.converter();
+    let convert = |levels: Vec<(Price, Quantity)>| {
+        levels
+            .into_iter()
+            .map(|(price, qty)| (converter.to_f64(price), converter.to_f64(qty)))
+            .collect()
+    };
+    DepthLevels {
+        bids: convert(snapshot.bids),
+        asks: convert(snapshot.asks),
+    }
+}
+
+#[wasm_bindgen]
+impl WasmOrderBook {
+    #[wasm_bindgen(constructor)]
+    pub fn new(symbol: &str) -> WasmOrderBook {
+        WasmOrderBook {
+            book: OrderBook::new(symbol.to_string()),
+        }
+    }
+
+    pub fn apply_depth_json(&mut self, json: &str) -> Result<(), JsError> {
+        let update = parse_depth(json).map_err(|error| JsError::new(&error.to_string()))?;
+        self.book.update_depth(&update);
+        Ok(())
+    }
+
+    // [bid price, bid quantity, ask price, ask quantity], undefined while a side is empty
+    pub fn best_bid_ask(&self) -> Option<Vec<f64>> {
+        self.book
+            .get_best_bid_ask()
+            .map(|((bid, bid_qty), (ask, ask_qty))| vec![bid, bid_qty, ask, ask_qty])
+    }
+
+    // JSON `{"bids": [[price, qty], ...], "asks": [...]}` with up to `levels` levels per side
+    pub fn depth_levels(&self, levels: usize) -> String {
+        serde_json::to_string(&depth_levels(&self.book, levels))
+            .expect("depth levels are always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_json_and_levels() {
+        let mut book = WasmOrderBook::new("BNBUSDT");
+        book.apply_depth_json(
+            r#"{"stream":"bnbusdt@depth5","data":{"lastUpdateId":1,"bids":[["25.35","1.5"],["25.34","2"]],"asks":[["25.36","3"]]}}"#,
+        )
+        .unwrap();
+        book.apply_depth_json(r#"{"lastUpdateId":2,"bids":[["25.34","0"]],"asks":[]}"#)
+            .unwrap();
+
+        assert_eq!(book.best_bid_ask(), Some(vec![25.35, 1.5, 25.36, 3.0]));
+        assert_eq!(
+            book.depth_levels(5),
+            r#"{"bids":[[25.35,1.5]],"asks":[[25.36,3.0]]}"#
+        );
+        assert!(parse_depth("{}").is_err());
+    }
+}
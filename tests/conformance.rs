@@ -0,0 +1,103 @@
+// Conformance of the L2 book against recorded Binance sequences. Every `<name>.jsonl` under
+// `tests/fixtures/conformance` is a recording in the `book-cli record` format, replayed through
+// the same path as the live feed, and `<name>.expected.json` holds the book it has to end in,
+// with prices and quantities as the decimal strings Binance sends. A new case is a recording
+// dropped into the directory together with its expected book.
+#![cfg(feature = "native")]
+
+use binance_orderbook::orderbook::{OrderBook, Price, Quantity};
+use binance_orderbook::recording;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+struct Expected {
+    symbol: String,
+    last_update_id: u64,
+    // Payloads that are neither depth nor book ticker updates, e.g. subscription responses
+    unrecognized: usize,
+    bids: Vec<(String, String)>,
+    asks: Vec<(String, String)>,
+}
+
+fn fixtures() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/conformance");
+    let mut recordings: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap_or_else(|error| panic!("cannot read {}: {}", directory.display(), error))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        })
+        .collect();
+    recordings.sort();
+    recordings
+}
+
+fn levels(book: &OrderBook, levels: &[(String, String)]) -> Vec<(Price, Quantity)> {
+    let converter = book.converter();
+    levels
+        .iter()
+        .map(|(price, quantity)| {
+            (
+                Price(converter.parse(price).unwrap()),
+                Quantity(converter.parse(quantity).unwrap()),
+            )
+        })
+        .collect()
+}
+
+// Differences between the replayed book and the expected one, empty when they agree
+fn check(recording: &Path) -> Vec<String> {
+    let expected: Expected = serde_json::from_str(
+        &fs::read_to_string(recording.with_extension("expected.json")).unwrap(),
+    )
+    .unwrap();
+    let payloads = recording::read_recording(recording).unwrap();
+    let (book, statistics) = recording::replay(expected.symbol.as_str(), payloads);
+    let snapshot = book.snapshot(usize::MAX);
+
+    let mut failures = Vec::new();
+    if snapshot.last_update_id != expected.last_update_id {
+        failures.push(format!(
+            "last update id {}, expected {}",
+            snapshot.last_update_id, expected.last_update_id
+        ));
+    }
+    if statistics.unrecognized != expected.unrecognized {
+        failures.push(format!(
+            "{} unrecognized payloads, expected {}",
+            statistics.unrecognized, expected.unrecognized
+        ));
+    }
+    for (side, actual, wanted) in [
+        ("bids", &snapshot.bids, levels(&book, &expected.bids)),
+        ("asks", &snapshot.asks, levels(&book, &expected.asks)),
+    ] {
+        if *actual != wanted {
+            failures.push(format!("{} {:?}, expected {:?}", side, actual, wanted));
+        }
+    }
+    failures
+}
+
+#[test]
+fn test_recorded_sequences_match_expected_books() {
+    let recordings = fixtures();
+    assert!(!recordings.is_empty(), "no conformance fixtures found");
+    let failures: Vec<String> = recordings
+        .iter()
+        .flat_map(|recording| {
+            let name = recording
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            check(recording)
+                .into_iter()
+                .map(move |failure| format!("{}: {}", name, failure))
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
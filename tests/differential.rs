@@ -0,0 +1,208 @@
+// Differential test of the aggregate L2 book against the matching engine. Random engine
+// activity is turned into L2 deltas two ways, the engine's market data feed and a diff of its
+// aggregated levels after every command, and each stream is applied to its own
+// `orderbook::OrderBook`. After every command all three books have to show the same levels.
+// A divergence panics with the seed, the step and the last commands, enough to replay it.
+#![cfg(feature = "std")]
+
+use binance_orderbook::binance_payloads::DepthUpdate;
+use binance_orderbook::orderbook::{self, CONVERSION_FACTOR};
+use binance_orderbook::orderbookv2::{
+    LevelInfo, MatchingPolicy, Order, OrderBook, OrderBookLevelInfos, OrderCommand, OrderId,
+    OrderModify, OrderType, Price, Quantity, Side,
+};
+use binance_orderbook::price_levels::BookBackend;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const STEPS: usize = 2_000;
+// Commands shown when the books diverge
+const HISTORY: usize = 10;
+
+// Random order flow around a fixed mid, cancels and amendments pick any id seen so far
+struct Activity {
+    rng: StdRng,
+    next_order_id: OrderId,
+}
+
+impl Activity {
+    fn new(seed: u64) -> Activity {
+        Activity {
+            rng: StdRng::seed_from_u64(seed),
+            next_order_id: 1,
+        }
+    }
+
+    fn side(&mut self) -> Side {
+        if self.rng.gen_bool(0.5) {
+            Side::Buy
+        } else {
+            Side::Sell
+        }
+    }
+
+    fn next_command(&mut self) -> OrderCommand {
+        let known = self.next_order_id;
+        match self.rng.gen_range(0..10) {
+            0..=1 if known > 1 => OrderCommand::Cancel(self.rng.gen_range(1..known)),
+            2 if known > 1 => OrderCommand::Modify(OrderModify::new(
+                self.rng.gen_range(1..known),
+                self.side(),
+                Price(self.rng.gen_range(90..=110)),
+                Quantity(self.rng.gen_range(1..=20)),
+            )),
+            _ => {
+                let order_type = match self.rng.gen_range(0..10) {
+                    0 => OrderType::FillAndKill,
+                    1 => OrderType::Day,
+                    _ => OrderType::GoodToCancel,
+                };
+                let order_id = self.next_order_id;
+                self.next_order_id += 1;
+                let side = self.side();
+                OrderCommand::New(Order::new(
+                    order_id,
+                    Price(self.rng.gen_range(90..=110)),
+                    Quantity(self.rng.gen_range(1..=20)),
+                    order_type,
+                    side,
+                ))
+            }
+        }
+    }
+}
+
+fn l2_levels(
+    levels: impl Iterator<Item = (orderbook::Price, orderbook::Quantity)>,
+) -> Vec<LevelInfo> {
+    levels
+        .map(|(price, quantity)| LevelInfo {
+            price: Price((price.to_f64() / CONVERSION_FACTOR) as i32),
+            quantity: Quantity((quantity.to_f64() / CONVERSION_FACTOR) as u32),
+        })
+        .collect()
+}
+
+// Changed and removed levels of one side, a removed level has a zero quantity
+fn diff_side(from: &[LevelInfo], to: &[LevelInfo]) -> Vec<(f64, f64)> {
+    let removed = from
+        .iter()
+        .filter(|old| !to.iter().any(|level| level.price == old.price))
+        .map(|old| (f64::from(old.price.0), 0.0));
+    let changed = to
+        .iter()
+        .filter(|level| !from.contains(level))
+        .map(|level| (f64::from(level.price.0), f64::from(level.quantity.0)));
+    removed.chain(changed).collect()
+}
+
+struct Harness {
+    seed: u64,
+    engine: OrderBook,
+    published: orderbook::OrderBook,
+    diffed: orderbook::OrderBook,
+    levels: OrderBookLevelInfos,
+    update_id: u64,
+    history: Vec<OrderCommand>,
+}
+
+impl Harness {
+    fn new(seed: u64, mut engine: OrderBook, backend: BookBackend) -> Harness {
+        engine.enable_market_data();
+        let book = || orderbook::OrderBook::with_backend("SIM", backend);
+        let mut harness = Harness {
+            seed,
+            levels: engine.get_orderbook_level_infos(),
+            engine,
+            published: book(),
+            diffed: book(),
+            update_id: 0,
+            history: Vec::new(),
+        };
+        harness.sync();
+        harness
+    }
+
+    fn apply(&mut self, command: OrderCommand) {
+        self.history.push(command.clone());
+        self.engine.apply_batch(vec![command]);
+        self.sync();
+        self.check();
+    }
+
+    // Feeds the deltas of the last command to both books
+    fn sync(&mut self) {
+        for message in self.engine.drain_market_data() {
+            self.published.apply_market_data(&message);
+        }
+        let levels = self.engine.get_orderbook_level_infos();
+        self.update_id += 1;
+        self.diffed.update_depth(&DepthUpdate {
+            event_time: None,
+            last_update_id: self.update_id,
+            bids: diff_side(self.levels.get_bids(), levels.get_bids()),
+            asks: diff_side(self.levels.get_asks(), levels.get_asks()),
+        });
+        self.levels = levels;
+    }
+
+    fn check(&self) {
+        for (name, book) in [("published", &self.published), ("diffed", &self.diffed)] {
+            let agree = &l2_levels(book.bids()) == self.levels.get_bids()
+                && &l2_levels(book.asks()) == self.levels.get_asks();
+            if !agree {
+                panic!(
+                    "{} book diverged from the engine, seed {} step {}\nlast commands: {:#?}\nengine: {:?}\nbook bids: {:?}\nbook asks: {:?}",
+                    name,
+                    self.seed,
+                    self.history.len(),
+                    &self.history[self.history.len().saturating_sub(HISTORY)..],
+                    self.levels,
+                    l2_levels(book.bids()),
+                    l2_levels(book.asks()),
+                );
+            }
+        }
+    }
+}
+
+fn run(seed: u64, engine: OrderBook, backend: BookBackend) {
+    let mut activity = Activity::new(seed);
+    let mut harness = Harness::new(seed, engine, backend);
+    for step in 0..STEPS {
+        harness.apply(activity.next_command());
+        // Day orders leave with the session now and then
+        if step % 500 == 499 {
+            harness.engine.roll_session();
+            harness.sync();
+            harness.check();
+        }
+    }
+}
+
+#[test]
+fn test_books_agree_under_price_time_priority() {
+    for seed in 0..5 {
+        run(seed, OrderBook::new(), BookBackend::BTree);
+    }
+}
+
+#[test]
+fn test_books_agree_under_pro_rata() {
+    for seed in 0..5 {
+        let mut engine = OrderBook::new();
+        engine.set_matching_policy(MatchingPolicy::ProRata {
+            min_allocation: Quantity(2),
+            top_order_priority: true,
+        });
+        run(seed, engine, BookBackend::BTree);
+    }
+}
+
+#[test]
+fn test_books_agree_on_dense_levels() {
+    let engine = OrderBook::with_backend(BookBackend::Dense { tick_size: 1 });
+    let backend = BookBackend::Dense {
+        tick_size: CONVERSION_FACTOR as u64,
+    };
+    run(42, engine, backend);
+}
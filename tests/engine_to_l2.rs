@@ -0,0 +1,76 @@
+// Runs random order flow through the matching engine and rebuilds the L2 book from the
+// published market data, both views have to agree after every command.
+#![cfg(feature = "std")]
+
+use binance_orderbook::orderbook::{self, CONVERSION_FACTOR};
+use binance_orderbook::orderbookv2::{
+    LevelInfo, Order, OrderBook, OrderType, Price, Quantity, Side,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn l2_levels(
+    levels: impl Iterator<Item = (orderbook::Price, orderbook::Quantity)>,
+) -> Vec<LevelInfo> {
+    levels
+        .map(|(price, quantity)| LevelInfo {
+            price: Price((price.to_f64() / CONVERSION_FACTOR) as i32),
+            quantity: Quantity((quantity.to_f64() / CONVERSION_FACTOR) as u32),
+        })
+        .collect()
+}
+
+#[test]
+fn test_l2_book_follows_engine_deltas() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut engine = OrderBook::new();
+    let mut book = orderbook::OrderBook::new("SIM".to_string());
+
+    // Start from a non empty book so the snapshot carries levels
+    engine.add_order(Order::new(
+        1,
+        Price(95),
+        Quantity(10),
+        OrderType::GoodToCancel,
+        Side::Buy,
+    ));
+    engine.add_order(Order::new(
+        2,
+        Price(105),
+        Quantity(10),
+        OrderType::GoodToCancel,
+        Side::Sell,
+    ));
+    engine.enable_market_data();
+
+    for order_id in 3..3_000 {
+        if rng.gen_bool(0.3) {
+            engine.submit_cancel(rng.gen_range(1..order_id));
+        } else {
+            let side = if rng.gen_bool(0.5) {
+                Side::Buy
+            } else {
+                Side::Sell
+            };
+            let order_type = if rng.gen_bool(0.1) {
+                OrderType::FillAndKill
+            } else {
+                OrderType::GoodToCancel
+            };
+            engine.add_order(Order::new(
+                order_id,
+                Price(rng.gen_range(90..=110)),
+                Quantity(rng.gen_range(1..=20)),
+                order_type,
+                side,
+            ));
+        }
+
+        for message in engine.drain_market_data() {
+            book.apply_market_data(&message);
+        }
+
+        let infos = engine.get_orderbook_level_infos();
+        assert_eq!(&l2_levels(book.bids()), infos.get_bids());
+        assert_eq!(&l2_levels(book.asks()), infos.get_asks());
+    }
+}
@@ -0,0 +1,123 @@
+// Randomized tests of the frame decoder. Streams of random frames are cut into random chunks,
+// damaged and padded with random bytes, and the decoder has to hand back exactly the frames
+// that survived, in order, without panicking. A failure names the seed to replay it with.
+#![cfg(feature = "std")]
+
+use binance_orderbook::framing::{encode_frame, Frame, FrameDecoder, HEADER_LEN, TRAILER_LEN};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::ops::Range;
+
+const SEEDS: u64 = 50;
+const FRAMES: usize = 200;
+const MAX_PAYLOAD: usize = 300;
+
+fn random_frames(rng: &mut StdRng) -> Vec<Frame> {
+    (0..FRAMES)
+        .map(|_| {
+            let len = rng.gen_range(0..=MAX_PAYLOAD);
+            Frame {
+                kind: rng.gen(),
+                payload: (0..len).map(|_| rng.gen()).collect(),
+            }
+        })
+        .collect()
+}
+
+// The stream and the byte range of every frame in it
+fn encode(frames: &[Frame]) -> (Vec<u8>, Vec<Range<usize>>) {
+    let mut stream = Vec::new();
+    let mut ranges = Vec::new();
+    for frame in frames {
+        let start = stream.len();
+        encode_frame(frame.kind, &frame.payload, &mut stream);
+        ranges.push(start..stream.len());
+    }
+    (stream, ranges)
+}
+
+// Feeds the stream in random chunks and collects the frames
+fn decode(rng: &mut StdRng, stream: &[u8]) -> Vec<Frame> {
+    let mut decoder = FrameDecoder::new().with_max_payload(MAX_PAYLOAD);
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < stream.len() {
+        let end = (offset + rng.gen_range(1..=512)).min(stream.len());
+        decoder.push(&stream[offset..end]);
+        offset = end;
+        while let Some(result) = decoder.next_frame() {
+            if let Ok(frame) = result {
+                frames.push(frame);
+            }
+        }
+    }
+    frames
+}
+
+fn is_subsequence(decoded: &[Frame], frames: &[Frame]) -> bool {
+    let mut frames = frames.iter();
+    decoded
+        .iter()
+        .all(|frame| frames.any(|original| original == frame))
+}
+
+#[test]
+fn test_any_chunking_yields_every_frame() {
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let frames = random_frames(&mut rng);
+        let (stream, _) = encode(&frames);
+        assert!(decode(&mut rng, &stream) == frames, "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_damaged_frames_are_dropped_alone() {
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let frames = random_frames(&mut rng);
+        let (mut stream, ranges) = encode(&frames);
+
+        // Flip bits in the payload or checksum of a tenth of the frames
+        let mut damaged = vec![false; frames.len()];
+        for (index, range) in ranges.iter().enumerate() {
+            if rng.gen_ratio(1, 10) {
+                let position = rng.gen_range(range.start + HEADER_LEN..range.end);
+                stream[position] ^= rng.gen_range(1..=255u8);
+                damaged[index] = true;
+            }
+        }
+        let intact: Vec<Frame> = frames
+            .iter()
+            .zip(&damaged)
+            .filter(|(_, damaged)| !**damaged)
+            .map(|(frame, _)| frame.clone())
+            .collect();
+        assert!(decode(&mut rng, &stream) == intact, "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_garbage_never_yields_foreign_frames() {
+    for seed in 0..SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let frames = random_frames(&mut rng);
+        let (stream, ranges) = encode(&frames);
+
+        // Random bytes between frames, a random damage anywhere and a truncated end
+        let mut noisy = Vec::new();
+        for range in &ranges {
+            let noise = rng.gen_range(0..=16);
+            noisy.extend((0..noise).map(|_| rng.gen::<u8>()));
+            noisy.extend_from_slice(&stream[range.clone()]);
+        }
+        for _ in 0..rng.gen_range(0..=20) {
+            let position = rng.gen_range(0..noisy.len());
+            noisy[position] = rng.gen();
+        }
+        noisy.truncate(noisy.len() - rng.gen_range(0..HEADER_LEN + TRAILER_LEN));
+
+        let decoded = decode(&mut rng, &noisy);
+        assert!(is_subsequence(&decoded, &frames), "seed {}", seed);
+        assert!(decoded.len() >= frames.len() - 22, "seed {}", seed);
+    }
+}
@@ -0,0 +1,83 @@
+// Drives the engine through the mock exchange: a session with a malformed frame, a gap and a
+// stale payload that ends in a dropped connection, then a reconnect that starts from a fresh
+// depth payload.
+#![cfg(feature = "native")]
+
+use binance_orderbook::clock;
+use binance_orderbook::config::EngineConfig;
+use binance_orderbook::engine::Engine;
+use binance_orderbook::orderbook::DepthSnapshot;
+use binance_orderbook::testkit::{MockExchange, Scenario};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+
+// Connects, subscribes and applies every payload until the server ends the connection.
+// Returns the snapshots the engine produced.
+async fn session(engine: &mut Engine, url: &str, frames: &[String]) -> Vec<DepthSnapshot> {
+    let (mut websocket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    for frame in frames {
+        websocket.send(Message::Text(frame.clone())).await.unwrap();
+    }
+    let clock = clock::system();
+    let mut snapshots = Vec::new();
+    while let Some(Ok(message)) = websocket.next().await {
+        if message.is_text() || message.is_binary() {
+            snapshots.extend(engine.on_payload(clock.stamp(), &message.into_data()));
+        }
+    }
+    snapshots
+}
+
+fn best_bid(snapshot: &DepthSnapshot) -> f64 {
+    snapshot.converter.to_f64(snapshot.bids[0].0)
+}
+
+#[tokio::test]
+async fn test_feed_survives_gaps_malformed_frames_and_disconnects() {
+    let exchange = MockExchange::start(vec![
+        Scenario::new()
+            .await_subscribe()
+            .depth("ETHUSDC", 5, 1, vec![(100.0, 1.0)], vec![(101.0, 1.0)])
+            .malformed()
+            // Updates 2 to 4 never arrive
+            .depth("ETHUSDC", 5, 5, vec![(100.5, 2.0)], vec![(101.0, 1.0)])
+            // Older than what the book holds
+            .depth("ETHUSDC", 5, 3, vec![(99.0, 1.0)], vec![(101.0, 1.0)])
+            .disconnect(),
+        Scenario::new()
+            .await_subscribe()
+            .depth("ETHUSDC", 5, 10, vec![(102.0, 1.0)], vec![(103.0, 1.0)])
+            .close(),
+    ])
+    .await
+    .unwrap();
+
+    let mut engine = Engine::new(
+        EngineConfig::from_toml(&format!(
+            "[venue]\nws_url = {:?}\n[[symbols]]\nsymbol = \"ETHUSDC\"\ndepth = 5\nbook_ticker = false",
+            exchange.url()
+        ))
+        .unwrap(),
+    )
+    .unwrap();
+    let url = engine.binance().ws_url;
+    let frames: Vec<String> = engine
+        .take_subscription_frames()
+        .iter()
+        .map(|frame| frame.to_json())
+        .collect();
+
+    let first = session(&mut engine, &url, &frames).await;
+    let bids: Vec<f64> = first.iter().map(best_bid).collect();
+    assert_eq!(bids, vec![100.0, 100.5, 100.5]);
+
+    let second = session(&mut engine, &url, &frames).await;
+    assert_eq!(second.iter().map(best_bid).collect::<Vec<_>>(), vec![102.0]);
+    assert_eq!(exchange.connections(), 2);
+    let received = exchange.received();
+    assert_eq!(received.len(), 2);
+    assert!(received[0].contains("ethusdc@depth5@100ms"));
+
+    // Nothing is scripted for a third connection
+    assert!(tokio_tungstenite::connect_async(&url).await.is_err());
+}
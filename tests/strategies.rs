@@ -0,0 +1,128 @@
+// Runs the reference strategies through the whole pipeline: combined stream payloads into the
+// runtime, orders into the paper exchange or the matching engine, fills back to the strategy.
+#![cfg(feature = "std")]
+
+use binance_orderbook::clock::MockClock;
+use binance_orderbook::orderbookv2::{self, Order, OrderType, Price, Quantity, Side};
+use binance_orderbook::paper::PaperExchange;
+use binance_orderbook::strategies::{MarketMakerConfig, QuotingMarketMaker, Twap, TwapConfig};
+use binance_orderbook::strategy::Runtime;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn depth(last_update_id: u64, bid: &str, ask: &str) -> Vec<u8> {
+    format!(
+        r#"{{"stream":"bnbusdt@depth5@100ms","data":{{"lastUpdateId":{},"bids":[[{}]],"asks":[[{}]]}}}}"#,
+        last_update_id, bid, ask
+    )
+    .into_bytes()
+}
+
+fn trade(trade_id: u64, price: &str, quantity: &str, is_buyer_maker: bool) -> Vec<u8> {
+    format!(
+        r#"{{"stream":"bnbusdt@trade","data":{{"e":"trade","E":{0},"s":"BNBUSDT","t":{0},"p":"{1}","q":"{2}","T":{0},"m":{3},"M":true}}}}"#,
+        trade_id, price, quantity, is_buyer_maker
+    )
+    .into_bytes()
+}
+
+#[test]
+fn test_market_maker_on_paper_exchange() {
+    let maker = QuotingMarketMaker::new(MarketMakerConfig {
+        half_spread: Price(1_000),
+        quantity: Quantity(10_000),
+        max_inventory: 10_000,
+        skew: Price(500),
+    });
+    let mut runtime = Runtime::new("BNBUSDT", maker, PaperExchange::new("BNBUSDT"));
+
+    runtime.run([depth(1, r#""10.0","5.0""#, r#""10.2","5.0""#)]);
+    let mut quotes: Vec<_> = runtime
+        .venue()
+        .open_orders()
+        .map(Order::get_price)
+        .collect();
+    quotes.sort();
+    assert_eq!(quotes, vec![Price(100_000), Price(102_000)]);
+
+    // A seller trades through the bid, the maker is long and only quotes the ask, skewed down
+    runtime.run([trade(1, "9.95", "2.0", true)]);
+    assert_eq!(runtime.strategy().inventory(), 10_000);
+    let quotes: Vec<_> = runtime
+        .venue()
+        .open_orders()
+        .map(|order| (order.get_side(), order.get_price()))
+        .collect();
+    assert_eq!(quotes, vec![(Side::Sell, Price(101_500))]);
+
+    runtime.run([trade(2, "10.16", "2.0", false)]);
+    assert_eq!(runtime.strategy().inventory(), 0);
+    assert_eq!(runtime.strategy().quotes().len(), 2);
+}
+
+#[test]
+fn test_twap_on_paper_exchange() {
+    let clock = MockClock::new(0);
+    let twap = Twap::new(TwapConfig {
+        side: Side::Buy,
+        quantity: Quantity(60_000),
+        slices: 3,
+        interval: Duration::from_secs(10),
+        limit: Price(101_000),
+    });
+    let mut runtime = Runtime::new("BNBUSDT", twap, PaperExchange::new("BNBUSDT"));
+    runtime.set_clock(Arc::new(clock.clone()));
+
+    // Only one unit offered within the limit, the rest of the slice rolls over
+    runtime.run([depth(1, r#""10.0","5.0""#, r#""10.1","1.0""#)]);
+    assert_eq!(runtime.strategy().filled(), Quantity(10_000));
+
+    runtime.run([depth(2, r#""10.0","5.0""#, r#""10.1","9.0""#)]);
+    clock.advance(Duration::from_secs(10));
+    runtime.poll_timers();
+    assert_eq!(runtime.strategy().filled(), Quantity(35_000));
+
+    clock.advance(Duration::from_secs(10));
+    runtime.poll_timers();
+    assert_eq!(runtime.strategy().filled(), Quantity(60_000));
+    assert!(runtime.strategy().is_done());
+    assert!(runtime.venue().open_orders().next().is_none());
+}
+
+#[test]
+fn test_twap_on_matching_engine() {
+    let clock = MockClock::new(0);
+    let mut engine = orderbookv2::OrderBook::new();
+    for (order_id, price) in [(1_000, 100), (1_001, 101), (1_002, 103)] {
+        engine.add_order(Order::new(
+            order_id,
+            Price(price),
+            Quantity(10),
+            OrderType::GoodToCancel,
+            Side::Sell,
+        ));
+    }
+    let twap = Twap::new(TwapConfig {
+        side: Side::Buy,
+        quantity: Quantity(30),
+        slices: 3,
+        interval: Duration::from_secs(1),
+        limit: Price(102),
+    });
+    let mut runtime = Runtime::new("BNBUSDT", twap, engine).with_account(1);
+    runtime.set_clock(Arc::new(clock.clone()));
+
+    runtime.run([depth(1, r#""0.0099","1.0""#, r#""0.0100","1.0""#)]);
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(1));
+        runtime.poll_timers();
+    }
+    // The last slice found nothing within the limit
+    assert_eq!(runtime.strategy().filled(), Quantity(20));
+    assert_eq!(runtime.strategy().slices_sent(), 3);
+    assert!(runtime.strategy().is_done());
+    assert_eq!(
+        runtime.venue().get_volume_at(Side::Sell, Price(103)),
+        Quantity(10)
+    );
+}